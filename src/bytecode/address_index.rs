@@ -1,8 +1,15 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
-use jmap::{ObjectType, Property};
+use jmap::{Enum, ObjectType, Property, PropertyType};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+use super::expr::Expr;
+use super::flags::{CPF_PARM, CPF_RETURN_PARM};
+use super::intrinsics::KnownStruct;
 use super::types::Address;
+use crate::error::KismetError;
+use crate::formatters::renames::RenameMap;
 
 #[derive(Debug, Clone)]
 pub struct ObjectInfo<'a> {
@@ -10,6 +17,26 @@ pub struct ObjectInfo<'a> {
     pub object: &'a ObjectType,
 }
 
+/// The part an object plays beyond its type, for objects code refers to in
+/// a special way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectRole<'a> {
+    /// The default object of `class`, e.g. `Default__GameplayStatics`
+    ClassDefault {
+        class: &'a str,
+    },
+    /// The single instance of a subsystem class
+    Subsystem {
+        class: &'a str,
+    },
+    Other,
+}
+
+/// Path of the engine's base class for subsystems
+const SUBSYSTEM_CLASS: &str = "/Script/Engine.Subsystem";
+/// Path of the engine's base class for actors
+const ACTOR_CLASS: &str = "/Script/Engine.Actor";
+
 #[derive(Debug, Clone)]
 pub struct PropertyInfo<'a> {
     pub owner: ObjectInfo<'a>,
@@ -18,49 +45,339 @@ pub struct PropertyInfo<'a> {
 
 pub struct AddressIndex<'a> {
     pub jmap: &'a jmap::Jmap,
-    pub object_index: BTreeMap<u64, &'a str>, // address => object path
-    pub property_index: BTreeMap<u64, (&'a str, usize)>, // address => (owner path, property index)
+    /// address => object paths, preferred candidate first
+    pub object_index: BTreeMap<u64, Vec<&'a str>>,
+    /// address => (owner path, property index), preferred candidate first
+    pub property_index: BTreeMap<u64, Vec<(&'a str, usize)>>,
+    /// Names to print properties by instead of their own
+    renames: RenameMap,
+    /// Number of each address scripts refer to that resolves to nothing, for
+    /// [`Self::symbolic_name`]
+    unknown_addresses: BTreeMap<u64, usize>,
+}
+
+/// An [`AddressIndex`] kept apart from its jmap, for loading again with the
+/// same jmap without sorting every object and property by address
+///
+/// Objects are referred to by their position in the jmap's sorted paths.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedAddressIndex {
+    objects: BTreeMap<u64, Vec<usize>>,
+    properties: BTreeMap<u64, Vec<(usize, usize)>>,
+    unknown_addresses: BTreeMap<u64, usize>,
+}
+
+// Shared read-only by the threads that decompile functions in parallel; it
+// only borrows the jmap and owns plain maps, the rename map's among them,
+// with no interior mutability
+const _: () = {
+    const fn assert_sync<T: Sync>() {}
+    assert_sync::<AddressIndex<'static>>();
+};
+
+/// Replace the `Address(1234)` values in debug output with what `replace`
+/// gives for them
+fn replace_debug_addresses(debug: &str, mut replace: impl FnMut(Address) -> String) -> String {
+    const PREFIX: &str = "Address(";
+    let mut replaced = String::with_capacity(debug.len());
+    let mut rest = debug;
+    while let Some(start) = rest.find(PREFIX) {
+        let (before, after) = rest.split_at(start + PREFIX.len());
+        let digits = after.find(')').map(|end| &after[..end]);
+        match digits.and_then(|digits| Some((digits, digits.parse::<u64>().ok()?))) {
+            Some((digits, value)) => {
+                replaced.push_str(&before[..start]);
+                replaced.push_str(&replace(Address::new(value)));
+                rest = &after[digits.len() + 1..];
+            }
+            None => {
+                replaced.push_str(before);
+                rest = after;
+            }
+        }
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// Name prefixes the engine gives to stale copies of objects, e.g. classes
+/// replaced during blueprint recompilation
+const STALE_PREFIXES: &[&str] = &["REINST_", "SKEL_", "TRASH_", "HOTRELOADED_", "PLACEHOLDER-"];
+
+/// Ordering key used to pick between objects sharing an address
+///
+/// Live objects win over stale copies, then shorter paths over longer ones,
+/// with the path itself as a deterministic tie-break.
+fn disambiguation_rank(path: &str) -> (bool, usize, &str) {
+    let name = path.rsplit(['/', '.', ':']).next().unwrap_or(path);
+    let stale = STALE_PREFIXES.iter().any(|prefix| name.starts_with(prefix));
+    (stale, path.len(), path)
+}
+
+/// Group `(address, value)` pairs sorted by address into a multimap
+fn group_by_address<T>(pairs: Vec<(u64, T)>) -> BTreeMap<u64, Vec<T>> {
+    let mut index: BTreeMap<u64, Vec<T>> = BTreeMap::new();
+    for (address, value) in pairs {
+        index.entry(address).or_default().push(value);
+    }
+    index
 }
 
 impl<'a> AddressIndex<'a> {
     pub fn new(jmap: &'a jmap::Jmap) -> Self {
-        let mut object_index = BTreeMap::new();
-        let mut property_index = BTreeMap::new();
+        let objects: Vec<(&'a String, &'a ObjectType)> = jmap.objects.iter().collect();
 
         // Index objects by address
-        for (path, obj) in &jmap.objects {
-            let address = obj.get_object().address.0;
-            object_index.insert(address, path.as_str());
-        }
+        let mut object_pairs: Vec<(u64, &'a str)> = objects
+            .par_iter()
+            .map(|(path, obj)| (obj.get_object().address.0, path.as_str()))
+            .collect();
+        object_pairs.par_sort_unstable_by(|(addr_a, path_a), (addr_b, path_b)| {
+            addr_a
+                .cmp(addr_b)
+                .then_with(|| disambiguation_rank(path_a).cmp(&disambiguation_rank(path_b)))
+        });
 
         // Index properties by address
-        for (path, obj) in &jmap.objects {
-            if let Some(struct_obj) = obj.get_struct() {
-                for (prop_idx, prop) in struct_obj.properties.iter().enumerate() {
-                    property_index.insert(prop.address.0, (path.as_str(), prop_idx));
-                }
-            }
-        }
+        let mut property_pairs: Vec<(u64, (&'a str, usize))> = objects
+            .par_iter()
+            .filter_map(|(path, obj)| Some((path.as_str(), obj.get_struct()?)))
+            .flat_map_iter(|(path, struct_obj)| {
+                struct_obj
+                    .properties
+                    .iter()
+                    .enumerate()
+                    .map(move |(prop_idx, prop)| (prop.address.0, (path, prop_idx)))
+            })
+            .collect();
+        property_pairs.par_sort_unstable_by(|(addr_a, (owner_a, _)), (addr_b, (owner_b, _))| {
+            addr_a
+                .cmp(addr_b)
+                .then_with(|| disambiguation_rank(owner_a).cmp(&disambiguation_rank(owner_b)))
+        });
 
         Self {
+            jmap,
+            object_index: group_by_address(object_pairs),
+            property_index: group_by_address(property_pairs),
+            renames: RenameMap::default(),
+            unknown_addresses: BTreeMap::new(),
+        }
+    }
+
+    /// Restore an index saved by [`Self::save`] from the same jmap, or `None`
+    /// if it refers to objects the jmap doesn't have
+    ///
+    /// Renames aren't saved, so they have to be given again.
+    pub fn from_saved(jmap: &'a jmap::Jmap, saved: SavedAddressIndex) -> Option<Self> {
+        let paths: Vec<&'a str> = jmap.objects.keys().map(String::as_str).collect();
+        let object_index = saved
+            .objects
+            .into_iter()
+            .map(|(address, objects)| {
+                let objects = objects.into_iter().map(|i| paths.get(i).copied());
+                Some((address, objects.collect::<Option<_>>()?))
+            })
+            .collect::<Option<_>>()?;
+        let property_index = saved
+            .properties
+            .into_iter()
+            .map(|(address, owners)| {
+                let owners = owners
+                    .into_iter()
+                    .map(|(i, prop_idx)| Some((*paths.get(i)?, prop_idx)));
+                Some((address, owners.collect::<Option<_>>()?))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
             jmap,
             object_index,
             property_index,
+            renames: RenameMap::default(),
+            unknown_addresses: saved.unknown_addresses,
+        })
+    }
+
+    /// The index without its jmap, for [`Self::from_saved`]
+    pub fn save(&self) -> SavedAddressIndex {
+        let positions: HashMap<&str, usize> = self
+            .jmap
+            .objects
+            .keys()
+            .enumerate()
+            .map(|(i, path)| (path.as_str(), i))
+            .collect();
+        SavedAddressIndex {
+            objects: self
+                .object_index
+                .iter()
+                .map(|(address, paths)| {
+                    (*address, paths.iter().map(|path| positions[path]).collect())
+                })
+                .collect(),
+            properties: self
+                .property_index
+                .iter()
+                .map(|(address, owners)| {
+                    let owners = owners
+                        .iter()
+                        .map(|(path, prop_idx)| (positions[path], *prop_idx));
+                    (*address, owners.collect())
+                })
+                .collect(),
+            unknown_addresses: self.unknown_addresses.clone(),
         }
     }
 
-    pub fn resolve_object(&self, address: Address) -> Option<ObjectInfo<'_>> {
+    /// Print properties by the names `renames` gives them
+    pub fn with_renames(mut self, renames: RenameMap) -> Self {
+        self.renames = renames;
+        self
+    }
+
+    /// Number the addresses `statements` refer to that resolve to nothing,
+    /// in the order they're first referred to, so [`Self::symbolic_name`]
+    /// tells them apart
+    pub fn with_unknown_addresses<'e>(
+        mut self,
+        statements: impl IntoIterator<Item = &'e Expr>,
+    ) -> Self {
+        for statement in statements {
+            replace_debug_addresses(&format!("{:?}", statement.kind), |address| {
+                if self.resolve_object(address).is_none()
+                    && self.resolve_property(address).is_none()
+                {
+                    let next = self.unknown_addresses.len() + 1;
+                    self.unknown_addresses.entry(address.0).or_insert(next);
+                }
+                String::new()
+            });
+        }
+        self
+    }
+
+    /// The name to print a property by
+    pub fn property_name<'p>(&'p self, property: &'p Property) -> &'p str {
+        self.renames.rename(&property.name)
+    }
+
+    /// Addresses claimed by more than one object, with every candidate path
+    pub fn object_collisions(&self) -> impl Iterator<Item = (Address, &[&'a str])> {
+        self.object_index
+            .iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(address, paths)| (Address::new(*address), paths.as_slice()))
+    }
+
+    /// Number of addresses claimed by more than one property
+    pub fn property_collision_count(&self) -> usize {
+        self.property_index
+            .values()
+            .filter(|owners| owners.len() > 1)
+            .count()
+    }
+
+    /// Path of the preferred object at an address
+    fn object_path(&self, address: Address) -> Option<&'a str> {
         self.object_index
             .get(&address.as_u64())
-            .map(|path| ObjectInfo {
-                object: self.jmap.objects.get(*path).unwrap(),
-                path,
-            })
+            .and_then(|paths| paths.first().copied())
+    }
+
+    pub fn resolve_object(&self, address: Address) -> Option<ObjectInfo<'_>> {
+        self.object_path(address).map(|path| ObjectInfo {
+            object: self.jmap.objects.get(path).unwrap(),
+            path,
+        })
+    }
+
+    /// The part the object at an address plays, e.g. whether it's a class
+    /// default object
+    pub fn object_role(&self, address: Address) -> ObjectRole<'a> {
+        let Some(path) = self.object_path(address) else {
+            return ObjectRole::Other;
+        };
+        let Some(object) = self.jmap.objects.get(path) else {
+            return ObjectRole::Other;
+        };
+        let class = object.get_object().class.as_str();
+        let name = path.rsplit(['/', '.', ':']).next().unwrap_or(path);
+        if name.starts_with("Default__") {
+            ObjectRole::ClassDefault { class }
+        } else if self
+            .class_chain(class)
+            .any(|ancestor| ancestor == SUBSYSTEM_CLASS)
+        {
+            ObjectRole::Subsystem { class }
+        } else {
+            ObjectRole::Other
+        }
+    }
+
+    /// A class and its ancestors, nearest first, as far as the dump has them
+    pub fn class_chain(&self, class: &'a str) -> impl Iterator<Item = &'a str> {
+        std::iter::successors(Some(class), |current| {
+            self.jmap
+                .objects
+                .get(*current)
+                .and_then(|o| o.get_struct())
+                .and_then(|struct_obj| struct_obj.super_struct.as_deref())
+        })
+    }
+
+    /// C++ name of a class, with the `A` prefix for actors and `U` for
+    /// everything else
+    pub fn cpp_class_name(&self, class: &'a str) -> String {
+        let prefix = if self
+            .class_chain(class)
+            .any(|ancestor| ancestor == ACTOR_CLASS)
+        {
+            'A'
+        } else {
+            'U'
+        };
+        format!(
+            "{}{}",
+            prefix,
+            class.rsplit(['/', '.']).next().unwrap_or(class)
+        )
+    }
+
+    /// The object at an address, failing if there isn't one
+    pub fn object(&self, address: Address) -> Result<ObjectInfo<'_>, KismetError> {
+        self.resolve_object(address).ok_or(KismetError::Resolution {
+            kind: "object",
+            address,
+        })
+    }
+
+    /// Stable stand-in for an address, derived from the path of what lives
+    /// there, for output that shouldn't change when a dump is regenerated
+    ///
+    /// Addresses that resolve to nothing are numbered as given to
+    /// [`Self::with_unknown_addresses`], as `@unknown_1`, `@unknown_2`, ...
+    pub fn symbolic_name(&self, address: Address) -> String {
+        if let Some(path) = self.object_path(address) {
+            format!("@{}", path)
+        } else if let Some(prop) = self.resolve_property(address) {
+            format!("@{}::{}", prop.owner.path, prop.property.name)
+        } else {
+            match self.unknown_addresses.get(&address.0) {
+                Some(number) => format!("@unknown_{}", number),
+                None => "@unknown".to_string(),
+            }
+        }
+    }
+
+    /// Replace the `Address(1234)` values in debug output with symbolic names
+    pub fn normalize_debug_addresses(&self, debug: &str) -> String {
+        replace_debug_addresses(debug, |address| self.symbolic_name(address))
     }
 
     pub fn resolve_property(&self, address: Address) -> Option<PropertyInfo<'_>> {
         self.property_index
             .get(&address.as_u64())
+            .and_then(|owners| owners.first())
             .map(|(path, prop_idx)| {
                 let object = self.jmap.objects.get(*path).unwrap();
                 PropertyInfo {
@@ -69,4 +386,315 @@ impl<'a> AddressIndex<'a> {
                 }
             })
     }
+
+    /// The property at an address, failing if there isn't one
+    pub fn property(&self, address: Address) -> Result<PropertyInfo<'_>, KismetError> {
+        self.resolve_property(address)
+            .ok_or(KismetError::Resolution {
+                kind: "property",
+                address,
+            })
+    }
+
+    /// Whether the property at this address holds a struct value (as opposed to an object pointer)
+    pub fn is_struct_property(&self, address: Address) -> bool {
+        self.resolve_property(address)
+            .is_some_and(|info| matches!(info.property.r#type, PropertyType::Struct { .. }))
+    }
+
+    /// Whether the property at this address is an array of struct values
+    pub fn is_struct_array_property(&self, address: Address) -> bool {
+        self.resolve_property(address)
+            .is_some_and(|info| match &info.property.r#type {
+                PropertyType::Array { inner } => {
+                    matches!(inner.r#type, PropertyType::Struct { .. })
+                }
+                _ => false,
+            })
+    }
+
+    /// The engine struct with a dedicated literal form at this address, if any
+    pub fn known_struct(&self, address: Address) -> Option<KnownStruct> {
+        self.object_path(address).and_then(KnownStruct::from_path)
+    }
+
+    /// All properties of a struct in serialization order, inherited properties first
+    pub fn struct_properties(&self, address: Address) -> Option<Vec<&'a Property>> {
+        let path = self.object_path(address)?;
+
+        let mut chain = Vec::new();
+        let mut current = self.jmap.objects.get(path).and_then(|o| o.get_struct());
+        while let Some(struct_obj) = current {
+            chain.push(struct_obj);
+            current = struct_obj
+                .super_struct
+                .as_ref()
+                .and_then(|super_path| self.jmap.objects.get(super_path))
+                .and_then(|o| o.get_struct());
+        }
+
+        if chain.is_empty() {
+            return None;
+        }
+
+        Some(
+            chain
+                .iter()
+                .rev()
+                .flat_map(|struct_obj| struct_obj.properties.iter())
+                .collect(),
+        )
+    }
+
+    /// Member properties of a class or struct and its ancestors in memory
+    /// order, each with the path of the class that declares it
+    ///
+    /// Properties at the same byte are ordered by bit, for bool bitfields,
+    /// then ancestors first. Returns `None` if `path` isn't a class or struct.
+    pub fn layout(&self, path: &'a str) -> Option<Vec<(&'a str, &'a Property)>> {
+        self.jmap.objects.get(path)?.get_struct()?;
+        let mut chain: Vec<&'a str> = self.class_chain(path).collect();
+        chain.reverse();
+
+        let mut members: Vec<(&'a str, &'a Property)> = chain
+            .into_iter()
+            .filter_map(|owner| {
+                let (path, object) = self.jmap.objects.get_key_value(owner)?;
+                Some((path.as_str(), object.get_struct()?))
+            })
+            .flat_map(|(owner, struct_obj)| {
+                struct_obj
+                    .properties
+                    .iter()
+                    .map(move |property| (owner, property))
+            })
+            .collect();
+        members.sort_by_key(|(_, property)| member_position(property));
+        Some(members)
+    }
+
+    /// Name of the function at `callee` if calling it non-virtually from
+    /// `caller` runs a parent implementation that the caller's class, or a
+    /// class between the two, overrides; that is, if it's a `Super::` call
+    pub fn super_call_name(&self, caller: &str, callee: Address) -> Option<&'a str> {
+        let (callee_class, name) = self.object_path(callee)?.rsplit_once(':')?;
+        let (caller_class, _) = caller.rsplit_once(':')?;
+
+        let mut overridden = false;
+        for class in self.class_chain(caller_class) {
+            if class == callee_class {
+                return overridden.then_some(name);
+            }
+            overridden |= self
+                .jmap
+                .objects
+                .contains_key(&format!("{}:{}", class, name));
+        }
+        None
+    }
+
+    /// Parameters of a function in call order, excluding the return value
+    pub fn function_params(&self, address: Address) -> Option<Vec<&'a Property>> {
+        let path = self.object_path(address)?;
+        let function = self.jmap.objects.get(path)?.get_struct()?;
+        Some(
+            function
+                .properties
+                .iter()
+                .filter(|prop| {
+                    let flags = prop.flags.bits();
+                    flags & CPF_PARM != 0 && flags & CPF_RETURN_PARM == 0
+                })
+                .collect(),
+        )
+    }
+
+    /// The enum a byte or enum property holds, if it's typed
+    pub fn property_enum(&self, property: &Property) -> Option<&'a Enum> {
+        let path = match &property.r#type {
+            PropertyType::Byte { r#enum } | PropertyType::Enum { r#enum, .. } => r#enum.as_ref()?,
+            _ => return None,
+        };
+        match self.jmap.objects.get(path)? {
+            ObjectType::Enum(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Byte and bit a member property starts at in its object
+pub fn member_position(property: &Property) -> (usize, u32) {
+    match property.r#type {
+        PropertyType::Bool {
+            byte_offset,
+            byte_mask,
+            field_mask,
+            ..
+        } if field_mask != 0xFF => (
+            property.offset + byte_offset as usize,
+            byte_mask.trailing_zeros(),
+        ),
+        _ => (property.offset, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{jmap_of, local, object_json, property_json};
+
+    #[test]
+    fn test_disambiguation_prefers_live_objects() {
+        let mut paths = vec![
+            "/Game/BP_Door.REINST_BP_Door_C_12",
+            "/Game/Variants/BP_Door.BP_Door_C",
+            "/Game/BP_Door.BP_Door_C",
+        ];
+        paths.sort_by_key(|path| disambiguation_rank(path));
+        assert_eq!(
+            paths,
+            vec![
+                "/Game/BP_Door.BP_Door_C",
+                "/Game/Variants/BP_Door.BP_Door_C",
+                "/Game/BP_Door.REINST_BP_Door_C_12",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_super_call_needs_an_override() {
+        let jmap = jmap_of(serde_json::json!({
+            "/Game/Base": object_json("Class", 1, None),
+            "/Game/Base:Fire": object_json("Function", 2, None),
+            "/Game/Base:Helper": object_json("Function", 3, None),
+            "/Game/Gun": object_json("Class", 4, Some("/Game/Base")),
+            "/Game/Gun:Fire": object_json("Function", 5, None),
+        }));
+        let index = AddressIndex::new(&jmap);
+
+        assert_eq!(
+            index.super_call_name("/Game/Gun:Fire", Address::new(2)),
+            Some("Fire")
+        );
+        // Not overridden, so an ordinary call to an inherited function
+        assert_eq!(
+            index.super_call_name("/Game/Gun:Fire", Address::new(3)),
+            None
+        );
+        // Not an ancestor of the caller's class
+        assert_eq!(
+            index.super_call_name("/Game/Base:Fire", Address::new(5)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_object_roles() {
+        let instance = |class: &str, address: u64| serde_json::json!({"type": "Object", "address": address, "outer": null, "class": class});
+        let jmap = jmap_of(serde_json::json!({
+            "/Script/Engine.Actor": object_json("Class", 1, None),
+            "/Game/BP_Door.BP_Door_C": object_json("Class", 2, Some("/Script/Engine.Actor")),
+            "/Game/BP_Door.Default__BP_Door_C": instance("/Game/BP_Door.BP_Door_C", 3),
+            "/Script/Engine.Subsystem": object_json("Class", 4, None),
+            "/Game/Doors.DoorSubsystem_C": object_json("Class", 5, Some("/Script/Engine.Subsystem")),
+            "/Game/Doors.DoorSubsystem_C_0": instance("/Game/Doors.DoorSubsystem_C", 6),
+        }));
+        let index = AddressIndex::new(&jmap);
+
+        assert_eq!(
+            index.object_role(Address::new(3)),
+            ObjectRole::ClassDefault {
+                class: "/Game/BP_Door.BP_Door_C"
+            }
+        );
+        assert_eq!(
+            index.object_role(Address::new(6)),
+            ObjectRole::Subsystem {
+                class: "/Game/Doors.DoorSubsystem_C"
+            }
+        );
+        assert_eq!(index.object_role(Address::new(2)), ObjectRole::Other);
+        assert_eq!(
+            index.cpp_class_name("/Game/BP_Door.BP_Door_C"),
+            "ABP_Door_C"
+        );
+        assert_eq!(
+            index.cpp_class_name("/Game/Doors.DoorSubsystem_C"),
+            "UDoorSubsystem_C"
+        );
+    }
+
+    #[test]
+    fn test_layout_interleaves_inherited_members() {
+        let bit = |mask: u8| {
+            serde_json::json!({"type": "Bool", "field_size": 1, "byte_offset": 0,
+                "byte_mask": mask, "field_mask": mask})
+        };
+        let mut base = object_json("Class", 1, None);
+        base["properties"] = serde_json::json!([
+            property_json("Health", 0, 0x10, serde_json::json!({"type": "Float"})),
+            property_json("bDead", 0, 0x18, bit(2)),
+        ]);
+        let mut derived = object_json("Class", 2, Some("/Game/Base"));
+        derived["properties"] = serde_json::json!([
+            property_json("Ammo", 0, 0x14, serde_json::json!({"type": "Int"})),
+            property_json("bArmed", 0, 0x18, bit(1)),
+        ]);
+        let jmap = jmap_of(serde_json::json!({ "/Game/Base": base, "/Game/Gun": derived }));
+        let index = AddressIndex::new(&jmap);
+
+        let layout = index.layout("/Game/Gun").unwrap();
+        let members: Vec<(&str, &str)> = layout
+            .iter()
+            .map(|(owner, property)| (*owner, property.name.as_str()))
+            .collect();
+        assert_eq!(
+            members,
+            vec![
+                ("/Game/Base", "Health"),
+                ("/Game/Gun", "Ammo"),
+                ("/Game/Gun", "bArmed"),
+                ("/Game/Base", "bDead"),
+            ]
+        );
+        assert!(index.layout("/Game/Missing").is_none());
+    }
+
+    #[test]
+    fn test_unknown_addresses_are_numbered() {
+        let jmap = jmap_of(serde_json::json!({ "/Game/Base": object_json("Class", 1, None) }));
+        let statements = [
+            local(0, 0x30),
+            local(9, 1),
+            local(18, 0x20),
+            local(27, 0x30),
+        ];
+        let index = AddressIndex::new(&jmap).with_unknown_addresses(&statements);
+
+        assert_eq!(index.symbolic_name(Address::new(1)), "@/Game/Base");
+        assert_eq!(index.symbolic_name(Address::new(0x30)), "@unknown_1");
+        assert_eq!(index.symbolic_name(Address::new(0x20)), "@unknown_2");
+        assert_eq!(index.symbolic_name(Address::new(0x40)), "@unknown");
+        assert_eq!(
+            index.normalize_debug_addresses("Let(Address(32), Address(1))"),
+            "Let(@unknown_2, @/Game/Base)"
+        );
+    }
+
+    #[test]
+    fn test_saved_index_restores() {
+        let jmap = jmap_of(serde_json::json!({
+            "/Game/BP_Door.BP_Door_C": object_json("Class", 1, None),
+            "/Game/BP_Door.REINST_BP_Door_C_1": object_json("Class", 1, None),
+            "/Game/BP_Door.BP_Door_C:Open": object_json("Function", 2, None),
+        }));
+        let index = AddressIndex::new(&jmap).with_unknown_addresses(&[local(0, 0x30)]);
+        let restored = AddressIndex::from_saved(&jmap, index.save()).unwrap();
+        assert_eq!(restored.object_index, index.object_index);
+        assert_eq!(restored.property_index, index.property_index);
+        assert_eq!(restored.symbolic_name(Address::new(0x30)), "@unknown_1");
+
+        let other = jmap_of(serde_json::json!({ "/Game/Base": object_json("Class", 1, None) }));
+        assert!(AddressIndex::from_saved(&other, index.save()).is_none());
+    }
 }
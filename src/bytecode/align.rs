@@ -0,0 +1,106 @@
+/// Lining up two versions of a sequence, for diffing functions
+///
+/// Needleman-Wunsch global alignment: every element of both sequences ends
+/// up either paired with one from the other side or marked as inserted or
+/// deleted, with pairs of equal elements favoured over gaps. Equal runs at
+/// either end are paired up front, so the quadratic table only covers the
+/// part that changed.
+const MATCH: i32 = 2;
+const MISMATCH: i32 = -1;
+const GAP: i32 = -1;
+
+/// One step of an alignment, by index into the old and new sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Elements that line up, equal or substituted for each other
+    Pair(usize, usize),
+    /// Only in the old sequence
+    Deleted(usize),
+    /// Only in the new sequence
+    Inserted(usize),
+}
+
+/// Align `old` with `new`, in order, comparing elements with `equal`
+pub fn align<T>(old: &[T], new: &[T], equal: impl Fn(&T, &T) -> bool) -> Vec<Step> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| equal(a, b)).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| equal(a, b))
+        .count();
+    let (rows, cols) = (old.len() - prefix - suffix, new.len() - prefix - suffix);
+
+    // score[i][j]: best alignment of the first i changed old elements with
+    // the first j changed new ones
+    let width = cols + 1;
+    let mut score = vec![0i32; (rows + 1) * width];
+    for i in 0..=rows {
+        score[i * width] = i as i32 * GAP;
+    }
+    for (j, cell) in score[..width].iter_mut().enumerate() {
+        *cell = j as i32 * GAP;
+    }
+    let pair_score = |i: usize, j: usize| {
+        if equal(&old[prefix + i - 1], &new[prefix + j - 1]) {
+            MATCH
+        } else {
+            MISMATCH
+        }
+    };
+    for i in 1..=rows {
+        for j in 1..=cols {
+            let diagonal = score[(i - 1) * width + j - 1] + pair_score(i, j);
+            let up = score[(i - 1) * width + j] + GAP;
+            let left = score[i * width + j - 1] + GAP;
+            score[i * width + j] = diagonal.max(up).max(left);
+        }
+    }
+
+    let mut middle = Vec::with_capacity(rows.max(cols));
+    let (mut i, mut j) = (rows, cols);
+    while i > 0 || j > 0 {
+        let here = score[i * width + j];
+        if i > 0 && j > 0 && here == score[(i - 1) * width + j - 1] + pair_score(i, j) {
+            middle.push(Step::Pair(prefix + i - 1, prefix + j - 1));
+            i -= 1;
+            j -= 1;
+        } else if i > 0 && here == score[(i - 1) * width + j] + GAP {
+            middle.push(Step::Deleted(prefix + i - 1));
+            i -= 1;
+        } else {
+            middle.push(Step::Inserted(prefix + j - 1));
+            j -= 1;
+        }
+    }
+
+    let head = (0..prefix).map(|k| Step::Pair(k, k));
+    let tail = (0..suffix).map(|k| Step::Pair(prefix + rows + k, prefix + cols + k));
+    head.chain(middle.into_iter().rev()).chain(tail).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insertions_deletions_and_substitutions() {
+        let old = ['a', 'b', 'c', 'd', 'e'];
+        let new = ['a', 'x', 'c', 'e', 'f'];
+        assert_eq!(
+            align(&old, &new, |a, b| a == b),
+            vec![
+                Step::Pair(0, 0),
+                Step::Pair(1, 1),
+                Step::Pair(2, 2),
+                Step::Deleted(3),
+                Step::Pair(4, 3),
+                Step::Inserted(4),
+            ]
+        );
+        assert_eq!(
+            align(&old[..0], &new[..2], |a, b| a == b),
+            vec![Step::Inserted(0), Step::Inserted(1)]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+/// Reading array elements in place instead of through a copy
+///
+/// The Blueprint "Get (a copy)" node compiles to a call to `Array_Get` that
+/// copies the element into a temporary, which the next statement then reads,
+/// so `Items[i].Quantity` prints as the call followed by
+/// `Temp_struct_Variable.Quantity`. Where the copy can't be told apart from
+/// the element itself, the read is replaced with the indexing expression the
+/// "Get (a ref)" node compiles to and the call is dropped.
+///
+/// That takes a temporary used by nothing but the call and the one read,
+/// with the read in the statement right after the call and no jump landing
+/// on that statement. The array and index must not call anything, nothing
+/// in the statement may run a call before the read, and the read can't be
+/// passed to a call, which could change the element where it only changed
+/// the copy before.
+use std::collections::HashMap;
+
+use super::expr::{Expr, ExprKind, collect_referenced_offsets};
+use super::pattern::{call_parts, property_accesses};
+use super::refs::{FunctionRef, PropertyRef};
+use super::types::BytecodeOffset;
+
+pub const ARRAY_GET: &str = "/Script/Engine.KismetArrayLibrary:Array_Get";
+
+/// A call to `Array_Get` replaced by indexing where its copy was read
+#[derive(Debug, Clone)]
+pub struct InlinedArrayGet {
+    pub offset: BytecodeOffset,
+    /// The temporary the element was copied into
+    pub item: PropertyRef,
+}
+
+/// Replace copies of array elements that are read once with the element
+///
+/// `resolve` gives the path of a called function. Returns the rewritten
+/// statements and the calls that were dropped, in order.
+pub fn inline_array_gets(
+    expressions: &[Expr],
+    resolve: impl Fn(&FunctionRef) -> Option<String>,
+) -> (Vec<Expr>, Vec<InlinedArrayGet>) {
+    let referenced = collect_referenced_offsets(expressions);
+    let mut uses: HashMap<PropertyRef, usize> = HashMap::new();
+    for (_, property, _) in property_accesses(expressions) {
+        *uses.entry(property).or_default() += 1;
+    }
+
+    let mut rewritten = Vec::with_capacity(expressions.len());
+    let mut inlined = Vec::new();
+    let mut statements = expressions.iter().peekable();
+    while let Some(statement) = statements.next() {
+        let replaced = statements.peek().and_then(|next| {
+            let (array, index, item) = array_get(statement, &resolve)?;
+            // The call and the read
+            if uses.get(&item) != Some(&2) || referenced.contains(&next.offset) {
+                return None;
+            }
+            let read = element_read(next, item)?;
+            let element = ExprKind::ArrayGetByRef {
+                array_expr: Box::new(array.clone()),
+                index_expr: Box::new(index.clone()),
+            };
+            let mut next = Expr::clone(next).rewrite(&mut |e| match &e.kind {
+                ExprKind::LocalVariable(property) if e.offset == read && *property == item => {
+                    Expr::new(e.offset, element.clone()).with_end(e.end)
+                }
+                _ => e,
+            });
+            // Jumps to the call now land on the statement that replaces it
+            next.offset = statement.offset;
+            Some((next, item))
+        });
+        match replaced {
+            Some((next, item)) => {
+                inlined.push(InlinedArrayGet {
+                    offset: statement.offset,
+                    item,
+                });
+                rewritten.push(next);
+                statements.next();
+            }
+            None => rewritten.push(statement.clone()),
+        }
+    }
+    (rewritten, inlined)
+}
+
+/// The array, index and temporary of a statement copying an element
+fn array_get<'e>(
+    statement: &'e Expr,
+    resolve: &impl Fn(&FunctionRef) -> Option<String>,
+) -> Option<(&'e Expr, &'e Expr, PropertyRef)> {
+    let (func, [array, index, item]) = call_parts(statement)? else {
+        return None;
+    };
+    let ExprKind::LocalVariable(item) = item.kind else {
+        return None;
+    };
+    let calls_nothing = |expr: &Expr| {
+        let mut calls = false;
+        expr.walk(&mut |e| calls |= call_parts(e).is_some());
+        !calls
+    };
+    (resolve(func)? == ARRAY_GET && calls_nothing(array) && calls_nothing(index))
+        .then_some((array, index, item))
+}
+
+/// Offset of the read of `item` in `statement`, if nothing is called before
+/// it and it isn't passed to a call
+fn element_read(statement: &Expr, item: PropertyRef) -> Option<BytecodeOffset> {
+    let mut read = None;
+    let mut passed = false;
+    let mut calls = Vec::new();
+    statement.walk(&mut |e| {
+        if read.is_some() {
+            return;
+        }
+        if let Some((_, params)) = call_parts(e) {
+            passed |= params
+                .iter()
+                .any(|param| param.kind == ExprKind::LocalVariable(item));
+            calls.push(e.span());
+        }
+        if e.kind == ExprKind::LocalVariable(item) {
+            read = Some(e.offset);
+        }
+    });
+    let read = read?;
+    // Calls are walked before their arguments, so a call walked before the
+    // read ran first unless the read is inside it
+    let inside = calls
+        .iter()
+        .all(|span| span.start <= read && read < span.end);
+    (inside && !passed).then_some(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{
+        self, by_name, call_with, jmap_of, local, object_json, property_json,
+    };
+    use crate::bytecode::types::Address;
+
+    fn at(offset: usize, end: usize, kind: ExprKind) -> Expr {
+        testing::at(offset, kind).with_end(BytecodeOffset::new(end))
+    }
+
+    fn var(offset: usize, address: u64) -> Expr {
+        local(offset, address).with_end(BytecodeOffset::new(offset + 9))
+    }
+
+    fn call(offset: usize, end: usize, name: &str, params: Vec<Expr>) -> Expr {
+        call_with(offset, name, params).with_end(BytecodeOffset::new(end))
+    }
+
+    /// `Array_Get(Items, i, Temp); return Temp.Quantity;`, with `Print`
+    /// called first in the return if `print` is set
+    fn script(print: bool) -> Vec<Expr> {
+        let get = call(0, 30, ARRAY_GET, vec![var(1, 1), var(10, 2), var(19, 3)]);
+        let quantity = at(
+            41,
+            59,
+            ExprKind::StructMemberContext {
+                member: PropertyRef::new(Address::new(4)),
+                struct_expr: Box::new(var(50, 3)),
+            },
+        );
+        let value = match print {
+            true => call(
+                31,
+                60,
+                "Add",
+                vec![call(32, 40, "Print", Vec::new()), quantity],
+            ),
+            false => quantity,
+        };
+        vec![get, at(30, 60, ExprKind::Return(Box::new(value)))]
+    }
+
+    #[test]
+    fn test_copied_element_is_read_in_place() {
+        let (rewritten, inlined) = inline_array_gets(&script(false), by_name);
+        assert_eq!(inlined.len(), 1);
+        assert_eq!(inlined[0].item, PropertyRef::new(Address::new(3)));
+        let [statement] = rewritten.as_slice() else {
+            panic!("expected one statement, got {:?}", rewritten);
+        };
+        assert_eq!((statement.offset.0, statement.end.0), (0, 60));
+        let ExprKind::Return(value) = &statement.kind else {
+            panic!("expected a return, got {:?}", statement.kind);
+        };
+        let ExprKind::StructMemberContext { struct_expr, .. } = &value.kind else {
+            panic!("expected a member access, got {:?}", value.kind);
+        };
+        assert_eq!(
+            struct_expr.kind,
+            ExprKind::ArrayGetByRef {
+                array_expr: Box::new(var(1, 1)),
+                index_expr: Box::new(var(10, 2)),
+            }
+        );
+
+        // Print could change Items before the copy is read
+        let (rewritten, inlined) = inline_array_gets(&script(true), by_name);
+        assert!(inlined.is_empty());
+        assert_eq!(rewritten, script(true));
+    }
+
+    #[test]
+    fn test_element_prints_as_indexing() {
+        use crate::bytecode::address_index::AddressIndex;
+        use crate::formatters::cpp::{ExprRenderer, FormatContext};
+
+        let item = serde_json::json!({"type": "Struct", "struct": "/Game/S_Item.S_Item"});
+        let items = property_json("Items", 5, 0, item.clone());
+        let mut item_struct = object_json("ScriptStruct", 10, None);
+        item_struct["properties"] = serde_json::json!([property_json(
+            "Quantity",
+            4,
+            0,
+            serde_json::json!({"type": "Int"})
+        )]);
+        let mut function = object_json("Function", 11, None);
+        function["properties"] = serde_json::json!([
+            property_json(
+                "Items",
+                1,
+                0,
+                serde_json::json!({"type": "Array", "inner": items})
+            ),
+            property_json("I", 2, 0, serde_json::json!({"type": "Int"})),
+            property_json("Temp_struct_Variable", 3, 0, item),
+        ]);
+        let jmap = jmap_of(serde_json::json!({
+            "/Game/S_Item.S_Item": item_struct,
+            "/Game/BP.BP_C:UseItems": function,
+        }));
+        let index = AddressIndex::new(&jmap);
+        let renderer = ExprRenderer::new(&index);
+
+        let (rewritten, _) = inline_array_gets(&script(false), by_name);
+        let ExprKind::Return(value) = &rewritten[0].kind else {
+            panic!("expected a return, got {:?}", rewritten[0].kind);
+        };
+        assert_eq!(
+            renderer.format_expr_inline(value, &FormatContext::This),
+            "Items[I].Quantity"
+        );
+    }
+}
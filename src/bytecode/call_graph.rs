@@ -0,0 +1,305 @@
+/// Call graph between script functions
+///
+/// Edges come from the direct call expressions in each function's script, plus
+/// the places a function is bound to a delegate. Calls made through delegates
+/// or from native code aren't known.
+use super::expr::{Expr, ExprKind};
+use super::refs::FunctionRef;
+use super::types::BytecodeOffset;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// How a function is referenced from a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CallKind {
+    /// Called directly
+    Call,
+    /// Bound to a delegate, to be called later
+    DelegateBinding,
+}
+
+/// A call expression or delegate binding inside a function's script
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallSite {
+    /// Full path of the referenced function
+    pub callee: String,
+    /// Offset of the call or binding expression
+    pub offset: BytecodeOffset,
+    pub kind: CallKind,
+}
+
+/// A set of functions that can reach each other through calls
+#[derive(Debug, Clone)]
+pub struct RecursionCycle {
+    /// Functions in the cycle, sorted by path
+    pub members: Vec<String>,
+    /// Calls between members of the cycle, as (caller, call)
+    pub call_sites: Vec<(String, CallSite)>,
+}
+
+impl RecursionCycle {
+    /// A single function calling itself, as opposed to mutual recursion
+    pub fn is_direct(&self) -> bool {
+        self.members.len() == 1
+    }
+}
+
+/// Calls made by each function, keyed by the caller's full path
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CallGraph {
+    pub calls: BTreeMap<String, Vec<CallSite>>,
+}
+
+impl CallGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the calls and delegate bindings made by a function
+    ///
+    /// `resolve` maps a function reference to the callee's path; references
+    /// it can't resolve are left out of the graph. Delegate bindings only name
+    /// the function, so they're passed to it as [`FunctionRef::ByName`].
+    pub fn add_function(
+        &mut self,
+        function: &str,
+        expressions: &[Expr],
+        resolve: impl Fn(&FunctionRef) -> Option<String>,
+    ) {
+        let sites = self.calls.entry(function.to_string()).or_default();
+        for expr in expressions {
+            expr.walk(&mut |e| {
+                let (func, kind) = match &e.kind {
+                    ExprKind::VirtualFunction { func, .. }
+                    | ExprKind::FinalFunction { func, .. }
+                    | ExprKind::LocalVirtualFunction { func, .. }
+                    | ExprKind::LocalFinalFunction { func, .. }
+                    | ExprKind::CallMath { func, .. } => (func.clone(), CallKind::Call),
+                    ExprKind::BindDelegate {
+                        func_name: name, ..
+                    }
+                    | ExprKind::InstanceDelegate(name) => {
+                        (FunctionRef::ByName(*name), CallKind::DelegateBinding)
+                    }
+                    _ => return,
+                };
+                if let Some(callee) = resolve(&func) {
+                    sites.push(CallSite {
+                        callee,
+                        offset: e.offset,
+                        kind,
+                    });
+                }
+            });
+        }
+    }
+
+    /// Direct calls made by a function, leaving out delegate bindings
+    fn direct_calls<'g>(&'g self, caller: &str) -> impl Iterator<Item = &'g CallSite> {
+        self.calls
+            .get(caller)
+            .into_iter()
+            .flatten()
+            .filter(|site| site.kind == CallKind::Call)
+    }
+
+    /// Groups of functions that can all reach each other through direct calls,
+    /// using Tarjan's algorithm
+    ///
+    /// Every function is in exactly one component; most are on their own.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<&str>> {
+        let mut nodes: Vec<&str> = self.calls.keys().map(String::as_str).collect();
+        for sites in self.calls.values() {
+            for site in sites {
+                if !self.calls.contains_key(&site.callee) {
+                    nodes.push(&site.callee);
+                }
+            }
+        }
+        nodes.sort_unstable();
+        nodes.dedup();
+        let node_index: HashMap<&str, usize> =
+            nodes.iter().enumerate().map(|(i, &n)| (n, i)).collect();
+        let successors: Vec<Vec<usize>> = nodes
+            .iter()
+            .map(|node| {
+                self.direct_calls(node)
+                    .map(|site| node_index[site.callee.as_str()])
+                    .collect()
+            })
+            .collect();
+
+        let mut index = vec![usize::MAX; nodes.len()];
+        let mut lowlink = vec![0; nodes.len()];
+        let mut on_stack = vec![false; nodes.len()];
+        let mut stack = Vec::new();
+        let mut next_index = 0;
+        let mut components = Vec::new();
+
+        for root in 0..nodes.len() {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            // Explicit call stack of (node, next successor to visit) so deep
+            // call chains can't overflow
+            let mut work = vec![(root, 0)];
+            index[root] = next_index;
+            lowlink[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+
+            while let Some((node, next)) = work.last_mut() {
+                let node = *node;
+                if let Some(&succ) = successors[node].get(*next) {
+                    *next += 1;
+                    if index[succ] == usize::MAX {
+                        index[succ] = next_index;
+                        lowlink[succ] = next_index;
+                        next_index += 1;
+                        stack.push(succ);
+                        on_stack[succ] = true;
+                        work.push((succ, 0));
+                    } else if on_stack[succ] {
+                        lowlink[node] = lowlink[node].min(index[succ]);
+                    }
+                    continue;
+                }
+
+                work.pop();
+                if let Some(&(parent, _)) = work.last() {
+                    lowlink[parent] = lowlink[parent].min(lowlink[node]);
+                }
+                if lowlink[node] == index[node] {
+                    let mut component = Vec::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(nodes[member]);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    component.sort_unstable();
+                    components.push(component);
+                }
+            }
+        }
+
+        components
+    }
+
+    /// Functions that call themselves, directly or through other functions
+    pub fn recursion_cycles(&self) -> Vec<RecursionCycle> {
+        let mut cycles: Vec<RecursionCycle> = self
+            .strongly_connected_components()
+            .into_iter()
+            .filter_map(|component| {
+                let call_sites: Vec<(String, CallSite)> = component
+                    .iter()
+                    .flat_map(|&caller| {
+                        self.direct_calls(caller)
+                            .filter(|site| component.contains(&site.callee.as_str()))
+                            .map(move |site| (caller.to_string(), site.clone()))
+                    })
+                    .collect();
+                // A lone function is only a cycle if it calls itself
+                (!call_sites.is_empty()).then(|| RecursionCycle {
+                    members: component.iter().map(|m| m.to_string()).collect(),
+                    call_sites,
+                })
+            })
+            .collect();
+        cycles.sort_by(|a, b| a.members.cmp(&b.members));
+        cycles
+    }
+
+    /// Functions reachable from `function` through direct calls, not including
+    /// itself unless it's recursive
+    pub fn reachable_from(&self, function: &str) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        let mut work: Vec<&str> = vec![function];
+        while let Some(caller) = work.pop() {
+            for site in self.direct_calls(caller) {
+                if seen.insert(site.callee.as_str()) {
+                    work.push(&site.callee);
+                }
+            }
+        }
+        let mut reachable: Vec<&str> = seen.into_iter().collect();
+        reachable.sort_unstable();
+        reachable
+    }
+
+    /// Recorded functions that no other function calls or binds to a delegate
+    pub fn unreferenced(&self) -> Vec<&str> {
+        let referenced: HashSet<&str> = self
+            .calls
+            .iter()
+            .flat_map(|(caller, sites)| {
+                sites
+                    .iter()
+                    .filter(move |site| site.callee != *caller)
+                    .map(|site| site.callee.as_str())
+            })
+            .collect();
+        self.calls
+            .keys()
+            .map(String::as_str)
+            .filter(|function| !referenced.contains(function))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{by_name, call};
+    use crate::bytecode::types::Name;
+
+    #[test]
+    fn test_direct_and_mutual_recursion() {
+        let mut graph = CallGraph::new();
+        graph.add_function("Fib", &[call(0, "Fib"), call(10, "Log")], by_name);
+        graph.add_function("Ping", &[call(4, "Pong")], by_name);
+        graph.add_function("Pong", &[call(8, "Ping")], by_name);
+        graph.add_function("Main", &[call(0, "Ping"), call(10, "Fib")], by_name);
+
+        let cycles = graph.recursion_cycles();
+        assert_eq!(cycles.len(), 2);
+
+        assert_eq!(cycles[0].members, vec!["Fib"]);
+        assert!(cycles[0].is_direct());
+        assert_eq!(cycles[0].call_sites.len(), 1);
+
+        assert_eq!(cycles[1].members, vec!["Ping", "Pong"]);
+        assert!(!cycles[1].is_direct());
+        let sites: Vec<_> = cycles[1]
+            .call_sites
+            .iter()
+            .map(|(caller, site)| (caller.as_str(), site.callee.as_str()))
+            .collect();
+        assert_eq!(sites, vec![("Ping", "Pong"), ("Pong", "Ping")]);
+    }
+
+    #[test]
+    fn test_unreferenced_ignores_self_calls_but_counts_bindings() {
+        let bind = Expr::new(
+            BytecodeOffset::new(20),
+            ExprKind::InstanceDelegate(Name::new("OnHit")),
+        );
+        let mut graph = CallGraph::new();
+        graph.add_function("Main", &[call(0, "Helper"), bind], by_name);
+        graph.add_function("Helper", &[], by_name);
+        graph.add_function("OnHit", &[], by_name);
+        graph.add_function("Loop", &[call(0, "Loop")], by_name);
+
+        assert_eq!(graph.unreferenced(), vec!["Loop", "Main"]);
+        // Binding a delegate doesn't make a cycle
+        assert!(
+            graph
+                .recursion_cycles()
+                .iter()
+                .all(|c| c.members != ["OnHit"])
+        );
+    }
+}
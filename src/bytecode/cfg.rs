@@ -1,14 +1,98 @@
 use crate::bytecode::address_index::AddressIndex;
 
-use super::expr::{Expr, ExprKind};
+use super::dominators::DominatorTree;
+use super::expr::{Expr, ExprKind, collect_referenced_offsets};
 use super::logger::{Logger, NullLogger};
-use super::types::BytecodeOffset;
+use super::loops::LoopInfo;
+use super::refs::FunctionRef;
+use super::regions::{
+    COLLAPSE_THRESHOLD, CollapsibleRegion, RegionKind, collapsed_blocks, collapsible_regions,
+};
+use super::trace::TraceCoverage;
+use super::types::{BytecodeOffset, Name};
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use tracing::info_span;
+
+/// Fill colors of loops in DOT output, by how deeply they're nested
+const LOOP_FILLS: [&str; 4] = ["#eef3fb", "#dbe6f6", "#c6d7f0", "#b1c8ea"];
 
 /// Unique identifier for a basic block
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct BlockId(pub usize);
 
+/// Name of a block for output, from the offset it starts at (`Block_0x1A4`)
+///
+/// Block ids are positions, so they shift whenever a change to parsing adds
+/// or removes a block earlier in the function; offsets don't. Blocks the
+/// analyses add at an offset that already starts a block, such as loop
+/// pre-headers, are numbered after it (`Block_0x1A4_1`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockName {
+    offset: BytecodeOffset,
+    index: usize,
+}
+
+impl BlockName {
+    /// Offset of the block's first instruction
+    pub fn offset(&self) -> BytecodeOffset {
+        self.offset
+    }
+}
+
+impl fmt::Display for BlockName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            0 => write!(f, "Block_{}", self.offset),
+            index => write!(f, "Block_{}_{}", self.offset, index),
+        }
+    }
+}
+
+/// Depth-first postorder over a graph of blocks, starting from each root in turn
+///
+/// `neighbors` returns the blocks to visit after a given block, or `None` if
+/// the block isn't part of the graph and should be skipped entirely. Uses an
+/// explicit stack so long chains of blocks can't overflow the call stack.
+pub fn dfs_postorder<'g>(
+    roots: impl IntoIterator<Item = BlockId>,
+    neighbors: impl Fn(BlockId) -> Option<&'g [BlockId]>,
+) -> Vec<BlockId> {
+    let mut visited = HashSet::new();
+    let mut postorder = Vec::new();
+    // (block, its neighbors, index of the next neighbor to visit)
+    let mut stack: Vec<(BlockId, &'g [BlockId], usize)> = Vec::new();
+
+    for root in roots {
+        if visited.contains(&root) {
+            continue;
+        }
+        let Some(root_neighbors) = neighbors(root) else {
+            continue;
+        };
+        visited.insert(root);
+        stack.push((root, root_neighbors, 0));
+
+        while let Some((block, block_neighbors, next)) = stack.last_mut() {
+            if let Some(&succ) = block_neighbors.get(*next) {
+                *next += 1;
+                if visited.contains(&succ) {
+                    continue;
+                }
+                if let Some(succ_neighbors) = neighbors(succ) {
+                    visited.insert(succ);
+                    stack.push((succ, succ_neighbors, 0));
+                }
+            } else {
+                postorder.push(*block);
+                stack.pop();
+            }
+        }
+    }
+
+    postorder
+}
+
 /// Represents a unique (block, stack) state for flow-sensitive CFG analysis
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct BlockStackState {
@@ -17,15 +101,18 @@ struct BlockStackState {
 }
 
 /// Control flow terminator - the control flow decision at the end of a basic block
+///
+/// `E` is what a branch tests and a return returns: a Kismet [`Expr`] for a
+/// [`BasicBlock`], whatever another graph's code uses otherwise.
 #[derive(Debug, Clone)]
-pub enum Terminator {
+pub enum Terminator<E = Expr> {
     /// Unconditional jump to target block
     Goto { target: BlockId },
 
     /// Conditional branch: if !condition goto false_target else true_target
     /// After flow analysis, both targets are statically known
     Branch {
-        condition: Expr,
+        condition: E,
         true_target: BlockId,
         false_target: BlockId,
     },
@@ -36,7 +123,10 @@ pub enum Terminator {
     DynamicJump,
 
     /// Return from function
-    Return(Expr),
+    Return(E),
+
+    /// Execution stops; only used by the synthetic block failed assertions lead to
+    Abort,
 
     /// No terminator - used when control flow is implicit (e.g., loop bodies)
     None,
@@ -80,6 +170,18 @@ impl BasicBlock {
     }
 }
 
+/// Options controlling how a CFG is built from expressions
+#[derive(Default, Debug, Clone, Copy)]
+pub struct CfgOptions {
+    /// Turn statements on fail-silent contexts (`obj?->Foo()`) into an explicit
+    /// null check so the skip shows up as a branch in the graph
+    pub split_fail_silent_contexts: bool,
+    /// End blocks at `Assert` statements and give each a "check failed" edge
+    /// to a shared abort block, so post-dominance doesn't treat code after an
+    /// assertion as always reached
+    pub model_asserts: bool,
+}
+
 /// Control Flow Graph - represents the control flow structure of bytecode
 #[derive(Debug, Clone)]
 pub struct ControlFlowGraph {
@@ -97,6 +199,25 @@ impl ControlFlowGraph {
 
     /// Build a CFG from a flat list of expressions with a custom logger
     pub fn from_expressions_with_logger(expressions: &[Expr], logger: &dyn Logger) -> Self {
+        Self::from_expressions_with_options(expressions, &CfgOptions::default(), logger)
+    }
+
+    /// Build a CFG from a flat list of expressions with custom options and logger
+    pub fn from_expressions_with_options(
+        expressions: &[Expr],
+        options: &CfgOptions,
+        logger: &dyn Logger,
+    ) -> Self {
+        let _span = info_span!("cfg").entered();
+        if options.split_fail_silent_contexts {
+            let lowered = Self::lower_fail_silent_contexts(expressions);
+            Self::build(&lowered, options, logger)
+        } else {
+            Self::build(expressions, options, logger)
+        }
+    }
+
+    fn build(expressions: &[Expr], options: &CfgOptions, logger: &dyn Logger) -> Self {
         if expressions.is_empty() {
             return Self {
                 blocks: Vec::new(),
@@ -106,10 +227,11 @@ impl ControlFlowGraph {
         }
 
         // Step 1: Identify leaders (first instruction of each basic block)
-        let leaders = Self::identify_leaders(expressions);
+        let leaders = Self::identify_leaders(expressions, options.model_asserts);
 
         // Step 2: Create basic blocks
-        let (blocks, offset_to_block) = Self::create_basic_blocks(expressions, &leaders);
+        let (blocks, offset_to_block) =
+            Self::create_basic_blocks(expressions, &leaders, options.model_asserts);
 
         // Step 3: Build edges between blocks
         let blocks = Self::build_edges(expressions, blocks, &offset_to_block, logger);
@@ -121,76 +243,92 @@ impl ControlFlowGraph {
         }
     }
 
+    /// Rewrite top-level fail-silent context statements into an explicit null check
+    ///
+    /// When the context object is null the VM skips the whole r-value, which for a
+    /// statement means skipping to the next statement. `obj?->Foo()` at offset N is
+    /// rewritten to `if (!IsValid(obj)) goto next` at offset N followed by
+    /// `obj->Foo()` at the offset of the r-value, so jumps to N still land on the check.
+    fn lower_fail_silent_contexts(expressions: &[Expr]) -> Vec<Expr> {
+        let mut lowered = Vec::with_capacity(expressions.len());
+
+        for (i, expr) in expressions.iter().enumerate() {
+            let (
+                ExprKind::Context {
+                    object,
+                    field,
+                    context,
+                    skip_offset,
+                    fail_silent: true,
+                },
+                Some(next),
+            ) = (&expr.kind, expressions.get(i + 1))
+            else {
+                lowered.push(expr.clone());
+                continue;
+            };
+
+            let is_valid = Expr::new(
+                object.offset,
+                ExprKind::VirtualFunction {
+                    func: FunctionRef::ByName(Name::new("IsValid")),
+                    params: vec![(**object).clone()],
+                },
+            )
+            .with_end(object.end);
+            lowered.push(Expr::new(
+                expr.offset,
+                ExprKind::JumpIfNot {
+                    condition: Box::new(is_valid),
+                    target: next.offset,
+                },
+            ));
+            lowered.push(
+                Expr::new(
+                    context.offset,
+                    ExprKind::Context {
+                        object: object.clone(),
+                        field: *field,
+                        context: context.clone(),
+                        skip_offset: *skip_offset,
+                        fail_silent: false,
+                    },
+                )
+                .with_end(context.end),
+            );
+        }
+
+        lowered
+    }
+
     /// Identify leader instructions (start of basic blocks)
     /// Leaders are:
     /// 1. The first instruction
-    /// 2. Any instruction that is a jump target
-    /// 3. Any instruction immediately following a jump/branch
-    fn identify_leaders(expressions: &[Expr]) -> HashSet<BytecodeOffset> {
-        let mut leaders = HashSet::new();
+    /// 2. Any referenced code offset (jump, switch and push targets, latent
+    ///    resume points), wherever the reference appears
+    /// 3. Any instruction immediately following a jump/branch (or an assert,
+    ///    when asserts are modeled)
+    fn identify_leaders(expressions: &[Expr], model_asserts: bool) -> HashSet<BytecodeOffset> {
+        let mut leaders = collect_referenced_offsets(expressions);
 
         // First instruction is always a leader
         if let Some(first) = expressions.first() {
             leaders.insert(first.offset);
         }
 
-        // Find all jump targets and instructions after jumps
-        for (i, expr) in expressions.iter().enumerate() {
-            match &expr.kind {
-                ExprKind::Jump { target } => {
-                    // Target is a leader
-                    leaders.insert(*target);
-                    // Instruction after jump is a leader (if it exists)
-                    if let Some(next) = expressions.get(i + 1) {
-                        leaders.insert(next.offset);
-                    }
-                }
-                ExprKind::JumpIfNot { target, .. } => {
-                    // Target is a leader
-                    leaders.insert(*target);
-                    // Instruction after conditional jump is a leader (fallthrough)
-                    if let Some(next) = expressions.get(i + 1) {
-                        leaders.insert(next.offset);
-                    }
-                }
-                ExprKind::SwitchValue {
-                    cases, end_offset, ..
-                } => {
-                    // All case targets are leaders
-                    for case in cases {
-                        leaders.insert(case.case_offset);
-                        leaders.insert(case.next_offset);
-                    }
-                    // End offset is a leader
-                    leaders.insert(*end_offset);
-                    // Default case (next instruction) is a leader
-                    if let Some(next) = expressions.get(i + 1) {
-                        leaders.insert(next.offset);
-                    }
-                }
-                ExprKind::PushExecutionFlow { push_offset } => {
-                    // Target is a leader
-                    leaders.insert(*push_offset);
-                }
-                ExprKind::PopExecutionFlow => {
-                    // Unconditional jump to stack top - instruction after is a leader
-                    if let Some(next) = expressions.get(i + 1) {
-                        leaders.insert(next.offset);
-                    }
-                }
-                ExprKind::PopExecutionFlowIfNot { .. } => {
-                    // Conditional jump to stack top - instruction after is a leader (fallthrough)
-                    if let Some(next) = expressions.get(i + 1) {
-                        leaders.insert(next.offset);
-                    }
-                }
-                ExprKind::Return(_) => {
-                    // Instruction after return is a leader (if it exists)
-                    if let Some(next) = expressions.get(i + 1) {
-                        leaders.insert(next.offset);
-                    }
-                }
-                _ => {}
+        // Instructions following a control flow transfer start a new block
+        for (expr, next) in expressions.iter().zip(expressions.iter().skip(1)) {
+            if matches!(
+                expr.kind,
+                ExprKind::Jump { .. }
+                    | ExprKind::JumpIfNot { .. }
+                    | ExprKind::SwitchValue { .. }
+                    | ExprKind::PopExecutionFlow
+                    | ExprKind::PopExecutionFlowIfNot { .. }
+                    | ExprKind::Return(_)
+            ) || (model_asserts && matches!(expr.kind, ExprKind::Assert { .. }))
+            {
+                leaders.insert(next.offset);
             }
         }
 
@@ -201,6 +339,7 @@ impl ControlFlowGraph {
     fn create_basic_blocks(
         expressions: &[Expr],
         leaders: &HashSet<BytecodeOffset>,
+        model_asserts: bool,
     ) -> (Vec<BasicBlock>, HashMap<BytecodeOffset, BlockId>) {
         let mut blocks = Vec::new();
         let mut offset_to_block = HashMap::new();
@@ -230,7 +369,8 @@ impl ControlFlowGraph {
                         | ExprKind::PopExecutionFlow
                         | ExprKind::PopExecutionFlowIfNot { .. }
                         | ExprKind::Return(_)
-                );
+                ) || (model_asserts
+                    && matches!(expr.kind, ExprKind::Assert { .. }));
 
                 if is_terminator {
                     // Store the terminator expression for later resolution
@@ -253,6 +393,40 @@ impl ControlFlowGraph {
         (blocks, offset_to_block)
     }
 
+    /// Resolve a code offset to the block it transfers control to
+    ///
+    /// Targets normally land on a block leader. A target inside a decoded
+    /// expression (misdecoded or hand-crafted bytecode) resolves to the block
+    /// holding that expression instead of silently losing the edge. Targets
+    /// past the last expression don't resolve.
+    fn resolve_target(
+        target: BytecodeOffset,
+        expressions: &[Expr],
+        blocks: &[BasicBlock],
+        offset_to_block: &HashMap<BytecodeOffset, BlockId>,
+        logger: &dyn Logger,
+    ) -> Option<BlockId> {
+        if let Some(&block_id) = offset_to_block.get(&target) {
+            return Some(block_id);
+        }
+
+        if expressions.last().is_none_or(|last| target > last.offset) {
+            logger.warn(&format!(
+                "Jump target {} is past the end of the script",
+                target
+            ));
+            return None;
+        }
+
+        let containing = blocks.partition_point(|block| block.start_offset <= target);
+        let block = blocks.get(containing.checked_sub(1)?)?;
+        logger.warn(&format!(
+            "Jump target {} is inside an expression, resolving to block {:?}",
+            target, block.id
+        ));
+        Some(block.id)
+    }
+
     /// Helper to find the next block after the given block's end offset
     fn find_next_block(
         block_end_offset: BytecodeOffset,
@@ -261,7 +435,7 @@ impl ControlFlowGraph {
     ) -> Option<BlockId> {
         // Find the first expression after block_end_offset
         for expr in expressions {
-            if expr.offset.as_usize() > block_end_offset.as_usize() {
+            if expr.offset > block_end_offset {
                 return offset_to_block.get(&expr.offset).copied();
             }
         }
@@ -290,6 +464,22 @@ impl ControlFlowGraph {
         // Track which (block, stack) combinations we've already processed
         let mut visited: HashSet<BlockStackState> = HashSet::new();
 
+        // Failed assertions all lead to one synthetic block, appended once the
+        // real blocks are done so it never takes part in offset lookups
+        let abort_block = blocks
+            .iter()
+            .any(|block| {
+                matches!(
+                    block.terminator_expr.as_ref().map(|expr| &expr.kind),
+                    Some(ExprKind::Assert { .. })
+                )
+            })
+            .then_some(BlockId(blocks.len()));
+
+        let resolve = |target: BytecodeOffset| {
+            Self::resolve_target(target, expressions, &blocks, offset_to_block, logger)
+        };
+
         // Start with entry block and empty stack
         worklist.push((BlockId(0), Vec::new()));
 
@@ -334,29 +524,8 @@ impl ControlFlowGraph {
 
             // Process statements in the block to update stack state
             for stmt in &block.statements {
-                match &stmt.kind {
-                    ExprKind::PushExecutionFlow { push_offset } => {
-                        stack.push(*push_offset);
-                    }
-                    ExprKind::SwitchValue {
-                        cases, end_offset, ..
-                    } => {
-                        // Switch is a statement, not a terminator
-                        // Multiple successors
-                        let mut targets = HashSet::new();
-                        for case in cases {
-                            targets.insert(case.case_offset);
-                        }
-                        targets.insert(*end_offset);
-
-                        for target in targets {
-                            if let Some(&target_block) = offset_to_block.get(&target) {
-                                block_successors.push(target_block);
-                                worklist.push((target_block, stack.clone()));
-                            }
-                        }
-                    }
-                    _ => {}
+                if let ExprKind::PushExecutionFlow { push_offset } = &stmt.kind {
+                    stack.push(*push_offset);
                 }
             }
 
@@ -365,7 +534,7 @@ impl ControlFlowGraph {
                 match &term_expr.kind {
                     ExprKind::Jump { target } => {
                         // Unconditional jump
-                        if let Some(&target_block) = offset_to_block.get(target) {
+                        if let Some(target_block) = resolve(*target) {
                             block_successors.push(target_block);
                             worklist.push((target_block, stack.clone()));
                         }
@@ -373,7 +542,7 @@ impl ControlFlowGraph {
                     ExprKind::JumpIfNot { target, .. } => {
                         // Conditional branch
                         // false branch: jump to target
-                        if let Some(&false_target) = offset_to_block.get(target) {
+                        if let Some(false_target) = resolve(*target) {
                             block_successors.push(false_target);
                             worklist.push((false_target, stack.clone()));
                         }
@@ -389,7 +558,7 @@ impl ControlFlowGraph {
                         // Conditional pop
                         // 1. If condition is false, pop and jump to stack top
                         if let Some(&target_offset) = stack.last()
-                            && let Some(&target_block) = offset_to_block.get(&target_offset)
+                            && let Some(target_block) = resolve(target_offset)
                         {
                             block_successors.push(target_block);
                             let mut popped_stack = stack.clone();
@@ -408,17 +577,40 @@ impl ControlFlowGraph {
                     ExprKind::PopExecutionFlow => {
                         // Unconditional pop from stack
                         if let Some(target_offset) = stack.pop()
-                            && let Some(&target_block) = offset_to_block.get(&target_offset)
+                            && let Some(target_block) = resolve(target_offset)
                         {
                             block_successors.push(target_block);
                             worklist.push((target_block, stack.clone()));
                         }
                     }
+                    ExprKind::Assert { .. } => {
+                        // Check failed: execution stops
+                        if let Some(abort_block) = abort_block {
+                            block_successors.push(abort_block);
+                        }
+                        // Check passed: fallthrough
+                        if let Some(next_block) =
+                            Self::find_next_block(block.end_offset, expressions, offset_to_block)
+                        {
+                            block_successors.push(next_block);
+                            worklist.push((next_block, stack.clone()));
+                        }
+                    }
                     ExprKind::Return(_) => {
                         // No successors - exit block
                     }
                     _ => unreachable!("Invalid terminator expression"),
                 }
+            } else if let Some(ExprKind::SwitchValue { end_offset, .. }) =
+                block.statements.last().map(|stmt| &stmt.kind)
+            {
+                // Whichever case a switch in statement position picks, the
+                // VM carries on at its end offset. The parser reports one
+                // that isn't just past the switch, but it's still followed.
+                if let Some(next_block) = resolve(*end_offset) {
+                    block_successors.push(next_block);
+                    worklist.push((next_block, stack.clone()));
+                }
             } else {
                 // No terminator expression - fallthrough to next block
                 if let Some(next_block) =
@@ -461,22 +653,28 @@ impl ControlFlowGraph {
             predecessors.retain(|&x| seen.insert(x));
         }
 
+        // Resolve jump targets up front, including for blocks the flow analysis never reached
+        let jump_targets: HashMap<BlockId, Option<BlockId>> = blocks
+            .iter()
+            .filter_map(|block| match &block.terminator_expr.as_ref()?.kind {
+                ExprKind::Jump { target } => Some((block.id, resolve(*target))),
+                _ => None,
+            })
+            .collect();
+
         // Now construct final Terminator enums based on terminator_expr and computed successors
         for block in &mut blocks {
             let successors = successors_map.get(&block.id).cloned().unwrap_or_default();
 
             block.terminator = if let Some(ref term_expr) = block.terminator_expr {
                 match &term_expr.kind {
-                    ExprKind::Jump { target } => {
-                        // Convert bytecode offset to block ID
-                        let target_block = offset_to_block
-                            .get(target)
-                            .copied()
-                            .expect("jump target must be a valid block");
-                        Terminator::Goto {
-                            target: target_block,
-                        }
-                    }
+                    ExprKind::Jump { .. } => match jump_targets.get(&block.id) {
+                        Some(Some(target_block)) => Terminator::Goto {
+                            target: *target_block,
+                        },
+                        // Unresolvable target, already reported
+                        _ => Terminator::DynamicJump,
+                    },
                     ExprKind::JumpIfNot { condition, .. } => {
                         // Should have exactly 2 successors: false target and true target (fallthrough)
                         if successors.len() == 2 {
@@ -521,6 +719,18 @@ impl ControlFlowGraph {
                             Terminator::DynamicJump
                         }
                     }
+                    ExprKind::Assert { condition, .. } => {
+                        // Ordered [abort, fallthrough] like JumpIfNot's [false, true]
+                        if successors.len() == 2 {
+                            Terminator::Branch {
+                                condition: *condition.clone(),
+                                false_target: successors[0],
+                                true_target: successors[1],
+                            }
+                        } else {
+                            Terminator::DynamicJump
+                        }
+                    }
                     ExprKind::Return(val) => Terminator::Return(*val.clone()),
                     _ => unreachable!("Invalid terminator expression"),
                 }
@@ -547,6 +757,16 @@ impl ControlFlowGraph {
             block.predecessors = predecessors_map.get(&block.id).cloned().unwrap_or_default();
         }
 
+        if let Some(abort_block) = abort_block {
+            let end_offset = blocks
+                .last()
+                .map_or(BytecodeOffset(0), |block| block.end_offset);
+            let mut block = BasicBlock::new(abort_block, end_offset);
+            block.terminator = Terminator::Abort;
+            block.predecessors = predecessors_map.remove(&abort_block).unwrap_or_default();
+            blocks.push(block);
+        }
+
         blocks
     }
 
@@ -555,6 +775,23 @@ impl ControlFlowGraph {
         self.blocks.get(id.0)
     }
 
+    /// Name of every block, indexed by block id
+    pub fn block_names(&self) -> Vec<BlockName> {
+        let mut seen: HashMap<BytecodeOffset, usize> = HashMap::new();
+        self.blocks
+            .iter()
+            .map(|block| {
+                let count = seen.entry(block.start_offset).or_default();
+                let name = BlockName {
+                    offset: block.start_offset,
+                    index: *count,
+                };
+                *count += 1;
+                name
+            })
+            .collect()
+    }
+
     /// Get the block that contains a given bytecode offset
     pub fn get_block_at_offset(&self, offset: BytecodeOffset) -> Option<&BasicBlock> {
         self.offset_to_block
@@ -562,72 +799,97 @@ impl ControlFlowGraph {
             .and_then(|id| self.get_block(*id))
     }
 
-    /// Print CFG in a human-readable format
-    pub fn print_debug(&self, _expressions: &[Expr], address_index: &AddressIndex) {
-        use crate::formatters::cpp::CppFormatter;
-
-        println!("Control Flow Graph:");
-        println!("  Entry Block: {:?}", self.entry_block);
-        println!("  Total Blocks: {}", self.blocks.len());
-        println!();
+    /// Write the CFG in a human-readable format
+    pub fn write_debug(
+        &self,
+        out: &mut dyn fmt::Write,
+        address_index: &AddressIndex,
+    ) -> fmt::Result {
+        use crate::formatters::cpp::{ExprRenderer, StatementWriter};
+
+        let names = self.block_names();
+        let list = |blocks: &[BlockId]| {
+            let names: Vec<String> = blocks.iter().map(|id| names[id.0].to_string()).collect();
+            format!("[{}]", names.join(", "))
+        };
+
+        let renderer = ExprRenderer::new(address_index);
+        let mut writer = StatementWriter::new(&renderer, Default::default());
+        writer.set_indent_level(2);
+        writeln!(out, "Control Flow Graph:")?;
+        writeln!(out, "  Entry Block: {}", names[self.entry_block.0])?;
+        writeln!(out, "  Total Blocks: {}", self.blocks.len())?;
+        writeln!(out)?;
 
         for block in &self.blocks {
-            println!(
-                "Block {:?} [0x{:X}..0x{:X}]:",
-                block.id,
-                block.start_offset.as_usize(),
-                block.end_offset.as_usize()
-            );
-            println!("  Predecessors: {:?}", block.predecessors);
-            println!("  Successors: {:?}", block.successors);
-            println!("  Statements:");
-            let mut formatter = CppFormatter::new(address_index, Default::default());
-            formatter.set_indent_level(2);
+            writeln!(
+                out,
+                "{} [{}..{}]:",
+                names[block.id.0], block.start_offset, block.end_offset
+            )?;
+            writeln!(out, "  Predecessors: {}", list(&block.predecessors))?;
+            writeln!(out, "  Successors: {}", list(&block.successors))?;
+            writeln!(out, "  Statements:")?;
 
             for stmt in &block.statements {
-                print!("    0x{:X}: ", stmt.offset.as_usize());
-                formatter.format_statement(stmt);
+                write!(out, "    {}: ", stmt.offset)?;
+                writer.format_statement(out, stmt)?;
             }
 
             // Print terminator
             match &block.terminator {
                 Terminator::Goto { target } => {
-                    println!("    [goto {:?}]", target);
+                    writeln!(out, "    [goto {}]", names[target.0])?;
                 }
                 Terminator::Branch {
                     true_target,
                     false_target,
                     ..
                 } => {
-                    println!(
-                        "    [branch: if !(...) goto {:?} else {:?}]",
-                        false_target, true_target
-                    );
+                    writeln!(
+                        out,
+                        "    [branch: if !(...) goto {} else {}]",
+                        names[false_target.0], names[true_target.0]
+                    )?;
                 }
                 Terminator::DynamicJump => {
-                    println!("    [dynamic-jump]");
+                    writeln!(out, "    [dynamic-jump]")?;
                 }
                 Terminator::Return(expr) => {
-                    print!("    [return ");
-                    formatter.format_statement(expr);
-                    print!("]");
-                    println!();
+                    write!(out, "    [return ")?;
+                    writer.format_statement(out, expr)?;
+                    write!(out, "]")?;
+                    writeln!(out)?;
+                }
+                Terminator::Abort => {
+                    writeln!(out, "    [abort]")?;
                 }
                 Terminator::None => unreachable!(),
             }
-            println!();
+            writeln!(out)?;
         }
+        Ok(())
     }
 
     /// Generate a DOT graph representation of the CFG
+    ///
+    /// Loops are drawn as boxes shaded darker the deeper they're nested, with
+    /// their back edges dashed, and the entry and exit blocks get a thick
+    /// border; a legend node explains the colors. With a trace's coverage,
+    /// blocks that ran are highlighted and the edges taken are drawn bold and
+    /// labeled with how often they were taken.
     pub fn to_dot(
         &self,
         _expressions: &[Expr],
-        _address_index: &AddressIndex,
+        address_index: &AddressIndex,
+        coverage: Option<&TraceCoverage>,
+        expanded_regions: &[usize],
+        normalize_addresses: bool,
     ) -> crate::dot::Graph {
         use crate::dot::{Edge, Graph, Node, XmlTag};
 
         let mut graph = Graph::new("digraph");
+        let names = self.block_names();
 
         // Set graph attributes
         graph.base.graph_attributes.add("rankdir", "TB");
@@ -635,21 +897,67 @@ impl ControlFlowGraph {
         graph.base.node_attributes.add("fontname", "monospace");
         graph.base.node_attributes.add("fontsize", "10");
 
+        let loop_info = LoopInfo::analyze(self, &DominatorTree::compute(self));
+        let back_edges: HashSet<(BlockId, BlockId)> = loop_info
+            .loops
+            .iter()
+            .flat_map(|l| l.back_edges.iter().copied())
+            .collect();
+        let regions = match self.blocks.len() > COLLAPSE_THRESHOLD {
+            true => collapsible_regions(self, &loop_info),
+            false => Vec::new(),
+        };
+        let collapsed = collapsed_blocks(&regions, expanded_regions);
+        let node_id = |block: BlockId| match collapsed.get(&block) {
+            Some(region) => format!("Region_{}", region.number),
+            None => names[block.0].to_string(),
+        };
+
+        // Each block is drawn inside the innermost loop it's part of
+        let mut loop_nodes = vec![Vec::new(); loop_info.loops.len()];
+        let mut place = |block: BlockId, node: Node, top: &mut Vec<Node>| {
+            let innermost = loop_info
+                .loops
+                .iter()
+                .enumerate()
+                .filter(|(_, l)| l.blocks.contains(&block))
+                .min_by_key(|(_, l)| l.blocks.len());
+            match innermost {
+                Some((index, _)) => loop_nodes[index].push(node),
+                None => top.push(node),
+            }
+        };
+
         // Create a node for each block
         for block in &self.blocks {
+            if collapsed.contains_key(&block.id) {
+                continue;
+            }
             let mut table = XmlTag::new("TABLE")
-                .attr("BORDER", "0")
                 .attr("CELLBORDER", "1")
                 .attr("CELLSPACING", "0")
                 .attr("CELLPADDING", "4");
-
-            // Header row with block ID
-            let bgcolor = if block.id == self.entry_block {
-                "lightgreen"
+            table = if block.id == self.entry_block {
+                table.attr("BORDER", "3").attr("COLOR", "darkgreen")
             } else if block.successors.is_empty() {
-                "lightcoral"
+                table.attr("BORDER", "3").attr("COLOR", "darkred")
             } else {
-                "lightyellow"
+                table.attr("BORDER", "0")
+            };
+
+            // Header row with block ID
+            let hits = coverage.map(|c| c.block_hits.get(&block.id).copied().unwrap_or(0));
+            let bgcolor = match hits {
+                Some(0) => "white",
+                Some(_) => "gold",
+                None if block.id == self.entry_block => "lightgreen",
+                None if block.successors.is_empty() => "lightcoral",
+                None => "lightyellow",
+            };
+            let name = names[block.id.0];
+            let header = match hits {
+                Some(hits) => format!("{} ({} hits)", name, hits),
+                None => name.to_string(),
             };
 
             table = table.child(
@@ -657,7 +965,7 @@ impl ControlFlowGraph {
                     XmlTag::new("TD")
                         .attr("BGCOLOR", bgcolor)
                         .attr("ALIGN", "center")
-                        .child(format!("Block {:?}", block.id)),
+                        .child(header),
                 ),
             );
 
@@ -667,20 +975,17 @@ impl ControlFlowGraph {
                     XmlTag::new("TD")
                         .attr("ALIGN", "left")
                         .attr("BGCOLOR", "lightgray")
-                        .child(format!(
-                            "0x{:X}..0x{:X}",
-                            block.start_offset.as_usize(),
-                            block.end_offset.as_usize()
-                        )),
+                        .child(format!("{}..{}", block.start_offset, block.end_offset)),
                 ),
             );
 
             // Add statements
             for stmt in &block.statements {
+                let normalize = normalize_addresses.then_some(address_index);
                 let instr_text = format!(
-                    "0x{:X}: {}",
-                    stmt.offset.as_usize(),
-                    Self::format_expr_simple(stmt)
+                    "{}: {}",
+                    stmt.offset,
+                    Self::format_expr_simple(stmt, normalize)
                 );
 
                 table = table.child(
@@ -695,14 +1000,18 @@ impl ControlFlowGraph {
 
             // Add terminator
             let term_text = match &block.terminator {
-                Terminator::Goto { target } => format!("[goto {:?}]", target),
+                Terminator::Goto { target } => format!("[goto {}]", names[target.0]),
                 Terminator::Branch {
                     true_target,
                     false_target,
                     ..
-                } => format!("[branch {true_target:?} / {false_target:?}]"),
+                } => format!(
+                    "[branch {} / {}]",
+                    names[true_target.0], names[false_target.0]
+                ),
                 Terminator::DynamicJump => "[dynamic-jump]".to_string(),
                 Terminator::Return(_) => "[return]".to_string(),
+                Terminator::Abort => "[abort]".to_string(),
                 Terminator::None => unreachable!(),
             };
 
@@ -716,19 +1025,76 @@ impl ControlFlowGraph {
                 ),
             );
 
-            let node_id = format!("block_{}", block.id.0);
-            graph.base.nodes.push(Node::new_attr(
-                &node_id,
-                [("label", crate::dot::Id::Html(table.into()))],
-            ));
+            let label = crate::dot::Id::Html(table.into());
+            let node = Node::new_attr(node_id(block.id), [("label", label)]);
+            place(block.id, node, &mut graph.base.nodes);
         }
+        for region in &regions {
+            if collapsed
+                .get(&region.entry)
+                .is_some_and(|shown| shown.number == region.number)
+            {
+                let node = self.region_summary(region, &names);
+                place(region.entry, node, &mut graph.base.nodes);
+            }
+        }
+        for (index, l) in loop_info.loops.iter().enumerate() {
+            if l.parent.is_none()
+                && let Some(cluster) =
+                    Self::loop_cluster(&loop_info, index, &names, &mut loop_nodes)
+            {
+                graph.base.subgraphs.push(cluster);
+            }
+        }
+        let max_depth = loop_info
+            .loops
+            .iter()
+            .map(|l| l.nesting_depth(&loop_info.loops) + 1)
+            .max()
+            .unwrap_or(0);
+        graph
+            .base
+            .nodes
+            .push(Self::dot_legend(coverage.is_some(), max_depth));
 
-        // Add edges for successors
+        // Add edges for successors; a collapsed region gets each edge into
+        // or out of it once
+        let mut drawn = HashSet::new();
         for block in &self.blocks {
-            let from_id = format!("block_{}", block.id.0);
+            let from_id = node_id(block.id);
             for &succ in &block.successors {
-                let to_id = format!("block_{}", succ.0);
-                graph.base.edges.push(Edge::new(from_id.clone(), to_id));
+                let to_id = node_id(succ);
+                if collapsed.contains_key(&block.id) || collapsed.contains_key(&succ) {
+                    if from_id != to_id && drawn.insert((from_id.clone(), to_id.clone())) {
+                        graph.base.edges.push(Edge::new(from_id.clone(), to_id));
+                    }
+                    continue;
+                }
+                let taken = coverage.map(|c| c.edge_hits.get(&(block.id, succ)).copied());
+                let mut attributes = match taken {
+                    Some(Some(count)) => vec![
+                        ("color", "red".to_string()),
+                        ("penwidth", "2".to_string()),
+                        ("label", count.to_string()),
+                    ],
+                    Some(None) => vec![
+                        ("style", "dashed".to_string()),
+                        ("color", "gray".to_string()),
+                    ],
+                    None => Vec::new(),
+                };
+                if back_edges.contains(&(block.id, succ)) {
+                    if taken.is_none() {
+                        attributes.push(("color", "red".to_string()));
+                    }
+                    if !matches!(taken, Some(None)) {
+                        attributes.push(("style", "dashed".to_string()));
+                    }
+                }
+                graph
+                    .base
+                    .edges
+                    .push(Edge::new_attr(from_id.clone(), to_id, attributes));
                 // graph.base.edges.push(Edge::new_compass(
                 //     from_id.clone(),
                 //     Some("s"), // south (bottom) of source
@@ -742,9 +1108,284 @@ impl ControlFlowGraph {
         graph
     }
 
-    /// Simple expression formatter for DOT labels
-    fn format_expr_simple(expr: &Expr) -> String {
-        let debug_str = format!("{:?}", expr.kind);
+    /// The box a loop's blocks and nested loops are drawn in
+    fn loop_cluster(
+        loop_info: &LoopInfo,
+        index: usize,
+        names: &[BlockName],
+        loop_nodes: &mut [Vec<crate::dot::Node>],
+    ) -> Option<crate::dot::Subgraph> {
+        let l = &loop_info.loops[index];
+        let depth = l.nesting_depth(&loop_info.loops);
+        let mut cluster = crate::dot::Subgraph {
+            id: Some(format!("cluster_loop_{}", index)),
+            ..Default::default()
+        };
+        let attributes = &mut cluster.base.graph_attributes;
+        attributes.add("label", format!("loop at {}", names[l.header.0]));
+        attributes.add("style", "filled");
+        attributes.add("color", "gray");
+        attributes.add("fillcolor", LOOP_FILLS[depth.min(LOOP_FILLS.len() - 1)]);
+        cluster.base.nodes = std::mem::take(&mut loop_nodes[index]);
+        for &child in &l.children {
+            if let Some(child) = Self::loop_cluster(loop_info, child, names, loop_nodes) {
+                cluster.base.subgraphs.push(child);
+            }
+        }
+        // Loops inside a collapsed region leave nothing to draw
+        (!cluster.base.nodes.is_empty() || !cluster.base.subgraphs.is_empty()).then_some(cluster)
+    }
+
+    /// The node a collapsed region is drawn as
+    fn region_summary(&self, region: &CollapsibleRegion, names: &[BlockName]) -> crate::dot::Node {
+        use crate::dot::{Node, XmlTag};
+
+        let statements: usize = region
+            .blocks
+            .iter()
+            .map(|block| self.blocks[block.0].statements.len())
+            .sum();
+        let kind = match region.kind {
+            RegionKind::Loop => "loop at",
+            RegionKind::SingleEntry => "entered at",
+        };
+        let row = |text: String| XmlTag::new("TR").child(XmlTag::new("TD").child(text));
+        let table = XmlTag::new("TABLE")
+            .attr("BORDER", "0")
+            .attr("CELLBORDER", "1")
+            .attr("CELLSPACING", "0")
+            .attr("CELLPADDING", "4")
+            .child(
+                XmlTag::new("TR").child(
+                    XmlTag::new("TD")
+                        .attr("BGCOLOR", "lightsteelblue")
+                        .child(format!("Region {}", region.number)),
+                ),
+            )
+            .child(row(format!("{} {}", kind, names[region.entry.0])))
+            .child(row(format!(
+                "{} blocks, {} statements",
+                region.blocks.len(),
+                statements
+            )))
+            .child(row(format!("--expand-region {}", region.number)));
+        Node::new_attr(
+            format!("Region_{}", region.number),
+            [("label", crate::dot::Id::Html(table.into()))],
+        )
+    }
+
+    /// A node explaining the colors and line styles of the DOT output
+    fn dot_legend(coverage: bool, loop_depths: usize) -> crate::dot::Node {
+        use crate::dot::{Node, XmlTag};
+
+        let row = |color: &str, text: &str| {
+            XmlTag::new("TR")
+                .child(XmlTag::new("TD").attr("BGCOLOR", color).child(" "))
+                .child(XmlTag::new("TD").attr("ALIGN", "left").child(text))
+        };
+        let mut table = XmlTag::new("TABLE")
+            .attr("BORDER", "0")
+            .attr("CELLBORDER", "1")
+            .attr("CELLSPACING", "0")
+            .attr("CELLPADDING", "4")
+            .child(
+                XmlTag::new("TR").child(
+                    XmlTag::new("TD")
+                        .attr("COLSPAN", "2")
+                        .attr("ALIGN", "center")
+                        .child("Legend"),
+                ),
+            );
+        table = match coverage {
+            true => table
+                .child(row("gold", "block that ran"))
+                .child(row("white", "block that never ran")),
+            false => table
+                .child(row("lightgreen", "entry block"))
+                .child(row("lightcoral", "exit block"))
+                .child(row("lightyellow", "block")),
+        };
+        table = table
+            .child(row("darkgreen", "entry block border"))
+            .child(row("darkred", "exit block border"))
+            .child(row("red", "back edge (dashed)"));
+        for (depth, fill) in LOOP_FILLS.iter().enumerate().take(loop_depths) {
+            let text = match depth {
+                0 => "loop".to_string(),
+                _ => format!("loop nested {} deep", depth + 1),
+            };
+            table = table.child(row(fill, &text));
+        }
+        Node::new_attr("legend", [("label", crate::dot::Id::Html(table.into()))])
+    }
+
+    /// Simple expression formatter for DOT labels, printing addresses by
+    /// symbolic name if `normalize` gives the index to look them up in
+    fn format_expr_simple(expr: &Expr, normalize: Option<&AddressIndex>) -> String {
+        let mut debug_str = format!("{:?}", expr.kind);
+        if let Some(address_index) = normalize {
+            debug_str = address_index.normalize_debug_addresses(&debug_str);
+        }
         debug_str.chars().take(20).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::{BytecodeBuilder, parse};
+
+    fn block_starts(cfg: &ControlFlowGraph) -> Vec<usize> {
+        cfg.blocks
+            .iter()
+            .map(|b| b.start_offset.as_usize())
+            .collect()
+    }
+
+    #[test]
+    fn test_backward_jump_splits_sequence() {
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::Nothing);
+        let loop_start = b.here();
+        b.op(EExprToken::Nothing).op(EExprToken::Nothing);
+        b.op(EExprToken::JumpIfNot);
+        let target = b.placeholder();
+        b.op(EExprToken::True);
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(target, loop_start);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        assert_eq!(block_starts(&cfg), vec![0, 1, 9]);
+        assert!(matches!(
+            cfg.blocks[1].terminator,
+            Terminator::Branch {
+                false_target: BlockId(1),
+                true_target: BlockId(2),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_block_names_survive_added_blocks() {
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::Nothing);
+        let loop_start = b.here();
+        b.op(EExprToken::Nothing).op(EExprToken::Nothing);
+        b.op(EExprToken::JumpIfNot);
+        let target = b.placeholder();
+        b.op(EExprToken::True);
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(target, loop_start);
+
+        let mut cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let dom_tree = crate::bytecode::dominators::DominatorTree::compute(&cfg);
+        let loop_info = crate::bytecode::loops::LoopInfo::analyze(&cfg, &dom_tree);
+        assert_eq!(loop_info.insert_preheaders(&mut cfg).len(), 1);
+
+        let names: Vec<String> = cfg.block_names().iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            names,
+            ["Block_0x0", "Block_0x1", "Block_0x9", "Block_0x1_1"]
+        );
+    }
+
+    #[test]
+    fn test_nested_offset_reference_is_leader() {
+        // A latent call whose resume point (a SkipOffsetConst argument) lands
+        // in the middle of a straight-line sequence
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::SkipOffsetConst);
+        let resume = b.placeholder();
+        b.op(EExprToken::EndFunctionParms);
+        b.op(EExprToken::Nothing);
+        let resume_offset = b.here();
+        b.op(EExprToken::Nothing);
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(resume, resume_offset);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        assert_eq!(block_starts(&cfg), vec![0, resume_offset]);
+    }
+
+    #[test]
+    fn test_jump_into_expression_is_tolerated() {
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::True).op(EExprToken::EndFunctionParms);
+        b.op(EExprToken::JumpIfNot);
+        let target = b.placeholder();
+        b.op(EExprToken::False);
+        b.op(EExprToken::Jump);
+        let past_end = b.placeholder();
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        // Into the call's address operand, and past the end of the script
+        b.patch(target, 3);
+        b.patch(past_end, 0x1000);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        assert_eq!(cfg.blocks[0].successors[0], BlockId(0));
+        assert!(matches!(cfg.blocks[1].terminator, Terminator::DynamicJump));
+    }
+
+    #[test]
+    fn test_statement_switch_continues_at_end_offset() {
+        // switch (0) { case 0: 1; default: 0 } with the end offset skipping
+        // the statement after it
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::SwitchValue).u16(1);
+        let end = b.placeholder();
+        b.op(EExprToken::IntZero).op(EExprToken::IntZero);
+        let next_case = b.placeholder();
+        b.op(EExprToken::IntOne);
+        b.patch(next_case, b.here());
+        b.op(EExprToken::IntZero);
+        let skipped = b.here();
+        b.op(EExprToken::Nothing);
+        let landing = b.here();
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(end, landing);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        assert_eq!(block_starts(&cfg), vec![0, skipped, landing]);
+        assert_eq!(cfg.blocks[0].successors, [BlockId(2)]);
+        assert!(matches!(
+            cfg.blocks[0].terminator,
+            Terminator::Goto { target: BlockId(2) }
+        ));
+    }
+
+    #[test]
+    fn test_modeled_assert_branches_to_abort() {
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::Assert).u16(42).u8(1).op(EExprToken::True);
+        let after = b.here();
+        b.op(EExprToken::Nothing);
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        let expressions = parse(&b.build());
+
+        let cfg = ControlFlowGraph::from_expressions(&expressions);
+        assert_eq!(block_starts(&cfg), vec![0]);
+
+        let options = CfgOptions {
+            model_asserts: true,
+            ..Default::default()
+        };
+        let cfg =
+            ControlFlowGraph::from_expressions_with_options(&expressions, &options, &NullLogger);
+        assert_eq!(block_starts(&cfg)[..2], [0, after]);
+        assert!(matches!(
+            cfg.blocks[0].terminator,
+            Terminator::Branch {
+                false_target: BlockId(2),
+                true_target: BlockId(1),
+                ..
+            }
+        ));
+        assert!(matches!(cfg.blocks[2].terminator, Terminator::Abort));
+        assert_eq!(cfg.blocks[2].predecessors, vec![BlockId(0)]);
+    }
+}
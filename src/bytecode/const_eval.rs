@@ -0,0 +1,142 @@
+/// Evaluating expressions whose value is known without running the script
+///
+/// A small abstract interpreter: literals evaluate to themselves, math
+/// library operators over known operands are folded, and everything else is
+/// unknown. Default object values aren't part of a dump, so reads of
+/// `Default__` properties stay unknown too.
+use super::expr::{Expr, ExprKind};
+use super::intrinsics::{Operator, lookup_operator};
+use super::refs::FunctionRef;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constant {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+}
+
+/// The value of `expr` if it only depends on literals
+///
+/// `resolve` gives the path of a called function, for recognizing operators.
+pub fn evaluate(
+    expr: &Expr,
+    resolve: &impl Fn(&FunctionRef) -> Option<String>,
+) -> Option<Constant> {
+    match &expr.kind {
+        ExprKind::True => Some(Constant::Bool(true)),
+        ExprKind::False => Some(Constant::Bool(false)),
+        ExprKind::IntZero => Some(Constant::Int(0)),
+        ExprKind::IntOne => Some(Constant::Int(1)),
+        ExprKind::IntConst(value) => Some(Constant::Int(i64::from(*value))),
+        ExprKind::Int64Const(value) => Some(Constant::Int(*value)),
+        ExprKind::ByteConst(value) | ExprKind::IntConstByte(value) => {
+            Some(Constant::Int(i64::from(*value)))
+        }
+        ExprKind::FloatConst(value) => Some(Constant::Float(f64::from(value.get()))),
+        ExprKind::DoubleConst(value) => Some(Constant::Float(value.get())),
+        ExprKind::CallMath { func, params } => {
+            let op = lookup_operator(&resolve(func)?)?;
+            match params.as_slice() {
+                [operand] => unary(op, evaluate(operand, resolve)?),
+                [left, right] => binary(op, evaluate(left, resolve)?, evaluate(right, resolve)?),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether a condition is always true or always false
+pub fn evaluate_condition(
+    expr: &Expr,
+    resolve: &impl Fn(&FunctionRef) -> Option<String>,
+) -> Option<bool> {
+    match evaluate(expr, resolve)? {
+        Constant::Bool(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn unary(op: Operator, operand: Constant) -> Option<Constant> {
+    match (op, operand) {
+        (Operator::Not, Constant::Bool(value)) => Some(Constant::Bool(!value)),
+        (Operator::Negate, Constant::Int(value)) => value.checked_neg().map(Constant::Int),
+        (Operator::Negate, Constant::Float(value)) => Some(Constant::Float(-value)),
+        _ => None,
+    }
+}
+
+fn binary(op: Operator, left: Constant, right: Constant) -> Option<Constant> {
+    use Constant::{Bool, Float, Int};
+    match (left, right) {
+        (Bool(l), Bool(r)) => match op {
+            Operator::And => Some(Bool(l && r)),
+            Operator::Or => Some(Bool(l || r)),
+            Operator::Xor | Operator::NotEqual => Some(Bool(l != r)),
+            Operator::Equal => Some(Bool(l == r)),
+            _ => None,
+        },
+        (Int(l), Int(r)) => match op {
+            Operator::Add => l.checked_add(r).map(Int),
+            Operator::Subtract => l.checked_sub(r).map(Int),
+            Operator::Multiply => l.checked_mul(r).map(Int),
+            Operator::Divide => l.checked_div(r).map(Int),
+            Operator::Modulo => l.checked_rem(r).map(Int),
+            _ => compare(op, l.cmp(&r)),
+        },
+        (Float(l), Float(r)) => match op {
+            Operator::Add => Some(Float(l + r)),
+            Operator::Subtract => Some(Float(l - r)),
+            Operator::Multiply => Some(Float(l * r)),
+            Operator::Divide => Some(Float(l / r)),
+            _ => compare(op, l.partial_cmp(&r)?),
+        },
+        _ => None,
+    }
+}
+
+fn compare(op: Operator, ordering: std::cmp::Ordering) -> Option<Constant> {
+    let result = match op {
+        Operator::Equal => ordering.is_eq(),
+        Operator::NotEqual => ordering.is_ne(),
+        Operator::Greater => ordering.is_gt(),
+        Operator::GreaterEqual => ordering.is_ge(),
+        Operator::Less => ordering.is_lt(),
+        Operator::LessEqual => ordering.is_le(),
+        _ => return None,
+    };
+    Some(Constant::Bool(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{at, by_name, math};
+
+    #[test]
+    fn test_folds_literal_conditions() {
+        // !(2 > 3) && true
+        let condition = math(
+            "BooleanAND",
+            vec![
+                math(
+                    "Not_PreBool",
+                    vec![math(
+                        "Greater_IntInt",
+                        vec![at(0, ExprKind::IntConst(2)), at(0, ExprKind::IntConst(3))],
+                    )],
+                ),
+                at(0, ExprKind::True),
+            ],
+        );
+        assert_eq!(evaluate_condition(&condition, &by_name), Some(true));
+
+        let unknown = math("Not_PreBool", vec![at(0, ExprKind::Self_)]);
+        assert_eq!(evaluate_condition(&unknown, &by_name), None);
+        let by_zero = math(
+            "Divide_IntInt",
+            vec![at(0, ExprKind::IntOne), at(0, ExprKind::IntZero)],
+        );
+        assert_eq!(evaluate(&by_zero, &by_name), None);
+    }
+}
@@ -0,0 +1,126 @@
+/// Rough execution cost estimates for script code
+///
+/// The VM pays for every expression it evaluates and much more for each
+/// function call, which has to set up a frame and copy parameters. Costs are
+/// in made-up units meant for ranking functions against each other, not for
+/// predicting time.
+use super::cfg::{BlockId, ControlFlowGraph};
+use super::expr::{Expr, ExprKind};
+use super::loops::LoopInfo;
+
+/// Evaluating any expression
+const EXPR_COST: u64 = 1;
+/// Extra for a call into another function
+const CALL_COST: u64 = 10;
+/// Extra for a virtual call, which looks the function up by name
+const VIRTUAL_CALL_COST: u64 = 5;
+/// Iterations assumed for each loop, since trip counts aren't known
+pub const ASSUMED_LOOP_ITERATIONS: u64 = 10;
+
+/// Cost of evaluating an expression once, including everything nested in it
+pub fn expr_cost(expr: &Expr) -> u64 {
+    let mut cost = 0;
+    expr.walk(&mut |e| {
+        cost += EXPR_COST;
+        if is_call(e) {
+            cost += CALL_COST;
+        }
+        if matches!(
+            e.kind,
+            ExprKind::VirtualFunction { .. } | ExprKind::LocalVirtualFunction { .. }
+        ) {
+            cost += VIRTUAL_CALL_COST;
+        }
+    });
+    cost
+}
+
+fn is_call(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::VirtualFunction { .. }
+            | ExprKind::FinalFunction { .. }
+            | ExprKind::LocalVirtualFunction { .. }
+            | ExprKind::LocalFinalFunction { .. }
+            | ExprKind::CallMath { .. }
+            | ExprKind::CallMulticastDelegate { .. }
+    )
+}
+
+/// Estimated cost of one run of a function
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FunctionCost {
+    /// Cost of every block, with code in loops counted
+    /// [`ASSUMED_LOOP_ITERATIONS`] times per enclosing loop
+    pub score: u64,
+    /// Call expressions in the function, not weighted by loops
+    pub calls: usize,
+    /// Deepest loop nesting; 0 if the function has no loops
+    pub max_loop_depth: usize,
+}
+
+impl FunctionCost {
+    pub fn compute(cfg: &ControlFlowGraph, loop_info: &LoopInfo) -> Self {
+        let mut cost = Self::default();
+        for block in &cfg.blocks {
+            let depth = loop_depth(loop_info, block.id);
+            cost.max_loop_depth = cost.max_loop_depth.max(depth);
+
+            let mut block_cost = 0;
+            for expr in block.statements.iter().chain(&block.terminator_expr) {
+                block_cost += expr_cost(expr);
+                expr.walk(&mut |e| cost.calls += usize::from(is_call(e)));
+            }
+            let weight = ASSUMED_LOOP_ITERATIONS.saturating_pow(depth as u32);
+            cost.score = cost.score.saturating_add(block_cost.saturating_mul(weight));
+        }
+        cost
+    }
+}
+
+/// Number of loops a block is in
+pub fn loop_depth(loop_info: &LoopInfo, block: BlockId) -> usize {
+    loop_info
+        .loops
+        .iter()
+        .filter(|lp| lp.blocks.contains(&block))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::dominators::DominatorTree;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::{BytecodeBuilder, parse};
+
+    #[test]
+    fn test_loop_code_is_weighted() {
+        // Math(); loop { Math(); if (!true) goto loop; } return;
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+        let loop_start = b.here();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+        b.op(EExprToken::JumpIfNot);
+        let target = b.placeholder();
+        b.op(EExprToken::True);
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(target, loop_start);
+
+        let expressions = parse(&b.build());
+        let cfg = ControlFlowGraph::from_expressions(&expressions);
+        let dom_tree = DominatorTree::compute(&cfg);
+        let loop_info = LoopInfo::analyze(&cfg, &dom_tree);
+
+        let cost = FunctionCost::compute(&cfg, &loop_info);
+        assert_eq!(cost.calls, 2);
+        assert_eq!(cost.max_loop_depth, 1);
+
+        // The call in the loop counts ten times, the one before it once
+        let call = expr_cost(&expressions[0]);
+        assert_eq!(call, EXPR_COST + CALL_COST);
+        assert!(cost.score >= call * (ASSUMED_LOOP_ITERATIONS + 1));
+    }
+}
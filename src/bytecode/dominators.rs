@@ -2,8 +2,11 @@
 ///
 /// A block D dominates block B if every path from entry to B must go through D.
 /// The dominator tree represents these relationships efficiently.
-use super::cfg::{BlockId, ControlFlowGraph};
+use super::cfg::{BlockId, dfs_postorder};
+use super::flow_graph::FlowGraph;
 use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use tracing::info_span;
 
 /// Dominator tree - represents dominance relationships between basic blocks
 #[derive(Debug, Clone)]
@@ -23,8 +26,9 @@ pub struct DominatorTree {
 impl DominatorTree {
     /// Compute the dominator tree using the iterative algorithm
     /// Based on Cooper, Harvey, and Kennedy's "A Simple, Fast Dominance Algorithm"
-    pub fn compute(cfg: &ControlFlowGraph) -> Self {
-        if cfg.blocks.is_empty() {
+    pub fn compute(cfg: &impl FlowGraph) -> Self {
+        let _span = info_span!("dominators").entered();
+        if cfg.nodes().next().is_none() {
             return Self {
                 idom: HashMap::new(),
                 children: HashMap::new(),
@@ -32,7 +36,7 @@ impl DominatorTree {
             };
         }
 
-        let entry = cfg.entry_block;
+        let entry = cfg.entry();
 
         // Step 1: Compute reverse postorder for efficient iteration
         let rpo = Self::reverse_postorder(cfg, entry);
@@ -54,11 +58,9 @@ impl DominatorTree {
 
             // Process blocks in reverse postorder (except entry)
             for &block_id in rpo.iter().skip(1) {
-                let block = cfg.get_block(block_id).unwrap();
-
                 // Find the first processed predecessor
                 let mut new_idom = None;
-                for &pred_id in &block.predecessors {
+                for &pred_id in cfg.predecessors(block_id) {
                     if idom.contains_key(&pred_id) {
                         new_idom = Some(pred_id);
                         break;
@@ -67,7 +69,7 @@ impl DominatorTree {
 
                 if let Some(mut new_idom_id) = new_idom {
                     // For all other predecessors
-                    for &pred_id in &block.predecessors {
+                    for &pred_id in cfg.predecessors(block_id) {
                         if pred_id != new_idom_id && idom.contains_key(&pred_id) {
                             // Find common dominator
                             new_idom_id = Self::intersect(&idom, &rpo_index, pred_id, new_idom_id);
@@ -100,31 +102,8 @@ impl DominatorTree {
     }
 
     /// Compute reverse postorder traversal of the CFG
-    fn reverse_postorder(cfg: &ControlFlowGraph, entry: BlockId) -> Vec<BlockId> {
-        let mut visited = HashSet::new();
-        let mut postorder = Vec::new();
-
-        fn dfs(
-            cfg: &ControlFlowGraph,
-            block_id: BlockId,
-            visited: &mut HashSet<BlockId>,
-            postorder: &mut Vec<BlockId>,
-        ) {
-            if visited.contains(&block_id) {
-                return;
-            }
-            visited.insert(block_id);
-
-            if let Some(block) = cfg.get_block(block_id) {
-                for &succ in &block.successors {
-                    dfs(cfg, succ, visited, postorder);
-                }
-            }
-
-            postorder.push(block_id);
-        }
-
-        dfs(cfg, entry, &mut visited, &mut postorder);
+    fn reverse_postorder(cfg: &impl FlowGraph, entry: BlockId) -> Vec<BlockId> {
+        let mut postorder = dfs_postorder([entry], |block_id| Some(cfg.successors(block_id)));
         postorder.reverse();
         postorder
     }
@@ -198,56 +177,55 @@ impl DominatorTree {
         self.idom.get(&block).copied().filter(|&idom| idom != block)
     }
 
-    /// Print the dominator tree in a human-readable format
-    pub fn print_debug(&self) {
-        println!("Dominator Tree:");
-        println!("  Entry Block: {:?}", self.entry);
-        println!();
+    /// Write the dominator tree in a human-readable format
+    pub fn write_debug(&self, out: &mut dyn Write) -> fmt::Result {
+        writeln!(out, "Dominator Tree:")?;
+        writeln!(out, "  Entry Block: {:?}", self.entry)?;
+        writeln!(out)?;
 
-        println!("Immediate Dominators:");
+        writeln!(out, "Immediate Dominators:")?;
         let mut blocks: Vec<_> = self.idom.keys().collect();
         blocks.sort();
         for &block in blocks {
             let idom = self.idom[&block];
             if block != idom {
-                println!("  idom({:?}) = {:?}", block, idom);
+                writeln!(out, "  idom({:?}) = {:?}", block, idom)?;
             }
         }
-        println!();
+        writeln!(out)?;
 
-        println!("Dominator Tree Structure:");
-        self.print_tree(self.entry, 0);
+        writeln!(out, "Dominator Tree Structure:")?;
+        self.write_tree(out, self.entry, 0)
     }
 
-    fn print_tree(&self, block: BlockId, depth: usize) {
+    fn write_tree(&self, out: &mut dyn Write, block: BlockId, depth: usize) -> fmt::Result {
         let indent = "  ".repeat(depth);
-        println!("{}{:?}", indent, block);
+        writeln!(out, "{}{:?}", indent, block)?;
 
         if let Some(children) = self.children.get(&block) {
             let mut children = children.clone();
             children.sort();
             for &child in &children {
-                self.print_tree(child, depth + 1);
+                self.write_tree(out, child, depth + 1)?;
             }
         }
+        Ok(())
     }
 
     /// Compute the dominance frontier of a block
     /// DF(X) = set of blocks where X's dominance stops
     /// (blocks that have a predecessor dominated by X, but are not strictly dominated by X)
-    pub fn dominance_frontier(&self, cfg: &ControlFlowGraph, block: BlockId) -> HashSet<BlockId> {
+    pub fn dominance_frontier(&self, cfg: &impl FlowGraph, block: BlockId) -> HashSet<BlockId> {
         let mut frontier = HashSet::new();
         let dominated = self.dominated_by(block);
 
         // For each block Y dominated by X
         for &y in &dominated {
-            if let Some(y_block) = cfg.get_block(y) {
-                // For each successor S of Y
-                for &s in &y_block.successors {
-                    // If S is not strictly dominated by X, it's in the frontier
-                    if !self.strictly_dominates(block, s) {
-                        frontier.insert(s);
-                    }
+            // For each successor S of Y
+            for &s in cfg.successors(y) {
+                // If S is not strictly dominated by X, it's in the frontier
+                if !self.strictly_dominates(block, s) {
+                    frontier.insert(s);
                 }
             }
         }
@@ -278,8 +256,9 @@ pub struct PostDominatorTree {
 impl PostDominatorTree {
     /// Compute the post-dominator tree using the iterative algorithm
     /// Similar to dominator tree but works backwards from exits
-    pub fn compute(cfg: &ControlFlowGraph) -> Self {
-        if cfg.blocks.is_empty() {
+    pub fn compute(cfg: &impl FlowGraph) -> Self {
+        let _span = info_span!("post_dominators").entered();
+        if cfg.nodes().next().is_none() {
             return Self {
                 ipdom: HashMap::new(),
                 children: HashMap::new(),
@@ -289,18 +268,16 @@ impl PostDominatorTree {
         }
 
         // Step 1: Identify exit blocks (blocks with no successors)
-        let mut exit_blocks = HashSet::new();
-        for block in &cfg.blocks {
-            if block.successors.is_empty() {
-                exit_blocks.insert(block.id);
-            }
-        }
+        let mut exit_blocks: HashSet<BlockId> = cfg
+            .nodes()
+            .filter(|&block| cfg.successors(block).is_empty())
+            .collect();
 
         // If no exit blocks found, use the last block as exit
         if exit_blocks.is_empty()
-            && let Some(last_block) = cfg.blocks.last()
+            && let Some(last_block) = cfg.nodes().last()
         {
-            exit_blocks.insert(last_block.id);
+            exit_blocks.insert(last_block);
         }
 
         // Step 2: Create a virtual exit block that all actual exits lead to
@@ -335,11 +312,9 @@ impl PostDominatorTree {
                     continue; // Skip exit blocks - already initialized
                 }
 
-                let block = cfg.get_block(block_id).unwrap();
-
                 // Find the first processed successor
                 let mut new_ipdom = None;
-                for &succ_id in &block.successors {
+                for &succ_id in cfg.successors(block_id) {
                     if ipdom.contains_key(&succ_id) {
                         new_ipdom = Some(succ_id);
                         break;
@@ -348,7 +323,7 @@ impl PostDominatorTree {
 
                 if let Some(mut new_ipdom_id) = new_ipdom {
                     // For all other successors
-                    for &succ_id in &block.successors {
+                    for &succ_id in cfg.successors(block_id) {
                         if succ_id != new_ipdom_id && ipdom.contains_key(&succ_id) {
                             // Find common post-dominator
                             new_ipdom_id =
@@ -385,38 +360,13 @@ impl PostDominatorTree {
     /// Compute reverse postorder from exit blocks (for post-dominator analysis)
     /// This is essentially a postorder traversal of the reverse CFG
     fn reverse_postorder_from_exits(
-        cfg: &ControlFlowGraph,
+        cfg: &impl FlowGraph,
         exit_blocks: &HashSet<BlockId>,
     ) -> Vec<BlockId> {
-        let mut visited = HashSet::new();
-        let mut postorder = Vec::new();
-
-        fn dfs_reverse(
-            cfg: &ControlFlowGraph,
-            block_id: BlockId,
-            visited: &mut HashSet<BlockId>,
-            postorder: &mut Vec<BlockId>,
-        ) {
-            if visited.contains(&block_id) {
-                return;
-            }
-            visited.insert(block_id);
-
-            if let Some(block) = cfg.get_block(block_id) {
-                // Visit predecessors (reverse CFG)
-                for &pred in &block.predecessors {
-                    dfs_reverse(cfg, pred, visited, postorder);
-                }
-            }
-
-            postorder.push(block_id);
-        }
-
-        // Start DFS from all exit blocks
-        for &exit in exit_blocks {
-            dfs_reverse(cfg, exit, &mut visited, &mut postorder);
-        }
-
+        // Start DFS from all exit blocks, visiting predecessors (reverse CFG)
+        let mut postorder = dfs_postorder(exit_blocks.iter().copied(), |block_id| {
+            Some(cfg.predecessors(block_id))
+        });
         postorder.reverse();
         postorder
     }
@@ -535,27 +485,27 @@ impl PostDominatorTree {
         None
     }
 
-    /// Print the post-dominator tree in a human-readable format
-    pub fn print_debug(&self) {
-        println!("Post-Dominator Tree:");
-        println!("  Virtual Exit: <exit>");
-        println!("  Exit Blocks: {:?}", self.exit_blocks);
-        println!();
+    /// Write the post-dominator tree in a human-readable format
+    pub fn write_debug(&self, out: &mut dyn Write) -> fmt::Result {
+        writeln!(out, "Post-Dominator Tree:")?;
+        writeln!(out, "  Virtual Exit: <exit>")?;
+        writeln!(out, "  Exit Blocks: {:?}", self.exit_blocks)?;
+        writeln!(out)?;
 
-        println!("Immediate Post-Dominators:");
+        writeln!(out, "Immediate Post-Dominators:")?;
         let mut blocks: Vec<_> = self.ipdom.keys().collect();
         blocks.sort();
         for &block in blocks {
             let ipdom = self.ipdom[&block];
             if block != ipdom {
                 if ipdom == self.virtual_exit {
-                    println!("  ipdom({:?}) = <exit>", block);
+                    writeln!(out, "  ipdom({:?}) = <exit>", block)?;
                 } else {
-                    println!("  ipdom({:?}) = {:?}", block, ipdom);
+                    writeln!(out, "  ipdom({:?}) = {:?}", block, ipdom)?;
                 }
             }
         }
-        println!();
+        writeln!(out)
     }
 }
 
@@ -1,21 +1,111 @@
 /// Expression intermediate representation for Kismet bytecode
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
 use super::refs::{ClassRef, FunctionRef, ObjectRef, PropertyRef, StructRef};
-use super::types::{BytecodeOffset, Name};
+use super::types::{BytecodeOffset, F32, F64, Name, OffsetRange, Real};
 
 /// An expression with its bytecode location
-#[derive(Debug, Clone)]
+///
+/// Equality and hashing are structural and ignore where the expression sits,
+/// so the same code at two offsets compares equal. Offsets the expression
+/// refers to, such as jump targets, still count. Each node stores a hash of
+/// its whole subtree, computed once in [`Expr::new`], so hashing and
+/// rejecting unequal trees don't need a walk. `kind` must not be replaced
+/// after construction or the stored hash goes stale.
+#[derive(Debug)]
 pub struct Expr {
     pub offset: BytecodeOffset,
+    /// Offset just past the expression's last byte, nested expressions
+    /// included; the same as `offset` for expressions built rather than parsed
+    pub end: BytecodeOffset,
     pub kind: ExprKind,
+    hash: u64,
+}
+
+// Cloning and dropping recurse through nested expressions just like a walk
+// does, so they need the same stack growth
+
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        ensure_stack(|| Self {
+            offset: self.offset,
+            end: self.end,
+            kind: self.kind.clone(),
+            hash: self.hash,
+        })
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && ensure_stack(|| self.kind == other.kind)
+    }
+}
+
+impl Eq for Expr {}
+
+impl Hash for Expr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let kind = std::mem::replace(&mut self.kind, ExprKind::Nothing);
+        ensure_stack(|| drop(kind));
+    }
+}
+
+/// Stack space that must remain before recursing into a nested expression
+const STACK_RED_ZONE: usize = 64 * 1024;
+/// Size of each new stack segment allocated when the red zone is reached
+const STACK_SEGMENT_SIZE: usize = 2 * 1024 * 1024;
+
+/// Run a step of a recursive expression traversal, growing the stack first if
+/// it is close to running out
+///
+/// Kismet expressions can nest thousands of levels deep (e.g. long string
+/// concatenation chains), so every recursion over the tree goes through this.
+pub fn ensure_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, f)
 }
 
 impl Expr {
     pub fn new(offset: BytecodeOffset, kind: ExprKind) -> Self {
-        Self { offset, kind }
+        // Nested expressions hash as their stored subtree hash, so this only
+        // looks at the node itself
+        let mut hasher = DefaultHasher::new();
+        kind.hash(&mut hasher);
+        Self {
+            offset,
+            end: offset,
+            kind,
+            hash: hasher.finish(),
+        }
+    }
+
+    /// The same expression, covering the bytes up to `end`
+    pub fn with_end(mut self, end: BytecodeOffset) -> Self {
+        self.end = end;
+        self
+    }
+
+    /// The bytes the expression was decoded from, empty if it was built
+    pub fn span(&self) -> OffsetRange {
+        OffsetRange::new(self.offset, self.end)
     }
 
     /// Walk the expression tree, calling the visitor function on each expression
     pub fn walk<F>(&self, visitor: &mut F)
+    where
+        F: FnMut(&Expr),
+    {
+        ensure_stack(|| self.walk_nested(visitor));
+    }
+
+    fn walk_nested<F>(&self, visitor: &mut F)
     where
         F: FnMut(&Expr),
     {
@@ -72,7 +162,7 @@ impl Expr {
             ExprKind::Assert { condition, .. } => {
                 condition.walk(visitor);
             }
-            ExprKind::Skip { expr, .. } => {
+            ExprKind::Skip { expr, .. } | ExprKind::DefaultParmValue { value: expr, .. } => {
                 expr.walk(visitor);
             }
 
@@ -236,12 +326,14 @@ impl Expr {
             | ExprKind::ByteConst(_)
             | ExprKind::IntConstByte(_)
             | ExprKind::FloatConst(_)
+            | ExprKind::DoubleConst(_)
             | ExprKind::StringConst(_)
             | ExprKind::UnicodeStringConst(_)
             | ExprKind::NameConst(_)
             | ExprKind::VectorConst { .. }
+            | ExprKind::Vector3fConst { .. }
             | ExprKind::RotationConst { .. }
-            | ExprKind::TransformConst { .. }
+            | ExprKind::TransformConst(_)
             | ExprKind::True
             | ExprKind::False
             | ExprKind::NoObject
@@ -260,7 +352,7 @@ impl Expr {
             | ExprKind::Tracepoint
             | ExprKind::WireTracepoint
             | ExprKind::InstrumentationEvent { .. }
-            | ExprKind::BitFieldConst
+            | ExprKind::BitFieldConst { .. }
             | ExprKind::DeprecatedOp4A
             | ExprKind::EndOfScript
             | ExprKind::EndParmValue => {
@@ -268,6 +360,194 @@ impl Expr {
             }
         }
     }
+
+    /// Rebuild the tree bottom-up, replacing each node with what `f` returns
+    /// for it once its children have been rebuilt
+    pub fn rewrite<F>(self, f: &mut F) -> Expr
+    where
+        F: FnMut(Expr) -> Expr,
+    {
+        ensure_stack(|| {
+            let mut this = self;
+            let offset = this.offset;
+            let mut kind = std::mem::replace(&mut this.kind, ExprKind::Nothing);
+            for child in kind.children_mut() {
+                let taken = std::mem::replace(child, Expr::new(offset, ExprKind::Nothing));
+                *child = taken.rewrite(f);
+            }
+            f(Expr::new(offset, kind).with_end(this.end))
+        })
+    }
+}
+
+impl ExprKind {
+    /// The expressions nested directly inside this one, in the order
+    /// [`Expr::walk`] visits them
+    fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            ExprKind::Let {
+                variable, value, ..
+            }
+            | ExprKind::LetObj { variable, value }
+            | ExprKind::LetWeakObjPtr { variable, value }
+            | ExprKind::LetBool { variable, value }
+            | ExprKind::LetDelegate { variable, value }
+            | ExprKind::LetMulticastDelegate { variable, value } => vec![variable, value],
+            ExprKind::LetValueOnPersistentFrame { value, .. } => vec![value],
+            ExprKind::Return(expr)
+            | ExprKind::JumpIfNot {
+                condition: expr, ..
+            }
+            | ExprKind::ComputedJump { offset_expr: expr }
+            | ExprKind::PopExecutionFlowIfNot { condition: expr }
+            | ExprKind::Assert {
+                condition: expr, ..
+            }
+            | ExprKind::Skip { expr, .. }
+            | ExprKind::DefaultParmValue { value: expr, .. }
+            | ExprKind::StructMemberContext {
+                struct_expr: expr, ..
+            }
+            | ExprKind::InterfaceContext(expr)
+            | ExprKind::DynamicCast { expr, .. }
+            | ExprKind::MetaCast { expr, .. }
+            | ExprKind::PrimitiveCast { expr, .. }
+            | ExprKind::ObjToInterfaceCast { expr, .. }
+            | ExprKind::InterfaceToObjCast { expr, .. }
+            | ExprKind::CrossInterfaceCast { expr, .. }
+            | ExprKind::ClearMulticastDelegate(expr)
+            | ExprKind::SoftObjectConst(expr)
+            | ExprKind::FieldPathConst(expr) => vec![expr],
+            ExprKind::SwitchValue {
+                index,
+                cases,
+                default,
+                ..
+            } => {
+                let mut children: Vec<&mut Expr> = vec![index];
+                for case in cases {
+                    children.push(&mut case.case_value);
+                    children.push(&mut case.result);
+                }
+                children.push(default);
+                children
+            }
+            ExprKind::Context {
+                object, context, ..
+            }
+            | ExprKind::ClassContext {
+                object, context, ..
+            } => vec![object, context],
+            ExprKind::VirtualFunction { params, .. }
+            | ExprKind::FinalFunction { params, .. }
+            | ExprKind::LocalVirtualFunction { params, .. }
+            | ExprKind::LocalFinalFunction { params, .. }
+            | ExprKind::CallMath { params, .. }
+            | ExprKind::ArrayConst {
+                elements: params, ..
+            }
+            | ExprKind::StructConst {
+                elements: params, ..
+            }
+            | ExprKind::SetConst {
+                elements: params, ..
+            }
+            | ExprKind::MapConst {
+                elements: params, ..
+            } => params.iter_mut().collect(),
+            ExprKind::CallMulticastDelegate {
+                delegate_expr: first,
+                params: rest,
+                ..
+            }
+            | ExprKind::SetArray {
+                array_expr: first,
+                elements: rest,
+            }
+            | ExprKind::SetSet {
+                set_expr: first,
+                elements: rest,
+                ..
+            }
+            | ExprKind::SetMap {
+                map_expr: first,
+                elements: rest,
+                ..
+            } => std::iter::once(&mut **first).chain(rest).collect(),
+            ExprKind::ArrayGetByRef {
+                array_expr: first,
+                index_expr: second,
+            }
+            | ExprKind::BindDelegate {
+                delegate_expr: first,
+                object_expr: second,
+                ..
+            }
+            | ExprKind::AddMulticastDelegate {
+                delegate_expr: first,
+                to_add_expr: second,
+            }
+            | ExprKind::RemoveMulticastDelegate {
+                delegate_expr: first,
+                to_remove_expr: second,
+            } => vec![first, second],
+            ExprKind::TextConst(text_lit) => match text_lit {
+                TextLiteral::LocalizedText {
+                    source,
+                    key,
+                    namespace,
+                } => vec![source, key, namespace],
+                TextLiteral::InvariantText { source } | TextLiteral::LiteralString { source } => {
+                    vec![source]
+                }
+                TextLiteral::StringTableEntry { table_id, key } => vec![table_id, key],
+                TextLiteral::Empty => Vec::new(),
+            },
+            ExprKind::LocalVariable(_)
+            | ExprKind::InstanceVariable(_)
+            | ExprKind::DefaultVariable(_)
+            | ExprKind::LocalOutVariable(_)
+            | ExprKind::ClassSparseDataVariable(_)
+            | ExprKind::IntConst(_)
+            | ExprKind::Int64Const(_)
+            | ExprKind::UInt64Const(_)
+            | ExprKind::IntZero
+            | ExprKind::IntOne
+            | ExprKind::ByteConst(_)
+            | ExprKind::IntConstByte(_)
+            | ExprKind::FloatConst(_)
+            | ExprKind::DoubleConst(_)
+            | ExprKind::StringConst(_)
+            | ExprKind::UnicodeStringConst(_)
+            | ExprKind::NameConst(_)
+            | ExprKind::VectorConst { .. }
+            | ExprKind::Vector3fConst { .. }
+            | ExprKind::RotationConst { .. }
+            | ExprKind::TransformConst(_)
+            | ExprKind::True
+            | ExprKind::False
+            | ExprKind::NoObject
+            | ExprKind::NoInterface
+            | ExprKind::Self_
+            | ExprKind::Nothing
+            | ExprKind::NothingInt32
+            | ExprKind::ObjectConst(_)
+            | ExprKind::PropertyConst(_)
+            | ExprKind::SkipOffsetConst(_)
+            | ExprKind::InstanceDelegate(_)
+            | ExprKind::Jump { .. }
+            | ExprKind::PushExecutionFlow { .. }
+            | ExprKind::PopExecutionFlow
+            | ExprKind::Breakpoint
+            | ExprKind::Tracepoint
+            | ExprKind::WireTracepoint
+            | ExprKind::InstrumentationEvent { .. }
+            | ExprKind::BitFieldConst { .. }
+            | ExprKind::DeprecatedOp4A
+            | ExprKind::EndOfScript
+            | ExprKind::EndParmValue => Vec::new(),
+        }
+    }
 }
 
 /// Collect all bytecode offsets that are referenced by control flow instructions
@@ -308,8 +588,43 @@ pub fn collect_referenced_offsets(
     offsets
 }
 
+/// Referenced offsets that land inside a statement rather than at its start,
+/// by the offset of the statement they land in
+///
+/// Flat output only labels statements, so a jump into the middle of one,
+/// which compilers don't emit but hand-made or obfuscated bytecode can,
+/// would otherwise go unmentioned. A switch's own case offsets don't count.
+pub fn targets_inside_statements(
+    statements: &[Expr],
+    referenced: &std::collections::HashSet<BytecodeOffset>,
+) -> std::collections::HashMap<BytecodeOffset, Vec<BytecodeOffset>> {
+    let mut targets: Vec<BytecodeOffset> = referenced.iter().copied().collect();
+    targets.sort_unstable();
+    let mut inside = std::collections::HashMap::new();
+    for statement in statements {
+        let span = statement.span();
+        let first = targets.partition_point(|&target| target <= span.start);
+        let mut cases = std::collections::HashSet::new();
+        statement.walk(&mut |e| {
+            if let ExprKind::SwitchValue { cases: switch, .. } = &e.kind {
+                cases.extend(switch.iter().flat_map(|c| [c.case_offset, c.next_offset]));
+            }
+        });
+        let landed: Vec<BytecodeOffset> = targets[first..]
+            .iter()
+            .copied()
+            .take_while(|&target| target < span.end)
+            .filter(|target| !cases.contains(target))
+            .collect();
+        if !landed.is_empty() {
+            inside.insert(statement.offset, landed);
+        }
+    }
+    inside
+}
+
 /// All possible expression types in Kismet bytecode
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ExprKind {
     // Variables
     LocalVariable(PropertyRef),
@@ -328,7 +643,8 @@ pub enum ExprKind {
     IntConstByte(u8),
 
     // Constants - floating point
-    FloatConst(f32),
+    FloatConst(F32),
+    DoubleConst(F64),
 
     // Constants - strings and names
     StringConst(String),
@@ -337,27 +653,22 @@ pub enum ExprKind {
 
     // Constants - vectors and transforms
     VectorConst {
-        x: f32,
-        y: f32,
-        z: f32,
+        x: Real,
+        y: Real,
+        z: Real,
+    },
+    /// A float vector in UE5, where `VectorConst` holds doubles
+    Vector3fConst {
+        x: F32,
+        y: F32,
+        z: F32,
     },
     RotationConst {
-        pitch: f32,
-        yaw: f32,
-        roll: f32,
-    },
-    TransformConst {
-        rot_x: f32,
-        rot_y: f32,
-        rot_z: f32,
-        rot_w: f32,
-        trans_x: f32,
-        trans_y: f32,
-        trans_z: f32,
-        scale_x: f32,
-        scale_y: f32,
-        scale_z: f32,
+        pitch: Real,
+        yaw: Real,
+        roll: Real,
     },
+    TransformConst(Box<TransformConst>),
 
     // Constants - special values
     True,
@@ -580,6 +891,13 @@ pub enum ExprKind {
         skip_count: u32,
         expr: Box<Expr>,
     },
+    /// Default value of an optional parameter, at the start of the function
+    /// that has it; a caller that passes the parameter skips `skip_count`
+    /// bytes past it
+    DefaultParmValue {
+        skip_count: u32,
+        value: Box<Expr>,
+    },
     Breakpoint,
     Tracepoint,
     WireTracepoint,
@@ -588,13 +906,33 @@ pub enum ExprKind {
     },
 
     // Special
-    BitFieldConst,
+    /// Value for a single bit of a packed bool property
+    BitFieldConst {
+        property: PropertyRef,
+        value: u8,
+    },
     DeprecatedOp4A,
     EndOfScript,
     EndParmValue,
 }
 
-#[derive(Debug, Clone)]
+/// A transform constant's components, boxed as they'd otherwise make every
+/// expression larger
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransformConst {
+    pub rot_x: Real,
+    pub rot_y: Real,
+    pub rot_z: Real,
+    pub rot_w: Real,
+    pub trans_x: Real,
+    pub trans_y: Real,
+    pub trans_z: Real,
+    pub scale_x: Real,
+    pub scale_y: Real,
+    pub scale_z: Real,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SwitchCase {
     pub case_offset: BytecodeOffset,
     pub case_value: Expr,
@@ -602,7 +940,7 @@ pub struct SwitchCase {
     pub result: Expr,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TextLiteral {
     Empty,
     LocalizedText {
@@ -621,3 +959,41 @@ pub enum TextLiteral {
         key: Box<Expr>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::types::Address;
+
+    fn add_one(offset: usize, local: u64) -> Expr {
+        let at = |kind| Expr::new(BytecodeOffset::new(offset), kind);
+        at(ExprKind::CallMath {
+            func: FunctionRef::ByName(Name::new("Add_IntInt")),
+            params: vec![
+                at(ExprKind::LocalVariable(PropertyRef::new(Address::new(
+                    local,
+                )))),
+                at(ExprKind::IntConst(1)),
+            ],
+        })
+    }
+
+    fn hash_of(expr: &Expr) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        expr.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_structural_equality_ignores_offsets() {
+        let a = add_one(0x10, 0x1000);
+        let b = add_one(0x80, 0x1000);
+        assert_eq!(a, b);
+        assert_eq!(hash_of(&a), hash_of(&b));
+        assert_ne!(a, add_one(0x10, 0x2000));
+
+        let zero = Expr::new(BytecodeOffset::new(0), ExprKind::FloatConst(F32(0.0)));
+        let negative_zero = Expr::new(BytecodeOffset::new(0), ExprKind::FloatConst(F32(-0.0)));
+        assert_ne!(zero, negative_zero);
+    }
+}
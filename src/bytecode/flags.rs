@@ -0,0 +1,88 @@
+//! Engine flag bits used when inspecting jmap properties and functions
+//!
+//! Compared against the raw `.bits()` of the jmap flag types so that checks
+//! don't depend on which named constants the dump format exposes.
+
+/// Property is a parameter of a function
+pub const CPF_PARM: u64 = 0x0000_0000_0000_0080;
+/// Parameter is passed by reference and written by the function
+pub const CPF_OUT_PARM: u64 = 0x0000_0000_0000_0100;
+/// Property is the return value of a function
+pub const CPF_RETURN_PARM: u64 = 0x0000_0000_0000_0400;
+
+/// Function can be invoked over the network
+pub const FUNC_NET: u32 = 0x0000_0040;
+/// Function is a console command
+pub const FUNC_EXEC: u32 = 0x0000_0200;
+/// Function is an event, invoked by the engine
+pub const FUNC_EVENT: u32 = 0x0000_0800;
+/// Function doesn't take an object, only its class
+pub const FUNC_STATIC: u32 = 0x0000_2000;
+/// Function is a delegate signature rather than real code
+pub const FUNC_DELEGATE: u32 = 0x0010_0000;
+/// Function is an event that native code can call into Blueprints
+pub const FUNC_BLUEPRINT_EVENT: u32 = 0x0800_0000;
+/// Network function runs on the server when called by a client
+pub const FUNC_NET_SERVER: u32 = 0x0020_0000;
+/// Network function has a `_Validate` function checked before it runs
+pub const FUNC_NET_VALIDATE: u32 = 0x8000_0000;
+
+/// Names of the `EFunctionFlags` bits, in bit order
+const FUNCTION_FLAG_NAMES: [&str; 32] = [
+    "Final",
+    "RequiredAPI",
+    "BlueprintAuthorityOnly",
+    "BlueprintCosmetic",
+    "0x10",
+    "0x20",
+    "Net",
+    "NetReliable",
+    "NetRequest",
+    "Exec",
+    "Native",
+    "Event",
+    "NetResponse",
+    "Static",
+    "NetMulticast",
+    "UbergraphFunction",
+    "MulticastDelegate",
+    "Public",
+    "Private",
+    "Protected",
+    "Delegate",
+    "NetServer",
+    "HasOutParms",
+    "HasDefaults",
+    "NetClient",
+    "DLLImport",
+    "BlueprintCallable",
+    "BlueprintEvent",
+    "BlueprintPure",
+    "EditorOnly",
+    "Const",
+    "NetValidate",
+];
+
+/// Names of the flags set in an `EFunctionFlags` value, e.g. `["Net", "NetServer"]`
+pub fn function_flag_names(bits: u32) -> Vec<&'static str> {
+    FUNCTION_FLAG_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| bits & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_flag_names() {
+        assert_eq!(
+            function_flag_names(FUNC_NET | FUNC_NET_SERVER | FUNC_NET_VALIDATE),
+            vec!["Net", "NetServer", "NetValidate"]
+        );
+        assert_eq!(function_flag_names(FUNC_EVENT), vec!["Event"]);
+    }
+}
@@ -0,0 +1,188 @@
+/// The shape of a control flow graph, apart from what its blocks contain
+///
+/// Dominators, post-dominators and loops only look at which nodes lead to
+/// which, so they're computed over any [`FlowGraph`] rather than just a
+/// [`ControlFlowGraph`] of Kismet blocks. A tool with its own graph, built
+/// from another script VM or by hand, implements this to reuse them, naming
+/// its nodes by [`BlockId`].
+///
+/// The structurer walks the graph it reduces through this trait as well.
+/// It also needs the code in each node, to rewrite the jumps ending them into
+/// breaks, continues and gotos, so it structures a
+/// [`CodeGraph`](super::structured::CodeGraph): a graph of Kismet blocks, or
+/// one whose blocks implement [`BlockCode`](super::structured::BlockCode).
+use std::collections::{HashMap, HashSet};
+
+use super::cfg::{BlockId, ControlFlowGraph, dfs_postorder};
+
+pub trait FlowGraph {
+    /// The node control starts at
+    fn entry(&self) -> BlockId;
+
+    /// Every node, in the order the graph keeps them
+    fn nodes(&self) -> impl Iterator<Item = BlockId> + '_;
+
+    /// Nodes control can pass to from `node`, empty if it ends the function
+    /// or isn't in the graph
+    fn successors(&self, node: BlockId) -> &[BlockId];
+
+    /// Nodes that can pass control to `node`
+    fn predecessors(&self, node: BlockId) -> &[BlockId];
+
+    /// Whether `node` does nothing but pass control on, so it can serve as a
+    /// loop's pre-header; nodes are taken to do something unless told otherwise
+    fn is_empty(&self, _node: BlockId) -> bool {
+        false
+    }
+}
+
+/// Nodes reachable from the entry, each after every node it leads to unless
+/// a cycle leads back to it
+pub fn postorder(graph: &impl FlowGraph) -> Vec<BlockId> {
+    dfs_postorder([graph.entry()], |node| Some(graph.successors(node)))
+}
+
+/// Whether control can pass from `from` to `to`, taking `from` to reach
+/// itself
+pub fn reaches(graph: &impl FlowGraph, from: BlockId, to: BlockId) -> bool {
+    let mut seen = HashSet::from([from]);
+    let mut stack = vec![from];
+    while let Some(node) = stack.pop() {
+        if node == to {
+            return true;
+        }
+        for &succ in graph.successors(node) {
+            if seen.insert(succ) {
+                stack.push(succ);
+            }
+        }
+    }
+    false
+}
+
+/// Whether any of the graph's nodes, reachable or not, lie on a cycle
+///
+/// Searching depth-first from every node, only an edge back to a node still
+/// being searched from, which is finished later, closes a cycle.
+pub fn has_cycle(graph: &impl FlowGraph) -> bool {
+    let finished: HashMap<BlockId, usize> =
+        dfs_postorder(graph.nodes(), |node| Some(graph.successors(node)))
+            .into_iter()
+            .enumerate()
+            .map(|(position, node)| (node, position))
+            .collect();
+    finished.iter().any(|(&node, &position)| {
+        graph
+            .successors(node)
+            .iter()
+            .any(|succ| finished.get(succ).is_some_and(|&after| after >= position))
+    })
+}
+
+impl FlowGraph for ControlFlowGraph {
+    fn entry(&self) -> BlockId {
+        self.entry_block
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = BlockId> + '_ {
+        self.blocks.iter().map(|block| block.id)
+    }
+
+    fn successors(&self, node: BlockId) -> &[BlockId] {
+        self.get_block(node).map_or(&[], |block| &block.successors)
+    }
+
+    fn predecessors(&self, node: BlockId) -> &[BlockId] {
+        self.get_block(node)
+            .map_or(&[], |block| &block.predecessors)
+    }
+
+    fn is_empty(&self, node: BlockId) -> bool {
+        self.get_block(node)
+            .is_some_and(|block| block.statements.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::dominators::{DominatorTree, PostDominatorTree};
+    use crate::bytecode::loops::LoopInfo;
+
+    /// A graph given as a list of edges, with no code in its nodes
+    struct Edges {
+        successors: Vec<Vec<BlockId>>,
+        predecessors: Vec<Vec<BlockId>>,
+    }
+
+    impl Edges {
+        fn new(nodes: usize, edges: &[(usize, usize)]) -> Self {
+            let mut graph = Self {
+                successors: vec![Vec::new(); nodes],
+                predecessors: vec![Vec::new(); nodes],
+            };
+            for &(from, to) in edges {
+                graph.successors[from].push(BlockId(to));
+                graph.predecessors[to].push(BlockId(from));
+            }
+            graph
+        }
+    }
+
+    impl FlowGraph for Edges {
+        fn entry(&self) -> BlockId {
+            BlockId(0)
+        }
+
+        fn nodes(&self) -> impl Iterator<Item = BlockId> + '_ {
+            (0..self.successors.len()).map(BlockId)
+        }
+
+        fn successors(&self, node: BlockId) -> &[BlockId] {
+            self.successors.get(node.0).map_or(&[], Vec::as_slice)
+        }
+
+        fn predecessors(&self, node: BlockId) -> &[BlockId] {
+            self.predecessors.get(node.0).map_or(&[], Vec::as_slice)
+        }
+    }
+
+    #[test]
+    fn test_traversals() {
+        // 0 -> 1 -> 2 -> 1; 3 -> 0 is unreachable
+        let looped = Edges::new(4, &[(0, 1), (1, 2), (2, 1), (3, 0)]);
+        assert_eq!(postorder(&looped), [BlockId(2), BlockId(1), BlockId(0)]);
+        assert!(reaches(&looped, BlockId(2), BlockId(1)));
+        assert!(!reaches(&looped, BlockId(1), BlockId(0)));
+        assert!(has_cycle(&looped));
+
+        // Two paths meeting again, which isn't a cycle
+        let diamond = Edges::new(4, &[(0, 1), (0, 2), (1, 3), (2, 3)]);
+        assert!(!has_cycle(&diamond));
+        assert!(has_cycle(&Edges::new(2, &[(0, 1), (1, 1)])));
+    }
+
+    #[test]
+    fn test_analyses_run_on_other_graphs() {
+        // 0 -> 1; 1 -> 2 | 4; 2 -> 3 | 1; 3 -> 1; 4 exits
+        let graph = Edges::new(5, &[(0, 1), (1, 2), (1, 4), (2, 3), (2, 1), (3, 1)]);
+        let dom_tree = DominatorTree::compute(&graph);
+        assert_eq!(dom_tree.immediate_dominator(BlockId(3)), Some(BlockId(2)));
+        assert_eq!(dom_tree.immediate_dominator(BlockId(4)), Some(BlockId(1)));
+
+        let post_dom_tree = PostDominatorTree::compute(&graph);
+        assert_eq!(
+            post_dom_tree.immediate_post_dominator(BlockId(2)),
+            Some(BlockId(1))
+        );
+
+        let loop_info = LoopInfo::analyze(&graph, &dom_tree);
+        assert_eq!(loop_info.loops.len(), 1);
+        let found = &loop_info.loops[0];
+        assert_eq!(found.header, BlockId(1));
+        assert_eq!(found.blocks.len(), 3);
+        assert_eq!(found.back_edges.len(), 2);
+        // Node 0 leads only to the header but isn't known to be empty
+        assert_eq!(found.preheader, None);
+    }
+}
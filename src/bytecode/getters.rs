@@ -0,0 +1,247 @@
+/// Inlining calls to functions that only return a property
+///
+/// Blueprint getters compile to a script that copies one member into the
+/// return value and returns. A call to such a function reads the same as the
+/// member itself, so `Target->GetMaxHealth()` can be printed as
+/// `Target->MaxHealth`. Calls with arguments are left alone, as are virtual
+/// calls to functions that may be overridden, since the override could do
+/// anything.
+use std::collections::{BTreeMap, HashMap};
+
+use super::expr::{Expr, ExprKind};
+use super::refs::{FunctionRef, PropertyRef};
+use super::types::BytecodeOffset;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Getter {
+    /// The member of `this` the function returns
+    pub property: PropertyRef,
+    /// Whether another function shares the getter's name, so a virtual call
+    /// may not end up in this one
+    pub overridden: bool,
+}
+
+/// Getters found among a set of functions, keyed by full path
+#[derive(Debug, Default)]
+pub struct Getters {
+    getters: HashMap<String, Getter>,
+}
+
+/// A call replaced by the property its callee returns
+#[derive(Debug, Clone)]
+pub struct InlinedCall {
+    pub offset: BytecodeOffset,
+    /// Full path of the getter
+    pub callee: String,
+    pub property: PropertyRef,
+}
+
+impl Getters {
+    /// Find the getters among `scripts`, parsed function bodies keyed by path
+    ///
+    /// `all_functions` are the paths of every function that could be called,
+    /// scripted or not, for telling which getters share their name.
+    pub fn find<'s>(
+        scripts: &BTreeMap<&'s str, Vec<Expr>>,
+        all_functions: impl IntoIterator<Item = &'s str>,
+    ) -> Self {
+        let mut name_counts: HashMap<&str, usize> = HashMap::new();
+        for path in all_functions {
+            *name_counts.entry(function_name(path)).or_insert(0) += 1;
+        }
+        let getters = scripts
+            .iter()
+            .filter_map(|(path, expressions)| {
+                let property = returned_property(expressions)?;
+                let overridden = name_counts.get(function_name(path)).is_some_and(|&n| n > 1);
+                Some((
+                    path.to_string(),
+                    Getter {
+                        property,
+                        overridden,
+                    },
+                ))
+            })
+            .collect();
+        Self { getters }
+    }
+
+    /// Replace calls to getters in `expressions` with the property they return
+    ///
+    /// `resolve` gives the path of a called function. Returns the rewritten
+    /// expressions and the calls that were replaced, in order.
+    pub fn inline(
+        &self,
+        expressions: &[Expr],
+        resolve: impl Fn(&FunctionRef) -> Option<String>,
+    ) -> (Vec<Expr>, Vec<InlinedCall>) {
+        let mut inlined = Vec::new();
+        let rewritten = expressions
+            .iter()
+            .map(|expr| {
+                expr.clone().rewrite(&mut |e| {
+                    let (func, virtual_call) = match &e.kind {
+                        ExprKind::FinalFunction { func, params }
+                        | ExprKind::LocalFinalFunction { func, params }
+                            if params.is_empty() =>
+                        {
+                            (func, false)
+                        }
+                        ExprKind::VirtualFunction { func, params }
+                        | ExprKind::LocalVirtualFunction { func, params }
+                            if params.is_empty() =>
+                        {
+                            (func, true)
+                        }
+                        _ => return e,
+                    };
+                    let Some(callee) = resolve(func) else {
+                        return e;
+                    };
+                    match self.getters.get(&callee) {
+                        Some(getter) if !(virtual_call && getter.overridden) => {
+                            inlined.push(InlinedCall {
+                                offset: e.offset,
+                                callee,
+                                property: getter.property,
+                            });
+                            Expr::new(e.offset, ExprKind::InstanceVariable(getter.property))
+                                .with_end(e.end)
+                        }
+                        _ => e,
+                    }
+                })
+            })
+            .collect();
+        inlined.sort_by_key(|call| call.offset);
+        (rewritten, inlined)
+    }
+}
+
+/// The member a function returns, if returning it is all the function does
+///
+/// Accepts `return this->Member;` as well as the usual compiled form of
+/// copying the member into an out parameter, possibly jumping to the return,
+/// and returning. Debugging markers are ignored.
+pub fn returned_property(expressions: &[Expr]) -> Option<PropertyRef> {
+    let statements: Vec<&Expr> = expressions
+        .iter()
+        .filter(|expr| {
+            !matches!(
+                expr.kind,
+                ExprKind::Nothing
+                    | ExprKind::EndOfScript
+                    | ExprKind::Tracepoint
+                    | ExprKind::WireTracepoint
+                    | ExprKind::InstrumentationEvent { .. }
+            )
+        })
+        .collect();
+    let (ret, body) = statements.split_last()?;
+    let ExprKind::Return(returned) = &ret.kind else {
+        return None;
+    };
+    let (assignment, jumps) = match body.split_first() {
+        None => return instance_variable(returned),
+        Some((first, rest)) => (first, rest),
+    };
+    // Jumps between the copy and the return can only go to the return
+    if !jumps
+        .iter()
+        .all(|jump| matches!(jump.kind, ExprKind::Jump { target } if target == ret.offset))
+    {
+        return None;
+    }
+    let (variable, value) = match &assignment.kind {
+        ExprKind::Let {
+            variable, value, ..
+        }
+        | ExprKind::LetObj { variable, value }
+        | ExprKind::LetWeakObjPtr { variable, value }
+        | ExprKind::LetBool { variable, value } => (variable, value),
+        _ => return None,
+    };
+    let ExprKind::LocalOutVariable(out) = &variable.kind else {
+        return None;
+    };
+    match &returned.kind {
+        ExprKind::LocalOutVariable(prop) if prop == out => {}
+        ExprKind::Nothing => {}
+        _ => return None,
+    }
+    instance_variable(value)
+}
+
+fn instance_variable(expr: &Expr) -> Option<PropertyRef> {
+    match &expr.kind {
+        ExprKind::InstanceVariable(prop) => Some(*prop),
+        _ => None,
+    }
+}
+
+/// The part of a function path after the class
+fn function_name(path: &str) -> &str {
+    path.rsplit_once(':').map_or(path, |(_, name)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{at, by_name};
+    use crate::bytecode::types::{Address, Name};
+
+    fn prop(address: u64) -> PropertyRef {
+        PropertyRef::new(Address::new(address))
+    }
+
+    #[test]
+    fn test_inlines_compiled_getter() {
+        // ReturnValue = this->MaxHealth; goto ret; ret: return ReturnValue;
+        let getter = vec![
+            at(
+                0,
+                ExprKind::Let {
+                    property: prop(2),
+                    variable: Box::new(at(1, ExprKind::LocalOutVariable(prop(2)))),
+                    value: Box::new(at(10, ExprKind::InstanceVariable(prop(1)))),
+                },
+            ),
+            at(
+                19,
+                ExprKind::Jump {
+                    target: BytecodeOffset::new(24),
+                },
+            ),
+            at(
+                24,
+                ExprKind::Return(Box::new(at(25, ExprKind::LocalOutVariable(prop(2))))),
+            ),
+            at(34, ExprKind::EndOfScript),
+        ];
+        let caller = vec![at(
+            0,
+            ExprKind::Return(Box::new(at(
+                1,
+                ExprKind::LocalVirtualFunction {
+                    func: FunctionRef::ByName(Name::new("A:GetMaxHealth")),
+                    params: Vec::new(),
+                },
+            ))),
+        )];
+        let scripts = BTreeMap::from([("A:GetMaxHealth", getter), ("A:Caller", caller.clone())]);
+
+        let getters = Getters::find(&scripts, scripts.keys().copied());
+        assert_eq!(getters.getters.len(), 1);
+        let (rewritten, inlined) = getters.inline(&caller, by_name);
+        assert_eq!(inlined.len(), 1);
+        assert_eq!(inlined[0].callee, "A:GetMaxHealth");
+        assert_eq!(
+            rewritten[0].kind,
+            ExprKind::Return(Box::new(at(1, ExprKind::InstanceVariable(prop(1)))))
+        );
+
+        // An override elsewhere keeps the virtual call
+        let getters = Getters::find(&scripts, scripts.keys().copied().chain(["B:GetMaxHealth"]));
+        assert!(getters.inline(&caller, by_name).1.is_empty());
+    }
+}
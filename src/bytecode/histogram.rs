@@ -0,0 +1,98 @@
+/// Counting how often each opcode occurs in scripts
+///
+/// Tallies are taken while parsing, so they include tokens that only
+/// terminate a list such as `EndFunctionParms`, and an unknown opcode is
+/// still counted before it stops the parse. That makes the table useful for
+/// checking which opcodes a new engine version actually emits, and for
+/// spotting bytecode the usual compiler wouldn't produce.
+use super::opcodes::EExprToken;
+
+#[derive(Debug, Clone)]
+pub struct OpcodeHistogram {
+    counts: [u64; 256],
+}
+
+impl Default for OpcodeHistogram {
+    fn default() -> Self {
+        Self { counts: [0; 256] }
+    }
+}
+
+impl OpcodeHistogram {
+    pub fn record(&mut self, opcode: EExprToken) {
+        self.counts[opcode.opcode_value() as usize] += 1;
+    }
+
+    pub fn count(&self, opcode: EExprToken) -> u64 {
+        self.counts[opcode.opcode_value() as usize]
+    }
+
+    /// Add the counts of another histogram to this one
+    pub fn merge(&mut self, other: &OpcodeHistogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+    }
+
+    /// Opcodes counted in total
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Opcodes that occurred, most frequent first, ties in opcode order
+    pub fn sorted(&self) -> Vec<(EExprToken, u64)> {
+        let mut entries: Vec<(EExprToken, u64)> = (0..=u8::MAX)
+            .map(|value| (EExprToken::from(value), self.counts[value as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        entries.sort_by(|(a, a_count), (b, b_count)| {
+            b_count
+                .cmp(a_count)
+                .then(a.opcode_value().cmp(&b.opcode_value()))
+        });
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::bytecode::parser::ScriptParser;
+    use crate::bytecode::reader::ScriptReader;
+    use crate::bytecode::testing::BytecodeBuilder;
+
+    #[test]
+    fn test_counts_terminators_and_unknown_opcodes() {
+        // return !true;
+        let mut builder = BytecodeBuilder::default();
+        builder
+            .op(EExprToken::Return)
+            .op(EExprToken::CallMath)
+            .address(0x1000);
+        builder
+            .op(EExprToken::True)
+            .op(EExprToken::EndFunctionParms);
+        let script = builder.build();
+        let names = BTreeMap::new();
+        let mut parser = ScriptParser::new(ScriptReader::new(&script, &names));
+        parser.parse_all().unwrap();
+        let mut histogram = parser.opcodes().clone();
+        let sorted = histogram.sorted();
+        assert_eq!(sorted.len(), 5);
+        assert!(sorted.contains(&(EExprToken::EndFunctionParms, 1)));
+        assert!(sorted.contains(&(EExprToken::EndOfScript, 1)));
+
+        // A byte no engine version defines
+        let script = [EExprToken::Nothing.opcode_value(), 0xFE];
+        let mut parser = ScriptParser::new(ScriptReader::new(&script, &names));
+        assert!(parser.parse_all().is_err());
+        histogram.merge(parser.opcodes());
+        assert_eq!(histogram.total(), 7);
+        assert_eq!(
+            histogram.sorted().last(),
+            Some(&(EExprToken::Unknown(0xFE), 1))
+        );
+    }
+}
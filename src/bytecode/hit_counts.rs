@@ -0,0 +1,61 @@
+/// Execution counts recorded by an external tracer
+///
+/// A profile maps bytecode offsets to the number of times the instruction at
+/// that offset ran. Structured output uses it to annotate blocks and to put
+/// the hotter side of each branch first.
+use std::collections::HashMap;
+
+use super::types::BytecodeOffset;
+
+/// Hit counts for one function
+#[derive(Debug, Clone, Default)]
+pub struct HitCounts {
+    counts: HashMap<BytecodeOffset, u64>,
+    /// Whether every instruction that ran is counted, so a missing offset
+    /// means it never ran rather than that it wasn't sampled
+    exhaustive: bool,
+}
+
+impl HitCounts {
+    pub fn new(counts: HashMap<BytecodeOffset, u64>) -> Self {
+        Self {
+            counts,
+            exhaustive: false,
+        }
+    }
+
+    /// Counts that cover every instruction that ran, such as a full trace
+    pub fn exhaustive(counts: HashMap<BytecodeOffset, u64>) -> Self {
+        Self {
+            counts,
+            exhaustive: true,
+        }
+    }
+
+    /// How often the instruction at `offset` ran, if known
+    pub fn get(&self, offset: BytecodeOffset) -> Option<u64> {
+        match self.counts.get(&offset) {
+            Some(&count) => Some(count),
+            None => self.exhaustive.then_some(0),
+        }
+    }
+}
+
+/// Hit counts for the functions of a dump
+///
+/// Profiles either hold one set of counts, used for whatever function is
+/// being decompiled, or a set per function path.
+#[derive(Debug, Clone)]
+pub enum HitProfile {
+    Shared(HitCounts),
+    PerFunction(HashMap<String, HitCounts>),
+}
+
+impl HitProfile {
+    pub fn for_function(&self, path: &str) -> Option<&HitCounts> {
+        match self {
+            HitProfile::Shared(counts) => Some(counts),
+            HitProfile::PerFunction(functions) => functions.get(path),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+/// Recognizing loops that count a variable up to a bound
+///
+/// Blueprint's ForLoop and ForEachLoop macros, and most hand-built loops,
+/// compare a counter against a limit each time round and add a constant to
+/// it somewhere in the body. This is only a heuristic: a loop whose counter
+/// is also changed some other way, or whose limit grows as it runs, still
+/// matches, so what it finds is a hint for a reader rather than a proof that
+/// the loop ends.
+use super::expr::{Expr, ExprKind};
+use super::intrinsics::{Operator, lookup_operator};
+use super::refs::FunctionRef;
+
+/// Where a loop's counter starts and the limit it runs up to
+#[derive(Debug)]
+pub struct CountedLoop<'e> {
+    /// The value last given to the counter before the loop, if that's in
+    /// the same sequence of code as the loop
+    pub start: Option<&'e Expr>,
+    pub limit: &'e Expr,
+    /// Whether the loop runs with the counter equal to the limit
+    pub inclusive: bool,
+    /// For a limit held in a variable, the value it was last given before
+    /// the loop
+    pub limit_value: Option<&'e Expr>,
+}
+
+/// The bounds of a loop that keeps going while `condition`
+/// holds, if it counts up
+///
+/// `body` holds every statement the loop can run and `before` the statements
+/// in order before it; `resolve` gives the path of a called function, for
+/// recognizing operators.
+pub fn counted_loop<'e>(
+    condition: &'e Expr,
+    body: &[&'e Expr],
+    before: &[&'e Expr],
+    resolve: &impl Fn(&FunctionRef) -> Option<String>,
+) -> Option<CountedLoop<'e>> {
+    let (counter, limit, inclusive) = comparison(condition, false, resolve)?;
+    if !is_variable(counter) || !counts_up(counter, body, resolve) {
+        return None;
+    }
+    let last_value = |variable: &Expr| {
+        before
+            .iter()
+            .rev()
+            .find_map(|statement| match &statement.kind {
+                ExprKind::Let {
+                    variable: assigned,
+                    value,
+                    ..
+                } if **assigned == *variable => Some(&**value),
+                _ => None,
+            })
+    };
+    Some(CountedLoop {
+        start: last_value(counter),
+        limit,
+        inclusive,
+        limit_value: is_variable(limit).then(|| last_value(limit)).flatten(),
+    })
+}
+
+/// The counter, limit and inclusiveness of `counter < limit` or one of the
+/// ways of writing it, `negated` if it sits under a `!`
+fn comparison<'e>(
+    condition: &'e Expr,
+    negated: bool,
+    resolve: &impl Fn(&FunctionRef) -> Option<String>,
+) -> Option<(&'e Expr, &'e Expr, bool)> {
+    let ExprKind::CallMath { func, params } = &condition.kind else {
+        return None;
+    };
+    let op = lookup_operator(&resolve(func)?)?;
+    match (op, params.as_slice(), negated) {
+        (Operator::Not, [operand], _) => comparison(operand, !negated, resolve),
+        (Operator::Less, [left, right], false) | (Operator::GreaterEqual, [left, right], true) => {
+            Some((left, right, false))
+        }
+        (Operator::LessEqual, [left, right], false) | (Operator::Greater, [left, right], true) => {
+            Some((left, right, true))
+        }
+        (Operator::Greater, [left, right], false) | (Operator::LessEqual, [left, right], true) => {
+            Some((right, left, false))
+        }
+        (Operator::GreaterEqual, [left, right], false) | (Operator::Less, [left, right], true) => {
+            Some((right, left, true))
+        }
+        _ => None,
+    }
+}
+
+fn is_variable(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::LocalVariable(_) | ExprKind::InstanceVariable(_) | ExprKind::LocalOutVariable(_)
+    )
+}
+
+/// Whether `body` adds a positive constant to `counter`, directly or through
+/// a temporary as the ForLoop macro does
+fn counts_up(
+    counter: &Expr,
+    body: &[&Expr],
+    resolve: &impl Fn(&FunctionRef) -> Option<String>,
+) -> bool {
+    let assignments = || {
+        body.iter().filter_map(|statement| match &statement.kind {
+            ExprKind::Let {
+                variable, value, ..
+            } => Some((&**variable, &**value)),
+            _ => None,
+        })
+    };
+    let is_step = |value: &Expr| {
+        let ExprKind::CallMath { func, params } = &value.kind else {
+            return false;
+        };
+        let [left, right] = params.as_slice() else {
+            return false;
+        };
+        resolve(func).and_then(|path| lookup_operator(&path)) == Some(Operator::Add)
+            && (*left == *counter && is_positive(right) || *right == *counter && is_positive(left))
+    };
+    assignments().any(|(variable, value)| {
+        *variable == *counter
+            && (is_step(value)
+                || is_variable(value)
+                    && assignments().any(|(temp, step)| *temp == *value && is_step(step)))
+    })
+}
+
+fn is_positive(expr: &Expr) -> bool {
+    match expr.kind {
+        ExprKind::IntOne => true,
+        ExprKind::IntConst(value) => value > 0,
+        ExprKind::Int64Const(value) => value > 0,
+        ExprKind::ByteConst(value) | ExprKind::IntConstByte(value) => value > 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::refs::PropertyRef;
+    use crate::bytecode::testing::{at, by_name, local, math};
+    use crate::bytecode::types::Address;
+
+    fn assign(variable: Expr, value: Expr) -> Expr {
+        at(
+            0,
+            ExprKind::Let {
+                property: PropertyRef::new(Address::new(0)),
+                variable: Box::new(variable),
+                value: Box::new(value),
+            },
+        )
+    }
+
+    fn refs(statements: &[Expr]) -> Vec<&Expr> {
+        statements.iter().collect()
+    }
+
+    #[test]
+    fn test_for_loop_macro_shape() {
+        // i = 0; n = 10; while (!(i >= n)) { t = i + 1; i = t; }
+        let (n, t) = (local(0, 2), local(0, 3));
+        let before = [
+            assign(local(0, 1), at(0, ExprKind::IntZero)),
+            assign(local(0, 2), at(0, ExprKind::IntConst(10))),
+        ];
+        let condition = math(
+            "Not_PreBool",
+            vec![math("GreaterEqual_IntInt", vec![local(0, 1), local(0, 2)])],
+        );
+        let body = [
+            assign(
+                local(0, 3),
+                math("Add_IntInt", vec![local(0, 1), at(0, ExprKind::IntOne)]),
+            ),
+            assign(local(0, 1), local(0, 3)),
+        ];
+
+        let found = counted_loop(&condition, &refs(&body), &refs(&before), &by_name).unwrap();
+        assert_eq!(*found.limit, n);
+        assert!(!found.inclusive);
+        assert_eq!(found.start.map(|e| &e.kind), Some(&ExprKind::IntZero));
+        assert_eq!(
+            found.limit_value.map(|e| &e.kind),
+            Some(&ExprKind::IntConst(10))
+        );
+
+        // Counting down, or by a step that isn't known, isn't recognized
+        let down = [assign(
+            local(0, 1),
+            math(
+                "Subtract_IntInt",
+                vec![local(0, 1), at(0, ExprKind::IntOne)],
+            ),
+        )];
+        assert!(counted_loop(&condition, &refs(&down), &[], &by_name).is_none());
+        let by_t = [assign(
+            local(0, 1),
+            math("Add_IntInt", vec![local(0, 1), t]),
+        )];
+        assert!(counted_loop(&condition, &refs(&by_t), &[], &by_name).is_none());
+    }
+}
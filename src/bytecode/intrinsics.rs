@@ -0,0 +1,410 @@
+//! Table of engine intrinsics with known source-level forms
+//!
+//! Calls to these library functions are rendered as operators or collapsed
+//! into idiomatic helpers instead of plain function calls. New entries only
+//! need to be added to the tables below.
+
+const KML: &str = "/Script/Engine.KismetMathLibrary:";
+
+/// Operators that KismetMathLibrary functions map onto
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Not,
+    Negate,
+    And,
+    Or,
+    Xor,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl Operator {
+    /// The source-level symbol for this operator
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Not => "!",
+            Operator::Negate => "-",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Xor => "^",
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Modulo => "%",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+        }
+    }
+
+    /// Number of operands the operator takes
+    pub fn arity(&self) -> usize {
+        match self {
+            Operator::Not | Operator::Negate => 1,
+            _ => 2,
+        }
+    }
+
+    /// The comparison that is true exactly when this one is false
+    ///
+    /// Ordered comparisons only invert over integers, since any comparison
+    /// with a float NaN is false.
+    pub fn inverse(&self, operand: OperandType) -> Option<Operator> {
+        let inverse = match self {
+            Operator::Equal => Operator::NotEqual,
+            Operator::NotEqual => Operator::Equal,
+            _ if !operand.is_integer() => return None,
+            Operator::Greater => Operator::LessEqual,
+            Operator::GreaterEqual => Operator::Less,
+            Operator::Less => Operator::GreaterEqual,
+            Operator::LessEqual => Operator::Greater,
+            _ => return None,
+        };
+        Some(inverse)
+    }
+}
+
+/// Type of an operator's operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandType {
+    Bool,
+    Byte,
+    Int,
+    Int64,
+    Float,
+    Double,
+}
+
+impl OperandType {
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            OperandType::Byte | OperandType::Int | OperandType::Int64
+        )
+    }
+}
+
+/// A library function that is rendered as an operator
+pub struct OperatorIntrinsic {
+    /// Function name relative to KismetMathLibrary
+    pub name: &'static str,
+    pub op: Operator,
+    pub operand: OperandType,
+}
+
+impl OperatorIntrinsic {
+    /// Full path of the library function
+    pub fn path(&self) -> String {
+        format!("{}{}", KML, self.name)
+    }
+}
+
+macro_rules! operators {
+    ($($name:literal => $op:ident($operand:ident)),* $(,)?) => {
+        &[$(OperatorIntrinsic {
+            name: $name,
+            op: Operator::$op,
+            operand: OperandType::$operand,
+        }),*]
+    };
+}
+
+static OPERATORS: &[OperatorIntrinsic] = operators![
+    // Unary operators
+    "Not_PreBool" => Not(Bool),
+    "NegateFloat" => Negate(Float),
+    "NegateInt" => Negate(Int),
+    "NegateInt64" => Negate(Int64),
+    // Logical operators
+    "BooleanAND" => And(Bool),
+    "BooleanOR" => Or(Bool),
+    "BooleanXOR" => Xor(Bool),
+    // Integer arithmetic
+    "Add_IntInt" => Add(Int),
+    "Subtract_IntInt" => Subtract(Int),
+    "Multiply_IntInt" => Multiply(Int),
+    "Divide_IntInt" => Divide(Int),
+    "Percent_IntInt" => Modulo(Int),
+    // Float arithmetic
+    "Add_FloatFloat" => Add(Float),
+    "Subtract_FloatFloat" => Subtract(Float),
+    "Multiply_FloatFloat" => Multiply(Float),
+    "Divide_FloatFloat" => Divide(Float),
+    // Double arithmetic
+    "Add_DoubleDouble" => Add(Double),
+    "Subtract_DoubleDouble" => Subtract(Double),
+    "Multiply_DoubleDouble" => Multiply(Double),
+    "Divide_DoubleDouble" => Divide(Double),
+    // Integer comparisons
+    "EqualEqual_IntInt" => Equal(Int),
+    "NotEqual_IntInt" => NotEqual(Int),
+    "Greater_IntInt" => Greater(Int),
+    "GreaterEqual_IntInt" => GreaterEqual(Int),
+    "Less_IntInt" => Less(Int),
+    "LessEqual_IntInt" => LessEqual(Int),
+    // Byte comparisons
+    "EqualEqual_ByteByte" => Equal(Byte),
+    "NotEqual_ByteByte" => NotEqual(Byte),
+    "Greater_ByteByte" => Greater(Byte),
+    "GreaterEqual_ByteByte" => GreaterEqual(Byte),
+    "Less_ByteByte" => Less(Byte),
+    "LessEqual_ByteByte" => LessEqual(Byte),
+    // Float comparisons
+    "EqualEqual_DoubleDouble" => Equal(Double),
+    "NotEqual_DoubleDouble" => NotEqual(Double),
+    "Greater_DoubleDouble" => Greater(Double),
+    "GreaterEqual_DoubleDouble" => GreaterEqual(Double),
+    "Less_DoubleDouble" => Less(Double),
+    "LessEqual_DoubleDouble" => LessEqual(Double),
+];
+
+/// A nested call pattern `outer(..., inner(args), ...)` with a concise equivalent
+///
+/// The replacement receives the outer call's arguments with the inner call's
+/// arguments spliced in at its position, e.g. `VSize(Subtract(A, B))` becomes
+/// `FVector::Distance(A, B)`.
+pub struct Idiom {
+    /// Outer function name relative to KismetMathLibrary
+    pub outer: &'static str,
+    /// Inner function name relative to KismetMathLibrary
+    pub inner: &'static str,
+    /// Name of the idiomatic form
+    pub replacement: &'static str,
+}
+
+macro_rules! idioms {
+    ($($outer:literal($inner:literal) => $replacement:literal),* $(,)?) => {
+        &[$(Idiom { outer: $outer, inner: $inner, replacement: $replacement }),*]
+    };
+}
+
+static IDIOMS: &[Idiom] = idioms![
+    // Distances
+    "VSize"("Subtract_VectorVector") => "FVector::Distance",
+    "VSizeSquared"("Subtract_VectorVector") => "FVector::DistSquared",
+    "VSize2D"("Subtract_VectorVector") => "FVector::Dist2D",
+    "VSize2DSquared"("Subtract_VectorVector") => "FVector::DistSquared2D",
+    // Scalar triple product, A | (B ^ C)
+    "Dot_VectorVector"("Cross_VectorVector") => "FVector::Triple",
+    // Degree-based trigonometry
+    "Sin"("DegreesToRadians") => "DegSin",
+    "Cos"("DegreesToRadians") => "DegCos",
+    "Tan"("DegreesToRadians") => "DegTan",
+    "RadiansToDegrees"("Asin") => "DegAsin",
+    "RadiansToDegrees"("Acos") => "DegAcos",
+    "RadiansToDegrees"("Atan") => "DegAtan",
+    "RadiansToDegrees"("Atan2") => "DegAtan2",
+];
+
+/// Small engine structs whose literals are rendered as constructor calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownStruct {
+    LinearColor,
+    Vector2D,
+    IntPoint,
+    DateTime,
+}
+
+static KNOWN_STRUCTS: &[(&str, KnownStruct)] = &[
+    ("/Script/CoreUObject.LinearColor", KnownStruct::LinearColor),
+    ("/Script/CoreUObject.Vector2D", KnownStruct::Vector2D),
+    ("/Script/CoreUObject.IntPoint", KnownStruct::IntPoint),
+    ("/Script/CoreUObject.DateTime", KnownStruct::DateTime),
+];
+
+impl KnownStruct {
+    /// Look up a struct by its full object path
+    pub fn from_path(path: &str) -> Option<Self> {
+        KNOWN_STRUCTS
+            .iter()
+            .find(|(known, _)| *known == path)
+            .map(|(_, s)| *s)
+    }
+
+    /// C++ type name used for the constructor call
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            KnownStruct::LinearColor => "FLinearColor",
+            KnownStruct::Vector2D => "FVector2D",
+            KnownStruct::IntPoint => "FIntPoint",
+            KnownStruct::DateTime => "FDateTime",
+        }
+    }
+
+    /// Number of serialized elements in the struct literal
+    pub fn element_count(&self) -> usize {
+        match self {
+            KnownStruct::LinearColor => 4,
+            KnownStruct::Vector2D | KnownStruct::IntPoint => 2,
+            KnownStruct::DateTime => 1,
+        }
+    }
+}
+
+const TICKS_PER_MILLISECOND: i64 = 10_000;
+const TICKS_PER_DAY: i64 = 864_000_000_000;
+
+/// Split `FDateTime` ticks into `[year, month, day, hour, minute, second, millisecond]`
+///
+/// Ticks count 100ns intervals since 0001-01-01 00:00. Returns `None` for
+/// negative values or ones with sub-millisecond precision, which the calendar
+/// constructor can't express.
+pub fn date_time_components(ticks: i64) -> Option<[i64; 7]> {
+    if ticks < 0 || ticks % TICKS_PER_MILLISECOND != 0 {
+        return None;
+    }
+
+    // Civil-from-days over a proleptic Gregorian calendar, with days counted
+    // from 0000-03-01 so leap days fall at the end of each year
+    let days = ticks / TICKS_PER_DAY + 306;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+
+    let millis = (ticks % TICKS_PER_DAY) / TICKS_PER_MILLISECOND;
+    Some([
+        year,
+        month,
+        day,
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000,
+    ])
+}
+
+/// Strip the KismetMathLibrary prefix from a function path
+fn math_library_name(full_path: &str) -> Option<&str> {
+    full_path.strip_prefix(KML)
+}
+
+/// Look up the operator a function path renders as, if any
+pub fn lookup_operator(full_path: &str) -> Option<Operator> {
+    lookup_operator_intrinsic(full_path).map(|intrinsic| intrinsic.op)
+}
+
+/// Look up the operator entry for a function path, with its operand type
+pub fn lookup_operator_intrinsic(full_path: &str) -> Option<&'static OperatorIntrinsic> {
+    let name = math_library_name(full_path)?;
+    OPERATORS.iter().find(|intrinsic| intrinsic.name == name)
+}
+
+/// The library function implementing an operator over a type, if there is one
+pub fn operator_intrinsic(
+    op: Operator,
+    operand: OperandType,
+) -> Option<&'static OperatorIntrinsic> {
+    OPERATORS
+        .iter()
+        .find(|intrinsic| intrinsic.op == op && intrinsic.operand == operand)
+}
+
+/// Find an idiom matching `outer(args)` where `arg_paths` gives the function
+/// path of each argument that is itself a call
+///
+/// Returns the idiom and the position of the argument that matched `inner`.
+pub fn match_idiom<'p>(
+    outer_path: &str,
+    arg_paths: impl IntoIterator<Item = Option<&'p str>>,
+) -> Option<(&'static Idiom, usize)> {
+    let outer = math_library_name(outer_path)?;
+    let candidates: Vec<&Idiom> = IDIOMS.iter().filter(|i| i.outer == outer).collect();
+    if candidates.is_empty() {
+        return None;
+    }
+
+    for (position, arg_path) in arg_paths.into_iter().enumerate() {
+        let Some(inner) = arg_path.and_then(math_library_name) else {
+            continue;
+        };
+        if let Some(idiom) = candidates.iter().find(|i| i.inner == inner) {
+            return Some((idiom, position));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operator_lookup() {
+        assert_eq!(
+            lookup_operator("/Script/Engine.KismetMathLibrary:Add_IntInt"),
+            Some(Operator::Add)
+        );
+        assert_eq!(
+            lookup_operator("/Script/Engine.KismetMathLibrary:Not_PreBool").map(|op| op.arity()),
+            Some(1)
+        );
+        assert_eq!(lookup_operator("/Script/Engine.Actor:Add_IntInt"), None);
+    }
+
+    #[test]
+    fn test_idiom_matching() {
+        let (idiom, position) = match_idiom(
+            "/Script/Engine.KismetMathLibrary:VSize",
+            [Some(
+                "/Script/Engine.KismetMathLibrary:Subtract_VectorVector",
+            )],
+        )
+        .unwrap();
+        assert_eq!(idiom.replacement, "FVector::Distance");
+        assert_eq!(position, 0);
+
+        let (idiom, position) = match_idiom(
+            "/Script/Engine.KismetMathLibrary:Dot_VectorVector",
+            [
+                None,
+                Some("/Script/Engine.KismetMathLibrary:Cross_VectorVector"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(idiom.replacement, "FVector::Triple");
+        assert_eq!(position, 1);
+
+        assert!(
+            match_idiom(
+                "/Script/Engine.KismetMathLibrary:VSize",
+                [Some("/Script/Engine.KismetMathLibrary:Add_VectorVector")],
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_date_time_components() {
+        assert_eq!(date_time_components(0), Some([1, 1, 1, 0, 0, 0, 0]));
+        // 2000-02-29 13:45:30.250
+        assert_eq!(
+            date_time_components(630_874_287_302_500_000),
+            Some([2000, 2, 29, 13, 45, 30, 250])
+        );
+        assert_eq!(date_time_components(1), None);
+    }
+}
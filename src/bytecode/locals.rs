@@ -0,0 +1,71 @@
+/// Local variables a function declares but never uses
+///
+/// Deleting nodes from a Blueprint doesn't always delete the local variables
+/// and temporaries they used, so the compiled function keeps declaring them.
+/// A local that no expression reads or writes, directly or as part of a
+/// struct or array it's reached through, is one of those leftovers.
+use std::collections::HashSet;
+
+use super::expr::Expr;
+use super::flags::CPF_PARM;
+use super::pattern::property_accesses;
+
+/// The properties of a function that aren't parameters and that
+/// `expressions` never read or write, in the order they're declared
+pub fn unused_locals<'p>(
+    properties: &'p [jmap::Property],
+    expressions: &[Expr],
+) -> Vec<&'p jmap::Property> {
+    let used: HashSet<u64> = property_accesses(expressions)
+        .into_iter()
+        .map(|(_, property, _)| property.address.0)
+        .collect();
+    properties
+        .iter()
+        .filter(|prop| prop.flags.bits() & CPF_PARM == 0 && !used.contains(&prop.address.0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::expr::ExprKind;
+    use crate::bytecode::refs::PropertyRef;
+    use crate::bytecode::testing::{at, call_with, local, property_json};
+    use crate::bytecode::types::Address;
+
+    #[test]
+    fn test_unused_locals() {
+        let property = |name: &str, address: u64, flags: u64| {
+            let mut property = property_json(name, address, 0, serde_json::json!({"type": "Int"}));
+            property["flags"] = flags.into();
+            serde_json::from_value::<jmap::Property>(property).unwrap()
+        };
+        let properties = [
+            property("Count", 1, CPF_PARM),
+            property("Total", 2, 0),
+            property("Temp_struct_Variable", 3, 0),
+            property("Temp_int_Variable", 4, 0),
+        ];
+        // Print(Total); return Temp_struct_Variable.Quantity
+        let expressions = [
+            call_with(0, "Print", vec![local(1, 2)]),
+            at(
+                10,
+                ExprKind::Return(Box::new(at(
+                    11,
+                    ExprKind::StructMemberContext {
+                        struct_expr: Box::new(local(12, 3)),
+                        member: PropertyRef::new(Address::new(0x40)),
+                    },
+                ))),
+            ),
+        ];
+
+        let unused: Vec<&str> = unused_locals(&properties, &expressions)
+            .into_iter()
+            .map(|prop| prop.name.as_str())
+            .collect();
+        assert_eq!(unused, ["Temp_int_Variable"]);
+    }
+}
@@ -1,9 +1,25 @@
 /// Loop detection and analysis
 ///
 /// Identifies natural loops in the control flow graph using back edges
-use super::cfg::{BlockId, ControlFlowGraph};
+use super::cfg::{BasicBlock, BlockId, ControlFlowGraph, Terminator};
 use super::dominators::DominatorTree;
+use super::flow_graph::FlowGraph;
 use std::collections::{HashSet, VecDeque};
+use std::fmt::{self, Write};
+use tracing::info_span;
+
+/// Dominators and loops of a CFG, after giving every loop a pre-header so
+/// the structurer sees a single entry into each one
+pub fn normalize_loops(cfg: &mut ControlFlowGraph) -> (DominatorTree, LoopInfo) {
+    let dom_tree = DominatorTree::compute(cfg);
+    let loop_info = LoopInfo::analyze(cfg, &dom_tree);
+    if loop_info.insert_preheaders(cfg).is_empty() {
+        return (dom_tree, loop_info);
+    }
+    let dom_tree = DominatorTree::compute(cfg);
+    let loop_info = LoopInfo::analyze(cfg, &dom_tree);
+    (dom_tree, loop_info)
+}
 
 /// A natural loop in the control flow graph
 #[derive(Debug, Clone)]
@@ -20,6 +36,10 @@ pub struct Loop {
     /// Exit blocks (blocks in the loop with successors outside)
     pub exit_blocks: HashSet<BlockId>,
 
+    /// Block outside the loop that is the only way into the header and does
+    /// nothing but jump to it, if there is one
+    pub preheader: Option<BlockId>,
+
     /// The parent loop (if this is a nested loop)
     pub parent: Option<usize>,
 
@@ -34,6 +54,7 @@ impl Loop {
             blocks: HashSet::new(),
             back_edges: Vec::new(),
             exit_blocks: HashSet::new(),
+            preheader: None,
             parent: None,
             children: Vec::new(),
         }
@@ -64,7 +85,8 @@ pub struct LoopInfo {
 
 impl LoopInfo {
     /// Detect all natural loops in the CFG
-    pub fn analyze(cfg: &ControlFlowGraph, dom_tree: &DominatorTree) -> Self {
+    pub fn analyze(cfg: &impl FlowGraph, dom_tree: &DominatorTree) -> Self {
+        let _span = info_span!("loops").entered();
         let mut loops = Vec::new();
 
         // Step 1: Find all back edges
@@ -94,9 +116,10 @@ impl LoopInfo {
             loops[loop_idx].blocks.extend(loop_blocks);
         }
 
-        // Step 3: Find exit blocks for each loop
+        // Step 3: Find exit blocks and pre-headers for each loop
         for loop_info in &mut loops {
             loop_info.exit_blocks = Self::find_exit_blocks(cfg, &loop_info.blocks);
+            loop_info.preheader = Self::find_preheader(cfg, loop_info);
         }
 
         // Step 4: Build loop nesting tree
@@ -107,17 +130,14 @@ impl LoopInfo {
 
     /// Find all back edges in the CFG
     /// A back edge (B -> H) exists if H dominates B
-    fn find_back_edges(
-        cfg: &ControlFlowGraph,
-        dom_tree: &DominatorTree,
-    ) -> Vec<(BlockId, BlockId)> {
+    fn find_back_edges(cfg: &impl FlowGraph, dom_tree: &DominatorTree) -> Vec<(BlockId, BlockId)> {
         let mut back_edges = Vec::new();
 
-        for block in &cfg.blocks {
-            for &succ in &block.successors {
+        for block in cfg.nodes() {
+            for &succ in cfg.successors(block) {
                 // If successor dominates this block, it's a back edge
-                if dom_tree.dominates(succ, block.id) {
-                    back_edges.push((block.id, succ));
+                if dom_tree.dominates(succ, block) {
+                    back_edges.push((block, succ));
                 }
             }
         }
@@ -130,7 +150,7 @@ impl LoopInfo {
     /// - The header
     /// - All nodes that can reach the latch without going through the header
     fn find_natural_loop(
-        cfg: &ControlFlowGraph,
+        cfg: &impl FlowGraph,
         header: BlockId,
         latch: BlockId,
     ) -> HashSet<BlockId> {
@@ -138,17 +158,18 @@ impl LoopInfo {
         loop_blocks.insert(header);
         loop_blocks.insert(latch);
 
-        // Work backwards from the latch to find all blocks that can reach it
+        // Work backwards from the latch to find all blocks that can reach it;
+        // a block looping to itself has nothing else in its loop
         let mut worklist = VecDeque::new();
-        worklist.push_back(latch);
+        if latch != header {
+            worklist.push_back(latch);
+        }
 
         while let Some(block_id) = worklist.pop_front() {
-            if let Some(block) = cfg.get_block(block_id) {
-                for &pred in &block.predecessors {
-                    // Don't go past the header
-                    if pred != header && loop_blocks.insert(pred) {
-                        worklist.push_back(pred);
-                    }
+            for &pred in cfg.predecessors(block_id) {
+                // Don't go past the header
+                if pred != header && loop_blocks.insert(pred) {
+                    worklist.push_back(pred);
                 }
             }
         }
@@ -158,19 +179,14 @@ impl LoopInfo {
 
     /// Find exit blocks for a loop
     /// An exit block is a block in the loop with a successor outside the loop
-    fn find_exit_blocks(
-        cfg: &ControlFlowGraph,
-        loop_blocks: &HashSet<BlockId>,
-    ) -> HashSet<BlockId> {
+    fn find_exit_blocks(cfg: &impl FlowGraph, loop_blocks: &HashSet<BlockId>) -> HashSet<BlockId> {
         let mut exit_blocks = HashSet::new();
 
         for &block_id in loop_blocks {
-            if let Some(block) = cfg.get_block(block_id) {
-                for &succ in &block.successors {
-                    if !loop_blocks.contains(&succ) {
-                        exit_blocks.insert(block_id);
-                        break;
-                    }
+            for &succ in cfg.successors(block_id) {
+                if !loop_blocks.contains(&succ) {
+                    exit_blocks.insert(block_id);
+                    break;
                 }
             }
         }
@@ -178,6 +194,91 @@ impl LoopInfo {
         exit_blocks
     }
 
+    /// Find the block that is a loop's single entry from outside, if it only
+    /// jumps to the header
+    fn find_preheader(cfg: &impl FlowGraph, loop_info: &Loop) -> Option<BlockId> {
+        let mut entering = cfg
+            .predecessors(loop_info.header)
+            .iter()
+            .filter(|pred| !loop_info.blocks.contains(pred));
+        let (Some(&pred), None) = (entering.next(), entering.next()) else {
+            return None;
+        };
+        (cfg.is_empty(pred) && cfg.successors(pred) == [loop_info.header]).then_some(pred)
+    }
+
+    /// Give every loop without one an empty pre-header block
+    ///
+    /// Edges entering the header from outside the loop are redirected to the
+    /// new block, which jumps to the header, so each loop has a single place
+    /// to put code that runs once before it: the initializer of a `for`, or
+    /// hoisted loop-invariant code. A header that is the entry block gets a
+    /// pre-header that becomes the new entry. Returns the blocks added; the
+    /// dominator tree and loop info must be recomputed if any were.
+    pub fn insert_preheaders(&self, cfg: &mut ControlFlowGraph) -> Vec<BlockId> {
+        let _span = info_span!("preheaders").entered();
+        let mut inserted = Vec::new();
+
+        for loop_info in &self.loops {
+            if loop_info.preheader.is_some() {
+                continue;
+            }
+            let header = loop_info.header;
+            let entering: Vec<BlockId> = cfg.blocks[header.0]
+                .predecessors
+                .iter()
+                .copied()
+                .filter(|pred| !loop_info.blocks.contains(pred))
+                .collect();
+
+            let id = BlockId(cfg.blocks.len());
+            let mut preheader = BasicBlock::new(id, cfg.blocks[header.0].start_offset);
+            preheader.terminator = Terminator::Goto { target: header };
+            preheader.successors = vec![header];
+            preheader.predecessors = entering.clone();
+
+            for &pred in &entering {
+                let block = &mut cfg.blocks[pred.0];
+                retarget(&mut block.successors, header, id);
+                match &mut block.terminator {
+                    Terminator::Goto { target } => {
+                        retarget(std::slice::from_mut(target), header, id)
+                    }
+                    Terminator::Branch {
+                        true_target,
+                        false_target,
+                        ..
+                    } => {
+                        retarget(std::slice::from_mut(true_target), header, id);
+                        retarget(std::slice::from_mut(false_target), header, id);
+                    }
+                    _ => {}
+                }
+            }
+
+            // The pre-header takes the place of the first entering edge
+            let predecessors = &mut cfg.blocks[header.0].predecessors;
+            let mut replaced = false;
+            predecessors.retain_mut(|pred| {
+                if !entering.contains(pred) {
+                    return true;
+                }
+                *pred = id;
+                !std::mem::replace(&mut replaced, true)
+            });
+            if !replaced {
+                predecessors.push(id);
+            }
+            if cfg.entry_block == header {
+                cfg.entry_block = id;
+            }
+            cfg.blocks.push(preheader);
+            inserted.push(id);
+        }
+
+        inserted
+    }
+
     /// Build the loop nesting tree
     /// A loop L1 is nested in L2 if all blocks of L1 are contained in L2
     fn build_loop_tree(loops: &mut Vec<Loop>) {
@@ -245,34 +346,87 @@ impl LoopInfo {
         self.loops.iter().any(|l| l.header == block)
     }
 
-    /// Print loop information
-    pub fn print_debug(&self) {
-        println!("Loop Analysis:");
-        println!("  Total Loops: {}", self.loops.len());
-        println!();
+    /// Write loop information in a human-readable format
+    pub fn write_debug(&self, out: &mut dyn Write) -> fmt::Result {
+        writeln!(out, "Loop Analysis:")?;
+        writeln!(out, "  Total Loops: {}", self.loops.len())?;
+        writeln!(out)?;
 
         for (i, loop_info) in self.loops.iter().enumerate() {
-            println!("Loop {}:", i);
-            println!("  Header: {:?}", loop_info.header);
-            println!("  Blocks: {:?}", {
+            writeln!(out, "Loop {}:", i)?;
+            writeln!(out, "  Header: {:?}", loop_info.header)?;
+            writeln!(out, "  Blocks: {:?}", {
                 let mut blocks: Vec<_> = loop_info.blocks.iter().collect();
                 blocks.sort();
                 blocks
-            });
-            println!("  Back Edges: {:?}", loop_info.back_edges);
-            println!("  Exit Blocks: {:?}", {
+            })?;
+            writeln!(out, "  Back Edges: {:?}", loop_info.back_edges)?;
+            if let Some(preheader) = loop_info.preheader {
+                writeln!(out, "  Pre-header: {:?}", preheader)?;
+            }
+            writeln!(out, "  Exit Blocks: {:?}", {
                 let mut exits: Vec<_> = loop_info.exit_blocks.iter().collect();
                 exits.sort();
                 exits
-            });
+            })?;
             if let Some(parent) = loop_info.parent {
-                println!("  Parent Loop: {}", parent);
+                writeln!(out, "  Parent Loop: {}", parent)?;
             }
             if !loop_info.children.is_empty() {
-                println!("  Child Loops: {:?}", loop_info.children);
+                writeln!(out, "  Child Loops: {:?}", loop_info.children)?;
             }
-            println!("  Nesting Depth: {}", loop_info.nesting_depth(&self.loops));
-            println!();
+            writeln!(
+                out,
+                "  Nesting Depth: {}",
+                loop_info.nesting_depth(&self.loops)
+            )?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+fn retarget(targets: &mut [BlockId], from: BlockId, to: BlockId) {
+    for target in targets {
+        if *target == from {
+            *target = to;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::{BytecodeBuilder, parse};
+
+    #[test]
+    fn test_insert_preheaders() {
+        // loop: if (!true) goto end; Math(); goto loop; end: return;
+        let mut b = BytecodeBuilder::default();
+        let start = b.here();
+        b.op(EExprToken::JumpIfNot);
+        let exit = b.placeholder();
+        b.op(EExprToken::True);
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+        b.op(EExprToken::Jump);
+        let back = b.placeholder();
+        let end = b.here();
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(exit, end);
+        b.patch(back, start);
+
+        let mut cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let loop_info = LoopInfo::analyze(&cfg, &DominatorTree::compute(&cfg));
+        assert_eq!(loop_info.loops[0].preheader, None);
+
+        let inserted = loop_info.insert_preheaders(&mut cfg);
+        assert_eq!(inserted.len(), 1);
+        assert_eq!(cfg.entry_block, inserted[0]);
+
+        let loop_info = LoopInfo::analyze(&cfg, &DominatorTree::compute(&cfg));
+        assert_eq!(loop_info.loops[0].preheader, Some(inserted[0]));
+        assert!(loop_info.insert_preheaders(&mut cfg).is_empty());
+    }
+}
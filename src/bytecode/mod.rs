@@ -1,12 +1,39 @@
 pub mod address_index;
+pub mod align;
+pub mod array_get;
+pub mod call_graph;
 pub mod cfg;
+pub mod const_eval;
+pub mod cost;
 pub mod dominators;
 pub mod expr;
+pub mod flags;
+pub mod flow_graph;
+pub mod getters;
+pub mod histogram;
+pub mod hit_counts;
+pub mod induction;
+pub mod intrinsics;
+pub mod locals;
 pub mod logger;
 pub mod loops;
+pub mod name_index;
+pub mod obfuscation;
 pub mod opcodes;
 pub mod parser;
+pub mod pattern;
+pub mod query;
 pub mod reader;
+pub mod reducibility;
 pub mod refs;
+pub mod regions;
+pub mod rpc_audit;
+pub mod simplify;
 pub mod structured;
+#[cfg(test)]
+pub mod testing;
+pub mod ticks;
+pub mod trace;
 pub mod types;
+pub mod ubergraph;
+pub mod xrefs;
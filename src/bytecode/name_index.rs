@@ -0,0 +1,105 @@
+/// FName usage index
+///
+/// Collects every `NameConst` literal across the dump so gameplay tags and
+/// named events can be traced back to the functions that use them.
+use super::expr::{Expr, ExprKind};
+use super::types::{BytecodeOffset, Name};
+use std::collections::BTreeMap;
+
+/// A single use of a name constant inside a function's script
+#[derive(Debug, Clone)]
+pub struct NameUse {
+    /// Full path of the function containing the reference
+    pub function: String,
+    /// Offset of the `NameConst` expression
+    pub offset: BytecodeOffset,
+}
+
+/// Index of name constants keyed by the name
+///
+/// Names differing only in case are one entry, printed with the casing
+/// they were first seen with.
+#[derive(Debug, Default)]
+pub struct NameIndex {
+    pub entries: BTreeMap<Name, Vec<NameUse>>,
+}
+
+impl NameIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record every `NameConst` found in the given function's expressions
+    pub fn add_function(&mut self, function: &str, expressions: &[Expr]) {
+        for expr in expressions {
+            expr.walk(&mut |e| {
+                if let ExprKind::NameConst(name) = &e.kind {
+                    self.entries.entry(*name).or_default().push(NameUse {
+                        function: function.to_string(),
+                        offset: e.offset,
+                    });
+                }
+            });
+        }
+    }
+
+    /// Find uses of a name
+    ///
+    /// Matches the name ignoring case, plus any dotted children so that
+    /// looking up a gameplay tag like `Weapon` also reports `Weapon.Fire`.
+    pub fn find(&self, query: &str) -> Vec<(&str, &[NameUse])> {
+        let child_prefix = format!("{}.", query);
+        let is_child = |name: &str| {
+            name.get(..child_prefix.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&child_prefix))
+        };
+        self.entries
+            .iter()
+            .filter(|(name, _)| name.matches(query) || is_child(name.as_str()))
+            .map(|(name, uses)| (name.as_str(), uses.as_slice()))
+            .collect()
+    }
+
+    /// Total number of recorded references
+    pub fn total_uses(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::types::Name;
+
+    fn name_const(offset: usize, name: &str) -> Expr {
+        Expr::new(
+            BytecodeOffset::new(offset),
+            ExprKind::NameConst(Name::new(name)),
+        )
+    }
+
+    #[test]
+    fn test_find_includes_child_tags() {
+        let mut index = NameIndex::new();
+        index.add_function(
+            "/Game/BP_Gun.BP_Gun_C:Fire",
+            &[
+                name_const(0, "Weapon.Fire"),
+                name_const(10, "Weapon.Reload"),
+            ],
+        );
+        index.add_function(
+            "/Game/BP_Gun.BP_Gun_C:Other",
+            &[name_const(4, "WeaponFire")],
+        );
+
+        let found = index.find("Weapon");
+        let names: Vec<_> = found.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["Weapon.Fire", "Weapon.Reload"]);
+
+        let exact = index.find("weapon.fire");
+        assert_eq!(exact.len(), 1);
+        assert_eq!(exact[0].1[0].offset, BytecodeOffset::new(0));
+        assert_eq!(index.total_uses(), 3);
+    }
+}
@@ -0,0 +1,575 @@
+/// Detecting and undoing shuffled opcode values
+///
+/// Some protected games ship a VM whose opcodes have different byte values
+/// than the stock engine. Scripts from such a game mostly fail to decode, and
+/// the ones that do decode have an odd mix of opcodes, so comparing the opcode
+/// distribution against a dump from a stock game shows the remapping.
+///
+/// The shuffled values can often be recovered, because compiled scripts obey
+/// rules no wrong assignment keeps for long: every script ends with
+/// `EndOfScript` right after a `Return`, call arguments are closed by
+/// `EndFunctionParms`, only statements and calls stand alone, jumps land on
+/// statements, and context skip offsets match the size of what they skip.
+/// Recovery searches for an assignment under which every script decodes and
+/// keeps those rules, preferring ones that move few opcodes. Opcodes with the
+/// same operand layout, such as `True` and `False`, can't be told apart this
+/// way; they're tried in order of how common they are, so the result is a
+/// consistent map rather than a proven one.
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::expr::{Expr, ExprKind};
+use super::histogram::OpcodeHistogram;
+use super::opcodes::{EExprToken, OpcodeMap};
+use super::parser::ScriptParser;
+use super::reader::ScriptReader;
+use super::types::BytecodeOffset;
+use crate::error::KismetError;
+
+/// Shannon entropy of the opcode distribution, in bits
+pub fn entropy(histogram: &OpcodeHistogram) -> f64 {
+    let total = histogram.total() as f64;
+    (0..=u8::MAX)
+        .map(|byte| histogram.count(EExprToken::from(byte)) as f64 / total)
+        .filter(|&p| p > 0.0)
+        .map(|p| -p * p.log2())
+        .sum()
+}
+
+/// Jensen-Shannon divergence of two opcode distributions, from 0 for the
+/// same distribution to 1 for ones with no opcode in common
+pub fn divergence(a: &OpcodeHistogram, b: &OpcodeHistogram) -> f64 {
+    let (a_total, b_total) = (a.total() as f64, b.total() as f64);
+    (0..=u8::MAX)
+        .map(EExprToken::from)
+        .map(|opcode| {
+            let p = a.count(opcode) as f64 / a_total;
+            let q = b.count(opcode) as f64 / b_total;
+            let m = (p + q) / 2.0;
+            let term = |x: f64| if x > 0.0 { x * (x / m).log2() } else { 0.0 };
+            (term(p) + term(q)) / 2.0
+        })
+        .sum()
+}
+
+/// An opcode map found by [`recover_opcode_map`]
+#[derive(Debug)]
+pub struct Recovery {
+    /// The map under which the most scripts decoded
+    pub map: OpcodeMap,
+    /// Scripts that decode and keep the rules under `map`
+    pub decoded: usize,
+    /// Whether every script does, rather than the search giving up
+    pub complete: bool,
+    /// Scripts parsed during the search
+    pub attempts: usize,
+}
+
+/// Search for the opcode map a set of scripts was compiled with
+///
+/// Each byte is first tried as its stock opcode, then as the `candidates` in
+/// order, most likely first; opcodes missing from them are tried last in
+/// opcode order. The search stops after parsing `budget` scripts.
+pub fn recover_opcode_map(
+    scripts: &[&[u8]],
+    names: &BTreeMap<u32, String>,
+    candidates: &[EExprToken],
+    budget: usize,
+) -> Recovery {
+    let mut scripts = scripts.to_vec();
+    scripts.sort_by_key(|script| script.len());
+
+    let mut order = candidates.to_vec();
+    order.extend(
+        EExprToken::ALL
+            .iter()
+            .filter(|token| !candidates.contains(token)),
+    );
+
+    // Every script ends with the same byte, so that's EndOfScript, and
+    // functions without a return value end with `return;`, which is usually
+    // the most common ending
+    let mut map = OpcodeMap::empty();
+    let last_bytes: Vec<u8> = scripts
+        .iter()
+        .filter_map(|script| script.last().copied())
+        .collect();
+    if let Some(&last) = last_bytes.first()
+        && last_bytes.iter().all(|&byte| byte == last)
+    {
+        map.assign(last, Some(EExprToken::EndOfScript));
+        let mut endings: BTreeMap<[u8; 2], usize> = BTreeMap::new();
+        for script in &scripts {
+            if let Some([ret, nothing, _]) = script.last_chunk::<3>() {
+                *endings.entry([*ret, *nothing]).or_default() += 1;
+            }
+        }
+        if let Some(([ret, nothing], &count)) = endings.iter().max_by_key(|(_, count)| **count)
+            && count * 2 > scripts.len()
+            && ret != nothing
+            && ![*ret, *nothing].contains(&last)
+        {
+            map.assign(*ret, Some(EExprToken::Return));
+            map.assign(*nothing, Some(EExprToken::Nothing));
+        }
+    }
+
+    let mut solver = Solver {
+        scripts: &scripts,
+        names,
+        order: &order,
+        leaves: leaf_sizes(),
+        max_moved: 0,
+        hit_max_moved: false,
+        attempts: 0,
+        budget,
+        best: (0, map.clone()),
+    };
+    // Look for maps that move few opcodes first, since wrong guesses tend to
+    // need more and more unusual bytes to keep decoding
+    let mut solved = None;
+    for max_moved in map.remapped().count()..=256 {
+        solver.max_moved = max_moved;
+        solver.hit_max_moved = false;
+        let mut attempt = map.clone();
+        match solver.solve(&mut attempt, 0) {
+            Outcome::Solved => {
+                solved = Some(attempt);
+                break;
+            }
+            Outcome::Conflict(_) if solver.hit_max_moved => {}
+            Outcome::Conflict(_) | Outcome::OutOfBudget => break,
+        }
+    }
+    let attempts = solver.attempts;
+    match solved {
+        Some(map) => Recovery {
+            map,
+            decoded: scripts.len(),
+            complete: true,
+            attempts,
+        },
+        None => {
+            let (decoded, map) = solver.best;
+            Recovery {
+                map,
+                decoded,
+                complete: false,
+                attempts,
+            }
+        }
+    }
+}
+
+/// Where decoding met a byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    /// The start of a statement
+    Statement,
+    /// Inside a statement
+    Operand,
+}
+
+enum Check {
+    Decoded,
+    /// Decoding reached a byte the map doesn't assign
+    Unassigned {
+        byte: u8,
+        position: Position,
+    },
+    Contradiction,
+}
+
+/// Whether `script` decodes under `map` and keeps the rules compiled code
+/// keeps, and the assigned bytes decoding read as opcodes
+///
+/// Only those bytes decide the outcome, so changing any other byte's
+/// assignment can't change it. Statements are checked as soon as they're
+/// decoded, so a bad guess is caught before decoding reaches more unassigned
+/// bytes.
+fn check(script: &[u8], names: &BTreeMap<u32, String>, map: &OpcodeMap) -> (Check, BTreeSet<u8>) {
+    let mut parser = ScriptParser::new(ScriptReader::new(script, names)).with_opcode_map(map);
+    let check = decode_statements(&mut parser, script.len());
+    let read = (0..=u8::MAX)
+        .filter(|&byte| {
+            let token = map.decode(byte);
+            !matches!(token, EExprToken::Unknown(_)) && parser.opcodes().count(token) > 0
+        })
+        .collect();
+    (check, read)
+}
+
+fn decode_statements(parser: &mut ScriptParser, len: usize) -> Check {
+    let mut statements = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let start = BytecodeOffset::new(offset);
+        let statement = match parser.parse_expr(&mut offset) {
+            Ok(statement) => statement,
+            Err(KismetError::BytecodeParse {
+                offset,
+                opcode: Some(EExprToken::Unknown(byte)),
+                ..
+            }) => {
+                let position = if offset == start {
+                    Position::Statement
+                } else {
+                    Position::Operand
+                };
+                return Check::Unassigned { byte, position };
+            }
+            Err(_) => return Check::Contradiction,
+        };
+        if let ExprKind::EndOfScript = statement.kind {
+            break;
+        }
+        if !is_well_formed(&statement) || !parser.diagnostics().is_empty() {
+            return Check::Contradiction;
+        }
+        statements.push(statement);
+    }
+    // Compiled functions end with a return, and nothing follows EndOfScript
+    let returns = matches!(
+        statements.last(),
+        Some(Expr {
+            kind: ExprKind::Return(_),
+            ..
+        })
+    );
+    if offset == len && returns && jumps_land_on_statements(&statements) {
+        Check::Decoded
+    } else {
+        Check::Contradiction
+    }
+}
+
+/// Whether `statement` could have been compiled: a statement or a call at
+/// the top, and no statement inside, like a jump passed as an argument
+fn is_well_formed(statement: &Expr) -> bool {
+    let mut statements = 0;
+    statement.walk(&mut |e| {
+        if is_statement(e) {
+            statements += 1;
+        }
+    });
+    if is_statement(statement) {
+        statements == 1
+    } else {
+        statements == 0 && is_expression_statement(statement)
+    }
+}
+
+/// Whether every jump goes to the start of a statement
+fn jumps_land_on_statements(expressions: &[Expr]) -> bool {
+    let starts: BTreeSet<BytecodeOffset> = expressions.iter().map(|e| e.offset).collect();
+    let mut lands = true;
+    for statement in expressions {
+        statement.walk(&mut |e| match &e.kind {
+            ExprKind::Jump { target }
+            | ExprKind::JumpIfNot { target, .. }
+            | ExprKind::PushExecutionFlow {
+                push_offset: target,
+            } => lands &= starts.contains(target),
+            _ => {}
+        });
+    }
+    lands
+}
+
+fn is_statement(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::Let { .. }
+            | ExprKind::LetObj { .. }
+            | ExprKind::LetWeakObjPtr { .. }
+            | ExprKind::LetBool { .. }
+            | ExprKind::LetDelegate { .. }
+            | ExprKind::LetMulticastDelegate { .. }
+            | ExprKind::LetValueOnPersistentFrame { .. }
+            | ExprKind::Return(_)
+            | ExprKind::Jump { .. }
+            | ExprKind::JumpIfNot { .. }
+            | ExprKind::ComputedJump { .. }
+            | ExprKind::PushExecutionFlow { .. }
+            | ExprKind::PopExecutionFlow
+            | ExprKind::PopExecutionFlowIfNot { .. }
+            | ExprKind::Assert { .. }
+            | ExprKind::Breakpoint
+            | ExprKind::Tracepoint
+            | ExprKind::WireTracepoint
+            | ExprKind::InstrumentationEvent { .. }
+            | ExprKind::EndOfScript
+    )
+}
+
+/// Expressions compiled for their side effects, which can stand alone
+fn is_expression_statement(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::VirtualFunction { .. }
+            | ExprKind::FinalFunction { .. }
+            | ExprKind::LocalVirtualFunction { .. }
+            | ExprKind::LocalFinalFunction { .. }
+            | ExprKind::CallMath { .. }
+            | ExprKind::CallMulticastDelegate { .. }
+            | ExprKind::Context { .. }
+            | ExprKind::ClassContext { .. }
+            | ExprKind::InterfaceContext(_)
+            | ExprKind::BindDelegate { .. }
+            | ExprKind::AddMulticastDelegate { .. }
+            | ExprKind::RemoveMulticastDelegate { .. }
+            | ExprKind::ClearMulticastDelegate(_)
+            | ExprKind::SetArray { .. }
+            | ExprKind::SetSet { .. }
+            | ExprKind::SetMap { .. }
+            | ExprKind::Nothing
+    )
+}
+
+/// Whether an opcode can decode to something allowed at `position`, the
+/// opcode counterpart of [`is_well_formed`]
+fn fits(token: EExprToken, position: Position) -> bool {
+    use EExprToken::*;
+    let statement = matches!(
+        token,
+        Let | LetObj
+            | LetWeakObjPtr
+            | LetBool
+            | LetDelegate
+            | LetMulticastDelegate
+            | LetValueOnPersistentFrame
+            | Return
+            | Jump
+            | JumpIfNot
+            | ComputedJump
+            | PushExecutionFlow
+            | PopExecutionFlow
+            | PopExecutionFlowIfNot
+            | Assert
+            | Breakpoint
+            | Tracepoint
+            | WireTracepoint
+            | InstrumentationEvent
+            | EndOfScript
+    );
+    let call = matches!(
+        token,
+        VirtualFunction
+            | FinalFunction
+            | LocalVirtualFunction
+            | LocalFinalFunction
+            | CallMath
+            | CallMulticastDelegate
+            | Context
+            | ContextFailSilent
+            | ClassContext
+            | InterfaceContext
+            | BindDelegate
+            | AddMulticastDelegate
+            | RemoveMulticastDelegate
+            | ClearMulticastDelegate
+            | SetArray
+            | SetSet
+            | SetMap
+            | Nothing
+    );
+    match position {
+        Position::Statement => statement || call,
+        Position::Operand => !statement,
+    }
+}
+
+/// The operand size of every opcode that decodes to a plain value without
+/// nested expressions, by opcode value
+///
+/// Two such opcodes with the same size decode the same bytes and pass the
+/// same checks, so the search only needs to try one of them. Sizes are found
+/// by decoding each opcode with two different operand fillers; opcodes whose
+/// size depends on their operands are left out.
+fn leaf_sizes() -> [Option<usize>; 256] {
+    let names = BTreeMap::new();
+    let mut sizes = [None; 256];
+    for &token in EExprToken::ALL {
+        if fits(token, Position::Statement) {
+            continue;
+        }
+        let size = |filler: u8| {
+            let mut probe = vec![filler; 64];
+            probe[0] = token.opcode_value();
+            let mut parser = ScriptParser::new(ScriptReader::new(&probe, &names));
+            let mut offset = 0;
+            parser.parse_expr(&mut offset).ok()?;
+            (parser.opcodes().total() == 1).then_some(offset)
+        };
+        if let (Some(a), Some(b)) = (size(0x00), size(0xFF))
+            && a == b
+        {
+            sizes[token.opcode_value() as usize] = Some(a);
+        }
+    }
+    sizes
+}
+
+enum Outcome {
+    Solved,
+    /// No assignment works without changing one of these bytes
+    Conflict(BTreeSet<u8>),
+    OutOfBudget,
+}
+
+struct Solver<'s> {
+    /// Shortest first, so wrong guesses fail on cheap scripts
+    scripts: &'s [&'s [u8]],
+    names: &'s BTreeMap<u32, String>,
+    order: &'s [EExprToken],
+    leaves: [Option<usize>; 256],
+    /// Most bytes allowed to decode as something other than their stock opcode
+    max_moved: usize,
+    /// Whether `max_moved` kept the search from trying a guess
+    hit_max_moved: bool,
+    attempts: usize,
+    budget: usize,
+    /// Most scripts decoded so far, and the map that decoded them
+    best: (usize, OpcodeMap),
+}
+
+impl Solver<'_> {
+    /// Extend `map` until scripts from `from` on decode
+    ///
+    /// Scripts before `from` already decode. Assigning more bytes can't change
+    /// that, since decoding them never reached an unassigned byte. When a
+    /// guess fails for reasons that don't involve the byte guessed, trying
+    /// other values for it can't help, so the search jumps straight back to
+    /// the latest byte that was involved.
+    fn solve(&mut self, map: &mut OpcodeMap, from: usize) -> Outcome {
+        for index in from..self.scripts.len() {
+            if self.attempts == self.budget {
+                return Outcome::OutOfBudget;
+            }
+            self.attempts += 1;
+            let (check, read) = check(self.scripts[index], self.names, map);
+            match check {
+                Check::Decoded => {
+                    if index + 1 > self.best.0 {
+                        self.best = (index + 1, map.clone());
+                    }
+                }
+                Check::Contradiction => return Outcome::Conflict(read),
+                Check::Unassigned { byte, position } => {
+                    let mut conflict = read;
+                    let mut tried_leaves = BTreeSet::new();
+                    // Games often only move some opcodes, so the stock
+                    // meaning of a byte goes first
+                    let stock = EExprToken::from(byte);
+                    let order = std::iter::once(stock)
+                        .chain(self.order.iter().copied().filter(|&token| token != stock));
+                    for token in order {
+                        if matches!(token, EExprToken::Unknown(_)) || !fits(token, position) {
+                            continue;
+                        }
+                        if let Some(owner) = map.encode(token) {
+                            conflict.insert(owner);
+                            continue;
+                        }
+                        if token != stock && map.remapped().count() >= self.max_moved {
+                            // Undoing any move would make room for this one
+                            self.hit_max_moved = true;
+                            conflict.extend(map.remapped().map(|(moved, _)| moved));
+                            continue;
+                        }
+                        if let Some(size) = self.leaves[token.opcode_value() as usize]
+                            && !tried_leaves.insert(size)
+                        {
+                            continue;
+                        }
+                        map.assign(byte, Some(token));
+                        match self.solve(map, index) {
+                            Outcome::Conflict(bytes) if bytes.contains(&byte) => {
+                                conflict.extend(bytes.into_iter().filter(|&b| b != byte));
+                            }
+                            outcome => {
+                                if !matches!(outcome, Outcome::Solved) {
+                                    map.assign(byte, None);
+                                }
+                                return outcome;
+                            }
+                        }
+                    }
+                    map.assign(byte, None);
+                    return Outcome::Conflict(conflict);
+                }
+            }
+        }
+        Outcome::Solved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::BytecodeBuilder;
+
+    /// A VM that moved a few opcodes around
+    fn shuffled(token: EExprToken) -> u8 {
+        match token {
+            EExprToken::Return => 0x90,
+            EExprToken::CallMath => 0x04,
+            EExprToken::EndFunctionParms => 0x91,
+            other => other.opcode_value(),
+        }
+    }
+
+    /// `f(f(1)); return;` with `calls` calls, opcodes encoded by `encode`
+    fn nested_calls(calls: usize, encode: fn(EExprToken) -> u8) -> Vec<u8> {
+        let mut builder = BytecodeBuilder::default();
+        if calls > 0 {
+            for _ in 0..calls {
+                builder.u8(encode(EExprToken::CallMath)).address(0x1000);
+            }
+            builder.u8(encode(EExprToken::IntOne));
+            for _ in 0..calls {
+                builder.u8(encode(EExprToken::EndFunctionParms));
+            }
+        }
+        builder.u8(encode(EExprToken::Return));
+        builder.u8(encode(EExprToken::Nothing));
+        builder.build()
+    }
+
+    #[test]
+    fn test_recovers_shuffled_opcodes() {
+        let names = BTreeMap::new();
+        // Opcode frequencies from the same kind of code on a stock engine
+        let mut baseline = OpcodeHistogram::default();
+        for calls in 0..4 {
+            let script = nested_calls(calls, |token| token.opcode_value());
+            let mut parser = ScriptParser::new(ScriptReader::new(&script, &names));
+            parser.parse_all().unwrap();
+            baseline.merge(parser.opcodes());
+        }
+        let candidates: Vec<EExprToken> = baseline.sorted().into_iter().map(|(t, _)| t).collect();
+
+        let scripts: Vec<Vec<u8>> = (0..4).map(|calls| nested_calls(calls, shuffled)).collect();
+        let scripts: Vec<&[u8]> = scripts.iter().map(Vec::as_slice).collect();
+        let recovery = recover_opcode_map(&scripts, &names, &candidates, 100_000);
+        assert!(recovery.complete);
+        for token in [
+            EExprToken::Return,
+            EExprToken::CallMath,
+            EExprToken::EndFunctionParms,
+        ] {
+            assert_eq!(recovery.map.decode(shuffled(token)), token);
+        }
+    }
+
+    #[test]
+    fn test_divergence() {
+        let mut calls = OpcodeHistogram::default();
+        calls.record(EExprToken::CallMath);
+        let mut returns = OpcodeHistogram::default();
+        returns.record(EExprToken::Return);
+        assert_eq!(divergence(&calls, &calls), 0.0);
+        assert!((divergence(&calls, &returns) - 1.0).abs() < 1e-9);
+        calls.record(EExprToken::Return);
+        assert_eq!(entropy(&calls), 1.0);
+    }
+}
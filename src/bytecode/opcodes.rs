@@ -10,6 +10,17 @@ macro_rules! define_opcodes {
         }
 
         impl EExprToken {
+            /// Every opcode the decoder knows, in opcode order
+            pub const ALL: &'static [EExprToken] = &[$(EExprToken::$variant,)*];
+
+            /// The opcode named `name`, with or without the engine's `EX_` prefix
+            pub fn from_name(name: &str) -> Option<EExprToken> {
+                match name.strip_prefix("EX_").unwrap_or(name) {
+                    $(stringify!($variant) => Some(EExprToken::$variant),)*
+                    _ => None,
+                }
+            }
+
             pub fn opcode_value(&self) -> u8 {
                 match self {
                     $(EExprToken::$variant => $value,)*
@@ -78,6 +89,7 @@ define_opcodes! {
     (0x34, UnicodeStringConst),
     (0x35, Int64Const),
     (0x36, UInt64Const),
+    (0x37, DoubleConst),
     (0x38, PrimitiveCast),
     (0x39, SetSet),
     (0x3A, EndSet),
@@ -87,12 +99,14 @@ define_opcodes! {
     (0x3E, EndSetConst),
     (0x3F, MapConst),
     (0x40, EndMapConst),
+    (0x41, Vector3fConst),
     (0x42, StructMemberContext),
     (0x43, LetMulticastDelegate),
     (0x44, LetDelegate),
     (0x45, LocalVirtualFunction),
     (0x46, LocalFinalFunction),
     (0x48, LocalOutVariable),
+    (0x49, DefaultParmValue),
     (0x4A, DeprecatedOp4A),
     (0x4B, InstanceDelegate),
     (0x4C, PushExecutionFlow),
@@ -127,6 +141,56 @@ define_opcodes! {
     (0x6D, FieldPathConst),
 }
 
+/// Which opcode each byte value stands for
+///
+/// Stock engines use the values above, but some protected games shuffle
+/// them. A map says what such a VM means by each byte instead; bytes it
+/// doesn't assign decode as `Unknown`.
+#[derive(Debug, Clone)]
+pub struct OpcodeMap {
+    tokens: [Option<EExprToken>; 256],
+}
+
+impl OpcodeMap {
+    /// The map of a stock engine
+    pub fn stock() -> Self {
+        let mut map = Self::empty();
+        for &token in EExprToken::ALL {
+            map.assign(token.opcode_value(), Some(token));
+        }
+        map
+    }
+
+    /// A map that assigns no byte
+    pub fn empty() -> Self {
+        Self {
+            tokens: [None; 256],
+        }
+    }
+
+    pub fn decode(&self, byte: u8) -> EExprToken {
+        self.tokens[byte as usize].unwrap_or(EExprToken::Unknown(byte))
+    }
+
+    pub fn assign(&mut self, byte: u8, token: Option<EExprToken>) {
+        self.tokens[byte as usize] = token;
+    }
+
+    /// The byte that decodes as `token`, if any
+    pub fn encode(&self, token: EExprToken) -> Option<u8> {
+        let byte = self.tokens.iter().position(|&t| t == Some(token))?;
+        Some(byte as u8)
+    }
+
+    /// Bytes that decode as something other than their stock opcode
+    pub fn remapped(&self) -> impl Iterator<Item = (u8, EExprToken)> + '_ {
+        (0..=u8::MAX).filter_map(|byte| {
+            let token = self.tokens[byte as usize]?;
+            (token.opcode_value() != byte).then_some((byte, token))
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum EBlueprintTextLiteralType {
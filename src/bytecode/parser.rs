@@ -1,120 +1,300 @@
 /// Parser for Kismet bytecode to expression IR
-use super::expr::{Expr, ExprKind, SwitchCase, TextLiteral};
-use super::opcodes::{EBlueprintTextLiteralType, EExprToken};
+use super::expr::{Expr, ExprKind, SwitchCase, TextLiteral, TransformConst, ensure_stack};
+use super::histogram::OpcodeHistogram;
+use super::opcodes::{EBlueprintTextLiteralType, EExprToken, OpcodeMap};
 use super::reader::ScriptReader;
 use super::refs::{ClassRef, FunctionRef, ObjectRef, PropertyRef, StructRef};
-use super::types::BytecodeOffset;
+use super::types::{BytecodeOffset, Real};
+use crate::error::KismetError;
+use tracing::info_span;
+
+/// A non-fatal inconsistency found while decoding bytecode
+#[derive(Debug, Clone)]
+pub struct ParseDiagnostic {
+    pub offset: BytecodeOffset,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.offset, self.message)
+    }
+}
+
+/// Engine generation that compiled the bytecode, which decides how wide some
+/// constants are
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EngineVersion {
+    /// Vectors, rotators and transforms hold floats
+    #[default]
+    Ue4,
+    /// Large world coordinates: vectors, rotators and transforms hold doubles
+    Ue5,
+}
+
+/// Iterator over a script's top-level statements, see
+/// [`ScriptParser::instructions`]
+pub struct Instructions<'p, 'a> {
+    parser: &'p mut ScriptParser<'a>,
+    offset: usize,
+    failed: bool,
+}
+
+impl Iterator for Instructions<'_, '_> {
+    type Item = Result<(BytecodeOffset, usize, EExprToken, Expr), KismetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.offset;
+        let &byte = self.parser.reader.script().get(start)?;
+        if self.failed {
+            return None;
+        }
+        let opcode = self.parser.decode(byte);
+        match self.parser.parse_expr(&mut self.offset) {
+            Ok(expr) => Some(Ok((expr.offset, self.offset - start, opcode, expr))),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
 
 /// Parser that converts bytecode to expression IR
 pub struct ScriptParser<'a> {
     reader: ScriptReader<'a>,
+    diagnostics: Vec<ParseDiagnostic>,
+    opcodes: OpcodeHistogram,
+    /// How to read opcode bytes, if not as the stock engine does
+    opcode_map: Option<&'a OpcodeMap>,
+    engine_version: EngineVersion,
 }
 
 impl<'a> ScriptParser<'a> {
     pub fn new(reader: ScriptReader<'a>) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            diagnostics: Vec::new(),
+            opcodes: OpcodeHistogram::default(),
+            opcode_map: None,
+            engine_version: EngineVersion::default(),
+        }
     }
 
-    /// Parse all expressions in the script
-    pub fn parse_all(&mut self) -> Vec<Expr> {
-        let mut expressions = Vec::new();
-        let mut offset = 0;
+    /// Read constants at the widths `version` compiles them with
+    pub fn with_engine_version(mut self, version: EngineVersion) -> Self {
+        self.engine_version = version;
+        self
+    }
 
-        while offset < self.reader.script().len() {
-            let expr = self.parse_expr(&mut offset);
-            expressions.push(expr);
+    /// Decode opcode bytes with `map` instead of the stock assignment
+    pub fn with_opcode_map(mut self, map: &'a OpcodeMap) -> Self {
+        self.opcode_map = Some(map);
+        self
+    }
+
+    /// A vector, rotator or transform component
+    fn read_real(&self, offset: &mut usize) -> Result<Real, KismetError> {
+        Ok(match self.engine_version {
+            EngineVersion::Ue4 => Real::Single(self.reader.read_float(offset)?),
+            EngineVersion::Ue5 => Real::Double(self.reader.read_double(offset)?),
+        })
+    }
+
+    fn decode(&self, byte: u8) -> EExprToken {
+        match self.opcode_map {
+            Some(map) => map.decode(byte),
+            None => EExprToken::from(byte),
         }
+    }
 
-        // Assert that bytecode properly terminates with EndOfScript
-        assert!(
-            !expressions.is_empty()
-                && matches!(expressions.last().unwrap().kind, ExprKind::EndOfScript),
-            "Bytecode must terminate with EndOfScript"
-        );
+    /// Inconsistencies found while parsing, such as mismatched skip offsets
+    pub fn diagnostics(&self) -> &[ParseDiagnostic] {
+        &self.diagnostics
+    }
 
-        // Remove EndOfScript from the result
-        expressions.pop();
+    /// Every opcode read so far, including ones that ended a list and an
+    /// unknown opcode that stopped the parse
+    pub fn opcodes(&self) -> &OpcodeHistogram {
+        &self.opcodes
+    }
 
-        expressions
+    /// Verify that a context's skip offset covers exactly its decoded r-value expression
+    ///
+    /// When the context object is null the VM skips `skip_offset` bytes, so a
+    /// mismatch means either the bytecode or our decoding of it is wrong.
+    fn check_skip_offset(&mut self, context: &Expr, skip_offset: u32) {
+        let decoded_size = context.span().len();
+        if skip_offset as usize != decoded_size {
+            self.diagnostics.push(ParseDiagnostic {
+                offset: context.offset,
+                message: format!(
+                    "context skip offset {} does not match decoded expression size {}",
+                    skip_offset, decoded_size
+                ),
+            });
+        }
+    }
+
+    /// Verify that a switch offset lands just past the expression decoded
+    /// before it
+    ///
+    /// The VM carries on at a case's skip offset when the case doesn't match
+    /// and at the end offset once the switch has its value, so either being
+    /// off means control flow doesn't go where the decoded cases suggest.
+    fn check_switch_offset(&mut self, what: &str, target: BytecodeOffset, decoded_end: usize) {
+        if target.0 != decoded_end {
+            self.diagnostics.push(ParseDiagnostic {
+                offset: target,
+                message: format!(
+                    "switch {} {} does not match decoded end {}",
+                    what,
+                    target,
+                    BytecodeOffset::new(decoded_end)
+                ),
+            });
+        }
+    }
+
+    /// Parse all expressions in the script
+    pub fn parse_all(&mut self) -> Result<Vec<Expr>, KismetError> {
+        let _span = info_span!("parse").entered();
+        let mut expressions = self
+            .instructions()
+            .map(|instruction| instruction.map(|(_, _, _, expr)| expr))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Bytecode must terminate with EndOfScript, which isn't part of the result
+        match expressions.pop() {
+            Some(Expr {
+                kind: ExprKind::EndOfScript,
+                ..
+            }) => Ok(expressions),
+            _ => Err(KismetError::bytecode(
+                self.reader.script().len(),
+                "script doesn't end with EndOfScript",
+            )),
+        }
+    }
+
+    /// The script's top-level statements as `(offset, length, opcode,
+    /// expression)`, each parsed only when the iterator gets to it
+    ///
+    /// Unlike [`Self::parse_all`], the final `EndOfScript` is included, and
+    /// a scan can stop early without decoding the rest of the script. The
+    /// iterator ends after the first error.
+    pub fn instructions(&mut self) -> Instructions<'_, 'a> {
+        Instructions {
+            parser: self,
+            offset: 0,
+            failed: false,
+        }
     }
 
     /// Parse a single expression starting at the given offset
-    pub fn parse_expr(&mut self, offset: &mut usize) -> Expr {
+    pub fn parse_expr(&mut self, offset: &mut usize) -> Result<Expr, KismetError> {
+        ensure_stack(|| self.parse_nested_expr(offset))
+    }
+
+    fn parse_nested_expr(&mut self, offset: &mut usize) -> Result<Expr, KismetError> {
         let expr_offset = BytecodeOffset::new(*offset);
-        let opcode = EExprToken::from(self.reader.script()[*offset]);
-        *offset += 1;
+        let opcode = self.decode(self.reader.read_byte(offset)?);
+        self.opcodes.record(opcode);
 
-        let kind = self.parse_opcode(opcode, offset);
+        let kind = self
+            .parse_opcode(opcode, offset)
+            .map_err(|e| e.in_opcode(opcode))?;
 
-        Expr::new(expr_offset, kind)
+        Ok(Expr::new(expr_offset, kind).with_end(BytecodeOffset::new(*offset)))
     }
 
-    fn parse_opcode(&mut self, opcode: EExprToken, offset: &mut usize) -> ExprKind {
-        match opcode {
+    fn parse_opcode(
+        &mut self,
+        opcode: EExprToken,
+        offset: &mut usize,
+    ) -> Result<ExprKind, KismetError> {
+        Ok(match opcode {
             // Variables
             EExprToken::LocalVariable => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 ExprKind::LocalVariable(PropertyRef::new(address))
             }
             EExprToken::InstanceVariable => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 ExprKind::InstanceVariable(PropertyRef::new(address))
             }
             EExprToken::DefaultVariable => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 ExprKind::DefaultVariable(PropertyRef::new(address))
             }
             EExprToken::LocalOutVariable => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 ExprKind::LocalOutVariable(PropertyRef::new(address))
             }
             EExprToken::ClassSparseDataVariable => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 ExprKind::ClassSparseDataVariable(PropertyRef::new(address))
             }
 
             // Integer constants
-            EExprToken::IntConst => ExprKind::IntConst(self.reader.read_int(offset)),
-            EExprToken::Int64Const => ExprKind::Int64Const(self.reader.read_qword(offset) as i64),
-            EExprToken::UInt64Const => ExprKind::UInt64Const(self.reader.read_qword(offset)),
+            EExprToken::IntConst => ExprKind::IntConst(self.reader.read_int(offset)?),
+            EExprToken::Int64Const => ExprKind::Int64Const(self.reader.read_qword(offset)? as i64),
+            EExprToken::UInt64Const => ExprKind::UInt64Const(self.reader.read_qword(offset)?),
             EExprToken::IntZero => ExprKind::IntZero,
             EExprToken::IntOne => ExprKind::IntOne,
-            EExprToken::ByteConst => ExprKind::ByteConst(self.reader.read_byte(offset)),
-            EExprToken::IntConstByte => ExprKind::IntConstByte(self.reader.read_byte(offset)),
+            EExprToken::ByteConst => ExprKind::ByteConst(self.reader.read_byte(offset)?),
+            EExprToken::IntConstByte => ExprKind::IntConstByte(self.reader.read_byte(offset)?),
 
             // Floating point constants
-            EExprToken::FloatConst => ExprKind::FloatConst(self.reader.read_float(offset)),
+            EExprToken::FloatConst => ExprKind::FloatConst(self.reader.read_float(offset)?),
+            EExprToken::DoubleConst => ExprKind::DoubleConst(self.reader.read_double(offset)?),
 
             // String constants
-            EExprToken::StringConst => ExprKind::StringConst(self.reader.read_string8(offset)),
+            EExprToken::StringConst => {
+                let start = BytecodeOffset::new(*offset);
+                let (text, lossy) = self.reader.read_string8(offset)?;
+                if lossy {
+                    self.diagnostics.push(ParseDiagnostic {
+                        offset: start,
+                        message: "string constant isn't valid UTF-8, bad bytes are shown as U+FFFD"
+                            .to_string(),
+                    });
+                }
+                ExprKind::StringConst(text)
+            }
             EExprToken::UnicodeStringConst => {
-                ExprKind::UnicodeStringConst(self.reader.read_string16(offset))
+                ExprKind::UnicodeStringConst(self.reader.read_string16(offset)?)
             }
-            EExprToken::NameConst => ExprKind::NameConst(self.reader.read_name(offset)),
+            EExprToken::NameConst => ExprKind::NameConst(self.reader.read_name(offset)?),
 
             // Vector/rotation/transform constants
             EExprToken::VectorConst => ExprKind::VectorConst {
-                x: self.reader.read_float(offset),
-                y: self.reader.read_float(offset),
-                z: self.reader.read_float(offset),
+                x: self.read_real(offset)?,
+                y: self.read_real(offset)?,
+                z: self.read_real(offset)?,
             },
-            EExprToken::RotationConst => ExprKind::RotationConst {
-                pitch: self.reader.read_float(offset),
-                yaw: self.reader.read_float(offset),
-                roll: self.reader.read_float(offset),
+            EExprToken::Vector3fConst => ExprKind::Vector3fConst {
+                x: self.reader.read_float(offset)?,
+                y: self.reader.read_float(offset)?,
+                z: self.reader.read_float(offset)?,
             },
-            EExprToken::TransformConst => ExprKind::TransformConst {
-                rot_x: self.reader.read_float(offset),
-                rot_y: self.reader.read_float(offset),
-                rot_z: self.reader.read_float(offset),
-                rot_w: self.reader.read_float(offset),
-                trans_x: self.reader.read_float(offset),
-                trans_y: self.reader.read_float(offset),
-                trans_z: self.reader.read_float(offset),
-                scale_x: self.reader.read_float(offset),
-                scale_y: self.reader.read_float(offset),
-                scale_z: self.reader.read_float(offset),
+            EExprToken::RotationConst => ExprKind::RotationConst {
+                pitch: self.read_real(offset)?,
+                yaw: self.read_real(offset)?,
+                roll: self.read_real(offset)?,
             },
+            EExprToken::TransformConst => ExprKind::TransformConst(Box::new(TransformConst {
+                rot_x: self.read_real(offset)?,
+                rot_y: self.read_real(offset)?,
+                rot_z: self.read_real(offset)?,
+                rot_w: self.read_real(offset)?,
+                trans_x: self.read_real(offset)?,
+                trans_y: self.read_real(offset)?,
+                trans_z: self.read_real(offset)?,
+                scale_x: self.read_real(offset)?,
+                scale_y: self.read_real(offset)?,
+                scale_z: self.read_real(offset)?,
+            })),
 
             // Special constants
             EExprToken::True => ExprKind::True,
@@ -127,48 +307,47 @@ impl<'a> ScriptParser<'a> {
 
             // Object references
             EExprToken::ObjectConst => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 ExprKind::ObjectConst(ObjectRef::new(address))
             }
             EExprToken::SoftObjectConst => {
-                let expr = self.parse_expr(offset);
+                let expr = self.parse_expr(offset)?;
                 ExprKind::SoftObjectConst(Box::new(expr))
             }
             EExprToken::PropertyConst => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 ExprKind::PropertyConst(PropertyRef::new(address))
             }
             EExprToken::SkipOffsetConst => ExprKind::SkipOffsetConst(BytecodeOffset::new(
-                self.reader.read_skip_count(offset) as usize,
+                self.reader.read_skip_count(offset)? as usize,
             )),
             EExprToken::FieldPathConst => {
-                let expr = self.parse_expr(offset);
+                let expr = self.parse_expr(offset)?;
                 ExprKind::FieldPathConst(Box::new(expr))
             }
 
             // Text constants
             EExprToken::TextConst => {
-                let text_type = EBlueprintTextLiteralType::from(self.reader.script()[*offset]);
-                *offset += 1;
+                let text_type = EBlueprintTextLiteralType::from(self.reader.read_byte(offset)?);
 
                 let text_literal = match text_type {
                     EBlueprintTextLiteralType::Empty => TextLiteral::Empty,
                     EBlueprintTextLiteralType::LocalizedText => TextLiteral::LocalizedText {
-                        source: Box::new(self.parse_expr(offset)),
-                        key: Box::new(self.parse_expr(offset)),
-                        namespace: Box::new(self.parse_expr(offset)),
+                        source: Box::new(self.parse_expr(offset)?),
+                        key: Box::new(self.parse_expr(offset)?),
+                        namespace: Box::new(self.parse_expr(offset)?),
                     },
                     EBlueprintTextLiteralType::InvariantText => TextLiteral::InvariantText {
-                        source: Box::new(self.parse_expr(offset)),
+                        source: Box::new(self.parse_expr(offset)?),
                     },
                     EBlueprintTextLiteralType::LiteralString => TextLiteral::LiteralString {
-                        source: Box::new(self.parse_expr(offset)),
+                        source: Box::new(self.parse_expr(offset)?),
                     },
                     EBlueprintTextLiteralType::StringTableEntry => {
-                        let _table_object = self.reader.read_address(offset);
+                        let _table_object = self.reader.read_address(offset)?;
                         TextLiteral::StringTableEntry {
-                            table_id: Box::new(self.parse_expr(offset)),
-                            key: Box::new(self.parse_expr(offset)),
+                            table_id: Box::new(self.parse_expr(offset)?),
+                            key: Box::new(self.parse_expr(offset)?),
                         }
                     }
                 };
@@ -178,40 +357,40 @@ impl<'a> ScriptParser<'a> {
 
             // Function calls
             EExprToken::VirtualFunction => {
-                let name = self.reader.read_name(offset);
+                let name = self.reader.read_name(offset)?;
                 let func = FunctionRef::from_name(name);
-                let params = self.parse_function_params(offset);
+                let params = self.parse_function_params(offset)?;
                 ExprKind::VirtualFunction { func, params }
             }
             EExprToken::FinalFunction => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let func = FunctionRef::from_address(address);
-                let params = self.parse_function_params(offset);
+                let params = self.parse_function_params(offset)?;
                 ExprKind::FinalFunction { func, params }
             }
             EExprToken::LocalVirtualFunction => {
-                let name = self.reader.read_name(offset);
+                let name = self.reader.read_name(offset)?;
                 let func = FunctionRef::from_name(name);
-                let params = self.parse_function_params(offset);
+                let params = self.parse_function_params(offset)?;
                 ExprKind::LocalVirtualFunction { func, params }
             }
             EExprToken::LocalFinalFunction => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let func = FunctionRef::from_address(address);
-                let params = self.parse_function_params(offset);
+                let params = self.parse_function_params(offset)?;
                 ExprKind::LocalFinalFunction { func, params }
             }
             EExprToken::CallMath => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let func = FunctionRef::from_address(address);
-                let params = self.parse_function_params(offset);
+                let params = self.parse_function_params(offset)?;
                 ExprKind::CallMath { func, params }
             }
             EExprToken::CallMulticastDelegate => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let stack_node = FunctionRef::from_address(address);
-                let delegate_expr = Box::new(self.parse_expr(offset));
-                let params = self.parse_function_params(offset);
+                let delegate_expr = Box::new(self.parse_expr(offset)?);
+                let params = self.parse_function_params(offset)?;
                 ExprKind::CallMulticastDelegate {
                     stack_node,
                     delegate_expr,
@@ -222,11 +401,12 @@ impl<'a> ScriptParser<'a> {
             // Context/member access
             EExprToken::Context | EExprToken::ContextFailSilent => {
                 let fail_silent = opcode == EExprToken::ContextFailSilent;
-                let object = Box::new(self.parse_expr(offset));
-                let skip_offset = self.reader.read_skip_count(offset);
-                let address = self.reader.read_address(offset);
+                let object = Box::new(self.parse_expr(offset)?);
+                let skip_offset = self.reader.read_skip_count(offset)?;
+                let address = self.reader.read_address(offset)?;
                 let field = PropertyRef::new(address);
-                let context = Box::new(self.parse_expr(offset));
+                let context = Box::new(self.parse_expr(offset)?);
+                self.check_skip_offset(&context, skip_offset);
                 ExprKind::Context {
                     object,
                     field,
@@ -236,11 +416,12 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::ClassContext => {
-                let object = Box::new(self.parse_expr(offset));
-                let skip_offset = self.reader.read_skip_count(offset);
-                let address = self.reader.read_address(offset);
+                let object = Box::new(self.parse_expr(offset)?);
+                let skip_offset = self.reader.read_skip_count(offset)?;
+                let address = self.reader.read_address(offset)?;
                 let field = PropertyRef::new(address);
-                let context = Box::new(self.parse_expr(offset));
+                let context = Box::new(self.parse_expr(offset)?);
+                self.check_skip_offset(&context, skip_offset);
                 ExprKind::ClassContext {
                     object,
                     field,
@@ -249,59 +430,59 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::StructMemberContext => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let member = PropertyRef::new(address);
-                let struct_expr = Box::new(self.parse_expr(offset));
+                let struct_expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::StructMemberContext {
                     struct_expr,
                     member,
                 }
             }
             EExprToken::InterfaceContext => {
-                let expr = Box::new(self.parse_expr(offset));
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::InterfaceContext(expr)
             }
 
             // Casts
             EExprToken::DynamicCast => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let target_class = ClassRef::new(address);
-                let expr = Box::new(self.parse_expr(offset));
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::DynamicCast { target_class, expr }
             }
             EExprToken::MetaCast => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let target_class = ClassRef::new(address);
-                let expr = Box::new(self.parse_expr(offset));
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::MetaCast { target_class, expr }
             }
             EExprToken::PrimitiveCast => {
-                let conversion_type = self.reader.read_byte(offset);
-                let expr = Box::new(self.parse_expr(offset));
+                let conversion_type = self.reader.read_byte(offset)?;
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::PrimitiveCast {
                     conversion_type,
                     expr,
                 }
             }
             EExprToken::ObjToInterfaceCast => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let target_interface = ClassRef::new(address);
-                let expr = Box::new(self.parse_expr(offset));
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::ObjToInterfaceCast {
                     target_interface,
                     expr,
                 }
             }
             EExprToken::InterfaceToObjCast => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let target_class = ClassRef::new(address);
-                let expr = Box::new(self.parse_expr(offset));
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::InterfaceToObjCast { target_class, expr }
             }
             EExprToken::CrossInterfaceCast => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let target_interface = ClassRef::new(address);
-                let expr = Box::new(self.parse_expr(offset));
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::CrossInterfaceCast {
                     target_interface,
                     expr,
@@ -310,10 +491,10 @@ impl<'a> ScriptParser<'a> {
 
             // Collections
             EExprToken::ArrayConst => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let element_type = PropertyRef::new(address);
-                let num_elements = self.reader.read_int(offset);
-                let elements = self.parse_until(offset, EExprToken::EndArrayConst);
+                let num_elements = self.reader.read_int(offset)?;
+                let elements = self.parse_until(offset, EExprToken::EndArrayConst)?;
                 ExprKind::ArrayConst {
                     element_type,
                     num_elements,
@@ -321,10 +502,10 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::StructConst => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let struct_type = StructRef::new(address);
-                let serialized_size = self.reader.read_int(offset);
-                let elements = self.parse_until(offset, EExprToken::EndStructConst);
+                let serialized_size = self.reader.read_int(offset)?;
+                let elements = self.parse_until(offset, EExprToken::EndStructConst)?;
                 ExprKind::StructConst {
                     struct_type,
                     serialized_size,
@@ -332,10 +513,10 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::SetConst => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let element_type = PropertyRef::new(address);
-                let num_elements = self.reader.read_int(offset);
-                let elements = self.parse_until(offset, EExprToken::EndSetConst);
+                let num_elements = self.reader.read_int(offset)?;
+                let elements = self.parse_until(offset, EExprToken::EndSetConst)?;
                 ExprKind::SetConst {
                     element_type,
                     num_elements,
@@ -343,12 +524,12 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::MapConst => {
-                let key_address = self.reader.read_address(offset);
+                let key_address = self.reader.read_address(offset)?;
                 let key_type = PropertyRef::new(key_address);
-                let value_address = self.reader.read_address(offset);
+                let value_address = self.reader.read_address(offset)?;
                 let value_type = PropertyRef::new(value_address);
-                let num_elements = self.reader.read_int(offset);
-                let elements = self.parse_until(offset, EExprToken::EndMapConst);
+                let num_elements = self.reader.read_int(offset)?;
+                let elements = self.parse_until(offset, EExprToken::EndMapConst)?;
                 ExprKind::MapConst {
                     key_type,
                     value_type,
@@ -359,17 +540,17 @@ impl<'a> ScriptParser<'a> {
 
             // Array/set/map operations
             EExprToken::SetArray => {
-                let array_expr = Box::new(self.parse_expr(offset));
-                let elements = self.parse_until(offset, EExprToken::EndArray);
+                let array_expr = Box::new(self.parse_expr(offset)?);
+                let elements = self.parse_until(offset, EExprToken::EndArray)?;
                 ExprKind::SetArray {
                     array_expr,
                     elements,
                 }
             }
             EExprToken::SetSet => {
-                let set_expr = Box::new(self.parse_expr(offset));
-                let num = self.reader.read_int(offset);
-                let elements = self.parse_until(offset, EExprToken::EndSet);
+                let set_expr = Box::new(self.parse_expr(offset)?);
+                let num = self.reader.read_int(offset)?;
+                let elements = self.parse_until(offset, EExprToken::EndSet)?;
                 ExprKind::SetSet {
                     set_expr,
                     num,
@@ -377,9 +558,9 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::SetMap => {
-                let map_expr = Box::new(self.parse_expr(offset));
-                let num = self.reader.read_int(offset);
-                let elements = self.parse_until(offset, EExprToken::EndMap);
+                let map_expr = Box::new(self.parse_expr(offset)?);
+                let num = self.reader.read_int(offset)?;
+                let elements = self.parse_until(offset, EExprToken::EndMap)?;
                 ExprKind::SetMap {
                     map_expr,
                     num,
@@ -387,8 +568,8 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::ArrayGetByRef => {
-                let array_expr = Box::new(self.parse_expr(offset));
-                let index_expr = Box::new(self.parse_expr(offset));
+                let array_expr = Box::new(self.parse_expr(offset)?);
+                let index_expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::ArrayGetByRef {
                     array_expr,
                     index_expr,
@@ -397,10 +578,10 @@ impl<'a> ScriptParser<'a> {
 
             // Assignments
             EExprToken::Let => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let property = PropertyRef::new(address);
-                let variable = Box::new(self.parse_expr(offset));
-                let value = Box::new(self.parse_expr(offset));
+                let variable = Box::new(self.parse_expr(offset)?);
+                let value = Box::new(self.parse_expr(offset)?);
                 ExprKind::Let {
                     property,
                     variable,
@@ -408,45 +589,45 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::LetObj => {
-                let variable = Box::new(self.parse_expr(offset));
-                let value = Box::new(self.parse_expr(offset));
+                let variable = Box::new(self.parse_expr(offset)?);
+                let value = Box::new(self.parse_expr(offset)?);
                 ExprKind::LetObj { variable, value }
             }
             EExprToken::LetWeakObjPtr => {
-                let variable = Box::new(self.parse_expr(offset));
-                let value = Box::new(self.parse_expr(offset));
+                let variable = Box::new(self.parse_expr(offset)?);
+                let value = Box::new(self.parse_expr(offset)?);
                 ExprKind::LetWeakObjPtr { variable, value }
             }
             EExprToken::LetBool => {
-                let variable = Box::new(self.parse_expr(offset));
-                let value = Box::new(self.parse_expr(offset));
+                let variable = Box::new(self.parse_expr(offset)?);
+                let value = Box::new(self.parse_expr(offset)?);
                 ExprKind::LetBool { variable, value }
             }
             EExprToken::LetDelegate => {
-                let variable = Box::new(self.parse_expr(offset));
-                let value = Box::new(self.parse_expr(offset));
+                let variable = Box::new(self.parse_expr(offset)?);
+                let value = Box::new(self.parse_expr(offset)?);
                 ExprKind::LetDelegate { variable, value }
             }
             EExprToken::LetMulticastDelegate => {
-                let variable = Box::new(self.parse_expr(offset));
-                let value = Box::new(self.parse_expr(offset));
+                let variable = Box::new(self.parse_expr(offset)?);
+                let value = Box::new(self.parse_expr(offset)?);
                 ExprKind::LetMulticastDelegate { variable, value }
             }
             EExprToken::LetValueOnPersistentFrame => {
-                let address = self.reader.read_address(offset);
+                let address = self.reader.read_address(offset)?;
                 let property = PropertyRef::new(address);
-                let value = Box::new(self.parse_expr(offset));
+                let value = Box::new(self.parse_expr(offset)?);
                 ExprKind::LetValueOnPersistentFrame { property, value }
             }
 
             // Delegates
             EExprToken::InstanceDelegate => {
-                ExprKind::InstanceDelegate(self.reader.read_name(offset))
+                ExprKind::InstanceDelegate(self.reader.read_name(offset)?)
             }
             EExprToken::BindDelegate => {
-                let func_name = self.reader.read_name(offset);
-                let delegate_expr = Box::new(self.parse_expr(offset));
-                let object_expr = Box::new(self.parse_expr(offset));
+                let func_name = self.reader.read_name(offset)?;
+                let delegate_expr = Box::new(self.parse_expr(offset)?);
+                let object_expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::BindDelegate {
                     func_name,
                     delegate_expr,
@@ -454,56 +635,57 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::AddMulticastDelegate => {
-                let delegate_expr = Box::new(self.parse_expr(offset));
-                let to_add_expr = Box::new(self.parse_expr(offset));
+                let delegate_expr = Box::new(self.parse_expr(offset)?);
+                let to_add_expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::AddMulticastDelegate {
                     delegate_expr,
                     to_add_expr,
                 }
             }
             EExprToken::RemoveMulticastDelegate => {
-                let delegate_expr = Box::new(self.parse_expr(offset));
-                let to_remove_expr = Box::new(self.parse_expr(offset));
+                let delegate_expr = Box::new(self.parse_expr(offset)?);
+                let to_remove_expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::RemoveMulticastDelegate {
                     delegate_expr,
                     to_remove_expr,
                 }
             }
             EExprToken::ClearMulticastDelegate => {
-                let expr = Box::new(self.parse_expr(offset));
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::ClearMulticastDelegate(expr)
             }
 
             // Control flow
             EExprToken::Return => {
-                let expr = Box::new(self.parse_expr(offset));
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::Return(expr)
             }
             EExprToken::Jump => {
-                let target = BytecodeOffset::new(self.reader.read_skip_count(offset) as usize);
+                let target = BytecodeOffset::new(self.reader.read_skip_count(offset)? as usize);
                 ExprKind::Jump { target }
             }
             EExprToken::JumpIfNot => {
-                let target = BytecodeOffset::new(self.reader.read_skip_count(offset) as usize);
-                let condition = Box::new(self.parse_expr(offset));
+                let target = BytecodeOffset::new(self.reader.read_skip_count(offset)? as usize);
+                let condition = Box::new(self.parse_expr(offset)?);
                 ExprKind::JumpIfNot { condition, target }
             }
             EExprToken::ComputedJump => {
-                let offset_expr = Box::new(self.parse_expr(offset));
+                let offset_expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::ComputedJump { offset_expr }
             }
             EExprToken::SwitchValue => {
-                let num_cases = self.reader.read_word(offset);
-                let end_offset = BytecodeOffset::new(self.reader.read_skip_count(offset) as usize);
-                let index = Box::new(self.parse_expr(offset));
+                let num_cases = self.reader.read_word(offset)?;
+                let end_offset = BytecodeOffset::new(self.reader.read_skip_count(offset)? as usize);
+                let index = Box::new(self.parse_expr(offset)?);
 
                 let mut cases = Vec::new();
                 for _ in 0..num_cases {
                     let case_offset = BytecodeOffset::new(*offset);
-                    let case_value = self.parse_expr(offset);
+                    let case_value = self.parse_expr(offset)?;
                     let next_offset =
-                        BytecodeOffset::new(self.reader.read_skip_count(offset) as usize);
-                    let result = self.parse_expr(offset);
+                        BytecodeOffset::new(self.reader.read_skip_count(offset)? as usize);
+                    let result = self.parse_expr(offset)?;
+                    self.check_switch_offset("case skip", next_offset, *offset);
 
                     cases.push(SwitchCase {
                         case_offset,
@@ -513,7 +695,8 @@ impl<'a> ScriptParser<'a> {
                     });
                 }
 
-                let default = Box::new(self.parse_expr(offset));
+                let default = Box::new(self.parse_expr(offset)?);
+                self.check_switch_offset("end offset", end_offset, *offset);
 
                 ExprKind::SwitchValue {
                     index,
@@ -525,20 +708,21 @@ impl<'a> ScriptParser<'a> {
 
             // Execution flow
             EExprToken::PushExecutionFlow => {
-                let push_offset = BytecodeOffset::new(self.reader.read_skip_count(offset) as usize);
+                let push_offset =
+                    BytecodeOffset::new(self.reader.read_skip_count(offset)? as usize);
                 ExprKind::PushExecutionFlow { push_offset }
             }
             EExprToken::PopExecutionFlow => ExprKind::PopExecutionFlow,
             EExprToken::PopExecutionFlowIfNot => {
-                let condition = Box::new(self.parse_expr(offset));
+                let condition = Box::new(self.parse_expr(offset)?);
                 ExprKind::PopExecutionFlowIfNot { condition }
             }
 
             // Debug/instrumentation
             EExprToken::Assert => {
-                let line = self.reader.read_word(offset);
-                let in_debug = self.reader.read_byte(offset) != 0;
-                let condition = Box::new(self.parse_expr(offset));
+                let line = self.reader.read_word(offset)?;
+                let in_debug = self.reader.read_byte(offset)? != 0;
+                let condition = Box::new(self.parse_expr(offset)?);
                 ExprKind::Assert {
                     line,
                     in_debug,
@@ -546,25 +730,57 @@ impl<'a> ScriptParser<'a> {
                 }
             }
             EExprToken::Skip => {
-                let skip_count = self.reader.read_skip_count(offset);
-                let expr = Box::new(self.parse_expr(offset));
+                let skip_count = self.reader.read_skip_count(offset)?;
+                let expr = Box::new(self.parse_expr(offset)?);
                 ExprKind::Skip { skip_count, expr }
             }
+            EExprToken::DefaultParmValue => {
+                let skip_count = self.reader.read_skip_count(offset)?;
+                let start = *offset;
+                let value = Box::new(self.parse_expr(offset)?);
+                let end_offset = *offset;
+                if self.decode(self.reader.peek_byte(end_offset)?) != EExprToken::EndParmValue {
+                    return Err(KismetError::bytecode(
+                        end_offset,
+                        "default parameter value doesn't end with EndParmValue",
+                    ));
+                }
+                self.opcodes.record(EExprToken::EndParmValue);
+                *offset += 1;
+                // The skip lands after the end marker
+                if skip_count as usize != *offset - start {
+                    self.diagnostics.push(ParseDiagnostic {
+                        offset: value.offset,
+                        message: format!(
+                            "default parameter value skip {} does not match decoded size {}",
+                            skip_count,
+                            *offset - start
+                        ),
+                    });
+                }
+                ExprKind::DefaultParmValue { skip_count, value }
+            }
             EExprToken::Breakpoint => ExprKind::Breakpoint,
             EExprToken::Tracepoint => ExprKind::Tracepoint,
             EExprToken::WireTracepoint => ExprKind::WireTracepoint,
             EExprToken::InstrumentationEvent => {
-                let event_type = self.reader.script()[*offset];
+                let event_type = self.reader.read_byte(offset)?;
                 const INLINE_EVENT: u8 = 0;
                 if event_type == INLINE_EVENT {
                     *offset += 12; // Skip FScriptName
                 }
-                *offset += 1; // Skip event type
                 ExprKind::InstrumentationEvent { event_type }
             }
 
             // Special/terminator tokens
-            EExprToken::BitFieldConst => ExprKind::BitFieldConst,
+            EExprToken::BitFieldConst => {
+                let address = self.reader.read_address(offset)?;
+                let value = self.reader.read_byte(offset)?;
+                ExprKind::BitFieldConst {
+                    property: PropertyRef::new(address),
+                    value,
+                }
+            }
             EExprToken::DeprecatedOp4A => ExprKind::DeprecatedOp4A,
             EExprToken::EndOfScript => ExprKind::EndOfScript,
             EExprToken::EndParmValue => ExprKind::EndParmValue,
@@ -579,44 +795,336 @@ impl<'a> ScriptParser<'a> {
             | EExprToken::EndSet
             | EExprToken::EndMap => {
                 // These should have been consumed by their container
-                panic!(
-                    "Unexpected end marker: {:?} at offset {}",
-                    opcode,
-                    *offset - 1
-                );
+                return Err(KismetError::bytecode(*offset - 1, "unexpected end marker"));
             }
 
-            EExprToken::Unknown(val) => {
-                panic!("Unknown opcode 0x{:02X} at offset {}", val, *offset - 1);
+            EExprToken::Unknown(_) => {
+                return Err(KismetError::bytecode(*offset - 1, "unknown opcode"));
             }
-        }
+        })
     }
 
     /// Parse function parameters until EndFunctionParms
-    fn parse_function_params(&mut self, offset: &mut usize) -> Vec<Expr> {
+    fn parse_function_params(&mut self, offset: &mut usize) -> Result<Vec<Expr>, KismetError> {
         let mut params = Vec::new();
         loop {
-            let opcode = EExprToken::from(self.reader.script()[*offset]);
+            let opcode = self.decode(self.reader.peek_byte(*offset)?);
             if opcode == EExprToken::EndFunctionParms {
+                self.opcodes.record(opcode);
                 *offset += 1;
                 break;
             }
-            params.push(self.parse_expr(offset));
+            params.push(self.parse_expr(offset)?);
         }
-        params
+        Ok(params)
     }
 
     /// Parse expressions until a specific end token
-    fn parse_until(&mut self, offset: &mut usize, end_token: EExprToken) -> Vec<Expr> {
+    fn parse_until(
+        &mut self,
+        offset: &mut usize,
+        end_token: EExprToken,
+    ) -> Result<Vec<Expr>, KismetError> {
         let mut elements = Vec::new();
         loop {
-            let opcode = EExprToken::from(self.reader.script()[*offset]);
+            let opcode = self.decode(self.reader.peek_byte(*offset)?);
             if opcode == end_token {
+                self.opcodes.record(opcode);
                 *offset += 1;
                 break;
             }
-            elements.push(self.parse_expr(offset));
+            elements.push(self.parse_expr(offset)?);
         }
-        elements
+        Ok(elements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::cfg::ControlFlowGraph;
+    use crate::bytecode::testing::{BytecodeBuilder, parse};
+
+    #[test]
+    fn test_instructions() {
+        // this->Foo(); return; with a truncated call after it
+        let mut builder = BytecodeBuilder::default();
+        builder
+            .op(EExprToken::FinalFunction)
+            .address(0x1000)
+            .op(EExprToken::EndFunctionParms);
+        builder.op(EExprToken::Return).op(EExprToken::Nothing);
+        let script = builder.build();
+        let names = std::collections::BTreeMap::new();
+        let mut parser = ScriptParser::new(ScriptReader::new(&script, &names));
+        let instructions: Vec<_> = parser
+            .instructions()
+            .map(|instruction| {
+                let (offset, len, opcode, _) = instruction.unwrap();
+                (offset.as_usize(), len, opcode)
+            })
+            .collect();
+        assert_eq!(
+            instructions,
+            [
+                (0, 10, EExprToken::FinalFunction),
+                (10, 2, EExprToken::Return),
+                (12, 1, EExprToken::EndOfScript),
+            ]
+        );
+
+        let truncated = &script[..5];
+        let mut parser = ScriptParser::new(ScriptReader::new(truncated, &names));
+        let mut instructions = parser.instructions();
+        assert!(instructions.next().unwrap().is_err());
+        assert!(instructions.next().is_none());
+    }
+
+    #[test]
+    fn test_deeply_nested_expression() {
+        const DEPTH: usize = 10_000;
+        const NOT_PRE_BOOL: u64 = 0x1000;
+
+        // Not(Not(Not(...(true)...)))
+        let mut builder = BytecodeBuilder::default();
+        for _ in 0..DEPTH {
+            builder.op(EExprToken::CallMath).address(NOT_PRE_BOOL);
+        }
+        builder.op(EExprToken::True);
+        for _ in 0..DEPTH {
+            builder.op(EExprToken::EndFunctionParms);
+        }
+        builder.op(EExprToken::Return).op(EExprToken::Nothing);
+        let expressions = parse(&builder.build());
+        assert_eq!(expressions.len(), 2);
+
+        let mut count = 0;
+        expressions[0].walk(&mut |_| count += 1);
+        assert_eq!(count, DEPTH + 1);
+
+        let cfg = ControlFlowGraph::from_expressions(&expressions);
+        assert_eq!(cfg.blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_default_parameter_value() {
+        // Count = 5 if the caller leaves it out; return;
+        let build = |skip_adjust: usize, end: EExprToken| {
+            let mut builder = BytecodeBuilder::default();
+            builder.op(EExprToken::DefaultParmValue);
+            let skip = builder.placeholder();
+            let start = builder.here();
+            builder
+                .op(EExprToken::Let)
+                .address(0x10)
+                .op(EExprToken::LocalVariable)
+                .address(0x10)
+                .op(EExprToken::IntConst)
+                .u32(5)
+                .op(end);
+            let size = builder.here() - start;
+            builder.patch(skip, size + skip_adjust);
+            builder.op(EExprToken::Return).op(EExprToken::Nothing);
+            builder.build()
+        };
+        let names = Default::default();
+
+        let script = build(0, EExprToken::EndParmValue);
+        let mut parser = ScriptParser::new(ScriptReader::new(&script, &names));
+        let expressions = parser.parse_all().unwrap();
+        assert!(parser.diagnostics().is_empty());
+        assert_eq!(expressions.len(), 2);
+        let ExprKind::DefaultParmValue { skip_count, value } = &expressions[0].kind else {
+            panic!("expected a default parameter value");
+        };
+        assert_eq!(*skip_count, 24);
+        assert!(matches!(value.kind, ExprKind::Let { .. }));
+
+        let script = build(1, EExprToken::EndParmValue);
+        let mut parser = ScriptParser::new(ScriptReader::new(&script, &names));
+        parser.parse_all().unwrap();
+        assert_eq!(parser.diagnostics().len(), 1);
+
+        let script = build(0, EExprToken::Nothing);
+        let mut parser = ScriptParser::new(ScriptReader::new(&script, &names));
+        assert!(parser.parse_all().is_err());
+    }
+
+    #[test]
+    fn test_switch_offsets_are_checked() {
+        // switch (0) { case 0: 1; default: 0 }, with the case skip and end
+        // offset moved on by the given number of bytes
+        let build = |case_off: usize, end_off: usize| {
+            let mut builder = BytecodeBuilder::default();
+            builder.op(EExprToken::SwitchValue).u16(1);
+            let end = builder.placeholder();
+            builder.op(EExprToken::IntZero).op(EExprToken::IntZero);
+            let next_case = builder.placeholder();
+            builder.op(EExprToken::IntOne);
+            builder.patch(next_case, builder.here() + case_off);
+            builder.op(EExprToken::IntZero);
+            builder.patch(end, builder.here() + end_off);
+            builder.op(EExprToken::Return).op(EExprToken::Nothing);
+            builder.build()
+        };
+        let names = Default::default();
+        let diagnostics = |script: &[u8]| {
+            let mut parser = ScriptParser::new(ScriptReader::new(script, &names));
+            parser.parse_all().unwrap();
+            parser
+                .diagnostics()
+                .iter()
+                .map(|d| d.message.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(diagnostics(&build(0, 0)).is_empty());
+        assert_eq!(
+            diagnostics(&build(1, 0)),
+            ["switch case skip 0xF does not match decoded end 0xE"]
+        );
+        assert_eq!(
+            diagnostics(&build(0, 2)),
+            ["switch end offset 0x11 does not match decoded end 0xF"]
+        );
+    }
+
+    #[test]
+    fn test_bad_bytecode_is_an_error() {
+        let names = Default::default();
+        let parse_err = |script: &[u8]| {
+            ScriptParser::new(ScriptReader::new(script, &names))
+                .parse_all()
+                .unwrap_err()
+        };
+
+        // Return(IntConst(<two of four bytes>))
+        let truncated = parse_err(&[0x04, 0x1D, 0x01, 0x00]);
+        assert!(matches!(
+            truncated,
+            KismetError::BytecodeParse {
+                offset: BytecodeOffset(2),
+                opcode: Some(EExprToken::IntConst),
+                ..
+            }
+        ));
+
+        let unknown = parse_err(&[0x04, 0xFF]);
+        assert!(matches!(
+            unknown,
+            KismetError::BytecodeParse {
+                offset: BytecodeOffset(1),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_unicode_string_surrogates() {
+        // Return("\u{1F600}" then an unpaired high surrogate)
+        let mut builder = BytecodeBuilder::default();
+        builder
+            .op(EExprToken::Return)
+            .op(EExprToken::UnicodeStringConst);
+        builder.u16(0xD83D).u16(0xDE00).u16(0xD83D).u16(0);
+        let expressions = parse(&builder.build());
+        let ExprKind::Return(value) = &expressions[0].kind else {
+            panic!("expected a return, got {:?}", expressions[0].kind);
+        };
+        assert!(matches!(
+            &value.kind,
+            ExprKind::UnicodeStringConst(text) if text == "\u{1F600}\u{FFFD}"
+        ));
+    }
+
+    #[test]
+    fn test_string_constant_utf8() {
+        let names = Default::default();
+        let parse_string = |bytes: &[u8]| {
+            let mut script = vec![0x04, 0x1F];
+            script.extend_from_slice(bytes);
+            script.extend_from_slice(&[0x00, 0x53]);
+            let mut parser = ScriptParser::new(ScriptReader::new(&script, &names));
+            let expressions = parser.parse_all().unwrap();
+            let ExprKind::Return(value) = &expressions[0].kind else {
+                panic!("expected a return, got {:?}", expressions[0].kind);
+            };
+            let ExprKind::StringConst(text) = &value.kind else {
+                panic!("expected a string, got {:?}", value.kind);
+            };
+            (text.clone(), parser.diagnostics().len())
+        };
+        assert_eq!(parse_string("Zoë".as_bytes()), ("Zoë".to_string(), 0));
+        assert_eq!(parse_string(b"Zo\xEB"), ("Zo\u{FFFD}".to_string(), 1));
+    }
+
+    #[test]
+    fn test_expression_spans() {
+        // Return(IntConst(5)); Return(Nothing)
+        let mut builder = BytecodeBuilder::default();
+        builder
+            .op(EExprToken::Return)
+            .op(EExprToken::IntConst)
+            .u32(5);
+        builder.op(EExprToken::Return).op(EExprToken::Nothing);
+        let expressions = parse(&builder.build());
+        let ExprKind::Return(value) = &expressions[0].kind else {
+            panic!("expected a return, got {:?}", expressions[0].kind);
+        };
+        let span = |expr: &Expr| (expr.span().start.as_usize(), expr.span().end.as_usize());
+        assert_eq!(span(&expressions[0]), (0, 6));
+        assert_eq!(span(value), (1, 6));
+        assert_eq!(span(&expressions[1]), (6, 8));
+
+        let referenced = [0, 2, 6].map(BytecodeOffset::new).into();
+        let inside = crate::bytecode::expr::targets_inside_statements(&expressions, &referenced);
+        assert_eq!(inside.len(), 1);
+        assert_eq!(inside[&BytecodeOffset::new(0)], [BytecodeOffset::new(2)]);
+    }
+
+    #[test]
+    fn test_large_world_constants() {
+        // Return(VectorConst(1, 2, 3)) with the components as floats or doubles
+        let script = |component: &dyn Fn(f64) -> Vec<u8>| {
+            let mut script = vec![
+                EExprToken::Return.opcode_value(),
+                EExprToken::VectorConst.opcode_value(),
+            ];
+            for value in [1.0, 2.0, 3.0] {
+                script.extend(component(value));
+            }
+            script.push(EExprToken::EndOfScript.opcode_value());
+            script
+        };
+        let names = Default::default();
+        let parse_vector = |script: &[u8], version: EngineVersion| {
+            let mut parser =
+                ScriptParser::new(ScriptReader::new(script, &names)).with_engine_version(version);
+            let expressions = parser.parse_all().unwrap();
+            let ExprKind::Return(value) = &expressions[0].kind else {
+                panic!("expected a return, got {:?}", expressions[0].kind);
+            };
+            (value.kind.clone(), value.span().len())
+        };
+
+        let floats = script(&|v| (v as f32).to_le_bytes().to_vec());
+        let (vector, size) = parse_vector(&floats, EngineVersion::Ue4);
+        assert!(matches!(
+            vector,
+            ExprKind::VectorConst {
+                x: Real::Single(_),
+                ..
+            }
+        ));
+        assert_eq!(size, 1 + 3 * 4);
+
+        let doubles = script(&|v| v.to_le_bytes().to_vec());
+        let (vector, size) = parse_vector(&doubles, EngineVersion::Ue5);
+        let ExprKind::VectorConst { x, y, z } = vector else {
+            panic!("expected a vector, got {:?}", vector);
+        };
+        assert!(matches!(x, Real::Double(_)));
+        assert!(x == 1.0 && y == 2.0 && z == 3.0);
+        assert_eq!(size, 1 + 3 * 8);
     }
 }
@@ -0,0 +1,241 @@
+/// Small pattern-matching helpers over the expression tree
+///
+/// Shared by the analyses that hunt for particular calls or shapes of code
+/// across many functions.
+use std::collections::HashSet;
+
+use super::expr::{Expr, ExprKind};
+use super::refs::{FunctionRef, PropertyRef};
+use super::types::BytecodeOffset;
+
+/// Match `text` against a pattern where `*` stands for any run of characters
+///
+/// Case-insensitive, since Blueprint names are.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let text = text.to_ascii_lowercase();
+    let mut parts = pattern.split('*');
+    // `split` always yields at least one part
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Match a function path against a pattern, either as a whole or by the
+/// function's own name, so `IsValid` matches `/Script/Engine.KismetSystemLibrary:IsValid`
+pub fn function_matches(pattern: &str, path: &str) -> bool {
+    let name = path.rsplit([':', '.']).next().unwrap_or(path);
+    glob_match(pattern, name) || glob_match(pattern, path)
+}
+
+/// The function a call expression calls
+pub fn called_function(expr: &Expr) -> Option<&FunctionRef> {
+    call_parts(expr).map(|(func, _)| func)
+}
+
+/// The function a call expression calls and the arguments it passes
+pub fn call_parts(expr: &Expr) -> Option<(&FunctionRef, &[Expr])> {
+    match &expr.kind {
+        ExprKind::VirtualFunction { func, params }
+        | ExprKind::FinalFunction { func, params }
+        | ExprKind::LocalVirtualFunction { func, params }
+        | ExprKind::LocalFinalFunction { func, params }
+        | ExprKind::CallMath { func, params } => Some((func, params)),
+        _ => None,
+    }
+}
+
+/// The variable an assignment writes to
+pub fn assigned_variable(expr: &Expr) -> Option<&Expr> {
+    match &expr.kind {
+        ExprKind::Let { variable, .. }
+        | ExprKind::LetObj { variable, .. }
+        | ExprKind::LetWeakObjPtr { variable, .. }
+        | ExprKind::LetBool { variable, .. }
+        | ExprKind::LetDelegate { variable, .. }
+        | ExprKind::LetMulticastDelegate { variable, .. } => Some(variable),
+        _ => None,
+    }
+}
+
+/// The property an assignment writes, including writes to an ubergraph's
+/// persistent frame
+pub fn written_property(expr: &Expr) -> Option<PropertyRef> {
+    match &expr.kind {
+        ExprKind::LetValueOnPersistentFrame { property, .. } => Some(*property),
+        _ => assigned_variable(expr).and_then(variable_property),
+    }
+}
+
+/// The property a variable expression refers to, looking through `obj->Prop`
+/// and `Struct.Member` to the property at the end
+pub fn variable_property(expr: &Expr) -> Option<PropertyRef> {
+    match &expr.kind {
+        ExprKind::LocalVariable(property)
+        | ExprKind::InstanceVariable(property)
+        | ExprKind::DefaultVariable(property)
+        | ExprKind::LocalOutVariable(property)
+        | ExprKind::ClassSparseDataVariable(property) => Some(*property),
+        ExprKind::StructMemberContext { member, .. } => Some(*member),
+        ExprKind::Context { context, .. } | ExprKind::ClassContext { context, .. } => {
+            variable_property(context)
+        }
+        _ => None,
+    }
+}
+
+/// How an expression uses a property
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Every use of a property anywhere in the expressions
+///
+/// A property is written where it's part of an assignment target, so
+/// assigning `Hit.Location` writes `Hit` as well as `Location`. The objects
+/// a target is reached through, as in `Target->Health = 0`, are read.
+pub fn property_accesses(expressions: &[Expr]) -> Vec<(BytecodeOffset, PropertyRef, Access)> {
+    let mut targets = HashSet::new();
+    for expr in expressions {
+        expr.walk(&mut |e| {
+            if let Some(variable) = assigned_variable(e) {
+                collect_targets(variable, &mut targets);
+            }
+        });
+    }
+
+    let mut found = Vec::new();
+    for expr in expressions {
+        expr.walk(&mut |e| {
+            if let ExprKind::LetValueOnPersistentFrame { property, .. } = &e.kind {
+                found.push((e.offset, *property, Access::Write));
+            }
+            // The property of `obj->Prop` is found again in its member expression
+            if matches!(
+                e.kind,
+                ExprKind::Context { .. } | ExprKind::ClassContext { .. }
+            ) {
+                return;
+            }
+            if let Some(property) = variable_property(e) {
+                let access = match targets.contains(&e.offset) {
+                    true => Access::Write,
+                    false => Access::Read,
+                };
+                found.push((e.offset, property, access));
+            }
+        });
+    }
+    found
+}
+
+/// Offsets of the expressions an assignment target is made of, except the
+/// objects it's reached through, which are read
+fn collect_targets(variable: &Expr, targets: &mut HashSet<BytecodeOffset>) {
+    targets.insert(variable.offset);
+    match &variable.kind {
+        ExprKind::Context { context, .. } | ExprKind::ClassContext { context, .. } => {
+            collect_targets(context, targets)
+        }
+        ExprKind::StructMemberContext { struct_expr, .. } => collect_targets(struct_expr, targets),
+        _ => {}
+    }
+}
+
+/// Calls anywhere in the expressions whose resolved callee matches `pattern`
+pub fn find_calls(
+    expressions: &[Expr],
+    pattern: &str,
+    resolve: impl Fn(&FunctionRef) -> Option<String>,
+) -> Vec<(BytecodeOffset, String)> {
+    let mut found = Vec::new();
+    for expr in expressions {
+        expr.walk(&mut |e| {
+            if let Some(path) = called_function(e).and_then(&resolve)
+                && function_matches(pattern, &path)
+            {
+                found.push((e.offset, path));
+            }
+        });
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("SpawnActor*", "SpawnActorFromClass"));
+        assert!(glob_match("*damage*", "ApplyPointDamage"));
+        assert!(glob_match("IsValid", "isvalid"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("IsValid", "IsValidClass"));
+        assert!(!glob_match("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn test_property_accesses() {
+        use super::super::types::Address;
+
+        let at = |offset, kind| Expr::new(BytecodeOffset::new(offset), kind);
+        let property = |address| PropertyRef::new(Address::new(address));
+        let variable = |offset, address| at(offset, ExprKind::InstanceVariable(property(address)));
+        // Hit.Location = Speed
+        let target = at(
+            1,
+            ExprKind::StructMemberContext {
+                struct_expr: Box::new(variable(2, 1)),
+                member: property(2),
+            },
+        );
+        let expressions = vec![at(
+            0,
+            ExprKind::Let {
+                property: property(2),
+                variable: Box::new(target),
+                value: Box::new(variable(10, 3)),
+            },
+        )];
+
+        let accesses: Vec<(usize, u64, Access)> = property_accesses(&expressions)
+            .into_iter()
+            .map(|(offset, property, access)| {
+                (offset.as_usize(), property.address.as_u64(), access)
+            })
+            .collect();
+        assert_eq!(
+            accesses,
+            vec![
+                (1, 2, Access::Write),
+                (2, 1, Access::Write),
+                (10, 3, Access::Read)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_matches_name_or_path() {
+        let path = "/Script/Engine.KismetSystemLibrary:IsValid";
+        assert!(function_matches("IsValid", path));
+        assert!(function_matches("*KismetSystemLibrary:*", path));
+        assert!(!function_matches("Valid", path));
+    }
+}
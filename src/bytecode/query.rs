@@ -0,0 +1,418 @@
+/// Pattern queries over a function's expressions
+///
+/// A query is a small boolean expression over atoms that each look for one
+/// kind of code, for example
+///
+/// ```text
+/// call("SpawnActor*") inside loop
+/// write(prop:"Health") && call("*Damage*")
+/// !call("IsValid") || name("Weapon.*")
+/// ```
+///
+/// Atoms are `call("fn")`, `write("prop")`, `read("prop")` and `name("name")`,
+/// with `*` wildcards; `call` takes an optional `fn:` label and `write`/`read`
+/// an optional `prop:` label. `inside loop` keeps only matches in a loop body.
+/// Atoms combine with `&&`, `||`, `!` and parentheses. A function matches
+/// `a && b` if it matches both anywhere in its body, not necessarily in the
+/// same statement.
+use super::expr::{Expr, ExprKind};
+use super::pattern::{
+    Access, called_function, function_matches, glob_match, property_accesses, written_property,
+};
+use super::refs::{FunctionRef, PropertyRef};
+use super::types::BytecodeOffset;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// A call to a function matching the pattern
+    Call(String),
+    /// An assignment to a property matching the pattern
+    Write(String),
+    /// A use of a property matching the pattern, other than as an assignment target
+    Read(String),
+    /// A name constant matching the pattern
+    Name(String),
+    InsideLoop(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+/// What a query needs to know about the function it runs over
+pub trait QueryEnv {
+    /// Path of a called function
+    fn callee(&self, func: &FunctionRef) -> Option<String>;
+    /// Name of a property
+    fn property_name(&self, property: PropertyRef) -> Option<String>;
+    /// Whether code at `offset` is in a loop body
+    fn in_loop(&self, offset: BytecodeOffset) -> bool;
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = QueryParser { tokens, pos: 0 };
+        let query = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(format!("unexpected {}", token.describe())),
+        }
+    }
+
+    /// Whether the query needs [`QueryEnv::in_loop`], which needs a CFG
+    pub fn uses_loops(&self) -> bool {
+        match self {
+            Query::InsideLoop(_) => true,
+            Query::And(a, b) | Query::Or(a, b) => a.uses_loops() || b.uses_loops(),
+            Query::Not(query) => query.uses_loops(),
+            _ => false,
+        }
+    }
+
+    /// Offsets of the expressions that make a function match, or `None` if it
+    /// doesn't match
+    ///
+    /// A match can have no offsets, for example one of `!call("IsValid")`.
+    pub fn evaluate(
+        &self,
+        expressions: &[Expr],
+        env: &impl QueryEnv,
+    ) -> Option<Vec<BytecodeOffset>> {
+        let mut offsets = self.matching_offsets(expressions, env)?;
+        offsets.sort_unstable();
+        offsets.dedup();
+        Some(offsets)
+    }
+
+    fn matching_offsets(
+        &self,
+        expressions: &[Expr],
+        env: &impl QueryEnv,
+    ) -> Option<Vec<BytecodeOffset>> {
+        let found = match self {
+            Query::Call(pattern) => find(expressions, |e| {
+                called_function(e)
+                    .and_then(|func| env.callee(func))
+                    .is_some_and(|path| function_matches(pattern, &path))
+            }),
+            Query::Write(pattern) => {
+                let property_matches = |property| {
+                    env.property_name(property)
+                        .is_some_and(|name| glob_match(pattern, &name))
+                };
+                find(expressions, |e| {
+                    written_property(e).is_some_and(property_matches)
+                })
+            }
+            Query::Read(pattern) => property_accesses(expressions)
+                .into_iter()
+                .filter(|&(_, property, access)| {
+                    access == Access::Read
+                        && env
+                            .property_name(property)
+                            .is_some_and(|name| glob_match(pattern, &name))
+                })
+                .map(|(offset, _, _)| offset)
+                .collect(),
+            Query::Name(pattern) => find(expressions, |e| match &e.kind {
+                ExprKind::NameConst(name) => glob_match(pattern, name.as_str()),
+                _ => false,
+            }),
+            Query::InsideLoop(query) => {
+                let mut offsets = query.matching_offsets(expressions, env)?;
+                offsets.retain(|&offset| env.in_loop(offset));
+                offsets
+            }
+            Query::And(a, b) => {
+                let mut offsets = a.matching_offsets(expressions, env)?;
+                offsets.extend(b.matching_offsets(expressions, env)?);
+                return Some(offsets);
+            }
+            Query::Or(a, b) => {
+                return match (
+                    a.matching_offsets(expressions, env),
+                    b.matching_offsets(expressions, env),
+                ) {
+                    (None, None) => None,
+                    (a, b) => Some(a.into_iter().chain(b).flatten().collect()),
+                };
+            }
+            Query::Not(query) => {
+                return match query.matching_offsets(expressions, env) {
+                    Some(_) => None,
+                    None => Some(Vec::new()),
+                };
+            }
+        };
+        (!found.is_empty()).then_some(found)
+    }
+}
+
+fn find(expressions: &[Expr], mut predicate: impl FnMut(&Expr) -> bool) -> Vec<BytecodeOffset> {
+    let mut found = Vec::new();
+    for expr in expressions {
+        expr.walk(&mut |e| {
+            if predicate(e) {
+                found.push(e.offset);
+            }
+        });
+    }
+    found
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    LParen,
+    RParen,
+    Colon,
+    And,
+    Or,
+    Not,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(ident) => format!("'{}'", ident),
+            Token::Str(text) => format!("\"{}\"", text),
+            Token::LParen => "'('".to_string(),
+            Token::RParen => "')'".to_string(),
+            Token::Colon => "':'".to_string(),
+            Token::And => "'&&'".to_string(),
+            Token::Or => "'||'".to_string(),
+            Token::Not => "'!'".to_string(),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            ':' => tokens.push(Token::Colon),
+            '!' => tokens.push(Token::Not),
+            '&' | '|' => {
+                if chars.next_if_eq(&c).is_none() {
+                    return Err(format!("expected '{0}{0}'", c));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => string.push(escaped),
+                            None => return Err("unterminated string".to_string()),
+                        },
+                        Some(c) => string.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(string));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    ident.push(c);
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => return Err(format!("unexpected character '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or := and ("||" and)*`, `and := unary ("&&" unary)*`,
+/// `unary := "!" unary | primary ["inside" "loop"]`
+struct QueryParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!(
+                "expected {}, found {}",
+                expected.describe(),
+                token.describe()
+            )),
+            None => Err(format!(
+                "expected {}, found end of query",
+                expected.describe()
+            )),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Query, String> {
+        let mut query = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, String> {
+        let mut query = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            query = Query::And(Box::new(query), Box::new(self.parse_unary()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Query::Not(Box::new(self.parse_unary()?)));
+        }
+
+        let query = self.parse_primary()?;
+        if self.peek() == Some(&Token::Ident("inside".to_string())) {
+            self.pos += 1;
+            match self.next() {
+                Some(Token::Ident(context)) if context == "loop" => {}
+                Some(token) => return Err(format!("expected 'loop', found {}", token.describe())),
+                None => return Err("expected 'loop', found end of query".to_string()),
+            }
+            return Ok(Query::InsideLoop(Box::new(query)));
+        }
+        Ok(query)
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, String> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let query = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                Ok(query)
+            }
+            Some(Token::Ident(atom)) => {
+                let label = match atom.as_str() {
+                    "call" => "fn",
+                    "write" | "read" => "prop",
+                    "name" => "name",
+                    _ => return Err(format!("unknown pattern '{}'", atom)),
+                };
+                self.expect(Token::LParen)?;
+                if let Some(Token::Ident(given)) = self.peek() {
+                    if given != label {
+                        return Err(format!("{}() takes '{}:', not '{}:'", atom, label, given));
+                    }
+                    self.pos += 1;
+                    self.expect(Token::Colon)?;
+                }
+                let pattern = match self.next() {
+                    Some(Token::Str(pattern)) => pattern,
+                    _ => return Err(format!("{}() takes a quoted pattern", atom)),
+                };
+                self.expect(Token::RParen)?;
+                Ok(match atom.as_str() {
+                    "call" => Query::Call(pattern),
+                    "write" => Query::Write(pattern),
+                    "read" => Query::Read(pattern),
+                    _ => Query::Name(pattern),
+                })
+            }
+            Some(token) => Err(format!("expected a pattern, found {}", token.describe())),
+            None => Err("expected a pattern, found end of query".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{at, by_name, call};
+    use crate::bytecode::types::Address;
+
+    struct TestEnv;
+
+    impl QueryEnv for TestEnv {
+        fn callee(&self, func: &FunctionRef) -> Option<String> {
+            by_name(func)
+        }
+
+        fn property_name(&self, property: PropertyRef) -> Option<String> {
+            Some(format!("Prop{}", property.address.as_u64()))
+        }
+
+        fn in_loop(&self, offset: BytecodeOffset) -> bool {
+            offset >= BytecodeOffset::new(100)
+        }
+    }
+
+    fn variable(offset: usize, address: u64) -> Expr {
+        at(
+            offset,
+            ExprKind::InstanceVariable(PropertyRef::new(Address::new(address))),
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Query::parse(r#"write(prop:"Health") && !call("*Damage*") inside loop"#),
+            Ok(Query::And(
+                Box::new(Query::Write("Health".to_string())),
+                Box::new(Query::Not(Box::new(Query::InsideLoop(Box::new(
+                    Query::Call("*Damage*".to_string())
+                )))))
+            ))
+        );
+        assert!(Query::parse(r#"call(prop:"X")"#).is_err());
+        assert!(Query::parse(r#"call("X") inside function"#).is_err());
+        assert!(Query::parse(r#"(call("X")"#).is_err());
+        assert!(Query::parse(r#"call("X") & read("Y")"#).is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        // Prop1 = Prop2; SpawnActor() at 100, inside a loop
+        let expressions = vec![
+            at(
+                0,
+                ExprKind::Let {
+                    property: PropertyRef::new(Address::new(1)),
+                    variable: Box::new(variable(1, 1)),
+                    value: Box::new(variable(10, 2)),
+                },
+            ),
+            call(100, "SpawnActor"),
+        ];
+        let run = |text: &str| Query::parse(text).unwrap().evaluate(&expressions, &TestEnv);
+        let offsets = |list: &[usize]| Some(list.iter().map(|&o| BytecodeOffset::new(o)).collect());
+
+        assert_eq!(run(r#"write("Prop1")"#), offsets(&[0]));
+        assert_eq!(run(r#"read("Prop*")"#), offsets(&[10]));
+        assert_eq!(run(r#"call("Spawn*") inside loop"#), offsets(&[100]));
+        assert_eq!(run(r#"write("Prop1") inside loop"#), None);
+        assert_eq!(run(r#"write("Prop2") || call("Spawn*")"#), offsets(&[100]));
+        assert_eq!(
+            run(r#"write("Prop1") && call("Spawn*")"#),
+            offsets(&[0, 100])
+        );
+        assert_eq!(run(r#"!call("IsValid")"#), offsets(&[]));
+    }
+}
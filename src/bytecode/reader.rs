@@ -1,8 +1,9 @@
 /// Low-level binary reader for Kismet bytecode
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 
-use super::address_index::AddressIndex;
-use super::types::{Address, Name};
+use super::types::{Address, F32, F64, Name};
+use crate::error::KismetError;
 
 pub type CodeSkipSizeType = u32;
 
@@ -10,20 +11,11 @@ pub type CodeSkipSizeType = u32;
 pub struct ScriptReader<'a> {
     script: &'a [u8],
     names: &'a BTreeMap<u32, String>,
-    address_index: &'a AddressIndex<'a>,
 }
 
 impl<'a> ScriptReader<'a> {
-    pub fn new(
-        script: &'a [u8],
-        names: &'a BTreeMap<u32, String>,
-        address_index: &'a AddressIndex<'a>,
-    ) -> Self {
-        Self {
-            script,
-            names,
-            address_index,
-        }
+    pub fn new(script: &'a [u8], names: &'a BTreeMap<u32, String>) -> Self {
+        Self { script, names }
     }
 
     pub fn script(&self) -> &[u8] {
@@ -32,96 +24,104 @@ impl<'a> ScriptReader<'a> {
 
     // Primitive reads
 
-    pub fn read_byte(&self, offset: &mut usize) -> u8 {
-        let value = self.script[*offset];
+    /// The byte at `offset`, without moving past it
+    pub fn peek_byte(&self, offset: usize) -> Result<u8, KismetError> {
+        self.script
+            .get(offset)
+            .copied()
+            .ok_or_else(|| KismetError::bytecode(offset, "unexpected end of script"))
+    }
+
+    fn read_bytes<const N: usize>(&self, offset: &mut usize) -> Result<[u8; N], KismetError> {
+        let bytes = self
+            .script
+            .get(*offset..)
+            .and_then(|rest| rest.first_chunk::<N>())
+            .ok_or_else(|| KismetError::bytecode(*offset, "unexpected end of script"))?;
+        *offset += N;
+        Ok(*bytes)
+    }
+
+    pub fn read_byte(&self, offset: &mut usize) -> Result<u8, KismetError> {
+        let value = self.peek_byte(*offset)?;
         *offset += 1;
-        value
+        Ok(value)
     }
 
-    pub fn read_word(&self, offset: &mut usize) -> u16 {
-        let bytes: [u8; 2] = self.script[*offset..*offset + 2].try_into().unwrap();
-        *offset += 2;
-        u16::from_le_bytes(bytes)
+    pub fn read_word(&self, offset: &mut usize) -> Result<u16, KismetError> {
+        self.read_bytes(offset).map(u16::from_le_bytes)
     }
 
-    pub fn read_int(&self, offset: &mut usize) -> i32 {
-        let bytes: [u8; 4] = self.script[*offset..*offset + 4].try_into().unwrap();
-        *offset += 4;
-        i32::from_le_bytes(bytes)
+    pub fn read_int(&self, offset: &mut usize) -> Result<i32, KismetError> {
+        self.read_bytes(offset).map(i32::from_le_bytes)
     }
 
-    pub fn read_qword(&self, offset: &mut usize) -> u64 {
-        let bytes: [u8; 8] = self.script[*offset..*offset + 8].try_into().unwrap();
-        *offset += 8;
-        u64::from_le_bytes(bytes)
+    pub fn read_qword(&self, offset: &mut usize) -> Result<u64, KismetError> {
+        self.read_bytes(offset).map(u64::from_le_bytes)
     }
 
-    pub fn read_float(&self, offset: &mut usize) -> f32 {
-        let int_value = self.read_int(offset);
-        f32::from_bits(int_value as u32)
+    pub fn read_float(&self, offset: &mut usize) -> Result<F32, KismetError> {
+        let int_value = self.read_int(offset)?;
+        Ok(F32(f32::from_bits(int_value as u32)))
     }
 
-    pub fn read_skip_count(&self, offset: &mut usize) -> CodeSkipSizeType {
-        self.read_int(offset) as CodeSkipSizeType
+    pub fn read_double(&self, offset: &mut usize) -> Result<F64, KismetError> {
+        Ok(F64(f64::from_bits(self.read_qword(offset)?)))
+    }
+
+    pub fn read_skip_count(&self, offset: &mut usize) -> Result<CodeSkipSizeType, KismetError> {
+        Ok(self.read_int(offset)? as CodeSkipSizeType)
     }
 
     // String reads
 
-    pub fn read_string8(&self, offset: &mut usize) -> String {
-        let mut result = String::new();
-        loop {
-            let byte = self.read_byte(offset);
-            if byte == 0 {
-                break;
-            }
-            result.push(byte as char);
-        }
-        result
+    /// Read a null-terminated 8-bit string as UTF-8, and whether it had to
+    /// replace bytes that aren't valid UTF-8 with U+FFFD
+    pub fn read_string8(&self, offset: &mut usize) -> Result<(String, bool), KismetError> {
+        let start = *offset;
+        while self.read_byte(offset)? != 0 {}
+        let bytes = &self.script[start..*offset - 1];
+        Ok(match String::from_utf8_lossy(bytes) {
+            Cow::Borrowed(text) => (text.to_string(), false),
+            Cow::Owned(text) => (text, true),
+        })
     }
 
-    pub fn read_string16(&self, offset: &mut usize) -> String {
-        let mut result = String::new();
+    /// Read a null-terminated UTF-16 string; characters outside the BMP are
+    /// surrogate pairs, and unpaired surrogates become U+FFFD
+    pub fn read_string16(&self, offset: &mut usize) -> Result<String, KismetError> {
+        let mut units = Vec::new();
         loop {
-            let word = self.read_word(offset);
+            let word = self.read_word(offset)?;
             if word == 0 {
                 break;
             }
-            if let Some(ch) = char::from_u32(word as u32) {
-                result.push(ch);
-            }
+            units.push(word);
         }
-        result
+        Ok(String::from_utf16_lossy(&units))
     }
 
     // Domain-specific reads
 
-    pub fn read_name(&self, offset: &mut usize) -> Name {
+    pub fn read_name(&self, offset: &mut usize) -> Result<Name, KismetError> {
         // FScriptName structure:
         // ComparisonIndex: u32 (FNameEntryId)
         // DisplayIndex: u32 (FNameEntryId)
         // Number: u32
-        let _comparison_index = self.read_int(offset) as u32;
-        let display_index = self.read_int(offset) as u32;
-        let number = self.read_int(offset) as u32;
+        let _comparison_index = self.read_int(offset)? as u32;
+        let display_index = self.read_int(offset)? as u32;
+        let number = self.read_int(offset)? as u32;
 
         // Look up the name in the name map
-        let base_name = self
-            .names
-            .get(&display_index)
-            .cloned()
-            .unwrap_or_else(|| format!("UnknownName_{}", display_index));
-
-        // Apply the _N suffix if needed
-        let name_str = if number == 0 {
-            base_name
-        } else {
-            format!("{}_{}", base_name, number - 1)
+        let base_name = match self.names.get(&display_index) {
+            Some(name) => Cow::Borrowed(name.as_str()),
+            None => Cow::Owned(format!("UnknownName_{}", display_index)),
         };
 
-        Name::new(name_str)
+        Ok(Name::with_number(&base_name, number))
     }
 
-    pub fn read_address(&self, offset: &mut usize) -> Address {
-        Address::new(self.read_qword(offset))
+    pub fn read_address(&self, offset: &mut usize) -> Result<Address, KismetError> {
+        self.read_qword(offset).map(Address::new)
     }
 }
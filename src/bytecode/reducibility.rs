@@ -0,0 +1,155 @@
+/// Reducibility of a control flow graph
+///
+/// A CFG is reducible when every loop has a single entry, i.e. every edge
+/// that closes a cycle in a depth-first walk goes back to a block that
+/// dominates its source. Code jumping into the middle of a loop breaks this,
+/// and the structurer can only express such regions with gotos.
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Write};
+
+use super::cfg::{BlockId, ControlFlowGraph, dfs_postorder};
+use super::dominators::DominatorTree;
+
+#[derive(Debug, Clone, Default)]
+pub struct Reducibility {
+    /// Cycle-closing edges whose target doesn't dominate their source
+    pub irreducible_edges: Vec<(BlockId, BlockId)>,
+    /// Blocks of each cycle with more than one entry; overlapping cycles are
+    /// merged into one region
+    pub irreducible_regions: Vec<BTreeSet<BlockId>>,
+}
+
+impl Reducibility {
+    pub fn analyze(cfg: &ControlFlowGraph, dom_tree: &DominatorTree) -> Self {
+        let successors = |id: BlockId| cfg.get_block(id).map(|b| b.successors.as_slice());
+        let postorder = dfs_postorder([cfg.entry_block], successors);
+        let post_index: HashMap<BlockId, usize> = postorder
+            .iter()
+            .enumerate()
+            .map(|(index, &id)| (id, index))
+            .collect();
+
+        // In a depth-first walk an edge closes a cycle exactly when its target
+        // finishes no earlier than its source
+        let mut irreducible_edges = Vec::new();
+        for &from in &postorder {
+            for &to in &cfg.blocks[from.0].successors {
+                if post_index.get(&to) >= post_index.get(&from) && !dom_tree.dominates(to, from) {
+                    irreducible_edges.push((from, to));
+                }
+            }
+        }
+
+        let mut irreducible_regions: Vec<BTreeSet<BlockId>> = Vec::new();
+        for &(from, to) in &irreducible_edges {
+            // The cycle is everything on a path from the edge's target back
+            // to its source
+            let forward = reachable(to, |id| &cfg.blocks[id.0].successors);
+            let backward = reachable(from, |id| &cfg.blocks[id.0].predecessors);
+            let mut region: BTreeSet<BlockId> = forward.intersection(&backward).copied().collect();
+
+            irreducible_regions.retain(|other| {
+                let overlaps = !other.is_disjoint(&region);
+                if overlaps {
+                    region.extend(other);
+                }
+                !overlaps
+            });
+            irreducible_regions.push(region);
+        }
+        irreducible_regions.sort();
+
+        Self {
+            irreducible_edges,
+            irreducible_regions,
+        }
+    }
+
+    pub fn is_reducible(&self) -> bool {
+        self.irreducible_edges.is_empty()
+    }
+
+    /// Write whether the graph is reducible, and if not where, in a
+    /// human-readable format
+    pub fn write_debug(&self, out: &mut dyn Write) -> fmt::Result {
+        writeln!(out, "Reducibility:")?;
+        if self.is_reducible() {
+            return writeln!(out, "  Reducible");
+        }
+        writeln!(
+            out,
+            "  Irreducible: {} region(s)",
+            self.irreducible_regions.len()
+        )?;
+        for (i, region) in self.irreducible_regions.iter().enumerate() {
+            writeln!(out, "  Region {}: {:?}", i, region)?;
+        }
+        writeln!(out, "  Entering Edges: {:?}", self.irreducible_edges)
+    }
+}
+
+fn reachable<'g>(start: BlockId, edges: impl Fn(BlockId) -> &'g [BlockId]) -> BTreeSet<BlockId> {
+    let mut seen = BTreeSet::from([start]);
+    let mut work = vec![start];
+    while let Some(id) = work.pop() {
+        for &next in edges(id) {
+            if seen.insert(next) {
+                work.push(next);
+            }
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::{BytecodeBuilder, parse};
+
+    #[test]
+    fn test_jump_into_loop_is_irreducible() {
+        // if (!true) goto b; a: Math(); b: Math(); if (!true) goto a; return;
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::JumpIfNot);
+        let to_b = b.placeholder();
+        b.op(EExprToken::True);
+        let a = b.here();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+        let b_start = b.here();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+        b.op(EExprToken::JumpIfNot);
+        let to_a = b.placeholder();
+        b.op(EExprToken::True);
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(to_b, b_start);
+        b.patch(to_a, a);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let dom_tree = DominatorTree::compute(&cfg);
+        let reducibility = Reducibility::analyze(&cfg, &dom_tree);
+        assert!(!reducibility.is_reducible());
+        assert_eq!(reducibility.irreducible_regions.len(), 1);
+        assert_eq!(reducibility.irreducible_regions[0].len(), 2);
+    }
+
+    #[test]
+    fn test_natural_loop_is_reducible() {
+        // loop: Math(); if (!true) goto loop; return;
+        let mut b = BytecodeBuilder::default();
+        let start = b.here();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+        b.op(EExprToken::JumpIfNot);
+        let target = b.placeholder();
+        b.op(EExprToken::True);
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(target, start);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let dom_tree = DominatorTree::compute(&cfg);
+        assert!(Reducibility::analyze(&cfg, &dom_tree).is_reducible());
+    }
+}
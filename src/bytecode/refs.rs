@@ -2,7 +2,7 @@
 use super::types::{Address, Name};
 
 /// Reference to a property (variable)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PropertyRef {
     pub address: Address,
 }
@@ -14,7 +14,7 @@ impl PropertyRef {
 }
 
 /// Reference to a function (can be either by address or by name)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum FunctionRef {
     ByAddress(Address),
     ByName(Name),
@@ -31,7 +31,7 @@ impl FunctionRef {
 }
 
 /// Reference to an object
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ObjectRef {
     pub address: Address,
 }
@@ -43,7 +43,7 @@ impl ObjectRef {
 }
 
 /// Reference to a struct type
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct StructRef {
     pub address: Address,
 }
@@ -55,7 +55,7 @@ impl StructRef {
 }
 
 /// Reference to a class type
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ClassRef {
     pub address: Address,
 }
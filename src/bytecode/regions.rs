@@ -0,0 +1,200 @@
+/// Collapsing parts of huge control flow graphs for drawing
+///
+/// Graphviz can't lay out graphs of thousands of blocks, which whole
+/// ubergraphs easily reach. Past [`COLLAPSE_THRESHOLD`] blocks, DOT output
+/// draws each loop and single-entry region as one summary node instead;
+/// `--expand-region N` draws region `N`'s blocks again, with the regions
+/// nested in it still collapsed until they're expanded too.
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::cfg::{BlockId, ControlFlowGraph, Terminator};
+use super::loops::LoopInfo;
+
+/// Graphs with more blocks than this are drawn with regions collapsed
+pub const COLLAPSE_THRESHOLD: usize = 1000;
+
+/// Regions smaller than this aren't worth a summary node
+const MIN_REGION_BLOCKS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Loop,
+    /// Blocks only reachable through the entry block, such as one side of a
+    /// branch or an event's code in an ubergraph
+    SingleEntry,
+}
+
+/// A part of a graph that can be drawn as one node
+#[derive(Debug, Clone)]
+pub struct CollapsibleRegion {
+    /// Number to pass to `--expand-region`, counting from 1 in bytecode order
+    pub number: usize,
+    pub kind: RegionKind,
+    pub entry: BlockId,
+    pub blocks: HashSet<BlockId>,
+}
+
+/// The loops and single-entry regions of a graph, nested in each other or
+/// apart; a region that partly overlaps a larger one is left out
+pub fn collapsible_regions(cfg: &ControlFlowGraph, loop_info: &LoopInfo) -> Vec<CollapsibleRegion> {
+    let mut candidates: Vec<(RegionKind, BlockId, HashSet<BlockId>)> = loop_info
+        .loops
+        .iter()
+        .map(|l| (RegionKind::Loop, l.header, l.blocks.clone()))
+        .collect();
+
+    // Code can start at the entry or at any block nothing jumps to
+    let starts: Vec<BlockId> = cfg
+        .blocks
+        .iter()
+        .filter(|block| block.id == cfg.entry_block || block.predecessors.is_empty())
+        .map(|block| block.id)
+        .collect();
+    let arms = cfg.blocks.iter().flat_map(|block| match block.terminator {
+        Terminator::Branch {
+            true_target,
+            false_target,
+            ..
+        } => vec![true_target, false_target],
+        _ => Vec::new(),
+    });
+    let roots: HashSet<BlockId> = starts
+        .iter()
+        .copied()
+        .filter(|&start| start != cfg.entry_block)
+        .chain(arms.filter(|&arm| cfg.blocks[arm.0].predecessors.len() == 1))
+        .collect();
+    for root in roots {
+        let blocks = dominated_region(cfg, &starts, root);
+        candidates.push((RegionKind::SingleEntry, root, blocks));
+    }
+    candidates.retain(|(_, _, blocks)| blocks.len() >= MIN_REGION_BLOCKS);
+
+    // Larger regions first, so the one kept of two equal ones is the loop
+    candidates.sort_by_key(|(kind, _, blocks)| {
+        (
+            std::cmp::Reverse(blocks.len()),
+            *kind == RegionKind::SingleEntry,
+        )
+    });
+    let mut kept: Vec<(RegionKind, BlockId, HashSet<BlockId>)> = Vec::new();
+    for (kind, entry, blocks) in candidates {
+        let nests = kept.iter().all(|(_, _, other)| {
+            blocks.is_disjoint(other) || (blocks.is_subset(other) && blocks != *other)
+        });
+        if nests {
+            kept.push((kind, entry, blocks));
+        }
+    }
+
+    kept.sort_by_key(|(_, entry, blocks)| {
+        (
+            cfg.blocks[entry.0].start_offset,
+            std::cmp::Reverse(blocks.len()),
+        )
+    });
+    kept.into_iter()
+        .enumerate()
+        .map(|(index, (kind, entry, blocks))| CollapsibleRegion {
+            number: index + 1,
+            kind,
+            entry,
+            blocks,
+        })
+        .collect()
+}
+
+/// Blocks that can only be reached from `starts` through `root`
+fn dominated_region(cfg: &ControlFlowGraph, starts: &[BlockId], root: BlockId) -> HashSet<BlockId> {
+    let reachable = |from: &[BlockId], avoid: Option<BlockId>| {
+        let mut seen: HashSet<BlockId> =
+            from.iter().copied().filter(|&b| Some(b) != avoid).collect();
+        let mut queue: VecDeque<BlockId> = seen.iter().copied().collect();
+        while let Some(block) = queue.pop_front() {
+            for &succ in &cfg.blocks[block.0].successors {
+                if Some(succ) != avoid && seen.insert(succ) {
+                    queue.push_back(succ);
+                }
+            }
+        }
+        seen
+    };
+    let around = reachable(starts, Some(root));
+    reachable(&[root], None)
+        .into_iter()
+        .filter(|block| !around.contains(block))
+        .collect()
+}
+
+/// Which collapsed region each block is drawn as part of
+///
+/// A region is collapsed unless it's in `expanded` or inside a region that's
+/// collapsed already.
+pub fn collapsed_blocks<'r>(
+    regions: &'r [CollapsibleRegion],
+    expanded: &[usize],
+) -> HashMap<BlockId, &'r CollapsibleRegion> {
+    let mut outermost_first: Vec<&CollapsibleRegion> = regions.iter().collect();
+    outermost_first.sort_by_key(|region| std::cmp::Reverse(region.blocks.len()));
+    let mut collapsed = HashMap::new();
+    for region in outermost_first {
+        if expanded.contains(&region.number) || collapsed.contains_key(&region.entry) {
+            continue;
+        }
+        for &block in &region.blocks {
+            collapsed.insert(block, region);
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::dominators::DominatorTree;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::{BytecodeBuilder, parse};
+
+    #[test]
+    fn test_regions_nest_and_expand() {
+        // loop: if (!v1) goto end; Math(); Math(); if (!v2) goto skip;
+        //   Math(); skip: Math(); goto loop;
+        // end: return;
+        let mut b = BytecodeBuilder::default();
+        let work = |b: &mut BytecodeBuilder| {
+            b.op(EExprToken::CallMath).address(0x1000);
+            b.op(EExprToken::EndFunctionParms);
+        };
+        let jump_unless = |b: &mut BytecodeBuilder, variable: u64| {
+            b.op(EExprToken::JumpIfNot);
+            let target = b.placeholder();
+            b.op(EExprToken::LocalVariable).address(variable);
+            target
+        };
+        let top = b.here();
+        let to_end = jump_unless(&mut b, 1);
+        work(&mut b);
+        let to_skip = jump_unless(&mut b, 2);
+        work(&mut b);
+        let skip = b.here();
+        work(&mut b);
+        b.op(EExprToken::Jump);
+        let back = b.placeholder();
+        let end = b.here();
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(to_end, end);
+        b.patch(to_skip, skip);
+        b.patch(back, top);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let loop_info = LoopInfo::analyze(&cfg, &DominatorTree::compute(&cfg));
+        let regions = collapsible_regions(&cfg, &loop_info);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].kind, RegionKind::Loop);
+        assert_eq!(regions[0].blocks.len(), 4);
+
+        let collapsed = collapsed_blocks(&regions, &[]);
+        assert_eq!(collapsed.len(), 4);
+        assert!(collapsed_blocks(&regions, &[1]).is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+/// Heuristic audit of Server RPCs
+///
+/// Server RPCs run on the server with arguments chosen by a client, so their
+/// bodies should check object arguments with `IsValid` and gate side effects
+/// on authority or a `_Validate` function. This looks for those checks in the
+/// decompiled body and reports RPCs where none are found. It's a pattern
+/// search, not a proof: a check anywhere in the body counts.
+use super::expr::Expr;
+use super::flags::{CPF_PARM, CPF_RETURN_PARM, FUNC_NET, FUNC_NET_SERVER, FUNC_NET_VALIDATE};
+use super::pattern::find_calls;
+use super::refs::FunctionRef;
+use super::types::BytecodeOffset;
+
+/// Calls that check an object reference
+const VALIDITY_CHECKS: &[&str] = &["IsValid", "IsValidClass", "IsValidSoftObjectReference"];
+/// Calls that check which side of the network the code is running on
+const AUTHORITY_CHECKS: &[&str] = &["HasAuthority", "IsServer", "GetLocalRole", "GetRemoteRole"];
+
+pub fn is_server_rpc(function_flags: u32) -> bool {
+    function_flags & (FUNC_NET | FUNC_NET_SERVER) == FUNC_NET | FUNC_NET_SERVER
+}
+
+/// Names of a function's parameters that hold object references
+pub fn object_params(properties: &[jmap::Property]) -> Vec<String> {
+    properties
+        .iter()
+        .filter(|prop| {
+            let flags = prop.flags.bits();
+            flags & CPF_PARM != 0 && flags & CPF_RETURN_PARM == 0
+        })
+        .filter(|prop| {
+            matches!(
+                prop.r#type,
+                jmap::PropertyType::Object { .. }
+                    | jmap::PropertyType::Class { .. }
+                    | jmap::PropertyType::Interface { .. }
+                    | jmap::PropertyType::WeakObject { .. }
+                    | jmap::PropertyType::SoftObject { .. }
+                    | jmap::PropertyType::SoftClass { .. }
+                    | jmap::PropertyType::LazyObject { .. }
+            )
+        })
+        .map(|prop| prop.name.clone())
+        .collect()
+}
+
+/// What was found in one RPC's body
+#[derive(Debug, Clone, Default)]
+pub struct RpcAudit {
+    /// The RPC has a `_Validate` function run before it
+    pub has_validate: bool,
+    /// Names of parameters holding object references
+    pub object_params: Vec<String>,
+    /// Offset and path of each validity check call
+    pub validity_checks: Vec<(BytecodeOffset, String)>,
+    /// Offset and path of each authority check call
+    pub authority_checks: Vec<(BytecodeOffset, String)>,
+}
+
+impl RpcAudit {
+    /// Look for checks in an RPC's body
+    ///
+    /// `bodies` holds the RPC's own script plus any code it runs, such as its
+    /// slice of the ubergraph and the functions it calls, each with the path
+    /// of the function it belongs to. `resolve` maps a call in that function
+    /// to the callee's path.
+    pub fn new(
+        function_flags: u32,
+        object_params: Vec<String>,
+        bodies: &[(&str, &[Expr])],
+        resolve: impl Fn(&str, &FunctionRef) -> Option<String>,
+    ) -> Self {
+        let find_any = |patterns: &[&str]| -> Vec<(BytecodeOffset, String)> {
+            bodies
+                .iter()
+                .flat_map(|&(function, body)| {
+                    patterns
+                        .iter()
+                        .flat_map(|pattern| find_calls(body, pattern, |f| resolve(function, f)))
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+
+        Self {
+            has_validate: function_flags & FUNC_NET_VALIDATE != 0,
+            object_params,
+            validity_checks: find_any(VALIDITY_CHECKS),
+            authority_checks: find_any(AUTHORITY_CHECKS),
+        }
+    }
+
+    /// Descriptions of the missing checks; empty if nothing looks wrong
+    pub fn findings(&self) -> Vec<String> {
+        let mut findings = Vec::new();
+        if !self.object_params.is_empty() && self.validity_checks.is_empty() {
+            findings.push(format!(
+                "object parameters never checked with IsValid: {}",
+                self.object_params.join(", ")
+            ));
+        }
+        if !self.has_validate && self.authority_checks.is_empty() {
+            findings.push("no _Validate function and no authority check".to_string());
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{by_name, call};
+
+    #[test]
+    fn test_findings() {
+        let flags = FUNC_NET | FUNC_NET_SERVER;
+        let unchecked = [call(0, "DestroyActor")];
+        let audit = RpcAudit::new(
+            flags,
+            vec!["Target".to_string()],
+            &[("Rpc", &unchecked)],
+            |_, func| by_name(func),
+        );
+        assert_eq!(audit.findings().len(), 2);
+
+        let checked = [call(0, "IsValid"), call(10, "HasAuthority")];
+        let audit = RpcAudit::new(
+            flags,
+            vec!["Target".to_string()],
+            &[("Rpc", &checked)],
+            |_, func| by_name(func),
+        );
+        assert!(audit.findings().is_empty());
+
+        let audit = RpcAudit::new(
+            flags | FUNC_NET_VALIDATE,
+            Vec::new(),
+            &[("Rpc", &unchecked)],
+            |_, func| by_name(func),
+        );
+        assert!(audit.findings().is_empty());
+    }
+}
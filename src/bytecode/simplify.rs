@@ -0,0 +1,219 @@
+/// Algebraic simplification of operator expressions
+///
+/// Rewrites trees of math library operators into smaller equivalents: adding
+/// zero, multiplying by one, `true && x`, double negation, and negated
+/// comparisons such as `!(a == b)` becoming `a != b`. Pushing `!` into `&&`
+/// and `||` only happens when every operand can be negated without growing,
+/// so the result is never bigger than the input. Rules never drop an operand
+/// that could have side effects.
+use super::expr::{Expr, ExprKind, ensure_stack};
+use super::intrinsics::{
+    OperandType, Operator, OperatorIntrinsic, lookup_operator_intrinsic, operator_intrinsic,
+};
+use super::refs::FunctionRef;
+use super::types::Name;
+
+/// A simpler form of `expr`, or `None` if it can't be simplified
+///
+/// `resolve` gives the path of a called function, for recognizing operators.
+pub fn simplify(expr: &Expr, resolve: &impl Fn(&FunctionRef) -> Option<String>) -> Option<Expr> {
+    let simplified = rewrite(expr, resolve);
+    (simplified != *expr).then_some(simplified)
+}
+
+/// An operator call split into its parts
+struct Operation<'e> {
+    intrinsic: &'static OperatorIntrinsic,
+    params: &'e [Expr],
+}
+
+fn operation<'e>(
+    expr: &'e Expr,
+    resolve: &impl Fn(&FunctionRef) -> Option<String>,
+) -> Option<Operation<'e>> {
+    let ExprKind::CallMath { func, params } = &expr.kind else {
+        return None;
+    };
+    let intrinsic = lookup_operator_intrinsic(&resolve(func)?)?;
+    (params.len() == intrinsic.op.arity()).then_some(Operation { intrinsic, params })
+}
+
+fn call(intrinsic: &OperatorIntrinsic, like: &Expr, params: Vec<Expr>) -> Expr {
+    Expr::new(
+        like.offset,
+        ExprKind::CallMath {
+            func: FunctionRef::ByName(Name::new(intrinsic.path())),
+            params,
+        },
+    )
+    .with_end(like.end)
+}
+
+fn rewrite(expr: &Expr, resolve: &impl Fn(&FunctionRef) -> Option<String>) -> Expr {
+    ensure_stack(|| {
+        let Some(operation) = operation(expr, resolve) else {
+            return expr.clone();
+        };
+        let params: Vec<Expr> = operation
+            .params
+            .iter()
+            .map(|param| rewrite(param, resolve))
+            .collect();
+        let mut current = call(operation.intrinsic, expr, params);
+        // Every rule makes the tree smaller, so this ends
+        while let Some(next) = apply_rules(&current, resolve) {
+            current = next;
+        }
+        // Keep the original call when nothing changed, so its function
+        // reference stays as it was
+        if current == call(operation.intrinsic, expr, operation.params.to_vec()) {
+            expr.clone()
+        } else {
+            current
+        }
+    })
+}
+
+fn apply_rules(expr: &Expr, resolve: &impl Fn(&FunctionRef) -> Option<String>) -> Option<Expr> {
+    let Operation { intrinsic, params } = operation(expr, resolve)?;
+    match (intrinsic.op, params) {
+        (Operator::Add, [left, right]) if is_zero(right) => Some(left.clone()),
+        (Operator::Add, [left, right]) if is_zero(left) => Some(right.clone()),
+        (Operator::Subtract, [left, right]) if is_zero(right) => Some(left.clone()),
+        (Operator::Multiply, [left, right]) if is_one(right) => Some(left.clone()),
+        (Operator::Multiply, [left, right]) if is_one(left) => Some(right.clone()),
+        (Operator::Divide, [left, right]) if is_one(right) => Some(left.clone()),
+        (Operator::And, [left, right]) if matches!(left.kind, ExprKind::True) => {
+            Some(right.clone())
+        }
+        (Operator::And, [left, right]) if matches!(right.kind, ExprKind::True) => {
+            Some(left.clone())
+        }
+        (Operator::Or, [left, right]) if matches!(left.kind, ExprKind::False) => {
+            Some(right.clone())
+        }
+        (Operator::Or, [left, right]) if matches!(right.kind, ExprKind::False) => {
+            Some(left.clone())
+        }
+        (Operator::Not, [operand]) => negate_without_growing(operand, resolve),
+        _ => None,
+    }
+}
+
+/// `!expr` at most as big as `expr`, if there is one
+fn negate_without_growing(
+    expr: &Expr,
+    resolve: &impl Fn(&FunctionRef) -> Option<String>,
+) -> Option<Expr> {
+    let Operation { intrinsic, params } = operation(expr, resolve)?;
+    match intrinsic.op {
+        Operator::Not => Some(params[0].clone()),
+        Operator::And | Operator::Or => {
+            let flipped = if intrinsic.op == Operator::And {
+                Operator::Or
+            } else {
+                Operator::And
+            };
+            let negated = params
+                .iter()
+                .map(|param| negate_without_growing(param, resolve))
+                .collect::<Option<Vec<_>>>()?;
+            Some(call(
+                operator_intrinsic(flipped, OperandType::Bool)?,
+                expr,
+                negated,
+            ))
+        }
+        op => {
+            let inverse = operator_intrinsic(op.inverse(intrinsic.operand)?, intrinsic.operand)?;
+            Some(call(inverse, expr, params.to_vec()))
+        }
+    }
+}
+
+fn is_zero(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::IntZero => true,
+        ExprKind::IntConst(value) => *value == 0,
+        ExprKind::Int64Const(value) => *value == 0,
+        ExprKind::ByteConst(value) | ExprKind::IntConstByte(value) => *value == 0,
+        ExprKind::FloatConst(value) => value.get() == 0.0,
+        ExprKind::DoubleConst(value) => value.get() == 0.0,
+        _ => false,
+    }
+}
+
+fn is_one(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::IntOne => true,
+        ExprKind::IntConst(value) => *value == 1,
+        ExprKind::Int64Const(value) => *value == 1,
+        ExprKind::ByteConst(value) | ExprKind::IntConstByte(value) => *value == 1,
+        ExprKind::FloatConst(value) => value.get() == 1.0,
+        ExprKind::DoubleConst(value) => value.get() == 1.0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{at, by_name, local, math};
+
+    #[test]
+    fn test_identities() {
+        let sum = math(
+            "Multiply_IntInt",
+            vec![
+                math("Add_IntInt", vec![local(0, 1), at(0, ExprKind::IntZero)]),
+                at(0, ExprKind::IntOne),
+            ],
+        );
+        assert_eq!(simplify(&sum, &by_name), Some(local(0, 1)));
+
+        let and = math("BooleanAND", vec![at(0, ExprKind::True), local(0, 2)]);
+        assert_eq!(simplify(&and, &by_name), Some(local(0, 2)));
+        assert_eq!(simplify(&local(0, 1), &by_name), None);
+    }
+
+    #[test]
+    fn test_negated_comparisons() {
+        let not_equal = math(
+            "Not_PreBool",
+            vec![math("EqualEqual_IntInt", vec![local(0, 1), local(0, 2)])],
+        );
+        assert_eq!(
+            simplify(&not_equal, &by_name),
+            Some(math("NotEqual_IntInt", vec![local(0, 1), local(0, 2)]))
+        );
+
+        // !(!a && b < c) => a || b >= c
+        let de_morgan = math(
+            "Not_PreBool",
+            vec![math(
+                "BooleanAND",
+                vec![
+                    math("Not_PreBool", vec![local(0, 1)]),
+                    math("Less_IntInt", vec![local(0, 2), local(0, 3)]),
+                ],
+            )],
+        );
+        assert_eq!(
+            simplify(&de_morgan, &by_name),
+            Some(math(
+                "BooleanOR",
+                vec![
+                    local(0, 1),
+                    math("GreaterEqual_IntInt", vec![local(0, 2), local(0, 3)])
+                ]
+            ))
+        );
+
+        // Float ordering doesn't invert, so this stays as written
+        let float_less = math(
+            "Not_PreBool",
+            vec![math("Less_DoubleDouble", vec![local(0, 1), local(0, 2)])],
+        );
+        assert_eq!(simplify(&float_less, &by_name), None);
+    }
+}
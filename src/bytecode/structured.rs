@@ -2,13 +2,32 @@
 //! https://github.com/angr/angr/blob/071ceda914755eb4933587af6e1e0536476fb8bb/angr/analyses/decompiler/structuring/phoenix.py
 //! https://www.usenix.org/conference/usenixsecurity13/technical-sessions/presentation/schwartz
 
-use super::cfg::{BasicBlock, BlockId, ControlFlowGraph, Terminator};
+use super::cfg::{BasicBlock, BlockId, BlockName, ControlFlowGraph, Terminator, dfs_postorder};
+use super::const_eval::evaluate_condition;
+use super::cost::expr_cost;
+use super::dominators::DominatorTree;
+use super::expr::{ExprKind, SwitchCase};
+use super::flow_graph::{FlowGraph, has_cycle, postorder, reaches};
+use super::hit_counts::HitCounts;
+use super::induction::{CountedLoop, counted_loop};
 use super::logger::{Logger, NullLogger};
-use super::loops::LoopInfo;
+use super::loops::{Loop, LoopInfo};
 use crate::bytecode::address_index::AddressIndex;
 use crate::bytecode::expr::Expr;
-use crate::formatters::cpp::{CppFormatter, FormatContext};
+use crate::bytecode::refs::FunctionRef;
+use crate::bytecode::types::{BytecodeOffset, Name};
+use crate::bytecode::ubergraph::ubergraph_entry;
+use crate::error::KismetError;
+use crate::formatters::FormattingOptions;
+use crate::formatters::confidence::Doubts;
+use crate::formatters::cpp::{ExprRenderer, FormatContext, StatementWriter};
+use crate::formatters::theme::Theme;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::time::Instant;
+use thiserror::Error;
+use tracing::info_span;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LoopType {
@@ -20,24 +39,89 @@ pub enum LoopType {
     Endless,
 }
 
+/// The code in one node of a graph being structured
+///
+/// The structurer moves blocks into structured nodes whole; it only reads
+/// the jump each one ends with, and rewrites it as the jump becomes a
+/// break, a continue or part of an if. [`BasicBlock`] is Kismet's.
+pub trait BlockCode: Clone + fmt::Debug {
+    /// What a branch tests and a return returns
+    type Expr: Clone + fmt::Debug;
+
+    /// The node the block is in its graph
+    fn id(&self) -> BlockId;
+
+    /// The jump the block ends with
+    fn terminator(&self) -> &Terminator<Self::Expr>;
+
+    fn terminator_mut(&mut self) -> &mut Terminator<Self::Expr>;
+
+    /// Whether the block does nothing before its jump
+    fn is_empty(&self) -> bool;
+
+    /// A branch condition that holds exactly when `condition` doesn't
+    fn negate(condition: Self::Expr) -> Self::Expr;
+}
+
+/// A [`FlowGraph`] whose nodes hold code, so [`PhoenixStructurer`] can
+/// structure it
+pub trait CodeGraph: FlowGraph {
+    type Block: BlockCode;
+
+    /// The code of `node`, one of the graph's nodes
+    fn block(&self, node: BlockId) -> &Self::Block;
+}
+
+impl BlockCode for BasicBlock {
+    type Expr = Expr;
+
+    fn id(&self) -> BlockId {
+        self.id
+    }
+
+    fn terminator(&self) -> &Terminator {
+        &self.terminator
+    }
+
+    fn terminator_mut(&mut self) -> &mut Terminator {
+        &mut self.terminator
+    }
+
+    fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    fn negate(condition: Expr) -> Expr {
+        negate(condition)
+    }
+}
+
+impl CodeGraph for ControlFlowGraph {
+    type Block = BasicBlock;
+
+    fn block(&self, node: BlockId) -> &BasicBlock {
+        &self.blocks[node.0]
+    }
+}
+
 #[derive(Debug, Clone)]
-pub enum StructuredNode {
+pub enum StructuredNode<B: BlockCode = BasicBlock> {
     Sequence {
-        nodes: Vec<StructuredNode>,
+        nodes: Vec<StructuredNode<B>>,
     },
 
     Conditional {
-        condition: Expr,
-        true_branch: Box<StructuredNode>,
-        false_branch: Option<Box<StructuredNode>>,
+        condition: B::Expr,
+        true_branch: Box<StructuredNode<B>>,
+        false_branch: Option<Box<StructuredNode<B>>>,
         /// original condition block ID
         condition_block: BlockId,
     },
 
     Loop {
         loop_type: LoopType,
-        condition: Option<Expr>,
-        body: Box<StructuredNode>,
+        condition: Option<B::Expr>,
+        body: Box<StructuredNode<B>>,
         /// loop header block ID
         header: BlockId,
     },
@@ -53,15 +137,22 @@ pub enum StructuredNode {
     },
 
     Code {
-        block: BasicBlock,
+        block: B,
+    },
+
+    /// An if whose condition always has the same value, left as a note where
+    /// the branch that can't run used to be
+    Pruned {
+        condition: B::Expr,
+        value: bool,
     },
 
     Empty,
 }
 
-impl StructuredNode {
+impl<B: BlockCode> StructuredNode<B> {
     /// Create a sequence from a vector of nodes
-    pub fn sequence(nodes: Vec<StructuredNode>, logger: &dyn Logger) -> Self {
+    pub fn sequence(nodes: Vec<StructuredNode<B>>, logger: &dyn Logger) -> Self {
         // Filter out empty nodes
         let mut nodes: Vec<_> = nodes
             .into_iter()
@@ -92,9 +183,9 @@ impl StructuredNode {
 
     /// Helper to get the block ID from a node (works for Code and structured nodes)
     /// For sequences, returns the ID of the first node in the sequence
-    fn get_block_id(node: &StructuredNode) -> Option<BlockId> {
+    fn get_block_id(node: &StructuredNode<B>) -> Option<BlockId> {
         match node {
-            StructuredNode::Code { block } => Some(block.id),
+            StructuredNode::Code { block } => Some(block.id()),
             StructuredNode::Loop { header, .. } => Some(*header),
             StructuredNode::Conditional {
                 condition_block, ..
@@ -109,30 +200,32 @@ impl StructuredNode {
 
     /// Helper version of strip_implicit_goto that doesn't need &self
     fn strip_implicit_goto_helper(
-        node: StructuredNode,
+        node: StructuredNode<B>,
         implicit_target: BlockId,
         logger: &dyn Logger,
-    ) -> StructuredNode {
+    ) -> StructuredNode<B> {
         match node {
             StructuredNode::Code { mut block } => {
                 logger.debug(&format!(
                     "    strip_implicit_goto_helper: checking Code block {:?}, terminator: {:?}",
-                    block.id,
-                    match &block.terminator {
+                    block.id(),
+                    match block.terminator() {
                         Terminator::Goto { target } => format!("Goto({:?})", target),
                         Terminator::Branch { .. } => "Branch".to_string(),
                         Terminator::Return(_) => "Return".to_string(),
                         Terminator::DynamicJump => "DynamicJump".to_string(),
+                        Terminator::Abort => "Abort".to_string(),
                         Terminator::None => "None".to_string(),
                     }
                 ));
-                match &block.terminator {
+                match block.terminator() {
                     Terminator::Goto { target } if *target == implicit_target => {
                         logger.debug(&format!(
                             "  Stripping implicit sequential goto from block {:?} to {:?}",
-                            block.id, target
+                            block.id(),
+                            target
                         ));
-                        block.terminator = Terminator::None;
+                        *block.terminator_mut() = Terminator::None;
                     }
                     _ => {}
                 }
@@ -163,9 +256,9 @@ impl StructuredNode {
 
     /// Create a conditional node
     pub fn conditional(
-        condition: Expr,
-        true_branch: StructuredNode,
-        false_branch: Option<StructuredNode>,
+        condition: B::Expr,
+        true_branch: StructuredNode<B>,
+        false_branch: Option<StructuredNode<B>>,
         condition_block: BlockId,
     ) -> Self {
         StructuredNode::Conditional {
@@ -179,8 +272,8 @@ impl StructuredNode {
     /// Create a loop node
     pub fn loop_node(
         loop_type: LoopType,
-        condition: Option<Expr>,
-        body: StructuredNode,
+        condition: Option<B::Expr>,
+        body: StructuredNode<B>,
         header: BlockId,
     ) -> Self {
         StructuredNode::Loop {
@@ -192,19 +285,294 @@ impl StructuredNode {
     }
 
     /// Create a code node from a basic block
-    pub fn code(block: BasicBlock) -> Self {
+    pub fn code(block: B) -> Self {
         StructuredNode::Code { block }
     }
+}
+
+impl StructuredNode {
+    /// Estimated cost of the node's code, counting both sides of a branch and
+    /// one iteration of each loop
+    pub fn cost(&self) -> u64 {
+        match self {
+            StructuredNode::Sequence { nodes } => nodes.iter().map(StructuredNode::cost).sum(),
+            StructuredNode::Conditional {
+                condition,
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                expr_cost(condition)
+                    + true_branch.cost()
+                    + false_branch.as_ref().map_or(0, |branch| branch.cost())
+            }
+            StructuredNode::Loop {
+                condition, body, ..
+            } => condition.as_ref().map_or(0, expr_cost) + body.cost(),
+            StructuredNode::Code { block } => block
+                .statements
+                .iter()
+                .chain(&block.terminator_expr)
+                .map(expr_cost)
+                .sum(),
+            StructuredNode::Break { .. }
+            | StructuredNode::Continue { .. }
+            | StructuredNode::Pruned { .. }
+            | StructuredNode::Empty => 0,
+        }
+    }
+
+    /// Every statement in the node's code blocks, nested ones included
+    fn statements<'s>(&'s self, statements: &mut Vec<&'s Expr>) {
+        match self {
+            StructuredNode::Sequence { nodes } => {
+                for node in nodes {
+                    node.statements(statements);
+                }
+            }
+            StructuredNode::Conditional {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                true_branch.statements(statements);
+                if let Some(false_branch) = false_branch {
+                    false_branch.statements(statements);
+                }
+            }
+            StructuredNode::Loop { body, .. } => body.statements(statements),
+            StructuredNode::Code { block } => statements.extend(&block.statements),
+            StructuredNode::Break { .. }
+            | StructuredNode::Continue { .. }
+            | StructuredNode::Pruned { .. }
+            | StructuredNode::Empty => {}
+        }
+    }
+
+    /// The statements the node always runs, in order: those of its code
+    /// blocks outside any if or loop
+    fn straight_line_statements<'s>(&'s self, statements: &mut Vec<&'s Expr>) {
+        match self {
+            StructuredNode::Sequence { nodes } => {
+                for node in nodes {
+                    node.straight_line_statements(statements);
+                }
+            }
+            StructuredNode::Code { block } => statements.extend(&block.statements),
+            _ => {}
+        }
+    }
+
+    /// The loops in this node that count a variable up to a limit, by header
+    ///
+    /// `before` holds the statements run before the node in the sequence it's
+    /// part of, where a counter's start value would be set.
+    fn counted_loops<'s>(
+        &'s self,
+        before: &[&'s Expr],
+        resolve: &impl Fn(&FunctionRef) -> Option<String>,
+        loops: &mut HashMap<BlockId, CountedLoop<'s>>,
+    ) {
+        match self {
+            StructuredNode::Sequence { nodes } => {
+                let mut before = before.to_vec();
+                for node in nodes {
+                    node.counted_loops(&before, resolve, loops);
+                    node.straight_line_statements(&mut before);
+                }
+            }
+            StructuredNode::Conditional {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                true_branch.counted_loops(&[], resolve, loops);
+                if let Some(false_branch) = false_branch {
+                    false_branch.counted_loops(&[], resolve, loops);
+                }
+            }
+            StructuredNode::Loop {
+                condition,
+                body,
+                header,
+                ..
+            } => {
+                let mut statements = Vec::new();
+                body.statements(&mut statements);
+                if let Some(found) = condition
+                    .as_ref()
+                    .and_then(|condition| counted_loop(condition, &statements, before, resolve))
+                {
+                    loops.insert(*header, found);
+                }
+                body.counted_loops(&[], resolve, loops);
+            }
+            StructuredNode::Code { .. }
+            | StructuredNode::Break { .. }
+            | StructuredNode::Continue { .. }
+            | StructuredNode::Pruned { .. }
+            | StructuredNode::Empty => {}
+        }
+    }
+
+    /// How often the node was entered, if the profile says
+    fn hit_count(&self, hits: &HitCounts) -> Option<u64> {
+        match self {
+            StructuredNode::Code { block } => hits.get(block.start_offset),
+            StructuredNode::Sequence { nodes } => {
+                nodes.iter().find_map(|node| node.hit_count(hits))
+            }
+            StructuredNode::Conditional {
+                true_branch,
+                false_branch: Some(false_branch),
+                ..
+            } => Some(true_branch.hit_count(hits)? + false_branch.hit_count(hits)?),
+            _ => None,
+        }
+    }
+
+    fn order_by_hits(&mut self, hits: &HitCounts) {
+        match self {
+            StructuredNode::Sequence { nodes } => {
+                for node in nodes {
+                    node.order_by_hits(hits);
+                }
+            }
+            StructuredNode::Conditional {
+                condition,
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                true_branch.order_by_hits(hits);
+                if let Some(false_branch) = false_branch {
+                    false_branch.order_by_hits(hits);
+                    if let (Some(taken), Some(not_taken)) =
+                        (true_branch.hit_count(hits), false_branch.hit_count(hits))
+                        && not_taken > taken
+                    {
+                        std::mem::swap(true_branch, false_branch);
+                        *condition = negate(condition.clone());
+                    }
+                }
+            }
+            StructuredNode::Loop { body, .. } => body.order_by_hits(hits),
+            StructuredNode::Code { block } => {
+                for stmt in &mut block.statements {
+                    order_switch_cases(stmt, hits);
+                }
+            }
+            StructuredNode::Break { .. }
+            | StructuredNode::Continue { .. }
+            | StructuredNode::Pruned { .. }
+            | StructuredNode::Empty => {}
+        }
+    }
+
+    /// Replace ifs with a constant condition by the branch that runs
+    fn prune_constant_branches(&mut self, resolve: &impl Fn(&FunctionRef) -> Option<String>) {
+        match self {
+            StructuredNode::Sequence { nodes } => {
+                for node in nodes {
+                    node.prune_constant_branches(resolve);
+                }
+            }
+            StructuredNode::Conditional {
+                condition,
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                let Some(value) = evaluate_condition(condition, resolve) else {
+                    true_branch.prune_constant_branches(resolve);
+                    if let Some(false_branch) = false_branch {
+                        false_branch.prune_constant_branches(resolve);
+                    }
+                    return;
+                };
+                let pruned = StructuredNode::Pruned {
+                    condition: condition.clone(),
+                    value,
+                };
+                let mut kept = match (value, false_branch.take()) {
+                    (true, _) => std::mem::replace(&mut **true_branch, StructuredNode::Empty),
+                    (false, Some(false_branch)) => *false_branch,
+                    (false, None) => StructuredNode::Empty,
+                };
+                kept.prune_constant_branches(resolve);
+                *self = match kept {
+                    StructuredNode::Empty => pruned,
+                    StructuredNode::Sequence { mut nodes } => {
+                        nodes.insert(0, pruned);
+                        StructuredNode::Sequence { nodes }
+                    }
+                    kept => StructuredNode::Sequence {
+                        nodes: vec![pruned, kept],
+                    },
+                };
+            }
+            StructuredNode::Loop { body, .. } => body.prune_constant_branches(resolve),
+            StructuredNode::Code { .. }
+            | StructuredNode::Break { .. }
+            | StructuredNode::Continue { .. }
+            | StructuredNode::Pruned { .. }
+            | StructuredNode::Empty => {}
+        }
+    }
+
+    /// Blocks a goto left in this node jumps to
+    fn goto_targets(&self, targets: &mut HashSet<BlockId>) {
+        match self {
+            StructuredNode::Sequence { nodes } => {
+                nodes.iter().for_each(|node| node.goto_targets(targets));
+            }
+            StructuredNode::Conditional {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                true_branch.goto_targets(targets);
+                if let Some(false_branch) = false_branch {
+                    false_branch.goto_targets(targets);
+                }
+            }
+            StructuredNode::Loop { body, .. } => body.goto_targets(targets),
+            StructuredNode::Code { block } => match block.terminator {
+                Terminator::Goto { target } => {
+                    targets.insert(target);
+                }
+                Terminator::Branch {
+                    true_target,
+                    false_target,
+                    ..
+                } => {
+                    targets.extend([true_target, false_target]);
+                }
+                _ => {}
+            },
+            StructuredNode::Break { .. }
+            | StructuredNode::Continue { .. }
+            | StructuredNode::Pruned { .. }
+            | StructuredNode::Empty => {}
+        }
+    }
 
     /// Format this node with proper indentation
-    pub fn format(&self, indent_level: usize, address_index: &AddressIndex) {
+    pub fn format(
+        &self,
+        out: &mut dyn Write,
+        indent_level: usize,
+        options: &FormattingOptions,
+        hits: Option<&HitCounts>,
+        context: &FunctionContext,
+    ) -> fmt::Result {
         let indent = "    ".repeat(indent_level);
-        let mut formatter = CppFormatter::new(address_index, Default::default());
+        let renderer = &context.renderer;
 
         match self {
             StructuredNode::Sequence { nodes } => {
                 for node in nodes {
-                    node.format(indent_level, address_index);
+                    node.format(out, indent_level, options, hits, context)?;
                 }
             }
 
@@ -214,15 +582,44 @@ impl StructuredNode {
                 false_branch,
                 condition_block,
             } => {
-                let cond_str = formatter.format_expr_inline(condition, &FormatContext::This);
-                println!("{}// Block {:?}", indent, condition_block);
-                println!("{}if ({}) {{", indent, cond_str);
-                true_branch.format(indent_level + 1, address_index);
+                let cond_str = renderer.format_expr_inline(condition, &FormatContext::This);
+                if options.show_block_ids {
+                    writeln!(out, "{}// {}", indent, context.name(*condition_block))?;
+                }
+                context.write_sequence_pin(
+                    out,
+                    &indent,
+                    context.name(*condition_block).offset(),
+                )?;
+                if let Some(hits) = hits {
+                    let taken = true_branch.hit_count(hits);
+                    let not_taken = false_branch.as_ref().and_then(|b| b.hit_count(hits));
+                    match (taken, not_taken) {
+                        (Some(taken), Some(not_taken)) => writeln!(
+                            out,
+                            "{}{}",
+                            indent,
+                            Theme::comment(format!("// taken {}, not taken {}", taken, not_taken))
+                        ),
+                        (Some(taken), None) => {
+                            writeln!(
+                                out,
+                                "{}{}",
+                                indent,
+                                Theme::comment(format!("// taken {}", taken))
+                            )
+                        }
+                        _ => Ok(()),
+                    }?;
+                }
+                let marker = context.doubt_marker(condition);
+                writeln!(out, "{}{}if ({}) {{", indent, marker, cond_str)?;
+                true_branch.format(out, indent_level + 1, options, hits, context)?;
                 if let Some(false_br) = false_branch {
-                    println!("{}}} else {{", indent);
-                    false_br.format(indent_level + 1, address_index);
+                    writeln!(out, "{}}} else {{", indent)?;
+                    false_br.format(out, indent_level + 1, options, hits, context)?;
                 }
-                println!("{}}}", indent);
+                writeln!(out, "{}}}", indent)?;
             }
 
             StructuredNode::Loop {
@@ -231,54 +628,121 @@ impl StructuredNode {
                 body,
                 header,
             } => {
-                println!("{}// Loop header: Block {:?}", indent, header);
+                if options.show_block_ids {
+                    writeln!(out, "{}// Loop header: {}", indent, context.name(*header))?;
+                }
+                context.write_sequence_pin(out, &indent, context.name(*header).offset())?;
+                if options.show_costs {
+                    let per_iteration = body.cost() + condition.as_ref().map_or(0, expr_cost);
+                    writeln!(out, "{}// cost per iteration: {}", indent, per_iteration)?;
+                }
+                if let Some(bounds) = context.loop_bounds.get(header) {
+                    writeln!(out, "{}{}", indent, Theme::comment(bounds))?;
+                }
                 match loop_type {
                     LoopType::While => {
                         let cond_str = condition
                             .as_ref()
-                            .map(|c| formatter.format_expr_inline(c, &FormatContext::This))
+                            .map(|c| renderer.format_expr_inline(c, &FormatContext::This))
                             .unwrap_or_else(|| "true".to_string());
-                        println!("{}while ({}) {{", indent, cond_str);
-                        body.format(indent_level + 1, address_index);
-                        println!("{}}}", indent);
+                        let marker = condition
+                            .as_ref()
+                            .map(|c| context.doubt_marker(c))
+                            .unwrap_or_default();
+                        writeln!(out, "{}{}while ({}) {{", indent, marker, cond_str)?;
+                        body.format(out, indent_level + 1, options, hits, context)?;
+                        writeln!(out, "{}}}", indent)?;
                     }
                     LoopType::DoWhile => {
-                        println!("{}do {{", indent);
-                        body.format(indent_level + 1, address_index);
+                        writeln!(out, "{}do {{", indent)?;
+                        body.format(out, indent_level + 1, options, hits, context)?;
                         let cond_str = condition
                             .as_ref()
-                            .map(|c| formatter.format_expr_inline(c, &FormatContext::This))
+                            .map(|c| renderer.format_expr_inline(c, &FormatContext::This))
                             .unwrap_or_else(|| "true".to_string());
-                        println!("{}}} while ({});", indent, cond_str);
+                        let marker = condition
+                            .as_ref()
+                            .map(|c| context.doubt_marker(c))
+                            .unwrap_or_default();
+                        writeln!(out, "{}{}}} while ({});", indent, marker, cond_str)?;
                     }
                     LoopType::Endless => {
-                        println!("{}loop {{", indent);
-                        body.format(indent_level + 1, address_index);
-                        println!("{}}}", indent);
+                        let keyword = if options.valid_cpp {
+                            "while (true)"
+                        } else {
+                            "loop"
+                        };
+                        writeln!(out, "{}{} {{", indent, keyword)?;
+                        body.format(out, indent_level + 1, options, hits, context)?;
+                        writeln!(out, "{}}}", indent)?;
                     }
                 }
             }
 
             StructuredNode::Break { target } => {
-                println!("{}break; // to Block {:?}", indent, target);
+                if options.show_block_ids {
+                    writeln!(out, "{}break; // to {}", indent, context.name(*target))?;
+                } else {
+                    writeln!(out, "{}break;", indent)?;
+                }
             }
 
             StructuredNode::Continue { target } => {
-                println!("{}continue; // to Block {:?}", indent, target);
+                if options.show_block_ids {
+                    writeln!(out, "{}continue; // to {}", indent, context.name(*target))?;
+                } else {
+                    writeln!(out, "{}continue;", indent)?;
+                }
             }
 
             StructuredNode::Code { block } => {
-                println!(
-                    "{}// Block {:?} [0x{:X}..0x{:X}]",
-                    indent,
-                    block.id,
-                    block.start_offset.as_usize(),
-                    block.end_offset.as_usize()
-                );
+                match (options.show_block_ids, options.show_bytecode_offsets) {
+                    (true, true) => writeln!(
+                        out,
+                        "{}// {} [{}..{}]",
+                        indent,
+                        context.name(block.id),
+                        block.start_offset,
+                        block.end_offset
+                    ),
+                    (true, false) => writeln!(out, "{}// {}", indent, context.name(block.id)),
+                    (false, _) => Ok(()),
+                }?;
+                if let Some(label) = context.labels.get(&block.id) {
+                    // A label has to be followed by a statement
+                    let empty = block.statements.iter().all(|stmt| {
+                        matches!(
+                            stmt.kind,
+                            super::expr::ExprKind::PushExecutionFlow { .. }
+                                | super::expr::ExprKind::PopExecutionFlow
+                                | super::expr::ExprKind::PopExecutionFlowIfNot { .. }
+                        )
+                    }) && matches!(block.terminator, Terminator::None);
+                    let semicolon = if empty { ";" } else { "" };
+                    writeln!(out, "{}{}:{}", indent, label, semicolon)?;
+                }
+                // Pre-headers share their loop header's offset but run no code
+                if (!block.statements.is_empty() || block.terminator_expr.is_some())
+                    && let Some(count) = hits.and_then(|hits| hits.get(block.start_offset))
+                {
+                    if count > 0 {
+                        writeln!(
+                            out,
+                            "{}{}",
+                            indent,
+                            Theme::covered(format!("// hits: {}", count))
+                        )?;
+                    } else {
+                        writeln!(out, "{}{}", indent, Theme::comment("// never run"))?;
+                    }
+                }
 
-                // Format statements using CppFormatter (skip execution flow control)
-                formatter.set_indent_level(indent_level);
+                // Format statements with a StatementWriter (skip execution flow control)
+                let mut writer = StatementWriter::new(renderer, Default::default())
+                    .with_doubts(context.doubts.take());
+                writer.set_indent_level(indent_level);
                 for stmt in &block.statements {
+                    context.write_sequence_pin(out, &indent, stmt.offset)?;
                     // Skip execution flow control instructions (internal VM state)
                     match &stmt.kind {
                         super::expr::ExprKind::PushExecutionFlow { .. }
@@ -289,32 +753,86 @@ impl StructuredNode {
                         }
                         _ => {}
                     }
-                    formatter.format_statement(stmt);
+                    match (options.show_bytecode_offsets, options.show_costs) {
+                        (true, true) => writer.set_statement_prefix(format!(
+                            "/* {}, cost {} */ ",
+                            stmt.offset,
+                            expr_cost(stmt)
+                        )),
+                        (true, false) => {
+                            writer.set_statement_prefix(format!("/* {} */ ", stmt.offset))
+                        }
+                        (false, true) => {
+                            writer.set_statement_prefix(format!("/* cost {} */ ", expr_cost(stmt)))
+                        }
+                        (false, false) => {}
+                    }
+                    writer.format_statement(out, stmt)?;
+                }
+
+                if let Some(term_expr) = &block.terminator_expr {
+                    context.write_sequence_pin(out, &indent, term_expr.offset)?;
+                }
+                if options.show_terminator_exprs
+                    && let Some(term_expr) = &block.terminator_expr
+                {
+                    writer.set_statement_prefix("// terminator: ".to_string());
+                    writer.format_statement(out, term_expr)?;
                 }
+                context.doubts.replace(writer.into_doubts());
 
                 // Format terminator if present
                 match &block.terminator {
                     Terminator::Goto { target } => {
-                        println!("{}goto Block {:?};", indent, target);
+                        writeln!(
+                            out,
+                            "{}goto {};{}",
+                            indent,
+                            context.label(*target),
+                            context.virtualized_note(block.id, &[*target])
+                        )?;
                     }
                     Terminator::Branch {
                         condition,
                         true_target,
                         false_target,
                     } => {
-                        let cond_str =
-                            formatter.format_expr_inline(condition, &FormatContext::This);
-                        println!(
-                            "{}if ({}) goto Block {:?}; else goto Block {:?};",
-                            indent, cond_str, true_target, false_target
-                        );
+                        let cond_str = renderer.format_expr_inline(condition, &FormatContext::This);
+                        writeln!(
+                            out,
+                            "{}{}if ({}) goto {}; else goto {};{}",
+                            indent,
+                            context.doubt_marker(condition),
+                            cond_str,
+                            context.label(*true_target),
+                            context.label(*false_target),
+                            context.virtualized_note(block.id, &[*true_target, *false_target])
+                        )?;
+                    }
+                    Terminator::DynamicJump if options.valid_cpp => {
+                        writeln!(
+                            out,
+                            "{}{}",
+                            indent,
+                            Theme::comment("// TODO: dynamic jump, C++ has no computed goto")
+                        )?;
                     }
                     Terminator::DynamicJump => {
-                        println!("{}// dynamic jump", indent);
+                        writeln!(out, "{}// dynamic jump", indent)?;
+                    }
+                    Terminator::Return(expr)
+                        if options.valid_cpp
+                            && matches!(expr.kind, ExprKind::Nothing | ExprKind::NothingInt32) =>
+                    {
+                        writeln!(out, "{}return;", indent)?;
                     }
                     Terminator::Return(expr) => {
-                        let ret_str = formatter.format_expr_inline(expr, &FormatContext::This);
-                        println!("{}return {};", indent, ret_str);
+                        let ret_str = renderer.format_expr_inline(expr, &FormatContext::This);
+                        let marker = context.doubt_marker(expr);
+                        writeln!(out, "{}{}return {};", indent, marker, ret_str)?;
+                    }
+                    Terminator::Abort => {
+                        writeln!(out, "{}// assertion failed, execution stops", indent)?;
                     }
                     Terminator::None => {
                         // No terminator - control flow is implicit
@@ -322,166 +840,498 @@ impl StructuredNode {
                 }
             }
 
+            StructuredNode::Pruned { condition, value } => {
+                let cond_str = renderer.format_expr_inline(condition, &FormatContext::This);
+                let removed = if *value { "else branch" } else { "then branch" };
+                let marker = context.doubts.borrow_mut().raise(format!(
+                    "the {} was left out because ({}) looks like it's always {}, judging \
+                     by its literals",
+                    removed, cond_str, value
+                ));
+                writeln!(
+                    out,
+                    "{}{} {}",
+                    indent,
+                    marker,
+                    Theme::comment(format!(
+                        "// pruned: ({}) is always {}, {} removed",
+                        cond_str, value, removed
+                    ))
+                )?;
+            }
+
             StructuredNode::Empty => {}
         }
+        Ok(())
     }
 }
 
-/// The result of structuring: a structured control flow graph
-#[derive(Debug, Clone)]
-pub struct StructuredGraph {
-    /// The root node of the structured graph
-    pub root: StructuredNode,
+/// What printing a structured graph needs to know about the whole function
+pub struct FunctionContext<'a> {
+    names: Vec<BlockName>,
+    /// Labels of the blocks a goto jumps to, which are printed before them
+    labels: HashMap<BlockId, String>,
+    /// Renders the function's expressions, shared by every node
+    renderer: ExprRenderer<'a>,
+    /// Reasons behind the doubt markers printed so far, passed to each
+    /// block's [`StatementWriter`] while it prints
+    doubts: RefCell<Doubts>,
+    /// Where each pin of a Sequence node starts, see [`sequence_pins`]
+    sequence_pins: HashMap<BytecodeOffset, SequencePin>,
+    /// Pins noted so far, as a block can be reached while printing both the
+    /// loop or branch it heads and its own code
+    printed_pins: RefCell<HashSet<BytecodeOffset>>,
+    /// Number of each jump the structurer left as a goto, by (from, to)
+    virtualized_edges: HashMap<(BlockId, BlockId), usize>,
+    /// Range each counting loop runs over, by header, with
+    /// `--show-loop-bounds`
+    loop_bounds: HashMap<BlockId, String>,
 }
 
-impl StructuredGraph {
-    /// Print the structured graph in a human-readable format
-    pub fn print(&self, address_index: &AddressIndex) {
-        println!("Structured Control Flow:");
-        println!();
-        self.root.format(0, address_index);
+impl FunctionContext<'_> {
+    /// Markers for what printing `expr` guesses at, see
+    /// [`StatementWriter::doubt_marker`]
+    fn doubt_marker(&self, expr: &Expr) -> String {
+        let reasons = self.renderer.doubt_reasons(expr);
+        self.doubts.borrow_mut().raise_all(reasons)
     }
-}
 
-impl Region {
-    /// Export the region graph to DOT format for visualization
-    pub fn to_dot(&self) -> String {
-        let mut dot = String::new();
-        dot.push_str("digraph Region {\n");
-        dot.push_str("  node [shape=box, fontname=\"monospace\"];\n");
-        dot.push_str("  rankdir=TB;\n\n");
+    fn name(&self, block: BlockId) -> BlockName {
+        self.names[block.0]
+    }
 
-        // Add nodes
-        for (&node_id, node) in &self.nodes {
-            let node_type = match node {
-                StructuredNode::Sequence { .. } => "Sequence",
-                StructuredNode::Conditional { .. } => "Conditional",
-                StructuredNode::Loop { .. } => "Loop",
-                StructuredNode::Code { .. } => "Code",
-                _ => "Other",
-            };
+    fn label(&self, block: BlockId) -> String {
+        match self.labels.get(&block) {
+            Some(label) => label.clone(),
+            None => self.name(block).to_string(),
+        }
+    }
 
-            let color = if node_id == self.head {
-                "lightgreen"
-            } else {
-                "lightblue"
-            };
+    /// Note pointing a goto left by the structurer back to its edge in the
+    /// function's header comment, or nothing for jumps the bytecode has
+    fn virtualized_note(&self, from: BlockId, targets: &[BlockId]) -> String {
+        targets
+            .iter()
+            .filter_map(|&to| self.virtualized_edges.get(&(from, to)))
+            .map(|number| format!(" {}", Theme::comment(format!("// edge {}", number))))
+            .collect()
+    }
 
-            dot.push_str(&format!(
-                "  n{} [label=\"{:?}\\n{}\", fillcolor={}, style=filled];\n",
-                node_id.0, node_id, node_type, color
-            ));
+    /// Note which Sequence pin starts at `offset`, if one does
+    fn write_sequence_pin(
+        &self,
+        out: &mut dyn Write,
+        indent: &str,
+        offset: BytecodeOffset,
+    ) -> fmt::Result {
+        if let Some(pin) = self.sequence_pins.get(&offset)
+            && self.printed_pins.borrow_mut().insert(offset)
+        {
+            writeln!(
+                out,
+                "{}{}",
+                indent,
+                Theme::comment(format!("// Sequence pin {} of {}", pin.number, pin.count))
+            )?;
         }
+        Ok(())
+    }
+}
 
-        dot.push('\n');
+/// One output pin of a Sequence node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SequencePin {
+    /// Counting from 1, in the order the pins run
+    number: usize,
+    count: usize,
+}
 
-        // Add edges
-        for (&from, succs) in &self.edges {
-            for &to in succs {
-                dot.push_str(&format!("  n{} -> n{};\n", from.0, to.0));
+/// The first statement of each pin of the graph's Sequence nodes
+///
+/// A Sequence node with N pins compiles to N - 1 `PushExecutionFlow`s in a
+/// row, pushing the later pins' code last pin first. The first pin's code
+/// follows the pushes, and each pin's code pops the flow stack to go on to
+/// the next.
+fn sequence_pins(cfg: &ControlFlowGraph) -> HashMap<BytecodeOffset, SequencePin> {
+    let mut offsets: Vec<BytecodeOffset> = cfg
+        .blocks
+        .iter()
+        .flat_map(|block| block.statements.iter().chain(&block.terminator_expr))
+        .map(|stmt| stmt.offset)
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    let mut pins = HashMap::new();
+    for block in &cfg.blocks {
+        let mut statements = block.statements.iter().peekable();
+        while let Some(stmt) = statements.next() {
+            let ExprKind::PushExecutionFlow { push_offset } = stmt.kind else {
+                continue;
+            };
+            let mut pushed = vec![push_offset];
+            let mut last = stmt.offset;
+            while let Some(next) =
+                statements.next_if(|next| matches!(next.kind, ExprKind::PushExecutionFlow { .. }))
+            {
+                if let ExprKind::PushExecutionFlow { push_offset } = next.kind {
+                    pushed.push(push_offset);
+                }
+                last = next.offset;
+            }
+            let count = pushed.len() + 1;
+            if let Some(&first) = offsets.iter().find(|&&offset| offset > last) {
+                pins.insert(first, SequencePin { number: 1, count });
+            }
+            for (index, offset) in pushed.into_iter().rev().enumerate() {
+                pins.insert(
+                    offset,
+                    SequencePin {
+                        number: index + 2,
+                        count,
+                    },
+                );
             }
         }
+    }
+    pins
+}
 
-        dot.push_str("}\n");
-        dot
+/// The range a counting loop runs over, as `// iterates 0..Count`, with the
+/// limit's value when it's a variable set before the loop
+fn describe_bounds(found: &CountedLoop, renderer: &ExprRenderer) -> String {
+    let render = |expr: &Expr| renderer.format_expr_inline(expr, &FormatContext::This);
+    let start = found.start.map_or_else(|| "?".to_string(), render);
+    let range = if found.inclusive { "..=" } else { ".." };
+    let limit = render(found.limit);
+    let mut bounds = format!("// iterates {}{}{}", start, range, limit);
+    if let Some(value) = found.limit_value {
+        bounds.push_str(&format!(" ({} = {})", limit, render(value)));
     }
+    bounds
 }
 
-/// Working region during structuring
-#[derive(Debug, Clone)]
-struct Region {
-    /// Current graph being structured
-    nodes: HashMap<BlockId, StructuredNode>,
-    /// Edges in the current graph
-    edges: HashMap<BlockId, Vec<BlockId>>,
-    /// Reverse edges (predecessors)
-    predecessors: HashMap<BlockId, Vec<BlockId>>,
-    /// Entry node of this region
+/// Name goto targets after what they are to the code around them, such as
+/// `loop_start` or `after_branch_2`, falling back to the block's name
+///
+/// Kinds with more than one target are numbered in bytecode order.
+fn goto_labels(cfg: &ControlFlowGraph, targets: &HashSet<BlockId>) -> HashMap<BlockId, String> {
+    let names = cfg.block_names();
+    let loop_info = LoopInfo::analyze(cfg, &DominatorTree::compute(cfg));
+    // An ubergraph dispatches to its events with a computed jump, which
+    // leaves each event's first block without predecessors
+    let dispatches = cfg.blocks.iter().any(|block| {
+        block
+            .statements
+            .iter()
+            .any(|stmt| matches!(stmt.kind, ExprKind::ComputedJump { .. }))
+    });
+    let kind = |id: BlockId| {
+        let block = &cfg.blocks[id.0];
+        if dispatches && id != cfg.entry_block && block.predecessors.is_empty() {
+            Some("ubergraph_entry")
+        } else if loop_info
+            .loops
+            .iter()
+            .any(|l| l.header == id || l.preheader == Some(id))
+        {
+            Some("loop_start")
+        } else if loop_info.loops.iter().any(|l| {
+            !l.blocks.contains(&id) && block.predecessors.iter().any(|p| l.blocks.contains(p))
+        }) {
+            Some("loop_end")
+        } else if block
+            .predecessors
+            .iter()
+            .any(|p| matches!(cfg.blocks[p.0].terminator, Terminator::Branch { .. }))
+        {
+            Some("after_branch")
+        } else {
+            None
+        }
+    };
+
+    let mut targets: Vec<BlockId> = targets.iter().copied().collect();
+    targets.sort_by_key(|id| (cfg.blocks[id.0].start_offset, *id));
+    let kinds: Vec<_> = targets.iter().map(|&id| kind(id)).collect();
+    let mut numbers: HashMap<&str, usize> = HashMap::new();
+    targets
+        .iter()
+        .zip(&kinds)
+        .map(|(&id, kind)| {
+            let Some(kind) = *kind else {
+                return (id, names[id.0].to_string());
+            };
+            let number = numbers.entry(kind).or_default();
+            *number += 1;
+            let label = match kinds.iter().filter(|k| **k == Some(kind)).count() {
+                1 => kind.to_string(),
+                _ => format!("{}_{}", kind, number),
+            };
+            (id, label)
+        })
+        .collect()
+}
+
+const NOT_PRE_BOOL: &str = "/Script/Engine.KismetMathLibrary:Not_PreBool";
+
+/// `!condition`, dropping a negation instead of doubling it
+fn negate(condition: Expr) -> Expr {
+    if let ExprKind::CallMath {
+        func: FunctionRef::ByName(name),
+        params,
+    } = &condition.kind
+        && name.matches(NOT_PRE_BOOL)
+        && let [operand] = params.as_slice()
+    {
+        return operand.clone();
+    }
+    let end = condition.end;
+    Expr::new(
+        condition.offset,
+        ExprKind::CallMath {
+            func: FunctionRef::ByName(Name::new(NOT_PRE_BOOL)),
+            params: vec![condition],
+        },
+    )
+    .with_end(end)
+}
+
+/// Sort the cases of a switch statement by how often each one's result ran,
+/// hottest first; cases the profile doesn't cover keep their place after them
+fn order_switch_cases(stmt: &mut Expr, hits: &HitCounts) {
+    let ExprKind::SwitchValue {
+        index,
+        cases,
+        default,
+        end_offset,
+    } = &stmt.kind
+    else {
+        return;
+    };
+    let count = |case: &SwitchCase| hits.get(case.result.offset);
+    if cases.iter().all(|case| count(case).is_none()) {
+        return;
+    }
+    let mut cases = cases.clone();
+    cases.sort_by_key(|case| std::cmp::Reverse(count(case)));
+    *stmt = Expr::new(
+        stmt.offset,
+        ExprKind::SwitchValue {
+            index: index.clone(),
+            cases,
+            default: default.clone(),
+            end_offset: *end_offset,
+        },
+    )
+    .with_end(stmt.end);
+}
+
+/// The result of structuring: a structured control flow graph
+#[derive(Debug, Clone)]
+pub struct StructuredGraph<B: BlockCode = BasicBlock> {
+    /// The root node of the structured graph
+    pub root: StructuredNode<B>,
+    /// Jumps left as gotos so the code around them could be structured, as
+    /// (block ending with the jump, target)
+    pub virtualized_edges: Vec<(BlockId, BlockId)>,
+}
+
+impl StructuredGraph {
+    /// Write the structured graph in a human-readable format
+    ///
+    /// `cfg` is the graph this was structured from, for naming blocks;
+    /// `function` is the path of the function the graph is for, if known.
+    pub fn write(
+        &self,
+        out: &mut dyn Write,
+        cfg: &ControlFlowGraph,
+        address_index: &AddressIndex,
+        options: &FormattingOptions,
+        hits: Option<&HitCounts>,
+        function: Option<&str>,
+    ) -> fmt::Result {
+        let _span = info_span!("format").entered();
+        writeln!(out, "Structured Control Flow:")?;
+        writeln!(out)?;
+        let names = cfg.block_names();
+        if !self.virtualized_edges.is_empty() {
+            let summary = match self.virtualized_edges.len() {
+                1 => "1 jump left as a goto so the code around it".to_string(),
+                count => format!("{} jumps left as gotos so the code around them", count),
+            };
+            writeln!(
+                out,
+                "{}",
+                Theme::comment(format!("// {} could be structured:", summary))
+            )?;
+            for (index, &(from, to)) in self.virtualized_edges.iter().enumerate() {
+                let block = &cfg.blocks[from.0];
+                let jump = match &block.terminator_expr {
+                    Some(expr) => format!("jump at {}", expr.offset),
+                    None => format!("falls through at {}", block.end_offset),
+                };
+                writeln!(
+                    out,
+                    "{}",
+                    Theme::comment(format!(
+                        "//   edge {}: {} -> {}, {}",
+                        index + 1,
+                        names[from.0],
+                        names[to.0],
+                        jump
+                    ))
+                )?;
+            }
+        }
+        let resolve = |func: &FunctionRef| match func {
+            FunctionRef::ByName(name) => Some(name.as_str().to_string()),
+            FunctionRef::ByAddress(address) => address_index
+                .resolve_object(*address)
+                .map(|object| object.path.to_string()),
+        };
+        let ubergraph_entry = cfg
+            .blocks
+            .iter()
+            .find_map(|block| ubergraph_entry(&block.statements, resolve));
+        let mut goto_targets = HashSet::new();
+        self.root.goto_targets(&mut goto_targets);
+        let mut renderer = ExprRenderer::new(address_index)
+            .with_options(*options)
+            .with_ubergraph_entry(ubergraph_entry);
+        if let Some(function) = function {
+            renderer = renderer.with_function(function);
+        }
+        let mut counted_loops = HashMap::new();
+        if options.show_loop_bounds {
+            self.root.counted_loops(&[], &resolve, &mut counted_loops);
+        }
+        let loop_bounds = counted_loops
+            .into_iter()
+            .map(|(header, found)| (header, describe_bounds(&found, &renderer)))
+            .collect();
+        let context = FunctionContext {
+            names,
+            labels: goto_labels(cfg, &goto_targets),
+            renderer,
+            doubts: RefCell::default(),
+            sequence_pins: sequence_pins(cfg),
+            printed_pins: RefCell::default(),
+            virtualized_edges: self
+                .virtualized_edges
+                .iter()
+                .enumerate()
+                .map(|(index, &edge)| (edge, index + 1))
+                .collect(),
+            loop_bounds,
+        };
+        self.root.format(out, 0, options, hits, &context)?;
+        if options.explain {
+            context.doubts.borrow().explain(out)?;
+        }
+        Ok(())
+    }
+
+    /// Put the side of each if/else that ran more often first, and the
+    /// cases of each switch statement in order of how often they ran
+    pub fn order_by_hits(&mut self, hits: &HitCounts) {
+        self.root.order_by_hits(hits);
+    }
+
+    /// Drop the branches of ifs whose condition only depends on literals,
+    /// leaving a note for each
+    ///
+    /// `resolve` gives the path of a called function, for recognizing
+    /// operators.
+    pub fn prune_constant_branches(&mut self, resolve: impl Fn(&FunctionRef) -> Option<String>) {
+        self.root.prune_constant_branches(&resolve);
+    }
+}
+
+impl<B: BlockCode> Region<B> {
+    /// Export the region graph to DOT format for visualization
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph Region {\n");
+        dot.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+        dot.push_str("  rankdir=TB;\n\n");
+
+        // Add nodes
+        for (&node_id, node) in &self.nodes {
+            let node_type = match node {
+                StructuredNode::Sequence { .. } => "Sequence",
+                StructuredNode::Conditional { .. } => "Conditional",
+                StructuredNode::Loop { .. } => "Loop",
+                StructuredNode::Code { .. } => "Code",
+                _ => "Other",
+            };
+
+            let color = if node_id == self.head {
+                "lightgreen"
+            } else {
+                "lightblue"
+            };
+
+            dot.push_str(&format!(
+                "  n{} [label=\"{:?}\\n{}\", fillcolor={}, style=filled];\n",
+                node_id.0, node_id, node_type, color
+            ));
+        }
+
+        dot.push('\n');
+
+        // Add edges
+        for (&from, succs) in &self.edges {
+            for &to in succs {
+                dot.push_str(&format!("  n{} -> n{};\n", from.0, to.0));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Working region during structuring
+#[derive(Debug, Clone)]
+struct Region<B: BlockCode> {
+    /// Current graph being structured
+    nodes: HashMap<BlockId, StructuredNode<B>>,
+    /// Edges in the current graph
+    edges: HashMap<BlockId, Vec<BlockId>>,
+    /// Reverse edges (predecessors)
+    predecessors: HashMap<BlockId, Vec<BlockId>>,
+    /// Entry node of this region
     head: BlockId,
 }
 
-impl Region {
-    fn new(cfg: &ControlFlowGraph) -> Self {
-        // Initialize region from CFG
+impl<B: BlockCode> Region<B> {
+    fn new(graph: &impl CodeGraph<Block = B>) -> Self {
+        // Initialize region from the graph
         let mut nodes = HashMap::new();
         let mut edges = HashMap::new();
         let mut predecessors = HashMap::new();
 
-        for block in &cfg.blocks {
-            nodes.insert(block.id, StructuredNode::code(block.clone()));
-            edges.insert(block.id, block.successors.clone());
-            predecessors.insert(block.id, block.predecessors.clone());
+        for id in graph.nodes() {
+            nodes.insert(id, StructuredNode::code(graph.block(id).clone()));
+            edges.insert(id, graph.successors(id).to_vec());
+            predecessors.insert(id, graph.predecessors(id).to_vec());
         }
 
         Self {
             nodes,
             edges,
             predecessors,
-            head: cfg.entry_block,
-        }
-    }
-
-    /// Check if the region graph has cycles
-    fn has_cycles(&self) -> bool {
-        // Simple DFS-based cycle detection
-        let mut visited = HashSet::new();
-        let mut rec_stack = HashSet::new();
-
-        fn has_cycle_util(
-            node: BlockId,
-            visited: &mut HashSet<BlockId>,
-            rec_stack: &mut HashSet<BlockId>,
-            edges: &HashMap<BlockId, Vec<BlockId>>,
-        ) -> bool {
-            visited.insert(node);
-            rec_stack.insert(node);
-
-            if let Some(successors) = edges.get(&node) {
-                for &succ in successors {
-                    if !visited.contains(&succ) {
-                        if has_cycle_util(succ, visited, rec_stack, edges) {
-                            return true;
-                        }
-                    } else if rec_stack.contains(&succ) {
-                        return true;
-                    }
-                }
-            }
-
-            rec_stack.remove(&node);
-            false
-        }
-
-        for &node in self.nodes.keys() {
-            if !visited.contains(&node)
-                && has_cycle_util(node, &mut visited, &mut rec_stack, &self.edges)
-            {
-                return true;
-            }
+            head: graph.entry(),
         }
-
-        false
     }
 
     fn len(&self) -> usize {
         self.nodes.len()
     }
 
-    /// Get successors of a node
-    fn successors(&self, node: BlockId) -> &[BlockId] {
-        self.edges.get(&node).map(|v| v.as_slice()).unwrap_or(&[])
-    }
-
-    /// Get predecessors of a node
-    fn predecessors(&self, node: BlockId) -> &[BlockId] {
-        self.predecessors
-            .get(&node)
-            .map(|v| v.as_slice())
-            .unwrap_or(&[])
-    }
-
     /// Replace a node with a new node
-    fn replace_node(&mut self, old: BlockId, new_id: BlockId, new_node: StructuredNode) {
+    fn replace_node(&mut self, old: BlockId, new_id: BlockId, new_node: StructuredNode<B>) {
         // Update nodes map
         self.nodes.remove(&old);
         self.nodes.insert(new_id, new_node);
@@ -536,55 +1386,118 @@ impl Region {
     }
 }
 
+impl<B: BlockCode> FlowGraph for Region<B> {
+    fn entry(&self) -> BlockId {
+        self.head
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = BlockId> + '_ {
+        self.nodes.keys().copied()
+    }
+
+    fn successors(&self, node: BlockId) -> &[BlockId] {
+        self.edges.get(&node).map_or(&[], Vec::as_slice)
+    }
+
+    fn predecessors(&self, node: BlockId) -> &[BlockId] {
+        self.predecessors.get(&node).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Structuring was abandoned because it ran past its deadline
+#[derive(Debug, Clone, Copy, Error)]
+#[error("structuring timed out after {iterations} iterations")]
+pub struct StructuringTimedOut {
+    /// Refinement iterations completed before giving up
+    pub iterations: usize,
+}
+
 /// Phoenix-based control flow structuring algorithm
-pub struct PhoenixStructurer<'a> {
+pub struct PhoenixStructurer<'a, B: BlockCode = BasicBlock> {
     loop_info: &'a LoopInfo,
-    region: Region,
+    region: Region<B>,
     /// Edges that should not be removed during refinement
     protected_edges: HashSet<(BlockId, BlockId)>,
     /// Logger for debug output
     logger: &'a dyn Logger,
+    /// Point after which structuring is abandoned
+    deadline: Option<Instant>,
+    /// Edges taken out of the region to make progress, see
+    /// [`PhoenixStructurer::virtualize_edge`]
+    virtualized_edges: Vec<(BlockId, BlockId)>,
 }
 
-impl<'a> PhoenixStructurer<'a> {
+/// Most jumps [`PhoenixStructurer`] leaves as gotos before giving up on
+/// structuring the rest of a function
+const MAX_VIRTUALIZED_EDGES: usize = 16;
+
+impl<'a, B: BlockCode> PhoenixStructurer<'a, B> {
     /// Create a new structurer (uses NullLogger by default)
-    pub fn new(cfg: &'a ControlFlowGraph, loop_info: &'a LoopInfo) -> Self {
-        Self::new_with_logger(cfg, loop_info, &NullLogger)
+    pub fn new(graph: &impl CodeGraph<Block = B>, loop_info: &'a LoopInfo) -> Self {
+        Self::new_with_logger(graph, loop_info, &NullLogger)
     }
 
     /// Create a new structurer with a custom logger
     pub fn new_with_logger(
-        cfg: &'a ControlFlowGraph,
+        graph: &impl CodeGraph<Block = B>,
         loop_info: &'a LoopInfo,
         logger: &'a dyn Logger,
     ) -> Self {
-        let region = Region::new(cfg);
+        let region = Region::new(graph);
         Self {
             loop_info,
             region,
             protected_edges: HashSet::new(),
             logger,
+            deadline: None,
+            virtualized_edges: Vec::new(),
         }
     }
 
+    /// Give up structuring once this deadline passes
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
     /// Main structuring algorithm
-    pub fn structure(mut self) -> Option<StructuredGraph> {
+    pub fn structure(self) -> Option<StructuredGraph<B>> {
+        self.try_structure().unwrap_or(None)
+    }
+
+    /// Structure the CFG, failing if the deadline passes first
+    pub fn try_structure(mut self) -> Result<Option<StructuredGraph<B>>, KismetError> {
+        let _span = info_span!("structure").entered();
         const MAX_ITERATIONS: usize = 1000;
         let mut iteration = 0;
 
         while self.region.len() > 1 && iteration < MAX_ITERATIONS {
+            self.check_deadline(iteration)?;
+
             iteration += 1;
 
-            let has_cycles = self.region.has_cycles();
+            let has_cycles = has_cycle(&self.region);
 
             // Try acyclic schema matching
             let mut progress = self.analyze_acyclic();
 
             // Try cyclic schema matching if we have cycles
-            if has_cycles {
+            if has_cycles && !self.past_deadline() {
                 progress |= self.analyze_cyclic();
             }
 
+            // The passes stop early once the deadline passes
+            self.check_deadline(iteration)?;
+
+            // Last resort: turn a jump into a multiple-entry region into a
+            // goto, so what's left can still be matched
+            if !progress
+                && self.virtualized_edges.len() < MAX_VIRTUALIZED_EDGES
+                && self.virtualize_edge()
+            {
+                continue;
+            }
+
             if !progress {
                 // Debug: show current state
                 self.print_region_state();
@@ -611,8 +1524,11 @@ impl<'a> PhoenixStructurer<'a> {
 
         // Return the final result
         if self.region.len() == 1 {
-            let root = self.region.nodes.values().next().cloned()?;
-            Some(StructuredGraph { root })
+            let root = self.region.nodes.values().next().cloned();
+            Ok(root.map(|root| StructuredGraph {
+                root,
+                virtualized_edges: self.virtualized_edges,
+            }))
         } else {
             self.logger.warn(&format!(
                 "Could not fully structure the CFG ({} nodes remain)",
@@ -631,7 +1547,7 @@ impl<'a> PhoenixStructurer<'a> {
                 .collect();
 
             if remaining_nodes.is_empty() {
-                return None;
+                return Ok(None);
             }
 
             // Return all remaining nodes as a sequence
@@ -644,18 +1560,114 @@ impl<'a> PhoenixStructurer<'a> {
                 }
             };
 
-            Some(StructuredGraph { root })
+            Ok(Some(StructuredGraph {
+                root,
+                virtualized_edges: self.virtualized_edges,
+            }))
+        }
+    }
+
+    fn past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Fail once the deadline has passed, after `iteration` iterations
+    fn check_deadline(&self, iteration: usize) -> Result<(), KismetError> {
+        if !self.past_deadline() {
+            return Ok(());
+        }
+        self.logger.warn(&format!(
+            "Structuring timed out after {} iterations, {} nodes remaining",
+            iteration,
+            self.region.len()
+        ));
+        Err(StructuringTimedOut {
+            iterations: iteration,
         }
+        .into())
+    }
+
+    /// Take one jump into a region with several entries out of the graph,
+    /// leaving it to be printed as a goto
+    ///
+    /// Regions are tried cheapest first, then tail first: the node with the
+    /// fewest extra entries, latest in the bytecode. Its entry from the
+    /// nearest block is kept, and a conditional jump from furthest away is
+    /// cut; unconditional ones usually join the arms of an if. Jumps that
+    /// close a loop are left to loop matching.
+    fn virtualize_edge(&mut self) -> bool {
+        let mut targets: Vec<BlockId> = self
+            .region
+            .nodes
+            .keys()
+            .copied()
+            .filter(|&id| self.region.predecessors(id).len() > 1)
+            .collect();
+        targets.sort_by_key(|&id| (self.region.predecessors(id).len(), std::cmp::Reverse(id)));
+        for to in targets {
+            let mut sources = self.region.predecessors(to).to_vec();
+            sources.sort_by_key(|&from| from.0.abs_diff(to.0));
+            // The nearest entry stays, a goto to the next block would be noise
+            for &from in sources.iter().skip(1).rev() {
+                if self.region.successors(from).len() > 1
+                    && !reaches(&self.region, to, from)
+                    && let Some(jump) = self.make_goto(from, to)
+                {
+                    self.logger.info(&format!(
+                        "virtualized edge {:?} -> {:?} into a goto",
+                        jump, to
+                    ));
+                    self.region.remove_edge(from, to);
+                    self.virtualized_edges.push((jump, to));
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Make sure node `from` ends with an explicit jump to `to`, returning
+    /// the block the jump is in, or `None` if the jump is inside structured
+    /// code and can't be printed
+    fn make_goto(&mut self, from: BlockId, to: BlockId) -> Option<BlockId> {
+        fn last_block<B: BlockCode>(node: &mut StructuredNode<B>) -> Option<&mut B> {
+            match node {
+                StructuredNode::Code { block } => Some(block),
+                StructuredNode::Sequence { nodes } => nodes.last_mut().and_then(last_block),
+                _ => None,
+            }
+        }
+        let falls_through = self.region.successors(from) == [to];
+        let block = self.region.nodes.get_mut(&from).and_then(last_block)?;
+        let jumps = match block.terminator() {
+            Terminator::Goto { target } => *target == to,
+            Terminator::Branch {
+                true_target,
+                false_target,
+                ..
+            } => *true_target == to || *false_target == to,
+            Terminator::None if falls_through => {
+                *block.terminator_mut() = Terminator::Goto { target: to };
+                true
+            }
+            _ => false,
+        };
+        jumps.then_some(block.id())
     }
 
     /// Analyze acyclic patterns
     fn analyze_acyclic(&mut self) -> bool {
+        let _span = info_span!("structure_acyclic").entered();
         let mut any_match = false;
 
         // Get nodes in postorder (children before parents) so inner loops are structured first
-        let nodes = self.get_postorder();
+        let nodes = postorder(&self.region);
 
         for node_id in nodes {
+            if self.past_deadline() {
+                break;
+            }
             if !self.region.nodes.contains_key(&node_id) {
                 continue;
             }
@@ -678,24 +1690,29 @@ impl<'a> PhoenixStructurer<'a> {
 
     /// Analyze cyclic patterns (loops)
     fn analyze_cyclic(&mut self) -> bool {
+        let _span = info_span!("structure_cyclic").entered();
         let mut any_match = false;
 
         // Get nodes in postorder (children before parents) so inner loops are structured first
-        let nodes = self.get_postorder();
+        let nodes = postorder(&self.region);
 
         for node_id in nodes {
+            if self.past_deadline() {
+                break;
+            }
             if !self.region.nodes.contains_key(&node_id) {
                 continue;
             }
 
-            // Try while loop pattern
-            if self.match_while_loop(node_id) {
+            // Try do-while loop pattern first, so a loop tested at its latch
+            // doesn't become an endless loop with a break
+            if self.match_dowhile_loop(node_id) {
                 any_match = true;
                 break;
             }
 
-            // Try do-while loop pattern
-            if self.match_dowhile_loop(node_id) {
+            // Try while loop pattern
+            if self.match_while_loop(node_id) {
                 any_match = true;
                 break;
             }
@@ -710,35 +1727,6 @@ impl<'a> PhoenixStructurer<'a> {
         any_match
     }
 
-    /// Get nodes in DFS postorder (children before parents)
-    /// This ensures inner loops are structured before outer loops
-    fn get_postorder(&self) -> Vec<BlockId> {
-        let mut visited = HashSet::new();
-        let mut postorder = Vec::new();
-
-        fn dfs(
-            node: BlockId,
-            region: &Region,
-            visited: &mut HashSet<BlockId>,
-            postorder: &mut Vec<BlockId>,
-        ) {
-            if visited.contains(&node) || !region.nodes.contains_key(&node) {
-                return;
-            }
-            visited.insert(node);
-
-            for &succ in region.successors(node) {
-                dfs(succ, region, visited, postorder);
-            }
-
-            postorder.push(node);
-        }
-
-        dfs(self.region.head, &self.region, &mut visited, &mut postorder);
-        // Return postorder (children before parents) so inner loops are structured first
-        postorder
-    }
-
     /// Match sequence pattern: A -> B where B has only one predecessor (A)
     fn match_sequence(&mut self, node_id: BlockId) -> bool {
         let succs = self.region.successors(node_id).to_vec();
@@ -825,7 +1813,7 @@ impl<'a> PhoenixStructurer<'a> {
 
         let (condition, true_target, false_target) = match node {
             StructuredNode::Code { block } => {
-                match &block.terminator {
+                match block.terminator() {
                     Terminator::Branch {
                         condition,
                         true_target,
@@ -836,7 +1824,7 @@ impl<'a> PhoenixStructurer<'a> {
             }
             StructuredNode::Sequence { nodes } => {
                 if let Some(StructuredNode::Code { block }) = nodes.last() {
-                    match &block.terminator {
+                    match block.terminator() {
                         Terminator::Branch {
                             condition,
                             true_target,
@@ -856,6 +1844,20 @@ impl<'a> PhoenixStructurer<'a> {
             node_id, true_target, false_target
         ));
 
+        // A loop header branching out of its loop is the loop's condition,
+        // which the cyclic schemas have to see
+        if let Some(header_loop) = self.loop_info.loops.iter().find(|l| l.header == node_id)
+            && header_loop.blocks.contains(&true_target)
+                != header_loop.blocks.contains(&false_target)
+        {
+            return false;
+        }
+
+        // Likewise a test at the end of a loop body is a do-while's condition
+        if self.latch_test_exit(node_id).is_some() {
+            return false;
+        }
+
         // Verify that the targets are in our successor list
         if !succs.contains(&true_target) || !succs.contains(&false_target) {
             self.logger.debug(&format!(
@@ -955,13 +1957,7 @@ impl<'a> PhoenixStructurer<'a> {
             let mut false_br = self.region.nodes.get(&false_target).cloned().unwrap();
             // Strip goto from false branch to true branch (merge point)
             false_br = Self::strip_implicit_goto(false_br, true_target, self.logger);
-            let inverted_condition = Expr::new(
-                condition.offset,
-                super::expr::ExprKind::VirtualFunction {
-                    func: super::refs::FunctionRef::ByName(super::types::Name::new("not")),
-                    params: vec![condition.clone()],
-                },
-            );
+            let inverted_condition = B::negate(condition.clone());
             (inverted_condition, false_br, None)
         } else if let Some(merge) = merge_point {
             // Normal case with merge point: strip gotos to merge from both branches
@@ -981,10 +1977,10 @@ impl<'a> PhoenixStructurer<'a> {
         // Since CFG analysis already separates statements from terminators,
         // we just need to preserve block.statements if they exist
         let statements_node = match node {
-            StructuredNode::Code { block } if !block.statements.is_empty() => {
+            StructuredNode::Code { block } if !block.is_empty() => {
                 // Create a Code node with just the statements (no terminator)
                 let mut stmt_block = block.clone();
-                stmt_block.terminator = Terminator::None;
+                *stmt_block.terminator_mut() = Terminator::None;
                 Some(StructuredNode::Code { block: stmt_block })
             }
             StructuredNode::Sequence { nodes } => {
@@ -1100,10 +2096,10 @@ impl<'a> PhoenixStructurer<'a> {
 
     /// Strip implicit terminators from a node (gotos that are now represented by structured control flow)
     fn strip_implicit_goto(
-        node: StructuredNode,
+        node: StructuredNode<B>,
         implicit_target: BlockId,
         logger: &dyn Logger,
-    ) -> StructuredNode {
+    ) -> StructuredNode<B> {
         StructuredNode::strip_implicit_goto_helper(node, implicit_target, logger)
     }
 
@@ -1111,14 +2107,14 @@ impl<'a> PhoenixStructurer<'a> {
     /// (back edges to header, gotos to other blocks in the loop)
     fn strip_loop_internal_terminators(
         &self,
-        node: StructuredNode,
+        node: StructuredNode<B>,
         loop_header: BlockId,
         loop_info: &super::loops::Loop,
-    ) -> StructuredNode {
+    ) -> StructuredNode<B> {
         match node {
             StructuredNode::Code { mut block } => {
                 // Check if the terminator is internal to the loop
-                let should_strip = match &block.terminator {
+                let should_strip = match block.terminator() {
                     Terminator::Goto { target } => {
                         // Strip if it's a back edge to header or goto to another loop block
                         *target == loop_header || loop_info.blocks.contains(target)
@@ -1134,9 +2130,10 @@ impl<'a> PhoenixStructurer<'a> {
                     // Replace with no terminator - control flow is implicit in the loop
                     self.logger.debug(&format!(
                         "  Stripping loop-internal terminator from block {:?}: {:?}",
-                        block.id, block.terminator
+                        block.id(),
+                        block.terminator()
                     ));
-                    block.terminator = Terminator::None;
+                    *block.terminator_mut() = Terminator::None;
                 }
 
                 StructuredNode::Code { block }
@@ -1158,14 +2155,14 @@ impl<'a> PhoenixStructurer<'a> {
 
     /// Extract statements from a header block (before the terminator)
     /// Returns None if the block has no statements or is already structured
-    fn extract_header_statements(&self, node_id: BlockId) -> Option<StructuredNode> {
+    fn extract_header_statements(&self, node_id: BlockId) -> Option<StructuredNode<B>> {
         let node = self.region.nodes.get(&node_id)?;
 
         match node {
-            StructuredNode::Code { block } if !block.statements.is_empty() => {
+            StructuredNode::Code { block } if !block.is_empty() => {
                 // Create a Code node with just the statements (no terminator)
                 let mut stmt_block = block.clone();
-                stmt_block.terminator = Terminator::None;
+                *stmt_block.terminator_mut() = Terminator::None;
                 Some(StructuredNode::Code { block: stmt_block })
             }
             StructuredNode::Sequence { nodes } => {
@@ -1178,10 +2175,10 @@ impl<'a> PhoenixStructurer<'a> {
                 } else if nodes.len() == 1 {
                     // Recursively extract from the single node
                     if let StructuredNode::Code { block } = &nodes[0]
-                        && !block.statements.is_empty()
+                        && !block.is_empty()
                     {
                         let mut stmt_block = block.clone();
-                        stmt_block.terminator = Terminator::None;
+                        *stmt_block.terminator_mut() = Terminator::None;
                         return Some(StructuredNode::Code { block: stmt_block });
                     }
                     None
@@ -1198,11 +2195,11 @@ impl<'a> PhoenixStructurer<'a> {
     /// - Jumps to loop exit → Break
     /// - Other jumps preserved as goto
     fn rewrite_loop_jumps(
-        loop_node: StructuredNode,
+        loop_node: StructuredNode<B>,
         loop_header: BlockId,
         loop_exit: BlockId,
         logger: &dyn Logger,
-    ) -> StructuredNode {
+    ) -> StructuredNode<B> {
         match loop_node {
             StructuredNode::Loop {
                 loop_type,
@@ -1226,11 +2223,11 @@ impl<'a> PhoenixStructurer<'a> {
 
     /// Rewrite jumps inside a loop with multiple exits (endless loops)
     fn rewrite_loop_jumps_multi_exit(
-        loop_node: StructuredNode,
+        loop_node: StructuredNode<B>,
         loop_header: BlockId,
         loop_exits: &[BlockId],
         logger: &dyn Logger,
-    ) -> StructuredNode {
+    ) -> StructuredNode<B> {
         match loop_node {
             StructuredNode::Loop {
                 loop_type,
@@ -1254,16 +2251,16 @@ impl<'a> PhoenixStructurer<'a> {
 
     /// Recursively rewrite jumps in a structured node tree (multi-exit version)
     fn rewrite_jumps_in_node_multi_exit(
-        node: StructuredNode,
+        node: StructuredNode<B>,
         loop_header: BlockId,
         loop_exits: &[BlockId],
         logger: &dyn Logger,
-    ) -> StructuredNode {
+    ) -> StructuredNode<B> {
         match node {
             StructuredNode::Code { mut block } => {
                 // Check if the terminator should be rewritten
                 // Clone values we need before mutating
-                let target_opt = match &block.terminator {
+                let target_opt = match block.terminator() {
                     Terminator::Goto { target } => Some(*target),
                     _ => None,
                 };
@@ -1272,7 +2269,7 @@ impl<'a> PhoenixStructurer<'a> {
                     if target == loop_header {
                         // Jump to loop header → Continue
                         logger.debug(&format!("  Rewriting goto {:?} → continue", target));
-                        block.terminator = Terminator::None;
+                        *block.terminator_mut() = Terminator::None;
                         return StructuredNode::Sequence {
                             nodes: vec![
                                 StructuredNode::Code { block },
@@ -1284,7 +2281,7 @@ impl<'a> PhoenixStructurer<'a> {
                     } else if loop_exits.contains(&target) {
                         // Jump to any loop exit → Break
                         logger.debug(&format!("  Rewriting goto {:?} → break", target));
-                        block.terminator = Terminator::None;
+                        *block.terminator_mut() = Terminator::None;
                         return StructuredNode::Sequence {
                             nodes: vec![
                                 StructuredNode::Code { block },
@@ -1299,13 +2296,13 @@ impl<'a> PhoenixStructurer<'a> {
                     condition,
                     true_target,
                     false_target,
-                } = &block.terminator
+                } = block.terminator()
                 {
                     // Clone values we need before mutating
                     let true_tgt = *true_target;
                     let false_tgt = *false_target;
                     let cond = condition.clone();
-                    let block_id = block.id;
+                    let block_id = block.id();
 
                     // Check if either branch goes to loop header or exit
                     let true_is_continue = true_tgt == loop_header;
@@ -1330,7 +2327,7 @@ impl<'a> PhoenixStructurer<'a> {
                             && (false_is_continue || false_is_break)
                         {
                             // Both branches are break/continue - rewrite fully
-                            block.terminator = Terminator::None;
+                            *block.terminator_mut() = Terminator::None;
 
                             let true_node = if true_is_continue {
                                 StructuredNode::Continue {
@@ -1408,16 +2405,16 @@ impl<'a> PhoenixStructurer<'a> {
 
     /// Recursively rewrite jumps in a structured node tree
     fn rewrite_jumps_in_node(
-        node: StructuredNode,
+        node: StructuredNode<B>,
         loop_header: BlockId,
         loop_exit: BlockId,
         logger: &dyn Logger,
-    ) -> StructuredNode {
+    ) -> StructuredNode<B> {
         match node {
             StructuredNode::Code { mut block } => {
                 // Check if the terminator should be rewritten
                 // Clone values we need before mutating
-                let target_opt = match &block.terminator {
+                let target_opt = match block.terminator() {
                     Terminator::Goto { target } => Some(*target),
                     _ => None,
                 };
@@ -1426,7 +2423,7 @@ impl<'a> PhoenixStructurer<'a> {
                     if target == loop_header {
                         // Jump to loop header → Continue
                         logger.debug(&format!("  Rewriting goto {:?} → continue", target));
-                        block.terminator = Terminator::None;
+                        *block.terminator_mut() = Terminator::None;
                         return StructuredNode::Sequence {
                             nodes: vec![
                                 StructuredNode::Code { block },
@@ -1438,7 +2435,7 @@ impl<'a> PhoenixStructurer<'a> {
                     } else if target == loop_exit {
                         // Jump to loop exit → Break
                         logger.debug(&format!("  Rewriting goto {:?} → break", target));
-                        block.terminator = Terminator::None;
+                        *block.terminator_mut() = Terminator::None;
                         return StructuredNode::Sequence {
                             nodes: vec![
                                 StructuredNode::Code { block },
@@ -1453,13 +2450,13 @@ impl<'a> PhoenixStructurer<'a> {
                     condition,
                     true_target,
                     false_target,
-                } = &block.terminator
+                } = block.terminator()
                 {
                     // Clone values we need before mutating
                     let true_tgt = *true_target;
                     let false_tgt = *false_target;
                     let cond = condition.clone();
-                    let block_id = block.id;
+                    let block_id = block.id();
 
                     // Check if either branch goes to loop header or exit
                     let true_is_continue = true_tgt == loop_header;
@@ -1482,7 +2479,7 @@ impl<'a> PhoenixStructurer<'a> {
                         if (true_is_continue || true_is_break)
                             && (false_is_continue || false_is_break)
                         {
-                            block.terminator = Terminator::None;
+                            *block.terminator_mut() = Terminator::None;
 
                             let true_node = if true_is_continue {
                                 StructuredNode::Continue {
@@ -1586,12 +2583,14 @@ impl<'a> PhoenixStructurer<'a> {
         }
 
         // Determine which successor is inside the loop and which is outside
-        let (_body_succ, exit_succ) = if loop_info.blocks.contains(&succs[0]) {
-            (succs[0], succs[1])
-        } else if loop_info.blocks.contains(&succs[1]) {
-            (succs[1], succs[0])
-        } else {
-            return false; // Neither successor is in the loop body?
+        let (_body_succ, exit_succ) = match (
+            loop_info.blocks.contains(&succs[0]),
+            loop_info.blocks.contains(&succs[1]),
+        ) {
+            (true, false) => (succs[0], succs[1]),
+            (false, true) => (succs[1], succs[0]),
+            // Both stay in the loop, so the header isn't its test
+            _ => return false,
         };
 
         // Extract header statements (before the branch terminator)
@@ -1602,14 +2601,14 @@ impl<'a> PhoenixStructurer<'a> {
         let node = self.region.nodes.get(&node_id).unwrap();
 
         let condition = match node {
-            StructuredNode::Code { block } => match &block.terminator {
+            StructuredNode::Code { block } => match block.terminator() {
                 Terminator::Branch { condition, .. } => condition.clone(),
                 _ => return false,
             },
             StructuredNode::Sequence { nodes } => {
                 // The last node in the sequence might have the branch
                 if let Some(StructuredNode::Code { block }) = nodes.last() {
-                    match &block.terminator {
+                    match block.terminator() {
                         Terminator::Branch { condition, .. } => condition.clone(),
                         _ => return false,
                     }
@@ -1621,13 +2620,7 @@ impl<'a> PhoenixStructurer<'a> {
         };
 
         // Collect all loop body blocks (excluding header)
-        let mut body_blocks: Vec<_> = loop_info
-            .blocks
-            .iter()
-            .filter(|&&b| b != node_id && self.region.nodes.contains_key(&b))
-            .copied()
-            .collect();
-        body_blocks.sort();
+        let body_blocks = self.loop_body_blocks(node_id, loop_info);
 
         // Create loop body as a sequence of blocks
         // Note: With postorder traversal, nested loops are already structured
@@ -1653,13 +2646,7 @@ impl<'a> PhoenixStructurer<'a> {
             self.logger.debug("DEBUG match_while_loop: Header has statements, using endless loop with break pattern");
 
             // Create inverted condition for break
-            let inverted_condition = Expr::new(
-                condition.offset,
-                super::expr::ExprKind::VirtualFunction {
-                    func: super::refs::FunctionRef::ByName(super::types::Name::new("not")),
-                    params: vec![condition.clone()],
-                },
-            );
+            let inverted_condition = B::negate(condition.clone());
 
             // Create break node
             let break_node = StructuredNode::Break { target: exit_succ };
@@ -1712,135 +2699,156 @@ impl<'a> PhoenixStructurer<'a> {
         true
     }
 
+    /// The blocks of a loop other than its header that are still in the
+    /// region, in the order control reaches them from the header
+    fn loop_body_blocks(&self, header: BlockId, loop_info: &Loop) -> Vec<BlockId> {
+        let in_body =
+            |b: BlockId| loop_info.blocks.contains(&b) && self.region.nodes.contains_key(&b);
+        let mut blocks: Vec<_> =
+            dfs_postorder([header], |b| in_body(b).then(|| self.region.successors(b)))
+                .into_iter()
+                .rev()
+                .filter(|&b| b != header)
+                .collect();
+        // Blocks the header doesn't reach within the loop go last, by ID
+        let mut unreached: Vec<_> = loop_info
+            .blocks
+            .iter()
+            .copied()
+            .filter(|&b| b != header && in_body(b) && !blocks.contains(&b))
+            .collect();
+        unreached.sort();
+        blocks.extend(unreached);
+        blocks
+    }
+
     /// Match do-while loop pattern: loop with condition at the end
     fn match_dowhile_loop(&mut self, node_id: BlockId) -> bool {
-        // Skip if already structured (not a simple Code block)
-        if let Some(node) = self.region.nodes.get(&node_id) {
-            match node {
-                StructuredNode::Code { .. } => {
-                    // Continue - this is a basic block we can match
-                }
-                _ => {
-                    return false;
-                }
-            }
+        if !self.loop_info.is_loop_header(node_id) {
+            return false;
         }
 
-        // For do-while, we need to find a loop where the latch (last block) has the condition
-        let loop_opt = self
-            .loop_info
-            .loops
-            .iter()
-            .find(|l| l.header == node_id && !l.back_edges.is_empty());
-
-        let Some(loop_info) = loop_opt else {
+        // The whole body, header included, has to be reduced to the header's
+        // node, which ends in the loop's test
+        let Some((exit_succ, latch)) = self.latch_test_exit(node_id) else {
             return false;
         };
-
-        // Get the latch block (source of back edge)
-        let (latch, _) = loop_info.back_edges[0];
-
-        // Check if latch has 2 successors (one back to header, one exit)
-        let latch_succs = self.region.successors(latch).to_vec();
-        if latch_succs.len() != 2 {
+        if !self.region.successors(latch).contains(&node_id) {
             return false;
         }
-
-        // One should be the header, the other is the exit
-        let exit_succ = if latch_succs[0] == node_id {
-            latch_succs[1]
-        } else if latch_succs[1] == node_id {
-            latch_succs[0]
-        } else {
-            return false; // Neither points back to header?
+        let Some(node) = self.region.nodes.get(&node_id) else {
+            return false;
         };
-
-        // Extract condition from latch
-        // The latch might be a Code block or a Sequence wrapping a Code block
-        let latch_node = self.region.nodes.get(&latch).unwrap();
-
-        let condition = match latch_node {
-            StructuredNode::Code { block } => match &block.terminator {
-                Terminator::Branch { condition, .. } => condition.clone(),
-                _ => return false,
-            },
-            StructuredNode::Sequence { nodes } => {
-                // The last node in the sequence might have the branch
-                if let Some(StructuredNode::Code { block }) = nodes.last() {
-                    match &block.terminator {
-                        Terminator::Branch { condition, .. } => condition.clone(),
-                        _ => return false,
-                    }
-                } else {
-                    return false;
-                }
-            }
-            _ => return false,
+        let Some(Terminator::Branch {
+            condition,
+            true_target,
+            ..
+        }) = Self::final_terminator(node)
+        else {
+            return false;
+        };
+        let condition = match *true_target == latch {
+            true => condition.clone(),
+            false => B::negate(condition.clone()),
         };
 
-        // Extract header statements (before any terminator)
-        let header_stmts = self.extract_header_statements(node_id);
-
-        // Collect all loop body blocks (excluding header for now)
-        let body_blocks: Vec<_> = loop_info
-            .blocks
-            .iter()
-            .filter(|&&b| b != node_id && self.region.nodes.contains_key(&b))
-            .copied()
-            .collect();
-
-        // Create loop body as a sequence of blocks
-        // Note: With postorder traversal, nested loops are already structured
-        // Strip terminators that are internal to the loop
-        let mut body_nodes: Vec<_> = Vec::new();
-
-        // For do-while, include header statements at the beginning
-        if let Some(header) = header_stmts {
-            body_nodes.push(header);
+        // With nothing before the test, it's a while loop
+        let body = Self::without_final_terminator(node.clone());
+        if matches!(&body, StructuredNode::Code { block } if block.is_empty()) {
+            return false;
         }
 
-        // Add the rest of the body blocks
-        let rest_nodes: Vec<_> = body_blocks
-            .iter()
-            .filter_map(|&b| {
-                let node = self.region.nodes.get(&b).cloned()?;
-                Some(self.strip_loop_internal_terminators(node, node_id, loop_info))
-            })
-            .collect();
-
-        body_nodes.extend(rest_nodes);
-
-        let loop_body = StructuredNode::sequence(body_nodes, self.logger);
+        self.logger.debug(&format!(
+            "match_dowhile_loop: {:?} tested at the latch, exits to {:?}",
+            node_id, exit_succ
+        ));
 
-        // Create do-while loop node
         let loop_node =
-            StructuredNode::loop_node(LoopType::DoWhile, Some(condition), loop_body, node_id);
-
-        // Rewrite jumps to breaks and continues
+            StructuredNode::loop_node(LoopType::DoWhile, Some(condition), body, node_id);
         let loop_node = Self::rewrite_loop_jumps(loop_node, node_id, exit_succ, self.logger);
 
-        // Update the region
         self.region.nodes.insert(node_id, loop_node);
-
-        // Remove loop body blocks
-        for &block in &body_blocks {
-            if block != node_id {
-                self.region.remove_node(block);
-            }
+        if latch != node_id {
+            self.region.remove_node(latch);
         }
-
-        // Update edges - loop points ONLY to the latch's exit
-        // Multi-exit paths from loop body are preserved as gotos in the loop body itself
+        self.region.remove_edge(node_id, node_id);
         self.region.edges.insert(node_id, vec![exit_succ]);
 
-        // Update exit's predecessors
-        let preds = self.region.predecessors.entry(exit_succ).or_default();
-        preds.retain(|p| !loop_info.blocks.contains(p) || *p == node_id);
-        if !preds.contains(&node_id) {
-            preds.push(node_id);
+        true
+    }
+
+    /// If `node_id` ends in the test of the innermost loop it's in, the exit
+    /// the test leaves through and the latch it continues the loop through
+    ///
+    /// Such a node branches either straight back to the loop's header or to
+    /// an empty block that only jumps there, and otherwise out of the loop;
+    /// that back edge has to be the loop's only one.
+    fn latch_test_exit(&self, node_id: BlockId) -> Option<(BlockId, BlockId)> {
+        let succs = self.region.successors(node_id);
+        let [first, second] = *succs else {
+            return None;
+        };
+        let loop_info = self
+            .loop_info
+            .loops
+            .iter()
+            .filter(|l| l.blocks.contains(&node_id))
+            .min_by_key(|l| l.blocks.len())?;
+        let header = loop_info.header;
+        let latches: Vec<BlockId> = self
+            .region
+            .predecessors(header)
+            .iter()
+            .copied()
+            .filter(|pred| loop_info.blocks.contains(pred))
+            .collect();
+        let [latch] = *latches else {
+            return None;
+        };
+        let continues = |target: BlockId| {
+            target == latch
+                && (target == node_id
+                    || (self.region.successors(target) == [header]
+                        && self.region.predecessors(target) == [node_id]
+                        && matches!(
+                            self.region.nodes.get(&target),
+                            Some(StructuredNode::Code { block }) if block.is_empty()
+                        )))
+        };
+        let leaves = |target: BlockId| !loop_info.blocks.contains(&target);
+        if continues(first) && leaves(second) {
+            Some((second, first))
+        } else if continues(second) && leaves(first) {
+            Some((first, second))
+        } else {
+            None
         }
+    }
 
-        true
+    /// The terminator a node ends with: its block's, or its last node's
+    fn final_terminator(node: &StructuredNode<B>) -> Option<&Terminator<B::Expr>> {
+        match node {
+            StructuredNode::Code { block } => Some(block.terminator()),
+            StructuredNode::Sequence { nodes } => Self::final_terminator(nodes.last()?),
+            _ => None,
+        }
+    }
+
+    /// A node without the terminator it ends with, keeping its statements
+    fn without_final_terminator(node: StructuredNode<B>) -> StructuredNode<B> {
+        match node {
+            StructuredNode::Code { mut block } => {
+                *block.terminator_mut() = Terminator::None;
+                StructuredNode::Code { block }
+            }
+            StructuredNode::Sequence { mut nodes } => {
+                if let Some(last) = nodes.pop() {
+                    nodes.push(Self::without_final_terminator(last));
+                }
+                StructuredNode::Sequence { nodes }
+            }
+            node => node,
+        }
     }
 
     /// Match natural loop pattern: any loop structure (catch-all)
@@ -1877,12 +2885,7 @@ impl<'a> PhoenixStructurer<'a> {
 
         // For natural loops, we just wrap all the blocks in an endless loop
         // Collect all loop blocks (excluding header for now)
-        let body_blocks: Vec<_> = loop_info
-            .blocks
-            .iter()
-            .filter(|&&b| b != node_id && self.region.nodes.contains_key(&b))
-            .copied()
-            .collect();
+        let body_blocks = self.loop_body_blocks(node_id, loop_info);
 
         // Create loop body as a sequence of blocks
         // Note: With postorder traversal, nested loops are already structured
@@ -1963,7 +2966,7 @@ impl<'a> PhoenixStructurer<'a> {
         self.logger.debug("\n=== Region State ===");
         self.logger.debug(&format!("Nodes: {}", self.region.len()));
         self.logger
-            .debug(&format!("Has cycles: {}", self.region.has_cycles()));
+            .debug(&format!("Has cycles: {}", has_cycle(&self.region)));
         self.logger.debug("\nNodes and their edges:");
 
         let mut node_ids: Vec<_> = self.region.nodes.keys().copied().collect();
@@ -1984,7 +2987,7 @@ impl<'a> PhoenixStructurer<'a> {
             match node {
                 StructuredNode::Code { block } => {
                     self.logger
-                        .debug(&format!("    Terminator: {:?}", block.terminator));
+                        .debug(&format!("    Terminator: {:?}", block.terminator()));
                 }
                 StructuredNode::Conditional {
                     condition_block, ..
@@ -2053,7 +3056,7 @@ impl<'a> PhoenixStructurer<'a> {
     }
 
     /// Get a human-readable name for the node type
-    fn node_type_name(node: &StructuredNode) -> &'static str {
+    fn node_type_name(node: &StructuredNode<B>) -> &'static str {
         match node {
             StructuredNode::Sequence { .. } => "Sequence",
             StructuredNode::Conditional { .. } => "Conditional",
@@ -2061,7 +3064,657 @@ impl<'a> PhoenixStructurer<'a> {
             StructuredNode::Break { .. } => "Break",
             StructuredNode::Continue { .. } => "Continue",
             StructuredNode::Code { .. } => "Code",
+            StructuredNode::Pruned { .. } => "Pruned",
             StructuredNode::Empty => "Empty",
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::dominators::DominatorTree;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::{BytecodeBuilder, parse};
+    use crate::bytecode::types::BytecodeOffset;
+
+    /// `Math();`, a statement with no control flow
+    fn work(b: &mut BytecodeBuilder) {
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+    }
+
+    /// `if (!v<variable>) goto <patched later>`
+    fn jump_unless(b: &mut BytecodeBuilder, variable: u64) -> usize {
+        b.op(EExprToken::JumpIfNot);
+        let target = b.placeholder();
+        b.op(EExprToken::LocalVariable).address(variable);
+        target
+    }
+
+    fn jump(b: &mut BytecodeBuilder) -> usize {
+        b.op(EExprToken::Jump);
+        b.placeholder()
+    }
+
+    fn ret(b: &mut BytecodeBuilder) {
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+    }
+
+    /// Structure a script the way `disassemble` does, with preheaders inserted
+    fn structure(b: &mut BytecodeBuilder) -> StructuredNode {
+        let mut cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let loop_info = LoopInfo::analyze(&cfg, &DominatorTree::compute(&cfg));
+        loop_info.insert_preheaders(&mut cfg);
+        let loop_info = LoopInfo::analyze(&cfg, &DominatorTree::compute(&cfg));
+        PhoenixStructurer::new(&cfg, &loop_info)
+            .structure()
+            .expect("structured")
+            .root
+    }
+
+    /// A condition as [`outline`] shows it
+    trait Condition {
+        fn outline(&self) -> String;
+    }
+
+    impl Condition for Expr {
+        fn outline(&self) -> String {
+            match &self.kind {
+                ExprKind::LocalVariable(prop) => format!("v{}", prop.address.as_u64()),
+                ExprKind::CallMath { params, .. } if params.len() == 1 => {
+                    format!("!{}", params[0].outline())
+                }
+                _ => "?".to_string(),
+            }
+        }
+    }
+
+    impl Condition for String {
+        fn outline(&self) -> String {
+            self.clone()
+        }
+    }
+
+    /// The shape of a statement tree, e.g. `[b0 if(v1; b1 | b2) b3]`, with
+    /// blocks by ID and the gotos left in them
+    fn outline<B: BlockCode>(node: &StructuredNode<B>) -> String
+    where
+        B::Expr: Condition,
+    {
+        match node {
+            StructuredNode::Sequence { nodes } => {
+                let nodes: Vec<String> = nodes.iter().map(outline).collect();
+                format!("[{}]", nodes.join(" "))
+            }
+            StructuredNode::Conditional {
+                condition: cond,
+                true_branch,
+                false_branch,
+                ..
+            } => match false_branch {
+                Some(false_branch) => format!(
+                    "if({}; {} | {})",
+                    cond.outline(),
+                    outline(true_branch),
+                    outline(false_branch)
+                ),
+                None => format!("if({}; {})", cond.outline(), outline(true_branch)),
+            },
+            StructuredNode::Loop {
+                loop_type,
+                condition: cond,
+                body,
+                ..
+            } => {
+                let kind = match loop_type {
+                    LoopType::While => "while",
+                    LoopType::DoWhile => "dowhile",
+                    LoopType::Endless => "loop",
+                };
+                match cond {
+                    Some(cond) => format!("{}({}; {})", kind, cond.outline(), outline(body)),
+                    None => format!("{}({})", kind, outline(body)),
+                }
+            }
+            StructuredNode::Break { .. } => "break".to_string(),
+            StructuredNode::Continue { .. } => "continue".to_string(),
+            StructuredNode::Code { block } => match block.terminator() {
+                Terminator::Goto { target } => format!("b{}->b{}", block.id().0, target.0),
+                Terminator::Branch {
+                    condition: cond,
+                    true_target,
+                    false_target,
+                } => format!(
+                    "b{}?{}->b{}|b{}",
+                    block.id().0,
+                    cond.outline(),
+                    true_target.0,
+                    false_target.0
+                ),
+                _ => format!("b{}", block.id().0),
+            },
+            StructuredNode::Pruned { .. } => "pruned".to_string(),
+            StructuredNode::Empty => "empty".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_if_else() {
+        // if (!v1) goto else; Math(); goto end; else: Math(); end: return;
+        let mut b = BytecodeBuilder::default();
+        let to_else = jump_unless(&mut b, 1);
+        work(&mut b);
+        let to_end = jump(&mut b);
+        let else_start = b.here();
+        work(&mut b);
+        let end = b.here();
+        ret(&mut b);
+        b.patch(to_else, else_start);
+        b.patch(to_end, end);
+
+        assert_eq!(outline(&structure(&mut b)), "[if(v1; b1 | b2) b3]");
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut b = BytecodeBuilder::default();
+        let to_end = jump_unless(&mut b, 1);
+        work(&mut b);
+        let end = b.here();
+        ret(&mut b);
+        b.patch(to_end, end);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let loop_info = LoopInfo::analyze(&cfg, &DominatorTree::compute(&cfg));
+        let result = PhoenixStructurer::new(&cfg, &loop_info)
+            .with_deadline(Instant::now())
+            .try_structure();
+        assert!(matches!(result, Err(KismetError::Structuring(_))));
+    }
+
+    #[test]
+    fn test_while_loop() {
+        // loop: if (!v1) goto end; Math(); goto loop; end: return;
+        let mut b = BytecodeBuilder::default();
+        let top = b.here();
+        let to_end = jump_unless(&mut b, 1);
+        work(&mut b);
+        let back = jump(&mut b);
+        let end = b.here();
+        ret(&mut b);
+        b.patch(to_end, end);
+        b.patch(back, top);
+
+        // b3 is the preheader
+        assert_eq!(outline(&structure(&mut b)), "[b3 [while(v1; b1) b2]]");
+    }
+
+    #[test]
+    fn test_nested_loops() {
+        // outer: if (!v1) goto end;
+        //   inner: if (!v2) goto next; Math(); goto inner;
+        //   next: Math(); goto outer;
+        // end: return;
+        let mut b = BytecodeBuilder::default();
+        let outer = b.here();
+        let to_end = jump_unless(&mut b, 1);
+        let inner = b.here();
+        let to_next = jump_unless(&mut b, 2);
+        work(&mut b);
+        let to_inner = jump(&mut b);
+        let next = b.here();
+        work(&mut b);
+        let to_outer = jump(&mut b);
+        let end = b.here();
+        ret(&mut b);
+        b.patch(to_end, end);
+        b.patch(to_next, next);
+        b.patch(to_inner, inner);
+        b.patch(to_outer, outer);
+
+        // b5 and b6 are the preheaders of the inner and outer loop
+        assert_eq!(
+            outline(&structure(&mut b)),
+            "[b6 [while(v1; [b5 [while(v2; b2) b3]]) b4]]"
+        );
+    }
+
+    #[test]
+    fn test_loop_with_break() {
+        // loop: if (!v1) goto end; Math(); if (!v2) goto end; Math(); goto loop;
+        // end: Math(); return;
+        let mut b = BytecodeBuilder::default();
+        let top = b.here();
+        let to_end = jump_unless(&mut b, 1);
+        work(&mut b);
+        let to_break = jump_unless(&mut b, 2);
+        work(&mut b);
+        let back = jump(&mut b);
+        let end = b.here();
+        work(&mut b);
+        ret(&mut b);
+        b.patch(to_end, end);
+        b.patch(to_break, end);
+        b.patch(back, top);
+
+        // The break is left as a conditional goto to the exit
+        assert_eq!(
+            outline(&structure(&mut b)),
+            "[b4 [while(v1; [b1?v2->b2|b3 b2]) b3]]"
+        );
+    }
+
+    #[test]
+    fn test_do_while_loop() {
+        // loop: Math(); if (!v1) goto end; goto loop; end: return;
+        let mut b = BytecodeBuilder::default();
+        let top = b.here();
+        work(&mut b);
+        let to_end = jump_unless(&mut b, 1);
+        let back = jump(&mut b);
+        let end = b.here();
+        ret(&mut b);
+        b.patch(to_end, end);
+        b.patch(back, top);
+        assert_eq!(outline(&structure(&mut b)), "[b3 [dowhile(v1; b0) b2]]");
+
+        // loop: Math(); if (!v1) goto loop; return;
+        let mut b = BytecodeBuilder::default();
+        let top = b.here();
+        work(&mut b);
+        let back = jump_unless(&mut b, 1);
+        ret(&mut b);
+        b.patch(back, top);
+        assert_eq!(outline(&structure(&mut b)), "[b2 [dowhile(!v1; b0) b1]]");
+    }
+
+    #[test]
+    fn test_do_while_loop_with_if() {
+        // loop: if (!v1) goto test; Math(); test: Math(); if (!v2) goto end;
+        // goto loop; end: return;
+        let mut b = BytecodeBuilder::default();
+        let top = b.here();
+        let to_test = jump_unless(&mut b, 1);
+        work(&mut b);
+        let test = b.here();
+        work(&mut b);
+        let to_end = jump_unless(&mut b, 2);
+        let back = jump(&mut b);
+        let end = b.here();
+        ret(&mut b);
+        b.patch(to_test, test);
+        b.patch(to_end, end);
+        b.patch(back, top);
+
+        assert_eq!(
+            outline(&structure(&mut b)),
+            "[b5 [dowhile(v2; [if(v1; b1) b2]) b4]]"
+        );
+    }
+
+    #[test]
+    fn test_goto_labels() {
+        // loop: if (!v1) goto end; Math(); if (!v2) goto end; Math(); goto loop;
+        // end: Math(); return;
+        let mut b = BytecodeBuilder::default();
+        let top = b.here();
+        let to_end = jump_unless(&mut b, 1);
+        work(&mut b);
+        let to_break = jump_unless(&mut b, 2);
+        work(&mut b);
+        let back = jump(&mut b);
+        let end = b.here();
+        work(&mut b);
+        ret(&mut b);
+        b.patch(to_end, end);
+        b.patch(to_break, end);
+        b.patch(back, top);
+
+        let mut cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let loop_info = LoopInfo::analyze(&cfg, &DominatorTree::compute(&cfg));
+        loop_info.insert_preheaders(&mut cfg);
+        let targets = HashSet::from([BlockId(0), BlockId(2), BlockId(3)]);
+        let labels = goto_labels(&cfg, &targets);
+        assert_eq!(labels[&BlockId(0)], "loop_start");
+        assert_eq!(labels[&BlockId(2)], "after_branch");
+        assert_eq!(labels[&BlockId(3)], "loop_end");
+    }
+
+    #[test]
+    fn test_tail_region_with_two_entries() {
+        // if (!v1) goto second; Math(); if (!v2) goto tail;
+        // second: Math(); tail: Math(); return;
+        let mut b = BytecodeBuilder::default();
+        let to_second = jump_unless(&mut b, 1);
+        work(&mut b);
+        let to_tail = jump_unless(&mut b, 2);
+        let second = b.here();
+        work(&mut b);
+        let tail = b.here();
+        work(&mut b);
+        ret(&mut b);
+        b.patch(to_second, second);
+        b.patch(to_tail, tail);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let loop_info = LoopInfo::analyze(&cfg, &DominatorTree::compute(&cfg));
+        let graph = PhoenixStructurer::new(&cfg, &loop_info)
+            .structure()
+            .expect("structured");
+        assert_eq!(graph.virtualized_edges, [(BlockId(1), BlockId(3))]);
+        assert_eq!(outline(&graph.root), "[if(v1; b1?v2->b2|b3) [b2 b3]]");
+    }
+
+    #[test]
+    fn test_sequence_pins() {
+        // PushExecutionFlow(third); PushExecutionFlow(second);
+        // Math(); PopExecutionFlow; second: Math(); PopExecutionFlow;
+        // third: return;
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::PushExecutionFlow);
+        let to_third = b.placeholder();
+        b.op(EExprToken::PushExecutionFlow);
+        let to_second = b.placeholder();
+        let first = b.here();
+        work(&mut b);
+        b.op(EExprToken::PopExecutionFlow);
+        let second = b.here();
+        work(&mut b);
+        b.op(EExprToken::PopExecutionFlow);
+        let third = b.here();
+        ret(&mut b);
+        b.patch(to_third, third);
+        b.patch(to_second, second);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let pins = sequence_pins(&cfg);
+        let pin = |offset: usize| pins[&BytecodeOffset::new(offset)];
+        assert_eq!(pins.len(), 3);
+        assert_eq!(
+            pin(first),
+            SequencePin {
+                number: 1,
+                count: 3
+            }
+        );
+        assert_eq!(
+            pin(second),
+            SequencePin {
+                number: 2,
+                count: 3
+            }
+        );
+        assert_eq!(
+            pin(third),
+            SequencePin {
+                number: 3,
+                count: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_switch() {
+        // A switch on an integer compiles to a chain of case tests:
+        // if (!v1) goto case1; Math(); goto end;
+        // case1: if (!v2) goto default; Math(); goto end;
+        // default: Math(); end: return;
+        let mut b = BytecodeBuilder::default();
+        let to_case1 = jump_unless(&mut b, 1);
+        work(&mut b);
+        let case0_end = jump(&mut b);
+        let case1 = b.here();
+        let to_default = jump_unless(&mut b, 2);
+        work(&mut b);
+        let case1_end = jump(&mut b);
+        let default = b.here();
+        work(&mut b);
+        let end = b.here();
+        ret(&mut b);
+        b.patch(to_case1, case1);
+        b.patch(to_default, default);
+        b.patch(case0_end, end);
+        b.patch(case1_end, end);
+
+        assert_eq!(
+            outline(&structure(&mut b)),
+            "[if(v1; b1 | if(v2; b3 | b4)) b5]"
+        );
+    }
+
+    #[test]
+    fn test_short_circuit_condition() {
+        // if (v1 && v2) Math(); else Math(); return;
+        let mut b = BytecodeBuilder::default();
+        let first = jump_unless(&mut b, 1);
+        let second = jump_unless(&mut b, 2);
+        work(&mut b);
+        let to_end = jump(&mut b);
+        let else_start = b.here();
+        work(&mut b);
+        let end = b.here();
+        ret(&mut b);
+        b.patch(first, else_start);
+        b.patch(second, else_start);
+        b.patch(to_end, end);
+
+        // Conditions sharing an else branch aren't combined yet, so the
+        // first one's jump to the else branch is left as a goto
+        assert_eq!(
+            outline(&structure(&mut b)),
+            "[b0?v1->b1|b3 [if(v2; b2 | b3) b4]]"
+        );
+    }
+
+    #[test]
+    fn test_irreducible_flow() {
+        // if (!v1) goto b; a: Math(); b: Math(); if (!v2) goto a; return;
+        let mut b = BytecodeBuilder::default();
+        let to_b = jump_unless(&mut b, 1);
+        let a = b.here();
+        work(&mut b);
+        let b_start = b.here();
+        work(&mut b);
+        let to_a = jump_unless(&mut b, 2);
+        ret(&mut b);
+        b.patch(to_b, b_start);
+        b.patch(to_a, a);
+
+        // A loop with two entries has no structured form; the jump into its
+        // middle is left as a goto, which only lets the blocks be grouped
+        assert_eq!(
+            outline(&structure(&mut b)),
+            "[b0?v1->b1|b2 [b1 b2?v2->b3|b1] b3]"
+        );
+    }
+
+    /// A block of a graph that isn't Kismet's: some work, then a jump on
+    /// a named flag
+    #[derive(Debug, Clone)]
+    struct Step {
+        id: BlockId,
+        terminator: Terminator<String>,
+    }
+
+    impl BlockCode for Step {
+        type Expr = String;
+
+        fn id(&self) -> BlockId {
+            self.id
+        }
+
+        fn terminator(&self) -> &Terminator<String> {
+            &self.terminator
+        }
+
+        fn terminator_mut(&mut self) -> &mut Terminator<String> {
+            &mut self.terminator
+        }
+
+        fn is_empty(&self) -> bool {
+            false
+        }
+
+        fn negate(condition: String) -> String {
+            format!("!{}", condition)
+        }
+    }
+
+    /// A graph of [`Step`]s, one per jump given, entered at the first
+    struct Steps {
+        steps: Vec<Step>,
+        successors: Vec<Vec<BlockId>>,
+        predecessors: Vec<Vec<BlockId>>,
+    }
+
+    impl Steps {
+        fn new(jumps: Vec<Terminator<String>>) -> Self {
+            let mut graph = Self {
+                steps: Vec::new(),
+                successors: Vec::new(),
+                predecessors: vec![Vec::new(); jumps.len()],
+            };
+            for (index, terminator) in jumps.into_iter().enumerate() {
+                let successors = match &terminator {
+                    Terminator::Goto { target } => vec![*target],
+                    Terminator::Branch {
+                        true_target,
+                        false_target,
+                        ..
+                    } => vec![*true_target, *false_target],
+                    _ => Vec::new(),
+                };
+                for succ in &successors {
+                    graph.predecessors[succ.0].push(BlockId(index));
+                }
+                graph.successors.push(successors);
+                graph.steps.push(Step {
+                    id: BlockId(index),
+                    terminator,
+                });
+            }
+            graph
+        }
+    }
+
+    impl FlowGraph for Steps {
+        fn entry(&self) -> BlockId {
+            BlockId(0)
+        }
+
+        fn nodes(&self) -> impl Iterator<Item = BlockId> + '_ {
+            (0..self.steps.len()).map(BlockId)
+        }
+
+        fn successors(&self, node: BlockId) -> &[BlockId] {
+            &self.successors[node.0]
+        }
+
+        fn predecessors(&self, node: BlockId) -> &[BlockId] {
+            &self.predecessors[node.0]
+        }
+    }
+
+    impl CodeGraph for Steps {
+        type Block = Step;
+
+        fn block(&self, node: BlockId) -> &Step {
+            &self.steps[node.0]
+        }
+    }
+
+    #[test]
+    fn test_structure_other_graph() {
+        let goto = |target| Terminator::Goto {
+            target: BlockId(target),
+        };
+        let branch = |condition: &str, true_target, false_target| Terminator::Branch {
+            condition: condition.to_string(),
+            true_target: BlockId(true_target),
+            false_target: BlockId(false_target),
+        };
+        // if (ready) {1} else {2}; while (more) {4}; return
+        let graph = Steps::new(vec![
+            branch("ready", 1, 2),
+            goto(3),
+            goto(3),
+            branch("more", 4, 5),
+            goto(3),
+            Terminator::Return("done".to_string()),
+        ]);
+        let loop_info = LoopInfo::analyze(&graph, &DominatorTree::compute(&graph));
+        let root = PhoenixStructurer::new(&graph, &loop_info)
+            .structure()
+            .expect("structured")
+            .root;
+
+        // The loop's header does work before its test, so the test becomes
+        // a break on the negated flag
+        assert_eq!(
+            outline(&root),
+            "[[b0 if(ready; b1 | b2)] [loop([b3 if(!more; break) b4]) b5]]"
+        );
+    }
+
+    fn code_at(offset: usize) -> StructuredNode {
+        StructuredNode::code(BasicBlock::new(
+            BlockId(offset),
+            BytecodeOffset::new(offset),
+        ))
+    }
+
+    #[test]
+    fn test_order_by_hits_puts_hot_branch_first() {
+        let condition = Expr::new(BytecodeOffset::new(0), ExprKind::True);
+        let mut graph = StructuredGraph {
+            root: StructuredNode::conditional(
+                condition,
+                code_at(10),
+                Some(code_at(20)),
+                BlockId(0),
+            ),
+            virtualized_edges: Vec::new(),
+        };
+        let hits = HitCounts::new(HashMap::from([
+            (BytecodeOffset::new(10), 1),
+            (BytecodeOffset::new(20), 5),
+        ]));
+        graph.order_by_hits(&hits);
+
+        let StructuredNode::Conditional {
+            condition,
+            true_branch,
+            ..
+        } = &graph.root
+        else {
+            panic!("expected a conditional");
+        };
+        assert_eq!(true_branch.hit_count(&hits), Some(5));
+        assert_eq!(negate(condition.clone()).kind, ExprKind::True);
+    }
+
+    #[test]
+    fn test_prune_constant_branches() {
+        let condition = Expr::new(BytecodeOffset::new(0), ExprKind::False);
+        let mut graph = StructuredGraph {
+            root: StructuredNode::conditional(
+                condition,
+                code_at(10),
+                Some(code_at(20)),
+                BlockId(0),
+            ),
+            virtualized_edges: Vec::new(),
+        };
+        graph.prune_constant_branches(|_| None);
+
+        let StructuredNode::Sequence { nodes } = &graph.root else {
+            panic!("expected the note and the else branch");
+        };
+        assert!(matches!(
+            nodes[0],
+            StructuredNode::Pruned { value: false, .. }
+        ));
+        assert_eq!(StructuredNode::get_block_id(&nodes[1]), Some(BlockId(20)));
+    }
+}
@@ -0,0 +1,154 @@
+//! Helpers for building synthetic bytecode in unit tests
+
+use std::collections::BTreeMap;
+
+use super::expr::{Expr, ExprKind};
+use super::opcodes::EExprToken;
+use super::parser::ScriptParser;
+use super::reader::ScriptReader;
+use super::refs::{FunctionRef, PropertyRef};
+use super::types::{Address, BytecodeOffset, Name};
+
+/// Minimal bytecode assembler
+#[derive(Default)]
+pub struct BytecodeBuilder {
+    bytes: Vec<u8>,
+}
+
+impl BytecodeBuilder {
+    /// Offset the next byte will be written at
+    pub fn here(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn op(&mut self, token: EExprToken) -> &mut Self {
+        self.bytes.push(token.opcode_value());
+        self
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.bytes.push(value);
+        self
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn address(&mut self, address: u64) -> &mut Self {
+        self.bytes.extend_from_slice(&address.to_le_bytes());
+        self
+    }
+
+    /// Write a code offset to be filled in later with [`Self::patch`]
+    pub fn placeholder(&mut self) -> usize {
+        let at = self.here();
+        self.u32(0);
+        at
+    }
+
+    pub fn patch(&mut self, at: usize, offset: usize) {
+        self.bytes[at..at + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+    }
+
+    /// Terminate the script with `EndOfScript` and return its bytes
+    pub fn build(&mut self) -> Vec<u8> {
+        self.op(EExprToken::EndOfScript);
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+/// Parse a script that doesn't reference any names
+pub fn parse(script: &[u8]) -> Vec<Expr> {
+    let names = BTreeMap::new();
+    ScriptParser::new(ScriptReader::new(script, &names))
+        .parse_all()
+        .unwrap()
+}
+
+/// An expression at `offset`
+pub fn at(offset: usize, kind: ExprKind) -> Expr {
+    Expr::new(BytecodeOffset::new(offset), kind)
+}
+
+/// A call to the function named `callee` with no arguments
+pub fn call(offset: usize, callee: &str) -> Expr {
+    call_with(offset, callee, Vec::new())
+}
+
+/// A call to the function named `callee`
+pub fn call_with(offset: usize, callee: &str, params: Vec<Expr>) -> Expr {
+    at(
+        offset,
+        ExprKind::CallMath {
+            func: FunctionRef::ByName(Name::new(callee)),
+            params,
+        },
+    )
+}
+
+/// A call to the KismetMathLibrary function `name`, at offset 0
+pub fn math(name: &str, params: Vec<Expr>) -> Expr {
+    call_with(
+        0,
+        &format!("/Script/Engine.KismetMathLibrary:{}", name),
+        params,
+    )
+}
+
+/// The local variable at `address`
+pub fn local(offset: usize, address: u64) -> Expr {
+    at(
+        offset,
+        ExprKind::LocalVariable(PropertyRef::new(Address::new(address))),
+    )
+}
+
+/// Path of a function called by name, which the helpers above give as the
+/// name
+pub fn by_name(func: &FunctionRef) -> Option<String> {
+    match func {
+        FunctionRef::ByName(name) => Some(name.as_str().to_string()),
+        FunctionRef::ByAddress(_) => None,
+    }
+}
+
+/// A jmap object of type `ty`, such as `Class` or `Function`, with no
+/// properties or script, as the JSON a dump holds
+pub fn object_json(ty: &str, address: u64, super_struct: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "type": ty, "address": address, "outer": null, "class": ty,
+        "super_struct": super_struct, "properties": [], "properties_size": 0,
+        "script": [], "function_flags": 0, "class_default_object": null,
+    })
+}
+
+/// A property as the JSON a dump holds, with the fields of its type, such as
+/// `{"type": "Int"}`, merged in
+pub fn property_json(
+    name: &str,
+    address: u64,
+    offset: usize,
+    r#type: serde_json::Value,
+) -> serde_json::Value {
+    let mut property = serde_json::json!({
+        "name": name, "address": address, "offset": offset, "array_dim": 1,
+        "size": 4, "flags": 0,
+    });
+    property
+        .as_object_mut()
+        .unwrap()
+        .extend(r#type.as_object().unwrap().clone());
+    property
+}
+
+/// A jmap with no names holding `objects`, a JSON map of path to object
+pub fn jmap_of(objects: serde_json::Value) -> jmap::Jmap {
+    serde_json::from_value(serde_json::json!({ "names": {}, "objects": objects })).unwrap()
+}
@@ -0,0 +1,279 @@
+/// Code that runs every frame or on a timer
+///
+/// Finds the three usual ways Blueprint code ends up running repeatedly: tick
+/// events, timers set with `K2_SetTimer`/`K2_SetTimerDelegate`, and loops
+/// that wait on a latent action such as `Delay` each time around.
+use std::collections::{HashMap, HashSet};
+
+use super::cfg::{BlockId, ControlFlowGraph};
+use super::cost::expr_cost;
+use super::expr::{Expr, ExprKind};
+use super::pattern::{call_parts, function_matches};
+use super::refs::FunctionRef;
+use super::types::BytecodeOffset;
+
+/// Events the engine calls once per frame
+const TICK_EVENTS: &[&str] = &[
+    "ReceiveTick",
+    "Tick",
+    "BlueprintUpdateAnimation",
+    "ReceiveDrawHUD",
+];
+
+/// Functions that start a timer
+const TIMER_SETTERS: &[&str] = &["K2_SetTimer", "K2_SetTimerDelegate"];
+
+/// Whether a function with this path is a per-frame event
+pub fn is_tick_event(path: &str) -> bool {
+    TICK_EVENTS
+        .iter()
+        .any(|event| function_matches(event, path))
+}
+
+/// A call that starts a timer
+#[derive(Debug, Clone)]
+pub struct TimerCall {
+    pub offset: BytecodeOffset,
+    /// Name of the function or event the timer calls, if given as a constant
+    pub target: Option<String>,
+    /// Seconds between calls, if given as a constant
+    pub interval: Option<f32>,
+    /// Whether the timer repeats, if given as a constant
+    pub looping: Option<bool>,
+}
+
+/// Timer-starting calls anywhere in the expressions
+///
+/// The arguments are picked out by type rather than position, since the two
+/// setters take different leading arguments: the first name, string or
+/// delegate is the target, the first float the interval and the first bool
+/// whether it loops.
+pub fn find_timer_calls(
+    expressions: &[Expr],
+    resolve: impl Fn(&FunctionRef) -> Option<String>,
+) -> Vec<TimerCall> {
+    let mut timers = Vec::new();
+    for expr in expressions {
+        expr.walk(&mut |e| {
+            let Some((func, params)) = call_parts(e) else {
+                return;
+            };
+            let Some(path) = resolve(func) else {
+                return;
+            };
+            if !TIMER_SETTERS
+                .iter()
+                .any(|setter| function_matches(setter, &path))
+            {
+                return;
+            }
+
+            let mut timer = TimerCall {
+                offset: e.offset,
+                target: None,
+                interval: None,
+                looping: None,
+            };
+            for param in params {
+                match &param.kind {
+                    ExprKind::NameConst(name)
+                    | ExprKind::InstanceDelegate(name)
+                    | ExprKind::BindDelegate {
+                        func_name: name, ..
+                    } if timer.target.is_none() => timer.target = Some(name.as_str().to_string()),
+                    ExprKind::StringConst(name) if timer.target.is_none() => {
+                        timer.target = Some(name.clone())
+                    }
+                    ExprKind::FloatConst(seconds) if timer.interval.is_none() => {
+                        timer.interval = Some(seconds.get())
+                    }
+                    ExprKind::True if timer.looping.is_none() => timer.looping = Some(true),
+                    ExprKind::False if timer.looping.is_none() => timer.looping = Some(false),
+                    _ => {}
+                }
+            }
+            timers.push(timer);
+        });
+    }
+    timers
+}
+
+/// Where a latent call, such as `Delay`, resumes once its action finishes
+pub fn latent_resume_offset(expr: &Expr) -> Option<BytecodeOffset> {
+    let (_, params) = call_parts(expr)?;
+    let mut resume = None;
+    for param in params {
+        param.walk(&mut |e| {
+            if let ExprKind::SkipOffsetConst(offset) = e.kind {
+                resume.get_or_insert(offset);
+            }
+        });
+    }
+    resume
+}
+
+/// Code that waits on a latent action and then runs back into the same call
+#[derive(Debug, Clone)]
+pub struct LatentLoop {
+    /// Offset of the latent call
+    pub latent_call: BytecodeOffset,
+    /// Where the code resumes after waiting
+    pub resume: BytecodeOffset,
+    /// Cost of the code run between two waits
+    pub iteration_cost: u64,
+}
+
+/// Latent calls whose resumed code can get back to the call
+///
+/// The CFG has no edge from a latent call to its resume point, since control
+/// returns to the engine in between, and its flow analysis doesn't reach code
+/// that's only entered by resuming. So edges are taken from each block's jump
+/// as well as its analyzed successors. The loop's code is every block on a
+/// path from the resume point back to the call.
+pub fn find_latent_loops(cfg: &ControlFlowGraph) -> Vec<LatentLoop> {
+    let successors = static_successors(cfg);
+    let mut predecessors = vec![Vec::new(); cfg.blocks.len()];
+    for (from, targets) in successors.iter().enumerate() {
+        for target in targets {
+            predecessors[target.0].push(BlockId(from));
+        }
+    }
+
+    let mut latent_loops = Vec::new();
+    for block in &cfg.blocks {
+        for stmt in &block.statements {
+            stmt.walk(&mut |e| {
+                let Some(resume) = latent_resume_offset(e) else {
+                    return;
+                };
+                let Some(resume_block) = cfg.blocks.iter().find(|b| b.start_offset == resume)
+                else {
+                    return;
+                };
+
+                let from_resume = reachable(resume_block.id, &successors);
+                let to_call = reachable(block.id, &predecessors);
+                let body: Vec<BlockId> = from_resume.intersection(&to_call).copied().collect();
+                if body.is_empty() {
+                    return;
+                }
+                let iteration_cost = body
+                    .iter()
+                    .flat_map(|id| {
+                        let b = &cfg.blocks[id.0];
+                        b.statements.iter().chain(&b.terminator_expr)
+                    })
+                    .map(expr_cost)
+                    .sum();
+                latent_loops.push(LatentLoop {
+                    latent_call: e.offset,
+                    resume,
+                    iteration_cost,
+                });
+            });
+        }
+    }
+    latent_loops
+}
+
+/// Each block's successors, adding the targets of its own jump to the ones
+/// flow analysis found
+fn static_successors(cfg: &ControlFlowGraph) -> Vec<Vec<BlockId>> {
+    let block_at: HashMap<BytecodeOffset, BlockId> = cfg
+        .blocks
+        .iter()
+        .map(|block| (block.start_offset, block.id))
+        .collect();
+    cfg.blocks
+        .iter()
+        .enumerate()
+        .map(|(index, block)| {
+            let next = cfg.blocks.get(index + 1).map(|next| next.id);
+            let mut successors = block.successors.clone();
+            match block.terminator_expr.as_ref().map(|expr| &expr.kind) {
+                Some(ExprKind::Jump { target }) => successors.extend(block_at.get(target)),
+                Some(ExprKind::JumpIfNot { target, .. }) => {
+                    successors.extend(block_at.get(target));
+                    successors.extend(next);
+                }
+                Some(ExprKind::PopExecutionFlowIfNot { .. }) => successors.extend(next),
+                Some(_) => {}
+                None => successors.extend(next),
+            }
+            successors
+        })
+        .collect()
+}
+
+/// Blocks reachable from `start` along `edges`, including `start`
+fn reachable(start: BlockId, edges: &[Vec<BlockId>]) -> HashSet<BlockId> {
+    let mut seen = HashSet::from([start]);
+    let mut work = vec![start];
+    while let Some(id) = work.pop() {
+        for &next in &edges[id.0] {
+            if seen.insert(next) {
+                work.push(next);
+            }
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::{BytecodeBuilder, at, by_name, call_with, parse};
+    use crate::bytecode::types::F32;
+
+    #[test]
+    fn test_timer_arguments() {
+        let call = call_with(
+            0,
+            "K2_SetTimer",
+            vec![
+                at(0, ExprKind::Self_),
+                at(0, ExprKind::StringConst("Regen".to_string())),
+                at(0, ExprKind::FloatConst(F32(0.5))),
+                at(0, ExprKind::True),
+            ],
+        );
+        let timers = find_timer_calls(&[call], by_name);
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0].target.as_deref(), Some("Regen"));
+        assert_eq!(timers[0].interval, Some(0.5));
+        assert_eq!(timers[0].looping, Some(true));
+    }
+
+    #[test]
+    fn test_latent_loop() {
+        // wait: Delay(resume); pop; resume: Math(); goto wait
+        let mut b = BytecodeBuilder::default();
+        let wait = b.here();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::SkipOffsetConst);
+        let resume = b.placeholder();
+        b.op(EExprToken::EndFunctionParms);
+        b.op(EExprToken::PopExecutionFlow);
+        let resume_offset = b.here();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+        b.op(EExprToken::Jump);
+        let back = b.placeholder();
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(resume, resume_offset);
+        b.patch(back, wait);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let loops = find_latent_loops(&cfg);
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].latent_call, BytecodeOffset::new(wait));
+        assert_eq!(loops[0].resume, BytecodeOffset::new(resume_offset));
+    }
+
+    #[test]
+    fn test_tick_events() {
+        assert!(is_tick_event("/Game/BP_Enemy.BP_Enemy_C:ReceiveTick"));
+        assert!(!is_tick_event("/Game/BP_Enemy.BP_Enemy_C:TickleTarget"));
+    }
+}
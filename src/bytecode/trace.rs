@@ -0,0 +1,131 @@
+/// Replaying a recorded execution trace over a function
+///
+/// A trace is the sequence of statement offsets a function ran, as recorded
+/// by an external tracer. Counting the offsets gives exhaustive hit counts
+/// for structured output, and pairing consecutive offsets gives the edges
+/// taken through the CFG.
+use std::collections::HashMap;
+
+use super::cfg::{BlockId, ControlFlowGraph};
+use super::hit_counts::HitCounts;
+use super::types::BytecodeOffset;
+
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub offsets: Vec<BytecodeOffset>,
+}
+
+/// How often each block and edge of a CFG was run in a trace
+#[derive(Debug, Clone, Default)]
+pub struct TraceCoverage {
+    pub block_hits: HashMap<BlockId, u64>,
+    pub edge_hits: HashMap<(BlockId, BlockId), u64>,
+}
+
+impl Trace {
+    pub fn new(offsets: Vec<BytecodeOffset>) -> Self {
+        Self { offsets }
+    }
+
+    /// How often each offset was run; offsets missing from the trace count
+    /// as never run
+    pub fn hit_counts(&self) -> HitCounts {
+        let mut counts = HashMap::new();
+        for &offset in &self.offsets {
+            *counts.entry(offset).or_insert(0) += 1;
+        }
+        HitCounts::exhaustive(counts)
+    }
+
+    /// Map the trace onto the blocks of a CFG
+    ///
+    /// An edge is taken when the trace moves into another block, or back to
+    /// the start of the same one. Moves that aren't CFG edges, such as
+    /// resuming after a latent action, are left out.
+    pub fn coverage(&self, cfg: &ControlFlowGraph) -> TraceCoverage {
+        // Blocks that hold code, by start offset; pre-headers and the abort
+        // block hold none
+        let mut ranges: Vec<_> = cfg
+            .blocks
+            .iter()
+            .filter(|block| !block.statements.is_empty() || block.terminator_expr.is_some())
+            .map(|block| (block.start_offset, block.end_offset, block.id))
+            .collect();
+        ranges.sort();
+        let block_of = |offset: BytecodeOffset| {
+            let index = ranges.partition_point(|&(start, _, _)| start <= offset);
+            let &(_, end, id) = ranges.get(index.checked_sub(1)?)?;
+            (offset <= end).then_some(id)
+        };
+
+        let mut coverage = TraceCoverage::default();
+        let mut previous: Option<(BytecodeOffset, BlockId)> = None;
+        for &offset in &self.offsets {
+            let Some(block) = block_of(offset) else {
+                previous = None;
+                continue;
+            };
+            let entered = match previous {
+                Some((previous_offset, previous_block)) => {
+                    let restarted = previous_block == block
+                        && offset <= previous_offset
+                        && offset == cfg.blocks[block.0].start_offset;
+                    if (previous_block != block || restarted)
+                        && cfg.blocks[previous_block.0].successors.contains(&block)
+                    {
+                        *coverage
+                            .edge_hits
+                            .entry((previous_block, block))
+                            .or_insert(0) += 1;
+                    }
+                    previous_block != block || restarted
+                }
+                None => true,
+            };
+            if entered {
+                *coverage.block_hits.entry(block).or_insert(0) += 1;
+            }
+            previous = Some((offset, block));
+        }
+        coverage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::{BytecodeBuilder, parse};
+
+    #[test]
+    fn test_loop_coverage() {
+        // loop: Math(); if (!true) goto loop; return;
+        let mut b = BytecodeBuilder::default();
+        let start = b.here();
+        b.op(EExprToken::CallMath).address(0x1000);
+        b.op(EExprToken::EndFunctionParms);
+        let branch = b.here();
+        b.op(EExprToken::JumpIfNot);
+        let target = b.placeholder();
+        b.op(EExprToken::True);
+        let end = b.here();
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(target, start);
+
+        let cfg = ControlFlowGraph::from_expressions(&parse(&b.build()));
+        let trace = Trace::new(
+            [start, branch, start, branch, end]
+                .into_iter()
+                .map(BytecodeOffset::new)
+                .collect(),
+        );
+        let coverage = trace.coverage(&cfg);
+        assert_eq!(coverage.block_hits[&BlockId(0)], 2);
+        assert_eq!(coverage.edge_hits[&(BlockId(0), BlockId(0))], 1);
+        assert_eq!(coverage.edge_hits[&(BlockId(0), BlockId(1))], 1);
+
+        let hits = trace.hit_counts();
+        assert_eq!(hits.get(BytecodeOffset::new(start)), Some(2));
+        assert_eq!(hits.get(BytecodeOffset::new(1)), Some(0));
+    }
+}
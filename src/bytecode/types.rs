@@ -1,15 +1,172 @@
 /// Core newtypes for type safety
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Sub};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Name(pub String);
+use lasso::{Spur, ThreadedRodeo};
+use serde::{Deserialize, Serialize};
+
+/// Process-wide name table; every distinct name string is stored once
+static NAMES: LazyLock<ThreadedRodeo> = LazyLock::new(ThreadedRodeo::new);
+static NAME_LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+static NAME_LOOKUP_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// An interned FName: its text and its instance number
+///
+/// FNames keep a numeric suffix apart from the base name, storing `Door_2`
+/// as `Door` with number 3; number 0 means no suffix. Names read from
+/// bytecode keep the number, so a name whose base really is `Door_2` stays
+/// distinct from `Door` numbered 3, though both print as `Door_2`.
+///
+/// Like FNames, names compare and hash ignoring ASCII case while keeping the
+/// casing they were created with for printing, so `OnHit` and `onhit` are
+/// the same name.
+#[derive(Clone, Copy)]
+pub struct Name {
+    text: Spur,
+    /// `text` in lowercase, which comparisons use
+    folded: Spur,
+    number: u32,
+}
+
+// Names are created and resolved from many threads at once: the table is
+// lock-free for readers and the counters are relaxed atomics
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Name>();
+    assert_send_sync::<ThreadedRodeo>();
+};
 
 impl Name {
-    pub fn new(s: impl Into<String>) -> Self {
-        Self(s.into())
+    /// A name with no number, such as one written out in the dump
+    pub fn new(s: impl AsRef<str>) -> Self {
+        Self::intern(s.as_ref(), 0)
+    }
+
+    /// A name as FNames store it, `base` with `number` one more than the
+    /// suffix it prints with
+    pub fn with_number(base: &str, number: u32) -> Self {
+        match number {
+            0 => Self::new(base),
+            _ => Self::intern(&format!("{}_{}", base, number - 1), number),
+        }
+    }
+
+    fn intern(text: &str, number: u32) -> Self {
+        NAME_LOOKUPS.fetch_add(1, Ordering::Relaxed);
+        NAME_LOOKUP_BYTES.fetch_add(text.len(), Ordering::Relaxed);
+        let folded = if text.bytes().any(|b| b.is_ascii_uppercase()) {
+            NAMES.get_or_intern(text.to_ascii_lowercase())
+        } else {
+            NAMES.get_or_intern(text)
+        };
+        Self {
+            text: NAMES.get_or_intern(text),
+            folded,
+            number,
+        }
+    }
+
+    /// The name as the engine prints it, with any number as a `_N` suffix
+    pub fn as_str(&self) -> &'static str {
+        NAMES.resolve(&self.text)
+    }
+
+    /// The name without its number's suffix
+    pub fn base(&self) -> &'static str {
+        let text = self.as_str();
+        match self.number {
+            0 => text,
+            number => {
+                let suffix_len = (number - 1).to_string().len() + 1;
+                &text[..text.len() - suffix_len]
+            }
+        }
+    }
+
+    /// The FName number, one more than the printed suffix, or 0 for none
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Whether the name prints as `text`, ignoring ASCII case
+    pub fn matches(&self, text: &str) -> bool {
+        self.as_str().eq_ignore_ascii_case(text)
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.folded == other.folded && self.number == other.number
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded.hash(state);
+        self.number.hash(state);
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name {
+    /// Alphabetical ignoring case, then by number
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let folded = |name: &Self| NAMES.resolve(&name.folded);
+        folded(self)
+            .cmp(folded(other))
+            .then(self.number.cmp(&other.number))
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tuple = f.debug_tuple("Name");
+        tuple.field(&self.base());
+        if self.number != 0 {
+            tuple.field(&self.number);
+        }
+        tuple.finish()
+    }
+}
+
+/// Counters describing how much the name table has deduplicated
+#[derive(Debug, Clone, Copy)]
+pub struct InternerStats {
+    /// Distinct strings stored in the table
+    pub unique_names: usize,
+    /// Bytes of string data stored in the table
+    pub unique_bytes: usize,
+    /// Number of `Name`s created
+    pub lookups: usize,
+    /// Bytes of string data across every created `Name`
+    pub lookup_bytes: usize,
+}
+
+impl InternerStats {
+    /// Estimated memory saved compared to storing every name as an owned `String`
+    pub fn bytes_saved(&self) -> usize {
+        let owned = self.lookups * size_of::<String>() + self.lookup_bytes;
+        let interned = self.lookups * size_of::<Name>() + self.unique_bytes;
+        owned.saturating_sub(interned)
     }
+}
 
-    pub fn as_str(&self) -> &str {
-        &self.0
+pub fn interner_stats() -> InternerStats {
+    InternerStats {
+        unique_names: NAMES.len(),
+        unique_bytes: NAMES.strings().map(str::len).sum(),
+        lookups: NAME_LOOKUPS.load(Ordering::Relaxed),
+        lookup_bytes: NAME_LOOKUP_BYTES.load(Ordering::Relaxed),
     }
 }
 
@@ -26,7 +183,13 @@ impl Address {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:X}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct BytecodeOffset(pub usize);
 
 impl BytecodeOffset {
@@ -38,3 +201,256 @@ impl BytecodeOffset {
         self.0
     }
 }
+
+impl BytecodeOffset {
+    pub fn checked_add(self, bytes: usize) -> Option<Self> {
+        self.0.checked_add(bytes).map(Self)
+    }
+
+    /// Number of bytes from `earlier` to this offset, if it isn't after it
+    pub fn checked_distance_from(self, earlier: BytecodeOffset) -> Option<usize> {
+        self.0.checked_sub(earlier.0)
+    }
+}
+
+impl Add<usize> for BytecodeOffset {
+    type Output = BytecodeOffset;
+
+    fn add(self, bytes: usize) -> Self::Output {
+        self.checked_add(bytes)
+            .unwrap_or_else(|| panic!("bytecode offset {} + {} overflows", self, bytes))
+    }
+}
+
+impl Sub<BytecodeOffset> for BytecodeOffset {
+    type Output = usize;
+
+    fn sub(self, earlier: BytecodeOffset) -> Self::Output {
+        self.checked_distance_from(earlier)
+            .unwrap_or_else(|| panic!("bytecode offset {} is before {}", self, earlier))
+    }
+}
+
+impl fmt::Display for BytecodeOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:X}", self.0)
+    }
+}
+
+/// Jump target label for a bytecode offset, rendered as `Label_0x1A`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(pub BytecodeOffset);
+
+impl Label {
+    pub fn new(offset: BytecodeOffset) -> Self {
+        Self(offset)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Label_{}", self.0)
+    }
+}
+
+/// Half-open range of bytecode offsets `[start, end)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OffsetRange {
+    pub start: BytecodeOffset,
+    pub end: BytecodeOffset,
+}
+
+impl OffsetRange {
+    /// Create a range, panicking if `end` is before `start`
+    pub fn new(start: BytecodeOffset, end: BytecodeOffset) -> Self {
+        assert!(start <= end, "offset range {}..{} is inverted", start, end);
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+}
+
+impl fmt::Display for OffsetRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// A float constant from the bytecode, compared and hashed by its bits
+///
+/// Bitwise comparison makes it usable as a key: a NaN equals itself and
+/// `0.0` and `-0.0` stay distinct, as they are in the script.
+#[derive(Clone, Copy)]
+pub struct F32(pub f32);
+
+impl F32 {
+    pub fn get(self) -> f32 {
+        self.0
+    }
+}
+
+impl PartialEq for F32 {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for F32 {}
+
+impl Hash for F32 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl PartialEq<f32> for F32 {
+    fn eq(&self, other: &f32) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Debug for F32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for F32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// A double constant from the bytecode, compared and hashed by its bits
+/// like [`F32`]
+#[derive(Clone, Copy)]
+pub struct F64(pub f64);
+
+impl F64 {
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl PartialEq for F64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for F64 {}
+
+impl Hash for F64 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl fmt::Debug for F64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for F64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// A component of a vector, rotator or transform constant
+///
+/// UE5's large world coordinates made these doubles; before that they were
+/// floats. Keeping the width read means neither is printed as the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Real {
+    Single(F32),
+    Double(F64),
+}
+
+impl PartialEq<f64> for Real {
+    fn eq(&self, other: &f64) -> bool {
+        match self {
+            Real::Single(value) => f64::from(value.get()) == *other,
+            Real::Double(value) => value.get() == *other,
+        }
+    }
+}
+
+impl fmt::Display for Real {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Real::Single(value) => value.fmt(f),
+            Real::Double(value) => value.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_are_interned() {
+        let a = Name::new("ReceiveBeginPlay");
+        let b = Name::new(String::from("ReceiveBeginPlay"));
+        assert_eq!(a, b);
+        assert_ne!(a, Name::new("ReceiveTick"));
+        assert_eq!(b.as_str(), "ReceiveBeginPlay");
+    }
+
+    #[test]
+    fn test_numbered_names_keep_their_base() {
+        let numbered = Name::with_number("Door", 3);
+        assert_eq!(numbered.as_str(), "Door_2");
+        assert_eq!((numbered.base(), numbered.number()), ("Door", 3));
+        // A base that ends in what looks like a suffix is a different name
+        let genuine = Name::new("Door_2");
+        assert_eq!(genuine.base(), "Door_2");
+        assert_ne!(numbered, genuine);
+        assert_eq!(Name::with_number("Door", 0), Name::new("Door"));
+    }
+
+    #[test]
+    fn test_names_ignore_case() {
+        let mixed = Name::new("OnHit_Event");
+        let lower = Name::new("onhit_event");
+        assert_eq!(mixed, lower);
+        assert_eq!(mixed.as_str(), "OnHit_Event");
+        assert!(mixed.matches("ONHIT_EVENT"));
+        let set: std::collections::HashSet<Name> = [mixed, lower].into_iter().collect();
+        assert_eq!(set.len(), 1);
+        assert!(Name::new("apple") < Name::new("Banana"));
+    }
+
+    #[test]
+    fn test_offset_arithmetic() {
+        let offset = BytecodeOffset::new(0x10);
+        assert_eq!(offset + 0xA, BytecodeOffset::new(0x1A));
+        assert_eq!(BytecodeOffset::new(0x1A) - offset, 0xA);
+        assert_eq!(
+            offset.checked_distance_from(BytecodeOffset::new(0x20)),
+            None
+        );
+        assert_eq!(BytecodeOffset::new(usize::MAX).checked_add(1), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(BytecodeOffset::new(0x1A3).to_string(), "0x1A3");
+        assert_eq!(
+            Label::new(BytecodeOffset::new(0x1A3)).to_string(),
+            "Label_0x1A3"
+        );
+
+        let range = OffsetRange::new(BytecodeOffset::new(4), BytecodeOffset::new(0x10));
+        assert_eq!(range.to_string(), "0x4..0x10");
+        assert_eq!(range.len(), 12);
+    }
+}
@@ -0,0 +1,203 @@
+/// Locating events' code inside the ubergraph
+///
+/// Blueprint events don't hold their own code. Each compiles to a stub that
+/// calls `ExecuteUbergraph_<Class>` with an entry offset, and the event's code
+/// sits at that offset in the ubergraph function shared by the whole class.
+use std::collections::HashMap;
+
+use super::expr::{Expr, ExprKind};
+use super::pattern::{Access, called_function, property_accesses};
+use super::refs::{FunctionRef, PropertyRef};
+use super::types::BytecodeOffset;
+
+/// The ubergraph function and entry offset an event stub jumps into, if any
+pub fn ubergraph_entry(
+    expressions: &[Expr],
+    resolve: impl Fn(&FunctionRef) -> Option<String>,
+) -> Option<(String, BytecodeOffset)> {
+    let mut entry = None;
+    for expr in expressions {
+        expr.walk(&mut |e| {
+            if entry.is_some() {
+                return;
+            }
+            let params = match &e.kind {
+                ExprKind::LocalFinalFunction { params, .. }
+                | ExprKind::FinalFunction { params, .. } => params,
+                _ => return,
+            };
+            if let Some(path) = called_function(e).and_then(&resolve)
+                && path.contains(":ExecuteUbergraph")
+                && let [param] = params.as_slice()
+                && let ExprKind::IntConst(offset) = param.kind
+            {
+                entry = Some((path, BytecodeOffset::new(offset as usize)));
+            }
+        });
+    }
+    entry
+}
+
+/// The straight run of ubergraph statements an event starts at `entry`
+///
+/// Stops at the first statement that leaves the event's code: a return, an
+/// unconditional jump, or a pop of the execution flow stack. Branches inside
+/// the event are followed only as far as they stay in that run.
+pub fn event_slice(ubergraph: &[Expr], entry: BytecodeOffset) -> &[Expr] {
+    let start = ubergraph.partition_point(|expr| expr.offset < entry);
+    let len = ubergraph[start..]
+        .iter()
+        .position(|expr| {
+            matches!(
+                expr.kind,
+                ExprKind::Return(_)
+                    | ExprKind::Jump { .. }
+                    | ExprKind::PopExecutionFlow
+                    | ExprKind::EndOfScript
+            )
+        })
+        .map_or(ubergraph.len() - start, |end| end + 1);
+    &ubergraph[start..start + len]
+}
+
+/// An event's code with the stub's parameters used directly
+///
+/// The stub copies each parameter into a frame property before calling the
+/// ubergraph, and `slice`, the event's code as [`event_slice`] finds it,
+/// reads them back from there. Frame properties the slice never assigns are
+/// replaced by the parameter copied into them. The stub's other statements
+/// before the call are kept, in front of the slice; the call and whatever
+/// follows it are dropped. `resolve` gives the path of a called function.
+pub fn fold_event(
+    stub: &[Expr],
+    slice: &[Expr],
+    resolve: impl Fn(&FunctionRef) -> Option<String>,
+) -> Vec<Expr> {
+    let written: Vec<PropertyRef> = property_accesses(slice)
+        .into_iter()
+        .filter(|&(_, _, access)| access == Access::Write)
+        .map(|(_, property, _)| property)
+        .collect();
+    let mut parameters: HashMap<PropertyRef, &Expr> = HashMap::new();
+    let mut folded = Vec::new();
+    for statement in stub {
+        match &statement.kind {
+            ExprKind::LetValueOnPersistentFrame { property, value }
+                if matches!(
+                    value.kind,
+                    ExprKind::LocalVariable(_) | ExprKind::LocalOutVariable(_)
+                ) && !written.contains(property) =>
+            {
+                parameters.insert(*property, value);
+            }
+            _ if ubergraph_entry(std::slice::from_ref(statement), &resolve).is_some() => break,
+            _ => folded.push(statement.clone()),
+        }
+    }
+    folded.extend(slice.iter().map(|expr| {
+        expr.clone().rewrite(&mut |e| match &e.kind {
+            ExprKind::LocalVariable(property) => match parameters.get(property) {
+                Some(parameter) => Expr::new(e.offset, parameter.kind.clone()).with_end(e.end),
+                None => e,
+            },
+            _ => e,
+        })
+    }));
+    folded
+}
+
+/// The event graph variable an ubergraph frame property holds
+///
+/// Event nodes hand their outputs to the ubergraph through frame properties
+/// named after the node and pin, such as `K2Node_Event_DeltaSeconds` or
+/// `K2Node_ComponentBoundEvent_OtherActor`; this is the pin's name. Other
+/// frame properties, such as call results, keep their name.
+pub fn event_variable_name(property: &str) -> &str {
+    property
+        .strip_prefix("K2Node_")
+        .and_then(|rest| rest.split_once("Event_"))
+        .map_or(property, |(_, pin)| pin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::testing::{at, by_name, call, call_with};
+    use crate::bytecode::types::{Address, Name};
+
+    #[test]
+    fn test_event_slice_stops_at_pop() {
+        let ubergraph = [
+            call(0, "A"),
+            at(5, ExprKind::PopExecutionFlow),
+            call(6, "B"),
+            at(11, ExprKind::PopExecutionFlow),
+        ];
+        let slice = event_slice(&ubergraph, BytecodeOffset::new(6));
+        assert_eq!(slice.len(), 2);
+        assert_eq!(slice[0].offset, BytecodeOffset::new(6));
+    }
+
+    #[test]
+    fn test_event_variable_name() {
+        assert_eq!(
+            event_variable_name("K2Node_Event_DeltaSeconds"),
+            "DeltaSeconds"
+        );
+        assert_eq!(event_variable_name("K2Node_CustomEvent_Amount"), "Amount");
+        assert_eq!(
+            event_variable_name("K2Node_ComponentBoundEvent_OtherActor"),
+            "OtherActor"
+        );
+        assert_eq!(
+            event_variable_name("CallFunc_GetActorLocation_ReturnValue"),
+            "CallFunc_GetActorLocation_ReturnValue"
+        );
+    }
+
+    #[test]
+    fn test_fold_event() {
+        let local = |address| ExprKind::LocalVariable(PropertyRef::new(Address::new(address)));
+        let frame = |offset, property, value| {
+            at(
+                offset,
+                ExprKind::LetValueOnPersistentFrame {
+                    property: PropertyRef::new(Address::new(property)),
+                    value: Box::new(at(offset + 9, value)),
+                },
+            )
+        };
+        let ubergraph = FunctionRef::ByName(Name::new("/Game/BP.BP_C:ExecuteUbergraph_BP"));
+        // Frame[10] = Delta; Frame[11] = 3; ExecuteUbergraph(100); return;
+        let stub = [
+            frame(0, 10, local(1)),
+            frame(18, 11, ExprKind::IntConst(3)),
+            at(
+                36,
+                ExprKind::LocalFinalFunction {
+                    func: ubergraph,
+                    params: vec![at(45, ExprKind::IntConst(100))],
+                },
+            ),
+            at(51, ExprKind::Return(Box::new(at(52, ExprKind::Nothing)))),
+        ];
+        // Log(Frame[10], Frame[11]); Frame[12] = 0; Log(Frame[12])
+        let log = |offset, params| call_with(offset, "/Game/BP.BP_C:Log", params);
+        let slice = [
+            log(100, vec![at(109, local(10)), at(118, local(11))]),
+            frame(128, 12, ExprKind::IntZero),
+            log(146, vec![at(155, local(12))]),
+        ];
+        let folded = fold_event(&stub, &slice, by_name);
+        // Only the constant's copy is left of the stub
+        assert_eq!(folded.len(), 4);
+        assert_eq!(folded[0], stub[1]);
+        let ExprKind::CallMath { params, .. } = &folded[1].kind else {
+            panic!("expected the first call, got {:?}", folded[1].kind);
+        };
+        assert_eq!(params[0].kind, local(1));
+        assert_eq!(params[0].offset, BytecodeOffset::new(109));
+        assert_eq!(params[1].kind, local(11));
+        assert_eq!(folded[3], slice[2]);
+    }
+}
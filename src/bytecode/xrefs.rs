@@ -0,0 +1,158 @@
+/// Where each object, property and function is used from scripts
+///
+/// Only uses by address are indexed: properties read or written, functions
+/// called through a final call, and object and property constants. Virtual
+/// calls name their function rather than pointing at it, so they're left to
+/// the call graph.
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::expr::{Expr, ExprKind};
+use super::pattern::{Access, called_function, property_accesses};
+use super::refs::FunctionRef;
+use super::types::{Address, BytecodeOffset};
+
+/// How a script uses an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum XrefKind {
+    Read,
+    Write,
+    Call,
+    /// Passes the object or property itself as a value
+    Constant,
+}
+
+impl XrefKind {
+    /// Name of the kind in exported tables
+    pub fn name(self) -> &'static str {
+        match self {
+            XrefKind::Read => "read",
+            XrefKind::Write => "write",
+            XrefKind::Call => "call",
+            XrefKind::Constant => "constant",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            XrefKind::Read => "reads",
+            XrefKind::Write => "writes",
+            XrefKind::Call => "calls",
+            XrefKind::Constant => "refers to",
+        }
+    }
+}
+
+/// One use of an address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Xref {
+    /// Full path of the function using it
+    pub function: String,
+    pub offset: BytecodeOffset,
+    pub kind: XrefKind,
+}
+
+/// Uses of every address, by address
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct XrefIndex {
+    pub uses: BTreeMap<u64, Vec<Xref>>,
+}
+
+impl XrefIndex {
+    /// Record the uses in one function's script
+    pub fn add_function(&mut self, function: &str, expressions: &[Expr]) {
+        let mut found: Vec<(Address, BytecodeOffset, XrefKind)> = property_accesses(expressions)
+            .into_iter()
+            .map(|(offset, property, access)| {
+                let kind = match access {
+                    Access::Read => XrefKind::Read,
+                    Access::Write => XrefKind::Write,
+                };
+                (property.address, offset, kind)
+            })
+            .collect();
+        for expr in expressions {
+            expr.walk(&mut |e| {
+                if let Some(FunctionRef::ByAddress(address)) = called_function(e) {
+                    found.push((*address, e.offset, XrefKind::Call));
+                }
+                match &e.kind {
+                    ExprKind::ObjectConst(object) => {
+                        found.push((object.address, e.offset, XrefKind::Constant))
+                    }
+                    ExprKind::PropertyConst(property) => {
+                        found.push((property.address, e.offset, XrefKind::Constant))
+                    }
+                    _ => {}
+                }
+            });
+        }
+        found.sort_by_key(|&(_, offset, _)| offset);
+        for (address, offset, kind) in found {
+            self.uses.entry(address.0).or_default().push(Xref {
+                function: function.to_string(),
+                offset,
+                kind,
+            });
+        }
+    }
+
+    /// Uses of `address`, by function in the order they were added and then
+    /// by offset
+    pub fn uses_of(&self, address: Address) -> &[Xref] {
+        self.uses.get(&address.0).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::refs::{ObjectRef, PropertyRef};
+
+    #[test]
+    fn test_uses_by_address() {
+        let at = |offset, kind| Expr::new(BytecodeOffset::new(offset), kind);
+        let health = PropertyRef::new(Address::new(0x20));
+        // Health = Heal(Health, SomeAsset)
+        let script = [at(
+            0,
+            ExprKind::Let {
+                property: health,
+                variable: Box::new(at(9, ExprKind::InstanceVariable(health))),
+                value: Box::new(at(
+                    18,
+                    ExprKind::FinalFunction {
+                        func: FunctionRef::ByAddress(Address::new(0x30)),
+                        params: vec![
+                            at(27, ExprKind::InstanceVariable(health)),
+                            at(
+                                36,
+                                ExprKind::ObjectConst(ObjectRef::new(Address::new(0x40))),
+                            ),
+                        ],
+                    },
+                )),
+            },
+        )];
+        let mut index = XrefIndex::default();
+        index.add_function("/Game/BP.BP_C:Regen", &script);
+        index.add_function("/Game/BP.BP_C:Nothing", &[]);
+
+        let kinds = |address| -> Vec<_> {
+            index
+                .uses_of(Address::new(address))
+                .iter()
+                .map(|xref| (xref.offset.0, xref.kind))
+                .collect()
+        };
+        assert_eq!(kinds(0x20), [(9, XrefKind::Write), (27, XrefKind::Read)]);
+        assert_eq!(kinds(0x30), [(18, XrefKind::Call)]);
+        assert_eq!(kinds(0x40), [(36, XrefKind::Constant)]);
+        assert!(kinds(0x50).is_empty());
+        assert_eq!(
+            index.uses_of(Address::new(0x30))[0].function,
+            "/Game/BP.BP_C:Regen"
+        );
+    }
+}
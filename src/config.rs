@@ -0,0 +1,187 @@
+//! Project config file (`kismet.toml`) holding defaults for command-line flags
+//!
+//! Values from the config are only used where the command line doesn't
+//! provide one, so any flag overrides the config. Boolean flags have a `--no-`
+//! form for turning off what the config turns on.
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{OutputFormat, profile::Profile};
+
+/// Looked up in the current directory when `--config` isn't given
+pub const DEFAULT_CONFIG_FILE: &str = "kismet.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Default function filter for every command
+    pub filter: Option<String>,
+    /// Files mapping generated names to readable ones, used when
+    /// `--rename-map` isn't given
+    pub rename_maps: Vec<PathBuf>,
+    /// Directory `disassemble` writes its output into and `export` writes its
+    /// files into, unless the command line gives one
+    pub output_dir: Option<PathBuf>,
+    pub theme: ThemeConfig,
+    pub disassemble: DisassembleConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// Whether to color output; by default colors are used when writing to a
+    /// terminal, unless `NO_COLOR` or `FORCE_COLOR` is set
+    pub color: ColorMode,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Decide whether output is colored; only `auto` looks at the environment
+    pub fn apply(self) {
+        let colors = match self {
+            ColorMode::Auto => env_colors(
+                std::env::var("NO_COLOR").ok().as_deref(),
+                std::env::var("FORCE_COLOR").ok().as_deref(),
+            ),
+            ColorMode::Always => Some(true),
+            ColorMode::Never => Some(false),
+        };
+        if let Some(colors) = colors {
+            colored::control::set_override(colors);
+        }
+    }
+}
+
+/// Whether the `NO_COLOR` and `FORCE_COLOR` variables ask for colors, or
+/// `None` to color only output to a terminal
+///
+/// A `FORCE_COLOR` of `0` or `false` turns colors off, any other non-empty
+/// one turns them on even with `NO_COLOR` set; otherwise a non-empty
+/// `NO_COLOR` turns them off.
+fn env_colors(no_color: Option<&str>, force_color: Option<&str>) -> Option<bool> {
+    match force_color.filter(|value| !value.is_empty()) {
+        Some("0" | "false") => Some(false),
+        Some(_) => Some(true),
+        None => no_color
+            .is_some_and(|value| !value.is_empty())
+            .then_some(false),
+    }
+}
+
+/// Defaults for the `disassemble` command's flags
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisassembleConfig {
+    pub format: Option<OutputFormat>,
+    pub profile: Option<Profile>,
+    pub show_block_ids: Option<bool>,
+    pub show_bytecode_offsets: Option<bool>,
+    pub show_terminator_exprs: Option<bool>,
+    pub split_fail_silent: Option<bool>,
+    pub model_asserts: Option<bool>,
+    pub show_costs: Option<bool>,
+    pub valid_cpp: Option<bool>,
+    pub faithful_casts: Option<bool>,
+    pub explain: Option<bool>,
+    pub show_prop_offsets: Option<bool>,
+    pub show_loop_bounds: Option<bool>,
+    pub show_unused_locals: Option<bool>,
+    pub prune_constant_branches: Option<bool>,
+    pub inline_getters: Option<bool>,
+    pub inline_array_gets: Option<bool>,
+    pub fold_events: Option<bool>,
+    pub max_script_size: Option<usize>,
+    pub limit: Option<usize>,
+    pub pager: Option<bool>,
+    /// Same syntax as `--timeout-per-function`, e.g. `"10s"`
+    pub timeout_per_function: Option<String>,
+}
+
+impl Config {
+    /// Load the config at `path`, or `kismet.toml` in the current directory if
+    /// no path is given and that file exists
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => Path::new(DEFAULT_CONFIG_FILE),
+            None => return Ok(Self::default()),
+        };
+
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("reading {}: {}", path.display(), e))?;
+        let mut config: Config =
+            toml::from_str(&text).map_err(|e| format!("parsing {}: {}", path.display(), e))?;
+
+        // Paths in the config are relative to the file
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for rename_map in &mut config.rename_maps {
+            *rename_map = dir.join(&rename_map);
+        }
+        if let Some(output_dir) = &mut config.output_dir {
+            *output_dir = dir.join(&output_dir);
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            filter = "BP_Player"
+            rename_maps = ["names.csv"]
+
+            [theme]
+            color = "never"
+
+            [disassemble]
+            format = "structured"
+            profile = "faithful"
+            split_fail_silent = true
+            timeout_per_function = "10s"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.filter.as_deref(), Some("BP_Player"));
+        assert_eq!(config.rename_maps, vec![PathBuf::from("names.csv")]);
+        assert_eq!(config.theme.color, ColorMode::Never);
+        assert!(matches!(
+            config.disassemble.format,
+            Some(OutputFormat::Structured)
+        ));
+        assert_eq!(config.disassemble.profile, Some(Profile::Faithful));
+        assert_eq!(config.disassemble.split_fail_silent, Some(true));
+        assert_eq!(config.disassemble.model_asserts, None);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("[disassemble]\nformatt = \"cpp\"").is_err());
+    }
+
+    #[test]
+    fn test_color_environment() {
+        assert_eq!(env_colors(None, None), None);
+        assert_eq!(env_colors(Some("1"), None), Some(false));
+        assert_eq!(env_colors(Some(""), None), None);
+        assert_eq!(env_colors(None, Some("1")), Some(true));
+        assert_eq!(env_colors(Some("1"), Some("3")), Some(true));
+        assert_eq!(env_colors(None, Some("0")), Some(false));
+        assert_eq!(env_colors(Some("1"), Some("")), Some(false));
+    }
+}
@@ -0,0 +1,271 @@
+//! Decompiling a dump's functions into data rather than printed output
+//!
+//! [`Decompiler`] runs the steps `disassemble` does before printing a
+//! function: decoding its script, building the CFG, finding dominators and
+//! loops, and structuring. Each step's result is kept in the
+//! [`DecompiledFunction`] returned, so callers can walk the expressions,
+//! graph and structured tree themselves.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::bytecode::{
+    address_index::AddressIndex,
+    cfg::{CfgOptions, ControlFlowGraph},
+    dominators::DominatorTree,
+    expr::Expr,
+    logger::NullLogger,
+    loops::{LoopInfo, normalize_loops},
+    opcodes::OpcodeMap,
+    parser::{EngineVersion, ParseDiagnostic, ScriptParser},
+    reader::ScriptReader,
+    structured::{PhoenixStructurer, StructuredGraph},
+};
+use crate::error::KismetError;
+
+/// Decompiles the functions of one dump
+pub struct Decompiler<'j> {
+    jmap: &'j jmap::Jmap,
+    address_index: AddressIndex<'j>,
+    /// Used when the dump has no name map
+    no_names: BTreeMap<u32, String>,
+    engine_version: EngineVersion,
+    opcode_map: Option<&'j OpcodeMap>,
+    cfg_options: CfgOptions,
+    /// Time allowed for structuring each function
+    timeout: Option<Duration>,
+}
+
+/// What each step of decompiling a function produced
+#[derive(Debug, Clone)]
+pub struct DecompiledFunction {
+    /// Full path of the function
+    pub path: String,
+    /// The script's statements
+    pub expressions: Vec<Expr>,
+    /// Inconsistencies the parser noticed without failing, such as
+    /// mismatched skip offsets
+    pub diagnostics: Vec<ParseDiagnostic>,
+    /// Control flow, with a pre-header given to every loop
+    pub cfg: ControlFlowGraph,
+    pub dominators: DominatorTree,
+    pub loops: LoopInfo,
+    /// The structured control flow, or `None` if some of it couldn't be
+    /// structured
+    pub structured: Option<StructuredGraph>,
+}
+
+impl<'j> Decompiler<'j> {
+    pub fn new(jmap: &'j jmap::Jmap) -> Self {
+        Self::from_address_index(AddressIndex::new(jmap))
+    }
+
+    /// Decompile the dump `address_index` was built from, resolving
+    /// references with it, such as one with renames or loaded from a session
+    pub fn from_address_index(address_index: AddressIndex<'j>) -> Self {
+        Self {
+            jmap: address_index.jmap,
+            address_index,
+            no_names: BTreeMap::new(),
+            engine_version: EngineVersion::default(),
+            opcode_map: None,
+            cfg_options: CfgOptions::default(),
+            timeout: None,
+        }
+    }
+
+    /// Decode scripts compiled by this engine generation
+    pub fn with_engine_version(mut self, version: EngineVersion) -> Self {
+        self.engine_version = version;
+        self
+    }
+
+    /// Decode scripts from a VM that numbers its opcodes differently
+    pub fn with_opcode_map(mut self, map: &'j OpcodeMap) -> Self {
+        self.opcode_map = Some(map);
+        self
+    }
+
+    pub fn with_cfg_options(mut self, options: CfgOptions) -> Self {
+        self.cfg_options = options;
+        self
+    }
+
+    /// Give up structuring a function after this long, failing with
+    /// [`KismetError::Structuring`]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Index of the dump's objects and properties by address, for resolving
+    /// the references in decompiled expressions and for the formatters
+    pub fn address_index(&self) -> &AddressIndex<'j> {
+        &self.address_index
+    }
+
+    /// Paths of the functions that have a script, in order
+    pub fn functions(&self) -> Vec<&'j str> {
+        let mut paths: Vec<&str> = self
+            .jmap
+            .objects
+            .iter()
+            .filter_map(|(path, obj)| match obj {
+                jmap::ObjectType::Function(func) if !func.r#struct.script.is_empty() => {
+                    Some(path.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        paths.sort_unstable();
+        paths
+    }
+
+    /// Decompile the function at `path`
+    pub fn decompile(&self, path: &str) -> Result<DecompiledFunction, KismetError> {
+        let mut diagnostics = Vec::new();
+        let expressions = self.parse(path, &mut diagnostics)?;
+        let (cfg, dominators, loops) = self.build_graph(&expressions);
+        let structured = self.structure(&cfg, &loops)?;
+
+        Ok(DecompiledFunction {
+            path: path.to_string(),
+            expressions,
+            diagnostics,
+            cfg,
+            dominators,
+            loops,
+            structured,
+        })
+    }
+
+    /// Decode the script of the function at `path`, the first step of
+    /// [`Self::decompile`]
+    ///
+    /// What the parser noticed is added to `diagnostics` even if decoding
+    /// fails.
+    pub fn parse(
+        &self,
+        path: &str,
+        diagnostics: &mut Vec<ParseDiagnostic>,
+    ) -> Result<Vec<Expr>, KismetError> {
+        let Some(jmap::ObjectType::Function(func)) = self.jmap.objects.get(path) else {
+            return Err(KismetError::UnknownFunction(path.to_string()));
+        };
+        let names = self.jmap.names.as_ref().unwrap_or(&self.no_names);
+        let mut parser = ScriptParser::new(ScriptReader::new(&func.r#struct.script, names))
+            .with_engine_version(self.engine_version);
+        if let Some(map) = self.opcode_map {
+            parser = parser.with_opcode_map(map);
+        }
+        let expressions = parser.parse_all();
+        diagnostics.extend_from_slice(parser.diagnostics());
+        expressions
+    }
+
+    /// Build the CFG of a decoded script as the bytecode jumps, with no
+    /// blocks added
+    pub fn build_cfg(&self, expressions: &[Expr]) -> ControlFlowGraph {
+        ControlFlowGraph::from_expressions_with_options(expressions, &self.cfg_options, &NullLogger)
+    }
+
+    /// Build the CFG of a decoded script, giving every loop a pre-header,
+    /// and find its dominators and loops
+    pub fn build_graph(&self, expressions: &[Expr]) -> (ControlFlowGraph, DominatorTree, LoopInfo) {
+        let mut cfg = self.build_cfg(expressions);
+        let (dominators, loops) = normalize_loops(&mut cfg);
+        (cfg, dominators, loops)
+    }
+
+    /// Structure a graph from [`Self::build_graph`], within the timeout if
+    /// one was given
+    pub fn structure(
+        &self,
+        cfg: &ControlFlowGraph,
+        loops: &LoopInfo,
+    ) -> Result<Option<StructuredGraph>, KismetError> {
+        let mut structurer = PhoenixStructurer::new(cfg, loops);
+        if let Some(timeout) = self.timeout {
+            structurer = structurer.with_deadline(Instant::now() + timeout);
+        }
+        structurer.try_structure()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::testing::BytecodeBuilder;
+    use crate::loader::{RAW_SCRIPT_FUNCTION, raw_script_jmap};
+
+    #[test]
+    fn test_decompile() {
+        // top: if (!true) goto end; goto top; end: return
+        let mut b = BytecodeBuilder::default();
+        let top = b.here();
+        b.op(EExprToken::JumpIfNot);
+        let end = b.placeholder();
+        b.op(EExprToken::True);
+        b.op(EExprToken::Jump);
+        let back = b.placeholder();
+        let end_offset = b.here();
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        b.patch(end, end_offset);
+        b.patch(back, top);
+
+        let (jmap, _) = raw_script_jmap(b.build(), Default::default()).unwrap();
+        let decompiler = Decompiler::new(&jmap);
+        assert_eq!(decompiler.functions(), [RAW_SCRIPT_FUNCTION]);
+        let function = decompiler.decompile(RAW_SCRIPT_FUNCTION).unwrap();
+        assert_eq!(function.expressions.len(), 3);
+        assert!(function.diagnostics.is_empty());
+        assert_eq!(function.loops.loops.len(), 1);
+        assert!(function.structured.is_some());
+
+        assert!(matches!(
+            decompiler.decompile("/Raw/Script:Missing"),
+            Err(KismetError::UnknownFunction(_))
+        ));
+    }
+
+    #[test]
+    fn test_stages_are_traced() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let mut b = BytecodeBuilder::default();
+        b.op(EExprToken::Return).op(EExprToken::Nothing);
+        let (jmap, _) = raw_script_jmap(b.build(), Default::default()).unwrap();
+        let decompiler = Decompiler::new(&jmap);
+
+        let path = std::env::temp_dir().join("kismet-test-trace.json");
+        let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+            .writer(std::fs::File::create(&path).unwrap())
+            .build();
+        // Only spans on this thread, while decompiling, are recorded
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            decompiler.decompile(RAW_SCRIPT_FUNCTION).unwrap();
+        });
+        drop(guard);
+
+        let trace: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let begun: Vec<&str> = trace
+            .iter()
+            .filter(|event| event["ph"] == "B")
+            .filter_map(|event| event["name"].as_str())
+            .collect();
+        assert_eq!(
+            begun,
+            [
+                "parse",
+                "cfg",
+                "dominators",
+                "loops",
+                "preheaders",
+                "structure"
+            ]
+        );
+    }
+}
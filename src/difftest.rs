@@ -0,0 +1,181 @@
+//! Differential testing against another decompiler
+//!
+//! The reference is run on each function and its output compared with ours
+//! by the functions called, in the order the calls appear. Decompilers print
+//! calls differently, so only the called function's own name is compared,
+//! and only names of functions the jmap knows count as calls in the
+//! reference's output; everything else in its text is ignored.
+//!
+//! The reference command is a template run through the shell, with
+//! `{jmap}`, `{function}` and `{script}` replaced by the jmap's path, the
+//! function's path and a file holding the function's script bytes:
+//!
+//! ```text
+//! kismet-analyzer decompile {script}
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+
+/// A difference between the calls we decoded and the reference's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// A call only the reference has
+    Missing { name: String, position: usize },
+    /// A call only we have
+    Extra { name: String, position: usize },
+    /// A call both have, but in a different place relative to the others
+    Moved { name: String },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing { name, position } => {
+                write!(
+                    f,
+                    "missing call {} (reference call #{})",
+                    name,
+                    position + 1
+                )
+            }
+            Self::Extra { name, position } => {
+                write!(f, "extra call {} (our call #{})", name, position + 1)
+            }
+            Self::Moved { name } => write!(f, "call {} is in a different order", name),
+        }
+    }
+}
+
+/// Fill in a reference command template
+pub fn reference_command(template: &str, jmap: &str, function: &str, script: &str) -> String {
+    template
+        .replace("{jmap}", &shell_quote(jmap))
+        .replace("{function}", &shell_quote(function))
+        .replace("{script}", &shell_quote(script))
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Names of `functions` called in a decompiler's output, in order
+///
+/// A call is a name directly followed by `(`; qualifiers such as `Class::`
+/// or a path are dropped.
+pub fn called_names(text: &str, functions: &HashSet<&str>) -> Vec<String> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(paren) = rest.find('(') {
+        let before = &rest[..paren];
+        let start = before.trim_end_matches(is_name_char).len();
+        let name = &before[start..];
+        if functions.contains(name) {
+            names.push(name.to_string());
+        }
+        rest = &rest[paren + 1..];
+    }
+    names
+}
+
+/// Compare two call sequences, ours and the reference's
+///
+/// Calls are matched up by the longest common subsequence; a name left
+/// unmatched on both sides was reordered rather than added or lost.
+pub fn compare_calls(ours: &[String], reference: &[String]) -> Vec<Divergence> {
+    // lengths[i][j]: longest common subsequence of ours[i..] and reference[j..]
+    let mut lengths = vec![vec![0usize; reference.len() + 1]; ours.len() + 1];
+    for i in (0..ours.len()).rev() {
+        for j in (0..reference.len()).rev() {
+            lengths[i][j] = if ours[i] == reference[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut extra, mut missing) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < ours.len() || j < reference.len() {
+        if i < ours.len() && j < reference.len() && ours[i] == reference[j] {
+            i += 1;
+            j += 1;
+        } else if j == reference.len() || (i < ours.len() && lengths[i + 1][j] >= lengths[i][j + 1])
+        {
+            extra.push(i);
+            i += 1;
+        } else {
+            missing.push(j);
+            j += 1;
+        }
+    }
+
+    let mut divergences = Vec::new();
+    let mut moved = HashSet::new();
+    for &j in &missing {
+        let name = &reference[j];
+        if let Some(at) = extra.iter().position(|&i| ours[i] == *name) {
+            extra.remove(at);
+            if moved.insert(name) {
+                divergences.push(Divergence::Moved { name: name.clone() });
+            }
+        } else {
+            divergences.push(Divergence::Missing {
+                name: name.clone(),
+                position: j,
+            });
+        }
+    }
+    divergences.extend(extra.into_iter().map(|i| Divergence::Extra {
+        name: ours[i].clone(),
+        position: i,
+    }));
+    divergences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_called_names() {
+        let functions = HashSet::from(["IsValid", "K2_DestroyActor", "Add_IntInt"]);
+        let text = "if (UKismetSystemLibrary::IsValid(Target)) {\n\
+                    \tTarget->K2_DestroyActor();\n\
+                    \tCount = Add_IntInt(Count, 1); // (one more)\n}";
+        assert_eq!(
+            called_names(text, &functions),
+            names(&["IsValid", "K2_DestroyActor", "Add_IntInt"])
+        );
+    }
+
+    #[test]
+    fn test_compare_calls() {
+        let ours = names(&["A", "B", "C", "D"]);
+        assert!(compare_calls(&ours, &ours).is_empty());
+
+        let reference = names(&["A", "C", "B", "E"]);
+        assert_eq!(
+            compare_calls(&ours, &reference),
+            vec![
+                Divergence::Moved {
+                    name: "B".to_string()
+                },
+                Divergence::Missing {
+                    name: "E".to_string(),
+                    position: 3
+                },
+                Divergence::Extra {
+                    name: "D".to_string(),
+                    position: 3
+                },
+            ]
+        );
+    }
+}
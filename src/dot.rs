@@ -103,8 +103,8 @@ impl GraphBase {
         self.write_attributes_for(s, "node", &self.node_attributes)?;
         self.write_attributes_for(s, "edge", &self.edge_attributes)?;
         self.nodes.iter().try_for_each(|i| i.write(s))?;
-        self.edges.iter().try_for_each(|i| i.write(s))?;
         self.subgraphs.iter().try_for_each(|i| i.write(s))?;
+        self.edges.iter().try_for_each(|i| i.write(s))?;
         Ok(())
     }
     fn write_attributes_for<S: Write>(
@@ -247,7 +247,7 @@ impl Edge {
 impl Subgraph {
     fn write<S: Write>(&self, s: &mut S) -> Result<()> {
         if let Some(id) = &self.id {
-            write!(s, "{}", escape_id(id))?;
+            write!(s, "subgraph {}", escape_id(id))?;
         }
         writeln!(s, "{{")?;
         self.base.write(s)?;
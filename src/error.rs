@@ -0,0 +1,87 @@
+//! Errors from loading, decoding and decompiling scripts
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::bytecode::opcodes::EExprToken;
+use crate::bytecode::structured::StructuringTimedOut;
+use crate::bytecode::types::{Address, BytecodeOffset};
+
+#[derive(Debug, Error)]
+pub enum KismetError {
+    #[error("reading {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("parsing jmap {}: {reason}", .path.display())]
+    JmapParse { path: PathBuf, reason: String },
+
+    /// Bytecode that couldn't be decoded; `opcode` is the expression being
+    /// decoded when the error happened, if any
+    #[error("bad bytecode at {offset}{}: {reason}", opcode_suffix(.opcode))]
+    BytecodeParse {
+        offset: BytecodeOffset,
+        opcode: Option<EExprToken>,
+        reason: String,
+    },
+
+    #[error(transparent)]
+    Structuring(#[from] StructuringTimedOut),
+
+    /// Structuring finished, but some of the control flow matched no
+    /// pattern
+    #[error("control flow left unstructured")]
+    Unstructured,
+
+    /// An address that isn't in the jmap
+    #[error("unresolved {kind} {address}")]
+    Resolution {
+        kind: &'static str,
+        address: Address,
+    },
+
+    /// A path that doesn't name a function in the jmap
+    #[error("no function {0}")]
+    UnknownFunction(String),
+}
+
+fn opcode_suffix(opcode: &Option<EExprToken>) -> String {
+    opcode
+        .map(|opcode| format!(" in {:?} (0x{:02X})", opcode, opcode.opcode_value()))
+        .unwrap_or_default()
+}
+
+impl KismetError {
+    pub fn bytecode(offset: usize, reason: impl Into<String>) -> Self {
+        Self::BytecodeParse {
+            offset: BytecodeOffset::new(offset),
+            opcode: None,
+            reason: reason.into(),
+        }
+    }
+
+    /// Attribute a bytecode error to the expression being decoded, unless a
+    /// nested expression already claimed it
+    pub fn in_opcode(mut self, expr_opcode: EExprToken) -> Self {
+        if let Self::BytecodeParse { opcode, .. } = &mut self {
+            opcode.get_or_insert(expr_opcode);
+        }
+        self
+    }
+
+    /// Process exit code for a command that failed with this error
+    ///
+    /// 1 is left for usage and config errors, which aren't `KismetError`s.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io { .. } => 2,
+            Self::JmapParse { .. } => 3,
+            Self::BytecodeParse { .. } => 4,
+            Self::Structuring(_) | Self::Unstructured => 5,
+            Self::Resolution { .. } | Self::UnknownFunction(_) => 6,
+        }
+    }
+}
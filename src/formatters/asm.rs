@@ -1,12 +1,16 @@
 use std::collections::HashSet;
+use std::fmt::{self, Write};
+
+use tracing::info_span;
 
 use crate::{
     bytecode::{
         address_index::AddressIndex,
-        expr::{Expr, ExprKind, TextLiteral},
+        expr::{Expr, ExprKind, TextLiteral, ensure_stack, targets_inside_statements},
         refs::{ClassRef, FunctionRef, ObjectRef, PropertyRef, StructRef},
-        types::BytecodeOffset,
+        types::{Address, BytecodeOffset, Label},
     },
+    error::KismetError,
     formatters::theme::Theme,
 };
 
@@ -14,6 +18,8 @@ pub struct AsmFormatter<'a> {
     indent_level: usize,
     address_index: &'a AddressIndex<'a>,
     referenced_offsets: HashSet<BytecodeOffset>,
+    /// Print unresolved addresses by [`AddressIndex::symbolic_name`]
+    normalize_addresses: bool,
 }
 
 impl<'a> AsmFormatter<'a> {
@@ -25,58 +31,77 @@ impl<'a> AsmFormatter<'a> {
             indent_level: 0,
             address_index,
             referenced_offsets,
+            normalize_addresses: false,
+        }
+    }
+
+    pub fn with_normalized_addresses(mut self, normalize: bool) -> Self {
+        self.normalize_addresses = normalize;
+        self
+    }
+
+    // Unresolved references are printed as the error, with the address, so
+    // the rest of the listing is still usable
+
+    fn unresolved(&self, error: KismetError) -> String {
+        match error {
+            KismetError::Resolution { kind, address } if self.normalize_addresses => format!(
+                "<unresolved {} {}>",
+                kind,
+                self.address_index.symbolic_name(address)
+            ),
+            e => format!("<{}>", e),
         }
     }
 
     fn resolve_property(&self, prop: &PropertyRef) -> String {
-        let prop_info = self.address_index.resolve_property(prop.address).unwrap();
-        format!("{}::{}", prop_info.owner.path, prop_info.property.name)
+        match self.address_index.property(prop.address) {
+            Ok(prop_info) => format!("{}::{}", prop_info.owner.path, prop_info.property.name),
+            Err(e) => self.unresolved(e),
+        }
+    }
+
+    fn object_path(&self, address: Address) -> String {
+        match self.address_index.object(address) {
+            Ok(obj_info) => obj_info.path.to_string(),
+            Err(e) => self.unresolved(e),
+        }
     }
 
     fn resolve_class(&self, class: &ClassRef) -> String {
-        self.address_index
-            .resolve_object(class.address)
-            .unwrap()
-            .path
-            .to_string()
+        self.object_path(class.address)
     }
 
     fn resolve_struct(&self, s: &StructRef) -> String {
-        self.address_index
-            .resolve_object(s.address)
-            .unwrap()
-            .path
-            .to_string()
+        self.object_path(s.address)
     }
 
     fn resolve_object(&self, obj: &ObjectRef) -> String {
-        self.address_index
-            .resolve_object(obj.address)
-            .unwrap()
-            .path
-            .to_string()
+        self.object_path(obj.address)
     }
 
     fn resolve_function(&self, func: &FunctionRef) -> String {
         match func {
             FunctionRef::ByName(n) => n.as_str().to_string(),
-            FunctionRef::ByAddress(addr) => self
-                .address_index
-                .resolve_object(*addr)
-                .unwrap()
-                .path
-                .to_string(),
+            FunctionRef::ByAddress(addr) => self.object_path(*addr),
         }
     }
 
-    pub fn format(&mut self, expressions: &[Expr]) {
+    pub fn format(&mut self, out: &mut dyn Write, expressions: &[Expr]) -> fmt::Result {
+        let _span = info_span!("format").entered();
+        let inside = targets_inside_statements(expressions, &self.referenced_offsets);
         for expr in expressions {
             // Only print label if this offset is referenced
             if self.referenced_offsets.contains(&expr.offset) {
-                println!("{}:", self.print_label(expr.offset));
+                writeln!(out, "{}:", self.print_label(expr.offset))?;
             }
-            self.format_expr(expr);
+            for &target in inside.get(&expr.offset).into_iter().flatten() {
+                let note = format!("// {} is inside this statement", Label::new(target));
+                writeln!(out, "{}", Theme::comment(note))?;
+            }
+            self.format_expr(out, expr)?;
         }
+        Ok(())
     }
 
     fn indent(&self) -> String {
@@ -94,280 +119,348 @@ impl<'a> AsmFormatter<'a> {
     }
 
     fn format_label(&self, offset: BytecodeOffset) -> String {
-        Theme::label(format!("Label_0x{:X}", offset.0)).to_string()
+        Theme::label(Label::new(offset)).to_string()
     }
 
     fn print_label(&self, offset: BytecodeOffset) -> String {
         self.format_label(offset)
     }
 
-    fn print_tag(&self, label: &str) {
-        println!("{}   {}:", self.indent(), Theme::tag(label));
+    fn write_tag(&self, out: &mut dyn Write, label: &str) -> fmt::Result {
+        writeln!(out, "{}   {}:", self.indent(), Theme::tag(label))
     }
 
-    fn format_tagged_expr(&mut self, label: &str, expr: &Expr) {
-        self.print_tag(label);
-        self.format_expr(expr);
+    fn format_tagged_expr(&mut self, out: &mut dyn Write, label: &str, expr: &Expr) -> fmt::Result {
+        self.write_tag(out, label)?;
+        self.format_expr(out, expr)
     }
 
-    fn format_params(&mut self, params: &[Expr]) {
+    fn format_params(&mut self, out: &mut dyn Write, params: &[Expr]) -> fmt::Result {
         if !params.is_empty() {
             for param in params {
-                self.format_expr(param);
+                self.format_expr(out, param)?;
             }
         }
+        Ok(())
     }
 
-    fn print_operation(&self, opcode: u8, description: impl std::fmt::Display) {
-        println!(
+    fn write_operation(
+        &self,
+        out: &mut dyn Write,
+        opcode: u8,
+        description: impl fmt::Display,
+    ) -> fmt::Result {
+        writeln!(
+            out,
             "{} {} {}",
             self.indent(),
             Theme::opcode(format!("${:02X}:", opcode)),
             description
-        );
+        )
+    }
+
+    fn format_expr(&mut self, out: &mut dyn Write, expr: &Expr) -> fmt::Result {
+        ensure_stack(|| self.format_nested_expr(out, expr))
     }
 
-    fn format_expr(&mut self, expr: &Expr) {
+    fn format_nested_expr(&mut self, out: &mut dyn Write, expr: &Expr) -> fmt::Result {
         self.add_indent();
 
         match &expr.kind {
             // Variables
             ExprKind::LocalVariable(prop) => {
                 let name = self.resolve_property(prop);
-                self.print_operation(0x00, format!("Local variable {}", Theme::variable(name)));
+                self.write_operation(
+                    out,
+                    0x00,
+                    format!("Local variable {}", Theme::variable(name)),
+                )?;
             }
             ExprKind::InstanceVariable(prop) => {
                 let name = self.resolve_property(prop);
-                self.print_operation(0x01, format!("Instance variable {}", Theme::variable(name)));
+                self.write_operation(
+                    out,
+                    0x01,
+                    format!("Instance variable {}", Theme::variable(name)),
+                )?;
             }
             ExprKind::DefaultVariable(prop) => {
                 let name = self.resolve_property(prop);
-                self.print_operation(0x02, format!("Default variable {}", Theme::variable(name)));
+                self.write_operation(
+                    out,
+                    0x02,
+                    format!("Default variable {}", Theme::variable(name)),
+                )?;
             }
             ExprKind::LocalOutVariable(prop) => {
                 let name = self.resolve_property(prop);
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x48,
                     format!("Local out variable {}", Theme::variable(name)),
-                );
+                )?;
             }
             ExprKind::ClassSparseDataVariable(prop) => {
                 let name = self.resolve_property(prop);
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x6C,
                     format!("Class sparse data variable {}", Theme::variable(name)),
-                );
+                )?;
             }
 
             // Integer constants
             ExprKind::IntConst(val) => {
-                self.print_operation(0x1D, format!("literal int32 {}", Theme::numeric_bold(val)));
+                self.write_operation(
+                    out,
+                    0x1D,
+                    format!("literal int32 {}", Theme::numeric_bold(val)),
+                )?;
             }
             ExprKind::Int64Const(val) => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x35,
                     format!(
                         "literal int64 {}",
                         Theme::numeric_bold(format!("0x{:X}", val))
                     ),
-                );
+                )?;
             }
             ExprKind::UInt64Const(val) => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x36,
                     format!(
                         "literal uint64 {}",
                         Theme::numeric_bold(format!("0x{:X}", val))
                     ),
-                );
+                )?;
             }
             ExprKind::IntZero => {
-                self.print_operation(0x25, "EX_IntZero");
+                self.write_operation(out, 0x25, "EX_IntZero")?;
             }
             ExprKind::IntOne => {
-                self.print_operation(0x26, "EX_IntOne");
+                self.write_operation(out, 0x26, "EX_IntOne")?;
             }
             ExprKind::ByteConst(val) => {
-                self.print_operation(0x24, format!("literal byte {}", val));
+                self.write_operation(out, 0x24, format!("literal byte {}", val))?;
             }
             ExprKind::IntConstByte(val) => {
-                self.print_operation(0x2C, format!("literal int {}", val));
+                self.write_operation(out, 0x2C, format!("literal int {}", val))?;
             }
 
             // Float constants
             ExprKind::FloatConst(val) => {
-                self.print_operation(0x1E, format!("literal float {}", Theme::numeric_bold(val)));
+                self.write_operation(
+                    out,
+                    0x1E,
+                    format!("literal float {}", Theme::numeric_bold(val)),
+                )?;
+            }
+            ExprKind::DoubleConst(val) => {
+                self.write_operation(
+                    out,
+                    0x37,
+                    format!("literal double {}", Theme::numeric_bold(val)),
+                )?;
             }
 
             // String constants
             ExprKind::StringConst(val) => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x1F,
                     format!(
                         "literal ansi string {}",
                         crate::formatters::theme::quoted_string(val)
                     ),
-                );
+                )?;
             }
             ExprKind::UnicodeStringConst(val) => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x34,
                     format!(
                         "literal unicode string {}",
                         crate::formatters::theme::quoted_string(val)
                     ),
-                );
+                )?;
             }
             ExprKind::NameConst(name) => {
-                self.print_operation(0x21, format!("literal name {}", name.as_str()));
+                // The number is shown apart, as a suffix could be part of the base
+                let text = match name.number() {
+                    0 => name.as_str().to_string(),
+                    number => format!(
+                        "{} (base {}, number {})",
+                        name.as_str(),
+                        name.base(),
+                        number
+                    ),
+                };
+                self.write_operation(out, 0x21, format!("literal name {}", text))?;
             }
 
             // Vector/rotation/transform
             ExprKind::VectorConst { x, y, z } => {
-                self.print_operation(0x23, format!("literal vector ({}, {}, {})", x, y, z));
+                self.write_operation(out, 0x23, format!("literal vector ({}, {}, {})", x, y, z))?;
+            }
+            ExprKind::Vector3fConst { x, y, z } => {
+                self.write_operation(
+                    out,
+                    0x41,
+                    format!("literal float vector ({}, {}, {})", x, y, z),
+                )?;
             }
             ExprKind::RotationConst { pitch, yaw, roll } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x22,
                     format!("literal rotation ({}, {}, {})", pitch, yaw, roll),
-                );
-            }
-            ExprKind::TransformConst {
-                rot_x,
-                rot_y,
-                rot_z,
-                rot_w,
-                trans_x,
-                trans_y,
-                trans_z,
-                scale_x,
-                scale_y,
-                scale_z,
-            } => {
-                self.print_operation(
+                )?;
+            }
+            ExprKind::TransformConst(transform) => {
+                self.write_operation(
+                    out,
                     0x2B,
                     format!(
                         "literal transform R({},{},{},{}) T({},{},{}) S({},{},{})",
-                        rot_x,
-                        rot_y,
-                        rot_z,
-                        rot_w,
-                        trans_x,
-                        trans_y,
-                        trans_z,
-                        scale_x,
-                        scale_y,
-                        scale_z
+                        transform.rot_x,
+                        transform.rot_y,
+                        transform.rot_z,
+                        transform.rot_w,
+                        transform.trans_x,
+                        transform.trans_y,
+                        transform.trans_z,
+                        transform.scale_x,
+                        transform.scale_y,
+                        transform.scale_z
                     ),
-                );
+                )?;
             }
 
             // Special constants
             ExprKind::True => {
-                self.print_operation(0x27, "EX_True");
+                self.write_operation(out, 0x27, "EX_True")?;
             }
             ExprKind::False => {
-                self.print_operation(0x28, "EX_False");
+                self.write_operation(out, 0x28, "EX_False")?;
             }
             ExprKind::NoObject => {
-                self.print_operation(0x2A, "EX_NoObject");
+                self.write_operation(out, 0x2A, "EX_NoObject")?;
             }
             ExprKind::NoInterface => {
-                self.print_operation(0x2D, "EX_NoInterface");
+                self.write_operation(out, 0x2D, "EX_NoInterface")?;
             }
             ExprKind::Self_ => {
-                self.print_operation(0x17, "EX_Self");
+                self.write_operation(out, 0x17, "EX_Self")?;
             }
             ExprKind::Nothing => {
-                self.print_operation(0x0B, "EX_Nothing");
+                self.write_operation(out, 0x0B, "EX_Nothing")?;
             }
             ExprKind::NothingInt32 => {
-                self.print_operation(0x0C, "EX_NothingInt32");
+                self.write_operation(out, 0x0C, "EX_NothingInt32")?;
             }
 
             // Object references
             ExprKind::ObjectConst(obj) => {
                 let name = self.resolve_object(obj);
-                self.print_operation(0x20, format!("EX_ObjectConst {}", Theme::object_ref(name)));
+                self.write_operation(
+                    out,
+                    0x20,
+                    format!("EX_ObjectConst {}", Theme::object_ref(name)),
+                )?;
             }
             ExprKind::PropertyConst(prop) => {
                 let name = self.resolve_property(prop);
-                self.print_operation(0x33, format!("EX_PropertyConst {}", Theme::variable(name)));
+                self.write_operation(
+                    out,
+                    0x33,
+                    format!("EX_PropertyConst {}", Theme::variable(name)),
+                )?;
             }
             ExprKind::SkipOffsetConst(val) => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x5B,
                     format!("literal CodeSkipSizeType -> {}", self.print_label(*val)),
-                );
+                )?;
             }
 
             // Text constants
             ExprKind::TextConst(text_lit) => match text_lit {
                 TextLiteral::Empty => {
-                    self.print_operation(0x29, "literal text - empty");
+                    self.write_operation(out, 0x29, "literal text - empty")?;
                 }
                 TextLiteral::LocalizedText {
                     source,
                     key,
                     namespace,
                 } => {
-                    self.print_operation(0x29, "literal text - localized text");
-                    self.format_tagged_expr("Source string", source);
-                    self.format_tagged_expr("Key string", key);
-                    self.format_tagged_expr("Namespace string", namespace);
+                    self.write_operation(out, 0x29, "literal text - localized text")?;
+                    self.format_tagged_expr(out, "Source string", source)?;
+                    self.format_tagged_expr(out, "Key string", key)?;
+                    self.format_tagged_expr(out, "Namespace string", namespace)?;
                 }
                 TextLiteral::InvariantText { source } => {
-                    self.print_operation(0x29, "literal text - invariant text");
-                    self.format_tagged_expr("Source string", source);
+                    self.write_operation(out, 0x29, "literal text - invariant text")?;
+                    self.format_tagged_expr(out, "Source string", source)?;
                 }
                 TextLiteral::LiteralString { source } => {
-                    self.print_operation(0x29, "literal text - literal string");
-                    self.format_tagged_expr("Source string", source);
+                    self.write_operation(out, 0x29, "literal text - literal string")?;
+                    self.format_tagged_expr(out, "Source string", source)?;
                 }
                 TextLiteral::StringTableEntry { table_id, key } => {
-                    self.print_operation(0x29, "literal text - string table entry");
-                    self.format_tagged_expr("Table ID string", table_id);
-                    self.format_tagged_expr("Key string", key);
+                    self.write_operation(out, 0x29, "literal text - string table entry")?;
+                    self.format_tagged_expr(out, "Table ID string", table_id)?;
+                    self.format_tagged_expr(out, "Key string", key)?;
                 }
             },
 
             // Function calls
             ExprKind::VirtualFunction { func, params } => {
                 let name = self.resolve_function(func);
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x1B,
                     format!("Virtual Function named {}", Theme::function(name)),
-                );
-                self.format_params(params);
+                )?;
+                self.format_params(out, params)?;
             }
             ExprKind::FinalFunction { func, params } => {
                 let name = self.resolve_function(func);
-                self.print_operation(0x1C, format!("Final Function {}", Theme::function(name)));
-                self.format_params(params);
+                self.write_operation(
+                    out,
+                    0x1C,
+                    format!("Final Function {}", Theme::function(name)),
+                )?;
+                self.format_params(out, params)?;
             }
             ExprKind::LocalVirtualFunction { func, params } => {
                 let name = self.resolve_function(func);
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x45,
                     format!(
                         "Local Virtual Script Function named {}",
                         Theme::function(name)
                     ),
-                );
-                self.format_params(params);
+                )?;
+                self.format_params(out, params)?;
             }
             ExprKind::LocalFinalFunction { func, params } => {
                 let name = self.resolve_function(func);
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x46,
                     format!("Local Final Script Function {}", Theme::function(name)),
-                );
-                self.format_params(params);
+                )?;
+                self.format_params(out, params)?;
             }
             ExprKind::CallMath { func, params } => {
                 let name = self.resolve_function(func);
-                self.print_operation(0x68, format!("Call Math {}", Theme::function(name)));
-                self.format_params(params);
+                self.write_operation(out, 0x68, format!("Call Math {}", Theme::function(name)))?;
+                self.format_params(out, params)?;
             }
             ExprKind::CallMulticastDelegate {
                 stack_node,
@@ -375,14 +468,15 @@ impl<'a> AsmFormatter<'a> {
                 params,
             } => {
                 let name = self.resolve_function(stack_node);
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x63,
                     format!("CallMulticastDelegate {}", Theme::function(name)),
-                );
-                self.format_tagged_expr("Delegate", delegate_expr);
+                )?;
+                self.format_tagged_expr(out, "Delegate", delegate_expr)?;
                 if !params.is_empty() {
-                    self.print_tag("Params");
-                    self.format_params(params);
+                    self.write_tag(out, "Params")?;
+                    self.format_params(out, params)?;
                 }
             }
 
@@ -400,15 +494,16 @@ impl<'a> AsmFormatter<'a> {
                 } else {
                     "Context"
                 };
-                self.print_operation(opcode, desc);
-                println!(
+                self.write_operation(out, opcode, desc)?;
+                writeln!(
+                    out,
                     "{}   Skip: {} | Field: {}",
                     self.indent(),
                     Theme::offset(format!("0x{:X}", skip_offset)),
                     Theme::variable(self.resolve_property(field))
-                );
-                self.format_tagged_expr("Object", object);
-                self.format_tagged_expr("Context", context);
+                )?;
+                self.format_tagged_expr(out, "Object", object)?;
+                self.format_tagged_expr(out, "Context", context)?;
             }
             ExprKind::ClassContext {
                 object,
@@ -416,100 +511,108 @@ impl<'a> AsmFormatter<'a> {
                 context,
                 skip_offset,
             } => {
-                self.print_operation(0x12, "Class Context");
-                println!(
+                self.write_operation(out, 0x12, "Class Context")?;
+                writeln!(
+                    out,
                     "{}   Skip: {} | Field: {}",
                     self.indent(),
                     Theme::offset(format!("0x{:X}", skip_offset)),
                     Theme::variable(self.resolve_property(field))
-                );
-                self.format_tagged_expr("Object", object);
-                self.format_tagged_expr("Context", context);
+                )?;
+                self.format_tagged_expr(out, "Object", object)?;
+                self.format_tagged_expr(out, "Context", context)?;
             }
             ExprKind::StructMemberContext {
                 struct_expr,
                 member,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x42,
                     format!(
                         "Struct member context - {}",
                         Theme::variable(self.resolve_property(member))
                     ),
-                );
-                self.format_tagged_expr("Struct", struct_expr);
+                )?;
+                self.format_tagged_expr(out, "Struct", struct_expr)?;
             }
             ExprKind::InterfaceContext(expr) => {
-                self.print_operation(0x51, "EX_InterfaceContext");
-                self.format_expr(expr);
+                self.write_operation(out, 0x51, "EX_InterfaceContext")?;
+                self.format_expr(out, expr)?;
             }
 
             // Casts
             ExprKind::DynamicCast { target_class, expr } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x2E,
                     format!(
                         "DynamicCast to {}",
                         Theme::type_name(self.resolve_class(target_class))
                     ),
-                );
-                self.format_expr(expr);
+                )?;
+                self.format_expr(out, expr)?;
             }
             ExprKind::MetaCast { target_class, expr } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x13,
                     format!(
                         "MetaCast to {}",
                         Theme::type_name(self.resolve_class(target_class))
                     ),
-                );
-                self.format_expr(expr);
+                )?;
+                self.format_expr(out, expr)?;
             }
             ExprKind::PrimitiveCast {
                 conversion_type,
                 expr,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x38,
                     format!("PrimitiveCast of type {}", Theme::numeric(conversion_type)),
-                );
-                self.format_tagged_expr("Argument", expr);
+                )?;
+                self.format_tagged_expr(out, "Argument", expr)?;
             }
             ExprKind::ObjToInterfaceCast {
                 target_interface,
                 expr,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x52,
                     format!(
                         "ObjToInterfaceCast to {}",
                         Theme::type_name(self.resolve_class(target_interface))
                     ),
-                );
-                self.format_expr(expr);
+                )?;
+                self.format_expr(out, expr)?;
             }
             ExprKind::InterfaceToObjCast { target_class, expr } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x55,
                     format!(
                         "InterfaceToObjCast to {}",
                         Theme::type_name(self.resolve_class(target_class))
                     ),
-                );
-                self.format_expr(expr);
+                )?;
+                self.format_expr(out, expr)?;
             }
             ExprKind::CrossInterfaceCast {
                 target_interface,
                 expr,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x54,
                     format!(
                         "InterfaceToInterfaceCast to {}",
                         Theme::type_name(self.resolve_class(target_interface))
                     ),
-                );
-                self.format_expr(expr);
+                )?;
+                self.format_expr(out, expr)?;
             }
 
             // Collections
@@ -518,45 +621,48 @@ impl<'a> AsmFormatter<'a> {
                 num_elements,
                 elements,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x65,
                     format!(
                         "array const<{}> - elements number: {}",
                         Theme::variable(self.resolve_property(element_type)),
                         Theme::numeric_bold(num_elements)
                     ),
-                );
-                self.format_params(elements);
+                )?;
+                self.format_params(out, elements)?;
             }
             ExprKind::StructConst {
                 struct_type,
                 serialized_size,
                 elements,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x2F,
                     format!(
                         "literal struct {} (serialized size: {})",
                         Theme::type_name(self.resolve_struct(struct_type)),
                         serialized_size
                     ),
-                );
-                self.format_params(elements);
+                )?;
+                self.format_params(out, elements)?;
             }
             ExprKind::SetConst {
                 element_type,
                 num_elements,
                 elements,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x3D,
                     format!(
                         "set const<{}> - elements number: {}",
                         Theme::variable(self.resolve_property(element_type)),
                         Theme::numeric_bold(num_elements)
                     ),
-                );
-                self.format_params(elements);
+                )?;
+                self.format_params(out, elements)?;
             }
             ExprKind::MapConst {
                 key_type,
@@ -564,7 +670,8 @@ impl<'a> AsmFormatter<'a> {
                 num_elements,
                 elements,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x3F,
                     format!(
                         "map const<{}, {}> - elements number: {}",
@@ -572,8 +679,8 @@ impl<'a> AsmFormatter<'a> {
                         Theme::variable(self.resolve_property(value_type)),
                         Theme::numeric_bold(num_elements)
                     ),
-                );
-                self.format_params(elements);
+                )?;
+                self.format_params(out, elements)?;
             }
 
             // Array/set/map operations
@@ -581,11 +688,11 @@ impl<'a> AsmFormatter<'a> {
                 array_expr,
                 elements,
             } => {
-                self.print_operation(0x31, "set array");
-                self.format_tagged_expr("Array", array_expr);
+                self.write_operation(out, 0x31, "set array")?;
+                self.format_tagged_expr(out, "Array", array_expr)?;
                 if !elements.is_empty() {
-                    self.print_tag("Elements");
-                    self.format_params(elements);
+                    self.write_tag(out, "Elements")?;
+                    self.format_params(out, elements)?;
                 }
             }
             ExprKind::SetSet {
@@ -593,11 +700,11 @@ impl<'a> AsmFormatter<'a> {
                 num: _,
                 elements,
             } => {
-                self.print_operation(0x39, "set set");
-                self.format_tagged_expr("Set", set_expr);
+                self.write_operation(out, 0x39, "set set")?;
+                self.format_tagged_expr(out, "Set", set_expr)?;
                 if !elements.is_empty() {
-                    self.print_tag("Elements");
-                    self.format_params(elements);
+                    self.write_tag(out, "Elements")?;
+                    self.format_params(out, elements)?;
                 }
             }
             ExprKind::SetMap {
@@ -605,20 +712,20 @@ impl<'a> AsmFormatter<'a> {
                 num: _,
                 elements,
             } => {
-                self.print_operation(0x3B, "set map");
-                self.format_tagged_expr("Map", map_expr);
+                self.write_operation(out, 0x3B, "set map")?;
+                self.format_tagged_expr(out, "Map", map_expr)?;
                 if !elements.is_empty() {
-                    self.print_tag("Elements");
-                    self.format_params(elements);
+                    self.write_tag(out, "Elements")?;
+                    self.format_params(out, elements)?;
                 }
             }
             ExprKind::ArrayGetByRef {
                 array_expr,
                 index_expr,
             } => {
-                self.print_operation(0x6B, "Array Get-by-Ref Index");
-                self.format_tagged_expr("Array", array_expr);
-                self.format_tagged_expr("Index", index_expr);
+                self.write_operation(out, 0x6B, "Array Get-by-Ref Index")?;
+                self.format_tagged_expr(out, "Array", array_expr)?;
+                self.format_tagged_expr(out, "Index", index_expr)?;
             }
 
             // Assignments
@@ -627,110 +734,115 @@ impl<'a> AsmFormatter<'a> {
                 variable,
                 value,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x0F,
                     format!(
                         "Let (Variable = Expression) - {}",
                         Theme::variable(self.resolve_property(property))
                     ),
-                );
-                self.format_tagged_expr("Variable", variable);
-                self.format_tagged_expr("Expression", value);
+                )?;
+                self.format_tagged_expr(out, "Variable", variable)?;
+                self.format_tagged_expr(out, "Expression", value)?;
             }
             ExprKind::LetObj { variable, value } => {
-                self.print_operation(0x5F, "Let Obj (Variable = Expression)");
-                self.format_tagged_expr("Variable", variable);
-                self.format_tagged_expr("Expression", value);
+                self.write_operation(out, 0x5F, "Let Obj (Variable = Expression)")?;
+                self.format_tagged_expr(out, "Variable", variable)?;
+                self.format_tagged_expr(out, "Expression", value)?;
             }
             ExprKind::LetWeakObjPtr { variable, value } => {
-                self.print_operation(0x60, "Let WeakObjPtr (Variable = Expression)");
-                self.format_tagged_expr("Variable", variable);
-                self.format_tagged_expr("Expression", value);
+                self.write_operation(out, 0x60, "Let WeakObjPtr (Variable = Expression)")?;
+                self.format_tagged_expr(out, "Variable", variable)?;
+                self.format_tagged_expr(out, "Expression", value)?;
             }
             ExprKind::LetBool { variable, value } => {
-                self.print_operation(0x14, "LetBool (Variable = Expression)");
-                self.format_tagged_expr("Variable", variable);
-                self.format_tagged_expr("Expression", value);
+                self.write_operation(out, 0x14, "LetBool (Variable = Expression)")?;
+                self.format_tagged_expr(out, "Variable", variable)?;
+                self.format_tagged_expr(out, "Expression", value)?;
             }
             ExprKind::LetDelegate { variable, value } => {
-                self.print_operation(0x44, "LetDelegate (Variable = Expression)");
-                self.format_tagged_expr("Variable", variable);
-                self.format_tagged_expr("Expression", value);
+                self.write_operation(out, 0x44, "LetDelegate (Variable = Expression)")?;
+                self.format_tagged_expr(out, "Variable", variable)?;
+                self.format_tagged_expr(out, "Expression", value)?;
             }
             ExprKind::LetMulticastDelegate { variable, value } => {
-                self.print_operation(0x43, "LetMulticastDelegate (Variable = Expression)");
-                self.format_tagged_expr("Variable", variable);
-                self.format_tagged_expr("Expression", value);
+                self.write_operation(out, 0x43, "LetMulticastDelegate (Variable = Expression)")?;
+                self.format_tagged_expr(out, "Variable", variable)?;
+                self.format_tagged_expr(out, "Expression", value)?;
             }
             ExprKind::LetValueOnPersistentFrame { property, value } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x64,
                     format!(
                         "LetValueOnPersistentFrame - {}",
                         Theme::variable(self.resolve_property(property))
                     ),
-                );
-                self.format_tagged_expr("Expression", value);
+                )?;
+                self.format_tagged_expr(out, "Expression", value)?;
             }
 
             // Delegates
             ExprKind::InstanceDelegate(name) => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x4B,
                     format!("instance delegate function named {}", name.as_str()),
-                );
+                )?;
             }
             ExprKind::BindDelegate {
                 func_name,
                 delegate_expr,
                 object_expr,
             } => {
-                self.print_operation(0x61, format!("BindDelegate '{}'", func_name.as_str()));
-                self.format_tagged_expr("Delegate", delegate_expr);
-                self.format_tagged_expr("Object", object_expr);
+                self.write_operation(out, 0x61, format!("BindDelegate '{}'", func_name.as_str()))?;
+                self.format_tagged_expr(out, "Delegate", delegate_expr)?;
+                self.format_tagged_expr(out, "Object", object_expr)?;
             }
             ExprKind::AddMulticastDelegate {
                 delegate_expr,
                 to_add_expr,
             } => {
-                self.print_operation(0x5C, "Add MC delegate");
-                self.format_tagged_expr("Delegate", delegate_expr);
-                self.format_tagged_expr("To Add", to_add_expr);
+                self.write_operation(out, 0x5C, "Add MC delegate")?;
+                self.format_tagged_expr(out, "Delegate", delegate_expr)?;
+                self.format_tagged_expr(out, "To Add", to_add_expr)?;
             }
             ExprKind::RemoveMulticastDelegate {
                 delegate_expr,
                 to_remove_expr,
             } => {
-                self.print_operation(0x62, "Remove MC delegate");
-                self.format_tagged_expr("Delegate", delegate_expr);
-                self.format_tagged_expr("To Remove", to_remove_expr);
+                self.write_operation(out, 0x62, "Remove MC delegate")?;
+                self.format_tagged_expr(out, "Delegate", delegate_expr)?;
+                self.format_tagged_expr(out, "To Remove", to_remove_expr)?;
             }
             ExprKind::ClearMulticastDelegate(expr) => {
-                self.print_operation(0x5D, "Clear MC delegate");
-                self.format_expr(expr);
+                self.write_operation(out, 0x5D, "Clear MC delegate")?;
+                self.format_expr(out, expr)?;
             }
 
             // Control flow
             ExprKind::Return(expr) => {
-                self.print_operation(0x04, "Return expression");
-                self.format_expr(expr);
+                self.write_operation(out, 0x04, "Return expression")?;
+                self.format_expr(out, expr)?;
             }
             ExprKind::Jump { target } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x06,
                     format!("Jump to offset {}", self.print_label(*target)),
-                );
+                )?;
             }
             ExprKind::JumpIfNot { condition, target } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x07,
                     format!("Jump to {} if not:", self.print_label(*target)),
-                );
-                self.format_expr(condition);
+                )?;
+                self.format_expr(out, condition)?;
             }
             ExprKind::ComputedJump { offset_expr } => {
-                self.print_operation(0x4E, "Computed Jump, offset specified by expression:");
-                self.format_expr(offset_expr);
+                self.write_operation(out, 0x4E, "Computed Jump, offset specified by expression:")?;
+                self.format_expr(out, offset_expr)?;
             }
             ExprKind::SwitchValue {
                 index,
@@ -738,53 +850,57 @@ impl<'a> AsmFormatter<'a> {
                 default,
                 end_offset,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x69,
                     format!(
                         "Switch Value {} cases, end in {}",
                         cases.len(),
                         self.print_label(*end_offset)
                     ),
-                );
-                self.format_tagged_expr("Index", index);
+                )?;
+                self.format_tagged_expr(out, "Index", index)?;
 
                 for (i, case) in cases.iter().enumerate() {
-                    self.print_tag(&format!(
-                        "Case [{}] ({})",
-                        i,
-                        self.print_label(case.case_offset)
-                    ));
-                    self.format_tagged_expr("Match Value", &case.case_value);
-                    println!(
+                    self.write_tag(
+                        out,
+                        &format!("Case [{}] ({})", i, self.print_label(case.case_offset)),
+                    )?;
+                    self.format_tagged_expr(out, "Match Value", &case.case_value)?;
+                    writeln!(
+                        out,
                         "{}   Next case offset: {}",
                         self.indent(),
-                        Theme::offset(format!("0x{:X}", case.next_offset.as_usize()))
-                    );
-                    self.format_tagged_expr("Result", &case.result);
+                        Theme::offset(case.next_offset)
+                    )?;
+                    self.format_tagged_expr(out, "Result", &case.result)?;
                 }
 
-                self.format_tagged_expr("Default result", default);
+                self.format_tagged_expr(out, "Default result", default)?;
             }
 
             // Execution flow
             ExprKind::PushExecutionFlow { push_offset } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x4C,
                     format!("FlowStack.Push({})", self.print_label(*push_offset)),
-                );
+                )?;
             }
             ExprKind::PopExecutionFlow => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x4D,
                     "if (FlowStack.Num()) { jump to FlowStack.Pop(); } else { ERROR!!! }",
-                );
+                )?;
             }
             ExprKind::PopExecutionFlowIfNot { condition } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x4F,
                     "if (!condition) { if (FlowStack.Num()) { jump to FlowStack.Pop(); } else { ERROR!!! } }"
-                );
-                self.format_expr(condition);
+                )?;
+                self.format_expr(out, condition)?;
             }
 
             // Debug/instrumentation
@@ -793,62 +909,86 @@ impl<'a> AsmFormatter<'a> {
                 in_debug,
                 condition,
             } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x09,
                     format!("assert at line {}, in debug mode = {}", line, in_debug),
-                );
-                self.format_expr(condition);
+                )?;
+                self.format_expr(out, condition)?;
             }
             ExprKind::Skip { skip_count, expr } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x18,
                     format!(
                         "possibly skip {} bytes of expr:",
                         Theme::offset(format!("0x{:X}", skip_count))
                     ),
-                );
-                self.format_expr(expr);
+                )?;
+                self.format_expr(out, expr)?;
+            }
+            ExprKind::DefaultParmValue { skip_count, value } => {
+                self.write_operation(
+                    out,
+                    0x49,
+                    format!(
+                        "default parameter value, {} bytes skipped if passed:",
+                        Theme::offset(format!("0x{:X}", skip_count))
+                    ),
+                )?;
+                self.format_expr(out, value)?;
             }
             ExprKind::Breakpoint => {
-                self.print_operation(0x50, "<<< BREAKPOINT >>>");
+                self.write_operation(out, 0x50, "<<< BREAKPOINT >>>")?;
             }
             ExprKind::Tracepoint => {
-                self.print_operation(0x5E, ".. debug site ..");
+                self.write_operation(out, 0x5E, ".. debug site ..")?;
             }
             ExprKind::WireTracepoint => {
-                self.print_operation(0x5A, ".. wire debug site ..");
+                self.write_operation(out, 0x5A, ".. wire debug site ..")?;
             }
             ExprKind::InstrumentationEvent { event_type } => {
-                self.print_operation(
+                self.write_operation(
+                    out,
                     0x6A,
                     format!(".. instrumented event type {} ..", event_type),
-                );
+                )?;
             }
 
             // Special
-            ExprKind::BitFieldConst => {
-                self.print_operation(0x11, "EX_BitFieldConst (unimplemented)");
+            ExprKind::BitFieldConst { property, value } => {
+                let name = self.resolve_property(property);
+                self.write_operation(
+                    out,
+                    0x11,
+                    format!(
+                        "EX_BitFieldConst {} = {}",
+                        Theme::variable(name),
+                        Theme::numeric(value)
+                    ),
+                )?;
             }
             ExprKind::DeprecatedOp4A => {
-                self.print_operation(0x4A, "This opcode has been removed and does nothing.");
+                self.write_operation(out, 0x4A, "This opcode has been removed and does nothing.")?;
             }
             ExprKind::EndOfScript => {
-                self.print_operation(0x53, "EX_EndOfScript");
+                self.write_operation(out, 0x53, "EX_EndOfScript")?;
             }
             ExprKind::EndParmValue => {
-                self.print_operation(0x15, "EX_EndParmValue");
+                self.write_operation(out, 0x15, "EX_EndParmValue")?;
             }
 
             ExprKind::SoftObjectConst(expr) => {
-                self.print_operation(0x67, "EX_SoftObjectConst");
-                self.format_expr(expr);
+                self.write_operation(out, 0x67, "EX_SoftObjectConst")?;
+                self.format_expr(out, expr)?;
             }
             ExprKind::FieldPathConst(expr) => {
-                self.print_operation(0x6D, "EX_FieldPathConst");
-                self.format_expr(expr);
+                self.write_operation(out, 0x6D, "EX_FieldPathConst")?;
+                self.format_expr(out, expr)?;
             }
         }
 
         self.drop_indent();
+        Ok(())
     }
 }
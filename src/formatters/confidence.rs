@@ -0,0 +1,74 @@
+/// Marking output that rests on a guess
+///
+/// Some of what the formatters print comes from a heuristic or a lossy step
+/// rather than from the bytecode alone: an operator whose operand types the
+/// dump can't confirm, an enum taken to be a bitmask, the targets of a
+/// computed jump, a branch pruned as dead. Lines like that are tagged with a
+/// `/*?N*/` marker, and `--explain` prints the reason behind each number
+/// after the function.
+use std::fmt::{self, Write};
+
+use super::theme::Theme;
+
+/// The reasons behind the markers printed for one function
+#[derive(Debug, Default)]
+pub struct Doubts {
+    reasons: Vec<String>,
+}
+
+impl Doubts {
+    /// Record why a line may be wrong, returning the marker to tag it with
+    pub fn raise(&mut self, reason: String) -> String {
+        // Lines guessing the same way, such as every use of one unconfirmed
+        // operator, share a number
+        let number = match self.reasons.iter().position(|known| *known == reason) {
+            Some(index) => index + 1,
+            None => {
+                self.reasons.push(reason);
+                self.reasons.len()
+            }
+        };
+        format!("/*?{}*/", number)
+    }
+
+    /// Markers for several reasons at once, each followed by a space, or
+    /// nothing if there are none
+    pub fn raise_all(&mut self, reasons: Vec<String>) -> String {
+        reasons
+            .into_iter()
+            .map(|reason| format!("{} ", self.raise(reason)))
+            .collect()
+    }
+
+    /// Write the reason for each marker, numbered like the markers
+    pub fn explain(&self, out: &mut dyn Write) -> fmt::Result {
+        if self.reasons.is_empty() {
+            return Ok(());
+        }
+        writeln!(out)?;
+        for (index, reason) in self.reasons.iter().enumerate() {
+            writeln!(
+                out,
+                "{}",
+                Theme::comment(format!("// ?{}: {}", index + 1, reason))
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_reasons_share_a_marker() {
+        let mut doubts = Doubts::default();
+        assert_eq!(doubts.raise("a".to_string()), "/*?1*/");
+        assert_eq!(doubts.raise("b".to_string()), "/*?2*/");
+        assert_eq!(
+            doubts.raise_all(vec!["a".to_string(), "c".to_string()]),
+            "/*?1*/ /*?3*/ "
+        );
+    }
+}
@@ -1,20 +1,58 @@
+use std::borrow::Cow;
 use std::collections::HashSet;
+use std::fmt::{self, Write};
+
+use jmap::{Enum, PropertyType};
+use tracing::info_span;
 
 use crate::{
     bytecode::{
-        address_index::AddressIndex,
-        expr::{Expr, ExprKind, TextLiteral},
+        address_index::{AddressIndex, ObjectRole},
+        cost::expr_cost,
+        expr::{Expr, ExprKind, SwitchCase, TextLiteral, ensure_stack, targets_inside_statements},
+        flags::{CPF_OUT_PARM, CPF_PARM, CPF_RETURN_PARM, FUNC_STATIC},
+        intrinsics::{self, KnownStruct, OperandType, OperatorIntrinsic, date_time_components},
+        pattern::{call_parts, variable_property},
         refs::{ClassRef, FunctionRef, PropertyRef, StructRef},
-        types::{Address, BytecodeOffset},
+        simplify::simplify,
+        types::{Address, BytecodeOffset, Label},
+        ubergraph::{event_variable_name, ubergraph_entry},
+    },
+    formatters::{
+        FormattingOptions,
+        confidence::Doubts,
+        theme::{Theme, escape_string},
     },
-    formatters::theme::Theme,
 };
 
-pub struct CppFormatter<'a> {
-    indent_level: usize,
+/// Renders expressions as C++ text
+///
+/// Holds only what stays the same across a function, so one renderer can be
+/// shared by every [`StatementWriter`] printing that function, nested or not.
+pub struct ExprRenderer<'a> {
     address_index: &'a AddressIndex<'a>,
+    options: FormattingOptions,
+    /// Path of the function being formatted, if known
+    function: Option<&'a str>,
+    /// Ubergraph function and entry offset the function being formatted
+    /// resumes, for naming what it writes to the persistent frame
+    ubergraph_entry: Option<(String, BytecodeOffset)>,
+}
+
+/// Prints statements line by line, rendering their expressions with an
+/// [`ExprRenderer`]
+///
+/// Keeps where it is in the output: the indentation, the prefix put before
+/// each statement, and the doubt markers raised so far.
+pub struct StatementWriter<'r, 'a> {
+    renderer: &'r ExprRenderer<'a>,
     referenced_offsets: HashSet<BytecodeOffset>,
+    indent_level: usize,
     statement_prefix: String,
+    /// Markers for the statement being printed, put on each of its lines
+    statement_marker: String,
+    /// Reasons behind the doubt markers printed so far
+    doubts: Doubts,
 }
 
 /// Context for formatting expressions - tracks the current object being operated on
@@ -23,181 +61,199 @@ pub enum FormatContext {
     /// Implicit 'this' context
     This,
     /// Explicit object context
-    Object(String),
+    Object(String, MemberAccess),
 }
 
-impl<'a> CppFormatter<'a> {
-    pub fn new(
-        address_index: &'a AddressIndex<'a>,
-        referenced_offsets: HashSet<BytecodeOffset>,
-    ) -> Self {
-        Self {
-            indent_level: 0,
-            address_index,
-            referenced_offsets,
-            statement_prefix: String::new(),
+/// How members are reached from a context object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberAccess {
+    /// The object is a pointer (`obj->Member`)
+    Pointer,
+    /// The object is a struct value (`obj.Member`)
+    Value,
+    /// The object is a pointer that may be null; a fail-silent context skips the
+    /// access and produces a default value instead (`obj?->Member`)
+    SafeNavigation,
+    /// The object is a class default object only there to call a static
+    /// function on (`UClass::Function()`)
+    Static,
+}
+
+impl MemberAccess {
+    pub fn separator(&self) -> &'static str {
+        match self {
+            MemberAccess::Pointer => "->",
+            MemberAccess::Value => ".",
+            MemberAccess::SafeNavigation => "?->",
+            MemberAccess::Static => "::",
         }
     }
+}
 
-    /// Check if a function is a KismetMathLibrary operator and format it accordingly
-    fn try_format_as_operator(&self, full_path: &str, params: &[String]) -> Option<String> {
-        // Unary operators
-        if params.len() == 1 {
-            let operand = &params[0];
-            return match full_path {
-                "/Script/Engine.KismetMathLibrary:Not_PreBool" => Some(format!("!{}", operand)),
-                "/Script/Engine.KismetMathLibrary:NegateFloat" => Some(format!("-{}", operand)),
-                "/Script/Engine.KismetMathLibrary:NegateInt" => Some(format!("-{}", operand)),
-                "/Script/Engine.KismetMathLibrary:NegateInt64" => Some(format!("-{}", operand)),
-                _ => None,
-            };
+impl<'a> ExprRenderer<'a> {
+    pub fn new(address_index: &'a AddressIndex<'a>) -> Self {
+        Self {
+            address_index,
+            options: FormattingOptions::default(),
+            function: None,
+            ubergraph_entry: None,
         }
+    }
 
-        // Binary operators
-        if params.len() == 2 {
-            let left = &params[0];
-            let right = &params[1];
-
-            return match full_path {
-                // Logical operators
-                "/Script/Engine.KismetMathLibrary:BooleanAND" => {
-                    Some(format!("({} && {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:BooleanOR" => {
-                    Some(format!("({} || {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:BooleanXOR" => {
-                    Some(format!("({} ^ {})", left, right))
-                }
+    pub fn with_options(mut self, options: FormattingOptions) -> Self {
+        self.options = options;
+        self
+    }
 
-                // Integer arithmetic
-                "/Script/Engine.KismetMathLibrary:Add_IntInt" => {
-                    Some(format!("({} + {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Subtract_IntInt" => {
-                    Some(format!("({} - {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Multiply_IntInt" => {
-                    Some(format!("({} * {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Divide_IntInt" => {
-                    Some(format!("({} / {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Percent_IntInt" => {
-                    Some(format!("({} % {})", left, right))
-                }
+    /// Format expressions as part of the function at `path`, so calls to the
+    /// parent implementation of an override print as `Super::` calls
+    pub fn with_function(mut self, path: &'a str) -> Self {
+        self.function = Some(path);
+        self
+    }
 
-                // Float arithmetic
-                "/Script/Engine.KismetMathLibrary:Add_FloatFloat" => {
-                    Some(format!("({} + {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Subtract_FloatFloat" => {
-                    Some(format!("({} - {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Multiply_FloatFloat" => {
-                    Some(format!("({} * {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Divide_FloatFloat" => {
-                    Some(format!("({} / {})", left, right))
-                }
+    /// Format expressions as part of an event stub that resumes the
+    /// ubergraph at `entry`, as found by [`ubergraph_entry`]
+    pub fn with_ubergraph_entry(mut self, entry: Option<(String, BytecodeOffset)>) -> Self {
+        self.ubergraph_entry = entry;
+        self
+    }
 
-                // Double arithmetic
-                "/Script/Engine.KismetMathLibrary:Add_DoubleDouble" => {
-                    Some(format!("({} + {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Subtract_DoubleDouble" => {
-                    Some(format!("({} - {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Multiply_DoubleDouble" => {
-                    Some(format!("({} * {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Divide_DoubleDouble" => {
-                    Some(format!("({} / {})", left, right))
-                }
+    /// Find the ubergraph entry `expressions` resume if they're an event
+    /// stub, unless one was given already
+    pub fn with_ubergraph_entry_of(mut self, expressions: &[Expr]) -> Self {
+        if self.ubergraph_entry.is_none() {
+            let entry = ubergraph_entry(expressions, |func| {
+                Some(self.resolve_function(func).to_string())
+            });
+            self.ubergraph_entry = entry;
+        }
+        self
+    }
 
-                // Integer comparisons
-                "/Script/Engine.KismetMathLibrary:EqualEqual_IntInt" => {
-                    Some(format!("({} == {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:NotEqual_IntInt" => {
-                    Some(format!("({} != {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Greater_IntInt" => {
-                    Some(format!("({} > {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:GreaterEqual_IntInt" => {
-                    Some(format!("({} >= {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Less_IntInt" => {
-                    Some(format!("({} < {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:LessEqual_IntInt" => {
-                    Some(format!("({} <= {})", left, right))
-                }
+    /// Check if a function is a KismetMathLibrary operator and format it accordingly
+    fn try_format_as_operator(&self, full_path: &str, params: &[String]) -> Option<String> {
+        if !self.options.sugar {
+            return None;
+        }
+        let op = intrinsics::lookup_operator(full_path)?;
+        if op.arity() != params.len() {
+            return None;
+        }
 
-                // Byte comparisons
-                "/Script/Engine.KismetMathLibrary:EqualEqual_ByteByte" => {
-                    Some(format!("({} == {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:NotEqual_ByteByte" => {
-                    Some(format!("({} != {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Greater_ByteByte" => {
-                    Some(format!("({} > {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:GreaterEqual_ByteByte" => {
-                    Some(format!("({} >= {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Less_ByteByte" => {
-                    Some(format!("({} < {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:LessEqual_ByteByte" => {
-                    Some(format!("({} <= {})", left, right))
-                }
+        match params {
+            [operand] => Some(format!("{}{}", op.symbol(), operand)),
+            [left, right] => Some(format!("({} {} {})", left, op.symbol(), right)),
+            _ => None,
+        }
+    }
 
-                // Float comparisons
-                "/Script/Engine.KismetMathLibrary:EqualEqual_DoubleDouble" => {
-                    Some(format!("({} == {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:NotEqual_DoubleDouble" => {
-                    Some(format!("({} != {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Greater_DoubleDouble" => {
-                    Some(format!("({} > {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:GreaterEqual_DoubleDouble" => {
-                    Some(format!("({} >= {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:Less_DoubleDouble" => {
-                    Some(format!("({} < {})", left, right))
-                }
-                "/Script/Engine.KismetMathLibrary:LessEqual_DoubleDouble" => {
-                    Some(format!("({} <= {})", left, right))
+    /// Full path of the function called by a math library call expression
+    fn call_math_path<'b>(&'b self, expr: &'b Expr) -> Option<&'b str> {
+        match &expr.kind {
+            ExprKind::CallMath { func, .. } => match func {
+                FunctionRef::ByAddress(addr) => {
+                    self.address_index.resolve_object(*addr).map(|o| o.path)
                 }
+                FunctionRef::ByName(name) => Some(name.as_str()),
+            },
+            _ => None,
+        }
+    }
 
-                _ => None,
-            };
+    /// Collapse nested math calls such as `VSize(A - B)` into their idiomatic form
+    fn try_format_as_idiom(&self, full_path: &str, params: &[Expr]) -> Option<String> {
+        if !self.options.sugar {
+            return None;
         }
+        let (idiom, position) =
+            intrinsics::match_idiom(full_path, params.iter().map(|p| self.call_math_path(p)))?;
+
+        let ExprKind::CallMath {
+            params: inner_params,
+            ..
+        } = &params[position].kind
+        else {
+            return None;
+        };
 
-        None
+        let args: Vec<String> = params[..position]
+            .iter()
+            .chain(inner_params)
+            .chain(&params[position + 1..])
+            .map(|p| self.format_expr_inline(p, &FormatContext::This))
+            .collect();
+        Some(format!(
+            "{}({})",
+            Theme::function(idiom.replacement),
+            args.join(", ")
+        ))
     }
 
     fn resolve_property(&self, prop: &PropertyRef) -> &str {
         self.address_index
             .resolve_property(prop.address)
-            .map(|p| p.property.name.as_str())
+            .map(|p| self.address_index.property_name(p.property))
             .unwrap_or("<err resolving prop>")
     }
 
+    /// Where a member property sits in its class or struct, as a comment to
+    /// follow the access with, if asked for and the property resolves
+    fn prop_offset_note(&self, prop: &PropertyRef) -> String {
+        if !self.options.show_prop_offsets {
+            return String::new();
+        }
+        let Some(info) = self.address_index.resolve_property(prop.address) else {
+            return String::new();
+        };
+        let property = info.property;
+        let size = match property.array_dim {
+            0 | 1 => format!("{} bytes", property.size),
+            dim => format!("{} x {} bytes", dim, property.size),
+        };
+        format!(
+            " {}",
+            Theme::comment(format!("/* +0x{:X}, {} */", property.offset, size))
+        )
+    }
+
+    /// Text of a string or name literal, escaped for a C++ string literal and
+    /// to ASCII if requested
+    fn literal_text<'b>(&self, text: &'b str) -> Cow<'b, str> {
+        let escaped = escape_string(text);
+        if self.options.ascii_only && !escaped.is_ascii() {
+            Cow::Owned(escape_non_ascii(&escaped))
+        } else {
+            escaped
+        }
+    }
+
     fn resolve_object(&self, address: Address) -> &str {
-        let obj_info = self.address_index.resolve_object(address).unwrap();
-        obj_info.path.rsplit('/').next().unwrap_or(obj_info.path)
+        self.address_index
+            .resolve_object(address)
+            .map(|o| o.path.rsplit('/').next().unwrap_or(o.path))
+            .unwrap_or("<err resolving object>")
     }
 
     fn resolve_class(&self, class: &ClassRef) -> &str {
         self.resolve_object(class.address)
     }
 
+    /// Class named in a cast
+    fn cast_target(&self, class: &ClassRef, interface: bool) -> Cow<'_, str> {
+        match self.options.valid_cpp {
+            true => self.valid_class_name(class, interface).map(Cow::Owned),
+            false => None,
+        }
+        .unwrap_or_else(|| Cow::Borrowed(self.resolve_class(class)))
+    }
+
+    /// Whether casts are named after the opcode they come from; valid C++
+    /// output can't use those names
+    fn faithful_casts(&self) -> bool {
+        self.options.faithful_casts && !self.options.valid_cpp
+    }
+
     fn resolve_struct(&self, s: &StructRef) -> &str {
         self.resolve_object(s.address)
     }
@@ -213,255 +269,602 @@ impl<'a> CppFormatter<'a> {
         }
     }
 
-    fn indent(&self) -> String {
-        format!(
-            "{}{}",
-            "    ".repeat(self.indent_level),
-            self.statement_prefix
-        )
+    /// Name of the function a non-virtual call runs, if it's the parent
+    /// implementation of a function overridden by the caller's class
+    fn super_call_name(&self, func: &FunctionRef) -> Option<&'a str> {
+        match func {
+            FunctionRef::ByAddress(addr) if self.options.sugar => {
+                self.address_index.super_call_name(self.function?, *addr)
+            }
+            _ => None,
+        }
     }
 
-    fn add_indent(&mut self) {
-        self.indent_level += 1;
-    }
+    /// Work out whether an expression used as a context yields a pointer or a struct value
+    fn member_access_for(&self, object: &Expr) -> MemberAccess {
+        let prop = match &object.kind {
+            ExprKind::LocalVariable(prop)
+            | ExprKind::LocalOutVariable(prop)
+            | ExprKind::InstanceVariable(prop)
+            | ExprKind::DefaultVariable(prop) => prop,
+            ExprKind::StructMemberContext { member, .. } => member,
+            // An element, as `Items[i]` of inlined array gets
+            ExprKind::ArrayGetByRef { array_expr, .. } => {
+                return match variable_property(array_expr) {
+                    Some(array) if self.address_index.is_struct_array_property(array.address) => {
+                        MemberAccess::Value
+                    }
+                    _ => MemberAccess::Pointer,
+                };
+            }
+            _ => return MemberAccess::Pointer,
+        };
 
-    fn drop_indent(&mut self) {
-        if self.indent_level > 0 {
-            self.indent_level -= 1;
+        if self.address_index.is_struct_property(prop.address) {
+            MemberAccess::Value
+        } else {
+            MemberAccess::Pointer
         }
     }
 
-    fn format_label(&self, offset: BytecodeOffset) -> String {
-        Theme::label(format!("Label_0x{:X}", offset.as_usize())).to_string()
+    /// Context for members of a class default object or subsystem, named by
+    /// class rather than by object path: `UGameplayStatics::` for static
+    /// calls, `GetDefault<UMyClass>()->` or `GetSubsystem<UMySubsystem>()->`
+    /// otherwise
+    fn singleton_context(&self, object: &Expr, member: &Expr) -> Option<FormatContext> {
+        let ExprKind::ObjectConst(obj) = &object.kind else {
+            return None;
+        };
+        if !self.options.sugar {
+            return None;
+        }
+        let (object, access) = match self.address_index.object_role(obj.address) {
+            ObjectRole::ClassDefault { class } if self.is_static_call(member) => (
+                Theme::type_name(self.address_index.cpp_class_name(class)).to_string(),
+                MemberAccess::Static,
+            ),
+            ObjectRole::ClassDefault { class } => (
+                format!(
+                    "{}<{}>()",
+                    Theme::function("GetDefault"),
+                    Theme::type_name(self.address_index.cpp_class_name(class))
+                ),
+                MemberAccess::Pointer,
+            ),
+            ObjectRole::Subsystem { class } => (
+                format!(
+                    "{}<{}>()",
+                    Theme::function("GetSubsystem"),
+                    Theme::type_name(self.address_index.cpp_class_name(class))
+                ),
+                MemberAccess::Pointer,
+            ),
+            ObjectRole::Other => return None,
+        };
+        Some(FormatContext::Object(object, access))
     }
 
-    pub fn set_indent_level(&mut self, level: usize) {
-        self.indent_level = level;
+    /// Whether an expression calls a static function
+    fn is_static_call(&self, expr: &Expr) -> bool {
+        let (ExprKind::FinalFunction { func, .. } | ExprKind::VirtualFunction { func, .. }) =
+            &expr.kind
+        else {
+            return false;
+        };
+        let FunctionRef::ByAddress(addr) = func else {
+            return false;
+        };
+        matches!(
+            self.address_index.resolve_object(*addr).map(|info| info.object),
+            Some(jmap::ObjectType::Function(function))
+                if function.function_flags.bits() & FUNC_STATIC != 0
+        )
     }
 
-    pub fn set_statement_prefix(&mut self, prefix: String) {
-        self.statement_prefix = prefix;
+    /// Prefix for accessing a member in the given context (`this->`, `obj->`, `obj.`)
+    fn member_prefix(&self, context: &FormatContext) -> String {
+        match context {
+            FormatContext::This => format!("{}->", Theme::object_ref("this")),
+            FormatContext::Object(obj, MemberAccess::SafeNavigation) if self.options.valid_cpp => {
+                let note = Theme::comment("/* TODO: skipped if null */");
+                format!("{} {}->", obj, note)
+            }
+            FormatContext::Object(obj, access) => format!("{}{}", obj, access.separator()),
+        }
     }
 
-    pub fn clear_statement_prefix(&mut self) {
-        self.statement_prefix.clear();
+    /// Name to call a function by in valid C++ rather than its path: its own
+    /// name, qualified by its class for a static function
+    fn valid_function_name(&self, func: &FunctionRef, is_static: bool) -> Option<String> {
+        let path = match func {
+            FunctionRef::ByName(name) => name.as_str(),
+            FunctionRef::ByAddress(addr) => self.address_index.resolve_object(*addr)?.path,
+        };
+        let (class, name) = path.rsplit_once(':')?;
+        Some(if is_static {
+            format!("{}::{}", self.address_index.cpp_class_name(class), name)
+        } else {
+            name.to_string()
+        })
     }
 
-    pub fn format(&mut self, expressions: &[Expr]) {
-        for expr in expressions {
-            // Only print label if this offset is referenced
-            if self.referenced_offsets.contains(&expr.offset) {
-                println!("{}{}:", self.indent(), self.format_label(expr.offset));
-            }
-            self.add_indent();
-            self.format_statement(expr);
-            self.drop_indent();
+    /// Name of a function to print in a call, see [`Self::valid_function_name`]
+    fn called_name<'b>(&'b self, func: &'b FunctionRef, is_static: bool) -> Cow<'b, str> {
+        match self.options.valid_cpp {
+            true => self.valid_function_name(func, is_static).map(Cow::Owned),
+            false => None,
         }
+        .unwrap_or_else(|| Cow::Borrowed(self.resolve_function(func)))
     }
 
-    pub fn format_statement(&mut self, expr: &Expr) {
-        match &expr.kind {
-            // Assignments
-            ExprKind::Let {
-                property: _,
-                variable,
-                value,
-            } => {
-                let var = self.format_expr_inline(variable, &FormatContext::This);
-                let val = self.format_expr_inline(value, &FormatContext::This);
-                println!("{}{} = {};", self.indent(), var, val);
+    /// C++ type of a class, for casts in valid C++; interfaces take the `I`
+    /// prefix of their native side
+    fn valid_class_name(&self, class: &ClassRef, interface: bool) -> Option<String> {
+        let path = self.address_index.resolve_object(class.address)?.path;
+        let name = self.address_index.cpp_class_name(path);
+        Some(match interface {
+            true => format!("I{}", &name[1..]),
+            false => name,
+        })
+    }
+
+    /// C++ type of a property's values
+    pub fn cpp_type(&self, property: &'a jmap::Property) -> String {
+        let short = |path: &str| path.rsplit(['/', '.']).next().unwrap_or(path).to_string();
+        let class = |class: &'a Option<String>, fallback: &str| {
+            class.as_deref().map_or(fallback.to_string(), |class| {
+                self.address_index.cpp_class_name(class)
+            })
+        };
+        match &property.r#type {
+            PropertyType::Struct { r#struct } => format!("F{}", short(r#struct)),
+            PropertyType::Str => "FString".to_string(),
+            PropertyType::Name => "FName".to_string(),
+            PropertyType::Text => "FText".to_string(),
+            PropertyType::MulticastInlineDelegate { .. }
+            | PropertyType::MulticastSparseDelegate { .. } => {
+                "FMulticastScriptDelegate".to_string()
             }
-            ExprKind::LetObj { variable, value }
-            | ExprKind::LetWeakObjPtr { variable, value }
-            | ExprKind::LetBool { variable, value }
-            | ExprKind::LetDelegate { variable, value }
-            | ExprKind::LetMulticastDelegate { variable, value } => {
-                let var = self.format_expr_inline(variable, &FormatContext::This);
-                let val = self.format_expr_inline(value, &FormatContext::This);
-                println!("{}{} = {};", self.indent(), var, val);
+            PropertyType::Delegate { .. } => "FScriptDelegate".to_string(),
+            PropertyType::Bool { .. } => "bool".to_string(),
+            PropertyType::Array { inner } => format!("TArray<{}>", self.cpp_type(inner)),
+            PropertyType::Enum {
+                r#enum: Some(r#enum),
+                ..
             }
-            ExprKind::LetValueOnPersistentFrame { property, value } => {
-                let prop_name = self.resolve_property(property);
-                println!(
-                    "{}// PersistentFrame: {}",
-                    self.indent(),
-                    Theme::comment(prop_name)
-                );
-                let val = self.format_expr_inline(value, &FormatContext::This);
-                println!("{}{} = {};", self.indent(), Theme::variable(prop_name), val);
+            | PropertyType::Byte {
+                r#enum: Some(r#enum),
+            } => short(r#enum),
+            PropertyType::Enum { container, .. } => self.cpp_type(container),
+            PropertyType::Map {
+                key_prop,
+                value_prop,
+            } => format!(
+                "TMap<{}, {}>",
+                self.cpp_type(key_prop),
+                self.cpp_type(value_prop)
+            ),
+            PropertyType::Set { key_prop } => format!("TSet<{}>", self.cpp_type(key_prop)),
+            PropertyType::Float => "float".to_string(),
+            PropertyType::Double => "double".to_string(),
+            PropertyType::Byte { r#enum: None } => "uint8".to_string(),
+            PropertyType::UInt16 => "uint16".to_string(),
+            PropertyType::UInt32 => "uint32".to_string(),
+            PropertyType::UInt64 => "uint64".to_string(),
+            PropertyType::Int8 => "int8".to_string(),
+            PropertyType::Int16 => "int16".to_string(),
+            PropertyType::Int => "int32".to_string(),
+            PropertyType::Int64 => "int64".to_string(),
+            PropertyType::Object { property_class } => {
+                format!("{}*", class(property_class, "UObject"))
             }
-
-            // Control flow
-            ExprKind::Return(ret_expr) => {
-                let expr_str = self.format_expr_inline(ret_expr, &FormatContext::This);
-                if expr_str == "<Nothing>" || expr_str.is_empty() {
-                    println!("{}return;", self.indent());
-                } else {
-                    println!("{}return {};", self.indent(), expr_str);
-                }
+            PropertyType::Class { meta_class, .. } => {
+                format!("TSubclassOf<{}>", class(meta_class, "UObject"))
             }
-            ExprKind::Jump { target } => {
-                println!("{}goto {};", self.indent(), self.format_label(*target));
+            PropertyType::WeakObject { property_class } => {
+                format!("TWeakObjectPtr<{}>", class(property_class, "UObject"))
             }
-            ExprKind::JumpIfNot { condition, target } => {
-                let cond = self.format_expr_inline(condition, &FormatContext::This);
-                println!(
-                    "{}if (!{}) goto {};",
-                    self.indent(),
-                    cond,
-                    self.format_label(*target)
-                );
+            PropertyType::SoftObject { property_class } => {
+                format!("TSoftObjectPtr<{}>", class(property_class, "UObject"))
             }
-            ExprKind::ComputedJump { offset_expr } => {
-                let expr = self.format_expr_inline(offset_expr, &FormatContext::This);
-                println!("{}goto {};", self.indent(), expr);
+            PropertyType::SoftClass { meta_class, .. } => {
+                format!("TSoftClassPtr<{}>", class(meta_class, "UObject"))
             }
-            ExprKind::SwitchValue {
-                index,
-                cases,
-                default,
-                end_offset: _,
-            } => {
-                let index_expr = self.format_expr_inline(index, &FormatContext::This);
-                println!("{}switch ({}) {{", self.indent(), index_expr);
-                self.add_indent();
+            PropertyType::LazyObject { property_class } => {
+                format!("TLazyObjectPtr<{}>", class(property_class, "UObject"))
+            }
+            PropertyType::Interface { interface_class } => {
+                let name = class(interface_class, "UInterface");
+                format!("TScriptInterface<I{}>", &name[1..])
+            }
+            PropertyType::FieldPath => "FFieldPath".to_string(),
+            PropertyType::Optional { inner } => format!("TOptional<{}>", self.cpp_type(inner)),
+        }
+    }
 
-                for case in cases {
-                    let case_val = self.format_expr_inline(&case.case_value, &FormatContext::This);
-                    println!("{}case {}:", self.indent(), case_val);
-                    self.add_indent();
-                    let result = self.format_expr_inline(&case.result, &FormatContext::This);
-                    if !result.is_empty() {
-                        println!("{}{};", self.indent(), result);
+    /// A function's C++ declaration, such as `bool TryUse(AActor* User, int32& Uses)`
+    pub fn signature(&self, name: &str, function: &'a jmap::Function) -> String {
+        let mut return_type = "void".to_string();
+        let mut params = Vec::new();
+        for prop in &function.r#struct.properties {
+            let flags = prop.flags.bits();
+            if flags & CPF_RETURN_PARM != 0 {
+                return_type = self.cpp_type(prop);
+            } else if flags & CPF_PARM != 0 {
+                let by_reference = match flags & CPF_OUT_PARM {
+                    0 => "",
+                    _ => "&",
+                };
+                params.push(format!(
+                    "{}{} {}",
+                    self.cpp_type(prop),
+                    by_reference,
+                    self.address_index.property_name(prop)
+                ));
+            }
+        }
+        format!("{} {}({})", return_type, name, params.join(", "))
+    }
+
+    /// Format a `SwitchValue` in an expression as valid C++
+    ///
+    /// A chain of ternaries when the index can be repeated in each test; when
+    /// it can't, a lambda evaluates it once, with a real `switch` if every
+    /// case value can be a `case` label. An index labeled with its enum's
+    /// names is cast to the enum, as a byte can't be compared with them.
+    fn format_switch_value_lifted(
+        &self,
+        index: &Expr,
+        cases: &[SwitchCase],
+        default: &Expr,
+        context: &FormatContext,
+    ) -> String {
+        let mut index_str = self.format_expr_inline(index, context);
+        if let Some(e) = self.switch_enum(index) {
+            index_str = format!("({}){}", Theme::type_name(&e.cpp_type), index_str);
+        }
+        let format = |expr: &Expr| self.format_expr_inline(expr, &FormatContext::This);
+        let (labeled, _) = self.switch_cases(index, cases);
+        let ternaries = |index_str: &str| {
+            let mut chain = String::new();
+            for (label, case) in &labeled {
+                chain.push_str(&format!(
+                    "{} == {} ? {} : ",
+                    index_str,
+                    label,
+                    format(&case.result)
+                ));
+            }
+            chain + &format(default)
+        };
+
+        if is_repeatable(index) {
+            format!("({})", ternaries(&index_str))
+        } else if cases
+            .iter()
+            .all(|case| is_integral_literal(&case.case_value))
+        {
+            let mut labels = String::new();
+            for (label, case) in &labeled {
+                labels.push_str(&format!(
+                    "case {}: return {}; ",
+                    label,
+                    format(&case.result)
+                ));
+            }
+            format!(
+                "[&] {{ switch ({}) {{ {}default: return {}; }} }}()",
+                index_str,
+                labels,
+                format(default)
+            )
+        } else {
+            format!(
+                "[&] {{ const auto SwitchIndex = {}; return {}; }}()",
+                index_str,
+                ternaries("SwitchIndex")
+            )
+        }
+    }
+
+    /// Format the arguments of a call, naming integer constants passed to enum parameters
+    ///
+    /// Arguments the caller leaves unset print as `/*unset*/`. Trailing ones
+    /// are dropped, but only when the callee's parameters are known and match
+    /// the arguments, so the ones left still line up with their parameters.
+    fn format_call_params(&self, func: &FunctionRef, params: &[Expr]) -> Vec<String> {
+        let callee_params = match func {
+            FunctionRef::ByAddress(addr) => self.address_index.function_params(*addr),
+            _ => None,
+        };
+        let passed = match &callee_params {
+            Some(props) if self.options.elide_omitted_args && props.len() == params.len() => {
+                params.len()
+                    - params
+                        .iter()
+                        .rev()
+                        .take_while(|p| is_omitted_arg(p))
+                        .count()
+            }
+            _ => params.len(),
+        };
+        params[..passed]
+            .iter()
+            .enumerate()
+            .map(|(i, param)| {
+                if is_omitted_arg(param) {
+                    if self.options.valid_cpp {
+                        return format!("{{}} {}", Theme::comment("/*unset*/"));
                     }
-                    println!("{}break;", self.indent());
-                    self.drop_indent();
+                    return Theme::comment("/*unset*/").to_string();
                 }
+                self.param_enum(func, i)
+                    .and_then(|e| format_enum_value(e, integer_literal(param)?))
+                    .unwrap_or_else(|| self.format_expr_inline(param, &FormatContext::This))
+            })
+            .collect()
+    }
 
-                println!("{}default:", self.indent());
-                self.add_indent();
-                let default_result = self.format_expr_inline(default, &FormatContext::This);
-                if !default_result.is_empty() {
-                    println!("{}{};", self.indent(), default_result);
-                }
-                println!("{}break;", self.indent());
-                self.drop_indent();
+    /// Format a struct literal as a designated initializer, e.g. `FHitResult{ .Time = 1f }`
+    ///
+    /// Fields holding default values are omitted. Falls back to positional
+    /// initializers when the struct layout can't be matched to the elements,
+    /// or when sugar is turned off.
+    fn format_struct_const(&self, struct_type: &StructRef, elements: &[Expr]) -> String {
+        if self.options.sugar
+            && let Some(known) = self.address_index.known_struct(struct_type.address)
+            && let Some(formatted) = self.try_format_known_struct(known, elements)
+        {
+            return formatted;
+        }
 
-                self.drop_indent();
-                println!("{}}}", self.indent());
+        let struct_name = self.resolve_struct(struct_type);
+        let type_name = format!("F{}", struct_name.rsplit('.').next().unwrap_or(struct_name));
+
+        let field_names: Option<Vec<String>> = self
+            .address_index
+            .struct_properties(struct_type.address)
+            .filter(|_| self.options.sugar)
+            .map(|props| {
+                props
+                    .iter()
+                    .flat_map(|prop| {
+                        (0..prop.array_dim).map(move |i| {
+                            let name = self.address_index.property_name(prop);
+                            if prop.array_dim > 1 {
+                                format!("{}[{}]", name, i)
+                            } else {
+                                name.to_string()
+                            }
+                        })
+                    })
+                    .collect()
+            });
+
+        let elem_strs: Vec<String> = match field_names {
+            Some(names) if names.len() == elements.len() => names
+                .iter()
+                .zip(elements)
+                .filter(|(_, e)| !is_default_literal(e))
+                .map(|(name, e)| {
+                    format!(
+                        ".{} = {}",
+                        Theme::variable(name),
+                        self.format_expr_inline(e, &FormatContext::This)
+                    )
+                })
+                .collect(),
+            _ => elements
+                .iter()
+                .map(|e| self.format_expr_inline(e, &FormatContext::This))
+                .collect(),
+        };
+
+        if elem_strs.is_empty() {
+            format!("{}{{}}", Theme::type_name(type_name))
+        } else {
+            format!(
+                "{}{{ {} }}",
+                Theme::type_name(type_name),
+                elem_strs.join(", ")
+            )
+        }
+    }
+
+    /// Format a literal of a common engine struct as a constructor call,
+    /// e.g. `FLinearColor(1f, 0.5f, 0f, 1f)` or `FDateTime(2024, 3, 1)`
+    fn try_format_known_struct(&self, known: KnownStruct, elements: &[Expr]) -> Option<String> {
+        if elements.len() != known.element_count() {
+            return None;
+        }
+
+        let args: Vec<String> = match known {
+            KnownStruct::DateTime => {
+                let ExprKind::Int64Const(ticks) = elements[0].kind else {
+                    return None;
+                };
+                if ticks == 0 {
+                    Vec::new()
+                } else {
+                    let components = date_time_components(ticks)?;
+                    // Year, month and day are required, the time of day is trimmed when zero
+                    let len = components[3..]
+                        .iter()
+                        .rposition(|&c| c != 0)
+                        .map_or(3, |i| i + 4);
+                    components[..len]
+                        .iter()
+                        .map(|c| Theme::numeric(c.to_string()).to_string())
+                        .collect()
+                }
             }
+            _ => elements
+                .iter()
+                .map(|e| self.format_expr_inline(e, &FormatContext::This))
+                .collect(),
+        };
 
-            // Delegates
-            ExprKind::BindDelegate {
-                func_name,
-                delegate_expr,
-                object_expr,
-            } => {
-                let delegate = self.format_expr_inline(delegate_expr, &FormatContext::This);
-                let object = self.format_expr_inline(object_expr, &FormatContext::This);
-                println!(
-                    "{}{}.BindDynamic({}, &{}::{});",
-                    self.indent(),
-                    delegate,
-                    object,
-                    object,
-                    func_name.as_str()
+        Some(format!(
+            "{}({})",
+            Theme::type_name(known.type_name()),
+            args.join(", ")
+        ))
+    }
+
+    fn format_label(&self, offset: BytecodeOffset) -> String {
+        Theme::label(Label::new(offset)).to_string()
+    }
+
+    /// Why printing `expr` would guess at what it does, or nothing if it's
+    /// all read straight from the bytecode
+    pub fn doubt_reasons(&self, expr: &Expr) -> Vec<String> {
+        let mut reasons = Vec::new();
+        expr.walk(&mut |e| {
+            if let ExprKind::ComputedJump { .. } = e.kind {
+                reasons.push(
+                    "a computed jump's targets aren't in the bytecode; the code nothing \
+                     else jumps to is taken to be what it jumps to"
+                        .to_string(),
                 );
             }
-            ExprKind::AddMulticastDelegate {
-                delegate_expr,
-                to_add_expr,
-            } => {
-                let delegate = self.format_expr_inline(delegate_expr, &FormatContext::This);
-                let to_add = self.format_expr_inline(to_add_expr, &FormatContext::This);
-                println!("{}{}.AddDynamic({});", self.indent(), delegate, to_add);
-            }
-            ExprKind::RemoveMulticastDelegate {
-                delegate_expr,
-                to_remove_expr,
-            } => {
-                let delegate = self.format_expr_inline(delegate_expr, &FormatContext::This);
-                let to_remove = self.format_expr_inline(to_remove_expr, &FormatContext::This);
-                println!(
-                    "{}{}.RemoveDynamic({});",
-                    self.indent(),
-                    delegate,
-                    to_remove
-                );
+            if !self.options.sugar {
+                return;
             }
-            ExprKind::ClearMulticastDelegate(delegate_expr) => {
-                let delegate = self.format_expr_inline(delegate_expr, &FormatContext::This);
-                println!("{}{}.Clear();", self.indent(), delegate);
+            let Some((func, params)) = call_parts(e) else {
+                return;
+            };
+            if let ExprKind::CallMath { .. } = e.kind
+                && let Some(intrinsic) =
+                    intrinsics::lookup_operator_intrinsic(self.resolve_function(func))
+                && intrinsic.op.arity() == params.len()
+                && !self.operands_confirmed(func, intrinsic)
+            {
+                reasons.push(format!(
+                    "`{}` is printed for {} without its parameters in the dump to confirm \
+                     the operands are {}",
+                    intrinsic.op.symbol(),
+                    intrinsic.name,
+                    format!("{:?}", intrinsic.operand).to_lowercase()
+                ));
             }
-            ExprKind::CallMulticastDelegate {
-                stack_node: _,
-                delegate_expr,
-                params,
-            } => {
-                let delegate = self.format_expr_inline(delegate_expr, &FormatContext::This);
-                let param_strs: Vec<String> = params
-                    .iter()
-                    .map(|p| self.format_expr_inline(p, &FormatContext::This))
-                    .collect();
-                println!(
-                    "{}{}.Broadcast({});",
-                    self.indent(),
-                    delegate,
-                    param_strs.join(", ")
-                );
+            for (index, param) in params.iter().enumerate() {
+                if let Some(e) = self.param_enum(func, index)
+                    && let Some(value) = integer_literal(param)
+                    && is_flag_combination(e, value)
+                {
+                    reasons.push(format!(
+                        "{} is printed as flags of {}, taken to be a bitmask because each \
+                         of its values is a single bit and they don't just count up",
+                        value, e.cpp_type
+                    ));
+                }
             }
+        });
+        reasons
+    }
 
-            // Debug/instrumentation
-            ExprKind::Assert {
-                line,
-                in_debug: _,
-                condition,
-            } => {
-                let cond = self.format_expr_inline(condition, &FormatContext::This);
-                println!("{}check({}); // line {}", self.indent(), cond, line);
-            }
-            ExprKind::PushExecutionFlow { push_offset } => {
-                println!(
-                    "{}PushExecutionFlow({});",
-                    self.indent(),
-                    self.format_label(*push_offset)
-                );
-            }
-            ExprKind::PopExecutionFlow => {
-                println!("{}PopExecutionFlow;", self.indent());
-            }
-            ExprKind::PopExecutionFlowIfNot { condition } => {
-                let cond = self.format_expr_inline(condition, &FormatContext::This);
-                println!("{}PopExecutionFlowIfNot({});", self.indent(), cond);
-            }
-            ExprKind::Breakpoint => {
-                println!("{} <<< BREAKPOINT >>>", self.indent());
-            }
-            ExprKind::Tracepoint | ExprKind::WireTracepoint => {
-                println!("{} <<< TRACEPOINT >>>", self.indent());
-            }
-            ExprKind::InstrumentationEvent { event_type } => {
-                println!(
-                    "{} <<< INSTRUMENTATION EVENT {} >>>",
-                    self.indent(),
-                    event_type
-                );
-            }
-            ExprKind::EndOfScript => {
-                println!("{}// End of script", self.indent());
-            }
+    /// Whether the dump has the parameters of an operator's function, with
+    /// the types its name says
+    fn operands_confirmed(&self, func: &FunctionRef, intrinsic: &OperatorIntrinsic) -> bool {
+        let FunctionRef::ByAddress(address) = func else {
+            return false;
+        };
+        let Some(params) = self.address_index.function_params(*address) else {
+            return false;
+        };
+        params.len() == intrinsic.op.arity()
+            && params.iter().all(|param| {
+                matches!(
+                    (&param.r#type, intrinsic.operand),
+                    (PropertyType::Bool { .. }, OperandType::Bool)
+                        | (PropertyType::Byte { .. }, OperandType::Byte)
+                        | (PropertyType::Int, OperandType::Int)
+                        | (PropertyType::Int64, OperandType::Int64)
+                        | (PropertyType::Float, OperandType::Float)
+                        | (PropertyType::Double, OperandType::Double)
+                )
+            })
+    }
 
-            // Everything else - try to format as expression statement
-            _ => {
-                let expr_str = self.format_expr_inline(expr, &FormatContext::This);
-                if !expr_str.is_empty() {
-                    println!("{}{};", self.indent(), expr_str);
-                }
-            }
+    /// The enum the parameter at `index` of a called function holds, if
+    /// sugar names its values
+    fn param_enum(&self, func: &FunctionRef, index: usize) -> Option<&'a Enum> {
+        let FunctionRef::ByAddress(address) = func else {
+            return None;
+        };
+        if !self.options.sugar {
+            return None;
+        }
+        let params = self.address_index.function_params(*address)?;
+        self.address_index.property_enum(params.get(index)?)
+    }
+
+    /// The enum a switch's index holds, when it's a variable of an enum type
+    fn switch_enum(&self, index: &Expr) -> Option<&'a Enum> {
+        if !self.options.sugar {
+            return None;
         }
+        let property = self
+            .address_index
+            .resolve_property(variable_property(index)?.address)?;
+        self.address_index.property_enum(property.property)
+    }
+
+    /// A switch's cases, each with the label to print for its value, and the
+    /// names of the index's enum values that no case handles
+    ///
+    /// On an enum, values are labeled with the enum's names, or cast to it if
+    /// it has no name for them, and cases are put in the enum's order unless
+    /// two share a value, as then the first of them is the one that runs.
+    fn switch_cases<'c>(
+        &self,
+        index: &Expr,
+        cases: &'c [SwitchCase],
+    ) -> (Vec<(String, &'c SwitchCase)>, Vec<String>) {
+        let e = self.switch_enum(index);
+        let mut labeled: Vec<(String, &SwitchCase)> = cases
+            .iter()
+            .map(|case| {
+                let label = match (e, integer_literal(&case.case_value)) {
+                    (Some(e), Some(value)) => match enum_entries(e).find(|(_, v)| *v == value) {
+                        Some((name, _)) => {
+                            Theme::type_name(qualified_enum_name(e, name)).to_string()
+                        }
+                        None => format!("({}){}", Theme::type_name(&e.cpp_type), value),
+                    },
+                    _ => self.format_expr_inline(&case.case_value, &FormatContext::This),
+                };
+                (label, case)
+            })
+            .collect();
+        let Some(e) = e else {
+            return (labeled, Vec::new());
+        };
+
+        let values: HashSet<i64> = cases
+            .iter()
+            .filter_map(|case| integer_literal(&case.case_value))
+            .collect();
+        if values.len() == cases.len() {
+            labeled.sort_by_key(|(_, case)| {
+                let value = integer_literal(&case.case_value);
+                enum_entries(e)
+                    .position(|(_, v)| Some(*v) == value)
+                    .unwrap_or(usize::MAX)
+            });
+        }
+        let unhandled = enum_entries(e)
+            .filter(|(_, v)| !values.contains(v))
+            .map(|(name, _)| qualified_enum_name(e, name))
+            .collect();
+        (labeled, unhandled)
     }
 
     pub fn format_expr_inline(&self, expr: &Expr, context: &FormatContext) -> String {
+        ensure_stack(|| self.format_nested_expr(expr, context))
+    }
+
+    fn format_nested_expr(&self, expr: &Expr, context: &FormatContext) -> String {
         match &expr.kind {
             // Variables
             ExprKind::LocalVariable(prop)
@@ -472,18 +875,20 @@ impl<'a> CppFormatter<'a> {
             }
             ExprKind::InstanceVariable(prop) => {
                 let name = self.resolve_property(prop);
-                let obj = match context {
-                    FormatContext::This => Theme::object_ref("this").to_string(),
-                    FormatContext::Object(obj) => obj.clone(),
-                };
-                format!("{}.{}", obj, Theme::variable(name))
+                format!(
+                    "{}{}{}",
+                    self.member_prefix(context),
+                    Theme::variable(name),
+                    self.prop_offset_note(prop)
+                )
             }
             ExprKind::DefaultVariable(prop) => {
                 let name = self.resolve_property(prop);
                 format!(
-                    "{}.{}",
+                    "{}->{}{}",
                     Theme::object_ref("GetDefaultObject()"),
-                    Theme::variable(name)
+                    Theme::variable(name),
+                    self.prop_offset_note(prop)
                 )
             }
 
@@ -498,46 +903,62 @@ impl<'a> CppFormatter<'a> {
             }
 
             // Constants - floating point
+            ExprKind::FloatConst(val) if self.options.valid_cpp && val.get().is_finite() => {
+                // `5f` isn't a C++ literal
+                Theme::numeric(format!("{:?}f", val.get())).to_string()
+            }
             ExprKind::FloatConst(val) => Theme::numeric(format!("{}f", val)).to_string(),
+            // No suffix, and a fraction so an integral value stays a double
+            ExprKind::DoubleConst(val) => Theme::numeric(format!("{:?}", val)).to_string(),
 
             // Constants - strings
-            ExprKind::StringConst(val) => crate::formatters::theme::quoted_string(val).to_string(),
+            ExprKind::StringConst(val) => {
+                Theme::string(format!("\"{}\"", self.literal_text(val))).to_string()
+            }
             ExprKind::UnicodeStringConst(val) => {
-                Theme::string(format!("TEXT(\"{}\")", val)).to_string()
+                Theme::string(format!("TEXT(\"{}\")", self.literal_text(val))).to_string()
             }
             ExprKind::NameConst(name) => {
-                Theme::string(format!("FName(\"{}\")", name.as_str())).to_string()
+                Theme::string(format!("FName(\"{}\")", self.literal_text(name.as_str())))
+                    .to_string()
             }
 
             // Constants - vectors and transforms
             ExprKind::VectorConst { x, y, z } => {
                 Theme::type_name(format!("FVector({}, {}, {})", x, y, z)).to_string()
             }
+            ExprKind::Vector3fConst { x, y, z } => {
+                Theme::type_name(format!("FVector3f({}, {}, {})", x, y, z)).to_string()
+            }
             ExprKind::RotationConst { pitch, yaw, roll } => {
                 Theme::type_name(format!("FRotator({}, {}, {})", pitch, yaw, roll)).to_string()
             }
-            ExprKind::TransformConst {
-                rot_x,
-                rot_y,
-                rot_z,
-                rot_w,
-                trans_x,
-                trans_y,
-                trans_z,
-                scale_x,
-                scale_y,
-                scale_z,
-            } => Theme::type_name(format!(
+            ExprKind::TransformConst(transform) => Theme::type_name(format!(
                 "FTransform(FQuat({}, {}, {}, {}), FVector({}, {}, {}), FVector({}, {}, {}))",
-                rot_x, rot_y, rot_z, rot_w, trans_x, trans_y, trans_z, scale_x, scale_y, scale_z
+                transform.rot_x,
+                transform.rot_y,
+                transform.rot_z,
+                transform.rot_w,
+                transform.trans_x,
+                transform.trans_y,
+                transform.trans_z,
+                transform.scale_x,
+                transform.scale_y,
+                transform.scale_z
             ))
             .to_string(),
 
             // Constants - special values
             ExprKind::True => Theme::keyword("true").to_string(),
             ExprKind::False => Theme::keyword("false").to_string(),
+            ExprKind::BitFieldConst { value, .. } => {
+                Theme::keyword(if *value != 0 { "true" } else { "false" }).to_string()
+            }
             ExprKind::NoObject | ExprKind::NoInterface => Theme::null_value("nullptr").to_string(),
             ExprKind::Self_ => Theme::object_ref("this").to_string(),
+            ExprKind::Nothing | ExprKind::NothingInt32 if self.options.valid_cpp => {
+                Theme::null_value("{}").to_string()
+            }
             ExprKind::Nothing | ExprKind::NothingInt32 => {
                 Theme::null_value("<Nothing>").to_string()
             }
@@ -545,20 +966,40 @@ impl<'a> CppFormatter<'a> {
             // Function calls
             ExprKind::VirtualFunction { func, params }
             | ExprKind::FinalFunction { func, params } => {
-                let func_name = self.resolve_function(func);
-                let param_strs: Vec<String> = params
-                    .iter()
-                    .map(|p| self.format_expr_inline(p, &FormatContext::This))
-                    .collect();
+                let param_strs = self.format_call_params(func, params);
+                if let ExprKind::FinalFunction { .. } = expr.kind
+                    && let FormatContext::This = context
+                    && let Some(name) = self.super_call_name(func)
+                {
+                    return format!(
+                        "{}::{}({})",
+                        Theme::keyword("Super"),
+                        Theme::function(name),
+                        param_strs.join(", ")
+                    );
+                }
+                let func_name = self.called_name(func, false);
                 // These can be called on an object context
                 match context {
                     FormatContext::This => {
                         format!("{}({})", Theme::function(func_name), param_strs.join(", "))
                     }
-                    FormatContext::Object(obj) => {
+                    FormatContext::Object(_, MemberAccess::Static) => {
+                        // The class is already named, so only the function's own name
+                        let name = func_name
+                            .rsplit_once(':')
+                            .map_or(&*func_name, |(_, name)| name);
                         format!(
-                            "{}.{}({})",
-                            obj,
+                            "{}{}({})",
+                            self.member_prefix(context),
+                            Theme::function(name),
+                            param_strs.join(", ")
+                        )
+                    }
+                    FormatContext::Object(..) => {
+                        format!(
+                            "{}{}({})",
+                            self.member_prefix(context),
                             Theme::function(func_name),
                             param_strs.join(", ")
                         )
@@ -566,45 +1007,41 @@ impl<'a> CppFormatter<'a> {
                 }
             }
             ExprKind::CallMath { func, params } => {
+                if self.options.simplify
+                    && let Some(simplified) =
+                        simplify(expr, &|func| Some(self.resolve_function(func).to_string()))
+                {
+                    return self.format_nested_expr(&simplified, context);
+                }
+
                 // Get the full function path for operator matching
-                let full_path = match func {
-                    FunctionRef::ByAddress(addr) => self
-                        .address_index
-                        .resolve_object(*addr)
-                        .unwrap()
-                        .path
-                        .to_string(),
-                    FunctionRef::ByName(name) => name.as_str().to_string(),
-                };
+                let full_path = self.resolve_function(func);
 
-                let param_strs: Vec<String> = params
-                    .iter()
-                    .map(|p| self.format_expr_inline(p, &FormatContext::This))
-                    .collect();
+                if let Some(idiom_form) = self.try_format_as_idiom(full_path, params) {
+                    return idiom_form;
+                }
+
+                let param_strs = self.format_call_params(func, params);
 
                 // Try to format as an operator first
-                if let Some(operator_form) = self.try_format_as_operator(&full_path, &param_strs) {
+                if let Some(operator_form) = self.try_format_as_operator(full_path, &param_strs) {
                     return operator_form;
                 }
 
                 // Otherwise, format as a function call
-                let func_name = self.resolve_function(func);
-                format!("{}({})", Theme::function(func_name), param_strs.join(", "))
+                format!(
+                    "{}({})",
+                    Theme::function(self.called_name(func, true)),
+                    param_strs.join(", ")
+                )
             }
             ExprKind::LocalVirtualFunction { func, params }
             | ExprKind::LocalFinalFunction { func, params } => {
-                let func_name = self.resolve_function(func);
-                let param_strs: Vec<String> = params
-                    .iter()
-                    .map(|p| self.format_expr_inline(p, &FormatContext::This))
-                    .collect();
-                let obj = match context {
-                    FormatContext::This => Theme::object_ref("this").to_string(),
-                    FormatContext::Object(obj) => obj.clone(),
-                };
+                let func_name = self.called_name(func, false);
+                let param_strs = self.format_call_params(func, params);
                 format!(
-                    "{}.{}({})",
-                    obj,
+                    "{}{}({})",
+                    self.member_prefix(context),
                     Theme::function(func_name),
                     param_strs.join(", ")
                 )
@@ -616,18 +1053,30 @@ impl<'a> CppFormatter<'a> {
                 field: _,
                 context,
                 skip_offset: _,
-                fail_silent: _,
+                fail_silent,
+            } => {
+                if let Some(new_context) = self.singleton_context(object, context) {
+                    return self.format_expr_inline(context, &new_context);
+                }
+                // The object expression determines the new context. Nested contexts
+                // chain naturally, e.g. `GetOwner()->GetMesh()->SetVisibility(true)`
+                let obj_expr = self.format_expr_inline(object, &FormatContext::This);
+                let access = match self.member_access_for(object) {
+                    MemberAccess::Pointer if *fail_silent => MemberAccess::SafeNavigation,
+                    access => access,
+                };
+                // Format the context expression with the new object context
+                let new_context = FormatContext::Object(obj_expr, access);
+                self.format_expr_inline(context, &new_context)
             }
-            | ExprKind::ClassContext {
+            ExprKind::ClassContext {
                 object,
                 field: _,
                 context,
                 skip_offset: _,
             } => {
-                // The object expression determines the new context
                 let obj_expr = self.format_expr_inline(object, &FormatContext::This);
-                // Format the context expression with the new object context
-                let new_context = FormatContext::Object(obj_expr.clone());
+                let new_context = FormatContext::Object(obj_expr, self.member_access_for(object));
                 self.format_expr_inline(context, &new_context)
             }
             ExprKind::StructMemberContext {
@@ -636,22 +1085,38 @@ impl<'a> CppFormatter<'a> {
             } => {
                 let expr = self.format_expr_inline(struct_expr, &FormatContext::This);
                 let member_name = self.resolve_property(member);
-                format!("{}.{}", expr, Theme::variable(member_name))
+                format!(
+                    "{}.{}{}",
+                    expr,
+                    Theme::variable(member_name),
+                    self.prop_offset_note(member)
+                )
             }
             ExprKind::InterfaceContext(expr) => {
                 let inner = self.format_expr_inline(expr, &FormatContext::This);
+                if self.options.valid_cpp {
+                    return format!("{}.{}()", inner, Theme::function("GetObject"));
+                }
                 format!("<InterfaceContext>({})", inner)
             }
 
             // Casts
             ExprKind::DynamicCast { target_class, expr } => {
-                let class_name = self.resolve_class(target_class);
+                let class_name = self.cast_target(target_class, false);
                 let expr_str = self.format_expr_inline(expr, &FormatContext::This);
-                format!("Cast<{}>({})", Theme::type_name(class_name), expr_str)
+                let cast = match self.faithful_casts() {
+                    true => "DynamicCast",
+                    false => "Cast",
+                };
+                format!("{}<{}>({})", cast, Theme::type_name(class_name), expr_str)
             }
             ExprKind::MetaCast { target_class, expr } => {
-                let class_name = self.resolve_class(target_class);
+                let class_name = self.cast_target(target_class, false);
                 let expr_str = self.format_expr_inline(expr, &FormatContext::This);
+                if self.options.valid_cpp {
+                    let todo = format!("/* TODO: null unless a subclass of {} */", class_name);
+                    return format!("Cast<UClass>({}) {}", expr_str, Theme::comment(todo));
+                }
                 format!("MetaCast<{}>({})", Theme::type_name(class_name), expr_str)
             }
             ExprKind::PrimitiveCast {
@@ -659,23 +1124,66 @@ impl<'a> CppFormatter<'a> {
                 expr,
             } => {
                 let expr_str = self.format_expr_inline(expr, &FormatContext::This);
+                if self.options.valid_cpp {
+                    let todo = format!("/* TODO: {} conversion */", conversion_type);
+                    return format!("{} {}", expr_str, Theme::comment(todo));
+                }
                 format!("({}<{}>)", expr_str, conversion_type)
             }
             ExprKind::ObjToInterfaceCast {
                 target_interface,
                 expr,
+            } => {
+                let class_name = self.cast_target(target_interface, true);
+                let expr_str = self.format_expr_inline(expr, &FormatContext::This);
+                let cast = match (self.faithful_casts(), self.options.valid_cpp) {
+                    (true, _) => "ObjToInterfaceCast",
+                    (false, true) => "TScriptInterface",
+                    (false, false) => "ScriptInterfaceCast",
+                };
+                format!("{}<{}>({})", cast, Theme::type_name(class_name), expr_str)
             }
-            | ExprKind::InterfaceToObjCast {
-                target_class: target_interface,
-                expr,
-            }
-            | ExprKind::CrossInterfaceCast {
+            ExprKind::CrossInterfaceCast {
                 target_interface,
                 expr,
             } => {
-                let class_name = self.resolve_class(target_interface);
+                let class_name = self.cast_target(target_interface, true);
                 let expr_str = self.format_expr_inline(expr, &FormatContext::This);
-                format!("Cast<{}>({})", Theme::type_name(class_name), expr_str)
+                if self.faithful_casts() {
+                    return format!(
+                        "CrossInterfaceCast<{}>({})",
+                        Theme::type_name(class_name),
+                        expr_str
+                    );
+                }
+                let cast = match self.options.valid_cpp {
+                    true => "TScriptInterface",
+                    false => "ScriptInterfaceCast",
+                };
+                format!(
+                    "{}<{}>({}.{}())",
+                    cast,
+                    Theme::type_name(class_name),
+                    expr_str,
+                    Theme::function("GetObject")
+                )
+            }
+            ExprKind::InterfaceToObjCast { target_class, expr } => {
+                let class_name = self.cast_target(target_class, false);
+                let expr_str = self.format_expr_inline(expr, &FormatContext::This);
+                if self.faithful_casts() {
+                    return format!(
+                        "InterfaceToObjCast<{}>({})",
+                        Theme::type_name(class_name),
+                        expr_str
+                    );
+                }
+                format!(
+                    "Cast<{}>({}.{}())",
+                    Theme::type_name(class_name),
+                    expr_str,
+                    Theme::function("GetObject")
+                )
             }
 
             // Collections
@@ -699,18 +1207,7 @@ impl<'a> CppFormatter<'a> {
                 struct_type,
                 serialized_size: _,
                 elements,
-            } => {
-                let struct_name = self.resolve_struct(struct_type);
-                let elem_strs: Vec<String> = elements
-                    .iter()
-                    .map(|e| self.format_expr_inline(e, &FormatContext::This))
-                    .collect();
-                format!(
-                    "{}{{ {} }}",
-                    Theme::type_name(struct_name),
-                    elem_strs.join(", ")
-                )
-            }
+            } => self.format_struct_const(struct_type, elements),
             ExprKind::SetConst {
                 element_type,
                 num_elements: _,
@@ -827,6 +1324,13 @@ impl<'a> CppFormatter<'a> {
                     .resolve_object(obj.address)
                     .map(|o| o.path)
                     .unwrap_or("<err resolving object>");
+                if self.options.valid_cpp {
+                    return format!(
+                        "{}<UObject>(nullptr, {})",
+                        Theme::function("FindObject"),
+                        Theme::string(format!("TEXT(\"{}\")", path))
+                    );
+                }
                 Theme::object_ref(path).to_string()
             }
             ExprKind::PropertyConst(prop) => {
@@ -836,6 +1340,14 @@ impl<'a> CppFormatter<'a> {
             ExprKind::SkipOffsetConst(offset) => self.format_label(*offset),
 
             // Control flow as expressions
+            ExprKind::SwitchValue {
+                index,
+                cases,
+                default,
+                end_offset: _,
+            } if self.options.valid_cpp => {
+                self.format_switch_value_lifted(index, cases, default, context)
+            }
             ExprKind::SwitchValue {
                 index,
                 cases,
@@ -846,8 +1358,7 @@ impl<'a> CppFormatter<'a> {
                 let index_str = self.format_expr_inline(index, context);
                 let mut case_strs = Vec::new();
 
-                for case in cases {
-                    let case_val = self.format_expr_inline(&case.case_value, &FormatContext::This);
+                for (case_val, case) in self.switch_cases(index, cases).0 {
                     let case_result = self.format_expr_inline(&case.result, &FormatContext::This);
                     case_strs.push(format!("{} => {}", case_val, case_result));
                 }
@@ -865,7 +1376,1042 @@ impl<'a> CppFormatter<'a> {
             }
 
             // Other
-            _ => Theme::comment(format!("<{:?}>", expr.kind)).to_string(),
+            _ => {
+                let debug = format!("{:?}", expr.kind);
+                let debug = if self.options.normalize_addresses {
+                    self.address_index.normalize_debug_addresses(&debug)
+                } else {
+                    debug
+                };
+                if self.options.valid_cpp {
+                    let todo = format!("/* TODO: no C++ for {} */", debug);
+                    return format!("{{}} {}", Theme::comment(todo));
+                }
+                Theme::comment(format!("<{}>", debug)).to_string()
+            }
+        }
+    }
+}
+
+impl<'r, 'a> StatementWriter<'r, 'a> {
+    pub fn new(
+        renderer: &'r ExprRenderer<'a>,
+        referenced_offsets: HashSet<BytecodeOffset>,
+    ) -> Self {
+        Self {
+            renderer,
+            referenced_offsets,
+            indent_level: 0,
+            statement_prefix: String::new(),
+            statement_marker: String::new(),
+            doubts: Doubts::default(),
+        }
+    }
+
+    /// Number doubt markers on from `doubts`, raised by an earlier writer
+    /// printing the same function
+    pub fn with_doubts(mut self, doubts: Doubts) -> Self {
+        self.doubts = doubts;
+        self
+    }
+
+    /// The reasons behind the doubt markers this writer printed, for the
+    /// next writer printing the same function or for explaining them
+    pub fn into_doubts(self) -> Doubts {
+        self.doubts
+    }
+
+    /// Markers for what printing `expr` guesses at, each followed by a
+    /// space, or nothing if it's all read straight from the bytecode
+    pub fn doubt_marker(&mut self, expr: &Expr) -> String {
+        self.doubts.raise_all(self.renderer.doubt_reasons(expr))
+    }
+
+    fn indent(&self) -> String {
+        format!(
+            "{}{}{}",
+            "    ".repeat(self.indent_level),
+            self.statement_prefix,
+            self.statement_marker
+        )
+    }
+
+    fn add_indent(&mut self) {
+        self.indent_level += 1;
+    }
+
+    fn drop_indent(&mut self) {
+        if self.indent_level > 0 {
+            self.indent_level -= 1;
+        }
+    }
+
+    pub fn set_indent_level(&mut self, level: usize) {
+        self.indent_level = level;
+    }
+
+    pub fn set_statement_prefix(&mut self, prefix: String) {
+        self.statement_prefix = prefix;
+    }
+
+    pub fn clear_statement_prefix(&mut self) {
+        self.statement_prefix.clear();
+    }
+
+    pub fn format(&mut self, out: &mut dyn Write, expressions: &[Expr]) -> fmt::Result {
+        let _span = info_span!("format").entered();
+        let inside = targets_inside_statements(expressions, &self.referenced_offsets);
+        for expr in expressions {
+            // Only print label if this offset is referenced
+            if self.referenced_offsets.contains(&expr.offset) {
+                writeln!(
+                    out,
+                    "{}{}:",
+                    self.indent(),
+                    self.renderer.format_label(expr.offset)
+                )?;
+            }
+            self.add_indent();
+            for &target in inside.get(&expr.offset).into_iter().flatten() {
+                let note = format!("// {} is inside this statement", Label::new(target));
+                writeln!(out, "{}{}", self.indent(), Theme::comment(note))?;
+            }
+            if self.renderer.options.show_costs {
+                self.set_statement_prefix(format!("/* cost {} */ ", expr_cost(expr)));
+            }
+            self.format_statement(out, expr)?;
+            self.drop_indent();
+        }
+        self.clear_statement_prefix();
+        if self.renderer.options.explain {
+            self.doubts.explain(out)?;
+        }
+        Ok(())
+    }
+
+    /// Declare the local variables of a function, which the bytecode only
+    /// ever assigns
+    pub fn declare_locals(&self, out: &mut dyn Write, function: &'a jmap::Function) -> fmt::Result {
+        let locals: Vec<&jmap::Property> = function
+            .r#struct
+            .properties
+            .iter()
+            .filter(|prop| prop.flags.bits() & CPF_PARM == 0)
+            .collect();
+        for local in &locals {
+            let dims = match local.array_dim {
+                0 | 1 => String::new(),
+                n => format!("[{}]", n),
+            };
+            writeln!(
+                out,
+                "{}{} {}{};",
+                self.indent(),
+                Theme::type_name(self.renderer.cpp_type(local)),
+                Theme::variable(self.renderer.address_index.property_name(local)),
+                dims
+            )?;
+        }
+        if !locals.is_empty() {
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    pub fn format_statement(&mut self, out: &mut dyn Write, expr: &Expr) -> fmt::Result {
+        let marker = self.doubt_marker(expr);
+        let outer = std::mem::replace(&mut self.statement_marker, marker);
+        let written = self.write_statement(out, expr);
+        self.statement_marker = outer;
+        written
+    }
+
+    fn write_statement(&mut self, out: &mut dyn Write, expr: &Expr) -> fmt::Result {
+        match &expr.kind {
+            // Assignments
+            ExprKind::Let {
+                property: _,
+                variable,
+                value,
+            } => {
+                let var = self
+                    .renderer
+                    .format_expr_inline(variable, &FormatContext::This);
+                let val = self
+                    .renderer
+                    .format_expr_inline(value, &FormatContext::This);
+                writeln!(out, "{}{} = {};", self.indent(), var, val)?;
+            }
+            ExprKind::LetObj { variable, value }
+            | ExprKind::LetWeakObjPtr { variable, value }
+            | ExprKind::LetBool { variable, value }
+            | ExprKind::LetDelegate { variable, value }
+            | ExprKind::LetMulticastDelegate { variable, value } => {
+                let var = self
+                    .renderer
+                    .format_expr_inline(variable, &FormatContext::This);
+                let val = self
+                    .renderer
+                    .format_expr_inline(value, &FormatContext::This);
+                writeln!(out, "{}{} = {};", self.indent(), var, val)?;
+            }
+            ExprKind::LetValueOnPersistentFrame { property, value } => {
+                let prop_name = self.renderer.resolve_property(property);
+                let val = self
+                    .renderer
+                    .format_expr_inline(value, &FormatContext::This);
+                if !self.renderer.options.sugar {
+                    writeln!(
+                        out,
+                        "{}// PersistentFrame: {}",
+                        self.indent(),
+                        Theme::comment(prop_name)
+                    )?;
+                    writeln!(
+                        out,
+                        "{}{} = {};",
+                        self.indent(),
+                        Theme::variable(prop_name),
+                        val
+                    )?;
+                    return Ok(());
+                }
+                let comment = match &self.renderer.ubergraph_entry {
+                    Some((ubergraph, entry)) => format!(
+                        "// {} on the ubergraph frame, read from {} in {}",
+                        prop_name,
+                        Label::new(*entry),
+                        ubergraph.rsplit(':').next().unwrap_or(ubergraph)
+                    ),
+                    None => format!("// {} on the ubergraph frame", prop_name),
+                };
+                writeln!(out, "{}{}", self.indent(), Theme::comment(&comment))?;
+                // Valid C++ keeps the frame property's own name, which a
+                // class flattened from the blueprint has as a member
+                let target = if self.renderer.options.valid_cpp {
+                    prop_name.to_string()
+                } else {
+                    format!("UbergraphFrame.{}", event_variable_name(prop_name))
+                };
+                writeln!(
+                    out,
+                    "{}{} = {};",
+                    self.indent(),
+                    Theme::variable(&target),
+                    val
+                )?;
+            }
+
+            // Control flow
+            ExprKind::Return(ret_expr) => {
+                let expr_str = self
+                    .renderer
+                    .format_expr_inline(ret_expr, &FormatContext::This);
+                if expr_str == "<Nothing>" || expr_str.is_empty() || is_nothing(ret_expr) {
+                    writeln!(out, "{}return;", self.indent())?;
+                } else {
+                    writeln!(out, "{}return {};", self.indent(), expr_str)?;
+                }
+            }
+            ExprKind::Jump { target } => {
+                writeln!(
+                    out,
+                    "{}goto {};",
+                    self.indent(),
+                    self.renderer.format_label(*target)
+                )?;
+            }
+            ExprKind::JumpIfNot { condition, target } => {
+                let cond = self
+                    .renderer
+                    .format_expr_inline(condition, &FormatContext::This);
+                writeln!(
+                    out,
+                    "{}if (!{}) goto {};",
+                    self.indent(),
+                    cond,
+                    self.renderer.format_label(*target)
+                )?;
+            }
+            ExprKind::ComputedJump { offset_expr } if self.renderer.options.valid_cpp => {
+                let expr = self
+                    .renderer
+                    .format_expr_inline(offset_expr, &FormatContext::This);
+                let todo = format!(
+                    "// TODO: jump to the offset in {}, C++ has no computed goto",
+                    expr
+                );
+                writeln!(out, "{}{}", self.indent(), Theme::comment(todo))?;
+            }
+            ExprKind::ComputedJump { offset_expr } => {
+                let expr = self
+                    .renderer
+                    .format_expr_inline(offset_expr, &FormatContext::This);
+                writeln!(out, "{}goto {};", self.indent(), expr)?;
+            }
+            ExprKind::SwitchValue {
+                index,
+                cases,
+                default,
+                end_offset: _,
+            } => {
+                let index_expr = self
+                    .renderer
+                    .format_expr_inline(index, &FormatContext::This);
+                writeln!(out, "{}switch ({}) {{", self.indent(), index_expr)?;
+                self.add_indent();
+
+                let (cases, unhandled) = self.renderer.switch_cases(index, cases);
+                for (case_val, case) in cases {
+                    writeln!(out, "{}case {}:", self.indent(), case_val)?;
+                    self.add_indent();
+                    let result = self
+                        .renderer
+                        .format_expr_inline(&case.result, &FormatContext::This);
+                    if !result.is_empty() {
+                        writeln!(out, "{}{};", self.indent(), result)?;
+                    }
+                    writeln!(out, "{}break;", self.indent())?;
+                    self.drop_indent();
+                }
+
+                writeln!(out, "{}{}", self.indent(), default_label(&unhandled))?;
+                self.add_indent();
+                let default_result = self
+                    .renderer
+                    .format_expr_inline(default, &FormatContext::This);
+                if !default_result.is_empty() {
+                    writeln!(out, "{}{};", self.indent(), default_result)?;
+                }
+                writeln!(out, "{}break;", self.indent())?;
+                self.drop_indent();
+
+                self.drop_indent();
+                writeln!(out, "{}}}", self.indent())?;
+            }
+
+            // Delegates
+            ExprKind::BindDelegate {
+                func_name,
+                delegate_expr,
+                object_expr,
+            } => {
+                let delegate = self
+                    .renderer
+                    .format_expr_inline(delegate_expr, &FormatContext::This);
+                let object = self
+                    .renderer
+                    .format_expr_inline(object_expr, &FormatContext::This);
+                writeln!(
+                    out,
+                    "{}{}.BindDynamic({}, &{}::{});",
+                    self.indent(),
+                    delegate,
+                    object,
+                    object,
+                    func_name.as_str()
+                )?;
+            }
+            ExprKind::AddMulticastDelegate {
+                delegate_expr,
+                to_add_expr,
+            } => {
+                let delegate = self
+                    .renderer
+                    .format_expr_inline(delegate_expr, &FormatContext::This);
+                let to_add = self
+                    .renderer
+                    .format_expr_inline(to_add_expr, &FormatContext::This);
+                writeln!(out, "{}{}.AddDynamic({});", self.indent(), delegate, to_add)?;
+            }
+            ExprKind::RemoveMulticastDelegate {
+                delegate_expr,
+                to_remove_expr,
+            } => {
+                let delegate = self
+                    .renderer
+                    .format_expr_inline(delegate_expr, &FormatContext::This);
+                let to_remove = self
+                    .renderer
+                    .format_expr_inline(to_remove_expr, &FormatContext::This);
+                writeln!(
+                    out,
+                    "{}{}.RemoveDynamic({});",
+                    self.indent(),
+                    delegate,
+                    to_remove
+                )?;
+            }
+            ExprKind::ClearMulticastDelegate(delegate_expr) => {
+                let delegate = self
+                    .renderer
+                    .format_expr_inline(delegate_expr, &FormatContext::This);
+                writeln!(out, "{}{}.Clear();", self.indent(), delegate)?;
+            }
+            ExprKind::CallMulticastDelegate {
+                stack_node,
+                delegate_expr,
+                params,
+            } => {
+                let delegate = self
+                    .renderer
+                    .format_expr_inline(delegate_expr, &FormatContext::This);
+                let param_strs = self.renderer.format_call_params(stack_node, params);
+                writeln!(
+                    out,
+                    "{}{}.Broadcast({});",
+                    self.indent(),
+                    delegate,
+                    param_strs.join(", ")
+                )?;
+            }
+
+            // Debug/instrumentation
+            ExprKind::Assert {
+                line,
+                in_debug: _,
+                condition,
+            } => {
+                let cond = self
+                    .renderer
+                    .format_expr_inline(condition, &FormatContext::This);
+                writeln!(out, "{}check({}); // line {}", self.indent(), cond, line)?;
+            }
+            ExprKind::PushExecutionFlow { .. }
+            | ExprKind::PopExecutionFlow
+            | ExprKind::PopExecutionFlowIfNot { .. }
+                if self.renderer.options.valid_cpp =>
+            {
+                // The flow stack only exists in the VM; structured output
+                // turns it back into control flow
+                let todo = match &expr.kind {
+                    ExprKind::PushExecutionFlow { push_offset } => {
+                        format!(
+                            "push {} on the flow stack",
+                            self.renderer.format_label(*push_offset)
+                        )
+                    }
+                    ExprKind::PopExecutionFlowIfNot { condition } => format!(
+                        "if !({}), continue at the top of the flow stack",
+                        self.renderer
+                            .format_expr_inline(condition, &FormatContext::This)
+                    ),
+                    _ => "continue at the top of the flow stack".to_string(),
+                };
+                writeln!(
+                    out,
+                    "{}{}",
+                    self.indent(),
+                    Theme::comment(format!("// TODO: {}", todo))
+                )?;
+            }
+            ExprKind::PushExecutionFlow { push_offset } => {
+                writeln!(
+                    out,
+                    "{}PushExecutionFlow({});",
+                    self.indent(),
+                    self.renderer.format_label(*push_offset)
+                )?;
+            }
+            ExprKind::PopExecutionFlow => {
+                writeln!(out, "{}PopExecutionFlow;", self.indent())?;
+            }
+            ExprKind::PopExecutionFlowIfNot { condition } => {
+                let cond = self
+                    .renderer
+                    .format_expr_inline(condition, &FormatContext::This);
+                writeln!(out, "{}PopExecutionFlowIfNot({});", self.indent(), cond)?;
+            }
+            ExprKind::Breakpoint
+            | ExprKind::Tracepoint
+            | ExprKind::WireTracepoint
+            | ExprKind::InstrumentationEvent { .. }
+                if self.renderer.options.valid_cpp =>
+            {
+                writeln!(
+                    out,
+                    "{}{}",
+                    self.indent(),
+                    Theme::comment("// debugger hook")
+                )?;
+            }
+            ExprKind::Breakpoint => {
+                writeln!(out, "{} <<< BREAKPOINT >>>", self.indent())?;
+            }
+            ExprKind::Tracepoint | ExprKind::WireTracepoint => {
+                writeln!(out, "{} <<< TRACEPOINT >>>", self.indent())?;
+            }
+            ExprKind::InstrumentationEvent { event_type } => {
+                writeln!(
+                    out,
+                    "{} <<< INSTRUMENTATION EVENT {} >>>",
+                    self.indent(),
+                    event_type
+                )?;
+            }
+            ExprKind::EndOfScript => {
+                writeln!(out, "{}// End of script", self.indent())?;
+            }
+            ExprKind::DefaultParmValue { value, .. } => {
+                writeln!(
+                    out,
+                    "{}{}",
+                    self.indent(),
+                    Theme::comment("// Default for a parameter the caller leaves out:")
+                )?;
+                self.format_statement(out, value)?;
+            }
+
+            // Everything else - try to format as expression statement
+            _ => {
+                let expr_str = self.renderer.format_expr_inline(expr, &FormatContext::This);
+                if !expr_str.is_empty() {
+                    writeln!(out, "{}{};", self.indent(), expr_str)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A switch's `default:` label, noting the enum values no case handles
+fn default_label(unhandled: &[String]) -> String {
+    if unhandled.is_empty() {
+        "default:".to_string()
+    } else {
+        let note = format!("// unhandled: {}", unhandled.join(", "));
+        format!("default: {}", Theme::comment(note))
+    }
+}
+
+/// The value of an integer literal, if the expression is one
+fn integer_literal(expr: &Expr) -> Option<i64> {
+    match &expr.kind {
+        ExprKind::IntZero => Some(0),
+        ExprKind::IntOne => Some(1),
+        ExprKind::IntConst(v) => Some(i64::from(*v)),
+        ExprKind::Int64Const(v) => Some(*v),
+        ExprKind::ByteConst(v) | ExprKind::IntConstByte(v) => Some(i64::from(*v)),
+        _ => None,
+    }
+}
+
+/// Replace non-ASCII characters with C++ universal character names (`\u00E9`)
+fn escape_non_ascii(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c as u32 {
+            _ if c.is_ascii() => escaped.push(c),
+            code @ ..=0xFFFF => escaped.push_str(&format!("\\u{:04X}", code)),
+            code => escaped.push_str(&format!("\\U{:08X}", code)),
+        }
+    }
+    escaped
+}
+
+/// Render an enum value by name, or as OR'd flag names for bitmask enums
+///
+/// A bitmask's `_MAX` is a combination of its flags, and printed as them;
+/// any other enum's is printed by name.
+fn format_enum_value(e: &Enum, value: i64) -> Option<String> {
+    let qualify = |name: &str| Theme::type_name(qualified_enum_name(e, name)).to_string();
+    if let Some((name, _)) = enum_entries(e).find(|(_, v)| *v == value) {
+        return Some(qualify(name));
+    }
+    match enum_flags(e, value) {
+        Some(flags) => Some(
+            flags
+                .iter()
+                .map(|(name, _)| qualify(name))
+                .collect::<Vec<_>>()
+                .join(" | "),
+        ),
+        None => e
+            .names
+            .iter()
+            .find(|(name, v)| *v == value && name.ends_with("_MAX"))
+            .map(|(name, _)| qualify(name)),
+    }
+}
+
+/// The flags OR'd together to make `value`, if `e` is a bitmask
+///
+/// The dump carries no property metadata, so an enum counts as a bitmask when
+/// every entry other than zero and the trailing `_MAX` is a single bit, and
+/// its values don't just count up: one is at least 4, or `_MAX` isn't one
+/// past the last. Otherwise `0, 1, 2` would print its `_MAX` of 3 as flags.
+fn enum_flags(e: &Enum, value: i64) -> Option<Vec<&(String, i64)>> {
+    let entries: Vec<&(String, i64)> = enum_entries(e).collect();
+    let single_bits = entries
+        .iter()
+        .all(|(_, v)| *v == 0 || (*v > 0 && (*v as u64).is_power_of_two()));
+    let max = e.names.iter().find(|(name, _)| name.ends_with("_MAX"));
+    let counts_up = entries.iter().all(|(_, v)| *v < 4)
+        && max.is_some_and(|(_, max)| Some(*max - 1) == entries.last().map(|(_, v)| *v));
+    if !single_bits || counts_up || value <= 0 {
+        return None;
+    }
+
+    let flags: Vec<&(String, i64)> = entries
+        .into_iter()
+        .filter(|(_, v)| *v != 0 && value & v == *v)
+        .collect();
+    let covered = flags.iter().fold(0, |acc, (_, v)| acc | v);
+    (covered == value).then_some(flags)
+}
+
+/// An enum's entries, leaving out the `_MAX` one the compiler generates
+fn enum_entries(e: &Enum) -> impl Iterator<Item = &(String, i64)> {
+    e.names.iter().filter(|(name, _)| !name.ends_with("_MAX"))
+}
+
+/// An entry's name with its enum's type in front, as in `EState::Idle`
+fn qualified_enum_name(e: &Enum, name: &str) -> String {
+    if name.contains("::") {
+        name.to_string()
+    } else {
+        format!("{}::{}", e.cpp_type, name)
+    }
+}
+
+/// Whether [`format_enum_value`] renders a value as flags rather than by the
+/// name of one entry
+fn is_flag_combination(e: &Enum, value: i64) -> bool {
+    !enum_entries(e).any(|(_, v)| *v == value) && enum_flags(e, value).is_some()
+}
+
+/// Whether an expression can be written out more than once without changing
+/// what the code does: a variable or a literal
+fn is_repeatable(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::LocalVariable(_)
+            | ExprKind::InstanceVariable(_)
+            | ExprKind::DefaultVariable(_)
+            | ExprKind::LocalOutVariable(_)
+            | ExprKind::Self_
+    ) || is_integral_literal(expr)
+        || matches!(
+            expr.kind,
+            ExprKind::FloatConst(_)
+                | ExprKind::DoubleConst(_)
+                | ExprKind::StringConst(_)
+                | ExprKind::UnicodeStringConst(_)
+                | ExprKind::NameConst(_)
+                | ExprKind::True
+                | ExprKind::False
+                | ExprKind::NoObject
+        )
+}
+
+/// Whether an expression is a literal a C++ `case` label accepts
+fn is_integral_literal(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::IntConst(_)
+            | ExprKind::Int64Const(_)
+            | ExprKind::UInt64Const(_)
+            | ExprKind::IntZero
+            | ExprKind::IntOne
+            | ExprKind::ByteConst(_)
+            | ExprKind::IntConstByte(_)
+    )
+}
+
+/// Whether an expression is the no-value placeholder, e.g. what a function
+/// without a return value returns
+fn is_nothing(expr: &Expr) -> bool {
+    matches!(expr.kind, ExprKind::Nothing | ExprKind::NothingInt32)
+}
+
+/// Whether a call argument stands for an optional parameter the caller
+/// doesn't pass; `DeprecatedOp4A` was `EX_EmptyParmValue` in older engines
+fn is_omitted_arg(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::Nothing | ExprKind::NothingInt32 | ExprKind::DeprecatedOp4A
+    )
+}
+
+/// Whether a literal expression holds the zero/empty value for its type
+fn is_default_literal(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::IntZero
+        | ExprKind::False
+        | ExprKind::NoObject
+        | ExprKind::NoInterface
+        | ExprKind::TextConst(TextLiteral::Empty) => true,
+        ExprKind::IntConst(v) => *v == 0,
+        ExprKind::Int64Const(v) => *v == 0,
+        ExprKind::UInt64Const(v) => *v == 0,
+        ExprKind::ByteConst(v) | ExprKind::IntConstByte(v) => *v == 0,
+        ExprKind::FloatConst(v) => *v == 0.0,
+        ExprKind::DoubleConst(v) => v.get() == 0.0,
+        ExprKind::StringConst(s) | ExprKind::UnicodeStringConst(s) => s.is_empty(),
+        ExprKind::NameConst(name) => name.matches("None"),
+        ExprKind::VectorConst { x, y, z } => *x == 0.0 && *y == 0.0 && *z == 0.0,
+        ExprKind::Vector3fConst { x, y, z } => *x == 0.0 && *y == 0.0 && *z == 0.0,
+        ExprKind::RotationConst { pitch, yaw, roll } => {
+            *pitch == 0.0 && *yaw == 0.0 && *roll == 0.0
+        }
+        ExprKind::ArrayConst { elements, .. }
+        | ExprKind::SetConst { elements, .. }
+        | ExprKind::MapConst { elements, .. } => elements.is_empty(),
+        ExprKind::StructConst { elements, .. } => elements.iter().all(is_default_literal),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::opcodes::EExprToken;
+    use crate::bytecode::refs::ObjectRef;
+    use crate::bytecode::testing::{
+        BytecodeBuilder, at, call, call_with, jmap_of, local, object_json, parse, property_json,
+    };
+    use crate::bytecode::types::Name;
+
+    /// A dump with the enum `EState` and a function `Move` with a byte
+    /// `State` of it at address 3 and an int `Count` at address 4
+    fn state_jmap() -> jmap::Jmap {
+        serde_json::from_value(serde_json::json!({
+            "names": {},
+            "objects": {
+                "/Game/EState.EState": {
+                    "type": "Enum", "address": 1, "outer": null,
+                    "class": "/Script/CoreUObject.Enum", "cpp_type": "EState",
+                    "names": [["EState::Idle", 0], ["EState::Walk", 1], ["EState::Run", 2],
+                        ["EState::Jump", 3], ["EState::EState_MAX", 4]],
+                },
+                "/Game/BP.BP_C:Move": {
+                    "type": "Function", "address": 2, "outer": null, "class": "Function",
+                    "super_struct": null, "properties_size": 0, "script": [],
+                    "function_flags": 0,
+                    "properties": [
+                        {"name": "State", "address": 3, "offset": 0, "array_dim": 1,
+                            "size": 1, "flags": 0, "type": "Byte",
+                            "enum": "/Game/EState.EState"},
+                        {"name": "Count", "address": 4, "offset": 0, "array_dim": 1,
+                            "size": 4, "flags": 0, "type": "Int"},
+                    ],
+                },
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_switch_cases_on_an_enum() {
+        let jmap = state_jmap();
+        let index = AddressIndex::new(&jmap);
+        let renderer = ExprRenderer::new(&index);
+        let cases = |values: &[i32]| -> Vec<SwitchCase> {
+            values
+                .iter()
+                .map(|&value| SwitchCase {
+                    case_offset: BytecodeOffset::new(0),
+                    case_value: at(0, ExprKind::IntConst(value)),
+                    next_offset: BytecodeOffset::new(0),
+                    result: at(0, ExprKind::Nothing),
+                })
+                .collect()
+        };
+        let labels = |labeled: &[(String, &SwitchCase)]| -> Vec<String> {
+            labeled.iter().map(|(label, _)| label.clone()).collect()
+        };
+
+        // Distinct values go in the enum's order, with unnamed ones last
+        let distinct = cases(&[2, 7, 0]);
+        let (labeled, unhandled) = renderer.switch_cases(&local(0, 3), &distinct);
+        assert_eq!(
+            labels(&labeled),
+            ["EState::Idle", "EState::Run", "(EState)7"]
+        );
+        assert_eq!(unhandled, ["EState::Walk", "EState::Jump"]);
+        assert_eq!(
+            default_label(&unhandled),
+            "default: // unhandled: EState::Walk, EState::Jump"
+        );
+
+        // Only the first case of a shared value runs, so the order is kept
+        let shared = cases(&[2, 0, 2]);
+        let (labeled, _) = renderer.switch_cases(&local(0, 3), &shared);
+        assert_eq!(
+            labels(&labeled),
+            ["EState::Run", "EState::Idle", "EState::Run"]
+        );
+
+        // Values switched on without an enum are printed as they are
+        let (labeled, unhandled) = renderer.switch_cases(&local(0, 4), &distinct);
+        assert_eq!(labels(&labeled), ["2", "7", "0"]);
+        assert!(unhandled.is_empty());
+        assert_eq!(default_label(&unhandled), "default:");
+    }
+
+    #[test]
+    fn test_lifted_switch_values() {
+        let jmap = state_jmap();
+        let index = AddressIndex::new(&jmap);
+        let renderer = ExprRenderer::new(&index);
+        let lifted = |index: Expr, values: &[ExprKind]| {
+            let cases: Vec<SwitchCase> = values
+                .iter()
+                .zip(10..)
+                .map(|(value, result)| SwitchCase {
+                    case_offset: BytecodeOffset::new(0),
+                    case_value: at(0, value.clone()),
+                    next_offset: BytecodeOffset::new(0),
+                    result: at(0, ExprKind::IntConst(result)),
+                })
+                .collect();
+            let default = at(0, ExprKind::IntConst(0));
+            plain(&renderer.format_switch_value_lifted(
+                &index,
+                &cases,
+                &default,
+                &FormatContext::This,
+            ))
+        };
+        let ints = [ExprKind::IntConst(1), ExprKind::IntConst(2)];
+        let names = [
+            ExprKind::NameConst(Name::new("Walk")),
+            ExprKind::NameConst(Name::new("Run")),
+        ];
+        // Move's State, read through a context, which can't be repeated
+        let state = || {
+            at(
+                0,
+                ExprKind::Context {
+                    object: Box::new(at(0, ExprKind::Self_)),
+                    field: PropertyRef::new(Address::new(3)),
+                    context: Box::new(local(0, 3)),
+                    skip_offset: 0,
+                    fail_silent: false,
+                },
+            )
+        };
+
+        // A variable is repeated in a chain of ternaries
+        assert_eq!(
+            lifted(local(0, 4), &ints),
+            "(Count == 1 ? 10 : Count == 2 ? 11 : 0)"
+        );
+        // Anything else is evaluated once, in a switch when it can be
+        assert_eq!(
+            lifted(call(0, "GetCount"), &ints),
+            "[&] { switch (GetCount()) { case 1: return 10; case 2: return 11; \
+             default: return 0; } }()"
+        );
+        assert_eq!(
+            lifted(call(0, "GetName"), &names),
+            "[&] { const auto SwitchIndex = GetName(); return SwitchIndex == FName(\"Walk\") \
+             ? 10 : SwitchIndex == FName(\"Run\") ? 11 : 0; }()"
+        );
+        // A byte labeled with its enum's names is cast to the enum
+        assert_eq!(
+            lifted(state(), &ints),
+            "[&] { switch ((EState)State) { case EState::Walk: return 10; \
+             case EState::Run: return 11; default: return 0; } }()"
+        );
+        assert_eq!(
+            lifted(local(0, 3), &ints),
+            "((EState)State == EState::Walk ? 10 : (EState)State == EState::Run ? 11 : 0)"
+        );
+    }
+
+    #[test]
+    fn test_enum_values_and_flags() {
+        let enum_of = |names: &[(&str, i64)]| -> Enum {
+            serde_json::from_value(serde_json::json!({
+                "address": 1, "outer": null, "class": "/Script/CoreUObject.Enum",
+                "cpp_type": "EMode", "names": names,
+            }))
+            .unwrap()
+        };
+        let render = |e: &Enum, value: i64| format_enum_value(e, value).map(|text| plain(&text));
+
+        // Values that count up are named, `_MAX` included, and never OR'd
+        let counting = enum_of(&[("A", 0), ("B", 1), ("C", 2), ("EMode_MAX", 3)]);
+        assert_eq!(render(&counting, 2).as_deref(), Some("EMode::C"));
+        assert_eq!(render(&counting, 3).as_deref(), Some("EMode::EMode_MAX"));
+        assert_eq!(render(&counting, 5), None);
+        assert!(!is_flag_combination(&counting, 3));
+
+        // Single bits reaching 4 are flags
+        let flags = enum_of(&[("None", 0), ("A", 1), ("B", 2), ("C", 4), ("EMode_MAX", 5)]);
+        assert_eq!(render(&flags, 6).as_deref(), Some("EMode::B | EMode::C"));
+        assert_eq!(render(&flags, 5).as_deref(), Some("EMode::A | EMode::C"));
+        assert_eq!(render(&flags, 8), None);
+        assert!(is_flag_combination(&flags, 3));
+        assert!(is_flag_combination(&flags, 5));
+        assert!(!is_flag_combination(&flags, 4));
+
+        // As are smaller ones when `_MAX` isn't one past the last
+        let small_flags = enum_of(&[("A", 1), ("B", 2), ("EMode_MAX", 4)]);
+        assert_eq!(
+            render(&small_flags, 3).as_deref(),
+            Some("EMode::A | EMode::B")
+        );
+    }
+
+    #[test]
+    fn test_bit_field_const() {
+        let mut builder = BytecodeBuilder::default();
+        builder.op(EExprToken::BitFieldConst).address(0x40).u8(1);
+        builder.op(EExprToken::BitFieldConst).address(0x40).u8(0);
+        let expressions = parse(&builder.build());
+        let jmap = jmap_of(serde_json::json!({}));
+        let index = AddressIndex::new(&jmap);
+        let renderer = ExprRenderer::new(&index);
+        let rendered: Vec<String> = expressions[..2]
+            .iter()
+            .map(|expr| {
+                assert!(matches!(
+                    expr.kind,
+                    ExprKind::BitFieldConst { property, .. } if property.address == Address::new(0x40)
+                ));
+                plain(&renderer.format_expr_inline(expr, &FormatContext::This))
+            })
+            .collect();
+        assert_eq!(rendered, ["true", "false"]);
+    }
+
+    #[test]
+    fn test_omitted_call_arguments() {
+        let param = |name: &str, address: u64| {
+            let mut param = property_json(name, address, 0, serde_json::json!({"type": "Int"}));
+            param["flags"] = CPF_PARM.into();
+            param
+        };
+        let mut fire = object_json("Function", 10, None);
+        fire["properties"] = serde_json::json!([
+            param("Count", 11),
+            param("Delay", 12),
+            param("Scale", 13),
+            param("Target", 14),
+        ]);
+        let jmap = jmap_of(serde_json::json!({ "/Game/BP.BP_C:Fire": fire }));
+        let index = AddressIndex::new(&jmap);
+        let renderer = ExprRenderer::new(&index);
+        let render = |renderer: &ExprRenderer, callee: u64, params: &[ExprKind]| {
+            let call = at(
+                0,
+                ExprKind::FinalFunction {
+                    func: FunctionRef::ByAddress(Address::new(callee)),
+                    params: params.iter().map(|kind| at(0, kind.clone())).collect(),
+                },
+            );
+            plain(&renderer.format_expr_inline(&call, &FormatContext::This))
+        };
+        let unset = ExprKind::Nothing;
+        let int = ExprKind::IntConst;
+
+        // A known callee drops trailing unset arguments and marks the others
+        let all = [int(1), unset.clone(), int(3), unset.clone()];
+        assert_eq!(
+            render(&renderer, 10, &all),
+            "/Game/BP.BP_C:Fire(1, /*unset*/, 3)"
+        );
+
+        // An unknown callee may not default them, so none are dropped
+        let unknown = render(&renderer, 99, &[int(1), unset.clone()]);
+        assert!(unknown.ends_with("(1, /*unset*/)"), "{}", unknown);
+
+        // Nor are they when the count doesn't match the callee's
+        let short = [int(1), unset.clone(), unset.clone()];
+        assert_eq!(
+            render(&renderer, 10, &short),
+            "/Game/BP.BP_C:Fire(1, /*unset*/, /*unset*/)"
+        );
+
+        // Valid C++ value-initializes the marked ones
+        let valid_cpp = ExprRenderer::new(&index).with_options(FormattingOptions {
+            valid_cpp: true,
+            ..FormattingOptions::default()
+        });
+        assert_eq!(render(&valid_cpp, 10, &all), "Fire(1, {} /*unset*/, 3)");
+    }
+
+    #[test]
+    fn test_valid_cpp_output() {
+        let jmap = state_jmap();
+        let index = AddressIndex::new(&jmap);
+        let renderer = ExprRenderer::new(&index).with_options(FormattingOptions {
+            valid_cpp: true,
+            ..FormattingOptions::default()
+        });
+        let jmap::ObjectType::Function(function) = &jmap.objects["/Game/BP.BP_C:Move"] else {
+            unreachable!("Move is a function");
+        };
+        let enum_object = || at(0, ExprKind::ObjectConst(ObjectRef::new(Address::new(1))));
+        let expressions = [
+            at(
+                0,
+                ExprKind::Jump {
+                    target: BytecodeOffset::new(0x20),
+                },
+            ),
+            // Nothing jumps to this one, so it gets no label
+            call_with(
+                0x10,
+                "Print",
+                vec![enum_object(), at(0x10, ExprKind::Nothing)],
+            ),
+            at(
+                0x20,
+                ExprKind::Context {
+                    object: Box::new(enum_object()),
+                    field: PropertyRef::new(Address::new(4)),
+                    context: Box::new(at(
+                        0x20,
+                        ExprKind::VirtualFunction {
+                            func: FunctionRef::ByName(Name::new("Reset")),
+                            params: Vec::new(),
+                        },
+                    )),
+                    skip_offset: 0,
+                    fail_silent: true,
+                },
+            ),
+            at(0x30, ExprKind::EndParmValue),
+        ];
+        let referenced = HashSet::from([BytecodeOffset::new(0x20)]);
+        let mut writer = StatementWriter::new(&renderer, referenced);
+        let mut out = String::new();
+        writer.declare_locals(&mut out, function).unwrap();
+        writer.format(&mut out, &expressions).unwrap();
+        let text = plain(&out);
+        let lines: Vec<&str> = text.lines().collect();
+
+        // Locals are declared, gotos go to labels that exist, and what C++
+        // has no form for is left as a TODO that still compiles
+        assert_eq!(
+            lines,
+            [
+                "EState State;",
+                "int32 Count;",
+                "",
+                "    goto Label_0x20;",
+                "    Print(FindObject<UObject>(nullptr, TEXT(\"/Game/EState.EState\")), {} /*unset*/);",
+                "Label_0x20:",
+                "    FindObject<UObject>(nullptr, TEXT(\"/Game/EState.EState\")) \
+                 /* TODO: skipped if null */->Reset();",
+                "    {} /* TODO: no C++ for EndParmValue */;",
+            ]
+        );
+    }
+
+    /// `text` with its colors taken out, whether or not colors are on
+    fn plain(text: &str) -> String {
+        let mut plain = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('\x1b') {
+            plain.push_str(&rest[..start]);
+            rest = &rest[start..];
+            rest = rest.find('m').map_or("", |end| &rest[end + 1..]);
         }
+        plain.push_str(rest);
+        plain
     }
 }
@@ -0,0 +1,154 @@
+/// Classic hex dump: offset, sixteen bytes in hex, then the printable ones
+///
+/// For `-o hexdump`, [`instruction_hexdump`] also shows where each decoded
+/// instruction's bytes start and end.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use colored::ColoredString;
+
+use super::theme::Theme;
+use crate::bytecode::types::OffsetRange;
+
+const BYTES_PER_LINE: usize = 16;
+
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7E => b as char,
+                _ => '.',
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "{:08x}  {:<width$}  |{}|",
+            line * BYTES_PER_LINE,
+            hex.join(" "),
+            ascii,
+            width = BYTES_PER_LINE * 3 - 1
+        );
+    }
+    out
+}
+
+/// Hex dump of a script split into the instructions decoded from it
+///
+/// `instructions` are the top-level statements' byte ranges, each with a
+/// name. A `|` goes before the first byte of each, neighbouring ones are
+/// colored apart, and each line ends with the instructions starting on it.
+/// Bytes no instruction was decoded from are dimmed. Unless `themed`, the
+/// dump is plain text whatever the color settings.
+pub fn instruction_hexdump(
+    bytes: &[u8],
+    instructions: &[(OffsetRange, String)],
+    themed: bool,
+) -> String {
+    // Index of the instruction each byte belongs to
+    let mut owners: Vec<Option<usize>> = vec![None; bytes.len()];
+    let mut starts: BTreeMap<usize, &str> = BTreeMap::new();
+    for (index, (range, name)) in instructions.iter().enumerate() {
+        if range.is_empty() {
+            continue;
+        }
+        for owner in owners.iter_mut().take(range.end.0).skip(range.start.0) {
+            *owner = Some(index);
+        }
+        starts.insert(range.start.0, name);
+    }
+    let paint = |offset: usize, text: String| -> ColoredString {
+        if !themed {
+            return text.into();
+        }
+        match owners[offset] {
+            None => Theme::null_value(text),
+            Some(index) if index % 2 == 1 => Theme::alternate(text),
+            Some(_) => text.into(),
+        }
+    };
+
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let first = line * BYTES_PER_LINE;
+        let mut hex = String::new();
+        let mut ascii = String::new();
+        for (i, &byte) in chunk.iter().enumerate() {
+            let offset = first + i;
+            let separator = if starts.contains_key(&offset) {
+                '|'
+            } else {
+                ' '
+            };
+            let _ = write!(
+                hex,
+                "{}{}",
+                separator,
+                paint(offset, format!("{:02x}", byte))
+            );
+            let printable = match byte {
+                0x20..=0x7E => byte as char,
+                _ => '.',
+            };
+            let _ = write!(ascii, "{}", paint(offset, printable.to_string()));
+        }
+        let padding = " ".repeat((BYTES_PER_LINE - chunk.len()) * 3);
+        let notes: Vec<String> = starts
+            .range(first..first + chunk.len())
+            .map(|(offset, name)| format!("0x{:X} {}", offset, name))
+            .collect();
+        let notes = match (notes.is_empty(), themed) {
+            (true, _) => String::new(),
+            (false, true) => format!("  {}", Theme::comment(notes.join(", "))),
+            (false, false) => format!("  {}", notes.join(", ")),
+        };
+        let _ = writeln!(
+            out,
+            "{:08x} {}{}  |{}|{}",
+            first, hex, padding, ascii, notes
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        let dump = hexdump(b"\x04\x0bHello, world!\x53\x00");
+        assert_eq!(
+            dump,
+            "00000000  04 0b 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 53  |..Hello, world!S|\n\
+             00000010  00                                               |.|\n"
+        );
+    }
+
+    #[test]
+    fn test_instruction_boundaries() {
+        let range = |start: usize, end: usize| {
+            OffsetRange::new(
+                crate::bytecode::types::BytecodeOffset(start),
+                crate::bytecode::types::BytecodeOffset(end),
+            )
+        };
+        let bytes: Vec<u8> = (0..18).collect();
+        let dump = instruction_hexdump(
+            &bytes,
+            &[
+                (range(0, 3), "Let".to_string()),
+                (range(3, 17), "CallMath".to_string()),
+            ],
+            false,
+        );
+        assert_eq!(
+            dump,
+            "00000000 |00 01 02|03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f  \
+             |................|  0x0 Let, 0x3 CallMath\n\
+             00000010  10 11                                            |..|\n"
+        );
+    }
+}
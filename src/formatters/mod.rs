@@ -1,8 +1,11 @@
 pub mod asm;
+pub mod confidence;
 pub mod cpp;
+pub mod hexdump;
+pub mod renames;
 pub mod theme;
 
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 pub struct FormattingOptions {
     /// Prefix blocks with block ID comments (e.g., `// block: BlockId(0)`)
     pub show_block_ids: bool,
@@ -10,4 +13,60 @@ pub struct FormattingOptions {
     pub show_bytecode_offsets: bool,
     /// Show terminator expressions as comments at the end of basic blocks
     pub show_terminator_exprs: bool,
+    /// Render intrinsics as operators, engine structs as constructors and
+    /// enum arguments by name instead of as the plain calls and literals they are
+    pub sugar: bool,
+    /// Escape non-ASCII characters in string and name literals
+    pub ascii_only: bool,
+    /// Print symbolic names in place of raw object addresses
+    pub normalize_addresses: bool,
+    /// Prefix statements with their estimated execution cost and loops with
+    /// the cost of one iteration
+    pub show_costs: bool,
+    /// Simplify operator expressions, e.g. `x + 0` to `x` and `!(a == b)` to
+    /// `a != b`
+    pub simplify: bool,
+    /// Leave out trailing arguments a call doesn't set, rather than marking
+    /// each `/*unset*/`
+    pub elide_omitted_args: bool,
+    /// Write only valid C++: no invented syntax, gotos to real labels, and
+    /// TODO comments for what C++ has no form for
+    pub valid_cpp: bool,
+    /// Name each cast after the opcode it comes from, e.g.
+    /// `CrossInterfaceCast<T>(x)`, rather than the C++ that does the same
+    pub faithful_casts: bool,
+    /// After each function, print why each line tagged with a `/*?N*/`
+    /// marker may not say exactly what the bytecode does
+    pub explain: bool,
+    /// Follow member accesses with the property's byte offset in its class
+    /// or struct and its size, e.g. `this->Health /* +0x2A0, 4 bytes */`
+    pub show_prop_offsets: bool,
+    /// Head loops that count a variable up to a limit with the range it
+    /// runs over, e.g. `// iterates 0..Count (Count = Array.Num())`
+    pub show_loop_bounds: bool,
+    /// Follow each function with the locals it declares but never reads or
+    /// writes, e.g. `// Unused locals: Temp_int_Variable`
+    pub show_unused_locals: bool,
+}
+
+impl Default for FormattingOptions {
+    fn default() -> Self {
+        Self {
+            show_block_ids: false,
+            show_bytecode_offsets: false,
+            show_terminator_exprs: false,
+            sugar: true,
+            ascii_only: false,
+            normalize_addresses: false,
+            show_costs: false,
+            simplify: true,
+            elide_omitted_args: true,
+            valid_cpp: false,
+            faithful_casts: false,
+            explain: false,
+            show_prop_offsets: false,
+            show_loop_bounds: false,
+            show_unused_locals: false,
+        }
+    }
 }
@@ -0,0 +1,88 @@
+/// Readable names to print in place of generated ones
+///
+/// Blueprint compilation names temporaries and node outputs after the node
+/// they came from, such as `CallFunc_GetActorLocation_ReturnValue`. A rename
+/// map lists names like that with the name to print instead, one pair per
+/// line separated by a comma:
+///
+/// ```text
+/// # name in the dump, name to print
+/// CallFunc_GetActorLocation_ReturnValue,Location
+/// K2Node_CustomEvent_DamageAmount,DamageAmount
+/// ```
+///
+/// Blank lines and lines starting with `#` are skipped. Names match ignoring
+/// case, as FNames do.
+use std::collections::HashMap;
+
+use crate::bytecode::types::Name;
+
+#[derive(Debug, Default, Clone)]
+pub struct RenameMap {
+    names: HashMap<Name, String>,
+}
+
+impl RenameMap {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, renamed) = line
+                .split_once(',')
+                .map(|(name, renamed)| (name.trim(), renamed.trim()))
+                .filter(|(name, renamed)| !name.is_empty() && !renamed.is_empty())
+                .ok_or_else(|| format!("line {}: expected `name,new name`", index + 1))?;
+            map.names.insert(Name::new(name), renamed.to_string());
+        }
+        Ok(map)
+    }
+
+    /// Add the names of another map, replacing any this one renames too
+    pub fn extend(&mut self, other: RenameMap) {
+        self.names.extend(other.names);
+    }
+
+    /// The name to print for `name`
+    pub fn rename<'n>(&'n self, name: &'n str) -> &'n str {
+        if self.names.is_empty() {
+            return name;
+        }
+        self.names
+            .get(&Name::new(name))
+            .map_or(name, String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_map() {
+        let mut map = RenameMap::parse(
+            "# generated, readable\n\
+             \n\
+             CallFunc_GetActorLocation_ReturnValue, Location\n\
+             Temp_int_Variable,Index\n",
+        )
+        .unwrap();
+        assert_eq!(
+            map.rename("callfunc_getactorlocation_returnvalue"),
+            "Location"
+        );
+        assert_eq!(map.rename("Health"), "Health");
+
+        map.extend(RenameMap::parse("Temp_int_Variable,I").unwrap());
+        assert_eq!(map.rename("Temp_int_Variable"), "I");
+
+        assert!(RenameMap::parse("Temp_int_Variable").is_err());
+        assert!(RenameMap::parse("Temp_int_Variable,").is_err());
+    }
+}
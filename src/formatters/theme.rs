@@ -2,7 +2,80 @@
 ///
 /// This module provides a consistent color scheme across all formatters,
 /// making it easy to maintain and customize the visual appearance of output.
+///
+/// For `-o tokens`, themed text is wrapped in markers naming its role instead
+/// of colored, and [`tokens`] splits printed lines back into roles and text.
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use colored::*;
+use serde::Serialize;
+
+/// Whether themed text is marked with its role rather than colored
+static MARK_ROLES: AtomicBool = AtomicBool::new(false);
+
+// Control characters never printed otherwise: literals escape them
+const ROLE_START: char = '\u{1}';
+const ROLE_TEXT: char = '\u{2}';
+const ROLE_END: char = '\u{3}';
+
+/// Mark themed text with its role from now on, for [`tokens`] to read back
+pub fn mark_roles() {
+    MARK_ROLES.store(true, Ordering::Relaxed);
+}
+
+fn styled(
+    role: &str,
+    text: impl std::fmt::Display,
+    color: impl FnOnce(String) -> ColoredString,
+) -> ColoredString {
+    if MARK_ROLES.load(Ordering::Relaxed) {
+        return marked(role, text).normal();
+    }
+    color(text.to_string())
+}
+
+fn marked(role: &str, text: impl std::fmt::Display) -> String {
+    format!("{}{}{}{}{}", ROLE_START, role, ROLE_TEXT, text, ROLE_END)
+}
+
+/// A run of printed text and the theme role it was printed with, named
+/// after the [`Theme`] method, or `plain` for text printed without one
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Token {
+    pub text: String,
+    pub role: String,
+}
+
+/// Split a line printed with [`mark_roles`] on into tokens; text themed
+/// inside other themed text takes the innermost role
+pub fn tokens(line: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut roles: Vec<&str> = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        let split = rest.find([ROLE_START, ROLE_END]).unwrap_or(rest.len());
+        if split > 0 {
+            let role = roles.last().copied().unwrap_or("plain");
+            match tokens.last_mut() {
+                Some(last) if last.role == role => last.text.push_str(&rest[..split]),
+                _ => tokens.push(Token {
+                    text: rest[..split].to_string(),
+                    role: role.to_string(),
+                }),
+            }
+            rest = &rest[split..];
+        } else if let Some(marked) = rest.strip_prefix(ROLE_START) {
+            let (role, text) = marked.split_once(ROLE_TEXT).unwrap_or(("plain", marked));
+            roles.push(role);
+            rest = text;
+        } else {
+            roles.pop();
+            rest = &rest[ROLE_END.len_utf8()..];
+        }
+    }
+    tokens
+}
 
 /// Semantic roles for syntax highlighting
 pub struct Theme;
@@ -12,84 +85,94 @@ impl Theme {
 
     /// Labels for jump targets and control flow markers
     pub fn label(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).red().bold()
+        styled("label", text, |text| text.red().bold())
     }
 
     // === Identifiers ===
 
     /// Variables, properties, and field names
     pub fn variable(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).bright_yellow()
+        styled("variable", text, |text| text.bright_yellow())
     }
 
     /// Function and method names
     pub fn function(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).magenta().bold()
+        styled("function", text, |text| text.magenta().bold())
     }
 
     /// Type names (classes, structs, interfaces)
     pub fn type_name(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).bright_cyan()
+        styled("type_name", text, |text| text.bright_cyan())
     }
 
     /// Object references and special identifiers (like 'this')
     pub fn object_ref(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).cyan()
+        styled("object_ref", text, |text| text.cyan())
     }
 
     // === Literals ===
 
     /// Numeric literals (integers, floats)
     pub fn numeric(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).yellow()
+        styled("numeric", text, |text| text.yellow())
     }
 
     /// Numeric literals with emphasis
     pub fn numeric_bold(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).yellow().bold()
+        styled("numeric_bold", text, |text| text.yellow().bold())
     }
 
     /// String literals
     pub fn string(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).green().bold()
+        styled("string", text, |text| text.green().bold())
     }
 
     /// Boolean literals and keywords
     pub fn keyword(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).green()
+        styled("keyword", text, |text| text.green())
     }
 
     // === Special values ===
 
     /// Null/none/nothing values
     pub fn null_value(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).dimmed()
+        styled("null_value", text, |text| text.dimmed())
     }
 
     // === Assembly-specific ===
 
     /// Opcode identifiers (assembly format only)
     pub fn opcode(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).cyan().bold()
+        styled("opcode", text, |text| text.cyan().bold())
     }
 
     /// Tag labels (assembly format only)
     pub fn tag(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).bright_black().bold()
+        styled("tag", text, |text| text.bright_black().bold())
+    }
+
+    /// Every other instruction's bytes in hex dumps, to tell neighbours apart
+    pub fn alternate(text: impl std::fmt::Display) -> ColoredString {
+        styled("alternate", text, |text| text.blue())
     }
 
     // === Comments and metadata ===
 
     /// Comments and secondary information
     pub fn comment(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).bright_black()
+        styled("comment", text, |text| text.bright_black())
+    }
+
+    /// Annotations on code that ran, in hit-count and trace output
+    pub fn covered(text: impl std::fmt::Display) -> ColoredString {
+        styled("covered", text, |text| text.bright_green())
     }
 
     // === Offsets and addresses ===
 
     /// Memory offsets and addresses
     pub fn offset(text: impl std::fmt::Display) -> ColoredString {
-        format!("{}", text).yellow().bold()
+        styled("offset", text, |text| text.yellow().bold())
     }
 }
 
@@ -97,7 +180,33 @@ impl Theme {
 
 /// Format a quoted string literal
 pub fn quoted_string(text: &str) -> ColoredString {
-    Theme::string(format!("\"{}\"", text))
+    Theme::string(format!("\"{}\"", escape_string(text)))
+}
+
+/// Escape quotes, backslashes and control characters for a C string literal
+///
+/// Control characters without a short escape use three-digit octal, which
+/// unlike `\x` can't run on into a following digit.
+pub fn escape_string(text: &str) -> Cow<'_, str> {
+    if !text
+        .chars()
+        .any(|c| c == '"' || c == '\\' || c.is_ascii_control())
+    {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
 }
 
 /// Format a type with angle brackets (e.g., TArray<int>)
@@ -138,6 +247,40 @@ mod tests {
         let _ = Theme::string("hello");
     }
 
+    #[test]
+    fn test_escape_string() {
+        assert_eq!(escape_string("plain"), "plain");
+        assert_eq!(
+            escape_string("say \"hi\"\\n\n\t\u{1}7"),
+            "say \\\"hi\\\"\\\\n\\n\\t\\0017"
+        );
+        assert_eq!(escape_string("caf\u{e9}"), "caf\u{e9}");
+    }
+
+    #[test]
+    fn test_tokens_take_the_innermost_role() {
+        let line = format!(
+            "{} = {};",
+            marked("variable", "Health"),
+            marked("comment", format!("/* {} */", marked("numeric", 5)))
+        );
+        let token = |text: &str, role: &str| Token {
+            text: text.to_string(),
+            role: role.to_string(),
+        };
+        assert_eq!(
+            tokens(&line),
+            vec![
+                token("Health", "variable"),
+                token(" = ", "plain"),
+                token("/* ", "comment"),
+                token("5", "numeric"),
+                token(" */", "comment"),
+                token(";", "plain"),
+            ]
+        );
+    }
+
     #[test]
     fn test_helper_functions() {
         let _ = quoted_string("hello world");
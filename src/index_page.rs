@@ -0,0 +1,197 @@
+//! Landing page for browsing an exported dump
+//!
+//! `export` writes `index.html` next to its CSV files. It lists every class
+//! with its functions, their script sizes, whether they structured, and
+//! which functions have byte-for-byte identical scripts, so a reader can
+//! skip copies of code they've already looked at. Analysts' notes on a
+//! function are shown beside it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::FunctionStats;
+
+/// Functions whose scripts are identical, numbered from 1 in name order
+fn duplicate_groups<'a>(functions: &'a [(FunctionStats, &[u8])]) -> Vec<Vec<&'a str>> {
+    let mut by_script: HashMap<&[u8], Vec<&str>> = HashMap::new();
+    for (stats, script) in functions {
+        by_script
+            .entry(script)
+            .or_default()
+            .push(stats.name.as_str());
+    }
+    let mut groups: Vec<Vec<&str>> = by_script
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .map(|mut names| {
+            names.sort_unstable();
+            names
+        })
+        .collect();
+    groups.sort_unstable();
+    groups
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The index page for `functions`, each with its script, and `notes` on them
+/// by function path
+pub fn render(
+    title: &str,
+    functions: &[(FunctionStats, &[u8])],
+    notes: &HashMap<&str, &str>,
+) -> String {
+    let groups = duplicate_groups(functions);
+    let group_of: HashMap<&str, usize> = groups
+        .iter()
+        .enumerate()
+        .flat_map(|(index, names)| names.iter().map(move |&name| (name, index + 1)))
+        .collect();
+
+    let mut classes: BTreeMap<&str, Vec<&FunctionStats>> = BTreeMap::new();
+    for (stats, _) in functions {
+        let class = stats.name.rsplit_once(':').map_or("", |(class, _)| class);
+        classes.entry(class).or_default().push(stats);
+    }
+    let structured = functions
+        .iter()
+        .filter(|(stats, _)| stats.structure_succeeded)
+        .count();
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n<style>\n\
+         body {{ font-family: sans-serif; }}\n\
+         table {{ border-collapse: collapse; }}\n\
+         td, th {{ padding: 2px 8px; text-align: left; }}\n\
+         td.size {{ text-align: right; }}\n\
+         .failed {{ color: #b00; }}\n\
+         td.note {{ white-space: pre-wrap; }}\n\
+         </style>\n</head>\n<body>\n<h1>{title}</h1>\n\
+         <p>{} classes, {} functions, {} structured, {} duplicate groups</p>\n",
+        classes.len(),
+        functions.len(),
+        structured,
+        groups.len(),
+        title = escape(title),
+    );
+
+    html.push_str("<h2>Classes</h2>\n<ul>\n");
+    for (class, members) in &classes {
+        let _ = writeln!(
+            html,
+            "<li><a href=\"#{0}\">{0}</a> ({1})</li>",
+            escape(class),
+            members.len()
+        );
+    }
+    html.push_str("</ul>\n");
+
+    for (class, members) in &classes {
+        let _ = write!(
+            html,
+            "<h2 id=\"{0}\">{0}</h2>\n<table>\n\
+             <tr><th>Function</th><th>Size</th><th>Blocks</th><th>Loops</th>\
+             <th>Structured</th><th>Duplicates</th><th>Notes</th></tr>\n",
+            escape(class)
+        );
+        for stats in members {
+            let short_name = stats
+                .name
+                .rsplit_once(':')
+                .map_or(stats.name.as_str(), |(_, n)| n);
+            let status = if stats.structure_succeeded {
+                "yes".to_string()
+            } else {
+                format!(
+                    "<span class=\"failed\">{}</span>",
+                    escape(&stats.structure_error)
+                )
+            };
+            let duplicates = group_of
+                .get(stats.name.as_str())
+                .map(|group| format!("<a href=\"#duplicates-{0}\">group {0}</a>", group))
+                .unwrap_or_default();
+            let _ = writeln!(
+                html,
+                "<tr id=\"{}\"><td>{}</td><td class=\"size\">{}</td><td>{}</td><td>{}</td>\
+                 <td>{}</td><td>{}</td><td class=\"note\">{}</td></tr>",
+                escape(&stats.name),
+                escape(short_name),
+                stats.script_size,
+                stats.num_blocks,
+                stats.num_loops,
+                status,
+                duplicates,
+                notes
+                    .get(stats.name.as_str())
+                    .map(|note| escape(note))
+                    .unwrap_or_default()
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    if !groups.is_empty() {
+        html.push_str("<h2>Duplicate groups</h2>\n");
+        for (index, names) in groups.iter().enumerate() {
+            let _ = write!(
+                html,
+                "<h3 id=\"duplicates-{}\">Group {}</h3>\n<ul>\n",
+                index + 1,
+                index + 1
+            );
+            for name in names {
+                let _ = writeln!(html, "<li><a href=\"#{0}\">{0}</a></li>", escape(name));
+            }
+            html.push_str("</ul>\n");
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(name: &str) -> FunctionStats {
+        FunctionStats {
+            name: name.to_string(),
+            script_size: 2,
+            cfg_built: true,
+            num_blocks: 1,
+            num_loops: 0,
+            structure_succeeded: true,
+            structure_error: String::new(),
+            unused_locals: 0,
+        }
+    }
+
+    #[test]
+    fn test_identical_scripts_are_grouped() {
+        let functions = [
+            (stats("/Game/B.B_C:Tick"), &[0x04, 0x0b][..]),
+            (stats("/Game/A.A_C:Tick"), &[0x04, 0x0b][..]),
+            (stats("/Game/A.A_C:<Init>"), &[0x0b, 0x53][..]),
+        ];
+        assert_eq!(
+            duplicate_groups(&functions),
+            vec![vec!["/Game/A.A_C:Tick", "/Game/B.B_C:Tick"]]
+        );
+        let notes = HashMap::from([("/Game/B.B_C:Tick", "Same as <A>")]);
+        let html = render("test", &functions, &notes);
+        assert!(html.contains("<h2 id=\"/Game/A.A_C\">"));
+        assert!(html.contains("<td class=\"note\">Same as &lt;A&gt;</td>"));
+        assert!(html.contains("&lt;Init&gt;"));
+        assert!(html.contains("<a href=\"#duplicates-1\">group 1</a>"));
+    }
+}
@@ -0,0 +1,39 @@
+//! Decompiling Unreal Engine Blueprint bytecode (Kismet) from jmap dumps
+//!
+//! The command-line tool is built on this library, which other tools can use
+//! to decode scripts, analyze their control flow and structure them without
+//! going through its printed output. [`Decompiler`] runs every step for a
+//! function and returns what each one produced:
+//!
+//! ```no_run
+//! use jmap_kismet_test::{Decompiler, loader};
+//!
+//! let (jmap, _) = loader::read_jmap("dump.json".as_ref())?;
+//! let decompiler = Decompiler::new(&jmap);
+//! for path in decompiler.functions() {
+//!     let function = decompiler.decompile(path)?;
+//!     println!(
+//!         "{}: {} blocks, {} loops, structured: {}",
+//!         path,
+//!         function.cfg.blocks.len(),
+//!         function.loops.loops.len(),
+//!         function.structured.is_some()
+//!     );
+//! }
+//! # Ok::<(), jmap_kismet_test::error::KismetError>(())
+//! ```
+//!
+//! The formatters write into any [`std::fmt::Write`], so a caller can render
+//! a function into a `String`: [`formatters::cpp::StatementWriter`] writes
+//! statements, [`formatters::asm::AsmFormatter`] the bytecode listing and
+//! [`formatters::cpp::ExprRenderer`] formats single expressions.
+
+pub mod bytecode;
+pub mod decompiler;
+pub mod dot;
+pub mod error;
+pub mod formatters;
+pub mod loader;
+
+pub use decompiler::{DecompiledFunction, Decompiler};
+pub use error::KismetError;
@@ -0,0 +1,634 @@
+//! Loading jmap dumps written by different dumper versions
+//!
+//! Older dumpers leave out the name map and the function and property flags.
+//! Rather than rejecting those dumps, the missing fields are filled with
+//! empty values before deserializing, and [`Capabilities`] records what was
+//! missing so features that depend on it can say so.
+//!
+//! Dumpers that split the world into several files share one global name
+//! table between them, so those files can be merged back into one jmap.
+//!
+//! A script captured from memory can be loaded too, with a symbol file
+//! standing in for the dump:
+//!
+//! ```json
+//! {
+//!     "function": "/Game/BP_Enemy.BP_Enemy_C:Attack",
+//!     "names": { "0": "None", "17": "Fire" },
+//!     "objects": { "0x7FF6A0001000": "/Script/Engine.KismetMathLibrary:Add_IntInt" },
+//!     "properties": { "0x7FF6A0002000": "Health" }
+//! }
+//! ```
+//!
+//! Every field is optional. Properties become properties of the function,
+//! since the symbol file doesn't say what owns them.
+//!
+//! Hit-count profiles from a tracer map bytecode offsets to execution counts,
+//! either for one function or keyed by function path:
+//!
+//! ```json
+//! { "/Game/BP_Enemy.BP_Enemy_C:Attack": { "0x0": 12, "0x2A": 3 } }
+//! ```
+//!
+//! Execution traces are plain text, one offset per line in the order they
+//! ran; blank lines and lines starting with `#` are skipped.
+//!
+//! Opcode maps for VMs with shuffled opcodes are TOML, giving the byte each
+//! moved opcode has:
+//!
+//! ```toml
+//! Return = 0x90
+//! EX_CallMath = 0x92
+//! ```
+//!
+//! Opcodes left out keep their stock value. A stock opcode whose byte is
+//! given to another one no longer decodes, unless it's moved too.
+//!
+//! Rename maps are described in [`crate::formatters::renames`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+use crate::bytecode::hit_counts::{HitCounts, HitProfile};
+use crate::bytecode::opcodes::{EExprToken, OpcodeMap};
+use crate::bytecode::trace::Trace;
+use crate::bytecode::types::BytecodeOffset;
+use crate::error::KismetError;
+use crate::formatters::renames::RenameMap;
+
+/// Which optional parts of the schema a dump has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Name map; without it every name reads as `UnknownName_N`
+    pub names: bool,
+    /// Function flags; without them RPCs, events and statics can't be told apart
+    pub function_flags: bool,
+    /// Property flags; without them parameters look like locals
+    pub property_flags: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            names: true,
+            function_flags: true,
+            property_flags: true,
+        }
+    }
+}
+
+impl Capabilities {
+    /// One line per missing field, saying what stops working
+    pub fn limitations(&self) -> Vec<&'static str> {
+        let mut limitations = Vec::new();
+        if !self.names {
+            limitations.push("no name map: names are shown as UnknownName_N");
+        }
+        if !self.function_flags {
+            limitations.push("no function flags: RPCs and function kinds aren't known");
+        }
+        if !self.property_flags {
+            limitations.push("no property flags: parameters aren't told apart from locals");
+        }
+        limitations
+    }
+}
+
+/// Something two merged dumps disagree on; the first dump's version is kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict {
+    Name {
+        index: u32,
+        kept: String,
+        dropped: String,
+    },
+    Object {
+        path: String,
+        kept: u64,
+        dropped: u64,
+    },
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name {
+                index,
+                kept,
+                dropped,
+            } => write!(f, "name {} is \"{}\" and \"{}\"", index, kept, dropped),
+            Self::Object {
+                path,
+                kept,
+                dropped,
+            } => write!(f, "{} is at 0x{:X} and 0x{:X}", path, kept, dropped),
+        }
+    }
+}
+
+/// Add another part of a split dump to `base`
+///
+/// Names and objects missing from `base` are taken from `other`. An object
+/// in both files is only a conflict if its address differs, since split
+/// dumps commonly repeat the engine packages.
+pub fn merge_jmap(
+    base: &mut jmap::Jmap,
+    base_capabilities: &mut Capabilities,
+    other: jmap::Jmap,
+    other_capabilities: Capabilities,
+) -> Vec<MergeConflict> {
+    let mut conflicts = Vec::new();
+
+    let names = base.names.get_or_insert_default();
+    for (index, name) in other.names.into_iter().flatten() {
+        match names.get(&index) {
+            Some(kept) if *kept != name => conflicts.push(MergeConflict::Name {
+                index,
+                kept: kept.clone(),
+                dropped: name,
+            }),
+            Some(_) => {}
+            None => {
+                names.insert(index, name);
+            }
+        }
+    }
+
+    for (path, object) in other.objects {
+        match base.objects.get(&path) {
+            Some(kept) => {
+                let kept = kept.get_object().address.0;
+                let dropped = object.get_object().address.0;
+                if kept != dropped {
+                    conflicts.push(MergeConflict::Object {
+                        path,
+                        kept,
+                        dropped,
+                    });
+                }
+            }
+            None => {
+                base.objects.insert(path, object);
+            }
+        }
+    }
+
+    // One file carrying the shared name table is enough, but flags missing
+    // from any file leave some objects without them
+    base_capabilities.names |= other_capabilities.names;
+    base_capabilities.function_flags &= other_capabilities.function_flags;
+    base_capabilities.property_flags &= other_capabilities.property_flags;
+
+    conflicts
+}
+
+pub fn read_jmap(path: &Path) -> Result<(jmap::Jmap, Capabilities), KismetError> {
+    let text = std::fs::read_to_string(path).map_err(|source| KismetError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_jmap(&text).map_err(|reason| KismetError::JmapParse {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+fn parse_jmap(text: &str) -> Result<(jmap::Jmap, Capabilities), String> {
+    let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    jmap_from_value(value)
+}
+
+fn jmap_from_value(mut value: Value) -> Result<(jmap::Jmap, Capabilities), String> {
+    let Some(root) = value.as_object_mut() else {
+        return Err("expected a JSON object".to_string());
+    };
+
+    let mut capabilities = Capabilities::default();
+    if root.get("names").is_none_or(Value::is_null) {
+        capabilities.names = false;
+        root.insert("names".to_string(), Value::Object(Map::new()));
+    }
+
+    if let Some(objects) = root.get_mut("objects").and_then(Value::as_object_mut) {
+        for object in objects.values_mut().filter_map(Value::as_object_mut) {
+            if object.get("type").and_then(Value::as_str) == Some("Function")
+                && fill_missing(object, "function_flags")
+            {
+                capabilities.function_flags = false;
+            }
+            if let Some(properties) = object.get_mut("properties").and_then(Value::as_array_mut) {
+                for property in properties {
+                    if fill_property_flags(property) {
+                        capabilities.property_flags = false;
+                    }
+                }
+            }
+        }
+    }
+
+    let jmap = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    Ok((jmap, capabilities))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Symbols {
+    function: Option<String>,
+    names: Option<BTreeMap<u32, String>>,
+    /// Address, in hex with `0x` or decimal, to object path
+    objects: BTreeMap<String, String>,
+    /// Address to property name
+    properties: BTreeMap<String, String>,
+}
+
+/// Path given to a raw script's function if the symbol file doesn't name it
+pub(crate) const RAW_SCRIPT_FUNCTION: &str = "/Raw/Script:RawScript";
+
+/// Load a raw script as a jmap holding one function
+pub fn read_raw_script(
+    script_path: &Path,
+    symbols_path: Option<&Path>,
+) -> Result<(jmap::Jmap, Capabilities), KismetError> {
+    let io_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| KismetError::Io { path, source }
+    };
+    let script = std::fs::read(script_path).map_err(io_error(script_path))?;
+    let symbols = match symbols_path {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(io_error(path))?;
+            serde_json::from_str(&text).map_err(|e| KismetError::JmapParse {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
+            })?
+        }
+        None => Symbols::default(),
+    };
+    raw_script_jmap(script, symbols).map_err(|reason| KismetError::JmapParse {
+        path: symbols_path.unwrap_or(script_path).to_path_buf(),
+        reason,
+    })
+}
+
+pub(crate) fn raw_script_jmap(
+    script: Vec<u8>,
+    symbols: Symbols,
+) -> Result<(jmap::Jmap, Capabilities), String> {
+    let mut objects = Map::new();
+    for (address, path) in &symbols.objects {
+        let object = json!({
+            "type": "Object",
+            "address": parse_address(address)?,
+            "outer": null,
+            "class": "/Script/CoreUObject.Object",
+        });
+        objects.insert(path.clone(), object);
+    }
+
+    let properties = symbols
+        .properties
+        .iter()
+        .map(|(address, name)| {
+            Ok(json!({
+                "name": name,
+                "address": parse_address(address)?,
+                "offset": 0,
+                "array_dim": 1,
+                "size": 0,
+                "type": "Int",
+            }))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let function = symbols
+        .function
+        .unwrap_or_else(|| RAW_SCRIPT_FUNCTION.to_string());
+    objects.insert(
+        function,
+        json!({
+            "type": "Function",
+            "address": 0,
+            "outer": null,
+            "class": "/Script/CoreUObject.Function",
+            "super_struct": null,
+            "properties": properties,
+            "properties_size": 0,
+            "script": script,
+        }),
+    );
+
+    jmap_from_value(json!({ "names": symbols.names, "objects": objects }))
+}
+
+pub fn read_hit_profile(path: &Path) -> Result<HitProfile, KismetError> {
+    let text = std::fs::read_to_string(path).map_err(|source| KismetError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_hit_profile(&text).map_err(|reason| KismetError::JmapParse {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+fn parse_hit_profile(text: &str) -> Result<HitProfile, String> {
+    let value: Map<String, Value> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    if value.values().all(Value::is_number) {
+        return parse_hit_counts(&value).map(HitProfile::Shared);
+    }
+    value
+        .iter()
+        .map(|(function, counts)| {
+            let counts = counts
+                .as_object()
+                .ok_or_else(|| format!("counts for '{}' aren't an object", function))?;
+            Ok((function.clone(), parse_hit_counts(counts)?))
+        })
+        .collect::<Result<_, String>>()
+        .map(HitProfile::PerFunction)
+}
+
+fn parse_hit_counts(counts: &Map<String, Value>) -> Result<HitCounts, String> {
+    counts
+        .iter()
+        .map(|(offset, count)| {
+            let offset = parse_address(offset)?;
+            let count = count
+                .as_u64()
+                .ok_or_else(|| format!("invalid hit count {} at {}", count, offset))?;
+            Ok((BytecodeOffset::new(offset as usize), count))
+        })
+        .collect::<Result<_, String>>()
+        .map(HitCounts::new)
+}
+
+pub fn read_trace(path: &Path) -> Result<Trace, KismetError> {
+    let text = std::fs::read_to_string(path).map_err(|source| KismetError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_trace(&text).map_err(|reason| KismetError::JmapParse {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+fn parse_trace(text: &str) -> Result<Trace, String> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            let offset = parse_address(line).map_err(|e| format!("line {}: {}", index + 1, e))?;
+            Ok(BytecodeOffset::new(offset as usize))
+        })
+        .collect::<Result<_, String>>()
+        .map(Trace::new)
+}
+
+fn parse_address(text: &str) -> Result<u64, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("invalid address '{}'", text))
+}
+
+pub fn read_opcode_map(path: &Path) -> Result<OpcodeMap, KismetError> {
+    let text = std::fs::read_to_string(path).map_err(|source| KismetError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_opcode_map(&text).map_err(|reason| KismetError::JmapParse {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+fn parse_opcode_map(text: &str) -> Result<OpcodeMap, String> {
+    let entries: BTreeMap<String, u8> = toml::from_str(text).map_err(|e| e.to_string())?;
+    let moved = entries
+        .iter()
+        .map(|(name, &byte)| {
+            let token =
+                EExprToken::from_name(name).ok_or_else(|| format!("unknown opcode '{}'", name))?;
+            Ok((token, byte))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut map = OpcodeMap::stock();
+    for &(token, _) in &moved {
+        map.assign(token.opcode_value(), None);
+    }
+    let mut taken: BTreeMap<u8, EExprToken> = BTreeMap::new();
+    for (token, byte) in moved {
+        if let Some(other) = taken.insert(byte, token) {
+            return Err(format!(
+                "0x{:02X} is given to both {:?} and {:?}",
+                byte, other, token
+            ));
+        }
+        map.assign(byte, Some(token));
+    }
+    Ok(map)
+}
+
+pub fn read_rename_map(path: &Path) -> Result<RenameMap, KismetError> {
+    let text = std::fs::read_to_string(path).map_err(|source| KismetError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    RenameMap::parse(&text).map_err(|reason| KismetError::JmapParse {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+/// Set a missing field to 0; returns whether it was missing
+fn fill_missing(object: &mut Map<String, Value>, field: &str) -> bool {
+    if object.contains_key(field) {
+        return false;
+    }
+    object.insert(field.to_string(), Value::from(0));
+    true
+}
+
+/// Fill in missing flags on a property and the properties nested in it, such
+/// as an array's element; returns whether any were missing
+fn fill_property_flags(property: &mut Value) -> bool {
+    let Some(property) = property.as_object_mut() else {
+        return false;
+    };
+    let mut missing = fill_missing(property, "flags");
+    for nested in ["inner", "container", "key_prop", "value_prop"] {
+        if let Some(nested) = property.get_mut(nested) {
+            missing |= fill_property_flags(nested);
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jmap_with(names: &str, objects: &[(&str, u64)]) -> jmap::Jmap {
+        let objects: Vec<String> = objects
+            .iter()
+            .map(|(path, address)| {
+                format!(
+                    r#""{}": {{"type": "Object", "address": {}, "outer": null, "class": "Object"}}"#,
+                    path, address
+                )
+            })
+            .collect();
+        let text = format!(
+            r#"{{"names": {}, "objects": {{{}}}}}"#,
+            names,
+            objects.join(", ")
+        );
+        parse_jmap(&text).unwrap().0
+    }
+
+    #[test]
+    fn test_hit_profile() {
+        let offset = BytecodeOffset::new(0x2A);
+        let Ok(HitProfile::Shared(counts)) = parse_hit_profile(r#"{"0x2A": 3, "0": 12}"#) else {
+            panic!("expected shared counts");
+        };
+        assert_eq!(counts.get(offset), Some(3));
+
+        let profile = parse_hit_profile(r#"{"/Game/A:F": {"42": 7}}"#).unwrap();
+        assert_eq!(
+            profile.for_function("/Game/A:F").unwrap().get(offset),
+            Some(7)
+        );
+        assert!(profile.for_function("/Game/A:G").is_none());
+        assert!(parse_hit_profile(r#"{"0x2A": -1}"#).is_err());
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = parse_trace("# ReceiveTick\n0x0\n\n0x1A\n26\n").unwrap();
+        assert_eq!(trace.offsets.len(), 3);
+        assert_eq!(trace.offsets[1], BytecodeOffset::new(0x1A));
+        assert!(
+            parse_trace("0x0\nloop\n")
+                .unwrap_err()
+                .starts_with("line 2")
+        );
+    }
+
+    #[test]
+    fn test_opcode_map() {
+        let map = parse_opcode_map("Return = 0x90\nEX_Nothing = 0x04\n").unwrap();
+        assert_eq!(map.decode(0x90), EExprToken::Return);
+        assert_eq!(map.decode(0x04), EExprToken::Nothing);
+        assert_eq!(map.decode(0x0B), EExprToken::Unknown(0x0B));
+        assert_eq!(map.decode(0x53), EExprToken::EndOfScript);
+
+        assert!(parse_opcode_map("Retrun = 0x90").is_err());
+        assert!(parse_opcode_map("Return = 0x100").is_err());
+        assert!(
+            parse_opcode_map("Return = 0x90\nNothing = 0x90")
+                .unwrap_err()
+                .contains("both")
+        );
+    }
+
+    #[test]
+    fn test_raw_script() {
+        let symbols: Symbols = serde_json::from_str(
+            r#"{
+                "names": { "0": "None" },
+                "objects": { "0x1000": "/Script/Engine.KismetMathLibrary:Add_IntInt" },
+                "properties": { "4096": "Health" }
+            }"#,
+        )
+        .unwrap();
+        let (jmap, capabilities) = raw_script_jmap(vec![0x04, 0x0B, 0x53], symbols).unwrap();
+
+        assert!(capabilities.names);
+        assert!(!capabilities.function_flags);
+        let Some(jmap::ObjectType::Function(func)) = jmap.objects.get(RAW_SCRIPT_FUNCTION) else {
+            panic!("no function in {:?}", jmap.objects.keys());
+        };
+        assert_eq!(func.r#struct.script, [0x04, 0x0B, 0x53]);
+        assert_eq!(func.r#struct.properties[0].address.0, 4096);
+        assert!(
+            jmap.objects
+                .contains_key("/Script/Engine.KismetMathLibrary:Add_IntInt")
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut base = jmap_with(r#"{"0": "None", "1": "Fire"}"#, &[("/Game/A", 16)]);
+        let other = jmap_with(
+            r#"{"1": "Reload", "2": "Ping"}"#,
+            &[("/Game/A", 32), ("/Game/B", 48)],
+        );
+        let mut capabilities = Capabilities {
+            names: false,
+            ..Capabilities::default()
+        };
+
+        let conflicts = merge_jmap(&mut base, &mut capabilities, other, Capabilities::default());
+        assert!(capabilities.names);
+        assert_eq!(base.objects.len(), 2);
+        assert_eq!(base.names.as_ref().unwrap()[&2], "Ping");
+        assert_eq!(
+            conflicts,
+            [
+                MergeConflict::Name {
+                    index: 1,
+                    kept: "Fire".to_string(),
+                    dropped: "Reload".to_string(),
+                },
+                MergeConflict::Object {
+                    path: "/Game/A".to_string(),
+                    kept: 16,
+                    dropped: 32,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_fields_are_filled() {
+        let (jmap, capabilities) = parse_jmap(
+            r#"{
+                "objects": {
+                    "/Game/BP.BP_C:Fire": {
+                        "type": "Function",
+                        "address": 16, "outer": null, "class": "Function",
+                        "super_struct": null, "properties_size": 0, "script": [],
+                        "properties": [{
+                            "name": "Targets", "address": 32, "offset": 0,
+                            "array_dim": 1, "size": 16, "type": "Array",
+                            "inner": {
+                                "name": "Targets", "address": 48, "offset": 0,
+                                "array_dim": 1, "size": 8, "type": "Int"
+                            }
+                        }]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            capabilities,
+            Capabilities {
+                names: false,
+                function_flags: false,
+                property_flags: false,
+            }
+        );
+        assert_eq!(capabilities.limitations().len(), 3);
+        assert!(jmap.names.is_some_and(|names| names.is_empty()));
+    }
+}
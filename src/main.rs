@@ -1,24 +1,79 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::io::{self, IsTerminal};
 use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
-mod bytecode;
-mod dot;
-mod formatters;
+use jmap_kismet_test::{Decompiler, bytecode, error, formatters, loader};
+
+mod config;
+mod difftest;
+mod index_page;
+mod notes;
+mod profile;
+mod redact;
+mod repl;
+mod rules;
+mod session;
+mod tables;
 
 use crate::{
     bytecode::{
         address_index::AddressIndex,
-        cfg::{ControlFlowGraph, Terminator},
+        align::{self, Step},
+        array_get::{InlinedArrayGet, inline_array_gets},
+        call_graph::{CallGraph, CallKind},
+        cfg::{CfgOptions, ControlFlowGraph, Terminator},
+        cost::{ASSUMED_LOOP_ITERATIONS, FunctionCost, expr_cost},
         dominators::{DominatorTree, PostDominatorTree},
-        expr::{ExprKind, collect_referenced_offsets},
-        logger::NullLogger,
-        loops::LoopInfo,
-        parser::ScriptParser,
+        expr::{Expr, ExprKind, collect_referenced_offsets},
+        flags::function_flag_names,
+        getters::{Getters, InlinedCall},
+        histogram::OpcodeHistogram,
+        hit_counts::HitProfile,
+        locals::unused_locals,
+        loops::{LoopInfo, normalize_loops},
+        name_index::NameIndex,
+        obfuscation,
+        opcodes::{EExprToken, OpcodeMap},
+        parser::{EngineVersion, ScriptParser},
+        pattern::{
+            Access, find_calls, function_matches, glob_match, property_accesses, written_property,
+        },
+        query::{Query, QueryEnv},
         reader::ScriptReader,
-        structured::PhoenixStructurer,
+        reducibility::Reducibility,
+        refs::{FunctionRef, PropertyRef},
+        rpc_audit::{self, RpcAudit},
+        structured::{StructuredGraph, StructuringTimedOut},
+        ticks,
+        trace::Trace,
+        types::{Address, BytecodeOffset, interner_stats},
+        ubergraph,
+        xrefs::XrefIndex,
+    },
+    config::Config,
+    error::KismetError,
+    formatters::{
+        FormattingOptions,
+        asm::AsmFormatter,
+        cpp::{ExprRenderer, StatementWriter},
+        renames::RenameMap,
+        theme,
     },
-    formatters::{asm::AsmFormatter, cpp::CppFormatter},
+    loader::Capabilities,
+    notes::Notes,
+    profile::Profile,
+    session::{Session, SourceFile},
+    tables::{CallRow, FunctionRow, XrefRow},
 };
 
 #[derive(Debug)]
@@ -30,9 +85,130 @@ struct FunctionStats {
     num_loops: usize,
     structure_succeeded: bool,
     structure_error: String,
+    /// Locals the function declares but never reads or writes
+    unused_locals: usize,
+}
+
+/// How `disassemble` prints each function
+struct DisassembleOutput {
+    /// Formats to print each function in, in order
+    formats: Vec<OutputFormat>,
+    formatting: FormattingOptions,
+    /// Execution counts to annotate and order structured output with
+    hit_profile: Option<HitProfile>,
+    /// Recorded run to highlight in DOT output
+    trace: Option<Trace>,
+    /// Regions of huge functions to draw in full in DOT output
+    expanded_regions: Vec<usize>,
+    /// Drop branches of structured ifs whose condition is a constant
+    prune_constant_branches: bool,
+    /// Replace calls to functions that only return a member with the member
+    inline_getters: bool,
+    /// Replace copies of array elements read once with the element
+    inline_array_gets: bool,
+    /// Print events with their code from the ubergraph in place of the call
+    /// into it
+    fold_events: bool,
+    /// Analysts' notes to print under each function's header
+    notes: Notes,
+    /// Print functions whose script matches an earlier one as a reference to it
+    dedupe: bool,
+    /// Print only the opcode histogram of all functions together
+    aggregate: bool,
+}
+
+/// Caps on how much `disassemble` prints
+#[derive(Debug, Default)]
+struct DisassembleLimits {
+    /// Skip functions with scripts larger than this many bytes
+    max_script_size: Option<usize>,
+    /// Stop after this many functions
+    limit: Option<usize>,
+    /// Time allowed for structuring each function
+    timeout_per_function: Option<Duration>,
+}
+
+/// What `disassemble` leaves out or normalizes so output can be committed and
+/// diffed between runs and game versions
+#[derive(Debug, Default)]
+struct Reproducibility {
+    /// Print functions sorted by path instead of in jmap order
+    sort_functions: bool,
+    /// Leave out timings, which differ from run to run
+    hide_timings: bool,
+    /// Leave out object addresses, which differ between builds
+    hide_addresses: bool,
+    /// Print object addresses as symbolic names derived from their paths
+    normalize_addresses: bool,
+}
+
+/// Why `disassemble` stopped writing a function
+#[derive(Debug)]
+enum FormatError {
+    /// The function couldn't be decompiled this far
+    Kismet(KismetError),
+    /// The output couldn't be written; [`OutputSink`] holds why
+    Output,
+}
+
+impl From<KismetError> for FormatError {
+    fn from(error: KismetError) -> Self {
+        Self::Kismet(error)
+    }
+}
+
+impl From<fmt::Error> for FormatError {
+    fn from(_: fmt::Error) -> Self {
+        Self::Output
+    }
+}
+
+/// Parse a duration like `10s`, `500ms` or `2m`; bare numbers are seconds
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", value))?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "ms" => number / 1000.0,
+        "m" => number * 60.0,
+        _ => return Err(format!("unknown duration unit '{}' (use ms, s or m)", unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("invalid duration '{}': {}", value, e))
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+/// A boolean flag's value: the last of `--flag` and `--no-flag` given, or
+/// else the config's, if either sets it
+fn flag_value(on: bool, off: bool, config: Option<bool>) -> Option<bool> {
+    if on || off { Some(on) } else { config }
+}
+
+/// Files given with `--merge`, loaded into every jmap a command reads
+static MERGE_FILES: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+/// Map given with `--opcode-map`, used to decode every script
+static OPCODE_MAP: OnceLock<Option<OpcodeMap>> = OnceLock::new();
+
+/// Secrets given with `--redact`, masked in every script loaded
+static REDACTIONS: OnceLock<Option<redact::Redactions>> = OnceLock::new();
+
+/// Maps given with `--rename-map` or the config, applied to every address
+/// index built
+static RENAMES: OnceLock<RenameMap> = OnceLock::new();
+
+/// Engine given with `--engine-version`, deciding how wide constants are read
+static ENGINE_VERSION: OnceLock<EngineVersion> = OnceLock::new();
+
+/// Writes the trace `--profile-output` records, finishing it when dropped
+static PROFILE: Mutex<Option<tracing_chrome::FlushGuard>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 enum OutputFormat {
     Cpp,
     Asm,
@@ -40,12 +216,75 @@ enum OutputFormat {
     Structured,
     Dot,
     Cfg,
+    Histogram,
+    Summary,
+    Tokens,
+    Hexdump,
+}
+
+impl OutputFormat {
+    /// Extension of a file holding output in this format
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Cpp => "cpp",
+            OutputFormat::Asm => "asm",
+            OutputFormat::Dot => "dot",
+            OutputFormat::Tokens => "jsonl",
+            OutputFormat::Analyze
+            | OutputFormat::Structured
+            | OutputFormat::Cfg
+            | OutputFormat::Histogram
+            | OutputFormat::Summary
+            | OutputFormat::Hexdump => "txt",
+        }
+    }
+}
+
+/// What `diff` compares two versions of a function by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DiffLevel {
+    /// Top-level instructions, aligned by opcode. For pseudocode, run a text
+    /// diff over `disassemble --profile diff` output
+    Bytecode,
 }
 
 #[derive(Parser, Debug)]
 #[command(name = "jmap-kismet")]
 #[command(about = "JMAP bytecode analysis and decompilation tool")]
 struct Args {
+    /// Config file with default flag values [default: ./kismet.toml if present];
+    /// a flag it turns on is turned off for one run with the flag's `--no-` form
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// More parts of a split dump to load along with the JMAP file
+    #[arg(long, global = true, num_args = 1.., value_name = "JMAP_FILE")]
+    merge: Vec<PathBuf>,
+
+    /// Opcode map for a VM with shuffled opcodes, such as one detect-obfuscation found
+    #[arg(long, global = true, value_name = "TOML_FILE")]
+    opcode_map: Option<PathBuf>,
+
+    /// Secrets to mask in script strings before any output, one [[redact]]
+    /// table each with the `text` to mask or a `prefix` masked to the
+    /// string's end, for sharing decompilations outside the team
+    #[arg(long, global = true, value_name = "TOML_FILE")]
+    redact: Option<PathBuf>,
+
+    /// Engine that compiled the scripts; UE5 reads vector, rotator and
+    /// transform constants as doubles
+    #[arg(long, global = true, value_enum, default_value_t = EngineVersion::Ue4)]
+    engine_version: EngineVersion,
+
+    /// Names to print properties and locals by instead of generated ones, as
+    /// `name,new name` lines; repeat to combine maps, later ones winning
+    #[arg(long, global = true, value_name = "CSV_FILE")]
+    rename_map: Vec<PathBuf>,
+
+    /// Time each stage and pass, writing the spans as JSON for chrome://tracing
+    #[arg(long, global = true, value_name = "JSON_FILE")]
+    profile_output: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -55,27 +294,227 @@ enum Commands {
     /// Disassemble and analyze functions
     Disassemble {
         /// Path to the JMAP file
-        jmap_file: String,
+        #[arg(required_unless_present = "raw_script")]
+        jmap_file: Option<String>,
+
+        /// Disassemble script bytes captured from memory instead of a JMAP
+        #[arg(long, conflicts_with = "jmap_file")]
+        raw_script: Option<PathBuf>,
+
+        /// JSON file with the name table and addresses for --raw-script
+        #[arg(long, requires = "raw_script")]
+        symbols: Option<PathBuf>,
 
         /// Filter functions by name (optional)
         #[arg(short, long)]
         filter: Option<String>,
 
-        /// Output format
-        #[arg(short = 'o', long, default_value = "cpp")]
-        format: OutputFormat,
+        /// Output format, or several separated by commas to print each
+        /// function in all of them from one parse [default: cpp]
+        #[arg(short = 'o', long, value_delimiter = ',')]
+        format: Vec<OutputFormat>,
+
+        /// Option bundle to start from; the flags below add to it [default: readable]
+        #[arg(long, value_enum)]
+        profile: Option<Profile>,
 
         /// Show block ID comments in structured output
-        #[arg(long)]
+        #[arg(long, overrides_with = "no_show_block_ids")]
         show_block_ids: bool,
+        #[arg(long, overrides_with = "show_block_ids", hide = true)]
+        no_show_block_ids: bool,
 
         /// Show bytecode offset comments in structured output
-        #[arg(long)]
+        #[arg(long, overrides_with = "no_show_bytecode_offsets")]
         show_bytecode_offsets: bool,
+        #[arg(long, overrides_with = "show_bytecode_offsets", hide = true)]
+        no_show_bytecode_offsets: bool,
 
         /// Show terminator expressions as comments in structured output
-        #[arg(long)]
+        #[arg(long, overrides_with = "no_show_terminator_exprs")]
         show_terminator_exprs: bool,
+        #[arg(long, overrides_with = "show_terminator_exprs", hide = true)]
+        no_show_terminator_exprs: bool,
+
+        /// Annotate statements and loops with estimated execution cost and
+        /// print a cost score for each function
+        #[arg(long, overrides_with = "no_show_costs")]
+        show_costs: bool,
+        #[arg(long, overrides_with = "show_costs", hide = true)]
+        no_show_costs: bool,
+
+        /// Only write valid C++: declare locals, write value switches as
+        /// ternaries, label blocks for gotos, and leave TODO comments for
+        /// what C++ can't express
+        #[arg(long, overrides_with = "no_valid_cpp")]
+        valid_cpp: bool,
+        #[arg(long, overrides_with = "valid_cpp", hide = true)]
+        no_valid_cpp: bool,
+
+        /// Name casts after the opcode they come from, such as
+        /// `ObjToInterfaceCast<T>`, instead of the equivalent C++; ignored
+        /// with --valid-cpp
+        #[arg(long, overrides_with = "no_faithful_casts")]
+        faithful_casts: bool,
+        #[arg(long, overrides_with = "faithful_casts", hide = true)]
+        no_faithful_casts: bool,
+
+        /// After each function, explain the `/*?N*/` markers on lines that
+        /// rest on a guess, such as an operator whose operand types the dump
+        /// can't confirm or a branch pruned as dead
+        #[arg(long, overrides_with = "no_explain")]
+        explain: bool,
+        #[arg(long, overrides_with = "explain", hide = true)]
+        no_explain: bool,
+
+        /// Follow each member access with the property's byte offset in its
+        /// class or struct and its size, from the dump
+        #[arg(long, overrides_with = "no_show_prop_offsets")]
+        show_prop_offsets: bool,
+        #[arg(long, overrides_with = "show_prop_offsets", hide = true)]
+        no_show_prop_offsets: bool,
+
+        /// Head each loop that counts a variable up to a limit with the range
+        /// it runs over, to tell bounded loops from ones that may not end
+        #[arg(long, overrides_with = "no_show_loop_bounds")]
+        show_loop_bounds: bool,
+        #[arg(long, overrides_with = "show_loop_bounds", hide = true)]
+        no_show_loop_bounds: bool,
+
+        /// Follow each function with the local variables it declares but
+        /// never reads or writes, often left behind by deleted nodes
+        #[arg(long, overrides_with = "no_show_unused_locals")]
+        show_unused_locals: bool,
+        #[arg(long, overrides_with = "show_unused_locals", hide = true)]
+        no_show_unused_locals: bool,
+
+        /// Leave out branches of structured ifs whose condition only depends
+        /// on literals, with a comment where each one was
+        #[arg(long, overrides_with = "no_prune_constant_branches")]
+        prune_constant_branches: bool,
+        #[arg(long, overrides_with = "prune_constant_branches", hide = true)]
+        no_prune_constant_branches: bool,
+
+        /// Print calls to functions that only return a member of their object
+        /// as that member, listing the inlined calls under the function header
+        #[arg(long, overrides_with = "no_inline_getters")]
+        inline_getters: bool,
+        #[arg(long, overrides_with = "inline_getters", hide = true)]
+        no_inline_getters: bool,
+
+        /// Print array elements copied into a temporary that's read once as
+        /// `Items[i]`, listing the dropped Array_Get calls under the function
+        /// header
+        #[arg(long, overrides_with = "no_inline_array_gets")]
+        inline_array_gets: bool,
+        #[arg(long, overrides_with = "inline_array_gets", hide = true)]
+        no_inline_array_gets: bool,
+
+        /// Print Blueprint events with their code from the ubergraph in place
+        /// of the call into it, using the event's parameters directly rather
+        /// than the ubergraph frame properties they're copied into
+        #[arg(long, overrides_with = "no_fold_events")]
+        fold_events: bool,
+        #[arg(long, overrides_with = "fold_events", hide = true)]
+        no_fold_events: bool,
+
+        /// Decompile every function, even ones whose script is byte-identical
+        /// to a function already printed
+        #[arg(long)]
+        no_dedupe: bool,
+
+        /// With `-o histogram`, print one table for all functions instead of
+        /// one per function
+        #[arg(long)]
+        aggregate: bool,
+
+        /// Model fail-silent contexts as explicit null-check branches in the CFG
+        #[arg(long, overrides_with = "no_split_fail_silent")]
+        split_fail_silent: bool,
+        #[arg(long, overrides_with = "split_fail_silent", hide = true)]
+        no_split_fail_silent: bool,
+
+        /// Give each Assert a "check failed" edge to an abort block, so analyze
+        /// mode doesn't treat code after an assertion as always reached
+        #[arg(long, overrides_with = "no_model_asserts")]
+        model_asserts: bool,
+        #[arg(long, overrides_with = "model_asserts", hide = true)]
+        no_model_asserts: bool,
+
+        /// Skip functions whose script is larger than this many bytes
+        #[arg(long)]
+        max_script_size: Option<usize>,
+
+        /// Stop after disassembling this many functions
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Write the output to a file in this directory, named after the JMAP
+        /// file and the format, instead of printing it
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+
+        /// Pipe output through $PAGER when writing to a terminal
+        #[arg(long, overrides_with = "no_pager")]
+        pager: bool,
+        #[arg(long, overrides_with = "pager", hide = true)]
+        no_pager: bool,
+
+        /// Deterministic, ASCII-only output without colors or timings, meant
+        /// to be committed and diffed across game versions
+        #[arg(long)]
+        plain: bool,
+
+        /// Leave object addresses out of function headers
+        #[arg(long)]
+        hide_addresses: bool,
+
+        /// Replace raw object addresses with stable names derived from object paths
+        #[arg(long)]
+        normalize_addresses: bool,
+
+        /// Abandon structuring a function after this long and fall back to CFG
+        /// output (e.g. `10s`, `500ms`, `2m`)
+        #[arg(long, value_parser = parse_duration)]
+        timeout_per_function: Option<Duration>,
+
+        /// JSON profile of execution counts by bytecode offset; structured
+        /// output is annotated with them and puts hotter branches first
+        #[arg(long, value_name = "PROFILE_FILE")]
+        hit_counts: Option<PathBuf>,
+
+        /// Execution trace, one bytecode offset per line, to replay over the
+        /// output: structured output shows what ran and which way branches
+        /// went, DOT output highlights the path taken. Use with --filter, since
+        /// the offsets belong to one function
+        #[arg(long, value_name = "TRACE_FILE", conflicts_with = "hit_counts")]
+        trace: Option<PathBuf>,
+
+        /// Notes to print under each function's header [default:
+        /// <jmap_file>.notes.toml, if it exists]
+        #[arg(long, value_name = "NOTES_FILE")]
+        notes: Option<PathBuf>,
+
+        /// In DOT output of functions with more than 1000 blocks, draw the
+        /// blocks of collapsed region N instead of its summary node; repeat to
+        /// expand nested regions
+        #[arg(long, value_name = "N")]
+        expand_region: Vec<usize>,
+    },
+    /// Parse, build the CFG of and structure every function without printing
+    /// any code, reporting only what went wrong; exits with the status of the
+    /// first function to fail, by path, if any did
+    Check {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Worker threads [default: one per core]
+        #[arg(long)]
+        threads: Option<usize>,
     },
     /// Generate CSV statistics for all functions
     Stats {
@@ -89,308 +528,3811 @@ enum Commands {
         /// Output CSV file path (defaults to stdout)
         #[arg(short, long)]
         output: Option<String>,
+
+        /// Worker threads for collecting statistics [default: one per core]
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// Instead of writing CSV, time collecting statistics with 1, 2, 4, ...
+        /// threads up to --threads and print how well it scales
+        #[arg(long)]
+        scaling: bool,
+    },
+    /// Write per-function metrics and the call table as CSV files, for
+    /// loading into a dataframe, and an index.html page to browse them from
+    Export {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Directory to write functions.csv, calls.csv, xrefs.csv and index.html
+        /// to, and functions.parquet and xrefs.parquet if built with the
+        /// `parquet` feature [default: the config's output_dir, or .]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Notes to show beside functions on index.html [default:
+        /// <jmap_file>.notes.toml, if it exists]
+        #[arg(long, value_name = "NOTES_FILE")]
+        notes: Option<PathBuf>,
+    },
+    /// Index FName constants (gameplay tags, named events) used by scripts
+    Names {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Only show uses of this name (and its dotted child tags)
+        #[arg(long)]
+        find_name: Option<String>,
+    },
+    /// Find functions matching a pattern query, such as
+    /// `call("SpawnActor*") inside loop` or `write(prop:"Health") && call("*Damage*")`
+    Query {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// The query; atoms are call(), write(), read() and name(), combined
+        /// with &&, || and !
+        query: String,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
+    /// For each class, show which functions read (R) and write (W) which of
+    /// its member properties
+    RwMatrix {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Only show properties matching this pattern, with `*` wildcards
+        #[arg(long)]
+        property: Option<String>,
+    },
+    /// Print the memory layout of a class or struct: every member property,
+    /// inherited ones included, with its offset, size and type
+    Layout {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Class or struct, by path or by the name after the path's last `.`
+        class: String,
+    },
+    /// Scan every function with the queries saved in a rule file
+    Scan {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// TOML file with a [[rule]] table per rule, each with a name and query
+        #[arg(short, long)]
+        rules: PathBuf,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Report functions that call themselves, directly or through other functions
+    Recursion {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Only report cycles with a function matching this filter (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
+    /// List script functions that no script calls or binds to a delegate
+    DeadFunctions {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
+    /// Report Server RPCs whose bodies don't check their object arguments
+    /// with IsValid or gate on authority
+    RpcAudit {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
+    /// List code that runs every frame or on a timer, sorted by estimated
+    /// cost per frame
+    Ticks {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Frame rate used to turn timer intervals into a cost per frame
+        #[arg(long, default_value_t = 60.0)]
+        frame_rate: f32,
+    },
+    /// Rank the engine functions scripts call most, with who calls them, to
+    /// pick which natives to hook or instrument first
+    HotNatives {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Only count calls made by functions matching this filter (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Number of engine functions to list
+        #[arg(long, default_value_t = 25)]
+        top: usize,
+    },
+    /// Check whether a game's VM uses shuffled opcode values, and try to find
+    /// out which value stands for which opcode
+    DetectObfuscation {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Dump of a game with a stock VM to compare opcode frequencies with
+        #[arg(long, value_name = "JMAP_FILE")]
+        baseline: Option<String>,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+
+        /// Search for an opcode map even if the opcodes look stock
+        #[arg(long)]
+        recover: bool,
+
+        /// Scripts to decode while searching before giving up
+        #[arg(long, default_value_t = 200_000)]
+        max_attempts: usize,
+
+        /// Write the map found to a file, for use with --opcode-map
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write a function's raw script bytes to a file, for other tools and tests
+    ExtractScript {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Path of the function, or a part of it that matches only one function
+        function: String,
+
+        /// Output file [default: <function name>.bin, or stdout with --hex]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Write a hex dump instead of the bytes
+        #[arg(long)]
+        hex: bool,
+    },
+    /// Show, write or remove an analyst's note on a function, kept in a notes
+    /// file beside the dump that `disassemble` and `export` show notes from;
+    /// with no function, list every note
+    Note {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Path of the function, or a part of it that matches only one function
+        function: Option<String>,
+
+        /// Markdown to store as the function's note, replacing any it has;
+        /// `-` reads it from stdin
+        #[arg(conflicts_with = "remove")]
+        text: Option<String>,
+
+        /// Remove the function's note
+        #[arg(long, requires = "function")]
+        remove: bool,
+
+        /// Notes file [default: <jmap_file>.notes.toml]
+        #[arg(long, value_name = "NOTES_FILE")]
+        notes: Option<PathBuf>,
+    },
+    /// Show how a function changed between two dumps, instruction by instruction
+    Diff {
+        /// Path to the JMAP file with the old version
+        old_jmap: String,
+
+        /// Path to the JMAP file with the new version
+        new_jmap: String,
+
+        /// Path of the function, or a part of it that matches only one function
+        function: String,
+
+        /// What to compare
+        #[arg(long, value_enum, default_value_t = DiffLevel::Bytecode)]
+        level: DiffLevel,
+    },
+    /// Compare the calls in each function with another decompiler's output,
+    /// to catch decoding bugs
+    Difftest {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Command running the reference on one function, with {jmap},
+        /// {function} and {script} filled in
+        #[arg(
+            long,
+            value_name = "TEMPLATE",
+            default_value = "kismet-analyzer decompile {script}"
+        )]
+        reference: String,
+
+        /// Filter functions by name (optional)
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
+    /// Print object paths starting with a prefix, one per line, for shell
+    /// completion and for finding the exact path to pass other commands
+    List {
+        /// Path to the JMAP file
+        jmap_file: String,
+
+        /// Only print paths starting with this
+        #[arg(default_value = "")]
+        prefix: String,
+
+        /// List classes
+        #[arg(long)]
+        classes: bool,
+
+        /// List functions
+        #[arg(long)]
+        functions: bool,
+
+        /// List the names of a class's member properties, inherited ones
+        /// included, instead of paths
+        #[arg(long, value_name = "CLASS", conflicts_with_all = ["classes", "functions"])]
+        properties: Option<String>,
+    },
+    /// Load a dump once and answer commands about it read from stdin, such
+    /// as `decompile`, `xref` and `callers`; type `help` for the list
+    Repl {
+        /// Path to the JMAP file
+        #[arg(required_unless_present = "session")]
+        jmap_file: Option<String>,
+
+        /// Start from a session saved by --save-session instead of indexing
+        /// the JMAP it was saved from again
+        #[arg(long, value_name = "SESSION_FILE", conflicts_with = "jmap_file")]
+        session: Option<PathBuf>,
+
+        /// Index every script up front and save the indexes with the path
+        /// and hash of the dump, for --session to start from next time
+        #[arg(long, value_name = "SESSION_FILE")]
+        save_session: Option<PathBuf>,
     },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate completions for
+        shell: Shell,
+    },
+    /// Print a man page in roff format
+    Manpage,
 }
 
 fn main() {
     let args = Args::parse();
 
+    let config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error loading config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    config.theme.color.apply();
+    MERGE_FILES.set(args.merge).expect("set once at startup");
+    let opcode_map = args
+        .opcode_map
+        .map(|path| loader::read_opcode_map(&path).unwrap_or_else(|e| exit_with(e)));
+    OPCODE_MAP.set(opcode_map).expect("set once at startup");
+    let redactions = args.redact.map(|path| {
+        redact::load_redactions(&path).unwrap_or_else(|e| {
+            eprintln!("Error in redactions: {}", e);
+            std::process::exit(1);
+        })
+    });
+    REDACTIONS.set(redactions).expect("set once at startup");
+    ENGINE_VERSION
+        .set(args.engine_version)
+        .expect("set once at startup");
+    if let Some(path) = &args.profile_output {
+        start_profile(path);
+    }
+    let rename_maps = match args.rename_map.is_empty() {
+        true => &config.rename_maps,
+        false => &args.rename_map,
+    };
+    let mut renames = RenameMap::default();
+    for path in rename_maps {
+        renames.extend(loader::read_rename_map(path).unwrap_or_else(|e| exit_with(e)));
+    }
+    RENAMES.set(renames).expect("set once at startup");
+
     match args.command {
         Commands::Disassemble {
             jmap_file,
+            raw_script,
+            symbols,
             filter,
             format,
+            profile,
             show_block_ids,
+            no_show_block_ids,
             show_bytecode_offsets,
+            no_show_bytecode_offsets,
             show_terminator_exprs,
+            no_show_terminator_exprs,
+            show_costs,
+            no_show_costs,
+            valid_cpp,
+            no_valid_cpp,
+            faithful_casts,
+            no_faithful_casts,
+            explain,
+            no_explain,
+            show_prop_offsets,
+            no_show_prop_offsets,
+            show_loop_bounds,
+            no_show_loop_bounds,
+            show_unused_locals,
+            no_show_unused_locals,
+            prune_constant_branches,
+            no_prune_constant_branches,
+            inline_getters,
+            no_inline_getters,
+            inline_array_gets,
+            no_inline_array_gets,
+            fold_events,
+            no_fold_events,
+            no_dedupe,
+            aggregate,
+            split_fail_silent,
+            no_split_fail_silent,
+            model_asserts,
+            no_model_asserts,
+            max_script_size,
+            limit,
+            output_dir,
+            pager,
+            no_pager,
+            plain,
+            hide_addresses,
+            normalize_addresses,
+            timeout_per_function,
+            hit_counts,
+            trace,
+            expand_region,
+            notes,
         } => {
-            run_disassemble(
-                &jmap_file,
-                filter,
-                format,
-                show_block_ids,
-                show_bytecode_offsets,
-                show_terminator_exprs,
-            );
-        }
-        Commands::Stats {
-            jmap_file,
-            filter,
-            output,
-        } => {
-            run_stats(&jmap_file, filter, output);
-        }
-    }
-}
+            let defaults = &config.disassemble;
+            let filter = filter.or(config.filter);
+            let mut formats = format;
+            if formats.is_empty() {
+                formats.push(defaults.format.unwrap_or(OutputFormat::Cpp));
+            }
+            // Keep the first of repeated formats, wherever they are
+            let mut unique = Vec::with_capacity(formats.len());
+            for format in formats {
+                if !unique.contains(&format) {
+                    unique.push(format);
+                }
+            }
+            let formats = unique;
+            // These replace the per-function output rather than adding to it
+            if formats.len() > 1
+                && formats.iter().any(|format| {
+                    matches!(
+                        format,
+                        OutputFormat::Histogram | OutputFormat::Summary | OutputFormat::Tokens
+                    )
+                })
+            {
+                eprintln!(
+                    "Error: histogram, summary and tokens output can't be combined with others"
+                );
+                std::process::exit(1);
+            }
+            let profile = profile.or(defaults.profile).unwrap_or_default();
+            let timeout_per_function = match (timeout_per_function, &defaults.timeout_per_function)
+            {
+                (Some(timeout), _) => Some(timeout),
+                (None, Some(timeout)) => match parse_duration(timeout) {
+                    Ok(timeout) => Some(timeout),
+                    Err(e) => {
+                        eprintln!("Error in config timeout_per_function: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+                (None, None) => None,
+            };
+            // Which functions time out depends on machine load
+            let timeout_per_function = timeout_per_function.filter(|_| {
+                if plain {
+                    eprintln!("warning: --timeout-per-function is ignored with --plain");
+                }
+                !plain
+            });
+
+            let output_file = output_dir.or(config.output_dir).map(|dir| {
+                let input = jmap_file
+                    .as_deref()
+                    .map(Path::new)
+                    .or(raw_script.as_deref());
+                let stem = input.and_then(Path::file_stem).unwrap_or("output".as_ref());
+                let extension = match formats.as_slice() {
+                    [format] => format.extension(),
+                    _ => "txt",
+                };
+                dir.join(format!("{}.{}", stem.to_string_lossy(), extension))
+            });
+            let mut sink = match &output_file {
+                Some(path) => OutputSink::file(path).unwrap_or_else(|source| {
+                    exit_with(KismetError::Io {
+                        path: path.clone(),
+                        source,
+                    })
+                }),
+                None if flag_value(pager, no_pager, defaults.pager).unwrap_or_default()
+                    && std::io::stdout().is_terminal() =>
+                {
+                    OutputSink::pager().unwrap_or_else(OutputSink::stdout)
+                }
+                None => OutputSink::stdout(),
+            };
+            if formats == [OutputFormat::Tokens] {
+                theme::mark_roles();
+                sink = sink.as_tokens();
+            }
+
+            // Files from --output-dir are read in editors, even when the
+            // config or environment asks for colors
+            if plain
+                || !profile.colors()
+                || formats == [OutputFormat::Tokens]
+                || output_file.is_some()
+            {
+                colored::control::set_override(false);
+            }
+
+            let base = profile.formatting();
+            let formatting = FormattingOptions {
+                show_block_ids: flag_value(
+                    show_block_ids,
+                    no_show_block_ids,
+                    defaults.show_block_ids,
+                )
+                .unwrap_or(base.show_block_ids),
+                show_bytecode_offsets: flag_value(
+                    show_bytecode_offsets,
+                    no_show_bytecode_offsets,
+                    defaults.show_bytecode_offsets,
+                )
+                .unwrap_or(base.show_bytecode_offsets),
+                show_terminator_exprs: flag_value(
+                    show_terminator_exprs,
+                    no_show_terminator_exprs,
+                    defaults.show_terminator_exprs,
+                )
+                .unwrap_or(base.show_terminator_exprs),
+                ascii_only: base.ascii_only || plain,
+                normalize_addresses,
+                show_costs: flag_value(show_costs, no_show_costs, defaults.show_costs)
+                    .unwrap_or(base.show_costs),
+                valid_cpp: flag_value(valid_cpp, no_valid_cpp, defaults.valid_cpp)
+                    .unwrap_or(base.valid_cpp),
+                faithful_casts: flag_value(
+                    faithful_casts,
+                    no_faithful_casts,
+                    defaults.faithful_casts,
+                )
+                .unwrap_or(base.faithful_casts),
+                explain: flag_value(explain, no_explain, defaults.explain).unwrap_or(base.explain),
+                show_prop_offsets: flag_value(
+                    show_prop_offsets,
+                    no_show_prop_offsets,
+                    defaults.show_prop_offsets,
+                )
+                .unwrap_or(base.show_prop_offsets),
+                show_loop_bounds: flag_value(
+                    show_loop_bounds,
+                    no_show_loop_bounds,
+                    defaults.show_loop_bounds,
+                )
+                .unwrap_or(base.show_loop_bounds),
+                show_unused_locals: flag_value(
+                    show_unused_locals,
+                    no_show_unused_locals,
+                    defaults.show_unused_locals,
+                )
+                .unwrap_or(base.show_unused_locals),
+                ..base
+            };
+            let cfg_options = CfgOptions {
+                split_fail_silent_contexts: flag_value(
+                    split_fail_silent,
+                    no_split_fail_silent,
+                    defaults.split_fail_silent,
+                )
+                .unwrap_or_default(),
+                model_asserts: flag_value(model_asserts, no_model_asserts, defaults.model_asserts)
+                    .unwrap_or_default(),
+            };
+            let limits = DisassembleLimits {
+                max_script_size: max_script_size.or(defaults.max_script_size),
+                limit: limit.or(defaults.limit),
+                timeout_per_function,
+            };
+            let reproducibility = Reproducibility {
+                sort_functions: plain || profile.sorted(),
+                hide_timings: plain,
+                hide_addresses,
+                normalize_addresses,
+            };
+            let trace =
+                trace.map(|path| loader::read_trace(&path).unwrap_or_else(|e| exit_with(e)));
+            let hit_profile = match (hit_counts, &trace) {
+                (Some(path), _) => {
+                    Some(loader::read_hit_profile(&path).unwrap_or_else(|e| exit_with(e)))
+                }
+                (None, Some(trace)) => Some(HitProfile::Shared(trace.hit_counts())),
+                (None, None) => None,
+            };
+            let output = DisassembleOutput {
+                formats,
+                formatting,
+                hit_profile,
+                trace,
+                expanded_regions: expand_region,
+                prune_constant_branches: flag_value(
+                    prune_constant_branches,
+                    no_prune_constant_branches,
+                    defaults.prune_constant_branches,
+                )
+                .unwrap_or_default(),
+                inline_getters: flag_value(
+                    inline_getters,
+                    no_inline_getters,
+                    defaults.inline_getters,
+                )
+                .unwrap_or_default(),
+                inline_array_gets: flag_value(
+                    inline_array_gets,
+                    no_inline_array_gets,
+                    defaults.inline_array_gets,
+                )
+                .unwrap_or_default(),
+                fold_events: flag_value(fold_events, no_fold_events, defaults.fold_events)
+                    .unwrap_or_default(),
+                notes: match (notes, &jmap_file) {
+                    (Some(path), _) => read_notes(&path),
+                    (None, Some(jmap_file)) => read_notes(&notes_path(jmap_file, None)),
+                    (None, None) => Notes::default(),
+                },
+                dedupe: !no_dedupe,
+                aggregate,
+            };
+            let jmap = match (&jmap_file, &raw_script) {
+                (_, Some(raw_script)) => load_raw_script(raw_script, symbols.as_deref()),
+                (Some(jmap_file), None) => load_jmap(jmap_file),
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+            // Build address index for resolving object and property references
+            let mut address_index = build_address_index(&jmap);
+            if reproducibility.normalize_addresses {
+                let scripts = parse_scripts(&jmap);
+                address_index = address_index.with_unknown_addresses(scripts.values().flatten());
+            }
+            let mut decompiler = given_decompiler(address_index).with_cfg_options(cfg_options);
+            if let Some(timeout) = limits.timeout_per_function {
+                decompiler = decompiler.with_timeout(timeout);
+            }
+            let result = run_disassemble(
+                &mut sink,
+                &decompiler,
+                filter,
+                &output,
+                &limits,
+                &reproducibility,
+            );
+            match (sink.finish(), &output_file) {
+                (Ok(()), Some(path)) => eprintln!("Written: {}", path.display()),
+                (Ok(()), None) => {}
+                // The pager was closed, or whatever read the output stopped
+                (Err(e), None) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                (Err(e), path) => {
+                    let target = path
+                        .as_ref()
+                        .map_or_else(|| "output".to_string(), |path| path.display().to_string());
+                    eprintln!("Error writing {}: {}", target, e);
+                    exit_after_run(2);
+                }
+            }
+            // Every function was attempted; the first failure decides the exit code
+            if let Err(FormatError::Kismet(error)) = result {
+                exit_after_run(error.exit_code());
+            }
+        }
+        Commands::Check {
+            jmap_file,
+            filter,
+            threads,
+        } => {
+            run_check(&jmap_file, filter.or(config.filter), threads);
+        }
+        Commands::Stats {
+            jmap_file,
+            filter,
+            output,
+            threads,
+            scaling,
+        } => {
+            let filter = filter.or(config.filter);
+            if scaling {
+                run_stats_scaling(&jmap_file, filter, threads);
+            } else {
+                run_stats(&jmap_file, filter, output, threads);
+            }
+        }
+        Commands::Export {
+            jmap_file,
+            output,
+            filter,
+            notes,
+        } => {
+            let notes = read_notes(&notes_path(&jmap_file, notes));
+            let output = output
+                .or(config.output_dir)
+                .unwrap_or_else(|| PathBuf::from("."));
+            run_export(&jmap_file, &output, filter.or(config.filter), &notes);
+        }
+        Commands::Names {
+            jmap_file,
+            filter,
+            find_name,
+        } => {
+            run_names(&jmap_file, filter.or(config.filter), find_name);
+        }
+        Commands::Query {
+            jmap_file,
+            query,
+            filter,
+        } => {
+            run_query(&jmap_file, &query, filter.or(config.filter));
+        }
+        Commands::RwMatrix {
+            jmap_file,
+            filter,
+            property,
+        } => {
+            run_rw_matrix(&jmap_file, filter.or(config.filter), property);
+        }
+        Commands::Layout { jmap_file, class } => {
+            run_layout(&jmap_file, &class);
+        }
+        Commands::Scan {
+            jmap_file,
+            rules,
+            filter,
+            json,
+        } => {
+            run_scan(&jmap_file, &rules, filter.or(config.filter), json);
+        }
+        Commands::Recursion { jmap_file, filter } => {
+            run_recursion(&jmap_file, filter.or(config.filter));
+        }
+        Commands::DeadFunctions { jmap_file, filter } => {
+            run_dead_functions(&jmap_file, filter.or(config.filter));
+        }
+        Commands::RpcAudit { jmap_file, filter } => {
+            run_rpc_audit(&jmap_file, filter.or(config.filter));
+        }
+        Commands::Ticks {
+            jmap_file,
+            filter,
+            frame_rate,
+        } => {
+            run_ticks(&jmap_file, filter.or(config.filter), frame_rate);
+        }
+        Commands::HotNatives {
+            jmap_file,
+            filter,
+            top,
+        } => {
+            run_hot_natives(&jmap_file, filter.or(config.filter), top);
+        }
+        Commands::DetectObfuscation {
+            jmap_file,
+            baseline,
+            filter,
+            recover,
+            max_attempts,
+            output,
+        } => {
+            run_detect_obfuscation(
+                &jmap_file,
+                baseline.as_deref(),
+                filter.or(config.filter),
+                recover,
+                max_attempts,
+                output,
+            );
+        }
+        Commands::ExtractScript {
+            jmap_file,
+            function,
+            output,
+            hex,
+        } => {
+            run_extract_script(&jmap_file, &function, output, hex);
+        }
+        Commands::Note {
+            jmap_file,
+            function,
+            text,
+            remove,
+            notes,
+        } => {
+            let path = notes_path(&jmap_file, notes);
+            run_note(&jmap_file, function.as_deref(), text, remove, &path);
+        }
+        Commands::Diff {
+            old_jmap,
+            new_jmap,
+            function,
+            level: DiffLevel::Bytecode,
+        } => {
+            run_bytecode_diff(&old_jmap, &new_jmap, &function);
+        }
+        Commands::Difftest {
+            jmap_file,
+            reference,
+            filter,
+        } => {
+            run_difftest(&jmap_file, &reference, filter.or(config.filter));
+        }
+        Commands::List {
+            jmap_file,
+            prefix,
+            classes,
+            functions,
+            properties,
+        } => {
+            run_list(&jmap_file, &prefix, classes, functions, properties);
+        }
+        Commands::Repl {
+            jmap_file,
+            session,
+            save_session,
+        } => {
+            run_repl(
+                jmap_file.as_deref(),
+                session.as_deref(),
+                save_session.as_deref(),
+            );
+        }
+        Commands::Completions { shell } => {
+            // Complete the binary as installed rather than the display name
+            clap_complete::generate(
+                shell,
+                &mut Args::command(),
+                env!("CARGO_BIN_NAME"),
+                &mut std::io::stdout(),
+            );
+        }
+        Commands::Manpage => {
+            if let Err(e) = clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout()) {
+                eprintln!("Error writing man page: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    write_profile();
+}
+
+/// Record every span from now on into a Chrome trace at `path`, for
+/// chrome://tracing or Perfetto to show as a timeline per thread
+fn start_profile(path: &Path) {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let file = fs::File::create(path).unwrap_or_else(|source| {
+        exit_with(KismetError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    });
+    let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+        .writer(io::BufWriter::new(file))
+        .include_args(true)
+        .build();
+    let subscriber = tracing_subscriber::registry().with(layer);
+    tracing::subscriber::set_global_default(subscriber).expect("set once at startup");
+    *PROFILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(guard);
+}
+
+/// Finish the trace `--profile-output` is recording, if it is
+fn write_profile() {
+    drop(PROFILE.lock().unwrap_or_else(|e| e.into_inner()).take());
+}
+
+/// Exit once a command has finished its work, keeping its profile
+fn exit_after_run(code: i32) -> ! {
+    write_profile();
+    std::process::exit(code);
+}
+
+/// Where `disassemble` writes: stdout, `$PAGER`, a file in `--output-dir`,
+/// or stdout as lines of JSON tokens for `-o tokens`
+struct OutputSink {
+    writer: Box<dyn io::Write>,
+    /// Write each line as a JSON array of its tokens
+    tokens: bool,
+    /// The end of the output not yet written as a line of tokens
+    line: String,
+    /// The pager reading `writer`, waited for once the output is done
+    pager: Option<std::process::Child>,
+    /// The write that failed; nothing is written after it
+    error: Option<io::Error>,
+}
+
+impl OutputSink {
+    fn new(writer: Box<dyn io::Write>) -> Self {
+        Self {
+            writer,
+            tokens: false,
+            line: String::new(),
+            pager: None,
+            error: None,
+        }
+    }
+
+    fn stdout() -> Self {
+        Self::new(Box::new(io::stdout()))
+    }
+
+    /// A file at `path`, with its directory created if need be
+    fn file(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::File::create(path)?;
+        Ok(Self::new(Box::new(io::BufWriter::new(file))))
+    }
+
+    /// `$PAGER`, or `less -R` if it isn't set; `None` if it couldn't be
+    /// started and output should go straight to the terminal
+    fn pager() -> Option<Self> {
+        let pager = std::env::var("PAGER")
+            .ok()
+            .filter(|pager| !pager.trim().is_empty())
+            .unwrap_or_else(|| "less -R".to_string());
+        let mut process = Command::new("sh")
+            .arg("-c")
+            .arg(&pager)
+            .stdin(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let stdin = process.stdin.take()?;
+        Some(Self {
+            pager: Some(process),
+            ..Self::new(Box::new(io::BufWriter::new(stdin)))
+        })
+    }
+
+    /// Write each line as the JSON array of its [`theme::tokens`] instead
+    fn as_tokens(mut self) -> Self {
+        self.tokens = true;
+        self
+    }
+
+    fn write_lines(&mut self, text: &str) -> io::Result<()> {
+        if !self.tokens {
+            return self.writer.write_all(text.as_bytes());
+        }
+        self.line.push_str(text);
+        while let Some(end) = self.line.find('\n') {
+            let rest = self.line.split_off(end + 1);
+            let line = std::mem::replace(&mut self.line, rest);
+            self.write_tokens(line.trim_end_matches('\n'))?;
+        }
+        Ok(())
+    }
+
+    fn write_tokens(&mut self, line: &str) -> io::Result<()> {
+        let json = serde_json::to_string(&theme::tokens(line)).expect("tokens are plain strings");
+        writeln!(self.writer, "{}", json)
+    }
+
+    /// Write what's left and wait for the pager to close; returns the first
+    /// write that failed
+    fn finish(mut self) -> io::Result<()> {
+        if self.error.is_none() && !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.error = self.write_tokens(&line).err();
+        }
+        if self.error.is_none() {
+            self.error = self.writer.flush().err();
+        }
+        // The pager reads until its input closes
+        drop(self.writer);
+        if let Some(mut pager) = self.pager {
+            let _ = pager.wait();
+        }
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+impl fmt::Write for OutputSink {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        if self.error.is_some() {
+            return Err(fmt::Error);
+        }
+        self.write_lines(text).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+/// Print an error and exit with its exit code
+fn exit_with(error: KismetError) -> ! {
+    eprintln!("Error: {}", error);
+    std::process::exit(error.exit_code());
+}
 
 fn load_jmap(jmap_file: &str) -> jmap::Jmap {
+    load_jmap_with_capabilities(jmap_file).0
+}
+
+/// Load a jmap, also returning which optional fields it has
+///
+/// Missing fields are reported as warnings here; commands that can't work
+/// without one check the returned capabilities.
+fn load_jmap_with_capabilities(jmap_file: &str) -> (jmap::Jmap, Capabilities) {
     eprintln!("Loading JMAP file: {}", jmap_file);
+    let (mut jmap, mut capabilities) =
+        loader::read_jmap(Path::new(jmap_file)).unwrap_or_else(|e| exit_with(e));
 
-    let jmap_data = match fs::read_to_string(jmap_file) {
-        Ok(data) => data,
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-            std::process::exit(1);
+    for merge_file in MERGE_FILES.get().into_iter().flatten() {
+        eprintln!("Merging JMAP file: {}", merge_file.display());
+        let (other, other_capabilities) =
+            loader::read_jmap(merge_file).unwrap_or_else(|e| exit_with(e));
+        let conflicts = loader::merge_jmap(&mut jmap, &mut capabilities, other, other_capabilities);
+        if !conflicts.is_empty() {
+            eprintln!(
+                "warning: {} names or objects conflict with ones already loaded, keeping those",
+                conflicts.len()
+            );
+            for conflict in conflicts.iter().take(MAX_REPORTED_COLLISIONS) {
+                eprintln!("    {}", conflict);
+            }
+            if conflicts.len() > MAX_REPORTED_COLLISIONS {
+                eprintln!(
+                    "    ... and {} more",
+                    conflicts.len() - MAX_REPORTED_COLLISIONS
+                );
+            }
         }
+    }
+
+    eprintln!("Loaded JMAP with {} objects", jmap.objects.len());
+    redact_scripts(&mut jmap);
+    for limitation in capabilities.limitations() {
+        eprintln!("warning: {}", limitation);
+    }
+    (jmap, capabilities)
+}
+
+fn load_raw_script(script: &Path, symbols: Option<&Path>) -> jmap::Jmap {
+    eprintln!("Loading raw script: {}", script.display());
+    let (mut jmap, capabilities) =
+        loader::read_raw_script(script, symbols).unwrap_or_else(|e| exit_with(e));
+    // Flags are never known for a raw script, so only missing names are worth a warning
+    if !capabilities.names {
+        eprintln!("warning: no name table given, names are shown as UnknownName_N");
+    }
+    redact_scripts(&mut jmap);
+    jmap
+}
+
+/// Mask the `--redact` secrets in every function's script
+fn redact_scripts(jmap: &mut jmap::Jmap) {
+    let Some(redactions) = REDACTIONS.get().and_then(Option::as_ref) else {
+        return;
     };
+    let found: usize = jmap
+        .objects
+        .values_mut()
+        .filter_map(|object| match object {
+            jmap::ObjectType::Function(func) => Some(redactions.apply(&mut func.r#struct.script)),
+            _ => None,
+        })
+        .sum();
+    eprintln!("Redacted {} strings", found);
+}
 
-    let jmap: jmap::Jmap = match serde_json::from_str(&jmap_data) {
-        Ok(jmap) => jmap,
-        Err(e) => {
-            eprintln!("Error parsing JMAP JSON: {}", e);
-            std::process::exit(1);
+/// Decode a function's script
+fn parse_script(jmap: &jmap::Jmap, script: &[u8]) -> Result<Vec<Expr>, KismetError> {
+    script_parser(jmap, script).parse_all()
+}
+
+/// A parser for a function's script, using the `--opcode-map` if one was given
+fn script_parser<'a>(jmap: &'a jmap::Jmap, script: &'a [u8]) -> ScriptParser<'a> {
+    // The loader fills in an empty name map if the dump has none
+    let names = jmap.names.as_ref().expect("name map is required");
+    let parser = ScriptParser::new(ScriptReader::new(script, names))
+        .with_engine_version(given_engine_version());
+    match given_opcode_map() {
+        Some(map) => parser.with_opcode_map(map),
+        None => parser,
+    }
+}
+
+/// A decompiler reading scripts as `--engine-version` and `--opcode-map` say,
+/// resolving references with `address_index`
+fn given_decompiler(address_index: AddressIndex<'_>) -> Decompiler<'_> {
+    let decompiler =
+        Decompiler::from_address_index(address_index).with_engine_version(given_engine_version());
+    match given_opcode_map() {
+        Some(map) => decompiler.with_opcode_map(map),
+        None => decompiler,
+    }
+}
+
+fn given_opcode_map() -> Option<&'static OpcodeMap> {
+    OPCODE_MAP.get().and_then(Option::as_ref)
+}
+
+fn given_engine_version() -> EngineVersion {
+    ENGINE_VERSION.get().copied().unwrap_or_default()
+}
+
+/// Number of colliding addresses listed individually before summarizing
+const MAX_REPORTED_COLLISIONS: usize = 5;
+
+fn build_address_index(jmap: &jmap::Jmap) -> AddressIndex<'_> {
+    let renames = RENAMES.get().cloned().unwrap_or_default();
+    let address_index = AddressIndex::new(jmap).with_renames(renames);
+    eprintln!(
+        "Built address index with {} entries",
+        address_index.object_index.len() + address_index.property_index.len()
+    );
+
+    let collisions: Vec<_> = address_index.object_collisions().collect();
+    if !collisions.is_empty() {
+        eprintln!(
+            "warning: {} addresses are shared by multiple objects, using the preferred candidate",
+            collisions.len()
+        );
+        for (address, paths) in collisions.iter().take(MAX_REPORTED_COLLISIONS) {
+            eprintln!(
+                "    {}: {} (also {})",
+                address,
+                paths[0],
+                paths[1..].join(", ")
+            );
         }
-    };
+        if collisions.len() > MAX_REPORTED_COLLISIONS {
+            eprintln!(
+                "    ... and {} more",
+                collisions.len() - MAX_REPORTED_COLLISIONS
+            );
+        }
+    }
 
-    eprintln!("Loaded JMAP with {} objects", jmap.objects.len());
+    let property_collisions = address_index.property_collision_count();
+    if property_collisions > 0 {
+        eprintln!(
+            "warning: {} addresses are shared by multiple properties",
+            property_collisions
+        );
+    }
 
-    jmap
+    address_index
 }
 
 fn collect_function_stats(
+    decompiler: &Decompiler,
     name: &str,
-    script: &[u8],
-    jmap: &jmap::Jmap,
-    address_index: &AddressIndex,
+    func: &jmap::Function,
 ) -> FunctionStats {
+    let _span = tracing::info_span!("function", name = %name).entered();
+    let mut unused = 0;
     let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
-        let reader = ScriptReader::new(
-            script,
-            jmap.names.as_ref().expect("name map is required"),
-            address_index,
-        );
-        let mut parser = ScriptParser::new(reader);
-        let expressions = parser.parse_all();
+        let expressions = match decompiler.parse(name, &mut Vec::new()) {
+            Ok(expressions) => expressions,
+            Err(_) => return (false, 0, 0, false, "parse_error".to_string()),
+        };
+        unused = unused_locals(&func.r#struct.properties, &expressions).len();
 
         // Try to build CFG
-        let logger = NullLogger;
-        let cfg_result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
-            ControlFlowGraph::from_expressions_with_logger(&expressions, &logger)
-        }));
-
-        let cfg = match cfg_result {
-            Ok(cfg) => cfg,
+        let graph = panic::catch_unwind(|| decompiler.build_graph(&expressions));
+        let (cfg, _, loop_info) = match graph {
+            Ok(graph) => graph,
             Err(_) => return (false, 0, 0, false, "cfg_panic".to_string()),
         };
 
         let cfg_built = !cfg.blocks.is_empty();
         let num_blocks = cfg.blocks.len();
 
-        // Try to analyze loops and structure
-        let (num_loops, structure_succeeded, structure_error) = if cfg_built {
-            let dom_tree = DominatorTree::compute(&cfg);
-            let loop_info = LoopInfo::analyze(&cfg, &dom_tree);
-            let num_loops = loop_info.loops.len();
+        // Try to structure
+        let (num_loops, structure_succeeded, structure_error) = if cfg_built {
+            let num_loops = loop_info.loops.len();
+            match panic::catch_unwind(|| decompiler.structure(&cfg, &loop_info)) {
+                Ok(Ok(Some(_))) => (num_loops, true, String::new()),
+                Ok(_) => (num_loops, false, "structure_failed".to_string()),
+                Err(_) => (num_loops, false, "structure_panic".to_string()),
+            }
+        } else {
+            (0, false, "cfg_empty".to_string())
+        };
+
+        (
+            cfg_built,
+            num_blocks,
+            num_loops,
+            structure_succeeded,
+            structure_error,
+        )
+    }));
+
+    let (cfg_built, num_blocks, num_loops, structure_succeeded, structure_error) = match result {
+        Ok(stats) => stats,
+        Err(_) => (false, 0, 0, false, "parser_panic".to_string()),
+    };
+
+    FunctionStats {
+        name: name.to_string(),
+        script_size: func.r#struct.script.len(),
+        cfg_built,
+        num_blocks,
+        num_loops,
+        structure_succeeded,
+        structure_error,
+        unused_locals: unused,
+    }
+}
+
+fn generate_csv(stats: &[FunctionStats]) -> String {
+    let mut output = String::from(
+        "function_name,script_size,cfg_built,num_blocks,num_loops,structure_succeeded,structure_error,\
+         unused_locals\n",
+    );
+    for stat in stats {
+        output.push_str(&format!(
+            "\"{}\",{},{},{},{},{},\"{}\",{}\n",
+            stat.name.replace('\"', "\"\""),
+            stat.script_size,
+            stat.cfg_built,
+            stat.num_blocks,
+            stat.num_loops,
+            stat.structure_succeeded,
+            stat.structure_error,
+            stat.unused_locals
+        ));
+    }
+    output
+}
+
+/// Statistics for every function with a script, collected in parallel
+///
+/// Functions share the jmap read-only, so each worker parses and structures
+/// its own functions without locking.
+fn collect_all_stats(jmap: &jmap::Jmap, filter: Option<&str>) -> Vec<FunctionStats> {
+    // Set a custom panic hook to suppress panic messages during stats collection
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {
+        // Silently ignore panics - they're caught and reported in the CSV
+    }));
+
+    let functions: Vec<(&str, &jmap::Function)> = jmap
+        .objects
+        .iter()
+        .filter_map(|(name, obj)| match obj {
+            jmap::ObjectType::Function(func) => Some((name.as_str(), func)),
+            _ => None,
+        })
+        .filter(|(name, func)| {
+            !func.r#struct.script.is_empty() && filter.is_none_or(|filter| name.contains(filter))
+        })
+        .collect();
+    let decompiler = given_decompiler(AddressIndex::new(jmap));
+    let stats = functions
+        .par_iter()
+        .map(|&(name, func)| collect_function_stats(&decompiler, name, func))
+        .collect();
+
+    // Restore the default panic hook
+    panic::set_hook(default_hook);
+    stats
+}
+
+/// Thread pool with `threads` workers, or one per core
+fn stats_thread_pool(threads: Option<usize>) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .build()
+        .unwrap_or_else(|e| {
+            eprintln!("Error starting worker threads: {}", e);
+            std::process::exit(1);
+        })
+}
+
+fn run_stats(
+    jmap_file: &str,
+    filter: Option<String>,
+    output: Option<String>,
+    threads: Option<usize>,
+) {
+    let jmap = load_jmap(jmap_file);
+    let stats = stats_thread_pool(threads).install(|| collect_all_stats(&jmap, filter.as_deref()));
+
+    let csv_output = generate_csv(&stats);
+
+    // Write to file or stdout
+    if let Some(output_path) = output {
+        if let Err(e) = fs::write(&output_path, csv_output) {
+            eprintln!("Error writing CSV file: {}", e);
+            std::process::exit(1);
+        }
+        eprintln!("CSV written to: {}", output_path);
+        eprintln!("Processed {} functions", stats.len());
+    } else {
+        print!("{}", csv_output);
+        eprintln!("Processed {} functions", stats.len());
+    }
+    let with_unused = stats.iter().filter(|stat| stat.unused_locals > 0).count();
+    if with_unused > 0 {
+        eprintln!(
+            "{} unused locals in {} functions",
+            stats.iter().map(|stat| stat.unused_locals).sum::<usize>(),
+            with_unused
+        );
+    }
+
+    let names = interner_stats();
+    eprintln!(
+        "Interned {} distinct names ({} bytes) from {} references, saving ~{} KiB",
+        names.unique_names,
+        names.unique_bytes,
+        names.lookups,
+        names.bytes_saved() / 1024
+    );
+}
+
+/// What `check` found in one function
+struct CheckedFunction<'j> {
+    name: &'j str,
+    /// Inconsistencies the parser noticed, such as mismatched skip offsets
+    warnings: Vec<String>,
+    failure: Option<CheckFailure>,
+}
+
+/// The step of `check` that failed on a function, and why
+struct CheckFailure {
+    step: &'static str,
+    reason: String,
+    /// [`KismetError::exit_code`] of the error, or 101, Rust's status for a
+    /// panic, if the step panicked
+    exit_code: i32,
+}
+
+impl CheckFailure {
+    fn error(step: &'static str, error: &KismetError) -> Self {
+        Self {
+            step,
+            reason: error.to_string(),
+            exit_code: error.exit_code(),
+        }
+    }
+
+    fn panic(step: &'static str, payload: Box<dyn std::any::Any + Send>) -> Self {
+        Self {
+            step,
+            reason: panic_message(payload),
+            exit_code: 101,
+        }
+    }
+}
+
+/// Run every step of decompiling a function short of printing it
+fn check_function<'j>(decompiler: &Decompiler, name: &'j str) -> CheckedFunction<'j> {
+    let mut checked = CheckedFunction {
+        name,
+        warnings: Vec::new(),
+        failure: None,
+    };
+    let _span = tracing::info_span!("function", name = %name).entered();
+    let mut diagnostics = Vec::new();
+    let parsed = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        decompiler.parse(name, &mut diagnostics)
+    }));
+    checked.warnings = diagnostics.iter().map(ToString::to_string).collect();
+    let expressions = match parsed {
+        Ok(Ok(expressions)) => expressions,
+        Ok(Err(e)) => {
+            checked.failure = Some(CheckFailure::error("parsing", &e));
+            return checked;
+        }
+        Err(payload) => {
+            checked.failure = Some(CheckFailure::panic("parsing", payload));
+            return checked;
+        }
+    };
+
+    let graph = panic::catch_unwind(|| decompiler.build_graph(&expressions));
+    let (cfg, _, loop_info) = match graph {
+        Ok(graph) => graph,
+        Err(payload) => {
+            checked.failure = Some(CheckFailure::panic("building the CFG", payload));
+            return checked;
+        }
+    };
+
+    let structured = panic::catch_unwind(|| decompiler.structure(&cfg, &loop_info));
+    checked.failure = match structured {
+        Ok(Ok(Some(_))) => None,
+        Ok(Ok(None)) => Some(CheckFailure::error(
+            "structuring",
+            &KismetError::Unstructured,
+        )),
+        Ok(Err(e)) => Some(CheckFailure::error("structuring", &e)),
+        Err(payload) => Some(CheckFailure::panic("structuring", payload)),
+    };
+    checked
+}
+
+/// The message a caught panic was raised with
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => format!("panicked: {}", message),
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => format!("panicked: {}", message),
+            Err(_) => "panicked".to_string(),
+        },
+    }
+}
+
+fn run_check(jmap_file: &str, filter: Option<String>, threads: Option<usize>) {
+    let jmap = load_jmap(jmap_file);
+    let started = Instant::now();
+
+    let mut functions: Vec<(&str, &[u8])> = jmap
+        .objects
+        .iter()
+        .filter_map(|(name, obj)| match obj {
+            jmap::ObjectType::Function(func) => {
+                Some((name.as_str(), func.r#struct.script.as_slice()))
+            }
+            _ => None,
+        })
+        .filter(|(name, script)| {
+            !script.is_empty() && filter.as_deref().is_none_or(|filter| name.contains(filter))
+        })
+        .collect();
+    functions.sort_by_key(|&(name, _)| name);
+
+    // Panics are reported with the function they happened in
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let decompiler = given_decompiler(AddressIndex::new(&jmap));
+    let checked: Vec<CheckedFunction> = stats_thread_pool(threads).install(|| {
+        functions
+            .par_iter()
+            .map(|&(name, _)| check_function(&decompiler, name))
+            .collect()
+    });
+    panic::set_hook(default_hook);
+
+    let mut failures: BTreeMap<&str, Vec<(&str, &str)>> = BTreeMap::new();
+    for function in &checked {
+        if let Some(failure) = &function.failure {
+            let reason = failure.reason.as_str();
+            failures
+                .entry(failure.step)
+                .or_default()
+                .push((function.name, reason));
+        }
+    }
+    let warned: Vec<&CheckedFunction> = checked
+        .iter()
+        .filter(|function| !function.warnings.is_empty())
+        .collect();
+
+    for (step, functions) in &failures {
+        println!("Failed while {} ({}):", step, functions.len());
+        for (name, reason) in functions {
+            println!("  {}: {}", name, reason);
+        }
+        println!();
+    }
+    if !warned.is_empty() {
+        println!("Parser warnings:");
+        for function in &warned {
+            for warning in &function.warnings {
+                println!("  {}: {}", function.name, warning);
+            }
+        }
+        println!();
+    }
+
+    let failed: usize = failures.values().map(Vec::len).sum();
+    println!(
+        "Checked {} functions in {:.1?}: {} ok, {} failed, {} with parser warnings",
+        checked.len(),
+        started.elapsed(),
+        checked.len() - failed,
+        failed,
+        warned.len()
+    );
+    // Functions are checked in order, so this is the first by path
+    if let Some(failure) = checked
+        .iter()
+        .find_map(|function| function.failure.as_ref())
+    {
+        exit_after_run(failure.exit_code);
+    }
+}
+
+fn run_export(jmap_file: &str, output: &Path, filter: Option<String>, notes: &Notes) {
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+    let mut scripts = parse_scripts(&jmap);
+    if let Some(filter_str) = &filter {
+        scripts.retain(|name, _| name.contains(filter_str.as_str()));
+    }
+    let graph = build_call_graph(&jmap, &address_index, &scripts);
+
+    let mut callers: HashMap<&str, usize> = HashMap::new();
+    for sites in graph.calls.values() {
+        for site in sites.iter().filter(|site| site.kind == CallKind::Call) {
+            *callers.entry(site.callee.as_str()).or_default() += 1;
+        }
+    }
+
+    let mut functions = Vec::new();
+    for (name, expressions) in &scripts {
+        let Some(jmap::ObjectType::Function(func)) = jmap.objects.get(*name) else {
+            continue;
+        };
+        let (class, short_name) = name.rsplit_once(':').unwrap_or(("", name));
+        let mut cfg = ControlFlowGraph::from_expressions(expressions);
+        let (_, loop_info) = normalize_loops(&mut cfg);
+        let cost = FunctionCost::compute(&cfg, &loop_info);
+        functions.push(FunctionRow {
+            function: name,
+            class,
+            name: short_name,
+            script_size: func.r#struct.script.len(),
+            blocks: cfg.blocks.len(),
+            loops: loop_info.loops.len(),
+            max_loop_depth: cost.max_loop_depth,
+            calls: cost.calls,
+            callers: callers.get(name).copied().unwrap_or(0),
+            cost: cost.score,
+        });
+    }
+
+    let mut calls = Vec::new();
+    for (caller, sites) in &graph.calls {
+        for site in sites {
+            calls.push(CallRow {
+                caller,
+                callee: &site.callee,
+                offset: site.offset.as_usize(),
+                kind: match site.kind {
+                    CallKind::Call => "call",
+                    CallKind::DelegateBinding => "delegate_binding",
+                },
+            });
+        }
+    }
+
+    let xref_index = build_xref_index(&scripts);
+    let mut xrefs = Vec::new();
+    for (&address, uses) in &xref_index.uses {
+        let target = match address_index.resolve_object(Address::new(address)) {
+            Some(object) => object.path.to_string(),
+            None => address_index
+                .resolve_property(Address::new(address))
+                .map(|info| format!("{}::{}", info.owner.path, info.property.name))
+                .unwrap_or_default(),
+        };
+        xrefs.extend(uses.iter().map(|xref| XrefRow {
+            address,
+            target: target.clone(),
+            function: &xref.function,
+            offset: xref.offset.as_usize(),
+            kind: xref.kind.name(),
+        }));
+    }
+
+    if let Err(e) = fs::create_dir_all(output) {
+        exit_with(KismetError::Io {
+            path: output.to_path_buf(),
+            source: e,
+        });
+    }
+    let write = |file: &str, contents: Vec<u8>| {
+        let path = output.join(file);
+        if let Err(e) = fs::write(&path, contents) {
+            exit_with(KismetError::Io { path, source: e });
+        }
+        eprintln!("Written: {}", path.display());
+    };
+    write(
+        "functions.csv",
+        tables::functions_csv(&functions).into_bytes(),
+    );
+    write("calls.csv", tables::calls_csv(&calls).into_bytes());
+    write("xrefs.csv", tables::xrefs_csv(&xrefs).into_bytes());
+    #[cfg(feature = "parquet")]
+    for (file, table) in [
+        ("functions.parquet", tables::functions_parquet(&functions)),
+        ("xrefs.parquet", tables::xrefs_parquet(&xrefs)),
+    ] {
+        match table {
+            Ok(contents) => write(file, contents),
+            Err(e) => exit_with(KismetError::Io {
+                path: output.join(file),
+                source: std::io::Error::other(e),
+            }),
+        }
+    }
+
+    // The landing page covers functions that failed to parse too
+    let functions: Vec<(FunctionStats, &[u8])> = collect_all_stats(&jmap, filter.as_deref())
+        .into_iter()
+        .filter_map(|stats| match jmap.objects.get(&stats.name) {
+            Some(jmap::ObjectType::Function(func)) => {
+                Some((stats, func.r#struct.script.as_slice()))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut function_notes: HashMap<&str, &str> = HashMap::new();
+    if !notes.is_empty() {
+        let mut out_of_date = 0;
+        for (name, expressions) in &scripts {
+            let Some(jmap::ObjectType::Function(func)) = jmap.objects.get(*name) else {
+                continue;
+            };
+            let print = notes::fingerprint(name, &func.r#struct.script, expressions);
+            if let Some(note) = notes.get(&print) {
+                function_notes.insert(name, note.text.as_str());
+            } else if notes.out_of_date(name, &print).is_some() {
+                out_of_date += 1;
+            }
+        }
+        if out_of_date > 0 {
+            eprintln!(
+                "Left out {} notes on functions that changed since",
+                out_of_date
+            );
+        }
+    }
+    let path = output.join("index.html");
+    let page = index_page::render(jmap_file, &functions, &function_notes);
+    if let Err(e) = fs::write(&path, page) {
+        exit_with(KismetError::Io { path, source: e });
+    }
+    eprintln!("Written: {}", path.display());
+    eprintln!("Exported {} functions", scripts.len());
+}
+
+/// Time collecting statistics with a growing number of threads
+fn run_stats_scaling(jmap_file: &str, filter: Option<String>, max_threads: Option<usize>) {
+    let jmap = load_jmap(jmap_file);
+    let max_threads = max_threads
+        .or_else(|| std::thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1)
+        .max(1);
+    let mut thread_counts: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2))
+        .take_while(|&n| n < max_threads)
+        .collect();
+    thread_counts.push(max_threads);
+
+    // Warm up the name table and allocator so the first run isn't penalized
+    let functions = collect_all_stats(&jmap, filter.as_deref()).len();
+    println!("Collecting statistics for {} functions", functions);
+    println!(
+        "{:>8} {:>12} {:>8} {:>11}",
+        "threads", "time", "speedup", "efficiency"
+    );
+
+    let mut baseline = None;
+    for threads in thread_counts {
+        let pool = stats_thread_pool(Some(threads));
+        let started = Instant::now();
+        pool.install(|| collect_all_stats(&jmap, filter.as_deref()));
+        let elapsed = started.elapsed();
+
+        let baseline = *baseline.get_or_insert(elapsed);
+        let speedup = baseline.as_secs_f64() / elapsed.as_secs_f64();
+        println!(
+            "{:>8} {:>12.1?} {:>7.2}x {:>10.0}%",
+            threads,
+            elapsed,
+            speedup,
+            100.0 * speedup / threads as f64
+        );
+    }
+}
+
+fn run_names(jmap_file: &str, filter: Option<String>, find_name: Option<String>) {
+    let jmap = load_jmap(jmap_file);
+
+    let mut index = NameIndex::new();
+    let mut skipped = 0;
+
+    for (name, obj) in &jmap.objects {
+        if let jmap::ObjectType::Function(func) = obj {
+            if let Some(ref filter_str) = filter
+                && !name.contains(filter_str)
+            {
+                continue;
+            }
+
+            let script = &func.r#struct.script;
+            if script.is_empty() {
+                continue;
+            }
+
+            match parse_script(&jmap, script) {
+                Ok(expressions) => index.add_function(name, &expressions),
+                // Functions that fail to parse are skipped rather than aborting the index
+                Err(_) => skipped += 1,
+            }
+        }
+    }
+
+    if skipped > 0 {
+        eprintln!("Skipped {} functions that failed to parse", skipped);
+    }
+
+    let matches: Vec<_> = match &find_name {
+        Some(query) => index.find(query),
+        None => index
+            .entries
+            .iter()
+            .map(|(name, uses)| (name.as_str(), uses.as_slice()))
+            .collect(),
+    };
+
+    if matches.is_empty() {
+        if let Some(query) = &find_name {
+            println!("No uses of name \"{}\" found", query);
+        }
+        return;
+    }
+
+    for (name, uses) in &matches {
+        println!("\"{}\" ({} references)", name, uses.len());
+        for name_use in *uses {
+            println!("    {} @ {}", name_use.function, name_use.offset);
+        }
+    }
+
+    eprintln!(
+        "{} distinct names, {} references",
+        index.entries.len(),
+        index.total_uses()
+    );
+}
+
+/// Parse the script of every function that has one
+///
+/// Returns the expressions keyed by function path. Functions that fail to parse
+/// are left out and counted in a message on stderr.
+fn parse_scripts(jmap: &jmap::Jmap) -> BTreeMap<&str, Vec<Expr>> {
+    let mut scripts = BTreeMap::new();
+    let mut skipped = 0;
+
+    for (name, obj) in &jmap.objects {
+        if let jmap::ObjectType::Function(func) = obj {
+            let script = &func.r#struct.script;
+            if script.is_empty() {
+                continue;
+            }
+
+            match parse_script(jmap, script) {
+                Ok(expressions) => {
+                    scripts.insert(name.as_str(), expressions);
+                }
+                Err(_) => skipped += 1,
+            }
+        }
+    }
+
+    if skipped > 0 {
+        eprintln!("Skipped {} functions that failed to parse", skipped);
+    }
+    scripts
+}
+
+/// Path of the function a call in `caller` refers to, if it's in the jmap
+///
+/// Virtual calls by name are resolved against the caller's own class, ignoring
+/// case like the engine does when the name's casing differs from the dump's.
+fn resolve_callee(
+    jmap: &jmap::Jmap,
+    address_index: &AddressIndex,
+    caller: &str,
+    func: &FunctionRef,
+) -> Option<String> {
+    match func {
+        FunctionRef::ByAddress(address) => address_index
+            .resolve_object(*address)
+            .filter(|info| matches!(info.object, jmap::ObjectType::Function(_)))
+            .map(|info| info.path.to_string()),
+        FunctionRef::ByName(callee) => {
+            let class = caller.rsplit_once(':').map_or(caller, |(class, _)| class);
+            let path = format!("{}:{}", class, callee.as_str());
+            if matches!(jmap.objects.get(&path), Some(jmap::ObjectType::Function(_))) {
+                return Some(path);
+            }
+            let prefix = format!("{}:", class);
+            jmap.objects
+                .range(prefix.clone()..)
+                .take_while(|(path, _)| path.starts_with(&prefix))
+                .find(|(path, object)| {
+                    matches!(object, jmap::ObjectType::Function(_))
+                        && callee.matches(&path[prefix.len()..])
+                })
+                .map(|(path, _)| path.clone())
+        }
+    }
+}
+
+/// Build the call graph over every parsed function
+fn build_call_graph(
+    jmap: &jmap::Jmap,
+    address_index: &AddressIndex,
+    scripts: &BTreeMap<&str, Vec<Expr>>,
+) -> CallGraph {
+    let mut graph = CallGraph::new();
+    for (name, expressions) in scripts {
+        graph.add_function(name, expressions, |func| {
+            resolve_callee(jmap, address_index, name, func)
+        });
+    }
+    graph
+}
+
+fn build_xref_index(scripts: &BTreeMap<&str, Vec<Expr>>) -> XrefIndex {
+    let mut index = XrefIndex::default();
+    for (name, expressions) in scripts {
+        index.add_function(name, expressions);
+    }
+    index
+}
+
+/// Lookups a query needs while running over one function
+struct FunctionQueryEnv<'a> {
+    jmap: &'a jmap::Jmap,
+    address_index: &'a AddressIndex<'a>,
+    function: &'a str,
+    /// Offsets of the function's top-level statements, in order
+    statement_offsets: Vec<BytecodeOffset>,
+    /// Offsets of the top-level statements in loop bodies
+    loop_statements: HashSet<BytecodeOffset>,
+}
+
+impl<'a> FunctionQueryEnv<'a> {
+    fn new(
+        jmap: &'a jmap::Jmap,
+        address_index: &'a AddressIndex<'a>,
+        function: &'a str,
+        expressions: &[Expr],
+        with_loops: bool,
+    ) -> Self {
+        let mut loop_statements = HashSet::new();
+        if with_loops {
+            let cfg = ControlFlowGraph::from_expressions(expressions);
+            let dom_tree = DominatorTree::compute(&cfg);
+            let loop_info = LoopInfo::analyze(&cfg, &dom_tree);
+            for lp in &loop_info.loops {
+                for block_id in &lp.blocks {
+                    let block = &cfg.blocks[block_id.0];
+                    loop_statements.extend(block.statements.iter().map(|stmt| stmt.offset));
+                    loop_statements.extend(block.terminator_expr.iter().map(|expr| expr.offset));
+                }
+            }
+        }
+
+        Self {
+            jmap,
+            address_index,
+            function,
+            statement_offsets: expressions.iter().map(|expr| expr.offset).collect(),
+            loop_statements,
+        }
+    }
+}
+
+impl QueryEnv for FunctionQueryEnv<'_> {
+    fn callee(&self, func: &FunctionRef) -> Option<String> {
+        resolve_callee(self.jmap, self.address_index, self.function, func)
+    }
+
+    fn property_name(&self, property: PropertyRef) -> Option<String> {
+        self.address_index
+            .resolve_property(property.address)
+            .map(|info| info.property.name.clone())
+    }
+
+    fn in_loop(&self, offset: BytecodeOffset) -> bool {
+        // Nested expressions come after the statement holding them
+        let index = self
+            .statement_offsets
+            .partition_point(|&start| start <= offset);
+        index > 0
+            && self
+                .loop_statements
+                .contains(&self.statement_offsets[index - 1])
+    }
+}
+
+/// Functions matching each of several queries
+struct QueryResults<'s> {
+    /// For each query, the matching functions and the offsets of what matched
+    matches: Vec<Vec<(&'s str, Vec<BytecodeOffset>)>>,
+    /// Number of functions the queries ran over
+    searched: usize,
+    /// Number of functions skipped because building their CFG failed
+    failed: usize,
+}
+
+/// Run queries over every parsed function, building each function's loop
+/// info once if any query needs it
+///
+/// A query's matches are limited to functions containing its filter, if any.
+fn run_queries<'s>(
+    jmap: &jmap::Jmap,
+    address_index: &AddressIndex,
+    scripts: &BTreeMap<&'s str, Vec<Expr>>,
+    queries: &[(&Query, Option<&str>)],
+) -> QueryResults<'s> {
+    let with_loops = queries.iter().any(|(query, _)| query.uses_loops());
+    let mut results = QueryResults {
+        matches: vec![Vec::new(); queries.len()],
+        searched: 0,
+        failed: 0,
+    };
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    for (&name, expressions) in scripts {
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let env = FunctionQueryEnv::new(jmap, address_index, name, expressions, with_loops);
+            queries
+                .iter()
+                .map(|(query, filter)| {
+                    if filter.is_some_and(|filter_str| !name.contains(filter_str)) {
+                        return None;
+                    }
+                    query.evaluate(expressions, &env)
+                })
+                .collect::<Vec<_>>()
+        }));
+        results.searched += 1;
+        let Ok(found) = result else {
+            results.failed += 1;
+            continue;
+        };
+        for (matches, offsets) in results.matches.iter_mut().zip(found) {
+            if let Some(offsets) = offsets {
+                matches.push((name, offsets));
+            }
+        }
+    }
+
+    panic::set_hook(default_hook);
+    if results.failed > 0 {
+        eprintln!(
+            "Skipped {} functions whose control flow couldn't be analyzed",
+            results.failed
+        );
+    }
+    results
+}
+
+/// `path @ 0x10, 0x2A`, or just the path if nothing in particular matched
+fn format_query_match(name: &str, offsets: &[BytecodeOffset]) -> String {
+    if offsets.is_empty() {
+        return name.to_string();
+    }
+    let offsets: Vec<String> = offsets.iter().map(|offset| offset.to_string()).collect();
+    format!("{} @ {}", name, offsets.join(", "))
+}
+
+fn run_query(jmap_file: &str, query_text: &str, filter: Option<String>) {
+    let query = match Query::parse(query_text) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("Error in query: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+    let mut scripts = parse_scripts(&jmap);
+    if let Some(ref filter_str) = filter {
+        scripts.retain(|name, _| name.contains(filter_str.as_str()));
+    }
+
+    let results = run_queries(&jmap, &address_index, &scripts, &[(&query, None)]);
+    for (name, offsets) in &results.matches[0] {
+        println!("{}", format_query_match(name, offsets));
+    }
+    eprintln!(
+        "{} of {} functions match",
+        results.matches[0].len(),
+        results.searched
+    );
+}
+
+#[derive(Serialize)]
+struct ScanReport<'a> {
+    rule: &'a str,
+    description: Option<&'a str>,
+    matches: Vec<ScanMatch<'a>>,
+}
+
+#[derive(Serialize)]
+struct ScanMatch<'a> {
+    function: &'a str,
+    offsets: Vec<String>,
+}
+
+fn run_scan(jmap_file: &str, rules_file: &Path, filter: Option<String>, json: bool) {
+    let rules = match rules::load_rules(rules_file) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("Error in rules: {}", e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Loaded {} rules from {}", rules.len(), rules_file.display());
+
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+    let mut scripts = parse_scripts(&jmap);
+    if let Some(ref filter_str) = filter {
+        scripts.retain(|name, _| name.contains(filter_str.as_str()));
+    }
+
+    let queries: Vec<_> = rules
+        .iter()
+        .map(|rule| (&rule.query, rule.filter.as_deref()))
+        .collect();
+    let results = run_queries(&jmap, &address_index, &scripts, &queries);
+
+    if json {
+        let reports: Vec<ScanReport> = rules
+            .iter()
+            .zip(&results.matches)
+            .map(|(rule, matches)| ScanReport {
+                rule: &rule.name,
+                description: rule.description.as_deref(),
+                matches: matches
+                    .iter()
+                    .map(|(name, offsets)| ScanMatch {
+                        function: name,
+                        offsets: offsets.iter().map(|offset| offset.to_string()).collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        match serde_json::to_string_pretty(&reports) {
+            Ok(text) => println!("{}", text),
+            Err(e) => {
+                eprintln!("Error writing report: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        for (rule, matches) in rules.iter().zip(&results.matches) {
+            match &rule.description {
+                Some(description) => println!("Rule {}: {}", rule.name, description),
+                None => println!("Rule {}", rule.name),
+            }
+            if matches.is_empty() {
+                println!("    no matches");
+            }
+            for (name, offsets) in matches {
+                println!("    {}", format_query_match(name, offsets));
+            }
+            println!();
+        }
+    }
+
+    let hit_rules = results.matches.iter().filter(|m| !m.is_empty()).count();
+    eprintln!(
+        "{} of {} rules matched across {} functions",
+        hit_rules,
+        rules.len(),
+        results.searched
+    );
+}
+
+fn run_recursion(jmap_file: &str, filter: Option<String>) {
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+
+    let scripts = parse_scripts(&jmap);
+    let graph = build_call_graph(&jmap, &address_index, &scripts);
+
+    let cycles: Vec<_> = graph
+        .recursion_cycles()
+        .into_iter()
+        .filter(|cycle| {
+            filter
+                .as_ref()
+                .is_none_or(|filter_str| cycle.members.iter().any(|m| m.contains(filter_str)))
+        })
+        .collect();
+
+    if cycles.is_empty() {
+        println!("No recursive functions found");
+        return;
+    }
+
+    for cycle in &cycles {
+        if cycle.is_direct() {
+            println!("Direct recursion: {}", cycle.members[0]);
+        } else {
+            println!(
+                "Mutual recursion between {} functions:",
+                cycle.members.len()
+            );
+            for member in &cycle.members {
+                println!("    {}", member);
+            }
+        }
+        println!("  Call sites:");
+        for (caller, site) in &cycle.call_sites {
+            println!("    {} @ {} -> {}", caller, site.offset, site.callee);
+        }
+        println!();
+    }
+
+    let direct = cycles.iter().filter(|cycle| cycle.is_direct()).count();
+    eprintln!(
+        "{} recursive cycles ({} direct, {} mutual)",
+        cycles.len(),
+        direct,
+        cycles.len() - direct
+    );
+}
+
+fn run_dead_functions(jmap_file: &str, filter: Option<String>) {
+    use bytecode::flags::{FUNC_BLUEPRINT_EVENT, FUNC_DELEGATE, FUNC_EVENT, FUNC_EXEC, FUNC_NET};
+    // Functions with these flags are entry points the engine calls itself
+    const ENTRY_POINT_FLAGS: u32 =
+        FUNC_NET | FUNC_EXEC | FUNC_EVENT | FUNC_DELEGATE | FUNC_BLUEPRINT_EVENT;
+
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+
+    let scripts = parse_scripts(&jmap);
+    let graph = build_call_graph(&jmap, &address_index, &scripts);
+
+    let dead: Vec<_> = graph
+        .unreferenced()
+        .into_iter()
+        .filter(|path| {
+            filter
+                .as_ref()
+                .is_none_or(|filter_str| path.contains(filter_str.as_str()))
+        })
+        .filter(|path| match jmap.objects.get(*path) {
+            Some(jmap::ObjectType::Function(func)) => {
+                func.function_flags.bits() & ENTRY_POINT_FLAGS == 0
+            }
+            _ => false,
+        })
+        .collect();
+
+    for path in &dead {
+        println!("{}", path);
+    }
+
+    eprintln!(
+        "{} of {} script functions are never called or bound from a script",
+        dead.len(),
+        graph.calls.len()
+    );
+}
+
+fn run_hot_natives(jmap_file: &str, filter: Option<String>, top: usize) {
+    // Callers listed under each engine function
+    const SHOWN_CALLERS: usize = 5;
+
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+    let mut scripts = parse_scripts(&jmap);
+    if let Some(filter_str) = &filter {
+        scripts.retain(|name, _| name.contains(filter_str.as_str()));
+    }
+    let graph = build_call_graph(&jmap, &address_index, &scripts);
+
+    // Anything without a script in the dump is implemented by the engine
+    let is_native = |path: &str| match jmap.objects.get(path) {
+        Some(jmap::ObjectType::Function(func)) => func.r#struct.script.is_empty(),
+        _ => true,
+    };
+    let mut natives: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+    for (caller, sites) in &graph.calls {
+        for site in sites {
+            if site.kind == CallKind::Call && is_native(&site.callee) {
+                *natives
+                    .entry(site.callee.as_str())
+                    .or_default()
+                    .entry(caller.as_str())
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    let total = |callers: &HashMap<&str, usize>| callers.values().sum::<usize>();
+    let mut ranked: Vec<(&str, HashMap<&str, usize>)> = natives.into_iter().collect();
+    ranked.sort_by(|(a, a_callers), (b, b_callers)| {
+        total(b_callers)
+            .cmp(&total(a_callers))
+            .then(b_callers.len().cmp(&a_callers.len()))
+            .then(a.cmp(b))
+    });
+
+    if ranked.is_empty() {
+        println!("No calls to engine functions found");
+        return;
+    }
+    println!("{:>7}  {:>7}  Engine function", "Calls", "Callers");
+    for (native, callers) in ranked.iter().take(top) {
+        println!("{:>7}  {:>7}  {}", total(callers), callers.len(), native);
+        let mut callers: Vec<(&str, usize)> = callers.iter().map(|(&c, &n)| (c, n)).collect();
+        callers.sort_by(|(a, a_calls), (b, b_calls)| b_calls.cmp(a_calls).then(a.cmp(b)));
+        for (caller, calls) in callers.iter().take(SHOWN_CALLERS) {
+            println!("{:>18}{} ({})", "", caller, calls);
+        }
+        if callers.len() > SHOWN_CALLERS {
+            println!("{:>18}and {} more", "", callers.len() - SHOWN_CALLERS);
+        }
+    }
+
+    eprintln!(
+        "{} engine functions called from {} script functions",
+        ranked.len(),
+        graph.calls.len()
+    );
+}
+
+/// Whether a program can be found on PATH, or at the path given
+fn program_exists(program: &str) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+fn run_difftest(jmap_file: &str, reference: &str, filter: Option<String>) {
+    let program = reference.split_whitespace().next().unwrap_or_default();
+    if !program_exists(program) {
+        eprintln!(
+            "Reference decompiler '{}' not found, nothing to compare with",
+            program
+        );
+        return;
+    }
+
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+    let mut scripts = parse_scripts(&jmap);
+    if let Some(filter_str) = &filter {
+        scripts.retain(|name, _| name.contains(filter_str.as_str()));
+    }
+    let graph = build_call_graph(&jmap, &address_index, &scripts);
+
+    // The reference may print calls to any function, not just script ones
+    let short_name = |path: &str| path.rsplit(':').next().unwrap_or(path).to_string();
+    let function_names: HashSet<String> = jmap
+        .objects
+        .iter()
+        .filter(|(_, obj)| matches!(obj, jmap::ObjectType::Function(_)))
+        .map(|(path, _)| short_name(path))
+        .collect();
+    let function_names: HashSet<&str> = function_names.iter().map(String::as_str).collect();
+
+    let script_path =
+        std::env::temp_dir().join(format!("jmap-kismet-difftest-{}.bin", std::process::id()));
+    let (mut diverged, mut failed) = (0, 0);
+    for name in scripts.keys() {
+        let Some(jmap::ObjectType::Function(func)) = jmap.objects.get(*name) else {
+            continue;
+        };
+        if let Err(e) = fs::write(&script_path, &func.r#struct.script) {
+            exit_with(KismetError::Io {
+                path: script_path,
+                source: e,
+            });
+        }
+        let command =
+            difftest::reference_command(reference, jmap_file, name, &script_path.to_string_lossy());
+        let output = match Command::new("sh").arg("-c").arg(&command).output() {
+            Ok(output) if output.status.success() => output,
+            Ok(output) => {
+                println!("{}: reference failed with {}", name, output.status);
+                failed += 1;
+                continue;
+            }
+            Err(e) => {
+                println!("{}: couldn't run reference: {}", name, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let ours: Vec<String> = graph
+            .calls
+            .get(*name)
+            .into_iter()
+            .flatten()
+            .filter(|site| site.kind == CallKind::Call)
+            .map(|site| short_name(&site.callee))
+            .collect();
+        let theirs =
+            difftest::called_names(&String::from_utf8_lossy(&output.stdout), &function_names);
+        let divergences = difftest::compare_calls(&ours, &theirs);
+        if !divergences.is_empty() {
+            println!("{}:", name);
+            for divergence in &divergences {
+                println!("    {}", divergence);
+            }
+            diverged += 1;
+        }
+    }
+    let _ = fs::remove_file(&script_path);
+
+    eprintln!(
+        "{} of {} functions diverge from the reference",
+        diverged,
+        scripts.len()
+    );
+    if failed > 0 {
+        eprintln!("The reference failed on {} functions", failed);
+    }
+    if diverged > 0 {
+        exit_after_run(1);
+    }
+}
+
+/// Where the notes on a dump are kept: the file given, or the one beside it
+fn notes_path(jmap_file: &str, notes_file: Option<PathBuf>) -> PathBuf {
+    notes_file.unwrap_or_else(|| notes::default_notes_path(Path::new(jmap_file)))
+}
+
+fn read_notes(path: &Path) -> Notes {
+    notes::load_notes(path).unwrap_or_else(|e| {
+        eprintln!("Error loading notes: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Fingerprint of a function's current code, or `None` if it doesn't parse
+fn function_fingerprint(jmap: &jmap::Jmap, name: &str, func: &jmap::Function) -> Option<String> {
+    let script = &func.r#struct.script;
+    let expressions = parse_script(jmap, script).ok()?;
+    Some(notes::fingerprint(name, script, &expressions))
+}
+
+/// Write the note on a function under its header, marking one written on an
+/// earlier version of its code
+fn write_note(
+    out: &mut dyn fmt::Write,
+    jmap: &jmap::Jmap,
+    name: &str,
+    func: &jmap::Function,
+    notes: &Notes,
+) -> fmt::Result {
+    let Some(print) = function_fingerprint(jmap, name, func) else {
+        return Ok(());
+    };
+    let (heading, note) = match (notes.get(&print), notes.out_of_date(name, &print)) {
+        (Some(note), _) => ("// Note:", note),
+        (None, Some(note)) => ("// Note, on an earlier version of this function:", note),
+        (None, None) => return Ok(()),
+    };
+    writeln!(out, "{}", formatters::theme::Theme::comment(heading))?;
+    for line in note.text.lines() {
+        writeln!(
+            out,
+            "{}",
+            formatters::theme::Theme::comment(format!("//   {}", line))
+        )?;
+    }
+    writeln!(out)
+}
+
+fn run_note(
+    jmap_file: &str,
+    function: Option<&str>,
+    text: Option<String>,
+    remove: bool,
+    path: &Path,
+) {
+    let mut notes = read_notes(path);
+    let jmap = load_jmap(jmap_file);
+    let Some(function) = function else {
+        for note in notes.iter() {
+            let status = match jmap.objects.get(&note.function) {
+                Some(jmap::ObjectType::Function(func)) => {
+                    match function_fingerprint(&jmap, &note.function, func) {
+                        Some(print) if print == note.fingerprint => "",
+                        _ => " (out of date)",
+                    }
+                }
+                _ => " (not in this dump)",
+            };
+            println!("{}{}", note.function, status);
+            for line in note.text.lines() {
+                println!("    {}", line);
+            }
+        }
+        eprintln!("{} notes in {}", notes.iter().count(), path.display());
+        return;
+    };
+
+    // A note stays removable after its function is gone from the dump
+    if remove && notes.remove(function) {
+        return save_notes_or_exit(path, &notes);
+    }
+    let (name, func) = find_function(&jmap, function);
+    if remove {
+        if !notes.remove(name) {
+            eprintln!("Error: {} has no note", name);
+            std::process::exit(1);
+        }
+        return save_notes_or_exit(path, &notes);
+    }
+    let print = match parse_script(&jmap, &func.r#struct.script) {
+        Ok(expressions) => notes::fingerprint(name, &func.r#struct.script, &expressions),
+        Err(e) => exit_with(e),
+    };
+    match text {
+        Some(text) => {
+            let text = if text == "-" {
+                let mut text = String::new();
+                if let Err(e) = std::io::Read::read_to_string(&mut std::io::stdin(), &mut text) {
+                    eprintln!("Error reading note from stdin: {}", e);
+                    std::process::exit(1);
+                }
+                text
+            } else {
+                text
+            };
+            notes.set(name, &print, text.trim_end().to_string());
+            save_notes_or_exit(path, &notes);
+        }
+        None => match (notes.get(&print), notes.out_of_date(name, &print)) {
+            (Some(note), _) => println!("{}", note.text),
+            (None, Some(note)) => {
+                eprintln!(
+                    "warning: the note was written on an earlier version of {}",
+                    name
+                );
+                println!("{}", note.text);
+            }
+            (None, None) => eprintln!("{} has no note", name),
+        },
+    }
+}
+
+fn save_notes_or_exit(path: &Path, notes: &Notes) {
+    if let Err(e) = notes::save_notes(path, notes) {
+        eprintln!("Error saving notes: {}", e);
+        std::process::exit(1);
+    }
+    eprintln!("Written: {}", path.display());
+}
+
+/// The function `function` names, by its path or by a part of the path no
+/// other function has, exiting if there isn't exactly one
+fn find_function<'j>(jmap: &'j jmap::Jmap, function: &str) -> (&'j str, &'j jmap::Function) {
+    // An exact path wins even if it's also part of other paths
+    let functions: Vec<(&str, &jmap::Function)> = jmap
+        .objects
+        .iter()
+        .filter_map(|(path, obj)| match obj {
+            jmap::ObjectType::Function(func) => Some((path.as_str(), func)),
+            _ => None,
+        })
+        .filter(|(path, _)| path.contains(function))
+        .collect();
+    match functions.iter().find(|(path, _)| *path == function) {
+        Some(&exact) => exact,
+        None => match functions.as_slice() {
+            [only] => *only,
+            [] => {
+                eprintln!("Error: no function matches '{}'", function);
+                std::process::exit(1);
+            }
+            matches => {
+                eprintln!("Error: '{}' matches {} functions:", function, matches.len());
+                for (path, _) in matches {
+                    eprintln!("    {}", path);
+                }
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+fn run_extract_script(jmap_file: &str, function: &str, output: Option<PathBuf>, hex: bool) {
+    let jmap = load_jmap(jmap_file);
+    let (path, func) = find_function(&jmap, function);
+
+    let script = &func.r#struct.script;
+    let bytes = if hex {
+        formatters::hexdump::hexdump(script).into_bytes()
+    } else {
+        script.clone()
+    };
+    let output = match output {
+        Some(output) => output,
+        None if hex => {
+            print!("{}", String::from_utf8_lossy(&bytes));
+            return;
+        }
+        None => {
+            let name = path.rsplit([':', '.', '/']).next().unwrap_or(path);
+            PathBuf::from(format!("{}.bin", name))
+        }
+    };
+    if let Err(source) = fs::write(&output, &bytes) {
+        exit_with(KismetError::Io {
+            path: output,
+            source,
+        });
+    }
+    eprintln!(
+        "Wrote the {}-byte script of {} to {}",
+        script.len(),
+        path,
+        output.display()
+    );
+}
+
+/// A function's top-level instructions, as the offset they start at, the
+/// opcode they start with and their bytes
+fn instruction_stream<'s>(jmap: &jmap::Jmap, script: &'s [u8]) -> Vec<(usize, String, &'s [u8])> {
+    let expressions = parse_script(jmap, script).unwrap_or_else(|e| exit_with(e));
+    let stock = OpcodeMap::stock();
+    let opcodes = given_opcode_map().unwrap_or(&stock);
+    expressions
+        .iter()
+        .map(|expr| {
+            let span = expr.span();
+            let bytes = &script[span.start.0..span.end.0.min(script.len())];
+            let opcode = bytes
+                .first()
+                .map_or_else(String::new, |&byte| format!("{:?}", opcodes.decode(byte)));
+            (span.start.0, opcode, bytes)
+        })
+        .collect()
+}
+
+fn run_bytecode_diff(old_jmap: &str, new_jmap: &str, function: &str) {
+    let (old_jmap, new_jmap) = (load_jmap(old_jmap), load_jmap(new_jmap));
+    let (old_path, old_func) = find_function(&old_jmap, function);
+    let (new_path, new_func) = find_function(&new_jmap, function);
+    let old = instruction_stream(&old_jmap, &old_func.r#struct.script);
+    let new = instruction_stream(&new_jmap, &new_func.r#struct.script);
+
+    println!(
+        "--- {} ({} bytes)",
+        old_path,
+        old_func.r#struct.script.len()
+    );
+    println!(
+        "+++ {} ({} bytes)",
+        new_path,
+        new_func.r#struct.script.len()
+    );
+    // A jump whose target moved counts as changed, its bytes differ
+    let (mut inserted, mut deleted, mut changed) = (0, 0, 0);
+    let offset = |offset: Option<usize>| {
+        offset.map_or_else(String::new, |offset| format!("0x{:04X}", offset))
+    };
+    for step in align::align(&old, &new, |(_, a, _), (_, b, _)| a == b) {
+        let (mark, old_offset, new_offset, opcode) = match step {
+            Step::Pair(i, j) => {
+                let same = old[i].1 == new[j].1 && old[i].2 == new[j].2;
+                if !same {
+                    changed += 1;
+                }
+                let opcode = if old[i].1 == new[j].1 {
+                    old[i].1.clone()
+                } else {
+                    format!("{} -> {}", old[i].1, new[j].1)
+                };
+                (
+                    if same { ' ' } else { '~' },
+                    Some(old[i].0),
+                    Some(new[j].0),
+                    opcode,
+                )
+            }
+            Step::Deleted(i) => {
+                deleted += 1;
+                ('-', Some(old[i].0), None, old[i].1.clone())
+            }
+            Step::Inserted(j) => {
+                inserted += 1;
+                ('+', None, Some(new[j].0), new[j].1.clone())
+            }
+        };
+        println!(
+            "{} {:>6} {:>6}  {}",
+            mark,
+            offset(old_offset),
+            offset(new_offset),
+            opcode
+        );
+    }
+    println!(
+        "{} instructions inserted, {} deleted, {} changed",
+        inserted, deleted, changed
+    );
+}
+
+/// All the script code an event or function runs
+struct EventCode<'s> {
+    /// The function itself and every script function it can call, excluding
+    /// ubergraphs
+    functions: Vec<&'s str>,
+    /// For a Blueprint event, its code in the ubergraph and the ubergraph's path
+    ubergraph_slice: Option<(&'s str, &'s [Expr])>,
+}
+
+impl<'s> EventCode<'s> {
+    fn find(
+        jmap: &jmap::Jmap,
+        address_index: &AddressIndex,
+        scripts: &'s BTreeMap<&'s str, Vec<Expr>>,
+        graph: &CallGraph,
+        function: &'s str,
+    ) -> Self {
+        let resolve =
+            |caller: &str, func: &FunctionRef| resolve_callee(jmap, address_index, caller, func);
+        let is_ubergraph = |path: &str| path.contains(":ExecuteUbergraph");
+
+        let own = scripts.get(function).map_or(&[][..], Vec::as_slice);
+        let ubergraph_slice = ubergraph::ubergraph_entry(own, |f| resolve(function, f)).and_then(
+            |(ubergraph, entry)| {
+                let (&ubergraph, expressions) = scripts.get_key_value(ubergraph.as_str())?;
+                Some((ubergraph, ubergraph::event_slice(expressions, entry)))
+            },
+        );
+
+        let mut callees: BTreeSet<String> = BTreeSet::new();
+        for (caller, body) in [(function, own)].into_iter().chain(ubergraph_slice) {
+            for (_, callee) in find_calls(body, "*", |f| resolve(caller, f)) {
+                if is_ubergraph(&callee) {
+                    continue;
+                }
+                callees.extend(
+                    graph
+                        .reachable_from(&callee)
+                        .into_iter()
+                        .map(str::to_string),
+                );
+                callees.insert(callee);
+            }
+        }
+
+        let mut functions = vec![function];
+        functions.extend(callees.iter().filter_map(|callee| {
+            let (&path, _) = scripts.get_key_value(&callee[..])?;
+            (path != function && !is_ubergraph(path)).then_some(path)
+        }));
+        Self {
+            functions,
+            ubergraph_slice,
+        }
+    }
+}
+
+/// Path of the class or struct `class` names, by path or by the name after
+/// the path's last `.`, exiting if there isn't exactly one
+fn find_struct_path<'j>(jmap: &'j jmap::Jmap, class: &str) -> &'j str {
+    let candidates: Vec<&str> = match jmap.objects.get_key_value(class) {
+        Some((path, object)) if object.get_struct().is_some() => vec![path.as_str()],
+        _ => jmap
+            .objects
+            .iter()
+            .filter(|(path, object)| {
+                let name = path.rsplit(['/', '.']).next().unwrap_or(path);
+                matches!(
+                    object,
+                    jmap::ObjectType::Class(_) | jmap::ObjectType::ScriptStruct(_)
+                ) && name.eq_ignore_ascii_case(class)
+            })
+            .map(|(path, _)| path.as_str())
+            .collect(),
+    };
+    match candidates[..] {
+        [path] => path,
+        [] => {
+            eprintln!("Error: no class or struct named {}", class);
+            std::process::exit(1);
+        }
+        _ => {
+            eprintln!("Error: {} names several classes or structs:", class);
+            for path in &candidates {
+                eprintln!("  {}", path);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_list(
+    jmap_file: &str,
+    prefix: &str,
+    classes: bool,
+    functions: bool,
+    properties: Option<String>,
+) {
+    let jmap = load_jmap(jmap_file);
+
+    if let Some(class) = properties {
+        let path = find_struct_path(&jmap, &class);
+        let address_index = AddressIndex::new(&jmap);
+        let names: BTreeSet<&str> = address_index
+            .class_chain(path)
+            .filter_map(|ancestor| jmap.objects.get(ancestor)?.get_struct())
+            .flat_map(|struct_obj| &struct_obj.properties)
+            .map(|property| property.name.as_str())
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        for name in names {
+            println!("{}", name);
+        }
+        return;
+    }
+
+    let everything = !classes && !functions;
+    let paths = jmap
+        .objects
+        .range(prefix.to_string()..)
+        .take_while(|(path, _)| path.starts_with(prefix))
+        .filter(|(_, object)| match object {
+            jmap::ObjectType::Class(_) => classes || everything,
+            jmap::ObjectType::Function(_) => functions || everything,
+            _ => everything,
+        });
+    for (path, _) in paths {
+        println!("{}", path);
+    }
+}
+
+fn run_repl(jmap_file: Option<&str>, session_file: Option<&Path>, save_session: Option<&Path>) {
+    use repl::ReplCommand;
+    use std::cell::OnceCell;
+    use std::io::{BufRead, Write};
+
+    let merged = MERGE_FILES.get().map_or(&[][..], Vec::as_slice);
+    let loaded = session_file.map(|path| {
+        eprintln!("Loading session: {}", path.display());
+        (
+            path,
+            session::load_session(path, merged).unwrap_or_else(|e| exit_with(e)),
+        )
+    });
+    let jmap_file = match &loaded {
+        Some((_, loaded)) => loaded.dump().path.to_string_lossy().into_owned(),
+        None => jmap_file
+            .expect("clap requires a JMAP file without --session")
+            .to_string(),
+    };
+    let jmap = load_jmap(&jmap_file);
+    let (address_index, mut indexes) = match loaded {
+        Some((path, loaded)) => {
+            let renames = RENAMES.get().cloned().unwrap_or_default();
+            let address_index = AddressIndex::from_saved(&jmap, loaded.addresses)
+                .unwrap_or_else(|| {
+                    exit_with(KismetError::JmapParse {
+                        path: path.to_path_buf(),
+                        reason: format!("refers to objects {} doesn't have", jmap_file),
+                    })
+                })
+                .with_renames(renames);
+            (address_index, Some((loaded.calls, loaded.xrefs)))
+        }
+        None => (build_address_index(&jmap), None),
+    };
+    let decompiler = given_decompiler(address_index);
+    let address_index = decompiler.address_index();
+    // Only xref and callers need every script decoded, so that waits for one
+    // unless the indexes are being saved or came from a session
+    let scripts = OnceCell::new();
+    let scripts = || scripts.get_or_init(|| parse_scripts(&jmap));
+    if let Some(path) = save_session {
+        let (calls, xrefs) = indexes.unwrap_or_else(|| {
+            let calls = build_call_graph(&jmap, &address_index, scripts());
+            (calls, build_xref_index(scripts()))
+        });
+        // The dump, then each part merged into it
+        let sources = std::iter::once(Path::new(&jmap_file))
+            .chain(merged.iter().map(PathBuf::as_path))
+            .map(SourceFile::read)
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|e| exit_with(e));
+        let saved = Session::new(sources, address_index.save(), calls, xrefs);
+        session::save_session(path, &saved).unwrap_or_else(|e| exit_with(e));
+        eprintln!("Saved session to {}", path.display());
+        indexes = Some((saved.calls, saved.xrefs));
+    }
+    let (graph, xref_index) = (OnceCell::new(), OnceCell::new());
+    if let Some((calls, xrefs)) = indexes {
+        let _ = graph.set(calls);
+        let _ = xref_index.set(xrefs);
+    }
+    let graph = || graph.get_or_init(|| build_call_graph(&jmap, &address_index, scripts()));
+    let xref_index = || xref_index.get_or_init(|| build_xref_index(scripts()));
+    let mut output = DisassembleOutput {
+        formats: vec![OutputFormat::Cpp],
+        formatting: FormattingOptions::default(),
+        hit_profile: None,
+        trace: None,
+        expanded_regions: Vec::new(),
+        prune_constant_branches: false,
+        inline_getters: false,
+        inline_array_gets: false,
+        fold_events: false,
+        notes: Notes::default(),
+        dedupe: false,
+        aggregate: false,
+    };
+    eprintln!("Type help for the commands");
+
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            break;
+        };
+        let command = match repl::parse_command(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        };
+        match command {
+            ReplCommand::Decompile(function) => {
+                let mut text = String::new();
+                // Failures are reported as they happen and don't end the session
+                let _ = run_disassemble(
+                    &mut text,
+                    &decompiler,
+                    Some(function),
+                    &output,
+                    &DisassembleLimits::default(),
+                    &Reproducibility::default(),
+                );
+                print!("{}", text);
+            }
+            ReplCommand::Xref(address) => {
+                let address = Address::new(address);
+                match address_index.resolve_object(address) {
+                    Some(object) => println!("{} is {}", address, object.path),
+                    None => match address_index.resolve_property(address) {
+                        Some(info) => println!(
+                            "{} is property {} of {}",
+                            address, info.property.name, info.owner.path
+                        ),
+                        None => println!("{} isn't an object or property the dump has", address),
+                    },
+                }
+                for xref in xref_index().uses_of(address) {
+                    println!(
+                        "    {} @ {}: {}",
+                        xref.function,
+                        xref.offset,
+                        xref.kind.describe()
+                    );
+                }
+            }
+            ReplCommand::Callers(pattern) => {
+                for (caller, sites) in &graph().calls {
+                    let matching = sites
+                        .iter()
+                        .filter(|site| function_matches(&pattern, &site.callee));
+                    for site in matching {
+                        let binds = match site.kind {
+                            CallKind::Call => "",
+                            CallKind::DelegateBinding => " (delegate binding)",
+                        };
+                        println!("{} @ {} -> {}{}", caller, site.offset, site.callee, binds);
+                    }
+                }
+            }
+            ReplCommand::SetFormat(formats) => {
+                if formats.iter().any(|format| {
+                    matches!(
+                        format,
+                        OutputFormat::Tokens | OutputFormat::Histogram | OutputFormat::Summary
+                    )
+                }) && formats.len() > 1
+                {
+                    eprintln!("Error: histogram, summary and tokens can't be combined");
+                } else if formats.contains(&OutputFormat::Tokens) {
+                    eprintln!("Error: tokens output isn't available in the repl");
+                } else {
+                    output.formats = formats;
+                }
+            }
+            ReplCommand::Help => println!("{}", repl::HELP),
+            ReplCommand::Quit => break,
+        }
+    }
+}
+
+fn run_layout(jmap_file: &str, class: &str) {
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+    let path = find_struct_path(&jmap, class);
+    let Some(layout) = address_index.layout(path) else {
+        unreachable!("{} was found as a class or struct", path);
+    };
+    let size = jmap.objects[path]
+        .get_struct()
+        .map_or(0, |s| s.properties_size);
+    let renderer = ExprRenderer::new(&address_index);
+    let short = |path: &str| path.rsplit(['/', '.']).next().unwrap_or(path).to_string();
+
+    // (offset, size, type, name, declared in), with a row for each gap
+    let mut rows: Vec<[String; 5]> = Vec::new();
+    let gap = |from: usize, to: usize| {
+        let name = "(padding, or members the dump doesn't list)".to_string();
+        [
+            format!("+0x{:04X}", from),
+            (to - from).to_string(),
+            String::new(),
+            name,
+            String::new(),
+        ]
+    };
+    let mut end = 0;
+    for (owner, property) in &layout {
+        let (byte, bit) = bytecode::address_index::member_position(property);
+        if byte > end {
+            rows.push(gap(end, byte));
+        }
+        let count = property.array_dim.max(1);
+        let (offset, name) = match property.r#type {
+            jmap::PropertyType::Bool { field_mask, .. } if field_mask != 0xFF => (
+                format!("+0x{:04X}.{}", byte, bit),
+                format!("{} : 1", property.name),
+            ),
+            _ if count > 1 => (
+                format!("+0x{:04X}", byte),
+                format!("{}[{}]", property.name, count),
+            ),
+            _ => (format!("+0x{:04X}", byte), property.name.clone()),
+        };
+        let bytes = match property.r#type {
+            jmap::PropertyType::Bool {
+                field_size,
+                field_mask,
+                ..
+            } if field_mask != 0xFF => field_size as usize,
+            _ => property.size * count,
+        };
+        rows.push([
+            offset,
+            bytes.to_string(),
+            renderer.cpp_type(property),
+            name,
+            short(owner),
+        ]);
+        end = end.max(byte + bytes);
+    }
+    if size > end {
+        rows.push(gap(end, size));
+    }
+
+    // Dumps without sizes give 0
+    println!("{} ({} bytes)", path, size.max(end));
+    for ancestor in address_index.class_chain(path).skip(1) {
+        println!("  : {}", ancestor);
+    }
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for [offset, bytes, ty, name, owner] in &rows {
+        let line = format!(
+            "  {:ow$}  {:>sw$}  {:tw$}  {:nw$}  {}",
+            offset,
+            bytes,
+            ty,
+            name,
+            owner,
+            ow = widths[0],
+            sw = widths[1],
+            tw = widths[2],
+            nw = widths[3],
+        );
+        println!("{}", line.trim_end());
+    }
+}
+
+fn run_rw_matrix(jmap_file: &str, filter: Option<String>, property: Option<String>) {
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+    let scripts = parse_scripts(&jmap);
+
+    // class => function => property => (read, written)
+    type Row<'p> = BTreeMap<&'p str, (bool, bool)>;
+    let mut classes: BTreeMap<&str, BTreeMap<&str, Row>> = BTreeMap::new();
+    for (&function, expressions) in &scripts {
+        if let Some(ref filter_str) = filter
+            && !function.contains(filter_str.as_str())
+        {
+            continue;
+        }
+        let Some((class, name)) = function.rsplit_once(':') else {
+            continue;
+        };
+        // Members include those inherited from script parent classes
+        let chain: HashSet<&str> = address_index.class_chain(class).collect();
+        let mut row = Row::new();
+        for (_, accessed, access) in property_accesses(expressions) {
+            let Some(info) = address_index.resolve_property(accessed.address) else {
+                continue;
+            };
+            let member = info.property.name.as_str();
+            if !chain.contains(info.owner.path)
+                || property
+                    .as_ref()
+                    .is_some_and(|pattern| !glob_match(pattern, member))
+            {
+                continue;
+            }
+            let cell = row.entry(member).or_default();
+            match access {
+                Access::Read => cell.0 = true,
+                Access::Write => cell.1 = true,
+            }
+        }
+        if !row.is_empty() {
+            classes.entry(class).or_default().insert(name, row);
+        }
+    }
+
+    for (class, functions) in &classes {
+        let members: BTreeSet<&str> = functions
+            .values()
+            .flat_map(|row| row.keys().copied())
+            .collect();
+        println!(
+            "{} ({} functions, {} properties)",
+            class,
+            functions.len(),
+            members.len()
+        );
+        let name_width = functions.keys().map(|name| name.len()).max().unwrap_or(0);
+        let mut header = format!("  {:name_width$}", "");
+        for member in &members {
+            header.push_str(&format!("  {:2}", member));
+        }
+        println!("{}", header);
+        for (name, row) in functions {
+            let mut line = format!("  {:name_width$}", name);
+            for member in &members {
+                let marker = match row.get(member) {
+                    Some((true, true)) => "RW",
+                    Some((true, false)) => "R",
+                    Some((false, true)) => "W",
+                    _ => ".",
+                };
+                line.push_str(&format!("  {:width$}", marker, width = member.len().max(2)));
+            }
+            println!("{}", line.trim_end());
+        }
+        println!();
+    }
+
+    if classes.is_empty() {
+        eprintln!("No member properties are read or written");
+    }
+}
+
+fn run_rpc_audit(jmap_file: &str, filter: Option<String>) {
+    let (jmap, capabilities) = load_jmap_with_capabilities(jmap_file);
+    if !capabilities.function_flags {
+        eprintln!("Error: rpc-audit needs function flags to find Server RPCs");
+        std::process::exit(1);
+    }
+    let address_index = build_address_index(&jmap);
+    let scripts = parse_scripts(&jmap);
+    let graph = build_call_graph(&jmap, &address_index, &scripts);
+
+    let mut audited = 0;
+    let mut suspicious = 0;
+    for (name, obj) in &jmap.objects {
+        let jmap::ObjectType::Function(func) = obj else {
+            continue;
+        };
+        let flags = func.function_flags.bits();
+        if !rpc_audit::is_server_rpc(flags) {
+            continue;
+        }
+        if let Some(ref filter_str) = filter
+            && !name.contains(filter_str.as_str())
+        {
+            continue;
+        }
+        if !scripts.contains_key(name.as_str()) {
+            continue;
+        }
+        audited += 1;
+
+        // Checks are often made in the ubergraph or moved into helper functions
+        let code = EventCode::find(&jmap, &address_index, &scripts, &graph, name);
+        let mut bodies: Vec<(&str, &[Expr])> = code
+            .functions
+            .iter()
+            .map(|&function| (function, scripts[function].as_slice()))
+            .collect();
+        bodies.extend(code.ubergraph_slice);
+        let resolve =
+            |caller: &str, func: &FunctionRef| resolve_callee(&jmap, &address_index, caller, func);
+
+        // Without property flags no parameters are found, so only the
+        // authority check is audited
+        let audit = RpcAudit::new(
+            flags,
+            rpc_audit::object_params(&func.r#struct.properties),
+            &bodies,
+            resolve,
+        );
+
+        let findings = audit.findings();
+        if findings.is_empty() {
+            continue;
+        }
+        suspicious += 1;
+        println!("{}", name);
+        println!("  Flags: {}", function_flag_names(flags).join(" | "));
+        for finding in &findings {
+            println!("  - {}", finding);
+        }
+        println!();
+    }
+
+    eprintln!("{} of {} server RPCs look unchecked", suspicious, audited);
+}
+
+/// Something that runs repeatedly, with its estimated cost per frame
+struct RepeatingCode {
+    per_frame: f64,
+    kind: String,
+    location: String,
+    note: String,
+}
+
+fn run_ticks(jmap_file: &str, filter: Option<String>, frame_rate: f32) {
+    let jmap = load_jmap(jmap_file);
+    let address_index = build_address_index(&jmap);
+    let scripts = parse_scripts(&jmap);
+    let graph = build_call_graph(&jmap, &address_index, &scripts);
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut costs: HashMap<&str, FunctionCost> = HashMap::new();
+    let mut latent_loops = Vec::new();
+    for (&name, expressions) in &scripts {
+        let analyzed = panic::catch_unwind(|| {
+            let cfg = ControlFlowGraph::from_expressions(expressions);
+            let dom_tree = DominatorTree::compute(&cfg);
+            let loop_info = LoopInfo::analyze(&cfg, &dom_tree);
+            (
+                FunctionCost::compute(&cfg, &loop_info),
+                ticks::find_latent_loops(&cfg),
+            )
+        });
+        if let Ok((cost, loops)) = analyzed {
+            costs.insert(name, cost);
+            latent_loops.extend(loops.into_iter().map(|lp| (name, lp)));
+        }
+    }
+    panic::set_hook(default_hook);
+
+    // Cost of one run of a function or event, including everything it calls
+    let inclusive_cost = |function: &str| -> Option<u64> {
+        let function = scripts.get_key_value(function)?.0;
+        let code = EventCode::find(&jmap, &address_index, &scripts, &graph, function);
+        let called: u64 = code
+            .functions
+            .iter()
+            .filter_map(|f| costs.get(f))
+            .map(|cost| cost.score)
+            .sum();
+        let slice: u64 = code
+            .ubergraph_slice
+            .map_or(0, |(_, slice)| slice.iter().map(expr_cost).sum());
+        Some(called + slice)
+    };
+    let matches_filter = |name: &str| {
+        filter
+            .as_ref()
+            .is_none_or(|filter_str| name.contains(filter_str.as_str()))
+    };
 
-            let structure_result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
-                let structurer = PhoenixStructurer::new_with_logger(&cfg, &loop_info, &logger);
-                structurer.structure().is_some()
-            }));
-
-            match structure_result {
-                Ok(succeeded) => {
-                    if succeeded {
-                        (num_loops, true, String::new())
-                    } else {
-                        (num_loops, false, "structure_failed".to_string())
-                    }
-                }
-                Err(_) => (num_loops, false, "structure_panic".to_string()),
-            }
-        } else {
-            (0, false, "cfg_empty".to_string())
-        };
+    let mut report = Vec::new();
+    for &name in scripts.keys() {
+        if ticks::is_tick_event(name) && matches_filter(name) {
+            report.push(RepeatingCode {
+                per_frame: inclusive_cost(name).unwrap_or(0) as f64,
+                kind: "tick".to_string(),
+                location: name.to_string(),
+                note: String::new(),
+            });
+        }
+    }
 
-        (
-            cfg_built,
-            num_blocks,
-            num_loops,
-            structure_succeeded,
-            structure_error,
-        )
-    }));
+    let frame_time = 1.0 / frame_rate;
+    for (&name, expressions) in &scripts {
+        if !matches_filter(name) {
+            continue;
+        }
+        let timers = ticks::find_timer_calls(expressions, |f| {
+            resolve_callee(&jmap, &address_index, name, f)
+        });
+        for timer in timers {
+            let class = name.rsplit_once(':').map_or(name, |(class, _)| class);
+            let target = timer
+                .target
+                .as_ref()
+                .map(|target| format!("{}:{}", class, target));
+            let cost = target.as_deref().and_then(inclusive_cost);
+            // Timers fire at most once per frame
+            let (per_frame, note) = match (timer.looping, timer.interval) {
+                (Some(false), _) => (0.0, "once".to_string()),
+                (_, Some(interval)) if interval > 0.0 => (
+                    cost.unwrap_or(0) as f64 * f64::from((frame_time / interval).min(1.0)),
+                    format!("every {}s", interval),
+                ),
+                _ => (
+                    cost.unwrap_or(0) as f64,
+                    "interval unknown, assuming every frame".to_string(),
+                ),
+            };
+            let target_name = timer.target.as_deref().unwrap_or("<unknown>");
+            report.push(RepeatingCode {
+                per_frame,
+                kind: "timer".to_string(),
+                location: format!("{} @ {} -> {}", name, timer.offset, target_name),
+                note: match cost {
+                    Some(_) => note,
+                    None => format!("{}, target cost unknown", note),
+                },
+            });
+        }
+    }
 
-    let (cfg_built, num_blocks, num_loops, structure_succeeded, structure_error) = match result {
-        Ok(stats) => stats,
-        Err(_) => (false, 0, 0, false, "parser_panic".to_string()),
-    };
+    for (name, lp) in &latent_loops {
+        if !matches_filter(name) {
+            continue;
+        }
+        // A latent action resumes at most once per frame
+        report.push(RepeatingCode {
+            per_frame: lp.iteration_cost as f64,
+            kind: "latent loop".to_string(),
+            location: format!("{} @ {}", name, lp.latent_call),
+            note: format!("resumes at {}", lp.resume),
+        });
+    }
 
-    FunctionStats {
-        name: name.to_string(),
-        script_size: script.len(),
-        cfg_built,
-        num_blocks,
-        num_loops,
-        structure_succeeded,
-        structure_error,
+    report.sort_by(|a, b| {
+        b.per_frame
+            .total_cmp(&a.per_frame)
+            .then_with(|| a.location.cmp(&b.location))
+    });
+    if report.is_empty() {
+        println!("No ticks, timers or latent loops found");
+        return;
+    }
+    println!("{:>10}  {:<12} Location", "Per frame", "Kind");
+    for entry in &report {
+        let note = if entry.note.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", entry.note)
+        };
+        println!(
+            "{:>10.1}  {:<12} {}{}",
+            entry.per_frame, entry.kind, entry.location, note
+        );
     }
-}
 
-fn generate_csv(stats: &[FunctionStats]) -> String {
-    let mut output = String::from(
-        "function_name,script_size,cfg_built,num_blocks,num_loops,structure_succeeded,structure_error\n",
+    let count = |kind: &str| report.iter().filter(|entry| entry.kind == kind).count();
+    eprintln!(
+        "{} ticks, {} timers, {} latent loops; costs assume {} fps and {} iterations per loop",
+        count("tick"),
+        count("timer"),
+        count("latent loop"),
+        frame_rate,
+        ASSUMED_LOOP_ITERATIONS
     );
-    for stat in stats {
-        output.push_str(&format!(
-            "\"{}\",{},{},{},{},{},\"{}\"\n",
-            stat.name.replace('\"', "\"\""),
-            stat.script_size,
-            stat.cfg_built,
-            stat.num_blocks,
-            stat.num_loops,
-            stat.structure_succeeded,
-            stat.structure_error
-        ));
+}
+
+/// Opcodes counted in the scripts of every function matching `filter`
+///
+/// Returns the histogram and how many scripts there were and how many of them
+/// failed to decode. Scripts that fail still count up to the failure.
+fn count_opcodes(
+    jmap: &jmap::Jmap,
+    filter: Option<&str>,
+    opcode_map: Option<&OpcodeMap>,
+) -> (OpcodeHistogram, usize, usize) {
+    let names = jmap.names.as_ref().expect("name map is required");
+    let mut histogram = OpcodeHistogram::default();
+    let (mut scripts, mut failed) = (0, 0);
+    for script in function_scripts(jmap, filter) {
+        let mut parser = ScriptParser::new(ScriptReader::new(script, names))
+            .with_engine_version(given_engine_version());
+        if let Some(map) = opcode_map {
+            parser = parser.with_opcode_map(map);
+        }
+        scripts += 1;
+        if parser.parse_all().is_err() {
+            failed += 1;
+        }
+        histogram.merge(parser.opcodes());
     }
-    output
+    (histogram, scripts, failed)
 }
 
-fn run_stats(jmap_file: &str, filter: Option<String>, output: Option<String>) {
-    // Set a custom panic hook to suppress panic messages during stats collection
-    let default_hook = panic::take_hook();
-    panic::set_hook(Box::new(|_| {
-        // Silently ignore panics - they're caught and reported in the CSV
-    }));
+/// Non-empty scripts of the functions matching `filter`
+fn function_scripts<'j>(jmap: &'j jmap::Jmap, filter: Option<&str>) -> Vec<&'j [u8]> {
+    jmap.objects
+        .iter()
+        .filter(|(name, _)| filter.is_none_or(|filter| name.contains(filter)))
+        .filter_map(|(_, obj)| match obj {
+            jmap::ObjectType::Function(func) if !func.r#struct.script.is_empty() => {
+                Some(func.r#struct.script.as_slice())
+            }
+            _ => None,
+        })
+        .collect()
+}
 
+fn run_detect_obfuscation(
+    jmap_file: &str,
+    baseline_file: Option<&str>,
+    filter: Option<String>,
+    recover: bool,
+    max_attempts: usize,
+    output: Option<PathBuf>,
+) {
     let jmap = load_jmap(jmap_file);
+    let baseline = baseline_file.map(|file| count_opcodes(&load_jmap(file), None, None).0);
+    let (histogram, scripts, failed) = count_opcodes(&jmap, filter.as_deref(), given_opcode_map());
+    if scripts == 0 {
+        println!("No scripts to check");
+        return;
+    }
 
-    // Build address index for resolving object and property references
-    let address_index = AddressIndex::new(&jmap);
-    eprintln!(
-        "Built address index with {} entries",
-        address_index.object_index.len() + address_index.property_index.len()
+    let failed_share = failed as f64 / scripts as f64;
+    println!("Scripts: {}", scripts);
+    println!(
+        "Failed to decode with {} opcodes: {} ({:.1}%)",
+        if given_opcode_map().is_some() {
+            "the given"
+        } else {
+            "stock"
+        },
+        failed,
+        failed_share * 100.0
     );
+    print!(
+        "Opcode entropy: {:.2} bits",
+        obfuscation::entropy(&histogram)
+    );
+    let divergence = baseline.as_ref().map(|baseline| {
+        println!(" (baseline {:.2} bits)", obfuscation::entropy(baseline));
+        let divergence = obfuscation::divergence(&histogram, baseline);
+        println!("Divergence from baseline: {:.3}", divergence);
+        divergence
+    });
+    if divergence.is_none() {
+        println!();
+    }
 
-    let mut stats: Vec<FunctionStats> = Vec::new();
-
-    for (name, obj) in &jmap.objects {
-        if let jmap::ObjectType::Function(func) = obj {
-            // Apply filter if specified
-            if let Some(ref filter_str) = filter
-                && !name.contains(filter_str) {
-                    continue;
-                }
+    // Stock games decode nearly everything, and their opcode mix is close
+    // to any other game's
+    let remapped = failed_share > 0.05 || divergence.is_some_and(|d| d > 0.2);
+    if remapped {
+        println!("Verdict: opcodes look remapped");
+    } else {
+        println!("Verdict: opcodes look stock");
+        if !recover {
+            return;
+        }
+    }
 
-            let script = &func.r#struct.script;
-            if script.is_empty() {
-                continue;
-            }
+    // Try the opcodes common in stock games first
+    let candidates: Vec<EExprToken> = baseline
+        .as_ref()
+        .map(|baseline| {
+            baseline
+                .sorted()
+                .into_iter()
+                .map(|(opcode, _)| opcode)
+                .collect()
+        })
+        .unwrap_or_default();
+    let script_bytes = function_scripts(&jmap, filter.as_deref());
+    let names = jmap.names.as_ref().expect("name map is required");
+    let recovery = obfuscation::recover_opcode_map(&script_bytes, names, &candidates, max_attempts);
 
-            stats.push(collect_function_stats(name, script, &jmap, &address_index));
+    println!();
+    if recovery.complete {
+        println!(
+            "Found an opcode map that decodes all {} scripts ({} decoded while searching)",
+            scripts, recovery.attempts
+        );
+    } else {
+        println!(
+            "Gave up after decoding {} scripts; the best map decodes {} of {}",
+            recovery.attempts, recovery.decoded, scripts
+        );
+    }
+    let remapped: Vec<(u8, EExprToken)> = recovery.map.remapped().collect();
+    if remapped.is_empty() {
+        println!("Every opcode it assigns has its stock value");
+    }
+    for &(byte, opcode) in &remapped {
+        println!(
+            "  0x{:02X} -> {:?} (stock 0x{:02X})",
+            byte,
+            opcode,
+            opcode.opcode_value()
+        );
+    }
+    if let Some(output) = output {
+        // The format --opcode-map reads
+        let map: String = remapped
+            .iter()
+            .map(|(byte, opcode)| format!("{:?} = 0x{:02X}\n", opcode, byte))
+            .collect();
+        if let Err(source) = fs::write(&output, map) {
+            exit_with(KismetError::Io {
+                path: output,
+                source,
+            });
         }
+        eprintln!("Opcode map written to: {}", output.display());
+    }
+    if recovery.complete
+        && let Some(baseline) = &baseline
+    {
+        let (histogram, _, _) = count_opcodes(&jmap, filter.as_deref(), Some(&recovery.map));
+        println!(
+            "Divergence from baseline with this map: {:.3}",
+            obfuscation::divergence(&histogram, baseline)
+        );
     }
+}
 
-    // Restore the default panic hook
-    panic::set_hook(default_hook);
+fn write_function_header(
+    out: &mut dyn fmt::Write,
+    name: &str,
+    func: &jmap::Function,
+    reproducibility: &Reproducibility,
+) -> fmt::Result {
+    writeln!(out, "\n{}", "=".repeat(80))?;
+    writeln!(out, "Function: {}", name)?;
+    if reproducibility.normalize_addresses {
+        // A function's own address always resolves to its path
+        writeln!(out, "Address: @{}", name)?;
+    } else if !reproducibility.hide_addresses {
+        writeln!(out, "Address: {:?}", func.r#struct.object.address)?;
+    }
+    writeln!(out, "Flags: {:?}", func.function_flags)?;
+    writeln!(out, "Script size: {} bytes", func.r#struct.script.len())?;
+    writeln!(out, "{}\n", "=".repeat(80))
+}
 
-    let csv_output = generate_csv(&stats);
+/// Write opcode counts as a table, most frequent first
+///
+/// Opcodes the decoder doesn't know are marked, since they either mean a
+/// newer engine version or bytecode that didn't come from the compiler.
+fn write_opcode_histogram(out: &mut dyn fmt::Write, histogram: &OpcodeHistogram) -> fmt::Result {
+    let total = histogram.total();
+    writeln!(
+        out,
+        "{:>4}  {:<28} {:>10} {:>7}",
+        "Op", "Name", "Count", "Share"
+    )?;
+    for (opcode, count) in histogram.sorted() {
+        let name = match opcode {
+            EExprToken::Unknown(_) => "<unknown>".to_string(),
+            _ => format!("{:?}", opcode),
+        };
+        writeln!(
+            out,
+            "0x{:02X}  {:<28} {:>10} {:>6.2}%",
+            opcode.opcode_value(),
+            name,
+            count,
+            count as f64 * 100.0 / total as f64
+        )?;
+    }
+    writeln!(out, "{:>4}  {:<28} {:>10}", "", "Total", total)
+}
 
-    // Write to file or stdout
-    if let Some(output_path) = output {
-        if let Err(e) = fs::write(&output_path, csv_output) {
-            eprintln!("Error writing CSV file: {}", e);
-            std::process::exit(1);
-        }
-        eprintln!("CSV written to: {}", output_path);
-        eprintln!("Processed {} functions", stats.len());
-    } else {
-        print!("{}", csv_output);
-        eprintln!("Processed {} functions", stats.len());
+/// List the getter calls printed as the member they return
+/// An event stub's expressions with its code from the ubergraph folded in,
+/// noting where that code came from, or the expressions as they are for
+/// anything that isn't an event
+fn fold_event_code(
+    out: &mut dyn fmt::Write,
+    jmap: &jmap::Jmap,
+    address_index: &AddressIndex,
+    name: &str,
+    expressions: Vec<Expr>,
+    ubergraphs: &BTreeMap<&str, Vec<Expr>>,
+) -> Result<Vec<Expr>, fmt::Error> {
+    let resolve = |func: &FunctionRef| resolve_callee(jmap, address_index, name, func);
+    let Some((ubergraph, entry)) = ubergraph::ubergraph_entry(&expressions, resolve) else {
+        return Ok(expressions);
+    };
+    let Some(body) = ubergraphs.get(ubergraph.as_str()) else {
+        return Ok(expressions);
+    };
+    writeln!(
+        out,
+        "// Event code from {} at {}\n",
+        ubergraph
+            .rsplit_once(':')
+            .map_or(ubergraph.as_str(), |(_, name)| name),
+        entry
+    )?;
+    Ok(ubergraph::fold_event(
+        &expressions,
+        ubergraph::event_slice(body, entry),
+        resolve,
+    ))
+}
+
+fn write_inlined_getters(
+    out: &mut dyn fmt::Write,
+    inlined: &[InlinedCall],
+    address_index: &AddressIndex,
+) -> fmt::Result {
+    for call in inlined {
+        let property = address_index
+            .resolve_property(call.property.address)
+            .map_or("<unknown property>", |info| info.property.name.as_str());
+        writeln!(
+            out,
+            "// Inlined getter at {}: {}() -> {}",
+            call.offset,
+            call.callee
+                .rsplit_once(':')
+                .map_or(call.callee.as_str(), |(_, name)| name),
+            property
+        )?;
+    }
+    if !inlined.is_empty() {
+        writeln!(out)?;
     }
+    Ok(())
 }
 
-fn print_function_header(name: &str, func: &jmap::Function) {
-    println!("\n{}", "=".repeat(80));
-    println!("Function: {}", name);
-    println!("Address: {:?}", func.r#struct.object.address);
-    println!("Flags: {:?}", func.function_flags);
-    println!("Script size: {} bytes", func.r#struct.script.len());
-    println!("{}\n", "=".repeat(80));
+fn write_inlined_array_gets(
+    out: &mut dyn fmt::Write,
+    inlined: &[InlinedArrayGet],
+    address_index: &AddressIndex,
+) -> fmt::Result {
+    for get in inlined {
+        let item = address_index
+            .resolve_property(get.item.address)
+            .map_or("<unknown property>", |info| info.property.name.as_str());
+        writeln!(
+            out,
+            "// Inlined Array_Get at {}: {} read in place",
+            get.offset, item
+        )?;
+    }
+    if !inlined.is_empty() {
+        writeln!(out)?;
+    }
+    Ok(())
 }
 
 fn format_as_asm(
+    out: &mut dyn fmt::Write,
     expressions: &[bytecode::expr::Expr],
     address_index: &AddressIndex,
     referenced_offsets: std::collections::HashSet<bytecode::types::BytecodeOffset>,
-) {
-    let mut formatter = AsmFormatter::new(address_index, referenced_offsets);
-    formatter.format(expressions);
+    normalize_addresses: bool,
+) -> fmt::Result {
+    let mut formatter = AsmFormatter::new(address_index, referenced_offsets)
+        .with_normalized_addresses(normalize_addresses);
+    formatter.format(out, expressions)
+}
+
+/// The script's bytes, marked where each top-level instruction starts
+fn format_as_hexdump(
+    out: &mut dyn fmt::Write,
+    script: &[u8],
+    expressions: &[bytecode::expr::Expr],
+) -> fmt::Result {
+    let stock = OpcodeMap::stock();
+    let opcodes = given_opcode_map().unwrap_or(&stock);
+    let instructions: Vec<_> = expressions
+        .iter()
+        .map(|expr| {
+            let name = script
+                .get(expr.offset.0)
+                .map_or_else(String::new, |&byte| format!("{:?}", opcodes.decode(byte)));
+            (expr.span(), name)
+        })
+        .collect();
+    write!(
+        out,
+        "{}",
+        formatters::hexdump::instruction_hexdump(script, &instructions, true)
+    )
 }
 
 fn format_as_cpp(
+    out: &mut dyn fmt::Write,
     expressions: &[bytecode::expr::Expr],
     address_index: &AddressIndex,
+    name: &str,
     referenced_offsets: std::collections::HashSet<bytecode::types::BytecodeOffset>,
-) {
-    let mut formatter = CppFormatter::new(address_index, referenced_offsets);
-    formatter.format(expressions);
+    formatting: &FormattingOptions,
+) -> fmt::Result {
+    let renderer = ExprRenderer::new(address_index)
+        .with_options(*formatting)
+        .with_function(name)
+        .with_ubergraph_entry_of(expressions);
+    StatementWriter::new(&renderer, referenced_offsets).format(out, expressions)
 }
 
-fn format_as_analyze(expressions: &[bytecode::expr::Expr], address_index: &AddressIndex) {
-    let cfg = ControlFlowGraph::from_expressions(expressions);
-    cfg.print_debug(expressions, address_index);
+/// The structured control flow of a graph from [`Decompiler::build_graph`],
+/// structured the first time a format needs it and shared by the others
+fn structure_once<'s>(
+    decompiler: &Decompiler,
+    structured: &'s mut Option<Option<StructuredGraph>>,
+    (cfg, _, loop_info): &(ControlFlowGraph, DominatorTree, LoopInfo),
+) -> Result<Option<&'s StructuredGraph>, KismetError> {
+    if structured.is_none() {
+        *structured = Some(decompiler.structure(cfg, loop_info)?);
+    }
+    Ok(structured.as_ref().and_then(Option::as_ref))
+}
+
+fn format_as_analyze(
+    out: &mut dyn fmt::Write,
+    (cfg, dom_tree, loop_info): &(ControlFlowGraph, DominatorTree, LoopInfo),
+    structured: Result<Option<&StructuredGraph>, KismetError>,
+    address_index: &AddressIndex,
+    name: &str,
+    formatting: &FormattingOptions,
+) -> Result<(), FormatError> {
+    cfg.write_debug(out, address_index)?;
+
+    writeln!(out, "\n{}", "=".repeat(80))?;
+    dom_tree.write_debug(out)?;
 
-    println!("\n{}", "=".repeat(80));
-    let dom_tree = DominatorTree::compute(&cfg);
-    dom_tree.print_debug();
+    writeln!(out, "\n{}", "=".repeat(80))?;
+    loop_info.write_debug(out)?;
 
-    println!("\n{}", "=".repeat(80));
-    let loop_info = LoopInfo::analyze(&cfg, &dom_tree);
-    loop_info.print_debug();
+    writeln!(out, "\n{}", "=".repeat(80))?;
+    Reducibility::analyze(cfg, dom_tree).write_debug(out)?;
 
-    println!("\n{}", "=".repeat(80));
-    let post_dom_tree = PostDominatorTree::compute(&cfg);
-    post_dom_tree.print_debug();
+    writeln!(out, "\n{}", "=".repeat(80))?;
+    let post_dom_tree = PostDominatorTree::compute(cfg);
+    post_dom_tree.write_debug(out)?;
 
-    println!("\n{}", "=".repeat(80));
-    let structurer = PhoenixStructurer::new(&cfg, &loop_info);
-    if let Some(structured) = structurer.structure() {
-        structured.print(address_index);
+    writeln!(out, "\n{}", "=".repeat(80))?;
+    if let Some(structured) = structured? {
+        structured.write(out, cfg, address_index, formatting, None, Some(name))?;
     } else {
         eprintln!("Failed to fully structure the control flow");
     }
+    Ok(())
 }
 
-fn format_as_structured(expressions: &[bytecode::expr::Expr], address_index: &AddressIndex) {
-    let cfg = ControlFlowGraph::from_expressions(expressions);
-    let dom_tree = DominatorTree::compute(&cfg);
-    let loop_info = LoopInfo::analyze(&cfg, &dom_tree);
+fn format_as_structured(
+    out: &mut dyn fmt::Write,
+    cfg: &ControlFlowGraph,
+    structured: Result<Option<&StructuredGraph>, KismetError>,
+    address_index: &AddressIndex,
+    name: &str,
+    output: &DisassembleOutput,
+) -> Result<(), FormatError> {
+    let hits = output
+        .hit_profile
+        .as_ref()
+        .and_then(|profile| profile.for_function(name));
 
-    let structurer = PhoenixStructurer::new(&cfg, &loop_info);
-    if let Some(structured) = structurer.structure() {
-        structured.print(address_index);
+    if let Some(structured) = structured? {
+        // Pruning and reordering are for this output only
+        let mut structured = structured.clone();
+        if output.prune_constant_branches {
+            structured.prune_constant_branches(|func| match func {
+                FunctionRef::ByName(name) => Some(name.as_str().to_string()),
+                FunctionRef::ByAddress(address) => address_index
+                    .resolve_object(*address)
+                    .map(|o| o.path.to_string()),
+            });
+        }
+        if let Some(hits) = hits {
+            structured.order_by_hits(hits);
+        }
+        structured.write(
+            out,
+            cfg,
+            address_index,
+            &output.formatting,
+            hits,
+            Some(name),
+        )?;
     } else {
         eprintln!("Failed to fully structure the control flow");
     }
+    Ok(())
 }
 
-fn format_as_dot(expressions: &[bytecode::expr::Expr], address_index: &AddressIndex) {
-    let cfg = ControlFlowGraph::from_expressions(expressions);
-    let graph = cfg.to_dot(expressions, address_index);
+fn format_as_dot(
+    cfg: &ControlFlowGraph,
+    expressions: &[Expr],
+    address_index: &AddressIndex,
+    output: &DisassembleOutput,
+) {
+    if cfg.blocks.len() > bytecode::regions::COLLAPSE_THRESHOLD {
+        eprintln!(
+            "{} blocks: loops and regions are collapsed, pass --expand-region N to draw one",
+            cfg.blocks.len()
+        );
+    }
+    let coverage = output.trace.as_ref().map(|trace| trace.coverage(cfg));
+    let graph = cfg.to_dot(
+        expressions,
+        address_index,
+        coverage.as_ref(),
+        &output.expanded_regions,
+        output.formatting.normalize_addresses,
+    );
 
-    let mut output = String::new();
+    let mut dot = String::new();
     graph
-        .write(&mut output)
+        .write(&mut dot)
         .expect("Failed to generate DOT output");
 
-    render_dot_and_open(output);
+    render_dot_and_open(dot);
+}
+
+/// Write a function as one line: its signature, how many blocks it has, the
+/// functions it calls and the properties it writes, each in order of first use
+fn format_as_summary(
+    out: &mut dyn fmt::Write,
+    jmap: &jmap::Jmap,
+    cfg: &ControlFlowGraph,
+    expressions: &[Expr],
+    address_index: &AddressIndex,
+    (name, func): (&str, &jmap::Function),
+    formatting: &FormattingOptions,
+) -> fmt::Result {
+    let short = |path: &str| path.rsplit([':', '.']).next().unwrap_or(path).to_string();
+    let list = |items: Vec<String>| {
+        let mut seen = HashSet::new();
+        let unique: Vec<String> = items
+            .into_iter()
+            .filter(|item| seen.insert(item.clone()))
+            .collect();
+        match unique.is_empty() {
+            true => "-".to_string(),
+            false => unique.join(", "),
+        }
+    };
+
+    let calls = find_calls(expressions, "*", |f| {
+        resolve_callee(jmap, address_index, name, f)
+    })
+    .into_iter()
+    .map(|(_, path)| short(&path))
+    .collect();
+    let mut writes = Vec::new();
+    for expr in expressions {
+        expr.walk(&mut |e| {
+            if let Some(info) = written_property(e)
+                .and_then(|property| address_index.resolve_property(property.address))
+            {
+                writes.push(info.property.name.clone());
+            }
+        });
+    }
+
+    let qualified = match name.rsplit_once(':') {
+        Some((class, function)) => {
+            format!("{}::{}", address_index.cpp_class_name(class), function)
+        }
+        None => name.to_string(),
+    };
+    let signature = ExprRenderer::new(address_index)
+        .with_options(*formatting)
+        .signature(&qualified, func);
+    writeln!(
+        out,
+        "{} | blocks: {} | calls: {} | writes: {}",
+        signature,
+        cfg.blocks.len(),
+        list(calls),
+        list(writes)
+    )
 }
 
 fn format_as_cfg(
-    expressions: &[bytecode::expr::Expr],
+    out: &mut dyn fmt::Write,
+    cfg: &ControlFlowGraph,
     address_index: &AddressIndex,
+    name: &str,
     referenced_offsets: std::collections::HashSet<bytecode::types::BytecodeOffset>,
-) {
-    let cfg = ControlFlowGraph::from_expressions(expressions);
+    formatting: &FormattingOptions,
+) -> fmt::Result {
+    let names = cfg.block_names();
+    let renderer = ExprRenderer::new(address_index)
+        .with_options(*formatting)
+        .with_function(name);
+    let mut writer = StatementWriter::new(&renderer, referenced_offsets);
+    writer.set_indent_level(1);
 
     for block in &cfg.blocks {
-        println!(
+        writeln!(
+            out,
             "{}:",
-            formatters::theme::Theme::label(format!("Block_{}", block.id.0))
-        );
+            formatters::theme::Theme::label(names[block.id.0])
+        )?;
 
-        let mut formatter = CppFormatter::new(address_index, referenced_offsets.clone());
-        formatter.set_indent_level(1);
         for stmt in &block.statements {
             match &stmt.kind {
                 ExprKind::PushExecutionFlow { .. }
@@ -399,17 +4341,18 @@ fn format_as_cfg(
                     continue;
                 }
                 _ => {
-                    formatter.format_statement(stmt);
+                    writer.format_statement(out, stmt)?;
                 }
             }
         }
 
         match &block.terminator {
             Terminator::Goto { target } => {
-                println!(
+                writeln!(
+                    out,
                     "    goto {};",
-                    formatters::theme::Theme::label(format!("Block_{}", target.0))
-                );
+                    formatters::theme::Theme::label(names[target.0])
+                )?;
             }
             Terminator::Branch {
                 condition,
@@ -417,51 +4360,106 @@ fn format_as_cfg(
                 false_target,
             } => {
                 let cond_str =
-                    formatter.format_expr_inline(condition, &formatters::cpp::FormatContext::This);
-                println!(
-                    "    if ({}) goto {}; else goto {};",
+                    renderer.format_expr_inline(condition, &formatters::cpp::FormatContext::This);
+                writeln!(
+                    out,
+                    "    {}if ({}) goto {}; else goto {};",
+                    writer.doubt_marker(condition),
                     cond_str,
-                    formatters::theme::Theme::label(format!("Block_{}", true_target.0)),
-                    formatters::theme::Theme::label(format!("Block_{}", false_target.0))
-                );
+                    formatters::theme::Theme::label(names[true_target.0]),
+                    formatters::theme::Theme::label(names[false_target.0])
+                )?;
             }
             Terminator::DynamicJump => {
-                println!("    // dynamic jump");
+                writeln!(out, "    // dynamic jump")?;
             }
             Terminator::Return(expr) => {
                 let ret_str =
-                    formatter.format_expr_inline(expr, &formatters::cpp::FormatContext::This);
-                println!("    return {};", ret_str);
+                    renderer.format_expr_inline(expr, &formatters::cpp::FormatContext::This);
+                writeln!(out, "    {}return {};", writer.doubt_marker(expr), ret_str)?;
+            }
+            Terminator::Abort => {
+                writeln!(out, "    // assertion failed, execution stops")?;
             }
             Terminator::None => unreachable!(),
         }
 
-        println!();
+        writeln!(out)?;
+    }
+    if formatting.explain {
+        writer.into_doubts().explain(out)?;
     }
+    Ok(())
 }
 
+/// Write each function in `output.formats`, then a summary of them all
+///
+/// Functions that fail don't stop the others; the first of them is returned.
 fn run_disassemble(
-    jmap_file: &str,
+    out: &mut dyn fmt::Write,
+    decompiler: &Decompiler,
     filter: Option<String>,
-    format: OutputFormat,
-    _show_block_ids: bool,
-    _show_bytecode_offsets: bool,
-    _show_terminator_exprs: bool,
-) {
-    let jmap = load_jmap(jmap_file);
-
-    // Build address index for resolving object and property references
-    let address_index = AddressIndex::new(&jmap);
-    eprintln!(
-        "Built address index with {} entries",
-        address_index.object_index.len() + address_index.property_index.len()
-    );
+    output: &DisassembleOutput,
+    limits: &DisassembleLimits,
+    reproducibility: &Reproducibility,
+) -> Result<(), FormatError> {
+    let address_index = decompiler.address_index();
+    let jmap = address_index.jmap;
+    let formatting = output.formatting;
+    // Assembly, histograms and hex dumps show the bytecode as it is
+    let bytecode_only = output.formats.iter().all(|format| {
+        matches!(
+            format,
+            OutputFormat::Asm | OutputFormat::Histogram | OutputFormat::Hexdump
+        )
+    });
+    let histogram = output.formats.contains(&OutputFormat::Histogram);
+    // Summaries print each function as one line, with no header
+    let one_line = output.formats.contains(&OutputFormat::Summary);
+    let getters = (output.inline_getters && !bytecode_only && !one_line).then(|| {
+        let functions = jmap
+            .objects
+            .iter()
+            .filter(|(_, obj)| matches!(obj, jmap::ObjectType::Function(_)))
+            .map(|(path, _)| path.as_str());
+        Getters::find(&parse_scripts(jmap), functions)
+    });
+    let ubergraphs: Option<BTreeMap<&str, Vec<Expr>>> =
+        (output.fold_events && !bytecode_only && !one_line).then(|| {
+            jmap.objects
+                .iter()
+                .filter_map(|(path, obj)| match obj {
+                    jmap::ObjectType::Function(func) if path.contains(":ExecuteUbergraph") => {
+                        Some((
+                            path.as_str(),
+                            parse_script(jmap, &func.r#struct.script).ok()?,
+                        ))
+                    }
+                    _ => None,
+                })
+                .collect()
+        });
 
     // Count and disassemble functions
     let mut function_count = 0;
     let mut disassembled_count = 0;
+    let mut oversized_count = 0;
+    let mut limited_count = 0;
+    let mut timed_out: Vec<(&str, Duration, StructuringTimedOut)> = Vec::new();
+    let mut failed: Vec<KismetError> = Vec::new();
+    // First function printed with each script. Per-function hit counts and
+    // getters resolved against the caller's class can make the same script
+    // print differently, so those also key on the function or its class.
+    let mut printed_scripts: HashMap<(&[u8], &str), &str> = HashMap::new();
+    let mut duplicate_count = 0;
+    let mut opcodes = OpcodeHistogram::default();
 
-    for (name, obj) in &jmap.objects {
+    let mut objects: Vec<_> = jmap.objects.iter().collect();
+    if reproducibility.sort_functions {
+        objects.sort_by_key(|(name, _)| *name);
+    }
+
+    for (name, obj) in objects {
         if let jmap::ObjectType::Function(func) = obj {
             function_count += 1;
 
@@ -472,54 +4470,334 @@ fn run_disassemble(
 
             // Apply filter if specified
             if let Some(ref filter_str) = filter
-                && !name.contains(filter_str) {
-                    continue;
-                }
+                && !name.contains(filter_str)
+            {
+                continue;
+            }
 
             let script = &func.r#struct.script;
             if script.is_empty() {
                 continue;
             }
 
+            if let Some(limit) = limits.limit
+                && disassembled_count >= limit
+            {
+                // Keep going to count the rest, so the total stays right
+                if limited_count == 0 {
+                    eprintln!("Stopping after {} functions (--limit)", limit);
+                }
+                limited_count += 1;
+                continue;
+            }
+
+            if let Some(max_size) = limits.max_script_size
+                && script.len() > max_size
+            {
+                eprintln!(
+                    "Skipping {}: script is {} bytes (--max-script-size {})",
+                    name,
+                    script.len(),
+                    max_size
+                );
+                oversized_count += 1;
+                continue;
+            }
+
             disassembled_count += 1;
+            let _span = tracing::info_span!("function", name = %name).entered();
+
+            // Every function counts towards the histogram, so it doesn't dedupe
+            if histogram {
+                let mut parser = script_parser(jmap, script);
+                let result = parser.parse_all();
+                if !output.aggregate {
+                    write_function_header(out, name, func, reproducibility)?;
+                    write_opcode_histogram(out, parser.opcodes())?;
+                }
+                if let Err(e) = result {
+                    eprintln!("Error: {}: {}", name, e);
+                    failed.push(e);
+                }
+                opcodes.merge(parser.opcodes());
+                continue;
+            }
+
+            if !one_line {
+                write_function_header(out, name, func, reproducibility)?;
+                if !output.notes.is_empty() {
+                    write_note(out, jmap, name, func, &output.notes)?;
+                }
+            }
 
-            print_function_header(name, func);
+            let dedupe_scope = match (&output.hit_profile, &getters) {
+                (Some(HitProfile::PerFunction(_)), _) => name.as_str(),
+                (_, Some(_)) => name
+                    .rsplit_once(':')
+                    .map_or(name.as_str(), |(class, _)| class),
+                _ => "",
+            };
+            if output.dedupe
+                && !one_line
+                && let Some(original) = printed_scripts.get(&(script.as_slice(), dedupe_scope))
+            {
+                writeln!(out, "// Identical to {}", original)?;
+                duplicate_count += 1;
+                continue;
+            }
 
             // Parse bytecode to IR
-            let reader = ScriptReader::new(
-                script,
-                jmap.names.as_ref().expect("name map is required"),
-                &address_index,
-            );
-            let mut parser = ScriptParser::new(reader);
-            let expressions = parser.parse_all();
+            let mut diagnostics = Vec::new();
+            let parsed = decompiler.parse(name, &mut diagnostics);
+            for diagnostic in &diagnostics {
+                eprintln!("warning: {}: {}", name, diagnostic);
+            }
+            let expressions = match parsed {
+                Ok(expressions) => expressions,
+                Err(e) => {
+                    eprintln!("Error: {}: {}", name, e);
+                    // The bytes are what's wanted when decoding goes wrong
+                    if output.formats.contains(&OutputFormat::Hexdump) {
+                        write!(out, "{}", formatters::hexdump::hexdump(script))?;
+                    }
+                    failed.push(e);
+                    continue;
+                }
+            };
+            if output.dedupe {
+                printed_scripts.insert((script.as_slice(), dedupe_scope), name.as_str());
+            }
+            let expressions = match &ubergraphs {
+                Some(ubergraphs) => {
+                    fold_event_code(out, jmap, address_index, name, expressions, ubergraphs)?
+                }
+                None => expressions,
+            };
+            let expressions = match &getters {
+                Some(getters) => {
+                    let (expressions, inlined) = getters.inline(&expressions, |func| {
+                        resolve_callee(jmap, address_index, name, func)
+                    });
+                    write_inlined_getters(out, &inlined, address_index)?;
+                    expressions
+                }
+                None => expressions,
+            };
+            let expressions = if output.inline_array_gets && !bytecode_only && !one_line {
+                let (expressions, inlined) = inline_array_gets(&expressions, |func| {
+                    resolve_callee(jmap, address_index, name, func)
+                });
+                write_inlined_array_gets(out, &inlined, address_index)?;
+                expressions
+            } else {
+                expressions
+            };
+
+            // Graphs are only built for the formats that print them, once for
+            // all of them. Blocks and edges are shown as the bytecode has
+            // them; only analyzing and structuring need loop pre-headers.
+            let prints = |formats: &[OutputFormat]| {
+                output.formats.iter().any(|format| formats.contains(format))
+            };
+            let cfg = (formatting.show_costs
+                || prints(&[OutputFormat::Dot, OutputFormat::Summary, OutputFormat::Cfg]))
+            .then(|| decompiler.build_cfg(&expressions));
+            let graph = prints(&[OutputFormat::Analyze, OutputFormat::Structured])
+                .then(|| decompiler.build_graph(&expressions));
+            let mut structured = None;
+
+            if formatting.show_costs
+                && let Some(cfg) = &cfg
+            {
+                let dom_tree = DominatorTree::compute(cfg);
+                let cost = FunctionCost::compute(cfg, &LoopInfo::analyze(cfg, &dom_tree));
+                writeln!(
+                    out,
+                    "Estimated cost: {} ({} calls, loop depth {}, {} iterations per loop)\n",
+                    cost.score, cost.calls, cost.max_loop_depth, ASSUMED_LOOP_ITERATIONS
+                )?;
+            }
 
             // Collect all referenced bytecode offsets
             let referenced_offsets = collect_referenced_offsets(&expressions);
 
-            // Format based on output type
-            match format {
-                OutputFormat::Asm => {
-                    format_as_asm(&expressions, &address_index, referenced_offsets)
+            for (index, &format) in output.formats.iter().enumerate() {
+                if output.formats.len() > 1 {
+                    let name = format.to_possible_value().expect("no skipped formats");
+                    let gap = if index > 0 { "\n" } else { "" };
+                    writeln!(out, "{}// -o {}\n", gap, name.get_name())?;
+                }
+                let referenced_offsets = referenced_offsets.clone();
+                let started = Instant::now();
+                let cfg = || cfg.as_ref().expect("built for every format printing it");
+                let graph = || graph.as_ref().expect("built for every format printing it");
+
+                if formatting.valid_cpp
+                    && matches!(
+                        format,
+                        OutputFormat::Cpp | OutputFormat::Structured | OutputFormat::Tokens
+                    )
+                {
+                    let renderer = ExprRenderer::new(address_index).with_options(formatting);
+                    let mut declarations = StatementWriter::new(&renderer, HashSet::new());
+                    // Flat output indents statements under their labels
+                    if let OutputFormat::Cpp | OutputFormat::Tokens = format {
+                        declarations.set_indent_level(1);
+                    }
+                    declarations.declare_locals(out, func)?;
                 }
-                OutputFormat::Cpp => {
-                    format_as_cpp(&expressions, &address_index, referenced_offsets)
+
+                // Format based on output type
+                let outcome = match format {
+                    OutputFormat::Asm => format_as_asm(
+                        out,
+                        &expressions,
+                        address_index,
+                        referenced_offsets,
+                        formatting.normalize_addresses,
+                    )
+                    .map_err(FormatError::from),
+                    // Marked to be split into tokens as it's written
+                    OutputFormat::Cpp | OutputFormat::Tokens => format_as_cpp(
+                        out,
+                        &expressions,
+                        address_index,
+                        name,
+                        referenced_offsets,
+                        &formatting,
+                    )
+                    .map_err(FormatError::from),
+                    OutputFormat::Analyze => format_as_analyze(
+                        out,
+                        graph(),
+                        structure_once(decompiler, &mut structured, graph()),
+                        address_index,
+                        name,
+                        &formatting,
+                    ),
+                    OutputFormat::Structured => match format_as_structured(
+                        out,
+                        &graph().0,
+                        structure_once(decompiler, &mut structured, graph()),
+                        address_index,
+                        name,
+                        output,
+                    ) {
+                        Err(FormatError::Kismet(e)) => {
+                            eprintln!("Structuring failed, falling back to CFG output");
+                            format_as_cfg(
+                                out,
+                                &decompiler.build_cfg(&expressions),
+                                address_index,
+                                name,
+                                referenced_offsets,
+                                &formatting,
+                            )?;
+                            Err(FormatError::Kismet(e))
+                        }
+                        written => written,
+                    },
+                    OutputFormat::Dot => {
+                        format_as_dot(cfg(), &expressions, address_index, output);
+                        Ok(())
+                    }
+                    OutputFormat::Cfg => format_as_cfg(
+                        out,
+                        cfg(),
+                        address_index,
+                        name,
+                        referenced_offsets,
+                        &formatting,
+                    )
+                    .map_err(FormatError::from),
+                    OutputFormat::Summary => format_as_summary(
+                        out,
+                        jmap,
+                        cfg(),
+                        &expressions,
+                        address_index,
+                        (name, func),
+                        &formatting,
+                    )
+                    .map_err(FormatError::from),
+                    OutputFormat::Hexdump => {
+                        format_as_hexdump(out, script, &expressions).map_err(FormatError::from)
+                    }
+                    OutputFormat::Histogram => {
+                        unreachable!("histograms are printed before parsing")
+                    }
+                };
+
+                match outcome {
+                    Ok(()) => {}
+                    Err(FormatError::Kismet(KismetError::Structuring(timeout))) => {
+                        timed_out.push((name, started.elapsed(), timeout))
+                    }
+                    Err(FormatError::Kismet(e)) => {
+                        eprintln!("Error: {}: {}", name, e);
+                        failed.push(e);
+                    }
+                    Err(FormatError::Output) => return Err(FormatError::Output),
                 }
-                OutputFormat::Analyze => format_as_analyze(&expressions, &address_index),
-                OutputFormat::Structured => format_as_structured(&expressions, &address_index),
-                OutputFormat::Dot => format_as_dot(&expressions, &address_index),
-                OutputFormat::Cfg => {
-                    format_as_cfg(&expressions, &address_index, referenced_offsets)
+            }
+
+            if formatting.show_unused_locals && !one_line {
+                let unused = unused_locals(&func.r#struct.properties, &expressions);
+                if !unused.is_empty() {
+                    let names: Vec<&str> = unused.iter().map(|prop| prop.name.as_str()).collect();
+                    let comment = format!("// Unused locals: {}", names.join(", "));
+                    writeln!(out, "\n{}", formatters::theme::Theme::comment(comment))?;
                 }
             }
         }
     }
 
-    println!("\n{}", "=".repeat(80));
-    println!("Summary:");
-    println!("  Total functions: {}", function_count);
-    println!("  Disassembled: {}", disassembled_count);
-    println!("{}", "=".repeat(80));
+    if histogram {
+        writeln!(out, "\n{}", "=".repeat(80))?;
+        writeln!(out, "Opcodes in all {} functions:\n", disassembled_count)?;
+        write_opcode_histogram(out, &opcodes)?;
+    }
+
+    writeln!(out, "\n{}", "=".repeat(80))?;
+    writeln!(out, "Summary:")?;
+    writeln!(out, "  Total functions: {}", function_count)?;
+    writeln!(out, "  Disassembled: {}", disassembled_count)?;
+    if oversized_count > 0 {
+        writeln!(out, "  Skipped (too large): {}", oversized_count)?;
+    }
+    if limited_count > 0 {
+        writeln!(out, "  Skipped (--limit): {}", limited_count)?;
+    }
+    if duplicate_count > 0 {
+        writeln!(
+            out,
+            "  Identical to an earlier function: {}",
+            duplicate_count
+        )?;
+    }
+    if !timed_out.is_empty() {
+        writeln!(out, "  Structuring timed out: {}", timed_out.len())?;
+        for (name, elapsed, timeout) in &timed_out {
+            if reproducibility.hide_timings {
+                writeln!(out, "    {} ({} iterations)", name, timeout.iterations)?;
+            } else {
+                writeln!(
+                    out,
+                    "    {} ({:.1?}, {} iterations)",
+                    name, elapsed, timeout.iterations
+                )?;
+            }
+        }
+    }
+    if !failed.is_empty() {
+        writeln!(out, "  Failed: {}", failed.len())?;
+    }
+    writeln!(out, "{}", "=".repeat(80))?;
+
+    match failed.into_iter().next() {
+        Some(error) => Err(FormatError::Kismet(error)),
+        None => Ok(()),
+    }
 }
 
 fn render_dot_and_open(dot: String) {
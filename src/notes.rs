@@ -0,0 +1,176 @@
+//! Analysts' notes on functions, kept in a file beside the dump
+//!
+//! `note` reads and writes them, and `export` shows them on its index page.
+//! By default they live next to the JMAP file, in `<jmap>.notes.toml`, which
+//! can be committed and shared like any other text file:
+//!
+//! ```toml
+//! [[note]]
+//! function = "/Game/BP_Enemy.BP_Enemy_C:TakeDamage"
+//! fingerprint = "5c1e9d0a4b7f2e36"
+//! text = "Armor is applied twice, see `ApplyArmor`"
+//! ```
+//!
+//! Each note is keyed by a fingerprint of its function's path and code that
+//! leaves out operands such as addresses, which move between runs of the
+//! game. A note follows its function into every dump of the same build, and
+//! is reported as out of date once the function's code changes.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bytecode::expr::Expr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Note {
+    /// Path of the function when the note was written
+    pub function: String,
+    pub fingerprint: String,
+    /// Markdown, shown as written
+    pub text: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Notes {
+    #[serde(default, rename = "note")]
+    notes: Vec<Note>,
+}
+
+impl Notes {
+    /// The note on the function with this fingerprint
+    pub fn get(&self, fingerprint: &str) -> Option<&Note> {
+        self.notes
+            .iter()
+            .find(|note| note.fingerprint == fingerprint)
+    }
+
+    /// A note written on `function` before its code last changed
+    pub fn out_of_date(&self, function: &str, fingerprint: &str) -> Option<&Note> {
+        self.notes
+            .iter()
+            .find(|note| note.function == function && note.fingerprint != fingerprint)
+    }
+
+    /// Store `text` as the note on a function, replacing its note and any
+    /// left from earlier versions of its code
+    pub fn set(&mut self, function: &str, fingerprint: &str, text: String) {
+        self.notes
+            .retain(|note| note.function != function && note.fingerprint != fingerprint);
+        self.notes.push(Note {
+            function: function.to_string(),
+            fingerprint: fingerprint.to_string(),
+            text,
+        });
+        self.notes.sort_by(|a, b| a.function.cmp(&b.function));
+    }
+
+    /// Remove every note on `function`, returning whether it had one
+    pub fn remove(&mut self, function: &str) -> bool {
+        let before = self.notes.len();
+        self.notes.retain(|note| note.function != function);
+        self.notes.len() != before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Note> {
+        self.notes.iter()
+    }
+}
+
+/// Where a dump's notes are kept unless `--notes` says otherwise
+pub fn default_notes_path(jmap_file: &Path) -> PathBuf {
+    let mut path = jmap_file.as_os_str().to_owned();
+    path.push(".notes.toml");
+    PathBuf::from(path)
+}
+
+/// Read a notes file, or start an empty one if there's no file yet
+pub fn load_notes(path: &Path) -> Result<Notes, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Notes::default()),
+        Err(e) => return Err(format!("reading {}: {}", path.display(), e)),
+    };
+    toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn save_notes(path: &Path, notes: &Notes) -> Result<(), String> {
+    let text = toml::to_string(notes).map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| format!("writing {}: {}", path.display(), e))
+}
+
+/// Fingerprint of a function: its path and the opcode of every expression
+/// in its script, hashed with 64-bit FNV-1a
+///
+/// Opcodes alone are enough to tell edited code from the same code with
+/// objects at other addresses, and FNV gives the same value on every build
+/// of this tool, unlike the standard library's hashers.
+pub fn fingerprint(path: &str, script: &[u8], expressions: &[Expr]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |byte: u8| {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    };
+    path.bytes().for_each(&mut add);
+    add(0);
+    for expr in expressions {
+        expr.walk(&mut |e| add(script.get(e.offset.0).copied().unwrap_or(0)));
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::expr::ExprKind;
+    use crate::bytecode::refs::PropertyRef;
+    use crate::bytecode::types::{Address, BytecodeOffset};
+
+    #[test]
+    fn test_fingerprints_and_notes() {
+        // return Member; for the property at `address`, a local one if `local`
+        let script = |address: u8, local: bool| {
+            let member = PropertyRef::new(Address::new(u64::from(address)));
+            let (opcode, kind) = match local {
+                true => (0x00, ExprKind::LocalVariable(member)),
+                false => (0x01, ExprKind::InstanceVariable(member)),
+            };
+            let value = Expr::new(BytecodeOffset::new(1), kind);
+            let ret = Expr::new(BytecodeOffset::new(0), ExprKind::Return(Box::new(value)));
+            (vec![0x04, opcode, address, 0, 0, 0, 0, 0, 0, 0], vec![ret])
+        };
+        let print = |path: &str, (script, expressions): &(Vec<u8>, Vec<Expr>)| {
+            fingerprint(path, script, expressions)
+        };
+        let first = script(0x10, false);
+        let moved = script(0x20, false);
+        let edited = script(0x10, true);
+        let tick = print("/Game/A.A_C:Tick", &first);
+        assert_eq!(tick, print("/Game/A.A_C:Tick", &moved));
+        assert_ne!(tick, print("/Game/A.A_C:Tick", &edited));
+        assert_ne!(tick, print("/Game/B.B_C:Tick", &first));
+
+        let mut notes = Notes::default();
+        notes.set("/Game/A.A_C:Tick", "old", "first".to_string());
+        notes.set("/Game/A.A_C:Tick", &tick, "second".to_string());
+        assert_eq!(notes.iter().count(), 1);
+        assert_eq!(
+            notes.get(&tick).map(|note| note.text.as_str()),
+            Some("second")
+        );
+        assert!(notes.out_of_date("/Game/A.A_C:Tick", &tick).is_none());
+        assert!(notes.out_of_date("/Game/A.A_C:Tick", "new").is_some());
+
+        let text = toml::to_string(&notes).unwrap();
+        let loaded: Notes = toml::from_str(&text).unwrap();
+        assert_eq!(loaded.get(&tick), notes.get(&tick));
+        assert!(notes.remove("/Game/A.A_C:Tick"));
+        assert!(notes.get(&tick).is_none());
+    }
+}
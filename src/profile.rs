@@ -0,0 +1,59 @@
+//! Named bundles of decompilation options, so related passes and formatting
+//! flags are switched together instead of one by one
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::formatters::FormattingOptions;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// Output meant for reading: operators, constructors and named enum values
+    #[default]
+    Readable,
+    /// Output that stays close to the bytecode: no sugar, with offsets and
+    /// terminator expressions shown
+    Faithful,
+    /// Output meant for diffing two dumps: no colors, no offsets or block ids,
+    /// functions in a stable order
+    Diff,
+}
+
+impl Profile {
+    pub fn formatting(self) -> FormattingOptions {
+        match self {
+            Profile::Readable => FormattingOptions {
+                show_block_ids: true,
+                ..Default::default()
+            },
+            Profile::Faithful => FormattingOptions {
+                show_block_ids: true,
+                show_bytecode_offsets: true,
+                show_terminator_exprs: true,
+                sugar: false,
+                ascii_only: false,
+                normalize_addresses: false,
+                show_costs: false,
+                simplify: false,
+                elide_omitted_args: false,
+                valid_cpp: false,
+                faithful_casts: true,
+                explain: false,
+                show_prop_offsets: false,
+                show_loop_bounds: false,
+                show_unused_locals: false,
+            },
+            Profile::Diff => FormattingOptions::default(),
+        }
+    }
+
+    pub fn colors(self) -> bool {
+        self != Profile::Diff
+    }
+
+    /// Whether functions are printed sorted by path rather than in jmap order
+    pub fn sorted(self) -> bool {
+        self == Profile::Diff
+    }
+}
@@ -0,0 +1,142 @@
+//! Masking secrets in script strings before anything is printed
+//!
+//! `--redact` takes a TOML file with a `[[redact]]` table per secret:
+//!
+//! ```toml
+//! [[redact]]
+//! text = "sk-live-4f9a"               # masked wherever it appears
+//!
+//! [[redact]]
+//! prefix = "https://build.internal/"  # masked from here to the string's end
+//! ```
+//!
+//! Masking happens in the script bytes as they're loaded, so every output
+//! and every file written from them, hex dumps included, only sees the mask.
+//! Each masked byte becomes a `*` of the same width, in ANSI strings and
+//! UTF-16 ones, so offsets and jump targets don't move.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedactFile {
+    #[serde(default, rename = "redact")]
+    rules: Vec<RedactSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedactSpec {
+    text: Option<String>,
+    prefix: Option<String>,
+}
+
+/// One secret to mask
+#[derive(Debug)]
+enum Rule {
+    /// Just this text
+    Text(String),
+    /// This text and the rest of the string it starts in
+    Prefix(String),
+}
+
+/// The secrets a rule file lists
+#[derive(Debug)]
+pub struct Redactions {
+    rules: Vec<Rule>,
+}
+
+pub fn load_redactions(path: &Path) -> Result<Redactions, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path.display(), e))?;
+    parse_redactions(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_redactions(text: &str) -> Result<Redactions, String> {
+    let file: RedactFile = toml::from_str(text).map_err(|e| e.to_string())?;
+    if file.rules.is_empty() {
+        return Err("no [[redact]] tables".to_string());
+    }
+    let rules = file
+        .rules
+        .into_iter()
+        .enumerate()
+        .map(|(index, spec)| match (spec.text, spec.prefix) {
+            (Some(text), None) if !text.is_empty() => Ok(Rule::Text(text)),
+            (None, Some(prefix)) if !prefix.is_empty() => Ok(Rule::Prefix(prefix)),
+            _ => Err(format!(
+                "redaction {} needs one non-empty `text` or `prefix`",
+                index + 1
+            )),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Redactions { rules })
+}
+
+impl Redactions {
+    /// Mask every secret in a script, returning how many were found
+    pub fn apply(&self, script: &mut [u8]) -> usize {
+        let mut found = 0;
+        for rule in &self.rules {
+            let (text, to_end) = match rule {
+                Rule::Text(text) => (text, false),
+                Rule::Prefix(prefix) => (prefix, true),
+            };
+            let ansi: Vec<u8> = text.bytes().collect();
+            let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            found += mask(script, &ansi, 1, to_end);
+            found += mask(script, &utf16, 2, to_end);
+        }
+        found
+    }
+}
+
+/// Mask each occurrence of `needle` made of `width`-byte characters, and the
+/// rest of its string up to the terminating null if `to_end`
+fn mask(script: &mut [u8], needle: &[u8], width: usize, to_end: bool) -> usize {
+    let mut found = 0;
+    let mut start = 0;
+    while let Some(at) = script[start..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+    {
+        let at = start + at;
+        let mut end = at + needle.len();
+        if to_end {
+            while end + width <= script.len() && script[end..end + width].iter().any(|&b| b != 0) {
+                end += width;
+            }
+        }
+        for character in script[at..end].chunks_mut(width) {
+            character.fill(0);
+            character[0] = b'*';
+        }
+        found += 1;
+        start = end;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masks_keep_offsets() {
+        let redactions =
+            parse_redactions("[[redact]]\ntext = \"key42\"\n[[redact]]\nprefix = \"https://in\"\n")
+                .unwrap();
+        let mut script = b"\x1fuse key42!\0\x1fhttps://in.example/x\0\x0b".to_vec();
+        script.extend([0x34, b'k', 0, b'e', 0, b'y', 0, b'4', 0, b'2', 0, 0, 0]);
+        let length = script.len();
+
+        assert_eq!(redactions.apply(&mut script), 3);
+        assert_eq!(script.len(), length);
+        assert!(script.starts_with(b"\x1fuse *****!\0\x1f********************\0\x0b"));
+        assert!(script.ends_with(&[0x34, b'*', 0, b'*', 0, b'*', 0, b'*', 0, b'*', 0, 0, 0]));
+
+        assert!(parse_redactions("[[redact]]\ntext = \"a\"\nprefix = \"b\"\n").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+//! Commands for `repl`, which keeps one dump loaded between queries
+//!
+//! Loading a large dump takes far longer than any one query against it, so
+//! `repl` loads it once and then reads commands a line at a time:
+//!
+//! ```text
+//! > decompile BP_Enemy_C:TakeDamage
+//! > xref 0x7FF6A0001000
+//! > callers ApplyDamage
+//! > set format asm
+//! ```
+
+use clap::ValueEnum;
+
+use crate::OutputFormat;
+
+/// One line of input to the repl
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplCommand {
+    /// Print the functions whose path contains this, in the current formats
+    Decompile(String),
+    /// List the uses of the object, property or function at an address
+    Xref(u64),
+    /// List the calls to functions matching a pattern
+    Callers(String),
+    /// Print functions in these formats from now on
+    SetFormat(Vec<OutputFormat>),
+    Help,
+    Quit,
+}
+
+pub const HELP: &str = "\
+decompile <function>     print functions whose path contains <function>
+xref <address>           list uses of the object, property or function there
+callers <pattern>        list calls to functions matching <pattern>
+set format <f>[,<f>...]  print functions as cpp, asm, structured, ... from now on
+help                     show this list
+quit                     leave, as does end of input";
+
+/// Parse a line, or `None` for a blank one
+pub fn parse_command(line: &str) -> Result<Option<ReplCommand>, String> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    let rest: Vec<&str> = words.collect();
+    let argument = |what: &str| match rest[..] {
+        [argument] => Ok(argument.to_string()),
+        _ => Err(format!("{} takes one {}", command, what)),
+    };
+    let parsed = match command {
+        "decompile" | "d" => ReplCommand::Decompile(argument("function")?),
+        "xref" | "x" => {
+            let address = argument("address")?;
+            let parsed = match address.strip_prefix("0x").or(address.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => address.parse(),
+            };
+            ReplCommand::Xref(parsed.map_err(|_| format!("'{}' isn't an address", address))?)
+        }
+        "callers" | "c" => ReplCommand::Callers(argument("pattern")?),
+        "set" => match rest[..] {
+            ["format", formats] => ReplCommand::SetFormat(
+                formats
+                    .split(',')
+                    .map(|format| OutputFormat::from_str(format, true))
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => return Err("usage: set format <format>[,<format>...]".to_string()),
+        },
+        "help" | "?" => ReplCommand::Help,
+        "quit" | "exit" | "q" => ReplCommand::Quit,
+        _ => return Err(format!("unknown command '{}', try help", command)),
+    };
+    Ok(Some(parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse_command("   "), Ok(None));
+        assert_eq!(
+            parse_command("decompile BP_C:Tick"),
+            Ok(Some(ReplCommand::Decompile("BP_C:Tick".to_string())))
+        );
+        assert_eq!(
+            parse_command("xref 0x1A"),
+            Ok(Some(ReplCommand::Xref(0x1A)))
+        );
+        assert_eq!(parse_command("x 26"), Ok(Some(ReplCommand::Xref(26))));
+        assert_eq!(
+            parse_command("set format asm,cpp"),
+            Ok(Some(ReplCommand::SetFormat(vec![
+                OutputFormat::Asm,
+                OutputFormat::Cpp
+            ])))
+        );
+        assert!(parse_command("set format nope").is_err());
+        assert!(parse_command("xref").is_err());
+        assert!(parse_command("frobnicate").is_err());
+    }
+}
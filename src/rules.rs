@@ -0,0 +1,121 @@
+//! Rule files: saved queries for scanning a whole jmap in one run
+//!
+//! A rule file is TOML with a `[[rule]]` table per rule:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "spawn-in-loop"
+//! description = "Spawning actors every iteration"
+//! query = 'call("SpawnActor*") inside loop'
+//! filter = "BP_Enemy"  # optional, limits the rule to matching functions
+//! ```
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::bytecode::query::Query;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: String,
+    description: Option<String>,
+    query: String,
+    filter: Option<String>,
+}
+
+/// A rule with its query parsed
+#[derive(Debug)]
+pub struct Rule {
+    pub name: String,
+    pub description: Option<String>,
+    pub query: Query,
+    /// Only functions whose path contains this are scanned
+    pub filter: Option<String>,
+}
+
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path.display(), e))?;
+    parse_rules(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_rules(text: &str) -> Result<Vec<Rule>, String> {
+    let file: RuleFile = toml::from_str(text).map_err(|e| e.to_string())?;
+    if file.rules.is_empty() {
+        return Err("no [[rule]] tables".to_string());
+    }
+
+    let mut names = HashSet::new();
+    file.rules
+        .into_iter()
+        .map(|spec| {
+            if !names.insert(spec.name.clone()) {
+                return Err(format!("rule '{}' is defined twice", spec.name));
+            }
+            let query =
+                Query::parse(&spec.query).map_err(|e| format!("rule '{}': {}", spec.name, e))?;
+            Ok(Rule {
+                name: spec.name,
+                description: spec.description,
+                query,
+                filter: spec.filter,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse_rules(
+            r#"
+            [[rule]]
+            name = "spawn-in-loop"
+            description = "Spawning actors every iteration"
+            query = 'call("SpawnActor*") inside loop'
+
+            [[rule]]
+            name = "health"
+            query = 'write(prop:"Health")'
+            filter = "BP_Player"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].name, "spawn-in-loop");
+        assert!(rules[0].query.uses_loops());
+        assert_eq!(rules[1].query, Query::Write("Health".to_string()));
+        assert_eq!(rules[1].filter.as_deref(), Some("BP_Player"));
+    }
+
+    #[test]
+    fn test_bad_rules_are_rejected() {
+        let err = parse_rules("[[rule]]\nname = \"a\"\nquery = 'call('").unwrap_err();
+        assert!(err.starts_with("rule 'a':"), "{}", err);
+        let duplicate = r#"
+            [[rule]]
+            name = "a"
+            query = 'call("X")'
+
+            [[rule]]
+            name = "a"
+            query = 'call("Y")'
+            "#;
+        assert!(parse_rules(duplicate).is_err());
+        assert!(parse_rules("").is_err());
+    }
+}
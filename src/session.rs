@@ -0,0 +1,187 @@
+//! Saved analysis state, so `repl` can skip rebuilding it for the same dump
+//!
+//! A session holds the indexes built from a dump: the address index, and the
+//! call graph and cross-reference index built from decoding every script. It
+//! refers to the dump and its `--merge` parts by path and a hash of their
+//! contents instead of copying them, and is refused once any of them has
+//! changed. Loading one still loads the dump, but decodes no scripts and
+//! sorts nothing, which is most of the time indexing it takes.
+//!
+//! Sessions are written with bincode and tied to the version of this tool
+//! that wrote them; one from another version is refused rather than misread.
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bytecode::address_index::SavedAddressIndex;
+use crate::bytecode::call_graph::CallGraph;
+use crate::bytecode::xrefs::XrefIndex;
+use crate::error::KismetError;
+
+/// Start of every session file
+const MAGIC: &[u8; 8] = b"KISMETSS";
+
+/// Bumped whenever what a session holds changes shape within a release
+const SESSION_VERSION: u32 = 2;
+
+/// Checked on its own first, so a session from another build is reported as
+/// one rather than as whichever field changed
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    tool_version: String,
+    version: u32,
+}
+
+impl Header {
+    fn current() -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            version: SESSION_VERSION,
+        }
+    }
+}
+
+/// A file a session's dump was loaded from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceFile {
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// 64-bit FNV-1a of its contents
+    pub hash: String,
+}
+
+impl SourceFile {
+    pub fn read(path: &Path) -> Result<Self, KismetError> {
+        let io_error = |source| KismetError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let path = std::fs::canonicalize(path).map_err(io_error)?;
+        let mut file = File::open(&path).map_err(io_error)?;
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut buffer = vec![0; 1 << 16];
+        loop {
+            let read = file.read(&mut buffer).map_err(io_error)?;
+            if read == 0 {
+                break;
+            }
+            for &byte in &buffer[..read] {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        }
+        Ok(Self {
+            path,
+            hash: format!("{:016x}", hash),
+        })
+    }
+}
+
+/// Everything `repl` builds from a dump before taking commands
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    /// The dump, then each part merged into it
+    sources: Vec<SourceFile>,
+    pub addresses: SavedAddressIndex,
+    pub calls: CallGraph,
+    pub xrefs: XrefIndex,
+}
+
+impl Session {
+    pub fn new(
+        sources: Vec<SourceFile>,
+        addresses: SavedAddressIndex,
+        calls: CallGraph,
+        xrefs: XrefIndex,
+    ) -> Self {
+        Self {
+            sources,
+            addresses,
+            calls,
+            xrefs,
+        }
+    }
+
+    /// The dump the session was built from
+    pub fn dump(&self) -> &SourceFile {
+        &self.sources[0]
+    }
+}
+
+pub fn save_session(path: &Path, session: &Session) -> Result<(), KismetError> {
+    let io_error = |source| KismetError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+    writer.write_all(MAGIC).map_err(io_error)?;
+    let encode_error = |e: bincode::Error| io_error(std::io::Error::other(e));
+    bincode::serialize_into(&mut writer, &Header::current()).map_err(encode_error)?;
+    bincode::serialize_into(&mut writer, session).map_err(encode_error)?;
+    writer.flush().map_err(io_error)
+}
+
+/// Load a session, checking that its dump and the parts in `merged` are the
+/// files it was built from, unchanged
+pub fn load_session(path: &Path, merged: &[PathBuf]) -> Result<Session, KismetError> {
+    let bytes = std::fs::read(path).map_err(|source| KismetError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let parse_error = |reason: String| KismetError::JmapParse {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let Some(mut rest) = bytes.strip_prefix(MAGIC) else {
+        return Err(parse_error("not a session file".to_string()));
+    };
+    let header: Header = bincode::deserialize_from(&mut rest)
+        .map_err(|e| parse_error(format!("not a session file: {}", e)))?;
+    let current = Header::current();
+    if (&header.tool_version, header.version) != (&current.tool_version, current.version) {
+        return Err(parse_error(format!(
+            "session was saved by version {} (format {}), this is {} (format {}); save it again",
+            header.tool_version, header.version, current.tool_version, current.version
+        )));
+    }
+    let session: Session = bincode::deserialize_from(rest)
+        .map_err(|e| parse_error(format!("not a session file: {}", e)))?;
+    if session.sources.is_empty() {
+        return Err(parse_error("session names no dump".to_string()));
+    }
+
+    let mut sources = vec![SourceFile::read(&session.dump().path)?];
+    for part in merged {
+        sources.push(SourceFile::read(part)?);
+    }
+    let paths = |sources: &[SourceFile]| -> Vec<PathBuf> {
+        sources.iter().map(|source| source.path.clone()).collect()
+    };
+    if paths(&sources) != paths(&session.sources) {
+        let merged: Vec<String> = session.sources[1..]
+            .iter()
+            .map(|source| source.path.display().to_string())
+            .collect();
+        return Err(parse_error(match merged.is_empty() {
+            true => "session was saved without --merge; leave it out".to_string(),
+            false => format!(
+                "session was saved with --merge {}; give the same parts",
+                merged.join(", ")
+            ),
+        }));
+    }
+    if let Some(changed) = sources
+        .iter()
+        .zip(&session.sources)
+        .find(|(now, saved)| now.hash != saved.hash)
+    {
+        return Err(parse_error(format!(
+            "{} has changed since the session was saved; save it again",
+            changed.0.path.display()
+        )));
+    }
+    Ok(session)
+}
@@ -0,0 +1,226 @@
+//! Tables `export` writes for analyses in spreadsheets and dataframes
+//!
+//! Every table is written as CSV. Built with the `parquet` feature, the
+//! functions and xrefs tables are written as Parquet files too, which keep
+//! column types and load much faster for a whole game.
+
+use std::borrow::Cow;
+
+/// Metrics of one function
+pub struct FunctionRow<'a> {
+    pub function: &'a str,
+    pub class: &'a str,
+    pub name: &'a str,
+    pub script_size: usize,
+    pub blocks: usize,
+    pub loops: usize,
+    pub max_loop_depth: usize,
+    pub calls: usize,
+    /// Call sites in other functions calling this one
+    pub callers: usize,
+    pub cost: u64,
+}
+
+/// One call from a function to another
+pub struct CallRow<'a> {
+    pub caller: &'a str,
+    pub callee: &'a str,
+    pub offset: usize,
+    pub kind: &'static str,
+}
+
+/// One use of an object, property or function by a script
+pub struct XrefRow<'a> {
+    pub address: u64,
+    /// Path of the object, or `<owner>::<property>`; empty if the dump
+    /// doesn't have it
+    pub target: String,
+    pub function: &'a str,
+    pub offset: usize,
+    pub kind: &'static str,
+}
+
+/// Quote a CSV field if it needs it
+pub fn csv_field(text: &str) -> Cow<'_, str> {
+    if text.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", text.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+pub fn functions_csv(rows: &[FunctionRow]) -> String {
+    let mut csv = String::from(
+        "function,class,name,script_size,blocks,loops,max_loop_depth,calls,callers,cost\n",
+    );
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            csv_field(row.function),
+            csv_field(row.class),
+            csv_field(row.name),
+            row.script_size,
+            row.blocks,
+            row.loops,
+            row.max_loop_depth,
+            row.calls,
+            row.callers,
+            row.cost
+        ));
+    }
+    csv
+}
+
+pub fn calls_csv(rows: &[CallRow]) -> String {
+    let mut csv = String::from("caller,callee,offset,kind\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            csv_field(row.caller),
+            csv_field(row.callee),
+            row.offset,
+            row.kind
+        ));
+    }
+    csv
+}
+
+/// Addresses are written in hex, as everywhere else in the output
+pub fn xrefs_csv(rows: &[XrefRow]) -> String {
+    let mut csv = String::from("address,target,function,offset,kind\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "0x{:X},{},{},{},{}\n",
+            row.address,
+            csv_field(&row.target),
+            csv_field(row.function),
+            row.offset,
+            row.kind
+        ));
+    }
+    csv
+}
+
+#[cfg(feature = "parquet")]
+pub use parquet_tables::{functions_parquet, xrefs_parquet};
+
+#[cfg(feature = "parquet")]
+mod parquet_tables {
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt64Array};
+    use parquet::arrow::ArrowWriter;
+    use parquet::errors::ParquetError;
+
+    use super::{FunctionRow, XrefRow};
+
+    fn strings<'a>(values: impl Iterator<Item = &'a str>) -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(values))
+    }
+
+    fn numbers(values: impl Iterator<Item = usize>) -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(values.map(|n| n as u64)))
+    }
+
+    /// A Parquet file holding one table
+    fn write(columns: Vec<(&str, ArrayRef)>) -> Result<Vec<u8>, ParquetError> {
+        let batch = RecordBatch::try_from_iter(columns)?;
+        let mut file = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut file, batch.schema(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(file)
+    }
+
+    pub fn functions_parquet(rows: &[FunctionRow]) -> Result<Vec<u8>, ParquetError> {
+        write(vec![
+            ("function", strings(rows.iter().map(|row| row.function))),
+            ("class", strings(rows.iter().map(|row| row.class))),
+            ("name", strings(rows.iter().map(|row| row.name))),
+            (
+                "script_size",
+                numbers(rows.iter().map(|row| row.script_size)),
+            ),
+            ("blocks", numbers(rows.iter().map(|row| row.blocks))),
+            ("loops", numbers(rows.iter().map(|row| row.loops))),
+            (
+                "max_loop_depth",
+                numbers(rows.iter().map(|row| row.max_loop_depth)),
+            ),
+            ("calls", numbers(rows.iter().map(|row| row.calls))),
+            ("callers", numbers(rows.iter().map(|row| row.callers))),
+            (
+                "cost",
+                Arc::new(UInt64Array::from_iter_values(
+                    rows.iter().map(|row| row.cost),
+                )),
+            ),
+        ])
+    }
+
+    pub fn xrefs_parquet(rows: &[XrefRow]) -> Result<Vec<u8>, ParquetError> {
+        write(vec![
+            (
+                "address",
+                Arc::new(UInt64Array::from_iter_values(
+                    rows.iter().map(|row| row.address),
+                )),
+            ),
+            (
+                "target",
+                strings(rows.iter().map(|row| row.target.as_str())),
+            ),
+            ("function", strings(rows.iter().map(|row| row.function))),
+            ("offset", numbers(rows.iter().map(|row| row.offset))),
+            ("kind", strings(rows.iter().map(|row| row.kind))),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xrefs() -> [XrefRow<'static>; 1] {
+        [XrefRow {
+            address: 0x20,
+            target: "/Game/BP.BP_C::Health".to_string(),
+            function: "/Game/BP.BP_C:Regen",
+            offset: 9,
+            kind: "write",
+        }]
+    }
+
+    #[test]
+    fn test_csv_fields_are_quoted() {
+        assert_eq!(csv_field("Tick"), "Tick");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line\r"), "\"line\r\"");
+        assert_eq!(
+            xrefs_csv(&xrefs()),
+            "address,target,function,offset,kind\n\
+             0x20,/Game/BP.BP_C::Health,/Game/BP.BP_C:Regen,9,write\n"
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_tables() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let path = std::env::temp_dir().join("kismet-test-xrefs.parquet");
+        std::fs::write(&path, xrefs_parquet(&xrefs()).unwrap()).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 1);
+        let columns: Vec<&str> = metadata
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| column.name())
+            .collect();
+        assert_eq!(columns, ["address", "target", "function", "offset", "kind"]);
+        std::fs::remove_file(path).unwrap();
+    }
+}